@@ -102,7 +102,16 @@ criterion_group!(
     benches,
     cases::parsing::group,
     cases::rewriting::group,
-    cases::selector_matching::group
+    cases::selector_matching::group,
+    cases::head_only::group,
+    cases::handler_types::group
 );
 
+#[cfg(feature = "parse_ahead")]
+criterion_group!(parse_ahead_benches, cases::parse_ahead::group);
+
+#[cfg(not(feature = "parse_ahead"))]
 criterion_main!(benches);
+
+#[cfg(feature = "parse_ahead")]
+criterion_main!(benches, parse_ahead_benches);
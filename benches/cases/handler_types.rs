@@ -0,0 +1,49 @@
+use lol_html::*;
+
+define_group!(
+    "Handler types",
+    [
+        (
+            "Rewriting with the local handlers",
+            Settings {
+                element_content_handlers: vec![
+                    element!("ul", |el| {
+                        el.set_attribute("class", "list").unwrap();
+
+                        Ok(())
+                    }),
+                    text!("li", |t| {
+                        black_box(t.as_str());
+
+                        Ok(())
+                    })
+                ],
+                ..Settings::default()
+            }
+        ),
+        (
+            "Rewriting with the Send handlers",
+            Settings {
+                element_content_handlers: vec![
+                    (
+                        &"ul".parse::<Selector>().unwrap(),
+                        ElementContentHandlers::new_send().element(|el| {
+                            el.set_attribute("class", "list").unwrap();
+
+                            Ok(())
+                        })
+                    ),
+                    (
+                        &"li".parse::<Selector>().unwrap(),
+                        ElementContentHandlers::new_send().text(|t| {
+                            black_box(t.as_str());
+
+                            Ok(())
+                        })
+                    )
+                ],
+                ..Settings::new_send()
+            }
+        )
+    ]
+);
@@ -0,0 +1,31 @@
+use lol_html::html_content::*;
+use lol_html::*;
+
+define_group!(
+    "Head-only rewriting",
+    [
+        (
+            "Injection into head with full document rewriting",
+            Settings {
+                element_content_handlers: vec![element!("head", |el| {
+                    el.append("<script></script>", ContentType::Html);
+
+                    Ok(())
+                })],
+                ..Settings::default()
+            }
+        ),
+        (
+            "Injection into head with tail passthrough",
+            Settings {
+                element_content_handlers: vec![element!("head", |el| {
+                    el.append("<script></script>", ContentType::Html);
+
+                    Ok(())
+                })],
+                head_only: true,
+                ..Settings::default()
+            }
+        )
+    ]
+);
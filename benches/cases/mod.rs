@@ -1,3 +1,7 @@
+pub mod handler_types;
+pub mod head_only;
+#[cfg(feature = "parse_ahead")]
+pub mod parse_ahead;
 pub mod parsing;
 pub mod rewriting;
 pub mod selector_matching;
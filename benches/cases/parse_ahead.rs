@@ -0,0 +1,36 @@
+use lol_html::*;
+
+define_group!(
+    "Parse-ahead",
+    [
+        (
+            "Rewriting of all the elements without parse-ahead",
+            Settings {
+                element_content_handlers: vec![
+                    element!("*", |el| {
+                        el.set_attribute("data-foo", "bar")?;
+
+                        Ok(())
+                    }),
+                    text!("*", noop_handler!())
+                ],
+                ..Settings::default()
+            }
+        ),
+        (
+            "Rewriting of all the elements with parse-ahead",
+            Settings {
+                element_content_handlers: vec![
+                    element!("*", |el| {
+                        el.set_attribute("data-foo", "bar")?;
+
+                        Ok(())
+                    }),
+                    text!("*", noop_handler!())
+                ],
+                parse_ahead_queue_capacity: Some(16),
+                ..Settings::default()
+            }
+        )
+    ]
+);
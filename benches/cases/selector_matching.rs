@@ -31,6 +31,36 @@ define_group!(
                 ..Settings::default()
             }
         ),
+        (
+            "Absent attribute selectors",
+            Settings {
+                element_content_handlers: vec![
+                    element!("[data-foo]", noop_handler!()),
+                    element!("[data-bar]", noop_handler!()),
+                    element!("[data-baz]", noop_handler!()),
+                    element!("[aria-label]", noop_handler!()),
+                    element!("[itemprop]", noop_handler!()),
+                    element!("[srcset]", noop_handler!()),
+                    element!("[integrity]", noop_handler!()),
+                    element!("[crossorigin]", noop_handler!())
+                ],
+                ..Settings::default()
+            }
+        ),
+        (
+            "Shared attribute comparisons",
+            Settings {
+                element_content_handlers: vec![
+                    element!("div [href^='http']", noop_handler!()),
+                    element!("p [href^='http']", noop_handler!()),
+                    element!("li [href^='http']", noop_handler!()),
+                    element!("td [href^='http']", noop_handler!()),
+                    element!("span [href^='http']", noop_handler!()),
+                    element!("body [href^='http']", noop_handler!())
+                ],
+                ..Settings::default()
+            }
+        ),
         (
             "Multiple selectors",
             Settings {
@@ -217,6 +217,23 @@ typedef struct {
     // `lol_html_rewriter_write` and `lol_html_rewriter_end` will return an error
     // if this limit is exceeded.
     size_t max_allowed_memory_usage;
+    // Number of attributes for which the space is preallocated
+    // in the attribute buffer.
+    size_t preallocated_attribute_buffer_capacity;
+    // Preallocated depth of the element stack used for selector matching.
+    //
+    // Can be set to 0, in this case the stack grows on demand.
+    size_t preallocated_selector_matching_stack_depth;
+    // Preallocated size of the buffer for the output of the output filter.
+    //
+    // Can be set to 0, in this case the buffer grows on demand.
+    size_t preallocated_output_buffer_size;
+    // Amount of memory after which a rewriter disables optional features
+    // that consume memory (e.g. the selector matching cache) instead of
+    // returning an error.
+    //
+    // Can be set to 0, in this case there is no soft limit.
+    size_t soft_memory_usage_limit;
 } lol_html_memory_settings_t;
 
 // Builds HTML-rewriter out of the provided builder. Can be called
@@ -0,0 +1,49 @@
+//! A rewriter built with the minimal profile, i.e. with only the `basic_selectors` feature. It's
+//! used to track the code size of the profile for WASM and embedded targets, see
+//! `scripts/size_check.sh`.
+
+use lol_html::{doc_comments, element, HtmlRewriter, Settings};
+use std::slice;
+
+/// Removes all the comments and the `<script>` and `<style>` elements from the UTF-8 HTML in the
+/// provided buffer. Returns the length of the result or `usize::MAX` if the rewriting has failed.
+///
+/// # Safety
+///
+/// `ptr` should point to a writable buffer of `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn remove_comments(ptr: *mut u8, len: usize) -> usize {
+    let input = slice::from_raw_parts_mut(ptr, len);
+    let mut output = Vec::with_capacity(len);
+
+    let res = HtmlRewriter::try_new(
+        Settings {
+            element_content_handlers: vec![element!("script, style", |el| {
+                el.remove();
+
+                Ok(())
+            })],
+            document_content_handlers: vec![doc_comments!(|c| {
+                c.remove();
+
+                Ok(())
+            })],
+            ..Settings::default()
+        },
+        |c: &[u8]| output.extend_from_slice(c),
+    )
+    .map_err(|_| ())
+    .and_then(|mut rewriter| {
+        rewriter.write(input).map_err(|_| ())?;
+        rewriter.end().map_err(|_| ())
+    });
+
+    match res {
+        // NOTE: the output can only be shorter than the input, since the content is only removed.
+        Ok(()) => {
+            input[..output.len()].copy_from_slice(&output);
+            output.len()
+        }
+        Err(()) => usize::MAX,
+    }
+}
@@ -0,0 +1,26 @@
+#!/bin/sh
+
+# Checks that the crate built with the minimal profile (UTF-8 only, basic selectors) stays within
+# the code size budget for WASM and embedded targets.
+
+set -e
+
+MAX_SIZE=512000
+TARGET=wasm32-unknown-unknown
+WASM=target/$TARGET/release/examples/minimal.wasm
+
+CARGO_PROFILE_RELEASE_OPT_LEVEL=z \
+CARGO_PROFILE_RELEASE_LTO=true \
+CARGO_PROFILE_RELEASE_CODEGEN_UNITS=1 \
+CARGO_PROFILE_RELEASE_STRIP=true \
+cargo build --release --no-default-features --features=basic_selectors --target=$TARGET \
+    --example=minimal
+
+SIZE=$(wc -c < "$WASM")
+
+echo "Code size: $SIZE bytes (budget: $MAX_SIZE bytes)"
+
+if [ "$SIZE" -gt "$MAX_SIZE" ]; then
+    echo "The minimal build exceeds the code size budget."
+    exit 1
+fi
@@ -6,6 +6,13 @@ echo "===  Running library tests... ==="
 cargo clippy --features=integration_test --all-targets
 cargo test --features=integration_test "$@"
 
+echo "=== Checking the build without the default features... ==="
+cargo check --no-default-features
+cargo test --lib --no-default-features --features=basic_selectors
+
+echo "=== Checking the code size of the minimal build... ==="
+./scripts/size_check.sh
+
 echo "=== Running C API tests... ==="
 prove -e 'cargo' run ::  --manifest-path=./c-api/tests/Cargo.toml
 
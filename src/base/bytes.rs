@@ -1,4 +1,5 @@
-use super::Range;
+use super::encoding::{decode, encode};
+use super::{intern_lowercase, Range};
 use encoding_rs::{Encoding, WINDOWS_1252};
 use memchr::{memchr, memchr3};
 use std::borrow::Cow;
@@ -15,9 +16,16 @@ pub struct HasReplacementsError;
 pub struct Bytes<'b>(Cow<'b, [u8]>);
 
 impl<'b> Bytes<'b> {
+    /// Creates bytes from the ASCII literal, which has the same representation in all the
+    /// ASCII-compatible encodings.
+    #[inline]
+    pub const fn from_static(bytes: &'static [u8]) -> Bytes<'static> {
+        Bytes(Cow::Borrowed(bytes))
+    }
+
     #[inline]
     pub fn from_str(string: &'b str, encoding: &'static Encoding) -> Self {
-        encoding.encode(string).0.into()
+        encode(string, encoding).0.into()
     }
 
     #[inline]
@@ -25,7 +33,7 @@ impl<'b> Bytes<'b> {
         string: &'b str,
         encoding: &'static Encoding,
     ) -> Result<Self, HasReplacementsError> {
-        let (res, _, has_replacements) = encoding.encode(string);
+        let (res, has_replacements) = encode(string, encoding);
 
         if has_replacements {
             Err(HasReplacementsError)
@@ -36,12 +44,23 @@ impl<'b> Bytes<'b> {
 
     #[inline]
     pub fn as_string(&self, encoding: &'static Encoding) -> String {
-        encoding.decode(self).0.into_owned()
+        decode(self, encoding).into_owned()
     }
 
     #[inline]
     pub fn as_lowercase_string(&self, encoding: &'static Encoding) -> String {
-        encoding.decode(self).0.to_ascii_lowercase()
+        decode(self, encoding).to_ascii_lowercase()
+    }
+
+    /// Same as [`as_lowercase_string`], but doesn't allocate for the commonly used names.
+    ///
+    /// [`as_lowercase_string`]: #method.as_lowercase_string
+    #[inline]
+    pub fn as_interned_lowercase_string(&self, encoding: &'static Encoding) -> Cow<'static, str> {
+        match intern_lowercase(self) {
+            Some(name) => Cow::Borrowed(name),
+            None => Cow::Owned(self.as_lowercase_string(encoding)),
+        }
     }
 
     #[inline]
@@ -0,0 +1,65 @@
+//! Conversion of the strings from and to the encoding of the document.
+//!
+//! Without the `all_encodings` feature the rewriter accepts only UTF-8 (refer to
+//! `try_encoding_from_str`), so the strings are converted without `encoding_rs`. That
+//! lets the linker strip the conversion tables of all the other encodings.
+
+use encoding_rs::Encoding;
+use std::borrow::Cow;
+
+/// Encodes the `string` and returns the encoded bytes along with the flag that indicates
+/// whether some of the characters have been replaced with numeric character references.
+#[cfg(feature = "all_encodings")]
+#[inline]
+pub fn encode<'s>(string: &'s str, encoding: &'static Encoding) -> (Cow<'s, [u8]>, bool) {
+    let (bytes, _, has_replacements) = encoding.encode(string);
+
+    (bytes, has_replacements)
+}
+
+/// Decodes the `bytes` with the BOM sniffing, replacing the malformed sequences with
+/// the U+FFFD REPLACEMENT CHARACTER.
+#[cfg(feature = "all_encodings")]
+#[inline]
+pub fn decode<'b>(bytes: &'b [u8], encoding: &'static Encoding) -> Cow<'b, str> {
+    encoding.decode(bytes).0
+}
+
+/// Same as [`decode`], but without the BOM sniffing.
+///
+/// [`decode`]: fn.decode.html
+#[cfg(feature = "all_encodings")]
+#[inline]
+pub fn decode_without_bom_handling<'b>(
+    bytes: &'b [u8],
+    encoding: &'static Encoding,
+) -> Cow<'b, str> {
+    encoding.decode_without_bom_handling(bytes).0
+}
+
+#[cfg(not(feature = "all_encodings"))]
+#[inline]
+pub fn encode<'s>(string: &'s str, _encoding: &'static Encoding) -> (Cow<'s, [u8]>, bool) {
+    (Cow::Borrowed(string.as_bytes()), false)
+}
+
+#[cfg(not(feature = "all_encodings"))]
+#[inline]
+pub fn decode<'b>(bytes: &'b [u8], encoding: &'static Encoding) -> Cow<'b, str> {
+    const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+    if bytes.starts_with(BOM) {
+        decode_without_bom_handling(&bytes[BOM.len()..], encoding)
+    } else {
+        decode_without_bom_handling(bytes, encoding)
+    }
+}
+
+#[cfg(not(feature = "all_encodings"))]
+#[inline]
+pub fn decode_without_bom_handling<'b>(
+    bytes: &'b [u8],
+    _encoding: &'static Encoding,
+) -> Cow<'b, str> {
+    String::from_utf8_lossy(bytes)
+}
@@ -0,0 +1,215 @@
+//! Interning of commonly used tag and attribute names.
+//!
+//! Owned copies of names are created each time a name is decoded for a handler or a new name
+//! is provided via the mutation API. On attribute-heavy documents the same handful of names
+//! ("div", "class", "href", etc.) is produced over and over, so we keep a static table of such
+//! names and hand out `'static` references to its entries instead of allocating.
+//!
+//! NOTE: all the names in the table consist of ASCII characters only, so their byte
+//! representation is the same in all the ASCII-compatible encodings that we support.
+use std::cmp::Ordering;
+
+// NOTE: the table must be sorted and contain only lowercase names.
+static NAMES: &[&str] = &[
+    "a",
+    "abbr",
+    "action",
+    "address",
+    "alt",
+    "area",
+    "article",
+    "aside",
+    "async",
+    "audio",
+    "b",
+    "base",
+    "body",
+    "br",
+    "button",
+    "canvas",
+    "caption",
+    "charset",
+    "checked",
+    "cite",
+    "class",
+    "code",
+    "col",
+    "colgroup",
+    "cols",
+    "colspan",
+    "content",
+    "controls",
+    "crossorigin",
+    "data",
+    "datetime",
+    "dd",
+    "defer",
+    "del",
+    "details",
+    "dfn",
+    "dir",
+    "disabled",
+    "div",
+    "dl",
+    "download",
+    "dt",
+    "em",
+    "embed",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "for",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "height",
+    "hidden",
+    "hr",
+    "href",
+    "hreflang",
+    "html",
+    "http-equiv",
+    "i",
+    "id",
+    "iframe",
+    "img",
+    "input",
+    "integrity",
+    "itemprop",
+    "itemscope",
+    "itemtype",
+    "kbd",
+    "label",
+    "lang",
+    "li",
+    "link",
+    "loading",
+    "main",
+    "map",
+    "mark",
+    "media",
+    "meta",
+    "method",
+    "name",
+    "nav",
+    "noscript",
+    "object",
+    "ol",
+    "onclick",
+    "onload",
+    "optgroup",
+    "option",
+    "p",
+    "param",
+    "picture",
+    "placeholder",
+    "pre",
+    "property",
+    "q",
+    "readonly",
+    "referrerpolicy",
+    "rel",
+    "required",
+    "role",
+    "rows",
+    "rowspan",
+    "s",
+    "sandbox",
+    "script",
+    "section",
+    "select",
+    "selected",
+    "sizes",
+    "small",
+    "source",
+    "span",
+    "src",
+    "srcset",
+    "strong",
+    "style",
+    "sub",
+    "summary",
+    "sup",
+    "svg",
+    "tabindex",
+    "table",
+    "target",
+    "tbody",
+    "td",
+    "template",
+    "textarea",
+    "tfoot",
+    "th",
+    "thead",
+    "time",
+    "title",
+    "tr",
+    "track",
+    "type",
+    "u",
+    "ul",
+    "value",
+    "var",
+    "video",
+    "wbr",
+    "width",
+];
+
+#[inline]
+fn cmp_ignore_ascii_case(name: &[u8], other: &[u8]) -> Ordering {
+    name.iter()
+        .map(u8::to_ascii_lowercase)
+        .cmp(other.iter().map(u8::to_ascii_lowercase))
+}
+
+/// Returns the interned copy of the `name` if it's in the table of common names.
+#[inline]
+pub fn intern(name: &str) -> Option<&'static str> {
+    NAMES.binary_search(&name).ok().map(|idx| NAMES[idx])
+}
+
+/// Returns the interned lowercase copy of the `name` if it's in the table of common names,
+/// ignoring the ASCII case of the `name`.
+#[inline]
+pub fn intern_lowercase(name: &[u8]) -> Option<&'static str> {
+    NAMES
+        .binary_search_by(|probe| cmp_ignore_ascii_case(probe.as_bytes(), name))
+        .ok()
+        .map(|idx| NAMES[idx])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_table_is_sorted_and_lowercase() {
+        for pair in NAMES.windows(2) {
+            assert!(pair[0] < pair[1], "{} >= {}", pair[0], pair[1]);
+        }
+
+        for name in NAMES {
+            assert_eq!(*name, name.to_ascii_lowercase());
+        }
+    }
+
+    #[test]
+    fn intern_names() {
+        assert_eq!(intern("div"), Some("div"));
+        assert_eq!(intern("http-equiv"), Some("http-equiv"));
+        assert_eq!(intern("Div"), None);
+        assert_eq!(intern("divs"), None);
+
+        assert_eq!(intern_lowercase(b"HREF"), Some("href"));
+        assert_eq!(intern_lowercase(b"DaTa"), Some("data"));
+        assert_eq!(intern_lowercase(b"data-foo"), None);
+        assert_eq!(intern_lowercase(b""), None);
+    }
+}
@@ -3,8 +3,15 @@ mod debug_trace;
 
 mod align;
 mod bytes;
+pub mod encoding;
+mod interner;
 mod range;
+mod shared;
 
 pub use self::align::Align;
-pub use self::bytes::{Bytes, HasReplacementsError};
+pub use self::bytes::Bytes;
+#[cfg(feature = "basic_selectors")]
+pub use self::bytes::HasReplacementsError;
+pub use self::interner::{intern, intern_lowercase};
 pub use self::range::Range;
+pub use self::shared::{LocalSharing, SendSharing, Shared, Sharing};
@@ -0,0 +1,99 @@
+use std::cell::{RefCell, RefMut};
+use std::fmt::{self, Debug, Formatter};
+use std::ops::DerefMut;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Selects the pointer to the state that is shared by several parts of the rewriter.
+///
+/// The rewriters with the local handlers share the state with `Rc<RefCell<T>>`, so they don't
+/// pay for the synchronization. The rewriters with the `Send` handlers share it with
+/// `Arc<Mutex<T>>`, so the state can be sent to another thread along with the rewriter.
+pub trait Sharing: Sized + 'static {
+    type Ptr<T>: Clone;
+    type Guard<'a, T: 'a>: DerefMut<Target = T>;
+
+    fn new_ptr<T>(value: T) -> Self::Ptr<T>;
+    fn lock_ptr<T>(ptr: &Self::Ptr<T>) -> Self::Guard<'_, T>;
+}
+
+/// The [`Sharing`] of the rewriters that stay on the thread they have been created on.
+#[derive(Debug)]
+pub struct LocalSharing(());
+
+impl Sharing for LocalSharing {
+    type Ptr<T> = Rc<RefCell<T>>;
+    type Guard<'a, T: 'a> = RefMut<'a, T>;
+
+    #[inline]
+    fn new_ptr<T>(value: T) -> Rc<RefCell<T>> {
+        Rc::new(RefCell::new(value))
+    }
+
+    #[inline]
+    fn lock_ptr<T>(ptr: &Rc<RefCell<T>>) -> RefMut<'_, T> {
+        ptr.borrow_mut()
+    }
+}
+
+/// The [`Sharing`] of the rewriters that can be sent to another thread.
+#[derive(Debug)]
+pub struct SendSharing(());
+
+impl Sharing for SendSharing {
+    type Ptr<T> = Arc<Mutex<T>>;
+    type Guard<'a, T: 'a> = MutexGuard<'a, T>;
+
+    #[inline]
+    fn new_ptr<T>(value: T) -> Arc<Mutex<T>> {
+        Arc::new(Mutex::new(value))
+    }
+
+    #[inline]
+    fn lock_ptr<T>(ptr: &Arc<Mutex<T>>) -> MutexGuard<'_, T> {
+        // NOTE: the value is left in a consistent state if the thread has panicked while
+        // holding the lock, since none of the locks is held while a user callback is invoked.
+        ptr.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A value that is shared by several parts of the rewriter. The value is never accessed
+/// concurrently, so the lock is always uncontended.
+pub struct Shared<T, S: Sharing = LocalSharing>(S::Ptr<T>);
+
+impl<T, S: Sharing> Shared<T, S> {
+    #[inline]
+    pub fn new(value: T) -> Self {
+        Shared(S::new_ptr(value))
+    }
+
+    /// Locks the value. The lock is not reentrant, so it shouldn't be held while the value can
+    /// be locked again, e.g. by a content handler.
+    #[inline]
+    pub fn lock(&self) -> S::Guard<'_, T> {
+        S::lock_ptr(&self.0)
+    }
+}
+
+impl<T: Default, S: Sharing> Default for Shared<T, S> {
+    #[inline]
+    fn default() -> Self {
+        Shared::new(T::default())
+    }
+}
+
+impl<T, S: Sharing> Debug for Shared<T, S>
+where
+    S::Ptr<T>: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Shared").field(&self.0).finish()
+    }
+}
+
+impl<T, S: Sharing> Clone for Shared<T, S> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Shared(self.0.clone())
+    }
+}
@@ -1,5 +1,7 @@
 use super::Tag;
-use crate::base::{Bytes, HasReplacementsError, Range};
+#[cfg(feature = "basic_selectors")]
+use crate::base::HasReplacementsError;
+use crate::base::{Bytes, Range};
 use encoding_rs::Encoding;
 
 // NOTE: All standard tag names contain only ASCII alpha characters
@@ -70,6 +72,27 @@ impl LocalNameHash {
             };
         }
     }
+
+    /// Decodes the hash back into the lowercase tag name. Returns `None` if the hash is empty.
+    pub fn decode(self) -> Option<String> {
+        self.0.map(|mut h| {
+            let mut name = Vec::with_capacity(12);
+
+            // NOTE: tag names can't start with `1` (which is encoded as 0b00000), so the
+            // remaining hash is zero only once all the characters have been decoded.
+            while h != 0 {
+                let ch = (h & 0x1F) as u8;
+
+                name.push(if ch < 6 { b'1' + ch } else { b'a' + ch - 6 });
+                h >>= 5;
+            }
+
+            name.reverse();
+
+            // NOTE: the hash can only contain ASCII characters, so it's safe to unwrap here.
+            String::from_utf8(name).unwrap()
+        })
+    }
 }
 
 impl From<&str> for LocalNameHash {
@@ -122,6 +145,7 @@ impl<'i> LocalName<'i> {
         }
     }
 
+    #[cfg(feature = "basic_selectors")]
     #[inline]
     pub fn from_str_without_replacements<'s>(
         string: &'s str,
@@ -135,6 +159,14 @@ impl<'i> LocalName<'i> {
             Ok(LocalName::Hash(hash))
         }
     }
+
+    #[inline]
+    pub fn as_lowercase_string(&self, encoding: &'static Encoding) -> String {
+        match self {
+            LocalName::Hash(h) => h.decode().unwrap_or_default(),
+            LocalName::Bytes(b) => b.as_lowercase_string(encoding),
+        }
+    }
 }
 
 impl PartialEq<Tag> for LocalName<'_> {
@@ -169,6 +201,16 @@ mod tests {
         assert_eq!(LocalNameHash::from("div"), LocalNameHash(Some(9691u64)));
     }
 
+    #[test]
+    fn decode() {
+        for name in &["div", "h1", "blockquote", "a", "x16", "foobarbazqux"] {
+            assert_eq!(LocalNameHash::from(*name).decode().as_deref(), Some(*name));
+        }
+
+        assert_eq!(LocalNameHash::from("DiV").decode().as_deref(), Some("div"));
+        assert_eq!(LocalNameHash::from("div@&").decode(), None);
+    }
+
     #[test]
     fn hash_invalidation_for_non_ascii_chars() {
         assert!(LocalNameHash::from("div@&").is_empty());
@@ -9,3 +9,28 @@ pub use self::local_name::{LocalName, LocalNameHash};
 pub use self::namespace::Namespace;
 pub use self::tag::*;
 pub use self::text_type::TextType;
+
+/// Returns `true` if the byte is an [ASCII whitespace] that separates the tokens of the
+/// attribute values, e.g. the class names of the `class` attribute.
+///
+/// [ASCII whitespace]: https://infra.spec.whatwg.org/#ascii-whitespace
+#[inline]
+pub fn is_attr_whitespace(b: u8) -> bool {
+    b == b' ' || b == b'\n' || b == b'\r' || b == b'\t' || b == b'\x0c'
+}
+
+#[inline]
+pub fn is_void_element(local_name: &LocalName) -> bool {
+    // NOTE: fast path for the most commonly used elements
+    if tag_is_one_of!(*local_name, [Div, A, Span, Li]) {
+        return false;
+    }
+
+    tag_is_one_of!(
+        *local_name,
+        [
+            Area, Base, Basefont, Bgsound, Br, Col, Embed, Hr, Img, Input, Keygen, Link, Meta,
+            Param, Source, Track, Wbr
+        ]
+    )
+}
@@ -3,6 +3,14 @@ pub enum Namespace {
     Html,
     Svg,
     MathML,
+    // NOTE: the HTML parser places only the attributes of the foreign elements
+    // into the following namespaces, they never contain elements.
+    #[cfg(feature = "selector_matching")]
+    XLink,
+    #[cfg(feature = "selector_matching")]
+    Xml,
+    #[cfg(feature = "selector_matching")]
+    XmlNs,
 }
 
 impl Namespace {
@@ -15,6 +23,27 @@ impl Namespace {
             Html => "http://www.w3.org/1999/xhtml",
             Svg => "http://www.w3.org/2000/svg",
             MathML => "http://www.w3.org/1998/Math/MathML",
+            #[cfg(feature = "selector_matching")]
+            XLink => "http://www.w3.org/1999/xlink",
+            #[cfg(feature = "selector_matching")]
+            Xml => "http://www.w3.org/XML/1998/namespace",
+            #[cfg(feature = "selector_matching")]
+            XmlNs => "http://www.w3.org/2000/xmlns/",
+        }
+    }
+
+    /// Returns the prefix of the attributes in the namespace, e.g. `xlink` for `xlink:href`.
+    #[cfg(feature = "selector_matching")]
+    #[inline]
+    pub fn attribute_prefix(self) -> Option<&'static str> {
+        use Namespace::*;
+
+        // NOTE: https://html.spec.whatwg.org/multipage/parsing.html#adjust-foreign-attributes
+        match self {
+            XLink => Some("xlink"),
+            Xml => Some("xml"),
+            XmlNs => Some("xmlns"),
+            Html | Svg | MathML => None,
         }
     }
 }
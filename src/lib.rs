@@ -31,37 +31,65 @@ mod parser;
 mod rewritable_units;
 mod transform_stream;
 
+#[cfg(feature = "selector_matching")]
+pub mod presets;
+
+pub mod text_filters;
+
 use cfg_if::cfg_if;
 
 pub use self::rewriter::{
-    rewrite_str, DocumentContentHandlers, ElementContentHandlers, HtmlRewriter, MemorySettings,
-    RewriteStrSettings, Settings,
+    canonicalize, rewrite_str, rewrite_str_with_settings, sampled, DeadlinePolicy,
+    DocumentContentHandlers, ElementContentHandlers, EngagedOptimizations, EntityNormalization,
+    Finished, HandlerTypes, HtmlRewriter, InputFilter, IntoHandler, LocalHandlerTypes,
+    MatchingCutoff, MemorySettings, MemoryUsageWarning, NumericCharRefs, ObsoleteElementsPolicy,
+    OutputFilter, RewriteStrSettings, RewriterBuilder, RewriterTemplate, Sampler,
+    SelectContentPolicy, SelectorSwitch, SendHandlerTypes, Settings, SpillSettings, StripPrefix,
+    TableContentPolicy, TextChunkPolicy, TextFilter, TextPipeline, ValidationDiagnostic,
 };
 pub use self::selectors_vm::Selector;
-pub use self::transform_stream::OutputSink;
+
+#[cfg(feature = "futures")]
+pub use self::rewriter::{AsyncContent, AsyncHtmlRewriter};
+
+#[cfg(feature = "selector_matching")]
+pub use self::selectors_vm::{ElementInfo, SelectorBuilder};
+pub use self::transform_stream::{OutputSink, PrefixCache};
 
 /// The errors that can be produced by the crate's API.
 pub mod errors {
-    pub use super::memory::MemoryLimitExceededError;
+    pub use super::memory::{BufferedTokenType, MemoryLimitExceededError};
     pub use super::parser::ParsingAmbiguityError;
-    pub use super::rewritable_units::{AttributeNameError, CommentTextError, TagNameError};
-    pub use super::rewriter::{EncodingError, RewritingError};
+    pub use super::rewritable_units::{
+        AttributeNameError, ClassNameError, CommentTextError, ContentModelError, DatasetKeyError,
+        ProcessingInstructionDataError, ScriptContentError, StartTagPatchError, TagNameError,
+        UnknownAriaRoleError,
+    };
+    pub use super::rewriter::{EncodingError, RewriterBuilderError, RewritingError, SettingsError};
+
+    #[cfg(feature = "basic_selectors")]
     pub use super::selectors_vm::SelectorError;
 }
 
 /// HTML content descriptors that can be produced and modified by a rewriter.
 pub mod html_content {
     pub use super::rewritable_units::{
-        Attribute, Comment, ContentType, Doctype, DocumentEnd, Element, TextChunk, UserData,
+        Aria, AriaLive, AriaRole, AriaTristate, Attribute, ClassList, Comment, ContentType,
+        Dataset, Doctype, DocumentEnd, Element, EndTag, InnerHtml, MatchInfo,
+        ProcessingInstruction, ScriptType, ShadowRootMode, SourceLocation, SrcsetCandidate,
+        StreamingHandler, StreamingHandlerSink, TextChunk, UserData,
     };
 
     pub use super::html::TextType;
+    pub use super::rewritable_units::srcset;
 }
 
 #[cfg(any(test, feature = "integration_test"))]
 pub mod test_utils {
+    use super::{HtmlRewriter, Settings};
     use encoding_rs::*;
 
+    #[cfg(feature = "all_encodings")]
     pub static ASCII_COMPATIBLE_ENCODINGS: [&Encoding; 36] = [
         BIG5,
         EUC_JP,
@@ -101,6 +129,10 @@ pub mod test_utils {
         X_USER_DEFINED,
     ];
 
+    // NOTE: only UTF-8 is supported without the `all_encodings` feature.
+    #[cfg(not(feature = "all_encodings"))]
+    pub static ASCII_COMPATIBLE_ENCODINGS: [&Encoding; 1] = [UTF_8];
+
     pub struct Output {
         bytes: Vec<u8>,
         encoding: &'static Encoding,
@@ -143,6 +175,66 @@ pub mod test_utils {
                 .into_owned()
         }
     }
+
+    /// Rewrites the `html` in each of the ASCII-compatible encodings and asserts that the output
+    /// is equivalent to the `expected` one.
+    ///
+    /// For each encoding the `html` is transcoded to it and written to the rewriter both as a
+    /// single chunk and byte by byte, so multi-byte characters are split between the chunks.
+    /// The `settings` are created anew for each of the rewrites and their `encoding` is replaced.
+    /// The output is decoded and compared with the `expected` output that went through the same
+    /// transcoding, so the characters that can't be represented in the encoding are expected
+    /// to be replaced with the numeric character references. The encodings that can't represent
+    /// the `html` itself are skipped.
+    pub fn assert_rewrite_in_all_encodings<'h, 's>(
+        html: &str,
+        expected: &str,
+        mut settings: impl FnMut() -> Settings<'h, 's>,
+    ) {
+        for &encoding in ASCII_COMPATIBLE_ENCODINGS.iter() {
+            let (input, _, has_unmappable_characters) = encoding.encode(html);
+
+            if has_unmappable_characters {
+                continue;
+            }
+
+            let expected = encoding
+                .decode_without_bom_handling(&encoding.encode(expected).0)
+                .0
+                .into_owned();
+
+            for &chunk_size in &[input.len().max(1), 1] {
+                let mut output = Output::new(encoding);
+
+                {
+                    let mut rewriter = HtmlRewriter::try_new(
+                        Settings {
+                            encoding: encoding.name(),
+                            ..settings()
+                        },
+                        |c: &[u8]| output.push(c),
+                    )
+                    .unwrap();
+
+                    for chunk in input.chunks(chunk_size) {
+                        rewriter.write(chunk).unwrap();
+                    }
+
+                    rewriter.end().unwrap();
+                }
+
+                let actual: String = output.into();
+
+                assert_eq!(
+                    actual,
+                    expected,
+                    "Encoding: {}, chunk size: {}",
+                    encoding.name(),
+                    chunk_size
+                );
+            }
+        }
+    }
 }
 
 cfg_if! {
@@ -161,7 +253,10 @@ cfg_if! {
         pub use self::base::Bytes;
         pub use self::memory::MemoryLimiter;
         pub use self::html::{LocalName, LocalNameHash, Tag, Namespace};
+    } else if #[cfg(feature = "basic_selectors")] {
+        mod selectors_vm;
     } else {
+        #[path = "selectors_vm_stub.rs"]
         mod selectors_vm;
     }
 }
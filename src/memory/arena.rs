@@ -1,17 +1,17 @@
 use super::{MemoryLimitExceededError, SharedMemoryLimiter};
+use crate::base::Sharing;
 use safemem::copy_over;
 
 /// Preallocated region of memory that can grow and never deallocates during the lifetime of
 /// the limiter.
-#[derive(Debug)]
-pub struct Arena {
-    limiter: SharedMemoryLimiter,
+pub struct Arena<S: Sharing> {
+    limiter: SharedMemoryLimiter<S>,
     data: Vec<u8>,
 }
 
-impl Arena {
-    pub fn new(limiter: SharedMemoryLimiter, preallocated_size: usize) -> Self {
-        limiter.borrow_mut().preallocate(preallocated_size);
+impl<S: Sharing> Arena<S> {
+    pub fn new(limiter: SharedMemoryLimiter<S>, preallocated_size: usize) -> Self {
+        limiter.lock().preallocate(preallocated_size);
 
         Arena {
             limiter,
@@ -28,7 +28,7 @@ impl Arena {
 
             // NOTE: approximate usage, as `Vec::reserve_exact` doesn't
             // give guarantees about exact capacity value :).
-            self.limiter.borrow_mut().increase_usage(additional)?;
+            self.limiter.lock().increase_usage(additional)?;
 
             // NOTE: with wicely choosen preallocated size this branch should be
             // executed quite rarely. We can't afford to use double capacity
@@ -50,7 +50,12 @@ impl Arena {
     pub fn shift(&mut self, byte_count: usize) {
         let remainder_len = self.data.len() - byte_count;
 
-        copy_over(&mut self.data, byte_count, 0, remainder_len);
+        // NOTE: `copy_over` panics if the source index is out of bounds, even if there is
+        // nothing to copy.
+        if remainder_len > 0 {
+            copy_over(&mut self.data, byte_count, 0, remainder_len);
+        }
+
         self.data.truncate(remainder_len);
     }
 
@@ -63,77 +68,76 @@ impl Arena {
 mod tests {
     use super::super::limiter::MemoryLimiter;
     use super::*;
-    use std::rc::Rc;
 
     #[test]
     fn append() {
-        let limiter = MemoryLimiter::new_shared(10);
-        let mut arena = Arena::new(Rc::clone(&limiter), 2);
+        let limiter: SharedMemoryLimiter = MemoryLimiter::new_shared(10);
+        let mut arena = Arena::new(SharedMemoryLimiter::clone(&limiter), 2);
 
         arena.append(&[1, 2]).unwrap();
         assert_eq!(arena.bytes(), &[1, 2]);
-        assert_eq!(limiter.borrow().current_usage(), 2);
+        assert_eq!(limiter.lock().current_usage(), 2);
 
         arena.append(&[3, 4]).unwrap();
         assert_eq!(arena.bytes(), &[1, 2, 3, 4]);
-        assert_eq!(limiter.borrow().current_usage(), 4);
+        assert_eq!(limiter.lock().current_usage(), 4);
 
         arena.append(&[5, 6, 7, 8, 9, 10]).unwrap();
         assert_eq!(arena.bytes(), &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
-        assert_eq!(limiter.borrow().current_usage(), 10);
+        assert_eq!(limiter.lock().current_usage(), 10);
 
         let err = arena.append(&[11]).unwrap_err();
 
-        assert_eq!(err, MemoryLimitExceededError);
+        assert_eq!(err, MemoryLimitExceededError::new(10, 11));
     }
 
     #[test]
     fn init_with() {
-        let limiter = MemoryLimiter::new_shared(5);
-        let mut arena = Arena::new(Rc::clone(&limiter), 0);
+        let limiter: SharedMemoryLimiter = MemoryLimiter::new_shared(5);
+        let mut arena = Arena::new(SharedMemoryLimiter::clone(&limiter), 0);
 
         arena.init_with(&[1]).unwrap();
         assert_eq!(arena.bytes(), &[1]);
-        assert_eq!(limiter.borrow().current_usage(), 1);
+        assert_eq!(limiter.lock().current_usage(), 1);
 
         arena.append(&[1, 2]).unwrap();
         assert_eq!(arena.bytes(), &[1, 1, 2]);
-        assert_eq!(limiter.borrow().current_usage(), 3);
+        assert_eq!(limiter.lock().current_usage(), 3);
 
         arena.init_with(&[1, 2, 3]).unwrap();
         assert_eq!(arena.bytes(), &[1, 2, 3]);
-        assert_eq!(limiter.borrow().current_usage(), 3);
+        assert_eq!(limiter.lock().current_usage(), 3);
 
         arena.init_with(&[]).unwrap();
-        assert_eq!(arena.bytes(), &[]);
-        assert_eq!(limiter.borrow().current_usage(), 3);
+        assert!(arena.bytes().is_empty());
+        assert_eq!(limiter.lock().current_usage(), 3);
 
         let err = arena.init_with(&[1, 2, 3, 4, 5, 6, 7]).unwrap_err();
 
-        assert_eq!(err, MemoryLimitExceededError);
+        assert_eq!(err, MemoryLimitExceededError::new(5, 7));
     }
 
     #[test]
     fn shift() {
-        let limiter = MemoryLimiter::new_shared(10);
-        let mut arena = Arena::new(Rc::clone(&limiter), 0);
+        let limiter: SharedMemoryLimiter = MemoryLimiter::new_shared(10);
+        let mut arena = Arena::new(SharedMemoryLimiter::clone(&limiter), 0);
 
         arena.append(&[0, 1, 2, 3]).unwrap();
         arena.shift(2);
         assert_eq!(arena.bytes(), &[2, 3]);
-        assert_eq!(limiter.borrow().current_usage(), 4);
+        assert_eq!(limiter.lock().current_usage(), 4);
 
         arena.append(&[0, 1]).unwrap();
         assert_eq!(arena.bytes(), &[2, 3, 0, 1]);
-        assert_eq!(limiter.borrow().current_usage(), 4);
+        assert_eq!(limiter.lock().current_usage(), 4);
 
         arena.shift(3);
         assert_eq!(arena.bytes(), &[1]);
-        assert_eq!(limiter.borrow().current_usage(), 4);
+        assert_eq!(limiter.lock().current_usage(), 4);
 
         arena.append(&[2, 3, 4, 5]).unwrap();
         arena.shift(1);
         assert_eq!(arena.bytes(), &[2, 3, 4, 5]);
-        assert_eq!(limiter.borrow().current_usage(), 5);
+        assert_eq!(limiter.lock().current_usage(), 5);
     }
 }
@@ -1,19 +1,19 @@
 #![allow(clippy::len_without_is_empty)]
 
 use std::mem::size_of;
-use std::ops::{Deref, Index, RangeBounds};
+use std::ops::{Deref, DerefMut, Index, RangeBounds};
 use std::vec::Drain;
 
 use super::{MemoryLimitExceededError, SharedMemoryLimiter};
+use crate::base::{LocalSharing, Sharing};
 
-#[derive(Debug)]
-pub struct LimitedVec<T> {
-    limiter: SharedMemoryLimiter,
+pub struct LimitedVec<T, S: Sharing = LocalSharing> {
+    limiter: SharedMemoryLimiter<S>,
     vec: Vec<T>,
 }
 
-impl<T> LimitedVec<T> {
-    pub fn new(limiter: SharedMemoryLimiter) -> Self {
+impl<T, S: Sharing> LimitedVec<T, S> {
+    pub fn new(limiter: SharedMemoryLimiter<S>) -> Self {
         LimitedVec {
             vec: vec![],
             limiter,
@@ -21,11 +21,18 @@ impl<T> LimitedVec<T> {
     }
 
     pub fn push(&mut self, element: T) -> Result<(), MemoryLimitExceededError> {
-        self.limiter.borrow_mut().increase_usage(size_of::<T>())?;
+        self.limiter.lock().increase_usage(size_of::<T>())?;
         self.vec.push(element);
         Ok(())
     }
 
+    /// Reserves capacity for at least `additional` more elements. Reserved capacity is not
+    /// accounted by the limiter until it's occupied by the elements.
+    #[inline]
+    pub fn reserve(&mut self, additional: usize) {
+        self.vec.reserve(additional);
+    }
+
     /// Returns the number of elements in the vector, also referred to as its 'length'.
     #[inline]
     pub fn len(&self) -> usize {
@@ -65,14 +72,14 @@ impl<T> LimitedVec<T> {
         };
 
         self.limiter
-            .borrow_mut()
+            .lock()
             .decrease_usage(size_of::<T>() * (end - start));
 
         self.vec.drain(range)
     }
 }
 
-impl<T> Deref for LimitedVec<T> {
+impl<T, S: Sharing> Deref for LimitedVec<T, S> {
     type Target = [T];
 
     #[inline]
@@ -81,7 +88,14 @@ impl<T> Deref for LimitedVec<T> {
     }
 }
 
-impl<T> Index<usize> for LimitedVec<T> {
+impl<T, S: Sharing> DerefMut for LimitedVec<T, S> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.vec.as_mut_slice()
+    }
+}
+
+impl<T, S: Sharing> Index<usize> for LimitedVec<T, S> {
     type Output = T;
 
     fn index(&self, index: usize) -> &Self::Output {
@@ -89,10 +103,10 @@ impl<T> Index<usize> for LimitedVec<T> {
     }
 }
 
-impl<T> Drop for LimitedVec<T> {
+impl<T, S: Sharing> Drop for LimitedVec<T, S> {
     fn drop(&mut self) {
         self.limiter
-            .borrow_mut()
+            .lock()
             .decrease_usage(size_of::<T>() * self.vec.len());
     }
 }
@@ -101,76 +115,76 @@ impl<T> Drop for LimitedVec<T> {
 mod tests {
     use super::super::MemoryLimiter;
     use super::*;
-    use std::rc::Rc;
 
     #[test]
     fn current_usage() {
         {
-            let limiter = MemoryLimiter::new_shared(10);
-            let mut vec_u8: LimitedVec<u8> = LimitedVec::new(Rc::clone(&limiter));
+            let limiter: SharedMemoryLimiter = MemoryLimiter::new_shared(10);
+            let mut vec_u8: LimitedVec<u8> = LimitedVec::new(SharedMemoryLimiter::clone(&limiter));
 
             vec_u8.push(1).unwrap();
             vec_u8.push(2).unwrap();
-            assert_eq!(limiter.borrow().current_usage(), 2);
+            assert_eq!(limiter.lock().current_usage(), 2);
         }
 
         {
-            let limiter = MemoryLimiter::new_shared(10);
-            let mut vec_u32: LimitedVec<u32> = LimitedVec::new(Rc::clone(&limiter));
+            let limiter: SharedMemoryLimiter = MemoryLimiter::new_shared(10);
+            let mut vec_u32: LimitedVec<u32> =
+                LimitedVec::new(SharedMemoryLimiter::clone(&limiter));
 
             vec_u32.push(1).unwrap();
             vec_u32.push(2).unwrap();
-            assert_eq!(limiter.borrow().current_usage(), 8);
+            assert_eq!(limiter.lock().current_usage(), 8);
         }
     }
 
     #[test]
     fn max_limit() {
-        let limiter = MemoryLimiter::new_shared(2);
-        let mut vector: LimitedVec<u8> = LimitedVec::new(Rc::clone(&limiter));
+        let limiter: SharedMemoryLimiter = MemoryLimiter::new_shared(2);
+        let mut vector: LimitedVec<u8> = LimitedVec::new(SharedMemoryLimiter::clone(&limiter));
 
         vector.push(1).unwrap();
         vector.push(2).unwrap();
 
         let err = vector.push(3).unwrap_err();
 
-        assert_eq!(err, MemoryLimitExceededError);
+        assert_eq!(err, MemoryLimitExceededError::new(2, 3));
     }
 
     #[test]
     fn drop() {
-        let limiter = MemoryLimiter::new_shared(1);
+        let limiter: SharedMemoryLimiter = MemoryLimiter::new_shared(1);
 
         {
-            let mut vector: LimitedVec<u8> = LimitedVec::new(Rc::clone(&limiter));
+            let mut vector: LimitedVec<u8> = LimitedVec::new(SharedMemoryLimiter::clone(&limiter));
 
             vector.push(1).unwrap();
-            assert_eq!(limiter.borrow().current_usage(), 1);
+            assert_eq!(limiter.lock().current_usage(), 1);
         }
 
-        assert_eq!(limiter.borrow().current_usage(), 0);
+        assert_eq!(limiter.lock().current_usage(), 0);
     }
 
     #[test]
     fn drain() {
-        let limiter = MemoryLimiter::new_shared(10);
-        let mut vector: LimitedVec<u8> = LimitedVec::new(Rc::clone(&limiter));
+        let limiter: SharedMemoryLimiter = MemoryLimiter::new_shared(10);
+        let mut vector: LimitedVec<u8> = LimitedVec::new(SharedMemoryLimiter::clone(&limiter));
 
         vector.push(1).unwrap();
         vector.push(2).unwrap();
         vector.push(3).unwrap();
-        assert_eq!(limiter.borrow().current_usage(), 3);
+        assert_eq!(limiter.lock().current_usage(), 3);
 
         vector.drain(0..3);
-        assert_eq!(limiter.borrow().current_usage(), 0);
+        assert_eq!(limiter.lock().current_usage(), 0);
 
         vector.push(1).unwrap();
         vector.push(2).unwrap();
         vector.push(3).unwrap();
         vector.push(4).unwrap();
-        assert_eq!(limiter.borrow().current_usage(), 4);
+        assert_eq!(limiter.lock().current_usage(), 4);
 
         vector.drain(1..=2);
-        assert_eq!(limiter.borrow().current_usage(), 2);
+        assert_eq!(limiter.lock().current_usage(), 2);
     }
 }
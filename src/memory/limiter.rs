@@ -1,29 +1,133 @@
-use std::cell::RefCell;
-use std::rc::Rc;
+use crate::base::{LocalSharing, Shared, Sharing};
 use thiserror::Error;
 
-pub type SharedMemoryLimiter = Rc<RefCell<MemoryLimiter>>;
+pub type SharedMemoryLimiter<S = LocalSharing> = Shared<MemoryLimiter, S>;
+
+/// The type of the token the rewriter was buffering when the memory limit was exceeded.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum BufferedTokenType {
+    StartTag,
+    EndTag,
+    Comment,
+    ProcessingInstruction,
+    Doctype,
+}
 
 /// An error that occures when rewriter exceedes the memory limit specified in the
 /// [`MemorySettings`].
 ///
+/// The error describes the failed allocation, so the limit can be tuned for the documents that
+/// exceed it. If the limit has been exceeded while buffering a token that spans multiple input
+/// chunks, the error also describes the token.
+///
 /// [`MemorySettings`]: ../struct.MemorySettings.html
-#[derive(Error, Debug, PartialEq, Copy, Clone)]
-#[error("The memory limit has been exceeded.")]
-pub struct MemoryLimitExceededError;
+#[derive(Error, Debug, PartialEq, Eq, Clone)]
+#[error("The memory limit of {limit} bytes has been exceeded ({requested_usage} bytes requested).")]
+pub struct MemoryLimitExceededError {
+    limit: usize,
+    requested_usage: usize,
+    token_type: Option<BufferedTokenType>,
+    tag_name: Option<String>,
+}
+
+impl MemoryLimitExceededError {
+    #[inline]
+    pub(crate) fn new(limit: usize, requested_usage: usize) -> Self {
+        MemoryLimitExceededError {
+            limit,
+            requested_usage,
+            token_type: None,
+            tag_name: None,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn with_token(
+        mut self,
+        token_type: BufferedTokenType,
+        tag_name: Option<String>,
+    ) -> Self {
+        self.token_type = Some(token_type);
+        self.tag_name = tag_name;
+        self
+    }
+
+    /// Returns the configured memory limit in bytes.
+    #[inline]
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Returns the memory usage in bytes the rewriter would have reached if the allocation had
+    /// succeeded.
+    #[inline]
+    pub fn requested_usage(&self) -> usize {
+        self.requested_usage
+    }
+
+    /// Returns the type of the token the rewriter was buffering, if any.
+    #[inline]
+    pub fn token_type(&self) -> Option<BufferedTokenType> {
+        self.token_type
+    }
+
+    /// Returns the lowercased name of the buffered tag, if its name has been parsed.
+    #[inline]
+    pub fn tag_name(&self) -> Option<&str> {
+        self.tag_name.as_deref()
+    }
+}
 
 #[derive(Debug)]
 pub struct MemoryLimiter {
     current_usage: usize,
     max: usize,
+    warning_threshold: Option<usize>,
+    pending_warning: Option<usize>,
+    soft_max: Option<usize>,
+    soft_limit_exceeded: bool,
 }
 
 impl MemoryLimiter {
-    pub fn new_shared(max: usize) -> SharedMemoryLimiter {
-        Rc::new(RefCell::new(MemoryLimiter {
+    pub fn new_shared<S: Sharing>(max: usize) -> SharedMemoryLimiter<S> {
+        Shared::new(MemoryLimiter {
             max,
             current_usage: 0,
-        }))
+            warning_threshold: None,
+            pending_warning: None,
+            soft_max: None,
+            soft_limit_exceeded: false,
+        })
+    }
+
+    /// Makes the limiter record a warning each time the memory usage rises above the
+    /// `threshold`. The warning can be taken with [`take_warning`].
+    ///
+    /// [`take_warning`]: #method.take_warning
+    #[inline]
+    pub fn set_warning_threshold(&mut self, threshold: usize) {
+        self.warning_threshold = Some(threshold);
+    }
+
+    /// Returns the highest memory usage above the warning threshold since the last call, if
+    /// the threshold has been crossed.
+    #[inline]
+    pub fn take_warning(&mut self) -> Option<usize> {
+        self.pending_warning.take()
+    }
+
+    /// Sets the soft limit on the memory usage. Unlike the hard limit, exceeding the soft limit
+    /// doesn't fail the allocation, but is recorded, so the rewriter can give up the optional
+    /// features that consume memory.
+    #[inline]
+    pub fn set_soft_limit(&mut self, limit: usize) {
+        self.soft_max = Some(limit);
+    }
+
+    /// Returns `true` if the memory usage has ever risen above the soft limit.
+    #[inline]
+    pub fn is_soft_limit_exceeded(&self) -> bool {
+        self.soft_limit_exceeded
     }
 
     #[cfg(test)]
@@ -33,11 +137,28 @@ impl MemoryLimiter {
 
     #[inline]
     pub fn increase_usage(&mut self, byte_count: usize) -> Result<(), MemoryLimitExceededError> {
-        self.current_usage += byte_count;
+        let new_usage = self.current_usage + byte_count;
 
-        if self.current_usage > self.max {
-            Err(MemoryLimitExceededError)
+        // NOTE: usage is not increased on failure, so the rewriter can recover
+        // from the error (e.g. by spilling the token that doesn't fit into the limit).
+        if new_usage > self.max {
+            Err(MemoryLimitExceededError::new(self.max, new_usage))
         } else {
+            if let Some(threshold) = self.warning_threshold {
+                if self.current_usage <= threshold && new_usage > threshold {
+                    self.pending_warning = Some(
+                        self.pending_warning
+                            .map_or(new_usage, |usage| usage.max(new_usage)),
+                    );
+                }
+            }
+
+            if let Some(soft_max) = self.soft_max {
+                self.soft_limit_exceeded |= new_usage > soft_max;
+            }
+
+            self.current_usage = new_usage;
+
             Ok(())
         }
     }
@@ -61,8 +182,8 @@ mod tests {
 
     #[test]
     fn current_usage() {
-        let limiter = MemoryLimiter::new_shared(10);
-        let mut limiter = limiter.borrow_mut();
+        let limiter: SharedMemoryLimiter = MemoryLimiter::new_shared(10);
+        let mut limiter = limiter.lock();
 
         assert_eq!(limiter.current_usage(), 0);
 
@@ -77,7 +198,45 @@ mod tests {
 
         let err = limiter.increase_usage(15).unwrap_err();
 
-        assert_eq!(err, MemoryLimitExceededError);
+        assert_eq!(err, MemoryLimitExceededError::new(10, 19));
+        assert_eq!(limiter.current_usage(), 4);
+    }
+
+    #[test]
+    fn warning_threshold() {
+        let limiter: SharedMemoryLimiter = MemoryLimiter::new_shared(10);
+        let mut limiter = limiter.lock();
+
+        limiter.set_warning_threshold(5);
+
+        limiter.increase_usage(5).unwrap();
+        assert_eq!(limiter.take_warning(), None);
+
+        limiter.increase_usage(1).unwrap();
+        limiter.decrease_usage(3);
+        limiter.increase_usage(4).unwrap();
+        assert_eq!(limiter.take_warning(), Some(7));
+        assert_eq!(limiter.take_warning(), None);
+
+        limiter.increase_usage(1).unwrap();
+        assert_eq!(limiter.take_warning(), None);
+    }
+
+    #[test]
+    fn soft_limit() {
+        let limiter: SharedMemoryLimiter = MemoryLimiter::new_shared(10);
+        let mut limiter = limiter.lock();
+
+        limiter.set_soft_limit(5);
+
+        limiter.increase_usage(5).unwrap();
+        assert!(!limiter.is_soft_limit_exceeded());
+
+        limiter.increase_usage(2).unwrap();
+        assert!(limiter.is_soft_limit_exceeded());
+
+        limiter.decrease_usage(7);
+        assert!(limiter.is_soft_limit_exceeded());
     }
 
     #[test]
@@ -85,8 +244,8 @@ mod tests {
         expected = "Total preallocated memory size should be less than `MemorySettings::max_allowed_memory_usage`."
     )]
     fn preallocate() {
-        let limiter = MemoryLimiter::new_shared(10);
-        let mut limiter = limiter.borrow_mut();
+        let limiter: SharedMemoryLimiter = MemoryLimiter::new_shared(10);
+        let mut limiter = limiter.lock();
 
         limiter.preallocate(8);
         assert_eq!(limiter.current_usage(), 8);
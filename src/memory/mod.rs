@@ -1,7 +1,11 @@
 mod arena;
+#[cfg(feature = "basic_selectors")]
 mod limited_vec;
 mod limiter;
 
 pub use arena::Arena;
+#[cfg(feature = "basic_selectors")]
 pub use limited_vec::LimitedVec;
-pub use limiter::{MemoryLimitExceededError, MemoryLimiter, SharedMemoryLimiter};
+pub use limiter::{
+    BufferedTokenType, MemoryLimitExceededError, MemoryLimiter, SharedMemoryLimiter,
+};
@@ -1,6 +1,5 @@
 use crate::base::{Align, Range};
 use crate::html::{LocalNameHash, Namespace, TextType};
-use crate::parser::SharedAttributeBuffer;
 
 #[derive(Debug, Default, Copy, Clone)]
 pub struct AttributeOutline {
@@ -18,13 +17,48 @@ impl Align for AttributeOutline {
     }
 }
 
-#[derive(Debug)]
+/// A bloom filter of the attribute names of a start tag. The lexer adds each name once it's
+/// finished, so the selector matching VM can rule out the absent attributes without scanning
+/// the attribute buffer. Each name is hashed ASCII case-insensitively and sets two bits of
+/// the 64-bit filter, so for the typical number of attributes on an element the false
+/// positive rate stays within a few percent.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct AttributeNamesBloom(u64);
+
+impl AttributeNamesBloom {
+    #[inline]
+    fn bits(name: &[u8]) -> u64 {
+        // NOTE: FNV-1a, which is good enough for the short attribute names.
+        let hash = name.iter().fold(0x811c_9dc5_u32, |hash, &b| {
+            (hash ^ u32::from(b.to_ascii_lowercase())).wrapping_mul(0x0100_0193)
+        });
+
+        (1 << (hash & 63)) | (1 << ((hash >> 6) & 63))
+    }
+
+    #[inline]
+    pub fn add(&mut self, name: &[u8]) {
+        self.0 |= Self::bits(name);
+    }
+
+    /// Returns `false` if the tag definitely doesn't have the attribute with the `name`.
+    #[cfg(feature = "basic_selectors")]
+    #[inline]
+    pub fn may_contain(self, name: &[u8]) -> bool {
+        let bits = Self::bits(name);
+
+        self.0 & bits == bits
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum TagTokenOutline {
     StartTag {
         name: Range,
         name_hash: LocalNameHash,
         ns: Namespace,
-        attributes: SharedAttributeBuffer,
+        attributes: Vec<AttributeOutline>,
+        attr_names_bloom: AttributeNamesBloom,
         self_closing: bool,
     },
 
@@ -34,11 +68,23 @@ pub enum TagTokenOutline {
     },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum NonTagContentTokenOutline {
     Text(TextType),
     Comment(Range),
 
+    /// A part of a comment that is emitted before the whole comment has been parsed, so the
+    /// comment text doesn't need to be buffered.
+    CommentChunk {
+        text: Range,
+        first: bool,
+        last: bool,
+    },
+
+    /// A bogus comment that starts with `<?`, e.g. an XML declaration. The text of the comment
+    /// includes the leading `?` character.
+    ProcessingInstruction(Range),
+
     Doctype {
         name: Option<Range>,
         public_id: Option<Range>,
@@ -57,7 +103,7 @@ impl Align for TagTokenOutline {
                 name, attributes, ..
             } => {
                 name.align(offset);
-                attributes.borrow_mut().align(offset);
+                attributes.align(offset);
             }
             TagTokenOutline::EndTag { name, .. } => name.align(offset),
         }
@@ -68,7 +114,9 @@ impl Align for NonTagContentTokenOutline {
     #[inline]
     fn align(&mut self, offset: usize) {
         match self {
-            NonTagContentTokenOutline::Comment(text) => text.align(offset),
+            NonTagContentTokenOutline::Comment(text)
+            | NonTagContentTokenOutline::CommentChunk { text, .. }
+            | NonTagContentTokenOutline::ProcessingInstruction(text) => text.align(offset),
             NonTagContentTokenOutline::Doctype {
                 name,
                 public_id,
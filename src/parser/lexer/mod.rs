@@ -4,32 +4,33 @@ mod actions;
 mod conditions;
 mod lexeme;
 
-use crate::base::{Align, Range};
+use crate::base::{Align, Bytes, Range};
 use crate::html::{LocalNameHash, Namespace, TextType};
+use crate::memory::BufferedTokenType;
 use crate::parser::state_machine::{
     ActionError, ActionResult, FeedbackDirective, StateMachine, StateResult,
 };
-use crate::parser::{
-    ParserDirective, ParsingAmbiguityError, TreeBuilderFeedback, TreeBuilderSimulator,
-};
-use crate::rewriter::RewritingError;
-use std::cell::RefCell;
-use std::rc::Rc;
+use crate::parser::{ParserContext, ParserDirective, ParsingAmbiguityError, TreeBuilderFeedback};
+use crate::rewriter::{RewritingError, SpillSettings};
+use std::marker::PhantomData;
 
 pub use self::lexeme::*;
 
-const DEFAULT_ATTR_BUFFER_CAPACITY: usize = 256;
-
 pub trait LexemeSink {
     fn handle_tag(&mut self, lexeme: &TagLexeme) -> Result<ParserDirective, RewritingError>;
     fn handle_non_tag_content(
         &mut self,
         lexeme: &NonTagContentLexeme,
     ) -> Result<(), RewritingError>;
+    fn handle_spilled_tag(
+        &mut self,
+        lexeme: &TagLexeme,
+        forced: bool,
+    ) -> Result<bool, RewritingError>;
+    fn should_capture(&self, token: &NonTagContentTokenOutline) -> bool;
 }
 
-pub type State<S> = fn(&mut Lexer<S>, &[u8]) -> StateResult;
-pub type SharedAttributeBuffer = Rc<RefCell<Vec<AttributeOutline>>>;
+pub type State<S> = fn(&mut Lexer<S>, &mut ParserContext<S>, &[u8]) -> StateResult;
 
 pub struct Lexer<S: LexemeSink> {
     next_pos: usize,
@@ -38,21 +39,24 @@ pub struct Lexer<S: LexemeSink> {
     token_part_start: usize,
     is_state_enter: bool,
     cdata_allowed: bool,
-    lexeme_sink: S,
     state: State<S>,
     current_tag_token: Option<TagTokenOutline>,
     current_non_tag_content_token: Option<NonTagContentTokenOutline>,
     current_attr: Option<AttributeOutline>,
     last_start_tag_name_hash: LocalNameHash,
     closing_quote: u8,
-    attr_buffer: SharedAttributeBuffer,
-    tree_builder_simulator: Rc<RefCell<TreeBuilderSimulator>>,
+    attr_buffer: Vec<AttributeOutline>,
     last_text_type: TextType,
     feedback_directive: FeedbackDirective,
+    spilling: bool,
+    track_attributes: bool,
+    stream_comments: bool,
+    comment_streamed: bool,
+    sink: PhantomData<S>,
 }
 
 impl<S: LexemeSink> Lexer<S> {
-    pub fn new(lexeme_sink: S, tree_builder_simulator: Rc<RefCell<TreeBuilderSimulator>>) -> Self {
+    pub fn new(attr_buffer_capacity: usize, track_attributes: bool, stream_comments: bool) -> Self {
         Lexer {
             next_pos: 0,
             is_last_input: false,
@@ -60,31 +64,141 @@ impl<S: LexemeSink> Lexer<S> {
             token_part_start: 0,
             is_state_enter: true,
             cdata_allowed: false,
-            lexeme_sink,
             state: Lexer::data_state,
             current_tag_token: None,
             current_non_tag_content_token: None,
             current_attr: None,
             last_start_tag_name_hash: LocalNameHash::default(),
             closing_quote: b'"',
-            attr_buffer: Rc::new(RefCell::new(Vec::with_capacity(
-                DEFAULT_ATTR_BUFFER_CAPACITY,
-            ))),
-            tree_builder_simulator,
+            attr_buffer: Vec::with_capacity(attr_buffer_capacity),
             last_text_type: TextType::Data,
             feedback_directive: FeedbackDirective::None,
+            spilling: false,
+            track_attributes,
+            stream_comments,
+            comment_streamed: false,
+            sink: PhantomData,
+        }
+    }
+
+    /// Returns `true` if the lexer is in the same state as a new one, i.e. it parses data text
+    /// and doesn't have any unfinished token.
+    pub fn is_at_rest(&self) -> bool {
+        self.current_tag_token.is_none()
+            && self.current_non_tag_content_token.is_none()
+            && !self.spilling
+            && self.last_text_type == TextType::Data
+    }
+
+    /// Returns the type of the token that is currently being parsed along with the name of the
+    /// tag, if the token is a tag and its name has already been parsed. Text isn't reported,
+    /// since it's never buffered as a whole.
+    pub fn current_token_info<'i>(
+        &self,
+        input: &'i [u8],
+    ) -> Option<(BufferedTokenType, Option<Bytes<'i>>)> {
+        use NonTagContentTokenOutline::*;
+
+        match self.current_non_tag_content_token {
+            Some(Comment(_)) => Some((BufferedTokenType::Comment, None)),
+            Some(ProcessingInstruction(_)) => {
+                Some((BufferedTokenType::ProcessingInstruction, None))
+            }
+            Some(Doctype { .. }) => Some((BufferedTokenType::Doctype, None)),
+            Some(Text(_)) | Some(CommentChunk { .. }) | Some(Eof) => None,
+            None => self.current_tag_token.as_ref().map(|token| {
+                let (token_type, name) = match *token {
+                    TagTokenOutline::StartTag { name, .. } => (BufferedTokenType::StartTag, name),
+                    TagTokenOutline::EndTag { name, .. } => (BufferedTokenType::EndTag, name),
+                };
+
+                let name = input
+                    .get(name.start..name.end)
+                    .filter(|name| !name.is_empty())
+                    .map(Bytes::from);
+
+                (token_type, name)
+            }),
         }
     }
 
+    /// Switches the lexer into the mode in which the token that is currently being parsed is
+    /// passed through as is, without being buffered. Returns `false` if spilling is neither
+    /// enabled for the token in the `settings`, nor safe because the token can be observed by
+    /// the sink.
+    pub fn spill_current_token(
+        &mut self,
+        context: &mut ParserContext<S>,
+        input: &[u8],
+        settings: &SpillSettings,
+    ) -> Result<bool, RewritingError> {
+        use NonTagContentTokenOutline::*;
+
+        // NOTE: comments and doctypes that are not captured by the sink can't
+        // be observed by anyone, so it's always safe to spill them.
+        let spill = match self.current_non_tag_content_token {
+            Some(ref token @ Comment(_)) | Some(ref token @ ProcessingInstruction(_)) => {
+                settings.comments || !context.output_sink.should_capture(token)
+            }
+            Some(ref token @ Doctype { .. }) => {
+                settings.doctypes || !context.output_sink.should_capture(token)
+            }
+            Some(Text(_)) | Some(CommentChunk { .. }) | Some(Eof) => false,
+            None => match self.current_tag_token.take() {
+                Some(token) => self.spill_tag(context, input, token, settings.start_tags)?,
+                None => false,
+            },
+        };
+
+        if spill {
+            self.spilling = true;
+        }
+
+        Ok(spill)
+    }
+
+    fn spill_tag(
+        &mut self,
+        context: &mut ParserContext<S>,
+        input: &[u8],
+        mut token: TagTokenOutline,
+        forced: bool,
+    ) -> Result<bool, RewritingError> {
+        // NOTE: start tag can be spilled only if we've already parsed
+        // its name, otherwise we can't report the tag to the sink.
+        let can_report = match token {
+            TagTokenOutline::StartTag { name, .. } => name.end > name.start,
+            TagTokenOutline::EndTag { .. } => false,
+        };
+
+        if !can_report {
+            self.current_tag_token = Some(token);
+
+            return Ok(false);
+        }
+
+        if let TagTokenOutline::StartTag { ref mut ns, .. } = token {
+            *ns = context.tree_builder_simulator.current_ns();
+        }
+
+        let lexeme = self.create_lexeme_with_raw(input, token, input.len());
+        let result = context.output_sink.handle_spilled_tag(&lexeme, forced);
+
+        self.current_tag_token = Some(lexeme.token_outline);
+
+        result
+    }
+
     fn try_get_tree_builder_feedback(
         &mut self,
+        context: &mut ParserContext<S>,
         token: &TagTokenOutline,
     ) -> Result<Option<TreeBuilderFeedback>, ParsingAmbiguityError> {
         Ok(match self.feedback_directive.take() {
             FeedbackDirective::ApplyUnhandledFeedback(feedback) => Some(feedback),
             FeedbackDirective::Skip => None,
             FeedbackDirective::None => Some({
-                let mut simulator = self.tree_builder_simulator.borrow_mut();
+                let simulator = &mut context.tree_builder_simulator;
 
                 match *token {
                     TagTokenOutline::StartTag { name_hash, .. } => {
@@ -98,37 +212,175 @@ impl<S: LexemeSink> Lexer<S> {
         })
     }
 
-    fn handle_tree_builder_feedback(&mut self, feedback: TreeBuilderFeedback, lexeme: &TagLexeme) {
+    fn handle_tree_builder_feedback(
+        &mut self,
+        context: &mut ParserContext<S>,
+        feedback: TreeBuilderFeedback,
+        lexeme: &TagLexeme,
+    ) {
         match feedback {
             TreeBuilderFeedback::SwitchTextType(text_type) => self.set_last_text_type(text_type),
             TreeBuilderFeedback::SetAllowCdata(cdata_allowed) => self.cdata_allowed = cdata_allowed,
             TreeBuilderFeedback::RequestLexeme(mut callback) => {
-                let feedback = callback(&mut self.tree_builder_simulator.borrow_mut(), lexeme);
+                let feedback = callback(&mut context.tree_builder_simulator, lexeme);
 
-                self.handle_tree_builder_feedback(feedback, lexeme);
+                self.handle_tree_builder_feedback(context, feedback, lexeme);
             }
             TreeBuilderFeedback::None => (),
         }
     }
 
+    /// Takes the non-tag content token that is being parsed. The comments whose chunks have
+    /// already been emitted are finished with the last chunk.
     #[inline]
-    fn emit_lexeme(&mut self, lexeme: &NonTagContentLexeme) -> ActionResult {
+    fn take_current_non_tag_content_token(&mut self) -> Option<NonTagContentTokenOutline> {
+        use NonTagContentTokenOutline::*;
+
+        match self.current_non_tag_content_token.take() {
+            Some(Comment(text)) if self.comment_streamed => {
+                self.comment_streamed = false;
+
+                Some(CommentChunk {
+                    text,
+                    first: false,
+                    last: true,
+                })
+            }
+            token => token,
+        }
+    }
+
+    /// Emits the text of the comment that is being parsed as a comment chunk, so the parsed
+    /// text doesn't need to be buffered until the next input.
+    fn emit_comment_chunk(&mut self, context: &mut ParserContext<S>, input: &[u8]) -> ActionResult {
+        use NonTagContentTokenOutline::*;
+
+        if !self.stream_comments || self.spilling {
+            return Ok(());
+        }
+
+        let text = match self.current_non_tag_content_token {
+            Some(Comment(text)) => text,
+            _ => return Ok(()),
+        };
+
+        // NOTE: the chunk ends after a character that can't be a part of the comment closing
+        // sequence or of a multibyte character in any of the supported encodings. The rest
+        // of the text is parsed again along with the next input.
+        let chunk_end = match input[self.token_part_start..]
+            .iter()
+            .rposition(|&ch| ch < 0x30 && ch != b'-' && ch != b'!')
+        {
+            Some(idx) => self.token_part_start + idx + 1,
+            None => return Ok(()),
+        };
+
+        let token = CommentChunk {
+            text: Range {
+                start: self.token_part_start,
+                end: chunk_end,
+            },
+            first: !self.comment_streamed,
+            last: false,
+        };
+
+        let lexeme = self.create_lexeme_with_raw(input, Some(token), chunk_end);
+
+        self.emit_lexeme(context, &lexeme)?;
+
+        self.comment_streamed = true;
+        self.token_part_start = chunk_end;
+        self.current_non_tag_content_token = Some(Comment(Range {
+            start: chunk_end,
+            end: text.end.max(chunk_end),
+        }));
+
+        Ok(())
+    }
+
+    #[inline]
+    fn emit_lexeme(
+        &mut self,
+        context: &mut ParserContext<S>,
+        lexeme: &NonTagContentLexeme,
+    ) -> ActionResult {
         trace!(@output lexeme);
 
         self.lexeme_start = lexeme.raw_range().end;
 
-        self.lexeme_sink
+        context
+            .output_sink
             .handle_non_tag_content(lexeme)
             .map_err(ActionError::RewritingError)
     }
 
+    /// Emits the raw bytes of the spilled token as a lexeme without a token, so they are passed
+    /// through to the output as is.
+    #[inline]
+    fn emit_spilled_lexeme(
+        &mut self,
+        context: &mut ParserContext<S>,
+        input: &[u8],
+        raw_end: usize,
+    ) -> ActionResult {
+        self.spilling = false;
+
+        let lexeme = self.create_lexeme_with_raw(input, None, raw_end);
+
+        self.emit_lexeme(context, &lexeme)
+    }
+
+    fn emit_spilled_tag(
+        &mut self,
+        context: &mut ParserContext<S>,
+        input: &[u8],
+        token: TagTokenOutline,
+        feedback: Option<TreeBuilderFeedback>,
+    ) -> ActionResult {
+        self.set_last_text_type(TextType::Data);
+
+        match feedback {
+            Some(TreeBuilderFeedback::SwitchTextType(text_type)) => {
+                self.set_last_text_type(text_type)
+            }
+            Some(TreeBuilderFeedback::SetAllowCdata(cdata_allowed)) => {
+                self.cdata_allowed = cdata_allowed
+            }
+            // NOTE: parts of the spilled tag are not available, so we can't
+            // provide the lexeme to the tree builder simulator.
+            Some(TreeBuilderFeedback::RequestLexeme(_))
+            | Some(TreeBuilderFeedback::None)
+            | None => (),
+        }
+
+        if let TagTokenOutline::StartTag { name_hash, .. } = token {
+            self.last_start_tag_name_hash = name_hash;
+        }
+
+        self.reuse_attr_buffer(token);
+        self.emit_spilled_lexeme(context, input, self.pos() + 1)
+    }
+
+    /// Takes back the attribute buffer of the start tag that has been emitted or spilled, so
+    /// the buffer is reused for the following start tags.
     #[inline]
-    fn emit_tag_lexeme(&mut self, lexeme: &TagLexeme) -> Result<ParserDirective, RewritingError> {
+    fn reuse_attr_buffer(&mut self, token: TagTokenOutline) {
+        if let TagTokenOutline::StartTag { attributes, .. } = token {
+            self.attr_buffer = attributes;
+        }
+    }
+
+    #[inline]
+    fn emit_tag_lexeme(
+        &mut self,
+        context: &mut ParserContext<S>,
+        lexeme: &TagLexeme,
+    ) -> Result<ParserDirective, RewritingError> {
         trace!(@output lexeme);
 
         self.lexeme_start = lexeme.raw_range().end;
 
-        self.lexeme_sink.handle_tag(lexeme)
+        context.output_sink.handle_tag(lexeme)
     }
 
     #[inline]
@@ -187,14 +439,32 @@ impl<S: LexemeSink> StateMachine for Lexer<S> {
 
     #[inline]
     fn get_consumed_byte_count(&self, _input: &[u8]) -> usize {
-        self.lexeme_start
+        // NOTE: bytes of the spilled token are passed through as soon as
+        // they are parsed, so there is no need to preserve them for the
+        // next input.
+        if self.spilling {
+            self.pos()
+        } else {
+            self.lexeme_start
+        }
+    }
+
+    #[inline]
+    fn emit_streamed_token_part(
+        &mut self,
+        context: &mut ParserContext<S>,
+        input: &[u8],
+    ) -> ActionResult {
+        self.emit_comment_chunk(context, input)
     }
 
     fn adjust_for_next_input(&mut self) {
-        self.token_part_start.align(self.lexeme_start);
-        self.current_tag_token.align(self.lexeme_start);
-        self.current_non_tag_content_token.align(self.lexeme_start);
-        self.current_attr.align(self.lexeme_start);
+        let offset = self.get_consumed_byte_count(&[]);
+
+        self.token_part_start.align(offset);
+        self.current_tag_token.align(offset);
+        self.current_non_tag_content_token.align(offset);
+        self.current_attr.align(offset);
 
         self.lexeme_start = 0;
     }
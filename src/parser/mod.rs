@@ -9,18 +9,17 @@ use self::lexer::Lexer;
 use self::state_machine::{ActionError, ParsingTermination, StateMachine};
 use self::tag_scanner::TagScanner;
 use self::tree_builder_simulator::{TreeBuilderFeedback, TreeBuilderSimulator};
-use crate::html::{LocalName, Namespace};
-use crate::rewriter::RewritingError;
+use crate::base::Bytes;
+use crate::memory::BufferedTokenType;
+use crate::rewriter::{RewritingError, SpillSettings};
 use cfg_if::cfg_if;
-use std::cell::RefCell;
-use std::rc::Rc;
 
 pub use self::lexer::{
-    AttributeOutline, Lexeme, LexemeSink, NonTagContentLexeme, NonTagContentTokenOutline,
-    SharedAttributeBuffer, TagLexeme, TagTokenOutline,
+    AttributeNamesBloom, AttributeOutline, Lexeme, LexemeSink, NonTagContentLexeme,
+    NonTagContentTokenOutline, TagLexeme, TagTokenOutline,
 };
 pub use self::tag_scanner::TagHintSink;
-pub use self::tree_builder_simulator::ParsingAmbiguityError;
+pub use self::tree_builder_simulator::{content_text_type, ParsingAmbiguityError};
 
 // NOTE: tag scanner can implicitly force parser to switch to
 // the lexer mode if it fails to get tree builder feedback. It's up
@@ -32,43 +31,23 @@ pub enum ParserDirective {
     Lex,
 }
 
-impl<S: LexemeSink> LexemeSink for Rc<RefCell<S>> {
-    #[inline]
-    fn handle_tag(&mut self, lexeme: &TagLexeme) -> Result<ParserDirective, RewritingError> {
-        self.borrow_mut().handle_tag(lexeme)
-    }
-
-    #[inline]
-    fn handle_non_tag_content(
-        &mut self,
-        lexeme: &NonTagContentLexeme,
-    ) -> Result<(), RewritingError> {
-        self.borrow_mut().handle_non_tag_content(lexeme)
-    }
-}
-
-impl<S: TagHintSink> TagHintSink for Rc<RefCell<S>> {
-    #[inline]
-    fn handle_start_tag_hint(
-        &mut self,
-        name: LocalName,
-        ns: Namespace,
-    ) -> Result<ParserDirective, RewritingError> {
-        self.borrow_mut().handle_start_tag_hint(name, ns)
-    }
+pub trait ParserOutputSink: LexemeSink + TagHintSink {}
 
-    #[inline]
-    fn handle_end_tag_hint(&mut self, name: LocalName) -> Result<ParserDirective, RewritingError> {
-        self.borrow_mut().handle_end_tag_hint(name)
-    }
+/// The state that is shared by the lexer and the tag scanner. It's owned by the parser and
+/// passed to the state machine that is currently parsing the input.
+pub struct ParserContext<S> {
+    output_sink: S,
+    tree_builder_simulator: TreeBuilderSimulator,
 }
 
-pub trait ParserOutputSink: LexemeSink + TagHintSink {}
-
 pub struct Parser<S: ParserOutputSink> {
-    lexer: Lexer<Rc<RefCell<S>>>,
-    tag_scanner: TagScanner<Rc<RefCell<S>>>,
+    lexer: Lexer<S>,
+    tag_scanner: TagScanner<S>,
+    context: ParserContext<S>,
     current_directive: ParserDirective,
+    strict: bool,
+    attr_buffer_capacity: usize,
+    stream_comments: bool,
 }
 
 // NOTE: dynamic dispatch can't be used for the StateMachine trait
@@ -85,26 +64,104 @@ macro_rules! with_current_sm {
 
 impl<S: ParserOutputSink> Parser<S> {
     pub fn new(
-        output_sink: &Rc<RefCell<S>>,
+        output_sink: S,
         initial_directive: ParserDirective,
         strict: bool,
+        attr_buffer_capacity: usize,
+        track_attributes: bool,
+        stream_comments: bool,
     ) -> Self {
-        let tree_builder_simulator = Rc::new(RefCell::new(TreeBuilderSimulator::new(strict)));
-
         Parser {
-            lexer: Lexer::new(Rc::clone(output_sink), Rc::clone(&tree_builder_simulator)),
-            tag_scanner: TagScanner::new(
-                Rc::clone(output_sink),
-                Rc::clone(&tree_builder_simulator),
-            ),
+            lexer: Lexer::new(attr_buffer_capacity, track_attributes, stream_comments),
+            tag_scanner: TagScanner::new(),
+            context: ParserContext {
+                output_sink,
+                tree_builder_simulator: TreeBuilderSimulator::new(strict),
+            },
             current_directive: initial_directive,
+            strict,
+            attr_buffer_capacity,
+            stream_comments,
+        }
+    }
+
+    /// Resets the parser to the initial state, keeping the output sink, so the following input
+    /// is parsed from scratch.
+    pub fn reset(&mut self, initial_directive: ParserDirective, track_attributes: bool) {
+        self.lexer = Lexer::new(
+            self.attr_buffer_capacity,
+            track_attributes,
+            self.stream_comments,
+        );
+        self.tag_scanner = TagScanner::new();
+        self.context.tree_builder_simulator = TreeBuilderSimulator::new(self.strict);
+        self.current_directive = initial_directive;
+    }
+
+    #[inline]
+    pub fn output_sink(&self) -> &S {
+        &self.context.output_sink
+    }
+
+    #[inline]
+    pub fn output_sink_mut(&mut self) -> &mut S {
+        &mut self.context.output_sink
+    }
+
+    /// Switches the parser that hasn't parsed any input yet to the lexer mode.
+    #[inline]
+    pub fn start_with_lexer(&mut self) {
+        self.current_directive = ParserDirective::Lex;
+    }
+
+    /// Returns `true` if the parser is in the lexer mode and can be replaced by a new parser
+    /// in this mode, because all the parsed tokens have been finished.
+    #[inline]
+    pub fn is_at_rest(&self) -> bool {
+        match self.current_directive {
+            ParserDirective::Lex => {
+                self.lexer.is_at_rest() && self.context.tree_builder_simulator.is_at_rest()
+            }
+            ParserDirective::WherePossibleScanForTagsOnly => false,
+        }
+    }
+
+    /// Returns the type of the token that is currently being parsed and the name of the tag.
+    #[inline]
+    pub fn current_token_info<'i>(
+        &self,
+        input: &'i [u8],
+    ) -> Option<(BufferedTokenType, Option<Bytes<'i>>)> {
+        match self.current_directive {
+            ParserDirective::Lex => self.lexer.current_token_info(input),
+            // NOTE: the tag scanner doesn't track the tokens.
+            ParserDirective::WherePossibleScanForTagsOnly => None,
+        }
+    }
+
+    /// Makes the parser pass the token that is currently being parsed through to the output
+    /// without buffering it. Returns `false` if the token can't be spilled.
+    #[inline]
+    pub fn spill_current_token(
+        &mut self,
+        input: &[u8],
+        settings: &SpillSettings,
+    ) -> Result<bool, RewritingError> {
+        match self.current_directive {
+            ParserDirective::Lex => {
+                self.lexer
+                    .spill_current_token(&mut self.context, input, settings)
+            }
+            // NOTE: spilling is supported only in the lexer mode.
+            ParserDirective::WherePossibleScanForTagsOnly => Ok(false),
         }
     }
 
     pub fn parse(&mut self, input: &[u8], last: bool) -> Result<usize, RewritingError> {
         use ActionError::*;
 
-        let mut parse_result = with_current_sm!(self, sm.run_parsing_loop(input, last));
+        let mut parse_result =
+            with_current_sm!(self, sm.run_parsing_loop(&mut self.context, input, last));
 
         loop {
             match parse_result {
@@ -121,8 +178,10 @@ impl<S: ParserOutputSink> Parser<S> {
 
                     trace!(@continue_from_bookmark sm_bookmark, self.current_directive, input);
 
-                    parse_result =
-                        with_current_sm!(self, sm.continue_from_bookmark(input, last, sm_bookmark));
+                    parse_result = with_current_sm!(
+                        self,
+                        sm.continue_from_bookmark(&mut self.context, input, last, sm_bookmark)
+                    );
                 }
                 Err(ParsingTermination::ActionError(RewritingError(err))) => return Err(err),
                 Ok(unreachable) => match unreachable {},
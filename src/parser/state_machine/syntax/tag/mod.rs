@@ -7,7 +7,7 @@ define_state_group!(tag_states_group = {
         b'!'  => ( unmark_tag_start; --> markup_declaration_open_state )
         b'/'  => ( --> end_tag_open_state )
         alpha => ( create_start_tag; start_token_part; update_tag_name_hash; --> tag_name_state )
-        b'?'  => ( unmark_tag_start; create_comment; start_token_part; --> bogus_comment_state )
+        b'?'  => ( unmark_tag_start; create_processing_instruction; start_token_part; --> bogus_comment_state )
         eof   => ( emit_text?; emit_eof?; )
         _     => ( unmark_tag_start; emit_text?; reconsume in data_state )
     }
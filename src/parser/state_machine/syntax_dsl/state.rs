@@ -6,13 +6,13 @@ macro_rules! state {
 
         $($rest:tt)*
     ) => {
-        fn $name(&mut self, input: &[u8]) -> StateResult {
+        fn $name(&mut self, context: &mut Self::Context, input: &[u8]) -> StateResult {
             // NOTE: clippy complains about some states that break the loop in each match arm
             #[allow(clippy::never_loop)]
             loop {
                 let ch = self.consume_ch(input);
 
-                state_body!(|[self, input, ch]|> [$($arms)*], [$($($enter_actions)*)*]);
+                state_body!(|[self, context, input, ch]|> [$($arms)*], [$($($enter_actions)*)*]);
             }
         }
 
@@ -2,47 +2,49 @@ use super::*;
 use crate::parser::state_machine::{ActionError, ActionResult, StateMachineActions};
 
 impl<S: TagHintSink> StateMachineActions for TagScanner<S> {
+    type Context = ParserContext<S>;
+
     impl_common_sm_actions!();
 
     #[inline]
-    fn create_start_tag(&mut self, _input: &[u8]) {
+    fn create_start_tag(&mut self, _context: &mut ParserContext<S>, _input: &[u8]) {
         self.tag_name_start = self.pos();
         self.tag_name_hash = LocalNameHash::new();
     }
 
     #[inline]
-    fn create_end_tag(&mut self, _input: &[u8]) {
+    fn create_end_tag(&mut self, _context: &mut ParserContext<S>, _input: &[u8]) {
         self.tag_name_start = self.pos();
         self.tag_name_hash = LocalNameHash::new();
         self.is_in_end_tag = true;
     }
 
     #[inline]
-    fn mark_tag_start(&mut self, _input: &[u8]) {
+    fn mark_tag_start(&mut self, _context: &mut ParserContext<S>, _input: &[u8]) {
         self.tag_start = Some(self.pos());
     }
 
     #[inline]
-    fn unmark_tag_start(&mut self, _input: &[u8]) {
+    fn unmark_tag_start(&mut self, _context: &mut ParserContext<S>, _input: &[u8]) {
         self.tag_start = None;
     }
 
     #[inline]
-    fn update_tag_name_hash(&mut self, input: &[u8]) {
+    fn update_tag_name_hash(&mut self, _context: &mut ParserContext<S>, input: &[u8]) {
         if let Some(ch) = input.get(self.pos()).copied() {
             self.tag_name_hash.update(ch);
         }
     }
 
     #[inline]
-    fn finish_tag_name(&mut self, input: &[u8]) -> ActionResult {
+    fn finish_tag_name(&mut self, context: &mut ParserContext<S>, input: &[u8]) -> ActionResult {
         let tag_start = self
             .tag_start
             .take()
             .expect("Tag start should be set at this point");
 
         let unhandled_feedback = self
-            .try_apply_tree_builder_feedback()
+            .try_apply_tree_builder_feedback(context)
             .map_err(ActionError::from)?;
 
         if let Some(unhandled_feedback) = unhandled_feedback {
@@ -54,7 +56,7 @@ impl<S: TagHintSink> StateMachineActions for TagScanner<S> {
         }
 
         match self
-            .emit_tag_hint(input)
+            .emit_tag_hint(context, input)
             .map_err(ActionError::RewritingError)?
         {
             ParserDirective::WherePossibleScanForTagsOnly => Ok(()),
@@ -67,7 +69,7 @@ impl<S: TagHintSink> StateMachineActions for TagScanner<S> {
     }
 
     #[inline]
-    fn emit_tag(&mut self, _input: &[u8]) -> ActionResult {
+    fn emit_tag(&mut self, _context: &mut ParserContext<S>, _input: &[u8]) -> ActionResult {
         // NOTE: exit from any non-initial text parsing mode always happens on tag emission
         // (except for CDATA, but there is a special action to take care of it).
         let text_type = self
@@ -92,6 +94,7 @@ impl<S: TagHintSink> StateMachineActions for TagScanner<S> {
     noop_action!(
         create_doctype,
         create_comment,
+        create_processing_instruction,
         start_token_part,
         mark_comment_text_end,
         set_force_quirks,
@@ -106,7 +109,12 @@ impl<S: TagHintSink> StateMachineActions for TagScanner<S> {
     );
 
     #[inline]
-    fn shift_comment_text_end_by(&mut self, _input: &[u8], _offset: usize) {
+    fn shift_comment_text_end_by(
+        &mut self,
+        _context: &mut ParserContext<S>,
+        _input: &[u8],
+        _offset: usize,
+    ) {
         trace!(@noop);
     }
 }
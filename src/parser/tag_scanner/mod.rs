@@ -4,14 +4,11 @@ mod conditions;
 
 use crate::base::{Align, Bytes, Range};
 use crate::html::{LocalName, LocalNameHash, Namespace, TextType};
-use crate::parser::state_machine::{FeedbackDirective, StateMachine, StateResult};
-use crate::parser::{
-    ParserDirective, ParsingAmbiguityError, TreeBuilderFeedback, TreeBuilderSimulator,
-};
+use crate::parser::state_machine::{ActionResult, FeedbackDirective, StateMachine, StateResult};
+use crate::parser::{ParserContext, ParserDirective, ParsingAmbiguityError, TreeBuilderFeedback};
 use crate::rewriter::RewritingError;
-use std::cell::RefCell;
 use std::cmp::min;
-use std::rc::Rc;
+use std::marker::PhantomData;
 
 pub trait TagHintSink {
     fn handle_start_tag_hint(
@@ -22,7 +19,7 @@ pub trait TagHintSink {
     fn handle_end_tag_hint(&mut self, name: LocalName) -> Result<ParserDirective, RewritingError>;
 }
 
-pub type State<S> = fn(&mut TagScanner<S>, &[u8]) -> StateResult;
+pub type State<S> = fn(&mut TagScanner<S>, &mut ParserContext<S>, &[u8]) -> StateResult;
 
 /// Tag scanner skips the majority of lexer operations and, thus,
 /// is faster. It also has much less requirements for buffering which makes it more
@@ -46,19 +43,15 @@ pub struct TagScanner<S: TagHintSink> {
     last_start_tag_name_hash: LocalNameHash,
     is_state_enter: bool,
     cdata_allowed: bool,
-    tag_hint_sink: S,
     state: State<S>,
     closing_quote: u8,
-    tree_builder_simulator: Rc<RefCell<TreeBuilderSimulator>>,
     pending_text_type_change: Option<TextType>,
     last_text_type: TextType,
+    sink: PhantomData<S>,
 }
 
 impl<S: TagHintSink> TagScanner<S> {
-    pub fn new(
-        tag_hint_sink: S,
-        tree_builder_simulator: Rc<RefCell<TreeBuilderSimulator>>,
-    ) -> Self {
+    pub fn new() -> Self {
         TagScanner {
             next_pos: 0,
             is_last_input: false,
@@ -70,16 +63,19 @@ impl<S: TagHintSink> TagScanner<S> {
             last_start_tag_name_hash: LocalNameHash::default(),
             is_state_enter: true,
             cdata_allowed: false,
-            tag_hint_sink,
             state: TagScanner::data_state,
             closing_quote: b'"',
-            tree_builder_simulator,
             pending_text_type_change: None,
             last_text_type: TextType::Data,
+            sink: PhantomData,
         }
     }
 
-    fn emit_tag_hint(&mut self, input: &[u8]) -> Result<ParserDirective, RewritingError> {
+    fn emit_tag_hint(
+        &mut self,
+        context: &mut ParserContext<S>,
+        input: &[u8],
+    ) -> Result<ParserDirective, RewritingError> {
         let name_range = Range {
             start: self.tag_name_start,
             end: self.pos(),
@@ -92,21 +88,22 @@ impl<S: TagHintSink> TagScanner<S> {
 
         if self.is_in_end_tag {
             self.is_in_end_tag = false;
-            self.tag_hint_sink.handle_end_tag_hint(name)
+            context.output_sink.handle_end_tag_hint(name)
         } else {
             self.last_start_tag_name_hash = self.tag_name_hash;
 
-            let ns = self.tree_builder_simulator.borrow_mut().current_ns();
+            let ns = context.tree_builder_simulator.start_tag_ns();
 
-            self.tag_hint_sink.handle_start_tag_hint(name, ns)
+            context.output_sink.handle_start_tag_hint(name, ns)
         }
     }
 
     #[inline]
     fn try_apply_tree_builder_feedback(
         &mut self,
+        context: &mut ParserContext<S>,
     ) -> Result<Option<TreeBuilderFeedback>, ParsingAmbiguityError> {
-        let mut tree_builder_simulator = self.tree_builder_simulator.borrow_mut();
+        let tree_builder_simulator = &mut context.tree_builder_simulator;
 
         let feedback = if self.is_in_end_tag {
             tree_builder_simulator.get_feedback_for_end_tag(self.tag_name_hash)
@@ -173,6 +170,18 @@ impl<S: TagHintSink> StateMachine for TagScanner<S> {
         }
     }
 
+    #[inline]
+    fn emit_streamed_token_part(
+        &mut self,
+        _context: &mut ParserContext<S>,
+        _input: &[u8],
+    ) -> ActionResult {
+        // NOTE: the tag scanner doesn't produce tokens.
+        trace!(@noop);
+
+        Ok(())
+    }
+
     fn adjust_for_next_input(&mut self) {
         if let Some(tag_start) = self.tag_start {
             self.tag_name_start.align(tag_start);
@@ -144,6 +144,12 @@ impl Default for AmbiguityGuard {
 }
 
 impl AmbiguityGuard {
+    /// Returns `true` if the guard doesn't track any ambiguous context.
+    #[inline]
+    pub fn is_at_rest(&self) -> bool {
+        matches!(self.state, State::Default)
+    }
+
     pub fn track_start_tag(
         &mut self,
         tag_name: LocalNameHash,
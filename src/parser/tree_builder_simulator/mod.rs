@@ -27,7 +27,9 @@ const DEFAULT_NS_STACK_CAPACITY: usize = 256;
 pub enum TreeBuilderFeedback {
     SwitchTextType(TextType),
     SetAllowCdata(bool),
-    RequestLexeme(Box<dyn FnMut(&mut TreeBuilderSimulator, &TagLexeme) -> TreeBuilderFeedback>),
+    RequestLexeme(
+        Box<dyn FnMut(&mut TreeBuilderSimulator, &TagLexeme) -> TreeBuilderFeedback + Send>,
+    ),
     None,
 }
 
@@ -40,7 +42,7 @@ impl From<TextType> for TreeBuilderFeedback {
 
 #[inline]
 fn request_lexeme(
-    callback: impl FnMut(&mut TreeBuilderSimulator, &TagLexeme) -> TreeBuilderFeedback + 'static,
+    callback: impl FnMut(&mut TreeBuilderSimulator, &TagLexeme) -> TreeBuilderFeedback + Send + 'static,
 ) -> TreeBuilderFeedback {
     TreeBuilderFeedback::RequestLexeme(Box::new(callback))
 }
@@ -69,23 +71,30 @@ fn eq_case_insensitive(actual: &Bytes, expected: &[u8]) -> bool {
     true
 }
 
+/// Returns the text type the content of the HTML element with the `tag_name` is parsed as,
+/// if it's not parsed as regular markup.
 #[inline]
-fn get_text_type_adjustment(tag_name: LocalNameHash) -> TreeBuilderFeedback {
+pub fn content_text_type(tag_name: LocalNameHash) -> Option<TextType> {
     use TextType::*;
 
     if tag_is_one_of!(tag_name, [Textarea, Title]) {
-        RCData.into()
+        Some(RCData)
     } else if tag_name == Tag::Plaintext {
-        PlainText.into()
+        Some(PlainText)
     } else if tag_name == Tag::Script {
-        ScriptData.into()
+        Some(ScriptData)
     } else if tag_is_one_of!(tag_name, [Style, Iframe, Xmp, Noembed, Noframes, Noscript]) {
-        RawText.into()
+        Some(RawText)
     } else {
-        TreeBuilderFeedback::None
+        None
     }
 }
 
+#[inline]
+fn get_text_type_adjustment(tag_name: LocalNameHash) -> TreeBuilderFeedback {
+    content_text_type(tag_name).map_or(TreeBuilderFeedback::None, TreeBuilderFeedback::from)
+}
+
 #[inline]
 fn causes_foreign_content_exit(tag_name: LocalNameHash) -> bool {
     tag_is_one_of!(
@@ -112,6 +121,7 @@ fn is_html_integration_point_in_svg(tag_name: LocalNameHash) -> bool {
 pub struct TreeBuilderSimulator {
     ns_stack: Vec<Namespace>,
     current_ns: Namespace,
+    integration_point_entered: bool,
     ambiguity_guard: AmbiguityGuard,
     strict: bool,
 }
@@ -121,6 +131,7 @@ impl TreeBuilderSimulator {
         let mut simulator = TreeBuilderSimulator {
             ns_stack: Vec::with_capacity(DEFAULT_NS_STACK_CAPACITY),
             current_ns: Namespace::Html,
+            integration_point_entered: false,
             ambiguity_guard: AmbiguityGuard::default(),
             strict,
         };
@@ -138,6 +149,8 @@ impl TreeBuilderSimulator {
             self.ambiguity_guard.track_start_tag(tag_name)?;
         }
 
+        self.integration_point_entered = false;
+
         Ok(if tag_name == Tag::Svg {
             self.enter_ns(Namespace::Svg)
         } else if tag_name == Tag::Math {
@@ -170,6 +183,25 @@ impl TreeBuilderSimulator {
         self.current_ns
     }
 
+    /// Returns the namespace of the element of the start tag, once the feedback for the start
+    /// tag has been applied. It's the current namespace, unless the element is an integration
+    /// point: its content is in the HTML namespace, but the element itself is not.
+    #[inline]
+    pub fn start_tag_ns(&self) -> Namespace {
+        if self.integration_point_entered {
+            self.ns_stack[self.ns_stack.len() - 2]
+        } else {
+            self.current_ns
+        }
+    }
+
+    /// Returns `true` if the simulator is in the same state as a new one, i.e. it's not in
+    /// foreign content and doesn't track any ambiguous context.
+    #[inline]
+    pub fn is_at_rest(&self) -> bool {
+        self.ns_stack.len() == 1 && self.ambiguity_guard.is_at_rest()
+    }
+
     #[inline]
     fn enter_ns(&mut self, ns: Namespace) -> TreeBuilderFeedback {
         self.ns_stack.push(ns);
@@ -177,6 +209,12 @@ impl TreeBuilderSimulator {
         TreeBuilderFeedback::SetAllowCdata(ns != Namespace::Html)
     }
 
+    #[inline]
+    fn enter_integration_point(&mut self) -> TreeBuilderFeedback {
+        self.integration_point_entered = true;
+        self.enter_ns(Namespace::Html)
+    }
+
     #[inline]
     fn leave_ns(&mut self) -> TreeBuilderFeedback {
         self.ns_stack.pop();
@@ -238,7 +276,7 @@ impl TreeBuilderSimulator {
                     if self_closing {
                         TreeBuilderFeedback::None
                     } else {
-                        this.enter_ns(Namespace::Html)
+                        this.enter_integration_point()
                     }
                 })
             });
@@ -249,7 +287,7 @@ impl TreeBuilderSimulator {
             // to decide on foreign context exit
             return request_lexeme(|this, lexeme| {
                 expect_tag!(lexeme, StartTag { ref attributes, .. } => {
-                    for attr in attributes.borrow().iter() {
+                    for attr in attributes.iter() {
                         let name = lexeme.part(attr.name);
 
                         if eq_case_insensitive(&name, b"color")
@@ -278,7 +316,7 @@ impl TreeBuilderSimulator {
                     let name = lexeme.part(name);
 
                     if !self_closing && eq_case_insensitive(&name, b"annotation-xml") {
-                        for attr in attributes.borrow().iter() {
+                        for attr in attributes.iter() {
                             let name = lexeme.part(attr.name);
                             let value = lexeme.part(attr.value);
 
@@ -286,7 +324,7 @@ impl TreeBuilderSimulator {
                                 && (eq_case_insensitive(&value, b"text/html")
                                     || eq_case_insensitive(&value, b"application/xhtml+xml"))
                             {
-                                return this.enter_ns(Namespace::Html);
+                                return this.enter_integration_point();
                             }
                         }
                     }
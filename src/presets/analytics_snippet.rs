@@ -0,0 +1,335 @@
+use crate::html_content::{ContentType, Element};
+use crate::{ElementContentHandlers, HandlerTypes, Selector};
+use std::sync::{Arc, Mutex};
+
+/// The place where the [`AnalyticsSnippet`] preset inserts the snippet.
+///
+/// [`AnalyticsSnippet`]: struct.AnalyticsSnippet.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnippetPlacement {
+    /// Before the end tag of the `<head>` element.
+    HeadEnd,
+    /// Before the end tag of the `<body>` element.
+    BodyEnd,
+}
+
+impl Default for SnippetPlacement {
+    #[inline]
+    fn default() -> Self {
+        SnippetPlacement::HeadEnd
+    }
+}
+
+#[derive(Default)]
+struct DocumentState {
+    inserted: bool,
+    already_present: bool,
+    page_nonce: Option<String>,
+}
+
+#[derive(Clone)]
+struct Snippet {
+    source: String,
+    id: Option<String>,
+    nonce: Option<String>,
+}
+
+impl Snippet {
+    fn to_html(&self, page_nonce: Option<&str>) -> String {
+        let mut html = String::from("<script");
+
+        if let Some(ref id) = self.id {
+            html.push_str(&format!(r#" id="{}""#, id.replace('"', "&quot;")));
+        }
+
+        if let Some(nonce) = self.nonce.as_deref().or(page_nonce) {
+            html.push_str(&format!(r#" nonce="{}""#, nonce.replace('"', "&quot;")));
+        }
+
+        html.push('>');
+        html.push_str(&self.source);
+        html.push_str("</script>");
+
+        html
+    }
+}
+
+/// Inserts an inline script snippet, e.g. the one of an analytics service, into the document.
+///
+/// The snippet is inserted exactly once, before the end tag of the first `<head>` or `<body>`
+/// element as specified by the [`SnippetPlacement`]. If the snippet should be inserted into the
+/// `<head>` element and the document doesn't have it, the snippet is inserted at the start of the
+/// `<body>` element instead.
+///
+/// If the snippet has an [`id`], it's not inserted when the document already contains a script
+/// with the same `id`, e.g. if the document has been already processed by the preset.
+///
+/// For the documents with a [Content Security Policy] that uses nonces, the snippet is given the
+/// [`nonce`] specified for the preset or, if it's not specified, the nonce of the first script
+/// of the document with a `nonce` attribute that precedes the insertion point.
+///
+/// # Example
+/// ```
+/// use lol_html::presets::AnalyticsSnippet;
+/// use lol_html::{rewrite_str, RewriteStrSettings};
+///
+/// let preset = AnalyticsSnippet::new("track()").id("analytics");
+///
+/// let html = rewrite_str(
+///     r#"<head><script nonce="r4nd0m" src="/app.js"></script></head>"#,
+///     RewriteStrSettings {
+///         element_content_handlers: preset.handlers(),
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(
+///     html,
+///     concat!(
+///         r#"<head><script nonce="r4nd0m" src="/app.js"></script>"#,
+///         r#"<script id="analytics" nonce="r4nd0m">track()</script></head>"#,
+///     )
+/// );
+/// ```
+///
+/// [`SnippetPlacement`]: enum.SnippetPlacement.html
+/// [`id`]: #method.id
+/// [`nonce`]: #method.nonce
+/// [Content Security Policy]: https://developer.mozilla.org/en-US/docs/Web/HTTP/CSP
+pub struct AnalyticsSnippet {
+    snippet: Snippet,
+    placement: SnippetPlacement,
+    scripts_selector: Selector,
+    head_selector: Selector,
+    body_selector: Selector,
+}
+
+impl AnalyticsSnippet {
+    /// Creates the preset that inserts a script with the `source`.
+    pub fn new(source: &str) -> Self {
+        AnalyticsSnippet {
+            snippet: Snippet {
+                // NOTE: the source can't end the `<script>` element prematurely.
+                source: source.replace("</script", "<\\/script"),
+                id: None,
+                nonce: None,
+            },
+            placement: SnippetPlacement::default(),
+            // NOTE: the selectors are constants, so it's safe to unwrap here.
+            scripts_selector: "script".parse().unwrap(),
+            head_selector: "head".parse().unwrap(),
+            body_selector: "body".parse().unwrap(),
+        }
+    }
+
+    /// Sets the place where the snippet is inserted.
+    ///
+    /// ### Default
+    ///
+    /// [`SnippetPlacement::HeadEnd`].
+    ///
+    /// [`SnippetPlacement::HeadEnd`]: enum.SnippetPlacement.html#variant.HeadEnd
+    #[inline]
+    pub fn placement(mut self, placement: SnippetPlacement) -> Self {
+        self.placement = placement;
+        self
+    }
+
+    /// Sets the `id` attribute of the snippet's script, which is used to detect whether the
+    /// snippet is already present in the document.
+    #[inline]
+    pub fn id(mut self, id: &str) -> Self {
+        self.snippet.id = Some(id.to_owned());
+        self
+    }
+
+    /// Sets the `nonce` attribute of the snippet's script. Overrides the nonce taken from the
+    /// scripts of the document.
+    #[inline]
+    pub fn nonce(mut self, nonce: &str) -> Self {
+        self.snippet.nonce = Some(nonce.to_owned());
+        self
+    }
+
+    fn inspect_script<H: HandlerTypes>(
+        &self,
+        el: &Element<'_, '_, H>,
+        state: &Mutex<DocumentState>,
+    ) {
+        let mut state = state.lock().unwrap();
+
+        if state.page_nonce.is_none() {
+            state.page_nonce = el.get_attribute("nonce");
+        }
+
+        if let Some(ref id) = self.snippet.id {
+            if el.get_attribute("id").as_ref() == Some(id) {
+                state.already_present = true;
+            }
+        }
+    }
+
+    fn insert_before_end_tag<H: HandlerTypes>(
+        &self,
+        el: &mut Element<'_, '_, H>,
+        state: &Arc<Mutex<DocumentState>>,
+    ) {
+        let snippet = self.snippet.clone();
+        let state = Arc::clone(state);
+
+        el.add_end_tag_handler(H::new_end_tag_handler(move |end| {
+            let state = state.lock().unwrap();
+
+            if !state.already_present {
+                end.before(
+                    &snippet.to_html(state.page_nonce.as_deref()),
+                    ContentType::Html,
+                );
+            }
+
+            Ok(())
+        }));
+    }
+
+    /// Constructs element content handlers that insert the snippet.
+    ///
+    /// The handlers of a single call should be used for a single document: they track whether the
+    /// snippet has already been inserted.
+    pub fn handlers<H: HandlerTypes>(&self) -> Vec<(&Selector, ElementContentHandlers<'_, H>)> {
+        let state = Arc::new(Mutex::new(DocumentState::default()));
+        let mut handlers = Vec::with_capacity(3);
+
+        handlers.push((&self.scripts_selector, {
+            let state = Arc::clone(&state);
+
+            ElementContentHandlers {
+                element: Some(H::new_element_handler(move |el| {
+                    self.inspect_script(el, &state);
+
+                    Ok(())
+                })),
+                ..ElementContentHandlers::default()
+            }
+        }));
+
+        if self.placement == SnippetPlacement::HeadEnd {
+            let state = Arc::clone(&state);
+
+            handlers.push((
+                &self.head_selector,
+                ElementContentHandlers {
+                    element: Some(H::new_element_handler(move |el| {
+                        let mut doc_state = state.lock().unwrap();
+
+                        if !doc_state.inserted {
+                            doc_state.inserted = true;
+                            self.insert_before_end_tag(el, &state);
+                        }
+
+                        Ok(())
+                    })),
+                    ..ElementContentHandlers::default()
+                },
+            ));
+        }
+
+        handlers.push((
+            &self.body_selector,
+            ElementContentHandlers {
+                element: Some(H::new_element_handler(move |el| {
+                    let mut doc_state = state.lock().unwrap();
+
+                    if !doc_state.inserted {
+                        doc_state.inserted = true;
+
+                        match self.placement {
+                            SnippetPlacement::HeadEnd if !doc_state.already_present => {
+                                el.prepend(
+                                    &self.snippet.to_html(doc_state.page_nonce.as_deref()),
+                                    ContentType::Html,
+                                );
+                            }
+                            SnippetPlacement::HeadEnd => (),
+                            SnippetPlacement::BodyEnd => self.insert_before_end_tag(el, &state),
+                        }
+                    }
+
+                    Ok(())
+                })),
+                ..ElementContentHandlers::default()
+            },
+        ));
+
+        handlers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rewrite_str, RewriteStrSettings};
+
+    fn rewrite(html: &str, preset: &AnalyticsSnippet) -> String {
+        rewrite_str(
+            html,
+            RewriteStrSettings {
+                element_content_handlers: preset.handlers(),
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn head_placement() {
+        let preset = AnalyticsSnippet::new("a()</script>").id("a");
+
+        assert_eq!(
+            rewrite("<head></head><head></head><body></body>", &preset),
+            r#"<head><script id="a">a()<\/script></script></head><head></head><body></body>"#
+        );
+
+        assert_eq!(
+            rewrite("<title>Foo</title><body><p>Bar</body>", &preset),
+            r#"<title>Foo</title><body><script id="a">a()<\/script></script><p>Bar</body>"#
+        );
+
+        let html = r#"<head><script id="a">a()</script></head>"#;
+
+        assert_eq!(rewrite(html, &preset), html);
+    }
+
+    #[test]
+    fn body_placement() {
+        let preset = AnalyticsSnippet::new("a()")
+            .placement(SnippetPlacement::BodyEnd)
+            .nonce("foo");
+
+        assert_eq!(
+            rewrite(
+                r#"<head></head><body><script nonce="bar"></script><p>Bar"#,
+                &preset
+            ),
+            concat!(
+                r#"<head></head><body><script nonce="bar"></script><p>Bar"#,
+                r#"<script nonce="foo">a()</script>"#
+            )
+        );
+    }
+
+    #[test]
+    fn page_nonce() {
+        let preset = AnalyticsSnippet::new("a()").placement(SnippetPlacement::BodyEnd);
+
+        assert_eq!(
+            rewrite(
+                r#"<body><script></script><script nonce="x"></script></body>"#,
+                &preset
+            ),
+            concat!(
+                r#"<body><script></script><script nonce="x"></script>"#,
+                r#"<script nonce="x">a()</script></body>"#
+            )
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use super::url::UrlParts;
+use crate::html_content::Element;
+use crate::{ElementContentHandlers, HandlerTypes, Selector};
+
+const ASSETS_SELECTOR: &str = "script[src], link[href], img[src], source[src]";
+
+const DEFAULT_EXTENSIONS: &[&str] = &[
+    "css", "js", "mjs", "png", "jpg", "jpeg", "gif", "svg", "webp", "avif", "ico", "woff", "woff2",
+];
+
+/// Appends a version query parameter to the URLs of static assets, so the cached assets are
+/// invalidated once a new version of the site is deployed.
+///
+/// The parameter is added to the URLs in the `src` attributes of `<script>`, `<img>` and
+/// `<source>` elements and in the `href` attributes of `<link>` elements if the file extension
+/// of the URL is one of the [`extensions`]. The absolute URLs are processed only if their
+/// host is one of the [`hosts`]. The existing query string and fragment of the URL are kept, and
+/// the URLs that already have the parameter are left intact.
+///
+/// # Example
+/// ```
+/// use lol_html::presets::CacheBusting;
+/// use lol_html::{rewrite_str, RewriteStrSettings};
+///
+/// let preset = CacheBusting::new("v", "42").hosts(&["static.example.com"]);
+///
+/// let html = rewrite_str(
+///     concat!(
+///         r#"<link rel="stylesheet" href="/main.css?theme=dark#top">"#,
+///         r#"<script src="https://static.example.com/app.js"></script>"#,
+///         r#"<script src="https://cdn.example.net/lib.js"></script>"#,
+///         r#"<img src="/logo.png?v=41">"#,
+///     ),
+///     RewriteStrSettings {
+///         element_content_handlers: vec![preset.handlers()],
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(
+///     html,
+///     concat!(
+///         r#"<link rel="stylesheet" href="/main.css?theme=dark&v=42#top">"#,
+///         r#"<script src="https://static.example.com/app.js?v=42"></script>"#,
+///         r#"<script src="https://cdn.example.net/lib.js"></script>"#,
+///         r#"<img src="/logo.png?v=41">"#,
+///     )
+/// );
+/// ```
+///
+/// [`extensions`]: #method.extensions
+/// [`hosts`]: #method.hosts
+pub struct CacheBusting {
+    param: String,
+    version: String,
+    extensions: Vec<String>,
+    hosts: Vec<String>,
+    selector: Selector,
+}
+
+impl CacheBusting {
+    /// Creates the preset that appends the `param` query parameter with the `version` value.
+    pub fn new(param: &str, version: &str) -> Self {
+        CacheBusting {
+            param: param.to_owned(),
+            version: version.to_owned(),
+            extensions: DEFAULT_EXTENSIONS.iter().map(|&e| e.to_owned()).collect(),
+            hosts: Vec::default(),
+            // NOTE: the selector is a constant, so it's safe to unwrap here.
+            selector: ASSETS_SELECTOR.parse().unwrap(),
+        }
+    }
+
+    /// Sets the file extensions (without the leading dot) of the assets. The extensions are
+    /// compared case-insensitively.
+    ///
+    /// ### Default
+    ///
+    /// The extensions of stylesheets, scripts, images and fonts: `css`, `js`, `mjs`, `png`, `jpg`,
+    /// `jpeg`, `gif`, `svg`, `webp`, `avif`, `ico`, `woff` and `woff2`.
+    #[inline]
+    pub fn extensions(mut self, extensions: &[&str]) -> Self {
+        self.extensions = extensions.iter().map(|e| e.to_ascii_lowercase()).collect();
+        self
+    }
+
+    /// Sets the hosts of the absolute URLs that are processed. The hosts are compared
+    /// case-insensitively and should include the port if it's specified in the URLs.
+    ///
+    /// ### Default
+    ///
+    /// No hosts, i.e. only the relative URLs are processed.
+    #[inline]
+    pub fn hosts(mut self, hosts: &[&str]) -> Self {
+        self.hosts = hosts.iter().map(|h| h.to_ascii_lowercase()).collect();
+        self
+    }
+
+    /// Returns the URL with the version parameter, or `None` if the URL should be left intact.
+    fn versioned_url(&self, url: &str) -> Option<String> {
+        let parts = UrlParts::parse(url)?;
+
+        if let Some(host) = parts.host {
+            if !self.hosts.iter().any(|h| h.eq_ignore_ascii_case(host)) {
+                return None;
+            }
+        }
+
+        let extension = parts.extension()?;
+
+        if !self.extensions.contains(&extension) || parts.has_query_param(&self.param) {
+            return None;
+        }
+
+        Some(parts.with_query_param(&self.param, &self.version))
+    }
+
+    #[inline]
+    fn url_attribute_name<H: HandlerTypes>(el: &Element<'_, '_, H>) -> &'static str {
+        if el.tag_name() == "link" {
+            "href"
+        } else {
+            "src"
+        }
+    }
+
+    /// Constructs an element content handler that appends the version parameter to the asset
+    /// URLs.
+    pub fn handlers<H: HandlerTypes>(&self) -> (&Selector, ElementContentHandlers<'_, H>) {
+        (
+            &self.selector,
+            ElementContentHandlers {
+                element: Some(H::new_element_handler(move |el| {
+                    let name = Self::url_attribute_name(el);
+
+                    if let Some(url) = el.get_attribute(name) {
+                        if let Some(versioned) = self.versioned_url(url.trim()) {
+                            el.set_attribute(name, &versioned)?;
+                        }
+                    }
+
+                    Ok(())
+                })),
+                ..ElementContentHandlers::default()
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versioned_urls() {
+        let preset = CacheBusting::new("v", "1")
+            .extensions(&["JS", "css"])
+            .hosts(&["A.com"]);
+
+        for (url, expected) in &[
+            ("/a.js", Some("/a.js?v=1")),
+            ("a.CSS?", Some("a.CSS?v=1")),
+            ("/a.js?x=1#y", Some("/a.js?x=1&v=1#y")),
+            ("/a.js#y", Some("/a.js?v=1#y")),
+            ("https://a.com/b.js", Some("https://a.com/b.js?v=1")),
+            ("//a.COM/b.js", Some("//a.COM/b.js?v=1")),
+            ("https://b.com/b.js", None),
+            ("/a.js?v=0", None),
+            ("/a.js?vv=0", Some("/a.js?vv=0&v=1")),
+            ("/a.png", None),
+            ("/a.js/", None),
+            ("/a", None),
+            ("data:application/javascript,a.js", None),
+        ] {
+            assert_eq!(
+                preset.versioned_url(url).as_deref(),
+                *expected,
+                "URL: {}",
+                url
+            );
+        }
+    }
+}
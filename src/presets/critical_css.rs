@@ -0,0 +1,274 @@
+use crate::html_content::{ContentType, Element};
+use crate::{ElementContentHandlers, HandlerTypes, Selector};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// NOTE: alternate stylesheets (`rel="alternate stylesheet"`) are not render-blocking,
+// so only the exact `stylesheet` link type is matched.
+const STYLESHEET_LINKS_SELECTOR: &str = r#"link[rel="stylesheet" i][href]"#;
+
+/// The way the [`CriticalCss`] preset makes the stylesheet links non-render-blocking.
+///
+/// [`CriticalCss`]: struct.CriticalCss.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StylesheetLoading {
+    /// Loads the stylesheet with `media="print"` and switches it to the original media once
+    /// the stylesheet is loaded.
+    MediaSwap,
+    /// Loads the stylesheet with `rel="preload"` and turns it into a stylesheet once it is
+    /// loaded.
+    Preload,
+}
+
+impl Default for StylesheetLoading {
+    #[inline]
+    fn default() -> Self {
+        StylesheetLoading::MediaSwap
+    }
+}
+
+#[inline]
+fn escape_attr_value(value: &str) -> String {
+    value.replace('"', "&quot;")
+}
+
+#[inline]
+fn escape_js_string(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+/// Inlines the critical CSS into the document and defers loading of its stylesheets.
+///
+/// The critical CSS is inserted into a `<style>` element at the start of the `<head>` element.
+/// If the document doesn't have the `<head>` element, the CSS is inserted before the first
+/// stylesheet link instead, so in either case it precedes the stylesheets in the cascade. The CSS
+/// is inserted exactly once, even if the document has multiple `<head>` elements.
+///
+/// Each `<link rel="stylesheet">` is rewritten to load without blocking rendering as specified by
+/// the [`StylesheetLoading`], and the original link is kept in a `<noscript>` element for the
+/// user agents with scripting disabled. The links that are already deferred, i.e. that have an
+/// `onload` attribute or `media="print"`, are left intact.
+///
+/// # Example
+/// ```
+/// use lol_html::presets::CriticalCss;
+/// use lol_html::{rewrite_str, RewriteStrSettings};
+///
+/// let preset = CriticalCss::new("body{margin:0}");
+///
+/// let html = rewrite_str(
+///     r#"<head><link rel="stylesheet" href="/main.css"></head>"#,
+///     RewriteStrSettings {
+///         element_content_handlers: preset.handlers(),
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(
+///     html,
+///     concat!(
+///         r#"<head><style>body{margin:0}</style>"#,
+///         r#"<link rel="stylesheet" href="/main.css" media="print" onload="this.media='all'">"#,
+///         r#"<noscript><link rel="stylesheet" href="/main.css"></noscript></head>"#,
+///     )
+/// );
+/// ```
+///
+/// [`StylesheetLoading`]: enum.StylesheetLoading.html
+pub struct CriticalCss {
+    style: String,
+    loading: StylesheetLoading,
+    head_selector: Selector,
+    links_selector: Selector,
+}
+
+impl CriticalCss {
+    /// Creates the preset that inlines the `css`.
+    pub fn new(css: &str) -> Self {
+        CriticalCss {
+            // NOTE: the CSS can't end the `<style>` element prematurely, `<\/style` is a valid
+            // CSS escape of the same text.
+            style: format!("<style>{}</style>", css.replace("</style", "<\\/style")),
+            loading: StylesheetLoading::default(),
+            // NOTE: the selectors are constants, so it's safe to unwrap here.
+            head_selector: "head".parse().unwrap(),
+            links_selector: STYLESHEET_LINKS_SELECTOR.parse().unwrap(),
+        }
+    }
+
+    /// Sets the way the stylesheet links are deferred.
+    ///
+    /// ### Default
+    ///
+    /// [`StylesheetLoading::MediaSwap`].
+    ///
+    /// [`StylesheetLoading::MediaSwap`]: enum.StylesheetLoading.html#variant.MediaSwap
+    #[inline]
+    pub fn loading(mut self, loading: StylesheetLoading) -> Self {
+        self.loading = loading;
+        self
+    }
+
+    fn defer_stylesheet<H: HandlerTypes>(&self, el: &mut Element<'_, '_, H>) {
+        let media = el.get_attribute("media");
+
+        if el.has_attribute("onload") || media.as_deref() == Some("print") {
+            return;
+        }
+
+        let href = el.get_attribute("href").unwrap_or_default();
+
+        let fallback = match media {
+            Some(ref media) => format!(
+                r#"<noscript><link rel="stylesheet" href="{}" media="{}"></noscript>"#,
+                escape_attr_value(&href),
+                escape_attr_value(media)
+            ),
+            None => format!(
+                r#"<noscript><link rel="stylesheet" href="{}"></noscript>"#,
+                escape_attr_value(&href)
+            ),
+        };
+
+        // NOTE: the attribute names are constants, so it's safe to unwrap here.
+        match self.loading {
+            StylesheetLoading::MediaSwap => {
+                let onload = format!(
+                    "this.media='{}'",
+                    escape_js_string(media.as_deref().unwrap_or("all"))
+                );
+
+                el.set_attribute("media", "print").unwrap();
+                el.set_attribute("onload", &onload).unwrap();
+            }
+            StylesheetLoading::Preload => {
+                el.set_attribute("rel", "preload").unwrap();
+                el.set_attribute("as", "style").unwrap();
+                el.set_attribute("onload", "this.onload=null;this.rel='stylesheet'")
+                    .unwrap();
+            }
+        }
+
+        el.after(&fallback, ContentType::Html);
+    }
+
+    /// Constructs element content handlers that inline the critical CSS and defer the
+    /// stylesheets.
+    ///
+    /// The handlers of a single call should be used for a single document: they track whether the
+    /// CSS has already been inserted.
+    pub fn handlers<H: HandlerTypes>(&self) -> Vec<(&Selector, ElementContentHandlers<'_, H>)> {
+        let inserted = Arc::new(AtomicBool::new(false));
+        let inserted_before_link = Arc::clone(&inserted);
+
+        vec![
+            (
+                &self.head_selector,
+                ElementContentHandlers {
+                    element: Some(H::new_element_handler(move |el| {
+                        if !inserted.swap(true, Ordering::Relaxed) {
+                            el.prepend(&self.style, ContentType::Html);
+                        }
+
+                        Ok(())
+                    })),
+                    ..ElementContentHandlers::default()
+                },
+            ),
+            (
+                &self.links_selector,
+                ElementContentHandlers {
+                    element: Some(H::new_element_handler(move |el| {
+                        if !inserted_before_link.swap(true, Ordering::Relaxed) {
+                            el.before(&self.style, ContentType::Html);
+                        }
+
+                        self.defer_stylesheet(el);
+
+                        Ok(())
+                    })),
+                    ..ElementContentHandlers::default()
+                },
+            ),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rewrite_str, RewriteStrSettings};
+
+    fn rewrite(html: &str, preset: &CriticalCss) -> String {
+        rewrite_str(
+            html,
+            RewriteStrSettings {
+                element_content_handlers: preset.handlers(),
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn insert_css_once() {
+        let preset = CriticalCss::new("a{}</style><script>");
+
+        assert_eq!(
+            rewrite("<head></head><head></head>", &preset),
+            r#"<head><style>a{}<\/style><script></style></head><head></head>"#
+        );
+
+        assert_eq!(
+            rewrite(
+                concat!(
+                    r#"<link rel="alternate stylesheet" href="a.css">"#,
+                    r#"<link rel="stylesheet" href="b.css" onload="x()">"#,
+                    r#"<link rel="stylesheet" href="c.css" media="print">"#,
+                ),
+                &preset
+            ),
+            concat!(
+                r#"<link rel="alternate stylesheet" href="a.css">"#,
+                r#"<style>a{}<\/style><script></style>"#,
+                r#"<link rel="stylesheet" href="b.css" onload="x()">"#,
+                r#"<link rel="stylesheet" href="c.css" media="print">"#,
+            )
+        );
+    }
+
+    #[test]
+    fn defer_stylesheets() {
+        let html = concat!(
+            r#"<head><link rel="StyleSheet" href="a.css" media="screen">"#,
+            r#"<link href='b"c.css' rel="stylesheet"></head>"#,
+        );
+
+        assert_eq!(
+            rewrite(html, &CriticalCss::new("")),
+            concat!(
+                r#"<head><style></style>"#,
+                r#"<link rel="StyleSheet" href="a.css" media="print" onload="this.media='screen'">"#,
+                r#"<noscript><link rel="stylesheet" href="a.css" media="screen"></noscript>"#,
+                r#"<link href='b"c.css' rel="stylesheet" media="print" onload="this.media='all'">"#,
+                r#"<noscript><link rel="stylesheet" href="b&quot;c.css"></noscript></head>"#,
+            )
+        );
+
+        assert_eq!(
+            rewrite(
+                html,
+                &CriticalCss::new("").loading(StylesheetLoading::Preload)
+            ),
+            concat!(
+                r#"<head><style></style>"#,
+                r#"<link rel="preload" href="a.css" media="screen" as="style" "#,
+                r#"onload="this.onload=null;this.rel='stylesheet'">"#,
+                r#"<noscript><link rel="stylesheet" href="a.css" media="screen"></noscript>"#,
+                r#"<link href='b"c.css' rel="preload" as="style" "#,
+                r#"onload="this.onload=null;this.rel='stylesheet'">"#,
+                r#"<noscript><link rel="stylesheet" href="b&quot;c.css"></noscript></head>"#,
+            )
+        );
+    }
+}
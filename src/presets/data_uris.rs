@@ -0,0 +1,507 @@
+use crate::{ElementContentHandlers, HandlerTypes, Selector};
+use std::error::Error;
+use std::io::{self, Read, Write};
+
+const DATA_URIS_SELECTOR: &str = r#"[src^="data:"], [href^="data:"], [poster^="data:"]"#;
+const DATA_URI_ATTRIBUTES: &[&str] = &["src", "href", "poster"];
+const DEFAULT_MEDIA_TYPE: &str = "text/plain;charset=US-ASCII";
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[inline]
+fn base64_sextet(byte: u8) -> Option<u8> {
+    match byte {
+        b'A'..=b'Z' => Some(byte - b'A'),
+        b'a'..=b'z' => Some(byte - b'a' + 26),
+        b'0'..=b'9' => Some(byte - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+#[inline]
+fn hex_digit(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|d| d as u8)
+}
+
+/// A `data:` URI in an attribute of an element, passed to the callback of the [`DataUris`]
+/// preset.
+///
+/// [`DataUris`]: struct.DataUris.html
+#[derive(Debug)]
+pub struct DataUri<'a> {
+    attribute_name: &'a str,
+    media_type: &'a str,
+    base64: bool,
+    data: &'a str,
+    replacement: Option<String>,
+}
+
+impl<'a> DataUri<'a> {
+    fn parse(attribute_name: &'a str, value: &'a str) -> Option<Self> {
+        let value = value.trim();
+
+        if !value.get(..5)?.eq_ignore_ascii_case("data:") {
+            return None;
+        }
+
+        let comma = value.find(',')?;
+        let mut media_type = value[5..comma].trim();
+        let mut base64 = false;
+
+        if let Some(semicolon) = media_type.rfind(';') {
+            if media_type[semicolon + 1..]
+                .trim()
+                .eq_ignore_ascii_case("base64")
+            {
+                media_type = media_type[..semicolon].trim();
+                base64 = true;
+            }
+        }
+
+        Some(DataUri {
+            attribute_name,
+            media_type,
+            base64,
+            data: &value[comma + 1..],
+            replacement: None,
+        })
+    }
+
+    /// Returns the name of the attribute that contains the URI.
+    #[inline]
+    pub fn attribute_name(&self) -> &str {
+        self.attribute_name
+    }
+
+    /// Returns the media type of the URI along with its parameters (e.g. `image/png` or
+    /// `image/svg+xml;charset=utf-8`), or `text/plain;charset=US-ASCII` if the URI doesn't
+    /// specify one.
+    #[inline]
+    pub fn media_type(&self) -> &str {
+        if self.media_type.is_empty() {
+            DEFAULT_MEDIA_TYPE
+        } else {
+            self.media_type
+        }
+    }
+
+    /// Returns `true` if the data of the URI is base64-encoded.
+    #[inline]
+    pub fn is_base64(&self) -> bool {
+        self.base64
+    }
+
+    /// Returns a reader of the decoded data of the URI.
+    ///
+    /// The data is decoded as it's read, so it's never held in memory as a whole. Reading
+    /// fails with the [`InvalidData`] error if the base64-encoded data is malformed.
+    ///
+    /// [`InvalidData`]: https://doc.rust-lang.org/std/io/enum.ErrorKind.html#variant.InvalidData
+    #[inline]
+    pub fn reader(&self) -> DataUriReader<'a> {
+        DataUriReader {
+            input: self.data.as_bytes(),
+            base64: self.base64,
+            decoded: [0; 3],
+            decoded_start: 0,
+            decoded_end: 0,
+        }
+    }
+
+    /// Replaces the URI with a base64-encoded `data:` URI of the `media_type`. The returned
+    /// writer encodes the data as it's written, and the encoding is completed once the writer
+    /// is dropped. The attribute is updated after the callback has returned.
+    ///
+    /// The consequent calls discard the data written before.
+    #[inline]
+    pub fn replace(&mut self, media_type: &str) -> DataUriWriter<'_> {
+        let output = self.replacement.get_or_insert_with(String::new);
+
+        output.clear();
+        output.push_str("data:");
+        output.push_str(media_type);
+        output.push_str(";base64,");
+
+        DataUriWriter {
+            output,
+            pending: [0; 3],
+            pending_len: 0,
+        }
+    }
+}
+
+/// A reader of the decoded data of a [`DataUri`].
+///
+/// [`DataUri`]: struct.DataUri.html
+#[derive(Debug)]
+pub struct DataUriReader<'a> {
+    input: &'a [u8],
+    base64: bool,
+    decoded: [u8; 3],
+    decoded_start: usize,
+    decoded_end: usize,
+}
+
+impl DataUriReader<'_> {
+    /// Decodes the next base64 quantum into the buffer of the decoded bytes.
+    fn decode_base64_quantum(&mut self) -> io::Result<()> {
+        let mut sextets = [0; 4];
+        let mut count = 0;
+
+        while count < 4 {
+            let (&byte, rest) = match self.input.split_first() {
+                Some(next) => next,
+                None => break,
+            };
+
+            self.input = rest;
+
+            match byte {
+                // NOTE: padding ends the data.
+                b'=' => self.input = &[],
+                _ if byte.is_ascii_whitespace() => (),
+                _ => {
+                    sextets[count] = base64_sextet(byte).ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidData, "Invalid base64 character.")
+                    })?;
+
+                    count += 1;
+                }
+            }
+        }
+
+        if count == 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Truncated base64 data.",
+            ));
+        }
+
+        let bits = sextets
+            .iter()
+            .fold(0u32, |bits, &sextet| (bits << 6) | u32::from(sextet));
+
+        self.decoded = [(bits >> 16) as u8, (bits >> 8) as u8, bits as u8];
+        self.decoded_start = 0;
+        self.decoded_end = count.saturating_sub(1);
+
+        Ok(())
+    }
+
+    /// Decodes the next percent-encoded byte into the buffer of the decoded bytes.
+    fn decode_percent_encoded_byte(&mut self) {
+        let (byte, len) = match self.input {
+            [b'%', hi, lo, ..] => match (hex_digit(*hi), hex_digit(*lo)) {
+                (Some(hi), Some(lo)) => ((hi << 4) | lo, 3),
+                _ => (b'%', 1),
+            },
+            [byte, ..] => (*byte, 1),
+            [] => return,
+        };
+
+        self.input = &self.input[len..];
+        self.decoded[0] = byte;
+        self.decoded_start = 0;
+        self.decoded_end = 1;
+    }
+}
+
+impl Read for DataUriReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut written = 0;
+
+        while written < buf.len() {
+            if self.decoded_start == self.decoded_end {
+                if self.input.is_empty() {
+                    break;
+                } else if self.base64 {
+                    self.decode_base64_quantum()?;
+                } else {
+                    self.decode_percent_encoded_byte();
+                }
+
+                continue;
+            }
+
+            let len = (self.decoded_end - self.decoded_start).min(buf.len() - written);
+
+            buf[written..written + len]
+                .copy_from_slice(&self.decoded[self.decoded_start..self.decoded_start + len]);
+
+            self.decoded_start += len;
+            written += len;
+        }
+
+        Ok(written)
+    }
+}
+
+/// A writer of the replacement data of a [`DataUri`], produced by [`DataUri::replace`].
+///
+/// [`DataUri`]: struct.DataUri.html
+/// [`DataUri::replace`]: struct.DataUri.html#method.replace
+#[derive(Debug)]
+pub struct DataUriWriter<'a> {
+    output: &'a mut String,
+    pending: [u8; 3],
+    pending_len: usize,
+}
+
+impl DataUriWriter<'_> {
+    fn encode_pending(&mut self) {
+        let bits = (u32::from(self.pending[0]) << 16)
+            | (u32::from(self.pending[1]) << 8)
+            | u32::from(self.pending[2]);
+
+        for i in 0..4 {
+            let ch = if i <= self.pending_len {
+                BASE64_ALPHABET[(bits >> (18 - i * 6)) as usize & 0x3F] as char
+            } else {
+                '='
+            };
+
+            self.output.push(ch);
+        }
+
+        self.pending = [0; 3];
+        self.pending_len = 0;
+    }
+}
+
+impl Write for DataUriWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            self.pending[self.pending_len] = byte;
+            self.pending_len += 1;
+
+            if self.pending_len == 3 {
+                self.encode_pending();
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for DataUriWriter<'_> {
+    fn drop(&mut self) {
+        if self.pending_len > 0 {
+            self.encode_pending();
+        }
+    }
+}
+
+/// Passes the `data:` URIs in the `src`, `href` and `poster` attributes to a callback that can
+/// transcode the inline assets, e.g. to optimize the inline images.
+///
+/// The callback receives the media type of each URI and can read its decoded data and write the
+/// replacement data without handling the encoding of the attribute value.
+///
+/// # Example
+/// ```
+/// use lol_html::presets::DataUris;
+/// use lol_html::{rewrite_str, RewriteStrSettings};
+/// use std::io::{Read, Write};
+///
+/// let preset = DataUris::new();
+///
+/// let html = rewrite_str(
+///     r#"<img src="data:text/plain,Hello%20world">"#,
+///     RewriteStrSettings {
+///         element_content_handlers: vec![preset.handlers(|uri| {
+///             if uri.media_type() == "text/plain" {
+///                 let mut text = String::new();
+///
+///                 uri.reader().read_to_string(&mut text)?;
+///                 uri.replace("text/plain").write_all(text.to_uppercase().as_bytes())?;
+///             }
+///
+///             Ok(())
+///         })],
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(html, r#"<img src="data:text/plain;base64,SEVMTE8gV09STEQ=">"#);
+/// ```
+pub struct DataUris {
+    selector: Selector,
+}
+
+impl DataUris {
+    /// Creates the preset.
+    pub fn new() -> Self {
+        DataUris {
+            // NOTE: the selector is a constant, so it's safe to unwrap here.
+            selector: DATA_URIS_SELECTOR.parse().unwrap(),
+        }
+    }
+
+    /// Constructs an element content handler that passes each `data:` URI to the `transform`
+    /// callback.
+    ///
+    /// An error returned by the callback stops the rewriting, as for any other content handler.
+    pub fn handlers<'s, 'h, H: HandlerTypes>(
+        &'s self,
+        mut transform: impl FnMut(&mut DataUri) -> Result<(), Box<dyn Error>> + Send + 'h,
+    ) -> (&'s Selector, ElementContentHandlers<'h, H>) {
+        (
+            &self.selector,
+            ElementContentHandlers {
+                element: Some(H::new_element_handler(move |el| {
+                    for &name in DATA_URI_ATTRIBUTES {
+                        let value = match el.get_attribute(name) {
+                            Some(value) => value,
+                            None => continue,
+                        };
+
+                        let replacement = match DataUri::parse(name, &value) {
+                            Some(mut uri) => {
+                                transform(&mut uri)?;
+
+                                uri.replacement
+                            }
+                            None => None,
+                        };
+
+                        if let Some(replacement) = replacement {
+                            el.set_attribute(name, &replacement)?;
+                        }
+                    }
+
+                    Ok(())
+                })),
+                ..ElementContentHandlers::default()
+            },
+        )
+    }
+}
+
+impl Default for DataUris {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rewrite_str, RewriteStrSettings};
+
+    fn read(uri: &str) -> io::Result<Vec<u8>> {
+        let mut data = vec![];
+
+        // NOTE: read with a small buffer to cover the quanta split between the reads.
+        let mut reader = DataUri::parse("src", uri).unwrap().reader();
+        let mut buf = [0; 2];
+
+        loop {
+            match reader.read(&mut buf)? {
+                0 => return Ok(data),
+                len => data.extend_from_slice(&buf[..len]),
+            }
+        }
+    }
+
+    #[test]
+    fn parse() {
+        let uri = DataUri::parse(
+            "src",
+            " DATA:image/svg+xml; charset=utf-8 ; Base64 ,PHN2Zz4=",
+        )
+        .unwrap();
+
+        assert_eq!(uri.media_type(), "image/svg+xml; charset=utf-8");
+        assert!(uri.is_base64());
+
+        let uri = DataUri::parse("href", "data:,a;base64").unwrap();
+
+        assert_eq!(uri.media_type(), DEFAULT_MEDIA_TYPE);
+        assert!(!uri.is_base64());
+
+        assert!(DataUri::parse("src", "data:image/png").is_none());
+        assert!(DataUri::parse("src", "/image.png").is_none());
+    }
+
+    #[test]
+    fn decode() {
+        assert_eq!(read("data:;base64,SGVs bG8=").unwrap(), b"Hello");
+        assert_eq!(read("data:;base64,SGVsbA").unwrap(), b"Hell");
+        assert_eq!(read("data:,100%25%2").unwrap(), b"100%%2");
+        assert_eq!(read("data:;base64,").unwrap(), b"");
+
+        assert_eq!(
+            read("data:;base64,SGV*").unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        assert_eq!(
+            read("data:;base64,SGVsb").unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn encode() {
+        for &(data, expected) in &[
+            (&b""[..], "data:a/b;base64,"),
+            (&b"H"[..], "data:a/b;base64,SA=="),
+            (&b"He"[..], "data:a/b;base64,SGU="),
+            (&b"Hel"[..], "data:a/b;base64,SGVs"),
+            (&b"Hello"[..], "data:a/b;base64,SGVsbG8="),
+        ] {
+            let mut uri = DataUri::parse("src", "data:,").unwrap();
+
+            uri.replace("a/b").write_all(data).unwrap();
+
+            assert_eq!(uri.replacement.unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn rewrite() {
+        let preset = DataUris::new();
+        let mut media_types = vec![];
+
+        let html = rewrite_str(
+            concat!(
+                r#"<img src="/a.png"><video poster="data:image/gif;base64,R0lG">"#,
+                r#"<a href="data:text/html,<b>">"#
+            ),
+            RewriteStrSettings {
+                element_content_handlers: vec![preset.handlers(|uri| {
+                    media_types.push(format!("{} {}", uri.attribute_name(), uri.media_type()));
+
+                    if uri.is_base64() {
+                        let mut data = vec![];
+
+                        uri.reader().read_to_end(&mut data)?;
+                        uri.replace("image/webp").write_all(&data)?;
+                    }
+
+                    Ok(())
+                })],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            html,
+            concat!(
+                r#"<img src="/a.png"><video poster="data:image/webp;base64,R0lG">"#,
+                r#"<a href="data:text/html,<b>">"#
+            )
+        );
+
+        assert_eq!(media_types, ["poster image/gif", "href text/html"]);
+    }
+}
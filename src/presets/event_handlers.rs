@@ -0,0 +1,196 @@
+use crate::{ElementContentHandlers, HandlerTypes, Selector};
+
+/// A report about an inline event handler attribute removed by the [`InlineEventHandlers`]
+/// preset.
+///
+/// [`InlineEventHandlers`]: struct.InlineEventHandlers.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemovedEventHandler {
+    /// The tag name of the element the attribute was removed from.
+    pub tag_name: String,
+    /// The value of the `id` attribute of the element, if present.
+    pub element_id: Option<String>,
+    /// The name of the removed attribute.
+    pub name: String,
+    /// The value of the removed attribute.
+    pub value: String,
+}
+
+/// Removes all inline event handler attributes (e.g. `onclick`) from the document.
+///
+/// Any attribute whose name consists of the `on` prefix followed by ASCII letters is treated as
+/// an event handler, so both the attributes from the specification and unknown or future event
+/// attributes are removed. Custom attributes that happen to have such names (e.g. `one` or
+/// `onboarding`) can be kept with [`allow`]. Instead of matching each of the attribute names with
+/// a separate selector, the preset filters the attribute list of every element in a single pass.
+///
+/// Note that the start tags spilled due to [`SpillSettings::start_tags`] are streamed to the
+/// output as is and are not passed to the element content handlers, so their event handler
+/// attributes are not removed. Start tag spilling shouldn't be enabled along with the preset if
+/// the output must not contain any event handlers. The start tags are never spilled otherwise,
+/// since the preset observes all of them.
+///
+/// # Example
+/// ```
+/// use lol_html::presets::InlineEventHandlers;
+/// use lol_html::{rewrite_str, RewriteStrSettings};
+///
+/// let preset = InlineEventHandlers::new();
+/// let mut reports = vec![];
+///
+/// let html = rewrite_str(
+///     r#"<body onload="init()"><a id="foo" href="/" onClick="track()">Foo</a></body>"#,
+///     RewriteStrSettings {
+///         element_content_handlers: vec![preset.handlers(|r| reports.push(r))],
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(html, r#"<body><a id="foo" href="/">Foo</a></body>"#);
+/// assert_eq!(reports.len(), 2);
+/// assert_eq!(reports[1].element_id.as_deref(), Some("foo"));
+/// assert_eq!(reports[1].name, "onclick");
+/// ```
+///
+/// [`allow`]: #method.allow
+/// [`SpillSettings::start_tags`]: ../struct.SpillSettings.html#structfield.start_tags
+pub struct InlineEventHandlers {
+    selector: Selector,
+    allowed: Vec<String>,
+}
+
+impl InlineEventHandlers {
+    /// Creates the preset.
+    pub fn new() -> Self {
+        InlineEventHandlers {
+            // NOTE: the selector is a constant, so it's safe to unwrap here.
+            selector: "*".parse().unwrap(),
+            allowed: Vec::default(),
+        }
+    }
+
+    /// Sets the names of the attributes that are kept even though they look like event
+    /// handlers. The names are compared case-insensitively.
+    ///
+    /// ### Default
+    ///
+    /// No names, i.e. all the event handler attributes are removed.
+    #[inline]
+    pub fn allow(mut self, names: &[&str]) -> Self {
+        self.allowed = names.iter().map(|n| n.to_ascii_lowercase()).collect();
+        self
+    }
+
+    /// Constructs an element content handler that removes inline event handler attributes and
+    /// passes a report about each removal to the `report` callback.
+    pub fn handlers<'s, 'h, H: HandlerTypes>(
+        &'s self,
+        mut report: impl FnMut(RemovedEventHandler) + Send + 'h,
+    ) -> (&'s Selector, ElementContentHandlers<'h, H>) {
+        let allowed = self.allowed.clone();
+
+        (
+            &self.selector,
+            ElementContentHandlers {
+                element: Some(H::new_element_handler(move |el| {
+                    let removed = el.remove_event_handler_attributes_except(&allowed);
+
+                    if !removed.is_empty() {
+                        let tag_name = el.tag_name();
+                        let element_id = el.get_attribute("id");
+
+                        for attr in removed {
+                            report(RemovedEventHandler {
+                                tag_name: tag_name.clone(),
+                                element_id: element_id.clone(),
+                                name: attr.name(),
+                                value: attr.value(),
+                            });
+                        }
+                    }
+
+                    Ok(())
+                })),
+                ..ElementContentHandlers::default()
+            },
+        )
+    }
+}
+
+impl Default for InlineEventHandlers {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rewrite_str, RewriteStrSettings};
+
+    #[test]
+    fn strip_event_handlers() {
+        let preset = InlineEventHandlers::new();
+        let mut reports = vec![];
+
+        let html = rewrite_str(
+            concat!(
+                r#"<div onmouseover=a() title="on"><svg onload="b()"></svg></div>"#,
+                r#"<p onfutureevent=1 on=2 online-id=3 on-x=4 ON5=5>"#,
+            ),
+            RewriteStrSettings {
+                element_content_handlers: vec![preset.handlers(|r| reports.push(r))],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            html,
+            r#"<div title="on"><svg></svg></div><p on=2 online-id=3 on-x=4 ON5=5>"#
+        );
+
+        assert_eq!(
+            reports,
+            vec![
+                RemovedEventHandler {
+                    tag_name: "div".into(),
+                    element_id: None,
+                    name: "onmouseover".into(),
+                    value: "a()".into(),
+                },
+                RemovedEventHandler {
+                    tag_name: "svg".into(),
+                    element_id: None,
+                    name: "onload".into(),
+                    value: "b()".into(),
+                },
+                RemovedEventHandler {
+                    tag_name: "p".into(),
+                    element_id: None,
+                    name: "onfutureevent".into(),
+                    value: "1".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn allowed_attributes() {
+        let preset = InlineEventHandlers::new().allow(&["One", "onboarding"]);
+        let mut reports = vec![];
+
+        let html = rewrite_str(
+            r#"<p one=1 onboarding=2 onclick=a() ONBOARDING=3>"#,
+            RewriteStrSettings {
+                element_content_handlers: vec![preset.handlers(|r| reports.push(r.name))],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(html, r#"<p one=1 onboarding=2 ONBOARDING=3>"#);
+        assert_eq!(reports, ["onclick"]);
+    }
+}
@@ -0,0 +1,252 @@
+use crate::html_content::{ContentType, Element};
+use crate::{ElementContentHandlers, HandlerTypes, Selector};
+
+const DEFAULT_STRIPPED_TAGS: &[&str] = &["script", "noscript", "iframe", "object", "embed", "form"];
+
+#[inline]
+fn is_stylesheet_link<H: HandlerTypes>(el: &Element<'_, '_, H>) -> bool {
+    el.get_attribute("rel").is_some_and(|rel| {
+        rel.split_ascii_whitespace()
+            .any(|t| t.eq_ignore_ascii_case("stylesheet"))
+    })
+}
+
+/// Prepares a document for sending as an HTML email.
+///
+/// Most email clients ignore the stylesheets of the message, so the styles should be inlined
+/// into the `style` attributes of the elements. The preset doesn't compute the styles itself:
+/// it passes the CSS of each `<style>` element to an external CSS inliner and asks the inliner
+/// for the declarations of each element of the document, while taking care of streaming the
+/// document and modifying the elements. The declarations are added in front of the element's own
+/// `style` attribute, so the latter keeps the precedence.
+///
+/// Additionally, the preset removes the stylesheet `<link>`s and the elements that are not
+/// supported by email clients, along with their content.
+///
+/// # Example
+/// ```
+/// use lol_html::presets::HtmlEmail;
+/// use lol_html::{rewrite_str, RewriteStrSettings};
+/// use std::sync::Mutex;
+///
+/// // NOTE: a real inliner would parse the CSS and match its selectors.
+/// let rules = Mutex::new(vec![]);
+/// let preset = HtmlEmail::new();
+///
+/// let html = rewrite_str(
+///     concat!(
+///         r#"<head><style>p{color:red}</style><link rel="stylesheet" href="a.css"></head>"#,
+///         r#"<body><p style="margin:0">Hi</p><script>track()</script></body>"#
+///     ),
+///     RewriteStrSettings {
+///         element_content_handlers: preset.handlers(
+///             |css| {
+///                 rules.lock().unwrap().push(css.to_owned());
+///                 String::new()
+///             },
+///             |el| {
+///                 let rules = rules.lock().unwrap();
+///
+///                 if el.tag_name() == "p" && rules.iter().any(|r| r == "p{color:red}") {
+///                     Some("color:red".into())
+///                 } else {
+///                     None
+///                 }
+///             },
+///         ),
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(
+///     html,
+///     r#"<head><style></style></head><body><p style="color:red;margin:0">Hi</p></body>"#
+/// );
+/// ```
+pub struct HtmlEmail {
+    stripped_tags: Vec<String>,
+    strip_stylesheet_links: bool,
+    elements_selector: Selector,
+    styles_selector: Selector,
+}
+
+impl HtmlEmail {
+    /// Creates the preset.
+    pub fn new() -> Self {
+        HtmlEmail {
+            stripped_tags: DEFAULT_STRIPPED_TAGS
+                .iter()
+                .map(|&t| t.to_owned())
+                .collect(),
+            strip_stylesheet_links: true,
+            // NOTE: the selectors are constants, so it's safe to unwrap here.
+            elements_selector: "*".parse().unwrap(),
+            styles_selector: "style".parse().unwrap(),
+        }
+    }
+
+    /// Sets the names of the elements that are removed from the document along with their
+    /// content.
+    ///
+    /// ### Default
+    ///
+    /// `script`, `noscript`, `iframe`, `object`, `embed` and `form`.
+    #[inline]
+    pub fn stripped_tags(mut self, tags: &[&str]) -> Self {
+        self.stripped_tags = tags.iter().map(|t| t.to_ascii_lowercase()).collect();
+        self
+    }
+
+    /// Specifies whether the `<link rel="stylesheet">` elements are removed.
+    ///
+    /// ### Default
+    ///
+    /// `true`.
+    #[inline]
+    pub fn strip_stylesheet_links(mut self, strip: bool) -> Self {
+        self.strip_stylesheet_links = strip;
+        self
+    }
+
+    fn should_strip<H: HandlerTypes>(&self, el: &Element<'_, '_, H>) -> bool {
+        let tag_name = el.tag_name();
+
+        self.stripped_tags.contains(&tag_name)
+            || (self.strip_stylesheet_links && tag_name == "link" && is_stylesheet_link(el))
+    }
+
+    /// Constructs element content handlers that prepare the document for an email.
+    ///
+    /// The `inline_css` callback receives the CSS of each `<style>` element and returns the CSS
+    /// that should be kept in the element, e.g. the media queries that can't be inlined. The
+    /// `declarations` callback receives each element of the document and returns the CSS
+    /// declarations that should be added to its `style` attribute, if any. Since the `<style>`
+    /// elements of the document normally precede its content, the callbacks can share the
+    /// parsed styles.
+    pub fn handlers<'h, H: HandlerTypes>(
+        &'h self,
+        mut inline_css: impl FnMut(&str) -> String + Send + 'h,
+        mut declarations: impl FnMut(&Element<'_, '_, H>) -> Option<String> + Send + 'h,
+    ) -> Vec<(&'h Selector, ElementContentHandlers<'h, H>)> {
+        let mut css = String::new();
+
+        vec![
+            (
+                &self.elements_selector,
+                ElementContentHandlers {
+                    element: Some(H::new_element_handler(move |el| {
+                        if self.should_strip(el) {
+                            el.remove();
+
+                            return Ok(());
+                        }
+
+                        if let Some(declarations) = declarations(el) {
+                            let declarations = declarations.trim().trim_end_matches(';');
+
+                            let style = match el.get_attribute("style") {
+                                Some(ref style) if !style.trim().is_empty() => {
+                                    format!("{};{}", declarations, style.trim())
+                                }
+                                _ => declarations.to_owned(),
+                            };
+
+                            if !style.is_empty() {
+                                el.set_attribute("style", &style)?;
+                            }
+                        }
+
+                        Ok(())
+                    })),
+                    ..ElementContentHandlers::default()
+                },
+            ),
+            (
+                &self.styles_selector,
+                ElementContentHandlers {
+                    text: Some(H::new_text_handler(move |t| {
+                        // NOTE: the CSS is passed to the inliner as a whole, so we hold back the
+                        // chunks of the style until its end.
+                        css.push_str(t.as_str());
+
+                        if t.last_in_text_node() {
+                            t.replace(&inline_css(&css), ContentType::Html);
+                            css.clear();
+                        } else {
+                            t.remove();
+                        }
+
+                        Ok(())
+                    })),
+                    ..ElementContentHandlers::default()
+                },
+            ),
+        ]
+    }
+}
+
+impl Default for HtmlEmail {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HtmlRewriter, Settings};
+
+    #[test]
+    fn prepare_email() {
+        let preset = HtmlEmail::new()
+            .stripped_tags(&["Video"])
+            .strip_stylesheet_links(false);
+
+        let mut styles = vec![];
+        let mut output = vec![];
+
+        {
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: preset.handlers(
+                        |css| {
+                            styles.push(css.to_owned());
+                            "@media{}".into()
+                        },
+                        |el| match el.tag_name().as_str() {
+                            "div" => Some(" color: red; ".into()),
+                            "span" => Some(";".into()),
+                            _ => None,
+                        },
+                    ),
+                    ..Settings::default()
+                },
+                |c: &[u8]| output.extend_from_slice(c),
+            )
+            .unwrap();
+
+            for chunk in &[
+                "<style>a{}",
+                "b{}</style><link rel=stylesheet href=a.css>",
+                "<div>1</div><div style=' top: 0 '><span>2</span><video>3</video>",
+                "<script>4</script></div>",
+            ] {
+                rewriter.write(chunk.as_bytes()).unwrap();
+            }
+
+            rewriter.end().unwrap();
+        }
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            concat!(
+                "<style>@media{}</style><link rel=stylesheet href=a.css>",
+                r#"<div style="color: red">1</div><div style="color: red;top: 0"><span>2</span>"#,
+                "<script>4</script></div>"
+            )
+        );
+
+        assert_eq!(styles, ["a{}b{}"]);
+    }
+}
@@ -0,0 +1,239 @@
+use super::json_scripts::json_script_handlers;
+use crate::{ElementContentHandlers, HandlerTypes, Selector};
+use serde_json::{Map, Value};
+use std::error::Error;
+
+/// The content of an import map, e.g. `{"imports": {"vue": "/vue.js"}}`.
+///
+/// Refer to [`ImportMaps`] for more information.
+///
+/// [`ImportMaps`]: struct.ImportMaps.html
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImportMap {
+    /// The `imports` map of the module specifiers to the URLs.
+    pub imports: Map<String, Value>,
+    /// The `scopes` map of the URL prefixes to the specifier maps that are used for the modules
+    /// imported from the URLs with the prefix.
+    pub scopes: Map<String, Value>,
+    /// The other top-level keys of the import map (e.g. `integrity`), which are preserved as is.
+    pub other: Map<String, Value>,
+}
+
+impl ImportMap {
+    /// Returns the import map for the `value`, or `None` if the value doesn't have the shape of
+    /// an import map.
+    fn from_value(value: &Value) -> Option<Self> {
+        let mut other = value.as_object()?.clone();
+
+        let mut take_map = |key| match other.remove(key) {
+            Some(Value::Object(map)) => Some(map),
+            Some(_) => None,
+            None => Some(Map::new()),
+        };
+
+        let imports = take_map("imports")?;
+        let scopes = take_map("scopes")?;
+
+        Some(ImportMap {
+            imports,
+            scopes,
+            other,
+        })
+    }
+
+    fn into_value(self) -> Value {
+        let mut value = Map::new();
+
+        // NOTE: the empty maps are equivalent to the absent ones. The keys of the serialized
+        // map are sorted, so the order of the insertion doesn't matter.
+        if !self.imports.is_empty() {
+            value.insert("imports".into(), self.imports.into());
+        }
+
+        if !self.scopes.is_empty() {
+            value.insert("scopes".into(), self.scopes.into());
+        }
+
+        value.extend(self.other);
+
+        value.into()
+    }
+}
+
+/// Edits the import map of the document, e.g. to pin the versions of the modules or to point
+/// them to a CDN.
+///
+/// A document can have only one import map: browsers ignore the `<script type="importmap">`
+/// elements that follow the first one. So, only the first import map of the document is passed
+/// to the callback, and the rest are left intact. The handlers keep track of the import maps
+/// they have seen, so new handlers should be constructed for each document.
+///
+/// Once the callback has edited the map, it's serialized back with all `<` characters escaped,
+/// so the content can't close the element prematurely. The import maps that are not valid JSON
+/// or don't have the shape of an import map are left intact.
+///
+/// Available with the `json` feature.
+///
+/// # Example
+/// ```
+/// use lol_html::presets::ImportMaps;
+/// use lol_html::{rewrite_str, RewriteStrSettings};
+///
+/// let preset = ImportMaps::new();
+///
+/// let html = rewrite_str(
+///     r#"<script type="importmap">{"imports": {"vue": "/vue.js"}}</script>"#,
+///     RewriteStrSettings {
+///         element_content_handlers: vec![preset.handlers(|map| {
+///             for url in map.imports.values_mut() {
+///                 if let Some(path) = url.as_str().map(str::to_owned) {
+///                     *url = format!("https://cdn.example.com{}", path).into();
+///                 }
+///             }
+///
+///             Ok(())
+///         })],
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(
+///     html,
+///     concat!(
+///         r#"<script type="importmap">"#,
+///         r#"{"imports":{"vue":"https://cdn.example.com/vue.js"}}</script>"#
+///     )
+/// );
+/// ```
+pub struct ImportMaps {
+    selector: Selector,
+}
+
+impl ImportMaps {
+    /// Creates the preset.
+    pub fn new() -> Self {
+        ImportMaps {
+            // NOTE: the selector is a constant, so it's safe to unwrap here.
+            selector: "script[type]".parse().unwrap(),
+        }
+    }
+
+    /// Constructs an element content handler that passes the first import map of the document
+    /// to the `edit` callback.
+    ///
+    /// An error returned by the callback stops the rewriting, as for any other content handler.
+    pub fn handlers<'s, 'h, H: HandlerTypes>(
+        &'s self,
+        mut edit: impl FnMut(&mut ImportMap) -> Result<(), Box<dyn Error>> + Send + 'h,
+    ) -> (&'s Selector, ElementContentHandlers<'h, H>) {
+        let mut seen = false;
+
+        let handlers = json_script_handlers(
+            move |el| {
+                let is_import_map = el
+                    .get_attribute("type")
+                    .is_some_and(|t| t.trim().eq_ignore_ascii_case("importmap"));
+
+                if is_import_map && !seen {
+                    seen = true;
+
+                    true
+                } else {
+                    false
+                }
+            },
+            move |value| match ImportMap::from_value(value) {
+                Some(mut map) => {
+                    edit(&mut map)?;
+
+                    *value = map.into_value();
+
+                    Ok(true)
+                }
+                None => Ok(false),
+            },
+        );
+
+        (&self.selector, handlers)
+    }
+}
+
+impl Default for ImportMaps {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rewrite_str, RewriteStrSettings};
+
+    fn rewrite(html: &str) -> (String, Vec<ImportMap>) {
+        let preset = ImportMaps::new();
+        let mut maps = vec![];
+
+        let output = rewrite_str(
+            html,
+            RewriteStrSettings {
+                element_content_handlers: vec![preset.handlers(|map| {
+                    maps.push(map.clone());
+
+                    map.imports.insert("app".into(), "/app.js".into());
+
+                    Ok(())
+                })],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        (output, maps)
+    }
+
+    #[test]
+    fn first_import_map_only() {
+        let (html, maps) = rewrite(concat!(
+            r#"<script type=" ImportMap ">{"scopes": {"/a/": {}}, "integrity": {}}</script>"#,
+            r#"<script type="importmap">{"imports": {}}</script>"#
+        ));
+
+        assert_eq!(
+            html,
+            concat!(
+                r#"<script type=" ImportMap ">"#,
+                r#"{"imports":{"app":"/app.js"},"integrity":{},"scopes":{"/a/":{}}}</script>"#,
+                r#"<script type="importmap">{"imports": {}}</script>"#
+            )
+        );
+
+        assert_eq!(maps.len(), 1);
+        assert!(maps[0].imports.is_empty());
+        assert_eq!(maps[0].scopes.len(), 1);
+        assert_eq!(maps[0].other.len(), 1);
+    }
+
+    #[test]
+    fn invalid_import_maps_are_preserved() {
+        for &html in &[
+            r#"<script type="importmap">{"imports": </script>"#,
+            r#"<script type="importmap">[ ]</script>"#,
+            r#"<script type="importmap">{"imports": []}</script>"#,
+        ] {
+            let (output, maps) = rewrite(html);
+
+            assert_eq!(output, html);
+            assert!(maps.is_empty());
+        }
+    }
+
+    #[test]
+    fn other_scripts_are_skipped() {
+        let html = r#"<script type="application/json">{"imports": {}}</script>"#;
+        let (output, maps) = rewrite(html);
+
+        assert_eq!(output, html);
+        assert!(maps.is_empty());
+    }
+}
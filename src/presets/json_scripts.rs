@@ -0,0 +1,243 @@
+use crate::html_content::{ContentType, Element, ScriptType};
+use crate::{ElementContentHandlers, HandlerTypes, Selector};
+use serde_json::Value;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Edits the JSON data blocks of the document, e.g. the server-side rendering state of a
+/// single-page application.
+///
+/// The content of each matched `<script>` element with a JSON type (e.g. `application/json` or
+/// `application/ld+json`) is aggregated, parsed and passed to the callback. Once the callback has
+/// edited the value, it's serialized back with all `<` characters escaped, so the content can't
+/// close the element prematurely. The scripts of other types are left intact, as well as the
+/// content that is not valid JSON.
+///
+/// Available with the `json` feature.
+///
+/// # Example
+/// ```
+/// use lol_html::presets::JsonScripts;
+/// use lol_html::{rewrite_str, RewriteStrSettings};
+///
+/// let preset = JsonScripts::new();
+///
+/// let html = rewrite_str(
+///     r#"<script id="state" type="application/json">{"user": null}</script>"#,
+///     RewriteStrSettings {
+///         element_content_handlers: vec![preset.handlers(|value| {
+///             value["user"] = "</script>".into();
+///
+///             Ok(())
+///         })],
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(
+///     html,
+///     r#"<script id="state" type="application/json">{"user":"\u003c/script>"}</script>"#
+/// );
+/// ```
+pub struct JsonScripts {
+    selector: Selector,
+}
+
+impl JsonScripts {
+    /// Creates the preset.
+    pub fn new() -> Self {
+        JsonScripts {
+            // NOTE: the selector is a constant, so it's safe to unwrap here.
+            selector: "script[type]".parse().unwrap(),
+        }
+    }
+
+    /// Sets the selector of the `<script>` elements to edit, e.g. `script#__NEXT_DATA__`. The
+    /// matched elements that are not JSON data blocks are skipped.
+    ///
+    /// ### Default
+    ///
+    /// `script[type]`, i.e. all the JSON data blocks of the document.
+    #[inline]
+    pub fn scripts(mut self, selector: Selector) -> Self {
+        self.selector = selector;
+        self
+    }
+
+    /// Constructs an element content handler that passes the parsed content of each JSON data
+    /// block to the `edit` callback.
+    ///
+    /// An error returned by the callback stops the rewriting, as for any other content handler.
+    pub fn handlers<'s, 'h, H: HandlerTypes>(
+        &'s self,
+        mut edit: impl FnMut(&mut Value) -> Result<(), Box<dyn Error>> + Send + 'h,
+    ) -> (&'s Selector, ElementContentHandlers<'h, H>) {
+        let handlers = json_script_handlers(
+            |el| {
+                let script_type =
+                    ScriptType::from_type_attribute(el.get_attribute("type").as_deref());
+
+                script_type == Some(ScriptType::Json)
+            },
+            move |value| edit(value).map(|_| true),
+        );
+
+        (&self.selector, handlers)
+    }
+}
+
+/// Constructs the handlers that pass the parsed content of the `<script>` elements accepted by
+/// the `filter` to the `edit` callback and replace the content with the edited value. If the
+/// callback returns `false`, the content is left intact.
+pub(super) fn json_script_handlers<'h, H: HandlerTypes>(
+    mut filter: impl FnMut(&Element<'_, '_, H>) -> bool + Send + 'h,
+    mut edit: impl FnMut(&mut Value) -> Result<bool, Box<dyn Error>> + Send + 'h,
+) -> ElementContentHandlers<'h, H> {
+    let is_json = Arc::new(AtomicBool::new(false));
+    let is_json_for_text = Arc::clone(&is_json);
+    let mut content = String::new();
+
+    ElementContentHandlers {
+        element: Some(H::new_element_handler(move |el| {
+            is_json.store(el.tag_name() == "script" && filter(el), Ordering::Relaxed);
+
+            Ok(())
+        })),
+        text: Some(H::new_text_handler(move |t| {
+            if !is_json_for_text.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+
+            content.push_str(t.as_str());
+
+            if !t.last_in_text_node() {
+                t.remove();
+
+                return Ok(());
+            }
+
+            let content = std::mem::take(&mut content);
+
+            let edited = match serde_json::from_str::<Value>(&content) {
+                Ok(mut value) => {
+                    if edit(&mut value)? {
+                        Some(value)
+                    } else {
+                        None
+                    }
+                }
+                Err(_) => None,
+            };
+
+            match edited {
+                Some(value) => t.replace_script_safe(&value.to_string(), ScriptType::Json)?,
+                // NOTE: the content is not valid JSON or it has been left intact by the
+                // callback, so it's restored as is. Script data is not escaped, so it's
+                // emitted as HTML.
+                None => t.replace(&content, ContentType::Html),
+            }
+
+            Ok(())
+        })),
+        ..ElementContentHandlers::default()
+    }
+}
+
+impl Default for JsonScripts {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::Output;
+    use crate::{HtmlRewriter, Settings};
+    use encoding_rs::UTF_8;
+
+    fn rewrite(chunks: &[&str], preset: &JsonScripts) -> (String, Vec<Value>) {
+        let mut output = Output::new(UTF_8);
+        let mut values = vec![];
+
+        {
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: vec![preset.handlers(|value| {
+                        values.push(value.clone());
+
+                        if let Value::Object(map) = value {
+                            map.insert("edited".into(), true.into());
+                        }
+
+                        Ok(())
+                    })],
+                    ..Settings::default()
+                },
+                |c: &[u8]| output.push(c),
+            )
+            .unwrap();
+
+            for chunk in chunks {
+                rewriter.write(chunk.as_bytes()).unwrap();
+            }
+
+            rewriter.end().unwrap();
+        }
+
+        (output.into(), values)
+    }
+
+    #[test]
+    fn edit_json_across_chunks() {
+        let (html, values) = rewrite(
+            &[
+                r#"<script type="application/ld+json">{"name": "<!"#,
+                r#"--a-->"}</script><script type="module">{"a": 1}</script>"#,
+            ],
+            &JsonScripts::new(),
+        );
+
+        assert_eq!(
+            html,
+            concat!(
+                r#"<script type="application/ld+json">{"edited":true,"name":"\u003c!--a-->"}"#,
+                r#"</script><script type="module">{"a": 1}</script>"#
+            )
+        );
+
+        assert_eq!(values, vec![serde_json::json!({ "name": "<!--a-->" })]);
+    }
+
+    #[test]
+    fn invalid_json_is_preserved() {
+        let html = r#"<script type="importmap">{"imports": </script>"#;
+        let (output, values) = rewrite(&[html], &JsonScripts::new());
+
+        assert_eq!(output, html);
+        assert!(values.is_empty());
+    }
+
+    #[test]
+    fn custom_selector() {
+        let preset = JsonScripts::new().scripts("#state".parse().unwrap());
+
+        let (html, _) = rewrite(
+            &[concat!(
+                r#"<script id="state" type="application/json">{}</script>"#,
+                r#"<div id="state">{}</div>"#
+            )],
+            &preset,
+        );
+
+        assert_eq!(
+            html,
+            concat!(
+                r#"<script id="state" type="application/json">{"edited":true}</script>"#,
+                r#"<div id="state">{}</div>"#
+            )
+        );
+    }
+}
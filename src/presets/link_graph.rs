@@ -0,0 +1,476 @@
+use super::url::UrlParts;
+use crate::html_content::{Element, TextType};
+use crate::{DocumentContentHandlers, ElementContentHandlers, HandlerTypes, Selector};
+use std::sync::{Arc, Mutex};
+
+// NOTE: the anchor text is truncated, so a link that wraps a large part of the document
+// doesn't make the extractor buffer all of it.
+const MAX_ANCHOR_TEXT_LEN: usize = 1024;
+
+/// A hyperlink of the document, produced by the [`LinkGraphExtractor`].
+///
+/// [`LinkGraphExtractor`]: struct.LinkGraphExtractor.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkRecord {
+    /// The URL of the link as specified in the `href` attribute, without the surrounding
+    /// whitespace.
+    pub href: String,
+    /// The text of the link with the whitespace collapsed, including the `alt` text of the
+    /// images in the link. The character references in the text are not decoded.
+    pub text: String,
+    /// The lowercased link types from the `rel` attribute.
+    pub rel: Vec<String>,
+    /// `true` if the link shouldn't be followed by crawlers, either because of the `nofollow`
+    /// link type or because the document has the `nofollow` directive in its
+    /// `<meta name="robots">` element.
+    pub nofollow: bool,
+    /// `true` if the link points to a host other than the [`hosts`] of the site.
+    ///
+    /// [`hosts`]: struct.LinkGraphExtractor.html#method.hosts
+    pub external: bool,
+}
+
+#[derive(Default)]
+struct ExtractorState {
+    /// The link whose text is being collected, along with its sequence number.
+    current: Option<(usize, LinkRecord)>,
+    pending_space: bool,
+    /// The links whose end tags have been reached, but which haven't been passed to the callback.
+    completed: Vec<LinkRecord>,
+    link_count: usize,
+    nofollow_document: bool,
+}
+
+impl ExtractorState {
+    fn append_text(&mut self, text: &str) {
+        let pending_space = &mut self.pending_space;
+
+        if let Some((_, ref mut link)) = self.current {
+            for ch in text.chars() {
+                if ch.is_whitespace() {
+                    *pending_space = true;
+                } else if link.text.len() + ch.len_utf8() < MAX_ANCHOR_TEXT_LEN {
+                    if *pending_space && !link.text.is_empty() {
+                        link.text.push(' ');
+                    }
+
+                    *pending_space = false;
+                    link.text.push(ch);
+                }
+            }
+        }
+    }
+
+    fn complete_current(&mut self) {
+        if let Some((_, link)) = self.current.take() {
+            self.completed.push(link);
+        }
+
+        self.pending_space = false;
+    }
+}
+
+/// Extracts the hyperlinks of a document as [`LinkRecord`]s, e.g. to build a link graph in a
+/// crawler.
+///
+/// The `<a>` and `<area>` elements with the `href` attribute are extracted, except for the links
+/// with the schemes other than `http` and `https`, e.g. `mailto:` or `javascript:` links. The
+/// records are produced in the document order. Since the text of the `<a>` element is known
+/// only once its end tag is reached, the record is passed to the callback as soon as any content
+/// that follows the element is processed, or at the end of the document.
+///
+/// # Example
+/// ```
+/// use lol_html::presets::{LinkGraphExtractor, LinkRecord};
+/// use lol_html::{rewrite_str, RewriteStrSettings};
+///
+/// let extractor = LinkGraphExtractor::new().hosts(&["example.com"]);
+/// let mut links = vec![];
+///
+/// {
+///     let (element_content_handlers, document_content_handlers) =
+///         extractor.handlers(|link| links.push(link));
+///
+///     rewrite_str(
+///         concat!(
+///             r#"<p>See <a href="/docs">the  <b>docs</b></a> or "#,
+///             r#"<a href="https://example.net" rel="NoFollow ugc">this</a>.</p>"#,
+///             r#"<a href="mailto:me@example.com">Mail</a>"#,
+///         ),
+///         RewriteStrSettings {
+///             element_content_handlers,
+///             document_content_handlers: vec![document_content_handlers],
+///             ..RewriteStrSettings::default()
+///         }
+///     ).unwrap();
+/// }
+///
+/// assert_eq!(
+///     links,
+///     [
+///         LinkRecord {
+///             href: "/docs".into(),
+///             text: "the docs".into(),
+///             rel: vec![],
+///             nofollow: false,
+///             external: false,
+///         },
+///         LinkRecord {
+///             href: "https://example.net".into(),
+///             text: "this".into(),
+///             rel: vec!["nofollow".into(), "ugc".into()],
+///             nofollow: true,
+///             external: true,
+///         },
+///     ]
+/// );
+/// ```
+///
+/// [`LinkRecord`]: struct.LinkRecord.html
+pub struct LinkGraphExtractor {
+    hosts: Vec<String>,
+    links_selector: Selector,
+    images_selector: Selector,
+    robots_selector: Selector,
+}
+
+impl LinkGraphExtractor {
+    /// Creates the extractor.
+    pub fn new() -> Self {
+        LinkGraphExtractor {
+            hosts: Vec::default(),
+            // NOTE: the selectors are constants, so it's safe to unwrap here.
+            links_selector: "a[href], area[href]".parse().unwrap(),
+            images_selector: "a[href] img[alt]".parse().unwrap(),
+            robots_selector: r#"meta[name="robots" i][content]"#.parse().unwrap(),
+        }
+    }
+
+    /// Sets the hosts of the site the document belongs to. The links with absolute URLs that
+    /// point to other hosts are marked as external. The hosts are compared case-insensitively
+    /// and should include the port if it's specified in the URLs.
+    ///
+    /// ### Default
+    ///
+    /// No hosts, i.e. all the links with absolute URLs are external.
+    #[inline]
+    pub fn hosts(mut self, hosts: &[&str]) -> Self {
+        self.hosts = hosts.iter().map(|h| h.to_ascii_lowercase()).collect();
+        self
+    }
+
+    /// Creates the record for the link element, or returns `None` if the link should be skipped.
+    fn link_record<H: HandlerTypes>(
+        &self,
+        el: &Element<'_, '_, H>,
+        nofollow_document: bool,
+    ) -> Option<LinkRecord> {
+        let href = el.get_attribute("href")?.trim().to_owned();
+        let parts = UrlParts::parse(&href)?;
+
+        let external = match parts.host {
+            Some(host) => !self.hosts.iter().any(|h| h.eq_ignore_ascii_case(host)),
+            None => false,
+        };
+
+        let rel: Vec<String> = el
+            .get_attribute("rel")
+            .map(|rel| {
+                rel.split_ascii_whitespace()
+                    .map(|t| t.to_ascii_lowercase())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let nofollow = nofollow_document || rel.iter().any(|t| t == "nofollow");
+
+        Some(LinkRecord {
+            href,
+            text: String::new(),
+            rel,
+            nofollow,
+            external,
+        })
+    }
+
+    /// Constructs the handlers that extract the links of the document and pass them to the
+    /// `on_link` callback.
+    ///
+    /// Both the element content handlers and the document content handlers should be used with
+    /// the same rewriter. The handlers of a single call should be used for a single document.
+    pub fn handlers<'h, H: HandlerTypes>(
+        &'h self,
+        on_link: impl FnMut(LinkRecord) + Send + 'h,
+    ) -> (
+        Vec<(&'h Selector, ElementContentHandlers<'h, H>)>,
+        DocumentContentHandlers<'h, H>,
+    ) {
+        let state = Arc::new(Mutex::new(ExtractorState::default()));
+
+        let flush = {
+            let state = Arc::clone(&state);
+            let on_link = Arc::new(Mutex::new(on_link));
+
+            move || {
+                let completed = std::mem::take(&mut state.lock().unwrap().completed);
+
+                for link in completed {
+                    (*on_link.lock().unwrap())(link);
+                }
+            }
+        };
+
+        let mut element_content_handlers = Vec::with_capacity(3);
+
+        element_content_handlers.push((&self.robots_selector, {
+            let state = Arc::clone(&state);
+
+            ElementContentHandlers {
+                element: Some(H::new_element_handler(move |el| {
+                    if let Some(content) = el.get_attribute("content") {
+                        let is_nofollow = content
+                            .split(|c: char| c == ',' || c.is_ascii_whitespace())
+                            .any(|d| {
+                                d.eq_ignore_ascii_case("nofollow") || d.eq_ignore_ascii_case("none")
+                            });
+
+                        if is_nofollow {
+                            state.lock().unwrap().nofollow_document = true;
+                        }
+                    }
+
+                    Ok(())
+                })),
+                ..ElementContentHandlers::default()
+            }
+        }));
+
+        element_content_handlers.push((&self.links_selector, {
+            let state = Arc::clone(&state);
+            let text_state = Arc::clone(&state);
+            let flush = flush.clone();
+
+            ElementContentHandlers {
+                element: Some(H::new_element_handler(move |el| {
+                    let mut link_state = state.lock().unwrap();
+
+                    // NOTE: links can't be nested, so the parser closes the current link.
+                    link_state.complete_current();
+
+                    let link = match self.link_record(el, link_state.nofollow_document) {
+                        Some(link) => link,
+                        None => {
+                            drop(link_state);
+                            flush();
+
+                            return Ok(());
+                        }
+                    };
+
+                    let seq = link_state.link_count;
+
+                    link_state.link_count += 1;
+
+                    if el.tag_name() == "area" {
+                        link_state.current = Some((seq, link));
+                        link_state.append_text(&el.get_attribute("alt").unwrap_or_default());
+                        link_state.complete_current();
+                    } else {
+                        let end_state = Arc::clone(&state);
+
+                        link_state.current = Some((seq, link));
+
+                        el.add_end_tag_handler(H::new_end_tag_handler(move |_| {
+                            let mut end_state = end_state.lock().unwrap();
+
+                            // NOTE: the link might have been already closed implicitly.
+                            if matches!(end_state.current, Some((s, _)) if s == seq) {
+                                end_state.complete_current();
+                            }
+
+                            Ok(())
+                        }));
+                    }
+
+                    drop(link_state);
+                    flush();
+
+                    Ok(())
+                })),
+                text: Some(H::new_text_handler(move |chunk| {
+                    if chunk.text_type() == TextType::Data {
+                        text_state.lock().unwrap().append_text(chunk.as_str());
+                    }
+
+                    Ok(())
+                })),
+                ..ElementContentHandlers::default()
+            }
+        }));
+
+        element_content_handlers.push((&self.images_selector, {
+            let state = Arc::clone(&state);
+
+            ElementContentHandlers {
+                element: Some(H::new_element_handler(move |el| {
+                    if let Some(alt) = el.get_attribute("alt") {
+                        let mut state = state.lock().unwrap();
+
+                        // NOTE: the alt text is separated from the surrounding text as a word.
+                        state.pending_space = true;
+                        state.append_text(&alt);
+                        state.pending_space = true;
+                    }
+
+                    Ok(())
+                })),
+                ..ElementContentHandlers::default()
+            }
+        }));
+
+        let document_content_handlers = DocumentContentHandlers {
+            text: Some(H::new_text_handler({
+                let flush = flush.clone();
+
+                move |_| {
+                    flush();
+
+                    Ok(())
+                }
+            })),
+            end: Some(H::new_end_handler(move |_| {
+                state.lock().unwrap().complete_current();
+                flush();
+
+                Ok(())
+            })),
+            ..DocumentContentHandlers::default()
+        };
+
+        (element_content_handlers, document_content_handlers)
+    }
+}
+
+impl Default for LinkGraphExtractor {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HtmlRewriter, Settings};
+
+    fn extract(chunks: &[&str]) -> Vec<LinkRecord> {
+        let extractor = LinkGraphExtractor::new().hosts(&["Example.com"]);
+        let mut links = vec![];
+
+        {
+            let (element_content_handlers, document_content_handlers) =
+                extractor.handlers(|link| links.push(link));
+
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers,
+                    document_content_handlers: vec![document_content_handlers],
+                    ..Settings::default()
+                },
+                |_: &[u8]| {},
+            )
+            .unwrap();
+
+            for chunk in chunks {
+                rewriter.write(chunk.as_bytes()).unwrap();
+            }
+
+            rewriter.end().unwrap();
+        }
+
+        links
+    }
+
+    fn link(href: &str, text: &str, nofollow: bool, external: bool) -> LinkRecord {
+        LinkRecord {
+            href: href.into(),
+            text: text.into(),
+            rel: if nofollow {
+                vec!["nofollow".into()]
+            } else {
+                vec![]
+            },
+            nofollow,
+            external,
+        }
+    }
+
+    #[test]
+    fn extract_links() {
+        assert_eq!(
+            extract(&[
+                "<a href=' //example.com/a '>\n A <img alt='B'> C</a>",
+                "<a href='https://EXAMPLE.COM:8080/b' rel=nofollow>D<a href='#c'>E",
+                "</a><a href='javascript:void(0)'>F</a><a>G</a>",
+                "<map><area href='/d' alt=' H '></map><a href=https://a.com/e><img alt=I>",
+            ]),
+            [
+                link("//example.com/a", "A B C", false, false),
+                link("https://EXAMPLE.COM:8080/b", "D", true, true),
+                link("#c", "E", false, false),
+                link("/d", "H", false, false),
+                link("https://a.com/e", "I", false, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn send_handler_types() {
+        let extractor = LinkGraphExtractor::new();
+        let mut links = vec![];
+
+        {
+            let (element_content_handlers, document_content_handlers) =
+                extractor.handlers(|link| links.push(link));
+
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers,
+                    document_content_handlers: vec![document_content_handlers],
+                    ..Settings::new_send()
+                },
+                |_: &[u8]| {},
+            )
+            .unwrap();
+
+            rewriter.write(b"<a href=/a>A</a><a href=/b>").unwrap();
+
+            // NOTE: the rewriter with the `Send` handlers can finish on another thread.
+            std::thread::scope(|s| {
+                s.spawn(move || {
+                    rewriter.write(b"B").unwrap();
+                    rewriter.end().unwrap();
+                });
+            });
+        }
+
+        assert_eq!(
+            links,
+            [link("/a", "A", false, false), link("/b", "B", false, false)]
+        );
+    }
+
+    #[test]
+    fn robots_nofollow() {
+        let links =
+            extract(&[r#"<meta name="Robots" content="noindex,NOFOLLOW"><a href=/a>A</a>"#]);
+
+        assert_eq!(
+            links,
+            [LinkRecord {
+                nofollow: true,
+                ..link("/a", "A", false, false)
+            }]
+        );
+    }
+}
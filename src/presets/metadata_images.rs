@@ -0,0 +1,177 @@
+use crate::html_content::Element;
+use crate::{ElementContentHandlers, HandlerTypes, Selector};
+
+// NOTE: metadata image URLs are stored in `content` attributes of `<meta>` elements and in `href`
+// attributes of `<link>` elements. The list covers Open Graph, Twitter cards, schema.org
+// microdata and the commonly used icon links.
+const METADATA_IMAGES_SELECTOR: &str = concat!(
+    r#"meta[property="og:image" i],"#,
+    r#"meta[property="og:image:url" i],"#,
+    r#"meta[property="og:image:secure_url" i],"#,
+    r#"meta[name="og:image" i],"#,
+    r#"meta[name="twitter:image" i],"#,
+    r#"meta[name="twitter:image:src" i],"#,
+    r#"meta[property="twitter:image" i],"#,
+    r#"meta[itemprop="image" i],"#,
+    r#"meta[name="msapplication-TileImage" i],"#,
+    r#"meta[name="msapplication-square70x70logo" i],"#,
+    r#"meta[name="msapplication-square150x150logo" i],"#,
+    r#"meta[name="msapplication-wide310x150logo" i],"#,
+    r#"meta[name="msapplication-square310x310logo" i],"#,
+    r#"link[rel~="icon" i],"#,
+    r#"link[rel~="apple-touch-icon" i],"#,
+    r#"link[rel~="apple-touch-icon-precomposed" i],"#,
+    r#"link[rel~="apple-touch-startup-image" i],"#,
+    r#"link[rel~="mask-icon" i],"#,
+    r#"link[rel~="fluid-icon" i],"#,
+    r#"link[rel~="image_src" i]"#,
+);
+
+/// Rewrites URLs of images referenced by the document metadata.
+///
+/// Such URLs are easy to miss with hand-written selectors: they are stored in `content`
+/// attributes of `<meta>` elements (Open Graph, Twitter cards, schema.org microdata, etc.) and
+/// in `href` attributes of icon `<link>` elements.
+///
+/// # Example
+/// ```
+/// use lol_html::presets::MetadataImageUrls;
+/// use lol_html::{rewrite_str, RewriteStrSettings};
+///
+/// let preset = MetadataImageUrls::new();
+///
+/// let html = rewrite_str(
+///     concat!(
+///         r#"<meta property="og:image" content="https://example.com/a.png">"#,
+///         r#"<link rel="shortcut icon" href="/favicon.ico">"#,
+///     ),
+///     RewriteStrSettings {
+///         element_content_handlers: vec![preset.handlers(|url| {
+///             Some(format!("https://proxy.example.net/?url={}", url))
+///         })],
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(
+///     html,
+///     concat!(
+///         r#"<meta property="og:image" content="https://proxy.example.net/?url=https://example.com/a.png">"#,
+///         r#"<link rel="shortcut icon" href="https://proxy.example.net/?url=/favicon.ico">"#,
+///     )
+/// );
+/// ```
+pub struct MetadataImageUrls {
+    selector: Selector,
+}
+
+impl MetadataImageUrls {
+    /// Creates the preset.
+    pub fn new() -> Self {
+        MetadataImageUrls {
+            // NOTE: the selector is a constant, so it's safe to unwrap here.
+            selector: METADATA_IMAGES_SELECTOR.parse().unwrap(),
+        }
+    }
+
+    /// Returns the name of the URL-bearing attribute for the element matched by the preset.
+    #[inline]
+    fn url_attribute_name<H: HandlerTypes>(el: &Element<'_, '_, H>) -> &'static str {
+        if el.tag_name() == "link" {
+            "href"
+        } else {
+            "content"
+        }
+    }
+
+    /// Constructs an element content handler that passes URLs of metadata images through the
+    /// `mapper`.
+    ///
+    /// The `mapper` receives the original attribute value. If it returns `None` the attribute
+    /// is left intact.
+    pub fn handlers<'s, 'h, H: HandlerTypes>(
+        &'s self,
+        mut mapper: impl FnMut(&str) -> Option<String> + Send + 'h,
+    ) -> (&'s Selector, ElementContentHandlers<'h, H>) {
+        (
+            &self.selector,
+            ElementContentHandlers {
+                element: Some(H::new_element_handler(move |el| {
+                    let name = Self::url_attribute_name(el);
+
+                    if let Some(url) = el.get_attribute(name) {
+                        if let Some(mapped) = mapper(url.trim()) {
+                            el.set_attribute(name, &mapped)?;
+                        }
+                    }
+
+                    Ok(())
+                })),
+                ..ElementContentHandlers::default()
+            },
+        )
+    }
+}
+
+impl Default for MetadataImageUrls {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rewrite_str, RewriteStrSettings};
+
+    fn rewrite(html: &str) -> String {
+        let preset = MetadataImageUrls::new();
+
+        rewrite_str(
+            html,
+            RewriteStrSettings {
+                element_content_handlers: vec![preset.handlers(|url| {
+                    if url.starts_with("data:") {
+                        None
+                    } else {
+                        Some(format!("/proxy/{}", url))
+                    }
+                })],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn rewrite_metadata_urls() {
+        assert_eq!(
+            rewrite(concat!(
+                r#"<meta property="OG:Image" content="a.png">"#,
+                r#"<meta name="twitter:image" content=" b.png ">"#,
+                r#"<meta itemprop="image" content="c.png">"#,
+                r#"<link rel="apple-touch-icon" href="d.png">"#,
+                r#"<link rel="icon" href="data:image/png;base64,AAAA">"#,
+            )),
+            concat!(
+                r#"<meta property="OG:Image" content="/proxy/a.png">"#,
+                r#"<meta name="twitter:image" content="/proxy/b.png">"#,
+                r#"<meta itemprop="image" content="/proxy/c.png">"#,
+                r#"<link rel="apple-touch-icon" href="/proxy/d.png">"#,
+                r#"<link rel="icon" href="data:image/png;base64,AAAA">"#,
+            )
+        );
+    }
+
+    #[test]
+    fn ignore_unrelated_metadata() {
+        let html = concat!(
+            r#"<meta property="og:title" content="a.png">"#,
+            r#"<link rel="stylesheet" href="b.css">"#,
+            r#"<meta property="og:image">"#,
+        );
+
+        assert_eq!(rewrite(html), html);
+    }
+}
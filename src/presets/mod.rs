@@ -0,0 +1,47 @@
+//! Ready-made rewriting handlers for common HTML transformation tasks.
+//!
+//! Presets own the CSS selectors they need, so they should outlive the [`Settings`] they are
+//! used in.
+//!
+//! The handlers of the presets can be used with the [`Settings::new_send`] as well, so the
+//! callbacks they take are required to be `Send`.
+//!
+//! [`Settings`]: ../struct.Settings.html
+//! [`Settings::new_send`]: ../struct.Settings.html#method.new_send
+
+mod analytics_snippet;
+mod cache_busting;
+mod critical_css;
+mod data_uris;
+mod event_handlers;
+mod html_email;
+#[cfg(feature = "json")]
+mod import_maps;
+#[cfg(feature = "json")]
+mod json_scripts;
+mod link_graph;
+mod metadata_images;
+mod progressive_web_app;
+mod resource_hints;
+mod text_statistics;
+mod theme_attributes;
+mod url;
+mod url_rewriter;
+
+pub use self::analytics_snippet::{AnalyticsSnippet, SnippetPlacement};
+pub use self::cache_busting::CacheBusting;
+pub use self::critical_css::{CriticalCss, StylesheetLoading};
+pub use self::data_uris::{DataUri, DataUriReader, DataUriWriter, DataUris};
+pub use self::event_handlers::{InlineEventHandlers, RemovedEventHandler};
+pub use self::html_email::HtmlEmail;
+#[cfg(feature = "json")]
+pub use self::import_maps::{ImportMap, ImportMaps};
+#[cfg(feature = "json")]
+pub use self::json_scripts::JsonScripts;
+pub use self::link_graph::{LinkGraphExtractor, LinkRecord};
+pub use self::metadata_images::MetadataImageUrls;
+pub use self::progressive_web_app::ProgressiveWebApp;
+pub use self::resource_hints::{ResourceHint, ResourceHints};
+pub use self::text_statistics::{TextStatistics, TextStatisticsExtractor};
+pub use self::theme_attributes::{ThemeAttributes, ThemeTarget};
+pub use self::url_rewriter::{UrlContext, UrlKind, UrlRewriter};
@@ -0,0 +1,256 @@
+use crate::html_content::{ContentType, TextChunk};
+use crate::{ElementContentHandlers, HandlerTypes, Selector};
+use std::cell::Cell;
+use std::sync::{Arc, Mutex};
+
+const SERVICE_WORKER_REGISTRATION: &str = "serviceWorker.register";
+
+#[derive(Default)]
+struct DocumentState {
+    has_manifest: bool,
+    has_service_worker: bool,
+    script_text: String,
+}
+
+impl DocumentState {
+    fn inspect_script_text(&mut self, chunk: &TextChunk) {
+        if self.has_service_worker {
+            return;
+        }
+
+        self.script_text.push_str(chunk.as_str());
+
+        if self.script_text.contains(SERVICE_WORKER_REGISTRATION) {
+            self.has_service_worker = true;
+        }
+
+        if chunk.last_in_text_node() {
+            self.script_text.clear();
+        } else {
+            // NOTE: the registration call can be split between the chunks, so we keep the tail
+            // of the text that can contain the beginning of the call.
+            let mut tail_start = self
+                .script_text
+                .len()
+                .saturating_sub(SERVICE_WORKER_REGISTRATION.len() - 1);
+
+            while !self.script_text.is_char_boundary(tail_start) {
+                tail_start -= 1;
+            }
+
+            self.script_text.drain(..tail_start);
+        }
+    }
+}
+
+/// Turns the document into a [progressive web app] by referencing the web app manifest and
+/// registering the service worker.
+///
+/// The `href` of the existing `<link rel="manifest">` elements is replaced with the manifest URL
+/// of the preset. If the document doesn't reference a manifest, the manifest link is inserted at
+/// the end of the first `<head>` element.
+///
+/// If the [`service_worker`] is specified, a script registering it is inserted at the end of the
+/// first `<head>` element, unless the document already has a script calling
+/// `serviceWorker.register`. So, the preset doesn't add the duplicate references if it's applied
+/// to the same document twice.
+///
+/// # Example
+/// ```
+/// use lol_html::presets::ProgressiveWebApp;
+/// use lol_html::{rewrite_str, RewriteStrSettings};
+///
+/// let preset = ProgressiveWebApp::new("/app.webmanifest").service_worker("/sw.js");
+///
+/// let html = rewrite_str(
+///     r#"<head><link rel="manifest" href="/old.json"></head>"#,
+///     RewriteStrSettings {
+///         element_content_handlers: preset.handlers(),
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(
+///     html,
+///     concat!(
+///         r#"<head><link rel="manifest" href="/app.webmanifest">"#,
+///         r#"<script>if('serviceWorker' in navigator){navigator.serviceWorker.register('/sw.js');}</script>"#,
+///         r#"</head>"#,
+///     )
+/// );
+/// ```
+///
+/// [progressive web app]: https://developer.mozilla.org/en-US/docs/Web/Progressive_web_apps
+/// [`service_worker`]: #method.service_worker
+pub struct ProgressiveWebApp {
+    manifest_url: String,
+    service_worker_url: Option<String>,
+    manifest_links_selector: Selector,
+    scripts_selector: Selector,
+    head_selector: Selector,
+}
+
+impl ProgressiveWebApp {
+    /// Creates the preset that references the manifest at the `manifest_url`.
+    pub fn new(manifest_url: &str) -> Self {
+        ProgressiveWebApp {
+            manifest_url: manifest_url.to_owned(),
+            service_worker_url: None,
+            // NOTE: the selectors are constants, so it's safe to unwrap here.
+            manifest_links_selector: r#"link[rel~="manifest" i]"#.parse().unwrap(),
+            scripts_selector: "script".parse().unwrap(),
+            head_selector: "head".parse().unwrap(),
+        }
+    }
+
+    /// Sets the URL of the service worker script that should be registered by the document.
+    #[inline]
+    pub fn service_worker(mut self, url: &str) -> Self {
+        self.service_worker_url = Some(url.to_owned());
+        self
+    }
+
+    fn manifest_link(&self) -> String {
+        format!(
+            r#"<link rel="manifest" href="{}">"#,
+            self.manifest_url.replace('"', "&quot;")
+        )
+    }
+
+    fn service_worker_registration(&self) -> Option<String> {
+        self.service_worker_url.as_ref().map(|url| {
+            format!(
+                "<script>if('serviceWorker' in navigator){{navigator.serviceWorker.register('{}');}}</script>",
+                url.replace('\\', "\\\\")
+                    .replace('\'', "\\'")
+                    .replace("</", "<\\/")
+            )
+        })
+    }
+
+    /// Constructs element content handlers that add the manifest and the service worker to the
+    /// document.
+    ///
+    /// The handlers of a single call should be used for a single document: they track whether the
+    /// document already references the manifest and the service worker.
+    pub fn handlers<H: HandlerTypes>(&self) -> Vec<(&Selector, ElementContentHandlers<'_, H>)> {
+        let state = Arc::new(Mutex::new(DocumentState::default()));
+        let head_seen = Cell::new(false);
+        let mut handlers = Vec::with_capacity(3);
+
+        handlers.push((&self.manifest_links_selector, {
+            let state = Arc::clone(&state);
+
+            ElementContentHandlers {
+                element: Some(H::new_element_handler(move |el| {
+                    state.lock().unwrap().has_manifest = true;
+                    el.set_attribute("href", &self.manifest_url)?;
+
+                    Ok(())
+                })),
+                ..ElementContentHandlers::default()
+            }
+        }));
+
+        if self.service_worker_url.is_some() {
+            let state = Arc::clone(&state);
+
+            handlers.push((
+                &self.scripts_selector,
+                ElementContentHandlers {
+                    text: Some(H::new_text_handler(move |t| {
+                        state.lock().unwrap().inspect_script_text(t);
+
+                        Ok(())
+                    })),
+                    ..ElementContentHandlers::default()
+                },
+            ));
+        }
+
+        handlers.push((
+            &self.head_selector,
+            ElementContentHandlers {
+                element: Some(H::new_element_handler(move |el| {
+                    if head_seen.replace(true) {
+                        return Ok(());
+                    }
+
+                    let manifest_link = self.manifest_link();
+                    let registration = self.service_worker_registration();
+                    let state = Arc::clone(&state);
+
+                    el.add_end_tag_handler(H::new_end_tag_handler(move |end| {
+                        let state = state.lock().unwrap();
+
+                        if !state.has_manifest {
+                            end.before(&manifest_link, ContentType::Html);
+                        }
+
+                        if let Some(registration) = registration {
+                            if !state.has_service_worker {
+                                end.before(&registration, ContentType::Html);
+                            }
+                        }
+
+                        Ok(())
+                    }));
+
+                    Ok(())
+                })),
+                ..ElementContentHandlers::default()
+            },
+        ));
+
+        handlers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rewrite_str, RewriteStrSettings};
+
+    fn rewrite(html: &str, preset: &ProgressiveWebApp) -> String {
+        rewrite_str(
+            html,
+            RewriteStrSettings {
+                element_content_handlers: preset.handlers(),
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn insert_manifest() {
+        let preset = ProgressiveWebApp::new(r#"/"m".json"#);
+
+        assert_eq!(
+            rewrite("<head><title>Foo</title></head><head></head>", &preset),
+            r#"<head><title>Foo</title><link rel="manifest" href="/&quot;m&quot;.json"></head><head></head>"#
+        );
+
+        assert_eq!(rewrite("<div></div>", &preset), "<div></div>");
+    }
+
+    #[test]
+    fn skip_registered_service_worker() {
+        let preset = ProgressiveWebApp::new("/m.json").service_worker("/it's.js");
+
+        assert_eq!(
+            rewrite("<head></head>", &preset),
+            concat!(
+                r#"<head><link rel="manifest" href="/m.json"><script>if('serviceWorker' in "#,
+                r#"navigator){navigator.serviceWorker.register('/it\'s.js');}</script></head>"#
+            )
+        );
+
+        let html = concat!(
+            r#"<head><link rel="manifest" href="/m.json">"#,
+            r#"<script>navigator.serviceWorker.register('/sw.js')</script></head>"#
+        );
+
+        assert_eq!(rewrite(html, &preset), html);
+    }
+}
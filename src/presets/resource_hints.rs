@@ -0,0 +1,262 @@
+use crate::html_content::{ContentType, Element};
+use crate::{ElementContentHandlers, HandlerTypes, Selector};
+use hashbrown::HashSet;
+use std::cell::Cell;
+use std::sync::{Arc, Mutex};
+
+const EXISTING_HINTS_SELECTOR: &str = concat!(
+    r#"link[rel~="preconnect" i][href],"#,
+    r#"link[rel~="dns-prefetch" i][href]"#
+);
+
+/// A type of the resource hint inserted by the [`ResourceHints`] preset.
+///
+/// [`ResourceHints`]: struct.ResourceHints.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResourceHint {
+    /// The `<link rel="preconnect">` hint.
+    Preconnect,
+    /// The `<link rel="dns-prefetch">` hint.
+    DnsPrefetch,
+}
+
+impl ResourceHint {
+    #[inline]
+    fn rel(self) -> &'static str {
+        match self {
+            ResourceHint::Preconnect => "preconnect",
+            ResourceHint::DnsPrefetch => "dns-prefetch",
+        }
+    }
+
+    #[inline]
+    fn from_rel(rel: &str) -> Option<Self> {
+        if rel.eq_ignore_ascii_case("preconnect") {
+            Some(ResourceHint::Preconnect)
+        } else if rel.eq_ignore_ascii_case("dns-prefetch") {
+            Some(ResourceHint::DnsPrefetch)
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns the key the hinted URLs are compared with: origins are case-insensitive and the hints
+/// are commonly written both with and without the trailing slash.
+#[inline]
+fn url_key(url: &str) -> String {
+    url.trim().trim_end_matches('/').to_ascii_lowercase()
+}
+
+type HintKeys = HashSet<(ResourceHint, String)>;
+
+/// Inserts resource hints (`<link rel="preconnect">` and `<link rel="dns-prefetch">`) into the
+/// `<head>` element of the document.
+///
+/// The hints are inserted at the end of the first `<head>` element, so the hints that are
+/// already present in it can be taken into account: a hint is not inserted if the document
+/// already has the hint of the same type for the same URL. The URLs are compared
+/// case-insensitively, ignoring the trailing slash. Nothing is inserted if the document doesn't
+/// have the `<head>` element.
+///
+/// # Example
+/// ```
+/// use lol_html::presets::{ResourceHint, ResourceHints};
+/// use lol_html::{rewrite_str, RewriteStrSettings};
+///
+/// let preset = ResourceHints::new()
+///     .add(ResourceHint::Preconnect, "https://fonts.example.com")
+///     .add(ResourceHint::Preconnect, "https://cdn.example.com")
+///     .add(ResourceHint::DnsPrefetch, "https://cdn.example.com");
+///
+/// let html = rewrite_str(
+///     r#"<head><link rel="preconnect" href="https://CDN.example.com/"></head>"#,
+///     RewriteStrSettings {
+///         element_content_handlers: preset.handlers(),
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(
+///     html,
+///     concat!(
+///         r#"<head><link rel="preconnect" href="https://CDN.example.com/">"#,
+///         r#"<link rel="preconnect" href="https://fonts.example.com">"#,
+///         r#"<link rel="dns-prefetch" href="https://cdn.example.com"></head>"#,
+///     )
+/// );
+/// ```
+pub struct ResourceHints {
+    hints: Vec<(ResourceHint, String)>,
+    head_selector: Selector,
+    existing_hints_selector: Selector,
+}
+
+impl ResourceHints {
+    /// Creates the preset without any hints.
+    pub fn new() -> Self {
+        ResourceHints {
+            hints: Vec::default(),
+            // NOTE: the selectors are constants, so it's safe to unwrap here.
+            head_selector: "head".parse().unwrap(),
+            existing_hints_selector: EXISTING_HINTS_SELECTOR.parse().unwrap(),
+        }
+    }
+
+    /// Adds the `hint` for the `url`. The hints are inserted in the order they have been added,
+    /// the duplicate hints are ignored.
+    pub fn add(mut self, hint: ResourceHint, url: &str) -> Self {
+        let key = url_key(url);
+
+        if !self
+            .hints
+            .iter()
+            .any(|(h, u)| *h == hint && url_key(u) == key)
+        {
+            self.hints.push((hint, url.trim().to_owned()));
+        }
+
+        self
+    }
+
+    fn record_existing_hints<H: HandlerTypes>(el: &Element<'_, '_, H>, existing: &Mutex<HintKeys>) {
+        if let (Some(rel), Some(href)) = (el.get_attribute("rel"), el.get_attribute("href")) {
+            let key = url_key(&href);
+            let mut existing = existing.lock().unwrap();
+
+            for hint in rel
+                .split_ascii_whitespace()
+                .filter_map(ResourceHint::from_rel)
+            {
+                existing.insert((hint, key.clone()));
+            }
+        }
+    }
+
+    /// Constructs element content handlers that insert the hints.
+    ///
+    /// The handlers of a single call should be used for a single document: they track the hints
+    /// that are already present in it.
+    pub fn handlers<H: HandlerTypes>(&self) -> Vec<(&Selector, ElementContentHandlers<'_, H>)> {
+        let existing = Arc::new(Mutex::new(HintKeys::default()));
+        let head_seen = Cell::new(false);
+
+        vec![
+            (&self.existing_hints_selector, {
+                let existing = Arc::clone(&existing);
+
+                ElementContentHandlers {
+                    element: Some(H::new_element_handler(move |el| {
+                        Self::record_existing_hints(el, &existing);
+
+                        Ok(())
+                    })),
+                    ..ElementContentHandlers::default()
+                }
+            }),
+            (
+                &self.head_selector,
+                ElementContentHandlers {
+                    element: Some(H::new_element_handler(move |el| {
+                        if head_seen.replace(true) {
+                            return Ok(());
+                        }
+
+                        let hints = self.hints.clone();
+                        let existing = Arc::clone(&existing);
+
+                        el.add_end_tag_handler(H::new_end_tag_handler(move |end| {
+                            let existing = existing.lock().unwrap();
+
+                            for (hint, url) in hints {
+                                if !existing.contains(&(hint, url_key(&url))) {
+                                    end.before(
+                                        &format!(
+                                            r#"<link rel="{}" href="{}">"#,
+                                            hint.rel(),
+                                            url.replace('"', "&quot;")
+                                        ),
+                                        ContentType::Html,
+                                    );
+                                }
+                            }
+
+                            Ok(())
+                        }));
+
+                        Ok(())
+                    })),
+                    ..ElementContentHandlers::default()
+                },
+            ),
+        ]
+    }
+}
+
+impl Default for ResourceHints {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rewrite_str, RewriteStrSettings};
+
+    fn rewrite(html: &str) -> String {
+        let preset = ResourceHints::new()
+            .add(ResourceHint::Preconnect, "https://a.com")
+            .add(ResourceHint::DnsPrefetch, "https://a.com")
+            .add(ResourceHint::Preconnect, " https://A.com/ ");
+
+        let output = rewrite_str(
+            html,
+            RewriteStrSettings {
+                element_content_handlers: preset.handlers(),
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        output
+    }
+
+    #[test]
+    fn insert_hints_once() {
+        assert_eq!(
+            rewrite("<head><title>Foo</title></head><head></head>"),
+            concat!(
+                r#"<head><title>Foo</title><link rel="preconnect" href="https://a.com">"#,
+                r#"<link rel="dns-prefetch" href="https://a.com"></head><head></head>"#,
+            )
+        );
+
+        assert_eq!(
+            rewrite("<head><meta charset=utf-8><body>"),
+            concat!(
+                r#"<head><meta charset=utf-8><link rel="preconnect" href="https://a.com">"#,
+                r#"<link rel="dns-prefetch" href="https://a.com"><body>"#,
+            )
+        );
+
+        assert_eq!(rewrite("<div></div>"), "<div></div>");
+    }
+
+    #[test]
+    fn skip_existing_hints() {
+        let html = r#"<head><link rel="DNS-Prefetch preconnect" href="https://a.com/"></head>"#;
+
+        assert_eq!(rewrite(html), html);
+
+        assert_eq!(
+            rewrite(r#"<head><link rel="preconnect" href="https://b.com"></head>"#),
+            concat!(
+                r#"<head><link rel="preconnect" href="https://b.com">"#,
+                r#"<link rel="preconnect" href="https://a.com">"#,
+                r#"<link rel="dns-prefetch" href="https://a.com"></head>"#,
+            )
+        );
+    }
+}
@@ -0,0 +1,466 @@
+use crate::html_content::{TextChunk, TextType};
+use crate::{DocumentContentHandlers, ElementContentHandlers, HandlerTypes, Selector};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// NOTE: character references are not decoded, but they shouldn't turn into words either.
+const MAX_CHAR_REF_LEN: usize = 32;
+
+/// Word, character and sentence counts of the text of a document, produced by the
+/// [`TextStatisticsExtractor`].
+///
+/// [`TextStatisticsExtractor`]: struct.TextStatisticsExtractor.html
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TextStatistics {
+    /// The number of words, i.e. whitespace-separated sequences of characters that contain at
+    /// least one letter or digit.
+    pub words: usize,
+    /// The number of non-whitespace characters. Each character reference, e.g. `&amp;`, counts
+    /// as a single character.
+    pub characters: usize,
+    /// The number of sentences. A sentence ends with a word that ends with `.`, `!` or `?`,
+    /// optionally followed by closing quotes or brackets.
+    pub sentences: usize,
+    /// The estimated number of syllables in the words. The estimation is based on the groups of
+    /// vowels in the words, so it's only meaningful for English text.
+    pub syllables: usize,
+}
+
+impl TextStatistics {
+    /// Returns the estimated time of reading the text at the given speed.
+    ///
+    /// Adults typically read 200 to 250 words per minute.
+    #[inline]
+    pub fn reading_time(&self, words_per_minute: u32) -> Duration {
+        let words_per_minute = u64::from(words_per_minute.max(1));
+
+        Duration::from_millis(self.words as u64 * 60_000 / words_per_minute)
+    }
+
+    /// Returns the [Flesch reading-ease score] of the text, or `None` if the text doesn't have
+    /// any words. Higher scores indicate text that is easier to read.
+    ///
+    /// [Flesch reading-ease score]: https://en.wikipedia.org/wiki/Flesch%E2%80%93Kincaid_readability_tests
+    pub fn flesch_reading_ease(&self) -> Option<f64> {
+        let (words_per_sentence, syllables_per_word) = self.ratios()?;
+
+        Some(206.835 - 1.015 * words_per_sentence - 84.6 * syllables_per_word)
+    }
+
+    /// Returns the [Flesch-Kincaid grade level] of the text, or `None` if the text doesn't have
+    /// any words.
+    ///
+    /// [Flesch-Kincaid grade level]: https://en.wikipedia.org/wiki/Flesch%E2%80%93Kincaid_readability_tests
+    pub fn flesch_kincaid_grade(&self) -> Option<f64> {
+        let (words_per_sentence, syllables_per_word) = self.ratios()?;
+
+        Some(0.39 * words_per_sentence + 11.8 * syllables_per_word - 15.59)
+    }
+
+    fn ratios(&self) -> Option<(f64, f64)> {
+        if self.words == 0 {
+            return None;
+        }
+
+        let words = self.words as f64;
+
+        Some((
+            words / self.sentences.max(1) as f64,
+            self.syllables as f64 / words,
+        ))
+    }
+}
+
+#[inline]
+fn is_vowel(ch: char) -> bool {
+    matches!(ch.to_ascii_lowercase(), 'a' | 'e' | 'i' | 'o' | 'u' | 'y')
+}
+
+/// The state of the word that is being counted.
+#[derive(Default)]
+struct WordState {
+    in_word: bool,
+    has_alphanumeric: bool,
+    vowel_groups: usize,
+    prev_is_vowel: bool,
+    last_letter: Option<char>,
+    prev_letter: Option<char>,
+    ends_sentence: bool,
+}
+
+impl WordState {
+    fn syllables(&self) -> usize {
+        let mut syllables = self.vowel_groups;
+
+        // NOTE: the trailing "e" is usually silent, unless it's preceded by a vowel ("free")
+        // or by "l" ("table").
+        let has_silent_e = matches!(self.last_letter, Some('e') | Some('E'))
+            && !matches!(
+                self.prev_letter,
+                Some(ch) if is_vowel(ch) || ch.eq_ignore_ascii_case(&'l')
+            );
+
+        if has_silent_e && syllables > 1 {
+            syllables -= 1;
+        }
+
+        syllables.max(1)
+    }
+}
+
+/// Counts the statistics of the text that is fed to it in arbitrary chunks.
+#[derive(Default)]
+struct TextStatisticsCounter {
+    stats: TextStatistics,
+    word: WordState,
+    char_ref_len: Option<usize>,
+    words_in_sentence: usize,
+}
+
+impl TextStatisticsCounter {
+    fn feed(&mut self, text: &str) {
+        for ch in text.chars() {
+            if let Some(len) = self.char_ref_len {
+                if ch == ';' {
+                    self.char_ref_len = None;
+                    continue;
+                } else if (ch.is_ascii_alphanumeric() || ch == '#') && len < MAX_CHAR_REF_LEN {
+                    self.char_ref_len = Some(len + 1);
+                    continue;
+                }
+
+                self.char_ref_len = None;
+            }
+
+            if ch.is_whitespace() {
+                self.end_word();
+                continue;
+            }
+
+            self.stats.characters += 1;
+            self.word.in_word = true;
+
+            match ch {
+                '&' => {
+                    self.char_ref_len = Some(0);
+                    self.word.ends_sentence = false;
+                }
+                '.' | '!' | '?' => self.word.ends_sentence = true,
+                '"' | '\'' | ')' | ']' | '\u{201D}' | '\u{2019}' | '\u{00BB}' => (),
+                _ => {
+                    self.word.ends_sentence = false;
+
+                    if ch.is_alphanumeric() {
+                        self.word.has_alphanumeric = true;
+                    }
+
+                    if ch.is_alphabetic() {
+                        let is_vowel = is_vowel(ch);
+
+                        if is_vowel && !self.word.prev_is_vowel {
+                            self.word.vowel_groups += 1;
+                        }
+
+                        self.word.prev_is_vowel = is_vowel;
+                        self.word.prev_letter = self.word.last_letter.replace(ch);
+                    } else {
+                        self.word.prev_is_vowel = false;
+                    }
+                }
+            }
+        }
+    }
+
+    fn end_word(&mut self) {
+        let word = std::mem::take(&mut self.word);
+
+        self.char_ref_len = None;
+
+        if !word.in_word || !word.has_alphanumeric {
+            return;
+        }
+
+        self.stats.words += 1;
+        self.stats.syllables += word.syllables();
+        self.words_in_sentence += 1;
+
+        if word.ends_sentence {
+            self.stats.sentences += 1;
+            self.words_in_sentence = 0;
+        }
+    }
+
+    fn finish(&mut self) -> TextStatistics {
+        self.end_word();
+
+        // NOTE: the text after the last sentence terminator is an unterminated sentence.
+        if self.words_in_sentence > 0 {
+            self.stats.sentences += 1;
+            self.words_in_sentence = 0;
+        }
+
+        self.stats
+    }
+}
+
+/// Extracts the [`TextStatistics`] of the text content of a document in a single pass, e.g. to
+/// estimate the reading time of an article.
+///
+/// Only the text that is rendered as the document's content is taken into account: the content
+/// of `<script>`, `<style>`, `<title>` and `<textarea>` elements is ignored. The content of the
+/// elements that match the [`excluded_elements`] selector, e.g. navigation and sidebars, is
+/// ignored as well. Each text node is counted separately, so words don't span across markup.
+///
+/// The statistics are passed to the callback at the end of the document.
+///
+/// # Example
+/// ```
+/// use lol_html::presets::TextStatisticsExtractor;
+/// use lol_html::{rewrite_str, RewriteStrSettings};
+/// use std::sync::Mutex;
+///
+/// let extractor = TextStatisticsExtractor::new();
+/// let stats = Mutex::new(None);
+/// let (element_content_handlers, document_content_handlers) =
+///     extractor.handlers(|s| *stats.lock().unwrap() = Some(s));
+///
+/// rewrite_str(
+///     concat!(
+///         "<nav><a href='/'>Home</a></nav>",
+///         "<article><h1>Hello!</h1><p>The cat sat on the mat. It was happy.</p></article>",
+///     ),
+///     RewriteStrSettings {
+///         element_content_handlers,
+///         document_content_handlers: vec![document_content_handlers],
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// let stats = stats.into_inner().unwrap().unwrap();
+///
+/// assert_eq!(stats.words, 10);
+/// assert_eq!(stats.sentences, 3);
+/// assert_eq!(stats.reading_time(200).as_secs(), 3);
+/// ```
+///
+/// [`TextStatistics`]: struct.TextStatistics.html
+/// [`excluded_elements`]: #method.excluded_elements
+pub struct TextStatisticsExtractor {
+    excluded_elements: Option<Selector>,
+}
+
+impl TextStatisticsExtractor {
+    /// Creates the extractor.
+    pub fn new() -> Self {
+        TextStatisticsExtractor {
+            // NOTE: the selector is a constant, so it's safe to unwrap here.
+            excluded_elements: Some("nav, aside".parse().unwrap()),
+        }
+    }
+
+    /// Sets the selector of the elements whose content is excluded from the statistics, or
+    /// includes the content of all the elements if `selector` is `None`.
+    ///
+    /// ### Default
+    ///
+    /// `nav, aside`.
+    #[inline]
+    pub fn excluded_elements(mut self, selector: Option<Selector>) -> Self {
+        self.excluded_elements = selector;
+        self
+    }
+
+    /// Constructs the handlers that extract the statistics and pass them to the `on_end`
+    /// callback once the document has ended.
+    ///
+    /// Both the element content handlers and the document content handlers should be used with
+    /// the same rewriter.
+    pub fn handlers<'h, H: HandlerTypes>(
+        &'h self,
+        on_end: impl FnOnce(TextStatistics) + Send + 'h,
+    ) -> (
+        Vec<(&'h Selector, ElementContentHandlers<'h, H>)>,
+        DocumentContentHandlers<'h, H>,
+    ) {
+        let counter = Arc::new(Mutex::new(TextStatisticsCounter::default()));
+        let is_excluded = Arc::new(AtomicBool::new(false));
+        let mut on_end = Some(on_end);
+        let mut element_content_handlers = Vec::with_capacity(1);
+
+        if let Some(ref selector) = self.excluded_elements {
+            let is_excluded = Arc::clone(&is_excluded);
+
+            // NOTE: the handler is invoked before the document's text handler, since it has a
+            // higher priority, and marks the text chunk as excluded. The flag is reset by the
+            // document's text handler, so it's only set for the text types that it handles.
+            element_content_handlers.push((
+                selector,
+                ElementContentHandlers {
+                    text: Some(H::new_text_handler(move |chunk| {
+                        if chunk.text_type() == TextType::Data {
+                            is_excluded.store(true, Ordering::Relaxed);
+                        }
+
+                        Ok(())
+                    })),
+                    priority: 1,
+                    ..ElementContentHandlers::default()
+                },
+            ));
+        }
+
+        let document_content_handlers = DocumentContentHandlers {
+            text: Some(H::new_text_handler({
+                let counter = Arc::clone(&counter);
+
+                move |chunk: &mut TextChunk| {
+                    let mut counter = counter.lock().unwrap();
+
+                    if !is_excluded.swap(false, Ordering::Relaxed) {
+                        counter.feed(chunk.as_str());
+                    }
+
+                    if chunk.last_in_text_node() {
+                        counter.end_word();
+                    }
+
+                    Ok(())
+                }
+            })),
+            text_types: Some(vec![TextType::Data]),
+            end: Some(H::new_end_handler(move |_| {
+                if let Some(on_end) = on_end.take() {
+                    on_end(counter.lock().unwrap().finish());
+                }
+
+                Ok(())
+            })),
+            ..DocumentContentHandlers::default()
+        };
+
+        (element_content_handlers, document_content_handlers)
+    }
+}
+
+impl Default for TextStatisticsExtractor {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HtmlRewriter, Settings};
+
+    fn count(chunks: &[&str]) -> TextStatistics {
+        let mut counter = TextStatisticsCounter::default();
+
+        for chunk in chunks {
+            counter.feed(chunk);
+        }
+
+        counter.finish()
+    }
+
+    #[test]
+    fn count_text() {
+        assert_eq!(count(&[]), TextStatistics::default());
+        assert_eq!(
+            count(&["  \n ", " - "]),
+            TextStatistics {
+                characters: 1,
+                ..TextStatistics::default()
+            }
+        );
+
+        assert_eq!(
+            count(&[
+                "The qu",
+                "ick fox (",
+                "really!) ",
+                "jumped. Tom &am",
+                "p; Jerry"
+            ]),
+            TextStatistics {
+                words: 7,
+                characters: 36,
+                sentences: 3,
+                syllables: 10,
+            }
+        );
+
+        assert_eq!(
+            count(&["\u{201C}Free the table!\u{201D} Make 42 snakes."]),
+            TextStatistics {
+                words: 6,
+                characters: 28,
+                sentences: 2,
+                syllables: 8,
+            }
+        );
+    }
+
+    #[test]
+    fn readability_scores() {
+        let stats = TextStatistics {
+            words: 100,
+            characters: 500,
+            sentences: 5,
+            syllables: 150,
+        };
+
+        assert_eq!(stats.reading_time(200), Duration::from_secs(30));
+        assert_eq!(stats.reading_time(0), Duration::from_secs(6000));
+        assert!((stats.flesch_reading_ease().unwrap() - 59.635).abs() < 1e-9);
+        assert!((stats.flesch_kincaid_grade().unwrap() - 9.91).abs() < 1e-9);
+
+        assert_eq!(TextStatistics::default().flesch_reading_ease(), None);
+        assert_eq!(TextStatistics::default().flesch_kincaid_grade(), None);
+    }
+
+    #[test]
+    fn extract_statistics() {
+        let extractor =
+            TextStatisticsExtractor::new().excluded_elements(Some("nav, .ad".parse().unwrap()));
+
+        let mut stats = None;
+
+        {
+            let (element_content_handlers, document_content_handlers) =
+                extractor.handlers(|s| stats = Some(s));
+
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers,
+                    document_content_handlers: vec![document_content_handlers],
+                    ..Settings::default()
+                },
+                |_: &[u8]| {},
+            )
+            .unwrap();
+
+            for chunk in &[
+                "<title>Title</title><nav>Home <b>About</b></nav><p>One t",
+                "wo<script>three()</script> four</p><div class=ad>Buy</div><aside>F",
+                "ive.</aside>",
+            ] {
+                rewriter.write(chunk.as_bytes()).unwrap();
+            }
+
+            rewriter.end().unwrap();
+        }
+
+        assert_eq!(
+            stats,
+            Some(TextStatistics {
+                words: 4,
+                characters: 15,
+                sentences: 1,
+                syllables: 4,
+            })
+        );
+    }
+}
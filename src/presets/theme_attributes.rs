@@ -0,0 +1,309 @@
+use crate::errors::AttributeNameError;
+use crate::html_content::{ContentType, Element};
+use crate::{ElementContentHandlers, HandlerTypes, Selector};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// The element the [`ThemeAttributes`] preset marks with the theme.
+///
+/// [`ThemeAttributes`]: struct.ThemeAttributes.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThemeTarget {
+    /// The `<html>` element.
+    Html,
+    /// The `<body>` element.
+    Body,
+}
+
+impl Default for ThemeTarget {
+    #[inline]
+    fn default() -> Self {
+        ThemeTarget::Html
+    }
+}
+
+/// Adds the `class` to the whitespace-separated class list, unless it's already there.
+fn add_class<H: HandlerTypes>(el: &mut Element<'_, '_, H>, class: &str) {
+    // NOTE: the attribute name is a constant, so it's safe to unwrap here.
+    match el.get_attribute("class") {
+        Some(ref classes) if classes.split_ascii_whitespace().any(|c| c == class) => (),
+        Some(ref classes) if !classes.trim().is_empty() => {
+            el.set_attribute("class", &format!("{} {}", classes.trim_end(), class))
+                .unwrap();
+        }
+        _ => el.set_attribute("class", class).unwrap(),
+    }
+}
+
+/// Marks the document with the color theme chosen for the request, e.g. from the
+/// `Sec-CH-Prefers-Color-Scheme` client hint or a cookie.
+///
+/// The theme is set as the value of the [`attribute`] (`data-theme` by default) and, if the
+/// [`class_prefix`] is specified, added as a class to the `<html>` or `<body>` element, regardless
+/// of whether the element has any attributes in the markup. Optionally, the preset also sets the
+/// content of the `<meta name="color-scheme">` element, inserting the element at the end of the
+/// first `<head>` element if the document doesn't have one.
+///
+/// # Example
+/// ```
+/// use lol_html::presets::ThemeAttributes;
+/// use lol_html::{rewrite_str, RewriteStrSettings};
+///
+/// let preset = ThemeAttributes::new().class_prefix("theme-");
+///
+/// let html = rewrite_str(
+///     r#"<html class="js"><head></head></html>"#,
+///     RewriteStrSettings {
+///         element_content_handlers: preset.handlers("dark", Some("dark")),
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(
+///     html,
+///     concat!(
+///         r#"<html class="js theme-dark" data-theme="dark">"#,
+///         r#"<head><meta name="color-scheme" content="dark"></head></html>"#,
+///     )
+/// );
+/// ```
+///
+/// [`attribute`]: #method.attribute
+/// [`class_prefix`]: #method.class_prefix
+pub struct ThemeAttributes {
+    target: ThemeTarget,
+    attribute: Option<String>,
+    class_prefix: Option<String>,
+    html_selector: Selector,
+    body_selector: Selector,
+    head_selector: Selector,
+    color_scheme_selector: Selector,
+}
+
+impl ThemeAttributes {
+    /// Creates the preset.
+    pub fn new() -> Self {
+        ThemeAttributes {
+            target: ThemeTarget::default(),
+            attribute: Some("data-theme".into()),
+            class_prefix: None,
+            // NOTE: the selectors are constants, so it's safe to unwrap here.
+            html_selector: "html".parse().unwrap(),
+            body_selector: "body".parse().unwrap(),
+            head_selector: "head".parse().unwrap(),
+            color_scheme_selector: r#"meta[name="color-scheme" i]"#.parse().unwrap(),
+        }
+    }
+
+    /// Sets the element marked with the theme.
+    ///
+    /// ### Default
+    ///
+    /// [`ThemeTarget::Html`].
+    ///
+    /// [`ThemeTarget::Html`]: enum.ThemeTarget.html#variant.Html
+    #[inline]
+    pub fn target(mut self, target: ThemeTarget) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Sets the name of the attribute that holds the theme, or disables the attribute if `name`
+    /// is `None`.
+    ///
+    /// ### Default
+    ///
+    /// `Some("data-theme")`.
+    #[inline]
+    pub fn attribute(mut self, name: Option<&str>) -> Self {
+        self.attribute = name.map(ToOwned::to_owned);
+        self
+    }
+
+    /// Enables the theme class which consists of the `prefix` followed by the theme.
+    #[inline]
+    pub fn class_prefix(mut self, prefix: &str) -> Self {
+        self.class_prefix = Some(prefix.to_owned());
+        self
+    }
+
+    fn mark_with_theme<H: HandlerTypes>(
+        &self,
+        el: &mut Element<'_, '_, H>,
+        theme: &str,
+    ) -> Result<(), AttributeNameError> {
+        if let Some(ref attribute) = self.attribute {
+            el.set_attribute(attribute, theme)?;
+        }
+
+        if let Some(ref prefix) = self.class_prefix {
+            add_class(el, &format!("{}{}", prefix, theme));
+        }
+
+        Ok(())
+    }
+
+    /// Constructs element content handlers that mark the document with the `theme` and, if the
+    /// `color_scheme` is specified, set it as the content of the `<meta name="color-scheme">`
+    /// element.
+    ///
+    /// The handlers of a single call should be used for a single document: they track whether the
+    /// document already has the `<meta name="color-scheme">` element.
+    pub fn handlers<H: HandlerTypes>(
+        &self,
+        theme: &str,
+        color_scheme: Option<&str>,
+    ) -> Vec<(&Selector, ElementContentHandlers<'_, H>)> {
+        let theme = theme.to_owned();
+        let mut handlers = Vec::with_capacity(3);
+
+        let target_selector = match self.target {
+            ThemeTarget::Html => &self.html_selector,
+            ThemeTarget::Body => &self.body_selector,
+        };
+
+        handlers.push((
+            target_selector,
+            ElementContentHandlers {
+                element: Some(H::new_element_handler(move |el| {
+                    self.mark_with_theme(el, &theme)?;
+
+                    Ok(())
+                })),
+                ..ElementContentHandlers::default()
+            },
+        ));
+
+        if let Some(color_scheme) = color_scheme {
+            let color_scheme = Arc::new(color_scheme.to_owned());
+            let has_meta = Arc::new(AtomicBool::new(false));
+            let head_seen = Cell::new(false);
+
+            handlers.push((&self.color_scheme_selector, {
+                let color_scheme = Arc::clone(&color_scheme);
+                let has_meta = Arc::clone(&has_meta);
+
+                ElementContentHandlers {
+                    element: Some(H::new_element_handler(move |el| {
+                        has_meta.store(true, Ordering::Relaxed);
+                        el.set_attribute("content", &color_scheme)?;
+
+                        Ok(())
+                    })),
+                    ..ElementContentHandlers::default()
+                }
+            }));
+
+            handlers.push((
+                &self.head_selector,
+                ElementContentHandlers {
+                    element: Some(H::new_element_handler(move |el| {
+                        if head_seen.replace(true) {
+                            return Ok(());
+                        }
+
+                        let color_scheme = Arc::clone(&color_scheme);
+                        let has_meta = Arc::clone(&has_meta);
+
+                        el.add_end_tag_handler(H::new_end_tag_handler(move |end| {
+                            if !has_meta.load(Ordering::Relaxed) {
+                                end.before(
+                                    &format!(
+                                        r#"<meta name="color-scheme" content="{}">"#,
+                                        color_scheme.replace('"', "&quot;")
+                                    ),
+                                    ContentType::Html,
+                                );
+                            }
+
+                            Ok(())
+                        }));
+
+                        Ok(())
+                    })),
+                    ..ElementContentHandlers::default()
+                },
+            ));
+        }
+
+        handlers
+    }
+}
+
+impl Default for ThemeAttributes {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rewrite_str, RewriteStrSettings};
+
+    fn rewrite(html: &str, preset: &ThemeAttributes, color_scheme: Option<&str>) -> String {
+        rewrite_str(
+            html,
+            RewriteStrSettings {
+                element_content_handlers: preset.handlers("light", color_scheme),
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn theme_attributes() {
+        let preset = ThemeAttributes::new()
+            .target(ThemeTarget::Body)
+            .attribute(Some("data-color"))
+            .class_prefix("is-");
+
+        for (html, expected) in &[
+            ("<body>", r#"<body data-color="light" class="is-light">"#),
+            (
+                r#"<body class="">"#,
+                r#"<body class="is-light" data-color="light">"#,
+            ),
+            (
+                r#"<body class="a  is-light">"#,
+                r#"<body class="a  is-light" data-color="light">"#,
+            ),
+            (
+                r#"<body class=" a " data-color=dark>"#,
+                r#"<body class=" a is-light" data-color="light">"#,
+            ),
+            ("<html></html>", "<html></html>"),
+        ] {
+            assert_eq!(rewrite(html, &preset, None), *expected);
+        }
+
+        let preset = ThemeAttributes::new().attribute(None);
+
+        assert_eq!(rewrite("<html>", &preset, None), "<html>");
+    }
+
+    #[test]
+    fn color_scheme() {
+        let preset = ThemeAttributes::new();
+
+        assert_eq!(
+            rewrite(
+                r#"<html><head><meta name="Color-Scheme" content="dark"></head><head></head>"#,
+                &preset,
+                Some("light dark")
+            ),
+            concat!(
+                r#"<html data-theme="light"><head><meta name="Color-Scheme" "#,
+                r#"content="light dark"></head><head></head>"#
+            )
+        );
+
+        assert_eq!(
+            rewrite("<head></head><head></head>", &preset, Some("light")),
+            r#"<head><meta name="color-scheme" content="light"></head><head></head>"#
+        );
+    }
+}
@@ -0,0 +1,155 @@
+/// The parts of a URL the presets need. Unlike a complete URL parser, it doesn't normalize or
+/// validate the URL.
+#[derive(Debug, PartialEq)]
+pub(crate) struct UrlParts<'u> {
+    /// The URL without the query and the fragment.
+    pub base: &'u str,
+    /// The host (with the port, if specified) of the absolute and scheme-relative URLs.
+    pub host: Option<&'u str>,
+    pub path: &'u str,
+    pub query: Option<&'u str>,
+    pub fragment: Option<&'u str>,
+}
+
+impl<'u> UrlParts<'u> {
+    /// Splits the URL into parts. Returns `None` for the URLs with schemes other than `http`
+    /// and `https`, e.g. `data:` or `blob:` URLs.
+    pub fn parse(url: &'u str) -> Option<Self> {
+        let (rest, fragment) = match url.find('#') {
+            Some(pos) => (&url[..pos], Some(&url[pos + 1..])),
+            None => (url, None),
+        };
+
+        let (base, query) = match rest.find('?') {
+            Some(pos) => (&rest[..pos], Some(&rest[pos + 1..])),
+            None => (rest, None),
+        };
+
+        let scheme_len = base
+            .find(':')
+            .filter(|&pos| {
+                pos > 0
+                    && base[..pos]
+                        .bytes()
+                        .all(|b| b.is_ascii_alphanumeric() || b == b'+' || b == b'-' || b == b'.')
+            })
+            .map(|pos| pos + 1);
+
+        let authority = match scheme_len {
+            Some(len) => {
+                let scheme = &base[..len - 1];
+
+                if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+                    return None;
+                }
+
+                Some(&base[len..])
+            }
+            None => Some(base),
+        }
+        .filter(|rest| rest.starts_with("//"))
+        .map(|rest| &rest[2..]);
+
+        let (host, path) = match authority {
+            Some(authority) => match authority.find('/') {
+                Some(pos) => (Some(&authority[..pos]), &authority[pos..]),
+                None => (Some(authority), ""),
+            },
+            None => (None, &base[scheme_len.unwrap_or(0)..]),
+        };
+
+        Some(UrlParts {
+            base,
+            host,
+            path,
+            query,
+            fragment,
+        })
+    }
+
+    /// Returns the lowercased extension of the last path segment.
+    pub fn extension(&self) -> Option<String> {
+        let segment = &self.path[self.path.rfind('/').map_or(0, |pos| pos + 1)..];
+
+        segment
+            .rfind('.')
+            .map(|pos| segment[pos + 1..].to_ascii_lowercase())
+    }
+
+    pub fn has_query_param(&self, name: &str) -> bool {
+        self.query.is_some_and(|query| {
+            query
+                .split('&')
+                .any(|pair| pair.split('=').next() == Some(name))
+        })
+    }
+
+    pub fn with_query_param(&self, name: &str, value: &str) -> String {
+        let mut url = String::from(self.base);
+
+        url.push('?');
+
+        match self.query {
+            Some(query) if !query.is_empty() => {
+                url.push_str(query);
+                url.push('&');
+            }
+            _ => (),
+        }
+
+        url.push_str(name);
+        url.push('=');
+        url.push_str(value);
+
+        if let Some(fragment) = self.fragment {
+            url.push('#');
+            url.push_str(fragment);
+        }
+
+        url
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_urls() {
+        assert_eq!(
+            UrlParts::parse("HTTPS://a.com:8080/b/c.js?d=1#e"),
+            Some(UrlParts {
+                base: "HTTPS://a.com:8080/b/c.js",
+                host: Some("a.com:8080"),
+                path: "/b/c.js",
+                query: Some("d=1"),
+                fragment: Some("e"),
+            })
+        );
+
+        assert_eq!(
+            UrlParts::parse("//a.com"),
+            Some(UrlParts {
+                base: "//a.com",
+                host: Some("a.com"),
+                path: "",
+                query: None,
+                fragment: None,
+            })
+        );
+
+        assert_eq!(
+            UrlParts::parse("c.js?#"),
+            Some(UrlParts {
+                base: "c.js",
+                host: None,
+                path: "c.js",
+                query: Some(""),
+                fragment: Some(""),
+            })
+        );
+
+        assert_eq!(UrlParts::parse("data:text/css,a{}"), None);
+        assert_eq!(UrlParts::parse("blob:https://a.com/b.js"), None);
+    }
+}
@@ -0,0 +1,667 @@
+use crate::html::is_attr_whitespace;
+use crate::html_content::{srcset, Element};
+use crate::{ElementContentHandlers, HandlerTypes, Selector};
+use std::borrow::Cow;
+use std::ops::Range;
+
+// NOTE: the attributes are listed along with the HTML elements they contain URLs in, as defined
+// by the HTML specification. The same attributes of the other elements are not URLs, e.g.
+// the `data` attribute of `<data>` elements or the `action` attribute of `<math>` elements.
+const URL_ATTRIBUTES: &[(&str, &[&str], UrlKind)] = &[
+    (
+        "href",
+        &["a", "area", "base", "link"],
+        UrlKind::AttributeValue,
+    ),
+    (
+        "src",
+        &[
+            "audio", "embed", "iframe", "img", "input", "script", "source", "track", "video",
+        ],
+        UrlKind::AttributeValue,
+    ),
+    ("srcset", &["img", "source"], UrlKind::SrcsetCandidate),
+    ("imagesrcset", &["link"], UrlKind::SrcsetCandidate),
+    ("poster", &["video"], UrlKind::AttributeValue),
+    ("action", &["form"], UrlKind::AttributeValue),
+    ("formaction", &["button", "input"], UrlKind::AttributeValue),
+    ("data", &["object"], UrlKind::AttributeValue),
+    (
+        "cite",
+        &["blockquote", "del", "ins", "q"],
+        UrlKind::AttributeValue,
+    ),
+];
+
+const META_REFRESH_SELECTOR: &str = r#"meta[http-equiv="refresh" i][content]"#;
+
+/// The way a URL discovered by the [`UrlRewriter`] is stored in the attribute value.
+///
+/// [`UrlRewriter`]: struct.UrlRewriter.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UrlKind {
+    /// The attribute value is the URL, e.g. in the `href` attribute.
+    AttributeValue,
+    /// The URL is one of the image candidates of the `srcset` or `imagesrcset` attribute.
+    SrcsetCandidate,
+    /// The URL is specified with the `url()` function in the inline `style` attribute.
+    CssUrl,
+    /// The URL is specified in the `content` attribute of the `<meta http-equiv="refresh">`
+    /// element, e.g. `5; url=/next`.
+    MetaRefresh,
+}
+
+/// The location of a URL discovered by the [`UrlRewriter`].
+///
+/// [`UrlRewriter`]: struct.UrlRewriter.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UrlContext<'c> {
+    /// The lowercased tag name of the element.
+    pub tag_name: &'c str,
+    /// The lowercased name of the attribute that contains the URL.
+    pub attribute: &'c str,
+    /// The way the URL is stored in the attribute value.
+    pub kind: UrlKind,
+}
+
+#[inline]
+fn url_kind(tag_name: &str, attribute: &str, is_meta_refresh: bool) -> Option<UrlKind> {
+    match attribute {
+        "style" => Some(UrlKind::CssUrl),
+        "content" if is_meta_refresh => Some(UrlKind::MetaRefresh),
+        _ => URL_ATTRIBUTES
+            .iter()
+            .find(|(name, elements, _)| *name == attribute && elements.contains(&tag_name))
+            .map(|&(_, _, kind)| kind),
+    }
+}
+
+#[inline]
+fn is_css_whitespace(b: u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\n' | b'\r' | b'\x0C')
+}
+
+#[inline]
+fn is_css_name_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b >= 0x80
+}
+
+/// Returns the position after the CSS escape that starts at the `pos`, right after
+/// the backslash.
+fn skip_css_escape(bytes: &[u8], mut pos: usize) -> usize {
+    let hex_start = pos;
+    let hex_end = (pos + 6).min(bytes.len());
+
+    while pos < hex_end && bytes[pos].is_ascii_hexdigit() {
+        pos += 1;
+    }
+
+    if pos == hex_start {
+        return (pos + 1).min(bytes.len());
+    }
+
+    // NOTE: the whitespace that follows the hex escape is a part of it.
+    if pos < bytes.len() && is_css_whitespace(bytes[pos]) {
+        pos += 1;
+    }
+
+    pos
+}
+
+/// Returns the ranges of the URLs of the `url()` functions in the CSS `value` along with
+/// the quote characters of the URLs that are specified as strings. The empty URLs are skipped.
+fn css_urls(value: &str) -> Vec<(Range<usize>, Option<char>)> {
+    let bytes = value.as_bytes();
+    let lowercased = value.to_ascii_lowercase();
+    let mut urls = Vec::default();
+    let mut pos = 0;
+
+    while let Some(offset) = lowercased[pos..].find("url(") {
+        let start = pos + offset;
+
+        pos = start + 4;
+
+        // NOTE: skip the functions whose names end with `url`, e.g. `myurl()`.
+        if start > 0 && is_css_name_byte(bytes[start - 1]) {
+            continue;
+        }
+
+        while pos < bytes.len() && is_css_whitespace(bytes[pos]) {
+            pos += 1;
+        }
+
+        let quote = match bytes.get(pos) {
+            Some(&b) if b == b'"' || b == b'\'' => {
+                pos += 1;
+                Some(b)
+            }
+            _ => None,
+        };
+
+        let url_start = pos;
+
+        while pos < bytes.len() {
+            let b = bytes[pos];
+
+            if b == b'\\' {
+                pos = skip_css_escape(bytes, pos + 1);
+                continue;
+            }
+
+            let is_end = match quote {
+                Some(quote) => b == quote,
+                None => b == b')' || is_css_whitespace(b),
+            };
+
+            if is_end {
+                break;
+            }
+
+            pos += 1;
+        }
+
+        if pos > url_start {
+            urls.push((url_start..pos, quote.map(char::from)));
+        }
+    }
+
+    urls
+}
+
+/// Decodes the CSS escapes in the `value`, e.g. both `\28` and `\(` are decoded to `(`.
+fn unescape_css(value: &str) -> Cow<str> {
+    if !value.contains('\\') {
+        return Cow::Borrowed(value);
+    }
+
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            unescaped.push(ch);
+            continue;
+        }
+
+        let mut code = String::new();
+
+        while let Some(&c) = chars.peek() {
+            if !c.is_ascii_hexdigit() || code.len() == 6 {
+                break;
+            }
+
+            code.push(c);
+            chars.next();
+        }
+
+        if code.is_empty() {
+            // NOTE: the escaped newline is a line continuation in CSS strings.
+            match chars.next() {
+                Some('\n') | None => (),
+                Some(c) => unescaped.push(c),
+            }
+        } else {
+            // NOTE: the whitespace that follows the hex escape is a part of it.
+            if chars.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+                chars.next();
+            }
+
+            let ch = u32::from_str_radix(&code, 16)
+                .ok()
+                .and_then(std::char::from_u32)
+                .filter(|&c| c != '\0')
+                .unwrap_or('\u{FFFD}');
+
+            unescaped.push(ch);
+        }
+    }
+
+    Cow::Owned(unescaped)
+}
+
+/// Escapes the `url` so it can be used in the `url()` function either as a string enclosed in
+/// the `quote` characters or as an unquoted URL.
+fn escape_css_url(url: &str, quote: Option<char>) -> String {
+    let mut escaped = String::with_capacity(url.len());
+
+    for ch in url.chars() {
+        match ch {
+            '\\' => escaped.push_str("\\\\"),
+            '"' | '\'' | '(' | ')' if quote.is_none() || quote == Some(ch) => {
+                escaped.push('\\');
+                escaped.push(ch);
+            }
+            ' ' if quote.is_none() => escaped.push_str("\\ "),
+            c if c.is_control() => escaped.push_str(&format!("\\{:x} ", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Returns the range of the URL in the `content` attribute value of the
+/// `<meta http-equiv="refresh">` element along with the quote character of the URL, if it's
+/// quoted, following the [parsing algorithm] of the HTML specification.
+///
+/// [parsing algorithm]: https://html.spec.whatwg.org/multipage/semantics.html#shared-declarative-refresh-steps
+fn meta_refresh_url(content: &str) -> Option<(Range<usize>, Option<char>)> {
+    let bytes = content.as_bytes();
+
+    let skip_whitespace = |mut pos: usize| {
+        while pos < bytes.len() && is_attr_whitespace(bytes[pos]) {
+            pos += 1;
+        }
+
+        pos
+    };
+
+    let mut pos = skip_whitespace(0);
+    let timeout_start = pos;
+
+    while pos < bytes.len() && (bytes[pos].is_ascii_digit() || bytes[pos] == b'.') {
+        pos += 1;
+    }
+
+    if pos == timeout_start {
+        return None;
+    }
+
+    if pos < bytes.len() && !matches!(bytes[pos], b';' | b',') && !is_attr_whitespace(bytes[pos]) {
+        return None;
+    }
+
+    pos = skip_whitespace(pos);
+
+    if pos < bytes.len() && matches!(bytes[pos], b';' | b',') {
+        pos += 1;
+    }
+
+    pos = skip_whitespace(pos);
+
+    // NOTE: the `url=` prefix is optional.
+    if bytes.len() - pos >= 3 && bytes[pos..pos + 3].eq_ignore_ascii_case(b"url") {
+        let eq_pos = skip_whitespace(pos + 3);
+
+        if bytes.get(eq_pos) == Some(&b'=') {
+            pos = skip_whitespace(eq_pos + 1);
+        }
+    }
+
+    let quote = match bytes.get(pos) {
+        Some(&b) if b == b'"' || b == b'\'' => {
+            pos += 1;
+            Some(char::from(b))
+        }
+        _ => None,
+    };
+
+    let end = match quote {
+        Some(quote) => content[pos..]
+            .find(quote)
+            .map_or(content.len(), |i| pos + i),
+        None => content.len(),
+    };
+
+    let url_end = pos + content[pos..end].trim_end().len();
+
+    if url_end > pos {
+        Some((pos..url_end, quote))
+    } else {
+        None
+    }
+}
+
+type UrlMapper<'m> = dyn FnMut(&str, &UrlContext) -> Option<String> + 'm;
+
+fn rewrite_srcset(value: &str, context: &UrlContext, mapper: &mut UrlMapper) -> Option<String> {
+    let mut candidates = srcset::parse(value);
+    let mut modified = false;
+
+    for candidate in &mut candidates {
+        if let Some(url) = mapper(candidate.url(), context) {
+            candidate.set_url(&url);
+            modified = true;
+        }
+    }
+
+    if modified {
+        Some(srcset::serialize(&candidates))
+    } else {
+        None
+    }
+}
+
+fn rewrite_css_urls(value: &str, context: &UrlContext, mapper: &mut UrlMapper) -> Option<String> {
+    let mut rewritten = String::with_capacity(value.len());
+    let mut last_end = 0;
+
+    for (range, quote) in css_urls(value) {
+        if let Some(url) = mapper(&unescape_css(&value[range.clone()]), context) {
+            rewritten.push_str(&value[last_end..range.start]);
+            rewritten.push_str(&escape_css_url(&url, quote));
+            last_end = range.end;
+        }
+    }
+
+    if last_end > 0 {
+        rewritten.push_str(&value[last_end..]);
+
+        Some(rewritten)
+    } else {
+        None
+    }
+}
+
+fn rewrite_meta_refresh(
+    value: &str,
+    context: &UrlContext,
+    mapper: &mut UrlMapper,
+) -> Option<String> {
+    let (range, quote) = meta_refresh_url(value)?;
+    let mut url = mapper(&value[range.clone()], context)?;
+
+    // NOTE: the quote character would terminate the quoted URL early.
+    if let Some(quote) = quote {
+        url = url.replace(quote, if quote == '"' { "%22" } else { "%27" });
+    }
+
+    Some(format!(
+        "{}{}{}",
+        &value[..range.start],
+        url,
+        &value[range.end..]
+    ))
+}
+
+fn rewrite_element<H: HandlerTypes>(el: &mut Element<'_, '_, H>, mapper: &mut UrlMapper) {
+    let tag_name = el.tag_name();
+
+    let is_meta_refresh = tag_name == "meta"
+        && el
+            .get_attribute("http-equiv")
+            .is_some_and(|v| v.trim().eq_ignore_ascii_case("refresh"));
+
+    for attr in el.attributes_mut() {
+        let name = attr.name();
+
+        let kind = match url_kind(&tag_name, &name, is_meta_refresh) {
+            Some(kind) => kind,
+            None => continue,
+        };
+
+        let context = UrlContext {
+            tag_name: &tag_name,
+            attribute: &name,
+            kind,
+        };
+
+        let value = attr.value();
+
+        let rewritten = match kind {
+            UrlKind::AttributeValue => mapper(value.trim(), &context),
+            UrlKind::SrcsetCandidate => rewrite_srcset(&value, &context, mapper),
+            UrlKind::CssUrl => rewrite_css_urls(&value, &context, mapper),
+            UrlKind::MetaRefresh => rewrite_meta_refresh(&value, &context, mapper),
+        };
+
+        if let Some(rewritten) = rewritten {
+            attr.set_value(&rewritten);
+        }
+    }
+}
+
+/// Rewrites all the URLs of the document that are specified in the HTML attributes.
+///
+/// The preset knows the URL attributes defined by the HTML specification, so the URLs are
+/// discovered in:
+/// * the `href` attributes of `<a>`, `<area>`, `<base>` and `<link>` elements;
+/// * the `src` attributes of `<audio>`, `<embed>`, `<iframe>`, `<img>`, `<input>`, `<script>`,
+///   `<source>`, `<track>` and `<video>` elements;
+/// * the image candidates of the `srcset` attributes of `<img>` and `<source>` elements and of
+///   the `imagesrcset` attributes of `<link>` elements;
+/// * the `poster` attributes of `<video>` elements;
+/// * the `action` attributes of `<form>` elements and the `formaction` attributes of
+///   `<button>` and `<input>` elements;
+/// * the `data` attributes of `<object>` elements;
+/// * the `cite` attributes of `<blockquote>`, `<del>`, `<ins>` and `<q>` elements;
+/// * the `url()` functions in the inline `style` attributes of any elements;
+/// * the `content` attributes of `<meta http-equiv="refresh">` elements.
+///
+/// Each URL is passed to the mapper along with its [`UrlContext`], so a single function
+/// handles all of them. The URLs are passed in the order of the attributes, without the
+/// surrounding whitespace and with the CSS escapes decoded. The character references in
+/// the attribute values are not decoded.
+///
+/// # Example
+/// ```
+/// use lol_html::presets::UrlRewriter;
+/// use lol_html::{rewrite_str, RewriteStrSettings};
+///
+/// let preset = UrlRewriter::new();
+///
+/// let html = rewrite_str(
+///     concat!(
+///         r#"<a href="/about">About</a>"#,
+///         r#"<img src="/a.png" srcset="/a.png 1x, /a@2x.png 2x">"#,
+///         r#"<div style="background: url('/bg.png')"></div>"#,
+///         r#"<meta http-equiv="refresh" content="5; url=/next">"#,
+///     ),
+///     RewriteStrSettings {
+///         element_content_handlers: vec![preset.handlers(|url, context| {
+///             if url.starts_with('/') && context.tag_name != "a" {
+///                 Some(format!("https://cdn.example.com{}", url))
+///             } else {
+///                 None
+///             }
+///         })],
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(
+///     html,
+///     concat!(
+///         r#"<a href="/about">About</a>"#,
+///         r#"<img src="https://cdn.example.com/a.png" "#,
+///         r#"srcset="https://cdn.example.com/a.png 1x, https://cdn.example.com/a@2x.png 2x">"#,
+///         r#"<div style="background: url('https://cdn.example.com/bg.png')"></div>"#,
+///         r#"<meta http-equiv="refresh" content="5; url=https://cdn.example.com/next">"#,
+///     )
+/// );
+/// ```
+///
+/// [`UrlContext`]: struct.UrlContext.html
+pub struct UrlRewriter {
+    selector: Selector,
+}
+
+impl UrlRewriter {
+    /// Creates the preset.
+    pub fn new() -> Self {
+        let mut selectors = vec![];
+
+        for (attribute, elements, _) in URL_ATTRIBUTES {
+            for element in *elements {
+                selectors.push(format!("{}[{}]", element, attribute));
+            }
+        }
+
+        selectors.push("[style]".into());
+        selectors.push(META_REFRESH_SELECTOR.into());
+
+        UrlRewriter {
+            // NOTE: the selector is produced from the constants, so it's safe to unwrap here.
+            selector: selectors.join(", ").parse().unwrap(),
+        }
+    }
+
+    /// Constructs an element content handler that passes the URLs through the `mapper`.
+    ///
+    /// The `mapper` receives the URL and its location in the document. If it returns `None`
+    /// the URL is left intact.
+    pub fn handlers<'s, 'h, H: HandlerTypes>(
+        &'s self,
+        mut mapper: impl FnMut(&str, &UrlContext) -> Option<String> + Send + 'h,
+    ) -> (&'s Selector, ElementContentHandlers<'h, H>) {
+        (
+            &self.selector,
+            ElementContentHandlers {
+                element: Some(H::new_element_handler(move |el| {
+                    rewrite_element(el, &mut mapper);
+
+                    Ok(())
+                })),
+                ..ElementContentHandlers::default()
+            },
+        )
+    }
+}
+
+impl Default for UrlRewriter {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rewrite_str, RewriteStrSettings};
+
+    fn rewrite(html: &str) -> (String, Vec<String>) {
+        let preset = UrlRewriter::new();
+        let mut urls = vec![];
+
+        let output = rewrite_str(
+            html,
+            RewriteStrSettings {
+                element_content_handlers: vec![preset.handlers(|url, context| {
+                    urls.push(format!(
+                        "{} {} {:?} {}",
+                        context.tag_name, context.attribute, context.kind, url
+                    ));
+
+                    if url.starts_with('/') {
+                        Some(format!("/p{}", url))
+                    } else {
+                        None
+                    }
+                })],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        (output, urls)
+    }
+
+    #[test]
+    fn url_attributes() {
+        let (output, urls) = rewrite(concat!(
+            "<a href=/a title=/x>A</a>",
+            r#"<img src=" /b.png " srcset="/c.png 1x, d.png 2x" alt=/y>"#,
+            "<video poster=/e.png><source src=/f.mp4></video>",
+            "<form action=/g><button formaction=/h></button></form>",
+            "<object data=/i></object><q cite=/j></q><div href=/z cite=/z></div>",
+        ));
+
+        assert_eq!(
+            output,
+            concat!(
+                r#"<a href="/p/a" title=/x>A</a>"#,
+                r#"<img src="/p/b.png" srcset="/p/c.png 1x, d.png 2x" alt=/y>"#,
+                r#"<video poster="/p/e.png"><source src="/p/f.mp4"></video>"#,
+                r#"<form action="/p/g"><button formaction="/p/h"></button></form>"#,
+                r#"<object data="/p/i"></object><q cite="/p/j"></q><div href=/z cite=/z></div>"#,
+            )
+        );
+
+        assert_eq!(
+            urls,
+            [
+                "a href AttributeValue /a",
+                "img src AttributeValue /b.png",
+                "img srcset SrcsetCandidate /c.png",
+                "img srcset SrcsetCandidate d.png",
+                "video poster AttributeValue /e.png",
+                "source src AttributeValue /f.mp4",
+                "form action AttributeValue /g",
+                "button formaction AttributeValue /h",
+                "object data AttributeValue /i",
+                "q cite AttributeValue /j",
+            ]
+        );
+    }
+
+    #[test]
+    fn style_urls() {
+        let (output, urls) = rewrite(concat!(
+            r#"<div style="background: URL( /a.png ) no-repeat, url('/b (1).png'), "#,
+            r#"myurl(/c.png), url(/d\28 2\29.png), url(e.png), url()"></div>"#,
+        ));
+
+        assert_eq!(
+            output,
+            concat!(
+                r#"<div style="background: URL( /p/a.png ) no-repeat, url('/p/b (1).png'), "#,
+                r#"myurl(/c.png), url(/p/d\(2\).png), url(e.png), url()"></div>"#,
+            )
+        );
+
+        assert_eq!(
+            urls,
+            [
+                "div style CssUrl /a.png",
+                "div style CssUrl /b (1).png",
+                "div style CssUrl /d(2).png",
+                "div style CssUrl e.png",
+            ]
+        );
+
+        assert_eq!(
+            escape_css_url("/a b'\"\\\n.png", None),
+            "/a\\ b\\'\\\"\\\\\\a .png"
+        );
+
+        assert_eq!(escape_css_url("/a b'(\".png", Some('"')), "/a b'(\\\".png");
+    }
+
+    #[test]
+    fn meta_refresh() {
+        for (content, expected) in &[
+            ("5; url=/a", Some("/a")),
+            ("0;URL = '/b c' ", Some("/b c")),
+            ("0, /c", Some("/c")),
+            ("3 url=/d", Some("/d")),
+            ("1.5;url=\"/e\"", Some("/e")),
+            ("5", None),
+            ("5; ", None),
+            ("x; url=/f", None),
+            ("5x; url=/g", None),
+        ] {
+            assert_eq!(
+                meta_refresh_url(content).map(|(range, _)| &content[range]),
+                *expected,
+                "Content: {}",
+                content
+            );
+        }
+
+        let (output, urls) = rewrite(concat!(
+            r#"<meta http-equiv=Refresh content="0; url='/a'">"#,
+            r#"<meta name=refresh content="0; url=/b">"#,
+        ));
+
+        assert_eq!(
+            output,
+            concat!(
+                r#"<meta http-equiv=Refresh content="0; url='/p/a'">"#,
+                r#"<meta name=refresh content="0; url=/b">"#,
+            )
+        );
+
+        assert_eq!(urls, ["meta content MetaRefresh /a"]);
+    }
+}
@@ -0,0 +1,546 @@
+use super::Element;
+use crate::rewriter::{HandlerTypes, LocalHandlerTypes};
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use thiserror::Error;
+
+// NOTE: https://www.w3.org/TR/wai-aria-1.2/#role_definitions
+// The table contains only non-abstract roles and must be sorted.
+static ROLES: &[&str] = &[
+    "alert",
+    "alertdialog",
+    "application",
+    "article",
+    "banner",
+    "blockquote",
+    "button",
+    "caption",
+    "cell",
+    "checkbox",
+    "code",
+    "columnheader",
+    "combobox",
+    "complementary",
+    "contentinfo",
+    "definition",
+    "deletion",
+    "dialog",
+    "directory",
+    "document",
+    "emphasis",
+    "feed",
+    "figure",
+    "form",
+    "generic",
+    "grid",
+    "gridcell",
+    "group",
+    "heading",
+    "img",
+    "insertion",
+    "link",
+    "list",
+    "listbox",
+    "listitem",
+    "log",
+    "main",
+    "marquee",
+    "math",
+    "menu",
+    "menubar",
+    "menuitem",
+    "menuitemcheckbox",
+    "menuitemradio",
+    "meter",
+    "navigation",
+    "none",
+    "note",
+    "option",
+    "paragraph",
+    "presentation",
+    "progressbar",
+    "radio",
+    "radiogroup",
+    "region",
+    "row",
+    "rowgroup",
+    "rowheader",
+    "scrollbar",
+    "search",
+    "searchbox",
+    "separator",
+    "slider",
+    "spinbutton",
+    "status",
+    "strong",
+    "subscript",
+    "superscript",
+    "switch",
+    "tab",
+    "table",
+    "tablist",
+    "tabpanel",
+    "term",
+    "textbox",
+    "time",
+    "timer",
+    "toolbar",
+    "tooltip",
+    "tree",
+    "treegrid",
+    "treeitem",
+];
+
+/// An error that occurs when a value that is not a known [WAI-ARIA role] is provided
+/// for the role.
+///
+/// [WAI-ARIA role]: https://www.w3.org/TR/wai-aria-1.2/#role_definitions
+#[derive(Error, Debug, PartialEq, Eq, Clone)]
+#[error("`{0}` is not a known WAI-ARIA role.")]
+pub struct UnknownAriaRoleError(pub String);
+
+/// A known non-abstract [WAI-ARIA role].
+///
+/// [WAI-ARIA role]: https://www.w3.org/TR/wai-aria-1.2/#role_definitions
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
+pub struct AriaRole(&'static str);
+
+impl AriaRole {
+    /// Returns the role for the `name`. The name is ASCII case-insensitive.
+    ///
+    /// Returns an error if the `name` is not a known non-abstract WAI-ARIA role.
+    pub fn new(name: &str) -> Result<Self, UnknownAriaRoleError> {
+        let lowercased = name.to_ascii_lowercase();
+
+        ROLES
+            .binary_search(&lowercased.as_str())
+            .map(|idx| AriaRole(ROLES[idx]))
+            .map_err(|_| UnknownAriaRoleError(name.to_owned()))
+    }
+
+    /// Returns the name of the role.
+    #[inline]
+    pub fn as_str(&self) -> &'static str {
+        self.0
+    }
+}
+
+impl FromStr for AriaRole {
+    type Err = UnknownAriaRoleError;
+
+    #[inline]
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        AriaRole::new(name)
+    }
+}
+
+impl Display for AriaRole {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+/// A value of the ARIA attributes with the [tristate] type, e.g. `aria-checked`.
+///
+/// [tristate]: https://www.w3.org/TR/wai-aria-1.2/#valuetype_tristate
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum AriaTristate {
+    /// The `true` value.
+    True,
+    /// The `false` value.
+    False,
+    /// The `mixed` value.
+    Mixed,
+}
+
+/// A value of the [`aria-live`] attribute.
+///
+/// [`aria-live`]: https://www.w3.org/TR/wai-aria-1.2/#aria-live
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum AriaLive {
+    /// The `off` value.
+    Off,
+    /// The `polite` value.
+    Polite,
+    /// The `assertive` value.
+    Assertive,
+}
+
+#[inline]
+fn bool_to_str(value: bool) -> &'static str {
+    if value {
+        "true"
+    } else {
+        "false"
+    }
+}
+
+/// Typed accessors for the [WAI-ARIA] role and attributes of an [`Element`].
+///
+/// Returned by [`Element::aria`]. Getters return `None` if the attribute is missing or its
+/// value is not valid for the attribute's type, in which case user agents treat the attribute
+/// as undefined as well.
+///
+/// # Example
+/// ```
+/// use lol_html::html_content::AriaRole;
+/// use lol_html::{element, rewrite_str, RewriteStrSettings};
+///
+/// let html = rewrite_str(
+///     r#"<div role="foo button" onclick="toggle()">Menu</div>"#,
+///     RewriteStrSettings {
+///         element_content_handlers: vec![
+///             element!("[role]", |el| {
+///                 let mut aria = el.aria();
+///
+///                 let is_button = aria.role() == Some(AriaRole::new("button")?);
+///
+///                 if is_button && aria.expanded().is_none() {
+///                     aria.set_expanded(false);
+///                     aria.set_label("Open the menu");
+///                 }
+///
+///                 Ok(())
+///             })
+///         ],
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(
+///     html,
+///     concat!(
+///         r#"<div role="foo button" onclick="toggle()" aria-expanded="false" "#,
+///         r#"aria-label="Open the menu">Menu</div>"#
+///     )
+/// );
+/// ```
+///
+/// [WAI-ARIA]: https://www.w3.org/TR/wai-aria-1.2/
+/// [`Element`]: struct.Element.html
+/// [`Element::aria`]: struct.Element.html#method.aria
+pub struct Aria<'e, 'r, 't, H: HandlerTypes = LocalHandlerTypes> {
+    element: &'e mut Element<'r, 't, H>,
+}
+
+impl<'e, 'r, 't, H: HandlerTypes> Aria<'e, 'r, 't, H> {
+    #[inline]
+    pub(super) fn new(element: &'e mut Element<'r, 't, H>) -> Self {
+        Aria { element }
+    }
+
+    #[inline]
+    fn set(&mut self, name: &str, value: &str) {
+        // NOTE: ARIA attribute names are always valid, so it's safe to unwrap here.
+        self.element.set_attribute(name, value).unwrap();
+    }
+
+    #[inline]
+    fn get_bool(&self, name: &str) -> Option<bool> {
+        let value = self.element.get_attribute(name)?;
+
+        if value.eq_ignore_ascii_case("true") {
+            Some(true)
+        } else if value.eq_ignore_ascii_case("false") {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    fn get_tristate(&self, name: &str) -> Option<AriaTristate> {
+        match self.get_bool(name) {
+            Some(true) => Some(AriaTristate::True),
+            Some(false) => Some(AriaTristate::False),
+            None => match self.element.get_attribute(name) {
+                Some(value) if value.eq_ignore_ascii_case("mixed") => Some(AriaTristate::Mixed),
+                _ => None,
+            },
+        }
+    }
+
+    #[inline]
+    fn set_tristate(&mut self, name: &str, value: AriaTristate) {
+        let value = match value {
+            AriaTristate::True => "true",
+            AriaTristate::False => "false",
+            AriaTristate::Mixed => "mixed",
+        };
+
+        self.set(name, value);
+    }
+
+    #[inline]
+    fn get_id_refs(&self, name: &str) -> Vec<String> {
+        self.element
+            .get_attribute(name)
+            .map(|value| value.split_ascii_whitespace().map(String::from).collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the role of the element, i.e. the first known role in the `role` attribute.
+    ///
+    /// The `role` attribute may contain a list of roles, where the following roles are
+    /// fallbacks for user agents that don't support the preceding ones. Unknown and abstract
+    /// roles are skipped.
+    pub fn role(&self) -> Option<AriaRole> {
+        self.element
+            .get_attribute("role")?
+            .split_ascii_whitespace()
+            .find_map(|name| AriaRole::new(name).ok())
+    }
+
+    /// Sets the `role` attribute of the element.
+    #[inline]
+    pub fn set_role(&mut self, role: AriaRole) {
+        self.set("role", role.as_str());
+    }
+
+    /// Returns the value of the `aria-label` attribute.
+    #[inline]
+    pub fn label(&self) -> Option<String> {
+        self.element.get_attribute("aria-label")
+    }
+
+    /// Sets the value of the `aria-label` attribute.
+    #[inline]
+    pub fn set_label(&mut self, label: &str) {
+        self.set("aria-label", label);
+    }
+
+    /// Returns the IDs of the elements listed in the `aria-labelledby` attribute.
+    #[inline]
+    pub fn labelled_by(&self) -> Vec<String> {
+        self.get_id_refs("aria-labelledby")
+    }
+
+    /// Sets the IDs of the elements listed in the `aria-labelledby` attribute.
+    #[inline]
+    pub fn set_labelled_by(&mut self, ids: &[&str]) {
+        self.set("aria-labelledby", &ids.join(" "));
+    }
+
+    /// Returns the IDs of the elements listed in the `aria-describedby` attribute.
+    #[inline]
+    pub fn described_by(&self) -> Vec<String> {
+        self.get_id_refs("aria-describedby")
+    }
+
+    /// Sets the IDs of the elements listed in the `aria-describedby` attribute.
+    #[inline]
+    pub fn set_described_by(&mut self, ids: &[&str]) {
+        self.set("aria-describedby", &ids.join(" "));
+    }
+
+    /// Returns the value of the `aria-hidden` attribute.
+    #[inline]
+    pub fn hidden(&self) -> Option<bool> {
+        self.get_bool("aria-hidden")
+    }
+
+    /// Sets the value of the `aria-hidden` attribute.
+    #[inline]
+    pub fn set_hidden(&mut self, hidden: bool) {
+        self.set("aria-hidden", bool_to_str(hidden));
+    }
+
+    /// Returns the value of the `aria-expanded` attribute.
+    #[inline]
+    pub fn expanded(&self) -> Option<bool> {
+        self.get_bool("aria-expanded")
+    }
+
+    /// Sets the value of the `aria-expanded` attribute.
+    #[inline]
+    pub fn set_expanded(&mut self, expanded: bool) {
+        self.set("aria-expanded", bool_to_str(expanded));
+    }
+
+    /// Returns the value of the `aria-disabled` attribute.
+    #[inline]
+    pub fn disabled(&self) -> Option<bool> {
+        self.get_bool("aria-disabled")
+    }
+
+    /// Sets the value of the `aria-disabled` attribute.
+    #[inline]
+    pub fn set_disabled(&mut self, disabled: bool) {
+        self.set("aria-disabled", bool_to_str(disabled));
+    }
+
+    /// Returns the value of the `aria-checked` attribute.
+    #[inline]
+    pub fn checked(&self) -> Option<AriaTristate> {
+        self.get_tristate("aria-checked")
+    }
+
+    /// Sets the value of the `aria-checked` attribute.
+    #[inline]
+    pub fn set_checked(&mut self, checked: AriaTristate) {
+        self.set_tristate("aria-checked", checked);
+    }
+
+    /// Returns the value of the `aria-pressed` attribute.
+    #[inline]
+    pub fn pressed(&self) -> Option<AriaTristate> {
+        self.get_tristate("aria-pressed")
+    }
+
+    /// Sets the value of the `aria-pressed` attribute.
+    #[inline]
+    pub fn set_pressed(&mut self, pressed: AriaTristate) {
+        self.set_tristate("aria-pressed", pressed);
+    }
+
+    /// Returns the value of the `aria-live` attribute.
+    pub fn live(&self) -> Option<AriaLive> {
+        let value = self
+            .element
+            .get_attribute("aria-live")?
+            .to_ascii_lowercase();
+
+        match value.as_str() {
+            "off" => Some(AriaLive::Off),
+            "polite" => Some(AriaLive::Polite),
+            "assertive" => Some(AriaLive::Assertive),
+            _ => None,
+        }
+    }
+
+    /// Sets the value of the `aria-live` attribute.
+    pub fn set_live(&mut self, live: AriaLive) {
+        let value = match live {
+            AriaLive::Off => "off",
+            AriaLive::Polite => "polite",
+            AriaLive::Assertive => "assertive",
+        };
+
+        self.set("aria-live", value);
+    }
+
+    /// Returns the value of the `aria-level` attribute.
+    #[inline]
+    pub fn level(&self) -> Option<u32> {
+        self.element
+            .get_attribute("aria-level")?
+            .trim()
+            .parse::<u32>()
+            .ok()
+            .filter(|&level| level > 0)
+    }
+
+    /// Sets the value of the `aria-level` attribute.
+    #[inline]
+    pub fn set_level(&mut self, level: u32) {
+        self.set("aria-level", &level.to_string());
+    }
+
+    /// Returns the value of the `aria-{name}` attribute, e.g. `get("controls")` returns
+    /// the value of the `aria-controls` attribute.
+    #[inline]
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.element.get_attribute(&format!("aria-{}", name))
+    }
+
+    /// Sets the value of the `aria-{name}` attribute.
+    #[inline]
+    pub fn set_attribute(&mut self, name: &str, value: &str) {
+        self.set(&format!("aria-{}", name), value);
+    }
+
+    /// Removes the `aria-{name}` attribute.
+    #[inline]
+    pub fn remove(&mut self, name: &str) {
+        self.element.remove_attribute(&format!("aria-{}", name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rewrite_str, RewriteStrSettings};
+
+    #[test]
+    fn roles_table_is_sorted() {
+        assert!(ROLES.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn role_validation() {
+        assert_eq!(AriaRole::new("Button").unwrap().as_str(), "button");
+        assert_eq!("treeitem".parse::<AriaRole>().unwrap().as_str(), "treeitem");
+
+        // NOTE: abstract roles are not allowed in the markup.
+        assert_eq!(
+            AriaRole::new("widget"),
+            Err(UnknownAriaRoleError("widget".into()))
+        );
+
+        assert!(AriaRole::new("").is_err());
+    }
+
+    #[test]
+    fn typed_getters() {
+        rewrite_str(
+            concat!(
+                r#"<div role="foo widget Tab" aria-hidden="TRUE" aria-expanded="yes" "#,
+                r#"aria-checked="mixed" aria-labelledby=" a  b " aria-level="0" "#,
+                r#"aria-live="Polite" aria-controls="c">"#
+            ),
+            RewriteStrSettings {
+                element_content_handlers: vec![element!("div", |el| {
+                    let aria = el.aria();
+
+                    assert_eq!(aria.role(), Some(AriaRole::new("tab")?));
+                    assert_eq!(aria.hidden(), Some(true));
+                    assert_eq!(aria.expanded(), None);
+                    assert_eq!(aria.disabled(), None);
+                    assert_eq!(aria.checked(), Some(AriaTristate::Mixed));
+                    assert_eq!(aria.pressed(), None);
+                    assert_eq!(aria.labelled_by(), vec!["a", "b"]);
+                    assert!(aria.described_by().is_empty());
+                    assert_eq!(aria.level(), None);
+                    assert_eq!(aria.live(), Some(AriaLive::Polite));
+                    assert_eq!(aria.get("controls"), Some("c".into()));
+
+                    Ok(())
+                })],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn typed_setters() {
+        let html = rewrite_str(
+            r#"<span aria-foo="bar"></span>"#,
+            RewriteStrSettings {
+                element_content_handlers: vec![element!("span", |el| {
+                    let mut aria = el.aria();
+
+                    aria.set_role(AriaRole::new("switch")?);
+                    aria.set_checked(AriaTristate::False);
+                    aria.set_described_by(&["x", "y"]);
+                    aria.set_level(2);
+                    aria.set_live(AriaLive::Off);
+                    aria.remove("foo");
+
+                    Ok(())
+                })],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            html,
+            concat!(
+                r#"<span role="switch" aria-checked="false" aria-describedby="x y" "#,
+                r#"aria-level="2" aria-live="off"></span>"#
+            )
+        );
+    }
+}
@@ -0,0 +1,288 @@
+use super::Element;
+use crate::html::is_attr_whitespace;
+use crate::rewriter::{HandlerTypes, LocalHandlerTypes};
+use thiserror::Error;
+
+/// An error that occurs when invalid value is provided for the class name.
+#[derive(Error, Debug, PartialEq, Copy, Clone)]
+pub enum ClassNameError {
+    /// The provided value is empty.
+    #[error("Class name can't be empty.")]
+    Empty,
+
+    /// The provided value contains whitespace, so it would be split into multiple class names.
+    #[error("Class name can't contain whitespace.")]
+    Whitespace,
+}
+
+#[inline]
+fn split_classes(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(|c: char| c.is_ascii() && is_attr_whitespace(c as u8))
+        .filter(|class| !class.is_empty())
+}
+
+/// Returns the class names without duplicates in the order of their appearance in the `value`.
+#[inline]
+fn unique_classes(value: &str) -> Vec<&str> {
+    let mut classes = Vec::default();
+
+    for class in split_classes(value) {
+        if !classes.contains(&class) {
+            classes.push(class);
+        }
+    }
+
+    classes
+}
+
+#[inline]
+fn validate_class(class: &str) -> Result<(), ClassNameError> {
+    if class.is_empty() {
+        Err(ClassNameError::Empty)
+    } else if class.bytes().any(is_attr_whitespace) {
+        Err(ClassNameError::Whitespace)
+    } else {
+        Ok(())
+    }
+}
+
+/// An accessor for the class names in the `class` attribute of an [`Element`], similar to
+/// the [`classList`] of the DOM elements.
+///
+/// Returned by [`Element::class_list`]. Class names are case-sensitive. The methods that modify
+/// the class names update the `class` attribute only if the class names are changed, in which
+/// case the attribute value is normalized: the duplicate class names are removed and the rest
+/// are separated by a single space.
+///
+/// # Example
+/// ```
+/// use lol_html::{element, rewrite_str, RewriteStrSettings};
+///
+/// let html = rewrite_str(
+///     r#"<div class="menu  hidden"></div><div></div>"#,
+///     RewriteStrSettings {
+///         element_content_handlers: vec![
+///             element!("div", |el| {
+///                 let mut class_list = el.class_list();
+///
+///                 if !class_list.contains("menu") {
+///                     class_list.add("empty")?;
+///                 }
+///
+///                 class_list.remove("hidden")?;
+///
+///                 Ok(())
+///             })
+///         ],
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(html, r#"<div class="menu"></div><div class="empty"></div>"#);
+/// ```
+///
+/// [`Element`]: struct.Element.html
+/// [`Element::class_list`]: struct.Element.html#method.class_list
+/// [`classList`]: https://dom.spec.whatwg.org/#dom-element-classlist
+pub struct ClassList<'e, 'r, 't, H: HandlerTypes = LocalHandlerTypes> {
+    element: &'e mut Element<'r, 't, H>,
+}
+
+impl<'e, 'r, 't, H: HandlerTypes> ClassList<'e, 'r, 't, H> {
+    #[inline]
+    pub(super) fn new(element: &'e mut Element<'r, 't, H>) -> Self {
+        ClassList { element }
+    }
+
+    #[inline]
+    fn set(&mut self, classes: Vec<&str>) {
+        // NOTE: `class` is a valid attribute name, so it's safe to unwrap here.
+        self.element
+            .set_attribute("class", &classes.join(" "))
+            .unwrap();
+    }
+
+    /// Returns the class names of the element in the order of their appearance in the `class`
+    /// attribute.
+    pub fn to_vec(&self) -> Vec<String> {
+        match self.element.get_attribute("class") {
+            Some(value) => unique_classes(&value)
+                .into_iter()
+                .map(str::to_owned)
+                .collect(),
+            None => Vec::default(),
+        }
+    }
+
+    /// Returns `true` if the element has the `class`.
+    #[inline]
+    pub fn contains(&self, class: &str) -> bool {
+        match self.element.get_attribute("class") {
+            Some(value) => split_classes(&value).any(|c| c == class),
+            None => false,
+        }
+    }
+
+    /// Adds the `class` to the element if the element doesn't have it.
+    ///
+    /// Returns an error if the `class` is empty or contains whitespace.
+    pub fn add(&mut self, class: &str) -> Result<(), ClassNameError> {
+        validate_class(class)?;
+
+        let value = self.element.get_attribute("class").unwrap_or_default();
+
+        if !split_classes(&value).any(|c| c == class) {
+            let mut classes = unique_classes(&value);
+
+            classes.push(class);
+
+            self.set(classes);
+        }
+
+        Ok(())
+    }
+
+    /// Removes the `class` from the element if the element has it.
+    ///
+    /// Returns an error if the `class` is empty or contains whitespace.
+    pub fn remove(&mut self, class: &str) -> Result<(), ClassNameError> {
+        validate_class(class)?;
+
+        if let Some(value) = self.element.get_attribute("class") {
+            if split_classes(&value).any(|c| c == class) {
+                let mut classes = unique_classes(&value);
+
+                classes.retain(|&c| c != class);
+
+                self.set(classes);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes the `class` from the element if the element has it and adds it otherwise.
+    ///
+    /// Returns `true` if the element has the `class` afterwards or an error if the `class` is
+    /// empty or contains whitespace.
+    pub fn toggle(&mut self, class: &str) -> Result<bool, ClassNameError> {
+        if self.contains(class) {
+            self.remove(class).map(|_| false)
+        } else {
+            self.add(class).map(|_| true)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::*;
+    use crate::html_content::*;
+    use crate::rewritable_units::test_utils::*;
+    use encoding_rs::UTF_8;
+
+    fn rewrite_class_list(html: &str, mut handler: impl FnMut(&mut ClassList)) -> String {
+        let mut handler_called = false;
+
+        let output = rewrite_html(
+            html.as_bytes(),
+            UTF_8,
+            vec![element!("div", |el| {
+                handler_called = true;
+                handler(&mut el.class_list());
+                Ok(())
+            })],
+            vec![],
+        );
+
+        assert!(handler_called, "Handler not called.");
+
+        output
+    }
+
+    #[test]
+    fn contains() {
+        rewrite_class_list("<div class='foo\tbar\n Baz'>", |class_list| {
+            assert!(class_list.contains("foo"));
+            assert!(class_list.contains("bar"));
+            assert!(class_list.contains("Baz"));
+            assert!(!class_list.contains("baz"));
+            assert!(!class_list.contains("foo bar"));
+            assert!(!class_list.contains(""));
+            assert_eq!(class_list.to_vec(), ["foo", "bar", "Baz"]);
+        });
+
+        rewrite_class_list("<div>", |class_list| {
+            assert!(!class_list.contains("foo"));
+            assert!(class_list.to_vec().is_empty());
+        });
+    }
+
+    #[test]
+    fn add() {
+        let output = rewrite_class_list("<div class='foo  bar foo'>", |class_list| {
+            class_list.add("baz").unwrap();
+        });
+
+        assert_eq!(output, r#"<div class="foo bar baz">"#);
+
+        let output = rewrite_class_list("<div>", |class_list| {
+            class_list.add("foo").unwrap();
+        });
+
+        assert_eq!(output, r#"<div class="foo">"#);
+    }
+
+    #[test]
+    fn add_existing() {
+        let html = "<div class='foo  bar'>";
+
+        let output = rewrite_class_list(html, |class_list| {
+            class_list.add("bar").unwrap();
+        });
+
+        assert_eq!(output, html);
+    }
+
+    #[test]
+    fn remove() {
+        let output = rewrite_class_list("<div class='foo bar\tfoo baz'>", |class_list| {
+            class_list.remove("foo").unwrap();
+            class_list.remove("qux").unwrap();
+        });
+
+        assert_eq!(output, r#"<div class="bar baz">"#);
+
+        let output = rewrite_class_list("<div class=foo>", |class_list| {
+            class_list.remove("foo").unwrap();
+        });
+
+        assert_eq!(output, r#"<div class="">"#);
+    }
+
+    #[test]
+    fn toggle() {
+        let output = rewrite_class_list("<div class='foo bar'>", |class_list| {
+            assert!(!class_list.toggle("foo").unwrap());
+            assert!(class_list.toggle("baz").unwrap());
+        });
+
+        assert_eq!(output, r#"<div class="bar baz">"#);
+    }
+
+    #[test]
+    fn invalid_class_names() {
+        rewrite_class_list("<div class=foo>", |class_list| {
+            assert_eq!(class_list.add("").unwrap_err(), ClassNameError::Empty);
+            assert_eq!(
+                class_list.remove("foo bar").unwrap_err(),
+                ClassNameError::Whitespace
+            );
+            assert_eq!(
+                class_list.toggle("foo\n").unwrap_err(),
+                ClassNameError::Whitespace
+            );
+        });
+    }
+}
@@ -0,0 +1,589 @@
+use super::ContentType;
+use crate::rewriter::{SelectContentPolicy, TableContentPolicy};
+use std::borrow::Cow;
+use thiserror::Error;
+
+/// An error that occurs when the content inserted into an element violates the element's content
+/// model and the corresponding policy rejects such content.
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum ContentModelError {
+    /// The content inserted into the table element would be moved out of the table by the
+    /// HTML parser.
+    ///
+    /// Refer to [`TableContentPolicy`] for more information.
+    ///
+    /// [`TableContentPolicy`]: ../enum.TableContentPolicy.html
+    #[error("Content inserted into the `<{0}>` element would be moved out of the table.")]
+    ForeignTableContent(String),
+
+    /// The content inserted into the `<select>` or `<optgroup>` element contains markup that is
+    /// not allowed in these elements.
+    ///
+    /// Refer to [`SelectContentPolicy`] for more information.
+    ///
+    /// [`SelectContentPolicy`]: ../enum.SelectContentPolicy.html
+    #[error("Content inserted into the `<{0}>` element contains disallowed markup.")]
+    DisallowedSelectContent(String),
+}
+
+/// Policies that are applied to the content inserted into the elements.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct ContentPolicies {
+    pub table: TableContentPolicy,
+    pub select: SelectContentPolicy,
+}
+
+impl ContentPolicies {
+    #[inline]
+    pub fn ignore_all(&self) -> bool {
+        self.table == TableContentPolicy::Ignore && self.select == SelectContentPolicy::Ignore
+    }
+
+    /// Applies the policies to the content inserted into the inner content of the HTML element
+    /// with the given tag name.
+    pub fn apply<'c>(
+        &self,
+        tag_name: &str,
+        content: &'c str,
+        content_type: ContentType,
+    ) -> Result<(Cow<'c, str>, ContentType), ContentModelError> {
+        if let Some(ctx) = TableContext::for_tag_name(tag_name) {
+            apply_table_content_policy(ctx, self.table, tag_name, content, content_type)
+        } else if let Some(ctx) = SelectContext::for_tag_name(tag_name) {
+            apply_select_content_policy(ctx, self.select, tag_name, content, content_type)
+        } else {
+            Ok((content.into(), content_type))
+        }
+    }
+}
+
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "keygen", "link", "meta", "param",
+    "source", "track", "wbr",
+];
+
+const RAW_TEXT_ELEMENTS: &[&str] = &[
+    "iframe", "noembed", "noframes", "noscript", "script", "style", "textarea", "title", "xmp",
+];
+
+/// An item of the HTML markup produced by [`scan_markup`].
+enum MarkupItem<'c> {
+    Text,
+    /// The content of the raw text element, e.g. `<script>`.
+    RawText,
+    /// A start tag with the given name. The second field indicates whether the tag is
+    /// self-closing.
+    StartTag(&'c str, bool),
+    EndTag(&'c str),
+    /// A comment, doctype or processing instruction.
+    Comment,
+}
+
+#[inline]
+fn is_void_element(tag_name: &str) -> bool {
+    VOID_ELEMENTS
+        .iter()
+        .any(|n| n.eq_ignore_ascii_case(tag_name))
+}
+
+#[inline]
+fn is_raw_text_element(tag_name: &str) -> bool {
+    RAW_TEXT_ELEMENTS
+        .iter()
+        .any(|n| n.eq_ignore_ascii_case(tag_name))
+}
+
+#[inline]
+fn starts_with_tag_name(markup: &str) -> bool {
+    markup.starts_with(|c: char| c.is_ascii_alphabetic())
+}
+
+#[inline]
+fn tag_name(markup: &str) -> &str {
+    let end = markup
+        .find(|c: char| c.is_ascii_whitespace() || c == '/' || c == '>')
+        .unwrap_or(markup.len());
+
+    &markup[..end]
+}
+
+#[inline]
+fn skip_past(input: &str, pattern: &str) -> usize {
+    input
+        .find(pattern)
+        .map_or(input.len(), |idx| idx + pattern.len())
+}
+
+// NOTE: `>` characters in the quoted attribute values don't terminate the tag.
+fn find_tag_end(markup: &str) -> usize {
+    let mut quote = None;
+
+    for (idx, ch) in markup.char_indices() {
+        match (quote, ch) {
+            (None, '"') | (None, '\'') => quote = Some(ch),
+            (None, '>') => return idx + 1,
+            (Some(q), _) if q == ch => quote = None,
+            _ => (),
+        }
+    }
+
+    markup.len()
+}
+
+fn find_raw_text_end(input: &str, tag_name: &str) -> usize {
+    for (idx, _) in input.match_indices("</") {
+        let name = input.as_bytes()[idx + 2..].get(..tag_name.len());
+
+        if name.is_some_and(|name| name.eq_ignore_ascii_case(tag_name.as_bytes())) {
+            return idx;
+        }
+    }
+
+    input.len()
+}
+
+/// Splits the HTML `content` into markup items and invokes the `handler` with each of them along
+/// with the corresponding part of the content until it returns `false`. Returns `false` if any of
+/// the invocations returned `false`.
+///
+/// The content is scanned with a simplified tokenizer that is only meant to handle the reasonably
+/// well-formed markup, since the content is usually produced by the content handlers rather than
+/// obtained from the untrusted sources.
+fn scan_markup(content: &str, mut handler: impl FnMut(MarkupItem, &str) -> bool) -> bool {
+    let mut rest = content;
+    let mut raw_text_element = None;
+
+    while !rest.is_empty() {
+        let (item, len) = if let Some(name) = raw_text_element.take() {
+            (MarkupItem::RawText, find_raw_text_end(rest, name))
+        } else if !rest.starts_with('<') {
+            (MarkupItem::Text, rest.find('<').unwrap_or(rest.len()))
+        } else if let Some(comment) = rest.strip_prefix("<!--") {
+            (MarkupItem::Comment, 4 + skip_past(comment, "-->"))
+        } else if rest.starts_with("</") && starts_with_tag_name(&rest[2..]) {
+            (MarkupItem::EndTag(tag_name(&rest[2..])), find_tag_end(rest))
+        } else if rest.starts_with("</") || rest.starts_with("<!") || rest.starts_with("<?") {
+            (MarkupItem::Comment, skip_past(rest, ">"))
+        } else if starts_with_tag_name(&rest[1..]) {
+            let name = tag_name(&rest[1..]);
+            let len = find_tag_end(rest);
+
+            // NOTE: the self-closing flag is ignored for the raw text elements.
+            let self_closing = if is_raw_text_element(name) {
+                raw_text_element = Some(name);
+                false
+            } else {
+                rest[..len].ends_with("/>")
+            };
+
+            (MarkupItem::StartTag(name, self_closing), len)
+        } else {
+            // NOTE: `<` that doesn't start any markup is a text.
+            (MarkupItem::Text, 1)
+        };
+
+        if len > 0 && !handler(item, &rest[..len]) {
+            return false;
+        }
+
+        rest = &rest[len..];
+    }
+
+    true
+}
+
+/// Invokes the `predicate` for the text and the start tags at the top level of the HTML `content`
+/// (i.e. not nested in any elements of the content) until it returns `false`. Returns `false` if
+/// any of the invocations returned `false`.
+fn all_top_level_items(content: &str, mut predicate: impl FnMut(MarkupItem, &str) -> bool) -> bool {
+    let mut depth = 0usize;
+
+    scan_markup(content, |item, markup| match item {
+        MarkupItem::Text => depth > 0 || predicate(item, markup),
+        MarkupItem::StartTag(name, self_closing) => {
+            let res = depth > 0 || predicate(item, markup);
+
+            if !self_closing && !is_void_element(name) {
+                depth += 1;
+            }
+
+            res
+        }
+        MarkupItem::EndTag(_) => {
+            depth = depth.saturating_sub(1);
+            true
+        }
+        MarkupItem::RawText | MarkupItem::Comment => true,
+    })
+}
+
+#[inline]
+fn is_whitespace(text: &str) -> bool {
+    text.bytes().all(|b| b.is_ascii_whitespace())
+}
+
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for ch in text.chars() {
+        match ch {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            _ => escaped.push(ch),
+        }
+    }
+
+    escaped
+}
+
+/// The insertion modes of the HTML parser in which the content is inserted into the table
+/// elements outside of the table cells.
+#[derive(Copy, Clone)]
+enum TableContext {
+    Table,
+    Section,
+    Row,
+}
+
+impl TableContext {
+    fn for_tag_name(tag_name: &str) -> Option<Self> {
+        match tag_name {
+            "table" => Some(TableContext::Table),
+            "thead" | "tbody" | "tfoot" => Some(TableContext::Section),
+            "tr" => Some(TableContext::Row),
+            _ => None,
+        }
+    }
+
+    // NOTE: `<td>` and `<tr>` start tags in the outer contexts are not foster parented,
+    // the parser inserts the missing table elements for them implicitly.
+    fn allows(self, tag_name: &str) -> bool {
+        let tag_name = tag_name.to_ascii_lowercase();
+
+        match tag_name.as_str() {
+            "script" | "style" | "template" | "td" | "th" => true,
+            "tr" => !matches!(self, TableContext::Row),
+            "caption" | "colgroup" | "col" | "thead" | "tbody" | "tfoot" => {
+                matches!(self, TableContext::Table)
+            }
+            _ => false,
+        }
+    }
+
+    fn wrappers(self) -> (&'static str, &'static str) {
+        match self {
+            TableContext::Table => ("<tbody><tr><td>", "</td></tr></tbody>"),
+            TableContext::Section => ("<tr><td>", "</td></tr>"),
+            TableContext::Row => ("<td>", "</td>"),
+        }
+    }
+
+    fn has_foreign_content(self, content: &str, content_type: &ContentType) -> bool {
+        match content_type {
+            ContentType::Html => !all_top_level_items(content, |item, markup| match item {
+                MarkupItem::StartTag(name, _) => self.allows(name),
+                _ => is_whitespace(markup),
+            }),
+            ContentType::Text => !is_whitespace(content),
+        }
+    }
+}
+
+fn apply_table_content_policy<'c>(
+    ctx: TableContext,
+    policy: TableContentPolicy,
+    tag_name: &str,
+    content: &'c str,
+    content_type: ContentType,
+) -> Result<(Cow<'c, str>, ContentType), ContentModelError> {
+    if policy == TableContentPolicy::Ignore || !ctx.has_foreign_content(content, &content_type) {
+        return Ok((content.into(), content_type));
+    }
+
+    match policy {
+        TableContentPolicy::Wrap => {
+            let (start, end) = ctx.wrappers();
+
+            let content = match content_type {
+                ContentType::Html => format!("{}{}{}", start, content, end),
+                ContentType::Text => format!("{}{}{}", start, escape_text(content), end),
+            };
+
+            Ok((content.into(), ContentType::Html))
+        }
+        _ => Err(ContentModelError::ForeignTableContent(tag_name.into())),
+    }
+}
+
+/// The insertion mode of the HTML parser in which the content is inserted into the `<select>`
+/// and `<optgroup>` elements.
+///
+/// In this mode the parser ignores the tags of all the elements except a few, keeping only the
+/// text of the ignored elements. Some of the ignored tags (e.g. `<input>` or `<textarea>`) also
+/// implicitly close the `<select>` element.
+#[derive(Copy, Clone)]
+enum SelectContext {
+    Select,
+    Optgroup,
+}
+
+impl SelectContext {
+    fn for_tag_name(tag_name: &str) -> Option<Self> {
+        match tag_name {
+            "select" => Some(SelectContext::Select),
+            "optgroup" => Some(SelectContext::Optgroup),
+            _ => None,
+        }
+    }
+
+    // NOTE: nested `<optgroup>` closes the current one, so it's allowed only in `<select>`.
+    fn allows(self, tag_name: &str) -> bool {
+        let tag_name = tag_name.to_ascii_lowercase();
+
+        match tag_name.as_str() {
+            "option" | "hr" | "script" | "template" => true,
+            "optgroup" => matches!(self, SelectContext::Select),
+            _ => false,
+        }
+    }
+
+    fn has_disallowed_markup(self, content: &str) -> bool {
+        !scan_markup(content, |item, _| match item {
+            MarkupItem::StartTag(name, _) | MarkupItem::EndTag(name) => self.allows(name),
+            _ => true,
+        })
+    }
+
+    /// Removes the tags of the disallowed elements from the `content` keeping their text.
+    fn strip_disallowed_markup(self, content: &str) -> String {
+        let mut stripped = String::with_capacity(content.len());
+        let mut raw_text_allowed = true;
+
+        scan_markup(content, |item, markup| {
+            match item {
+                MarkupItem::StartTag(name, _) => {
+                    raw_text_allowed = self.allows(name);
+
+                    if raw_text_allowed {
+                        stripped.push_str(markup);
+                    }
+                }
+                MarkupItem::EndTag(name) => {
+                    if self.allows(name) {
+                        stripped.push_str(markup);
+                    }
+                }
+                // NOTE: once the tag of the raw text element is removed its content is parsed
+                // as regular markup, so we need to escape it to keep it a text.
+                MarkupItem::RawText if !raw_text_allowed => {
+                    stripped.push_str(&escape_text(markup));
+                }
+                _ => stripped.push_str(markup),
+            }
+
+            true
+        });
+
+        stripped
+    }
+}
+
+fn apply_select_content_policy<'c>(
+    ctx: SelectContext,
+    policy: SelectContentPolicy,
+    tag_name: &str,
+    content: &'c str,
+    content_type: ContentType,
+) -> Result<(Cow<'c, str>, ContentType), ContentModelError> {
+    if policy == SelectContentPolicy::Ignore
+        || matches!(content_type, ContentType::Text)
+        || !ctx.has_disallowed_markup(content)
+    {
+        return Ok((content.into(), content_type));
+    }
+
+    match policy {
+        SelectContentPolicy::Strip => {
+            Ok((ctx.strip_disallowed_markup(content).into(), content_type))
+        }
+        _ => Err(ContentModelError::DisallowedSelectContent(tag_name.into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(
+        policy: TableContentPolicy,
+        tag_name: &str,
+        content: &str,
+    ) -> Result<String, ContentModelError> {
+        let policies = ContentPolicies {
+            table: policy,
+            ..ContentPolicies::default()
+        };
+
+        policies
+            .apply(tag_name, content, ContentType::Html)
+            .map(|(content, _)| content.into_owned())
+    }
+
+    fn apply_select(
+        policy: SelectContentPolicy,
+        tag_name: &str,
+        content: &str,
+    ) -> Result<String, ContentModelError> {
+        let policies = ContentPolicies {
+            select: policy,
+            ..ContentPolicies::default()
+        };
+
+        policies
+            .apply(tag_name, content, ContentType::Html)
+            .map(|(content, _)| content.into_owned())
+    }
+
+    #[test]
+    fn table_content() {
+        let allowed = [
+            ("table", "<tr><td>1</td></tr>"),
+            (
+                "table",
+                " <!-- comment --> <caption>Foo</caption><col><tbody></tbody>",
+            ),
+            ("table", "<script>document.write('<p>')</script>"),
+            ("tbody", "\n<tr><td><div>Foo</div></td></tr>\n"),
+            ("tr", r#"<td title="<p>">Foo</td><th>Bar</th>"#),
+            ("div", "<p>Foo</p>"),
+        ];
+
+        for (tag_name, content) in allowed.iter() {
+            assert_eq!(
+                apply(TableContentPolicy::Reject, tag_name, content),
+                Ok(content.to_string())
+            );
+        }
+
+        let foreign = [
+            ("table", "Foo"),
+            ("table", "<tr><td>1</td></tr><div>Foo</div>"),
+            ("thead", "<caption>Foo</caption>"),
+            ("tr", "<tr><td>Foo</td></tr>"),
+            ("tr", "<td>Foo</td> < "),
+        ];
+
+        for (tag_name, content) in foreign.iter() {
+            assert_eq!(
+                apply(TableContentPolicy::Reject, tag_name, content),
+                Err(ContentModelError::ForeignTableContent(tag_name.to_string()))
+            );
+
+            assert_eq!(
+                apply(TableContentPolicy::Ignore, tag_name, content),
+                Ok(content.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn wrap_table_content() {
+        assert_eq!(
+            apply(TableContentPolicy::Wrap, "table", "<p>Foo</p>"),
+            Ok("<tbody><tr><td><p>Foo</p></td></tr></tbody>".into())
+        );
+
+        assert_eq!(
+            apply(TableContentPolicy::Wrap, "tfoot", "Foo"),
+            Ok("<tr><td>Foo</td></tr>".into())
+        );
+
+        assert_eq!(
+            apply(TableContentPolicy::Wrap, "tr", "<td>Foo</td>"),
+            Ok("<td>Foo</td>".into())
+        );
+
+        let policies = ContentPolicies {
+            table: TableContentPolicy::Wrap,
+            ..ContentPolicies::default()
+        };
+
+        let (content, _) = policies
+            .apply("tr", "<b>Foo</b>", ContentType::Text)
+            .unwrap();
+
+        assert_eq!(content, "<td>&lt;b&gt;Foo&lt;/b&gt;</td>");
+    }
+
+    #[test]
+    fn select_content() {
+        let allowed = [
+            ("select", "<option>1</option><option selected>2</option>"),
+            ("select", "<optgroup label=Foo><option>1</optgroup><hr>Bar"),
+            ("optgroup", "<!-- comment --><option>1</option>"),
+            ("select", "<script>document.write('<p>')</script>"),
+            ("option", "<b>Foo</b>"),
+        ];
+
+        for (tag_name, content) in allowed.iter() {
+            assert_eq!(
+                apply_select(SelectContentPolicy::Reject, tag_name, content),
+                Ok(content.to_string())
+            );
+        }
+
+        let disallowed = [
+            ("select", "<option><b>Foo</b></option>"),
+            ("select", "<input>"),
+            ("optgroup", "<optgroup><option>1</option></optgroup>"),
+            ("optgroup", "<option>1</option></div>"),
+        ];
+
+        for (tag_name, content) in disallowed.iter() {
+            assert_eq!(
+                apply_select(SelectContentPolicy::Reject, tag_name, content),
+                Err(ContentModelError::DisallowedSelectContent(
+                    tag_name.to_string()
+                ))
+            );
+
+            assert_eq!(
+                apply_select(SelectContentPolicy::Ignore, tag_name, content),
+                Ok(content.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn strip_select_content() {
+        assert_eq!(
+            apply_select(
+                SelectContentPolicy::Strip,
+                "select",
+                r#"<option value="1"><b>Foo</b> <img src="a.png"></option><div>Bar</div>"#
+            ),
+            Ok(r#"<option value="1">Foo </option>Bar"#.into())
+        );
+
+        assert_eq!(
+            apply_select(
+                SelectContentPolicy::Strip,
+                "optgroup",
+                "<textarea><option>Foo</option></textarea><option>Bar</option>"
+            ),
+            Ok("&lt;option&gt;Foo&lt;/option&gt;<option>Bar</option>".into())
+        );
+
+        let policies = ContentPolicies {
+            select: SelectContentPolicy::Strip,
+            ..ContentPolicies::default()
+        };
+
+        let (content, _) = policies
+            .apply("select", "<b>Foo</b>", ContentType::Text)
+            .unwrap();
+
+        assert_eq!(content, "<b>Foo</b>");
+    }
+}
@@ -0,0 +1,294 @@
+use super::{AttributeNameError, Element};
+use crate::rewriter::{HandlerTypes, LocalHandlerTypes};
+use std::fmt::Display;
+use std::str::FromStr;
+use thiserror::Error;
+
+const DATA_PREFIX: &str = "data-";
+
+/// An error that occurs when invalid key is provided for the [`Dataset`].
+///
+/// [`Dataset`]: ../html_content/struct.Dataset.html
+#[derive(Error, Debug, PartialEq, Copy, Clone)]
+pub enum DatasetKeyError {
+    /// The provided key contains a hyphen followed by a lowercase ASCII letter, so it can't be
+    /// produced from any `data-*` attribute name.
+    #[error("Dataset key can't contain a hyphen followed by a lowercase ASCII letter.")]
+    HyphenBeforeLowercase,
+
+    /// The attribute name produced from the key is invalid.
+    #[error("{0}")]
+    AttributeName(#[from] AttributeNameError),
+}
+
+/// Converts the camelCase dataset `key` to the kebab-case `data-*` attribute name,
+/// e.g. `fooBar` to `data-foo-bar`.
+fn key_to_attribute_name(key: &str) -> Result<String, DatasetKeyError> {
+    let mut name = String::from(DATA_PREFIX);
+    let mut chars = key.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        if ch == '-' && chars.peek().is_some_and(|c| c.is_ascii_lowercase()) {
+            return Err(DatasetKeyError::HyphenBeforeLowercase);
+        }
+
+        if ch.is_ascii_uppercase() {
+            name.push('-');
+            name.push(ch.to_ascii_lowercase());
+        } else {
+            name.push(ch);
+        }
+    }
+
+    Ok(name)
+}
+
+/// Converts the kebab-case `data-*` attribute name to the camelCase dataset key,
+/// e.g. `data-foo-bar` to `fooBar`. Returns `None` for the other attributes.
+fn attribute_name_to_key(name: &str) -> Option<String> {
+    if !name.starts_with(DATA_PREFIX) {
+        return None;
+    }
+
+    let mut key = String::with_capacity(name.len() - DATA_PREFIX.len());
+    let mut chars = name[DATA_PREFIX.len()..].chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match chars.peek() {
+            Some(&next) if ch == '-' && next.is_ascii_lowercase() => {
+                key.push(next.to_ascii_uppercase());
+                chars.next();
+            }
+            _ => key.push(ch),
+        }
+    }
+
+    Some(key)
+}
+
+/// An accessor for the `data-*` attributes of an [`Element`], similar to the [`dataset`] of
+/// the DOM elements.
+///
+/// Returned by [`Element::dataset`]. The methods take camelCase keys which are mapped to
+/// the kebab-case attribute names, e.g. the `userId` key corresponds to the `data-user-id`
+/// attribute. Keys that contain a hyphen followed by a lowercase ASCII letter can't be mapped
+/// back from any attribute name, so they are rejected by the methods that modify the attributes.
+///
+/// # Example
+/// ```
+/// use lol_html::{element, rewrite_str, RewriteStrSettings};
+///
+/// let html = rewrite_str(
+///     r#"<div data-user-id="42" data-visits="1"></div>"#,
+///     RewriteStrSettings {
+///         element_content_handlers: vec![
+///             element!("div", |el| {
+///                 let mut dataset = el.dataset();
+///
+///                 if let Some(visits) = dataset.get_as::<u32>("visits") {
+///                     dataset.set("visits", visits + 1)?;
+///                 }
+///
+///                 dataset.set("isReturning", true)?;
+///                 dataset.remove("userId")?;
+///
+///                 Ok(())
+///             })
+///         ],
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(html, r#"<div data-visits="2" data-is-returning="true"></div>"#);
+/// ```
+///
+/// [`Element`]: struct.Element.html
+/// [`Element::dataset`]: struct.Element.html#method.dataset
+/// [`dataset`]: https://html.spec.whatwg.org/multipage/dom.html#dom-dataset
+pub struct Dataset<'e, 'r, 't, H: HandlerTypes = LocalHandlerTypes> {
+    element: &'e mut Element<'r, 't, H>,
+}
+
+impl<'e, 'r, 't, H: HandlerTypes> Dataset<'e, 'r, 't, H> {
+    #[inline]
+    pub(super) fn new(element: &'e mut Element<'r, 't, H>) -> Self {
+        Dataset { element }
+    }
+
+    /// Returns the keys and the values of the `data-*` attributes of the element in the order
+    /// of their appearance.
+    pub fn to_vec(&self) -> Vec<(String, String)> {
+        self.element
+            .attributes()
+            .iter()
+            .filter_map(|attr| attribute_name_to_key(&attr.name()).map(|key| (key, attr.value())))
+            .collect()
+    }
+
+    /// Returns the value of the `data-*` attribute that corresponds to the `key`.
+    ///
+    /// Returns `None` if the element doesn't have the attribute or the `key` is invalid.
+    #[inline]
+    pub fn get(&self, key: &str) -> Option<String> {
+        let name = key_to_attribute_name(key).ok()?;
+
+        self.element.get_attribute(&name)
+    }
+
+    /// Returns the value of the `data-*` attribute that corresponds to the `key` parsed
+    /// as `T`.
+    ///
+    /// Returns `None` if the element doesn't have the attribute, the `key` is invalid or
+    /// the value can't be parsed.
+    #[inline]
+    pub fn get_as<T: FromStr>(&self, key: &str) -> Option<T> {
+        self.get(key)?.parse().ok()
+    }
+
+    /// Returns `true` if the element has the `data-*` attribute that corresponds to the `key`.
+    #[inline]
+    pub fn contains(&self, key: &str) -> bool {
+        match key_to_attribute_name(key) {
+            Ok(name) => self.element.has_attribute(&name),
+            Err(_) => false,
+        }
+    }
+
+    /// Sets the `data-*` attribute that corresponds to the `key` to the string representation
+    /// of the `value`.
+    ///
+    /// Returns an error if the `key` is invalid.
+    #[inline]
+    pub fn set(&mut self, key: &str, value: impl Display) -> Result<(), DatasetKeyError> {
+        let name = key_to_attribute_name(key)?;
+
+        self.element.set_attribute(&name, &value.to_string())?;
+
+        Ok(())
+    }
+
+    /// Removes the `data-*` attribute that corresponds to the `key` if the element has it.
+    ///
+    /// Returns an error if the `key` is invalid.
+    #[inline]
+    pub fn remove(&mut self, key: &str) -> Result<(), DatasetKeyError> {
+        let name = key_to_attribute_name(key)?;
+
+        self.element.remove_attribute(&name);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rewritable_units::test_utils::*;
+    use encoding_rs::UTF_8;
+
+    fn rewrite_dataset(html: &str, mut handler: impl FnMut(&mut Dataset)) -> String {
+        let mut handler_called = false;
+
+        let output = rewrite_html(
+            html.as_bytes(),
+            UTF_8,
+            vec![element!("div", |el| {
+                handler_called = true;
+                handler(&mut el.dataset());
+                Ok(())
+            })],
+            vec![],
+        );
+
+        assert!(handler_called, "Handler not called.");
+
+        output
+    }
+
+    #[test]
+    fn name_mapping() {
+        for (key, name) in &[
+            ("foo", "data-foo"),
+            ("fooBarBaz", "data-foo-bar-baz"),
+            ("foo-1", "data-foo-1"),
+            ("foo-Bar", "data-foo--bar"),
+            ("", "data-"),
+        ] {
+            assert_eq!(key_to_attribute_name(key).unwrap(), *name);
+            assert_eq!(attribute_name_to_key(name).unwrap(), *key);
+        }
+
+        assert_eq!(
+            key_to_attribute_name("foo-bar").unwrap_err(),
+            DatasetKeyError::HyphenBeforeLowercase
+        );
+
+        assert_eq!(attribute_name_to_key("foo-bar"), None);
+    }
+
+    #[test]
+    fn get() {
+        rewrite_dataset(
+            "<div id=foo data-foo-bar=1 DATA-BAZ='qux' data-Count='42'>",
+            |dataset| {
+                assert_eq!(dataset.get("fooBar").unwrap(), "1");
+                assert_eq!(dataset.get("baz").unwrap(), "qux");
+                assert_eq!(dataset.get("foo-bar"), None);
+                assert_eq!(dataset.get("id"), None);
+                assert_eq!(dataset.get_as::<u32>("count"), Some(42));
+                assert_eq!(dataset.get_as::<u32>("baz"), None);
+                assert!(dataset.contains("fooBar"));
+                assert!(!dataset.contains("foo"));
+
+                assert_eq!(
+                    dataset.to_vec(),
+                    [
+                        ("fooBar".to_owned(), "1".to_owned()),
+                        ("baz".to_owned(), "qux".to_owned()),
+                        ("count".to_owned(), "42".to_owned())
+                    ]
+                );
+            },
+        );
+    }
+
+    #[test]
+    fn set() {
+        let output = rewrite_dataset("<div data-foo-bar=1>", |dataset| {
+            dataset.set("fooBar", 2).unwrap();
+            dataset.set("isHidden", false).unwrap();
+        });
+
+        assert_eq!(output, r#"<div data-foo-bar="2" data-is-hidden="false">"#);
+    }
+
+    #[test]
+    fn remove() {
+        let output = rewrite_dataset("<div data-foo-bar=1 data-baz=2>", |dataset| {
+            dataset.remove("fooBar").unwrap();
+            dataset.remove("qux").unwrap();
+        });
+
+        assert_eq!(output, "<div data-baz=2>");
+    }
+
+    #[test]
+    fn invalid_keys() {
+        rewrite_dataset("<div data-foo-bar=1>", |dataset| {
+            assert_eq!(
+                dataset.set("foo-bar", 2).unwrap_err(),
+                DatasetKeyError::HyphenBeforeLowercase
+            );
+
+            assert_eq!(
+                dataset.remove("foo-bar").unwrap_err(),
+                DatasetKeyError::HyphenBeforeLowercase
+            );
+
+            assert_eq!(
+                dataset.set("foo bar", 2).unwrap_err(),
+                DatasetKeyError::AttributeName(AttributeNameError::ForbiddenCharacter(' '))
+            );
+        });
+    }
+}
@@ -23,6 +23,20 @@ impl<'a> DocumentEnd<'a> {
         }
     }
 
+    #[inline]
+    pub(crate) fn encoding(&self) -> &'static Encoding {
+        self.encoding
+    }
+
+    /// Appends already serialized content at the end of the document.
+    #[inline]
+    pub(crate) fn append_raw(&mut self, content: &[u8]) {
+        // NOTE: empty chunk signifies the end of the output.
+        if !content.is_empty() {
+            self.output_sink.handle_chunk(content);
+        }
+    }
+
     /// Appends `content` at the end of the document.
     ///
     /// Subsequent calls to this method append `content` to the previously inserted content.
@@ -1,9 +1,18 @@
-use super::{Attribute, AttributeNameError, ContentType, EndTag, Mutations, StartTag};
-use crate::base::Bytes;
-use crate::rewriter::EndTagHandler;
+use super::srcset;
+use super::{
+    Aria, Attribute, AttributeNameError, ClassList, ContentModelError, ContentPolicies,
+    ContentType, Dataset, EndTag, InnerHtml, MatchInfo, Mutations, SourceLocation, SrcsetCandidate,
+    StartTag, StreamingHandler,
+};
+use crate::base::{intern, Bytes};
+use crate::html::{Namespace, TextType};
+use crate::parser::content_text_type;
+use crate::rewriter::{HandlerTypes, IntoHandler, LocalHandlerTypes};
 use encoding_rs::Encoding;
 use std::any::Any;
-use std::fmt::{self, Debug};
+use std::borrow::Cow;
+use std::fmt::{self, Debug, Display};
+use std::ops::Range;
 use thiserror::Error;
 
 /// An error that occurs when invalid value is provided for the tag name.
@@ -30,30 +39,102 @@ pub enum TagNameError {
     UnencodableCharacter,
 }
 
+/// An error that occurs when the raw bytes of the element's start tag can't be patched.
+#[derive(Error, Debug, PartialEq, Copy, Clone)]
+pub enum StartTagPatchError {
+    /// The start tag has been modified, so its raw bytes are not available anymore.
+    #[error("The start tag has been modified and doesn't have a raw representation.")]
+    Modified,
+
+    /// The provided range is out of bounds of the raw start tag bytes.
+    #[error("The range is out of bounds of the raw start tag bytes.")]
+    OutOfBounds,
+}
+
+// NOTE: https://html.spec.whatwg.org/multipage/custom-elements.html#valid-custom-element-name
+// We don't check the full grammar of the names, since the parser guarantees that tag names
+// don't contain whitespace and the HTML syntax-related characters anyway.
+fn is_valid_custom_element_name(name: &str) -> bool {
+    const RESERVED_NAMES: &[&str] = &[
+        "annotation-xml",
+        "color-profile",
+        "font-face",
+        "font-face-src",
+        "font-face-uri",
+        "font-face-format",
+        "font-face-name",
+        "missing-glyph",
+    ];
+
+    name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name.contains('-')
+        && !name.bytes().any(|b| b.is_ascii_uppercase())
+        && !RESERVED_NAMES.contains(&name)
+}
+
+/// The mode of a [declarative shadow root].
+///
+/// [declarative shadow root]: https://html.spec.whatwg.org/multipage/scripting.html#attr-template-shadowrootmode
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum ShadowRootMode {
+    /// The `shadowrootmode="open"` attribute.
+    Open,
+    /// The `shadowrootmode="closed"` attribute.
+    Closed,
+}
+
 /// An HTML element rewritable unit.
 ///
 /// Exposes API for examination and modification of a parsed HTML element.
-pub struct Element<'r, 't> {
+pub struct Element<'r, 't, H: HandlerTypes = LocalHandlerTypes> {
     start_tag: &'r mut StartTag<'t>,
     end_tag_mutations: Option<Mutations>,
     modified_end_tag_name: Option<Bytes<'static>>,
+    end_tag_handlers: Vec<H::EndTagHandler<'static>>,
+    inner_html_handlers: Vec<H::InnerHtmlHandler<'static>>,
     can_have_content: bool,
+    content_text_type: TextType,
     should_remove_content: bool,
+    modified_attributes: Vec<String>,
+    content_policies: ContentPolicies,
+    content_model_error: Option<ContentModelError>,
     encoding: &'static Encoding,
+    match_info: Option<MatchInfo>,
+    ancestors_path: Option<&'r dyn Display>,
     user_data: Box<dyn Any>,
 }
 
-impl<'r, 't> Element<'r, 't> {
-    pub(crate) fn new(start_tag: &'r mut StartTag<'t>, can_have_content: bool) -> Self {
+impl<'r, 't, H: HandlerTypes> Element<'r, 't, H> {
+    pub(crate) fn new(
+        start_tag: &'r mut StartTag<'t>,
+        can_have_content: bool,
+        content_policies: ContentPolicies,
+    ) -> Self {
         let encoding = start_tag.encoding();
 
+        // NOTE: the text type is determined before any handler has a chance to modify the tag
+        // name, since the parser switches to it for the original tag name.
+        let content_text_type = if start_tag.namespace_uri() == Namespace::Html.uri() {
+            content_text_type(start_tag.local_name_hash()).unwrap_or(TextType::Data)
+        } else {
+            TextType::Data
+        };
+
         Element {
             start_tag,
             end_tag_mutations: None,
             modified_end_tag_name: None,
+            end_tag_handlers: Vec::default(),
+            inner_html_handlers: Vec::default(),
             can_have_content,
+            content_text_type,
             should_remove_content: false,
+            modified_attributes: Vec::default(),
+            content_policies,
+            content_model_error: None,
             encoding,
+            match_info: None,
+            ancestors_path: None,
             user_data: Box::new(()),
         }
     }
@@ -67,6 +148,8 @@ impl<'r, 't> Element<'r, 't> {
                     .find(|&ch| matches!(ch, ' ' | '\n' | '\r' | '\t' | '\x0C' | '/' | '>'))
                 {
                     Err(TagNameError::ForbiddenCharacter(ch))
+                } else if let Some(name) = intern(name) {
+                    Ok(Bytes::from(name.as_bytes()))
                 } else {
                     // NOTE: if character can't be represented in the given
                     // encoding then encoding_rs replaces it with a numeric
@@ -89,6 +172,40 @@ impl<'r, 't> Element<'r, 't> {
         self.should_remove_content = true;
     }
 
+    /// Applies the content policies to the content inserted into the element. Returns `None` if
+    /// the content has been rejected.
+    fn checked_content<'c>(
+        &mut self,
+        content: &'c str,
+        content_type: ContentType,
+    ) -> Option<(Cow<'c, str>, ContentType)> {
+        let policies = self.content_policies;
+
+        if policies.ignore_all() || self.namespace_uri() != Namespace::Html.uri() {
+            return Some((content.into(), content_type));
+        }
+
+        match policies.apply(&self.tag_name(), content, content_type) {
+            Ok(res) => Some(res),
+            Err(e) => {
+                self.content_model_error = Some(e);
+                None
+            }
+        }
+    }
+
+    #[inline]
+    fn mark_attribute_modified(&mut self, name: &str) {
+        let is_marked = self
+            .modified_attributes
+            .iter()
+            .any(|n| n.eq_ignore_ascii_case(name));
+
+        if !is_marked {
+            self.modified_attributes.push(name.to_ascii_lowercase());
+        }
+    }
+
     #[inline]
     fn end_tag_mutations_mut(&mut self) -> &mut Mutations {
         let encoding = self.encoding;
@@ -104,6 +221,8 @@ impl<'r, 't> Element<'r, 't> {
     }
 
     /// Sets the tag name of the element.
+    ///
+    /// The end tag of the element, if it has one, is renamed as well.
     #[inline]
     pub fn set_tag_name(&mut self, name: &str) -> Result<(), TagNameError> {
         let name = self.tag_name_bytes_from_str(name)?;
@@ -117,6 +236,88 @@ impl<'r, 't> Element<'r, 't> {
         Ok(())
     }
 
+    /// Returns the bytes of the element's start tag exactly as they appear in the input, in the
+    /// document's [`encoding`].
+    ///
+    /// Unlike [`start_tag_raw_bytes`], the bytes are returned regardless of the modifications of
+    /// the start tag, which makes the method suitable for the observation of the input, e.g. for
+    /// hashing.
+    ///
+    /// [`encoding`]: ../struct.Settings.html#structfield.encoding
+    /// [`start_tag_raw_bytes`]: #method.start_tag_raw_bytes
+    #[inline]
+    pub fn raw(&self) -> &[u8] {
+        self.start_tag.input_raw_bytes()
+    }
+
+    /// Returns the raw bytes of the element's start tag as they appear in the input, in the
+    /// document's [`encoding`].
+    ///
+    /// Returns `None` if the start tag has been modified (e.g. its name or attributes were
+    /// changed) and, thus, will be serialized from its parts.
+    ///
+    /// [`encoding`]: ../struct.Settings.html#structfield.encoding
+    #[inline]
+    pub fn start_tag_raw_bytes(&self) -> Option<&[u8]> {
+        self.start_tag.raw_bytes()
+    }
+
+    /// Replaces the `range` of the [raw start tag bytes] with the `replacement`.
+    ///
+    /// The patched bytes are emitted to the output as is, without re-serialization of the start
+    /// tag, which makes the method suitable for surgical edits on hot paths. It's up to the user
+    /// to ensure that the patched start tag remains well-formed and that the `replacement` is in
+    /// the document's [`encoding`].
+    ///
+    /// Note that tag name and attribute accessors keep returning the original values. Also, if
+    /// the start tag is modified by any other method after patching, it will be serialized from
+    /// its parts and the patches will be discarded.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lol_html::{rewrite_str, element, RewriteStrSettings};
+    ///
+    /// let html = rewrite_str(
+    ///     r#"<div id="foo"><img src="a.png" ></div>"#,
+    ///     RewriteStrSettings {
+    ///         element_content_handlers: vec![
+    ///             element!("img", |el| {
+    ///                 let len = el.start_tag_raw_bytes().unwrap().len();
+    ///
+    ///                 el.patch_start_tag(len - 1..len - 1, br#"loading="lazy""#)?;
+    ///
+    ///                 Ok(())
+    ///             })
+    ///         ],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(html, r#"<div id="foo"><img src="a.png" loading="lazy"></div>"#);
+    /// ```
+    ///
+    /// [raw start tag bytes]: #method.start_tag_raw_bytes
+    /// [`encoding`]: ../struct.Settings.html#structfield.encoding
+    pub fn patch_start_tag(
+        &mut self,
+        range: Range<usize>,
+        replacement: &[u8],
+    ) -> Result<(), StartTagPatchError> {
+        let raw_len = match self.start_tag.raw_bytes() {
+            Some(raw) => raw.len(),
+            None => return Err(StartTagPatchError::Modified),
+        };
+
+        if range.start > range.end || range.end > raw_len {
+            return Err(StartTagPatchError::OutOfBounds);
+        }
+
+        self.start_tag.patch_raw(range, replacement);
+
+        Ok(())
+    }
+
     /// Returns the [namespace URI] of the element.
     ///
     /// [namespace URI]: https://developer.mozilla.org/en-US/docs/Web/API/Element/namespaceURI
@@ -125,12 +326,217 @@ impl<'r, 't> Element<'r, 't> {
         self.start_tag.namespace_uri()
     }
 
+    /// Returns the type of the text the content of the element is parsed as.
+    ///
+    /// The content of some elements (e.g. `<script>`, `<style>` or `<title>`) is not parsed as
+    /// markup, so the text inserted into it with [`prepend`], [`append`] or
+    /// [`set_inner_content`] should be escaped according to the rules of the text type. For
+    /// example, [HTML entities] are not decoded in the [`TextType::ScriptData`] and
+    /// [`TextType::RawText`] text. The text type is determined by the original tag name of the
+    /// element, even if it has been changed with [`set_tag_name`].
+    ///
+    /// Note that the text chunks of the content report their text type as well, with
+    /// [`TextChunk::text_type`].
+    ///
+    /// # Example
+    /// ```
+    /// use lol_html::html_content::TextType;
+    /// use lol_html::{rewrite_str, element, RewriteStrSettings};
+    ///
+    /// let mut text_types = vec![];
+    ///
+    /// rewrite_str(
+    ///     "<title></title><script></script><div></div><svg><style></style></svg>",
+    ///     RewriteStrSettings {
+    ///         element_content_handlers: vec![
+    ///             element!("*", |el| {
+    ///                 text_types.push(el.content_text_type());
+    ///
+    ///                 Ok(())
+    ///             })
+    ///         ],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(
+    ///     text_types,
+    ///     vec![
+    ///         TextType::RCData,
+    ///         TextType::ScriptData,
+    ///         TextType::Data,
+    ///         TextType::Data,
+    ///         TextType::Data
+    ///     ]
+    /// );
+    /// ```
+    ///
+    /// [`prepend`]: #method.prepend
+    /// [`append`]: #method.append
+    /// [`set_inner_content`]: #method.set_inner_content
+    /// [`set_tag_name`]: #method.set_tag_name
+    /// [HTML entities]: https://developer.mozilla.org/en-US/docs/Glossary/Entity
+    /// [`TextType::ScriptData`]: enum.TextType.html#variant.ScriptData
+    /// [`TextType::RawText`]: enum.TextType.html#variant.RawText
+    /// [`TextChunk::text_type`]: struct.TextChunk.html#method.text_type
+    #[inline]
+    pub fn content_text_type(&self) -> TextType {
+        self.content_text_type
+    }
+
+    /// Returns the name of the [custom element] the element is going to be upgraded to by a
+    /// browser.
+    ///
+    /// For autonomous custom elements (e.g. `<fancy-button>`) that is the tag name of the
+    /// element, for customized built-in elements (e.g. `<button is="fancy-button">`) that is
+    /// the value of the `is` attribute. Returns `None` if the element is not a custom element
+    /// or the name is not a valid custom element name.
+    ///
+    /// # Example
+    /// ```
+    /// use lol_html::{rewrite_str, element, RewriteStrSettings};
+    ///
+    /// let mut names = vec![];
+    ///
+    /// rewrite_str(
+    ///     r#"<fancy-button></fancy-button><button is="fancy-button"></button><button></button>"#,
+    ///     RewriteStrSettings {
+    ///         element_content_handlers: vec![
+    ///             element!("*", |el| {
+    ///                 names.push(el.custom_element_name());
+    ///
+    ///                 Ok(())
+    ///             })
+    ///         ],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(
+    ///     names,
+    ///     vec![Some("fancy-button".into()), Some("fancy-button".into()), None]
+    /// );
+    /// ```
+    ///
+    /// [custom element]: https://html.spec.whatwg.org/multipage/custom-elements.html
+    pub fn custom_element_name(&self) -> Option<String> {
+        if self.namespace_uri() != Namespace::Html.uri() {
+            return None;
+        }
+
+        let tag_name = self.start_tag.name_str();
+
+        if is_valid_custom_element_name(&tag_name) {
+            Some(tag_name.into_owned())
+        } else {
+            self.get_attribute("is")
+                .filter(|name| is_valid_custom_element_name(name))
+        }
+    }
+
+    /// Returns the mode of the [declarative shadow root] if the element is a `<template>` element
+    /// that declares one with a valid `shadowrootmode` attribute.
+    ///
+    /// Note that the selectors are matched against the content of declarative shadow roots
+    /// as well, unless [`match_declarative_shadow_roots`] is disabled in the settings.
+    ///
+    /// # Example
+    /// ```
+    /// use lol_html::html_content::{ContentType, ShadowRootMode};
+    /// use lol_html::{rewrite_str, element, RewriteStrSettings};
+    ///
+    /// let html = rewrite_str(
+    ///     r#"<template shadowrootmode="open"></template><template></template>"#,
+    ///     RewriteStrSettings {
+    ///         element_content_handlers: vec![
+    ///             element!("template", |el| {
+    ///                 if el.shadow_root_mode() == Some(ShadowRootMode::Open) {
+    ///                     el.prepend("<slot></slot>", ContentType::Html);
+    ///                 }
+    ///
+    ///                 Ok(())
+    ///             })
+    ///         ],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(
+    ///     html,
+    ///     r#"<template shadowrootmode="open"><slot></slot></template><template></template>"#
+    /// );
+    /// ```
+    ///
+    /// [declarative shadow root]: https://html.spec.whatwg.org/multipage/scripting.html#attr-template-shadowrootmode
+    /// [`match_declarative_shadow_roots`]: ../struct.Settings.html#structfield.match_declarative_shadow_roots
+    pub fn shadow_root_mode(&self) -> Option<ShadowRootMode> {
+        let is_html_template = self.start_tag.name_str() == "template"
+            && self.namespace_uri() == Namespace::Html.uri();
+
+        if !is_html_template {
+            return None;
+        }
+
+        let mode = self.get_attribute("shadowrootmode")?;
+
+        if mode.eq_ignore_ascii_case("open") {
+            Some(ShadowRootMode::Open)
+        } else if mode.eq_ignore_ascii_case("closed") {
+            Some(ShadowRootMode::Closed)
+        } else {
+            None
+        }
+    }
+
     /// Returns an immutable collection of element's attributes.
     #[inline]
     pub fn attributes(&self) -> &[Attribute<'t>] {
         self.start_tag.attributes()
     }
 
+    /// Returns an iterator over element's attributes which allows modification of
+    /// their values in place.
+    ///
+    /// This is more efficient than calling [`set_attribute`] for each of the attributes, since
+    /// it doesn't require lookup of an attribute by its name. Once the method is called, the start
+    /// tag is considered modified and [`start_tag_raw_bytes`] returns `None`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lol_html::{rewrite_str, element, RewriteStrSettings};
+    ///
+    /// let html = rewrite_str(
+    ///     r#"<img src="http://a.com/1.png" data-src="http://a.com/2.png" alt="http:">"#,
+    ///     RewriteStrSettings {
+    ///         element_content_handlers: vec![
+    ///             element!("img", |el| {
+    ///                 for attr in el.attributes_mut() {
+    ///                     if attr.name().ends_with("src") {
+    ///                         attr.set_value(&attr.value().replace("http:", "https:"));
+    ///                     }
+    ///                 }
+    ///
+    ///                 Ok(())
+    ///             })
+    ///         ],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(
+    ///     html,
+    ///     r#"<img src="https://a.com/1.png" data-src="https://a.com/2.png" alt="http:">"#
+    /// );
+    /// ```
+    ///
+    /// [`set_attribute`]: #method.set_attribute
+    /// [`start_tag_raw_bytes`]: #method.start_tag_raw_bytes
+    #[inline]
+    pub fn attributes_mut(&mut self) -> std::slice::IterMut<'_, Attribute<'t>> {
+        self.start_tag.attributes_mut().iter_mut()
+    }
+
     /// Returns the value of an attribute with the `name`.
     ///
     /// Returns `None` if the element doesn't have an attribute with the `name`.
@@ -139,7 +545,7 @@ impl<'r, 't> Element<'r, 't> {
         let name = name.to_ascii_lowercase();
 
         self.attributes().iter().find_map(|attr| {
-            if attr.name() == name {
+            if attr.name_str() == name {
                 Some(attr.value())
             } else {
                 None
@@ -147,12 +553,102 @@ impl<'r, 't> Element<'r, 't> {
         })
     }
 
+    /// Returns typed accessors for the [WAI-ARIA] role and attributes of the element.
+    ///
+    /// Refer to [`Aria`] documentation for more information.
+    ///
+    /// [WAI-ARIA]: https://www.w3.org/TR/wai-aria-1.2/
+    /// [`Aria`]: struct.Aria.html
+    #[inline]
+    pub fn aria(&mut self) -> Aria<'_, 'r, 't, H> {
+        Aria::new(self)
+    }
+
+    /// Returns an accessor for the class names in the `class` attribute of the element.
+    ///
+    /// Refer to [`ClassList`] documentation for more information.
+    ///
+    /// [`ClassList`]: struct.ClassList.html
+    #[inline]
+    pub fn class_list(&mut self) -> ClassList<'_, 'r, 't, H> {
+        ClassList::new(self)
+    }
+
+    /// Returns an accessor for the `data-*` attributes of the element.
+    ///
+    /// Refer to [`Dataset`] documentation for more information.
+    ///
+    /// [`Dataset`]: struct.Dataset.html
+    #[inline]
+    pub fn dataset(&mut self) -> Dataset<'_, 'r, 't, H> {
+        Dataset::new(self)
+    }
+
+    /// Rewrites the image candidates of the `srcset` attribute of the element with
+    /// the `rewriter`, e.g. to point the URLs of the images to a CDN.
+    ///
+    /// The attribute value is parsed with [`srcset::parse`], so the URLs that contain commas
+    /// and the width and pixel density descriptors are handled correctly. The attribute is
+    /// modified only if the `rewriter` changes any of the candidates.
+    ///
+    /// # Example
+    /// ```
+    /// use lol_html::{element, rewrite_str, RewriteStrSettings};
+    ///
+    /// let html = rewrite_str(
+    ///     r#"<img src="/a.png" srcset="/a-2x.png 2x, /a-3x.png 3x">"#,
+    ///     RewriteStrSettings {
+    ///         element_content_handlers: vec![
+    ///             element!("img[srcset]", |el| {
+    ///                 el.rewrite_srcset(|candidate| {
+    ///                     let url = format!("https://cdn.example.com{}", candidate.url());
+    ///
+    ///                     candidate.set_url(&url);
+    ///                 });
+    ///
+    ///                 Ok(())
+    ///             })
+    ///         ],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(
+    ///     html,
+    ///     concat!(
+    ///         r#"<img src="/a.png" srcset="https://cdn.example.com/a-2x.png 2x, "#,
+    ///         r#"https://cdn.example.com/a-3x.png 3x">"#
+    ///     )
+    /// );
+    /// ```
+    ///
+    /// [`srcset::parse`]: srcset/fn.parse.html
+    pub fn rewrite_srcset(&mut self, mut rewriter: impl FnMut(&mut SrcsetCandidate)) {
+        if let Some(value) = self.get_attribute("srcset") {
+            let mut candidates = srcset::parse(&value);
+            let mut modified = false;
+
+            for candidate in &mut candidates {
+                let original = candidate.clone();
+
+                rewriter(candidate);
+                modified |= *candidate != original;
+            }
+
+            if modified {
+                // NOTE: `srcset` is a valid attribute name, so it's safe to unwrap here.
+                self.set_attribute("srcset", &srcset::serialize(&candidates))
+                    .unwrap();
+            }
+        }
+    }
+
     /// Returns `true` if the element has an attribute with `name`.
     #[inline]
     pub fn has_attribute(&self, name: &str) -> bool {
         let name = name.to_ascii_lowercase();
 
-        self.attributes().iter().any(|attr| attr.name() == name)
+        self.attributes().iter().any(|attr| attr.name_str() == name)
     }
 
     /// Sets `value` of element's attribute with `name`.
@@ -161,13 +657,170 @@ impl<'r, 't> Element<'r, 't> {
     /// to the element with `name` and `value`.
     #[inline]
     pub fn set_attribute(&mut self, name: &str, value: &str) -> Result<(), AttributeNameError> {
-        self.start_tag.set_attribute(name, value)
+        self.start_tag.set_attribute(name, value)?;
+        self.mark_attribute_modified(name);
+
+        Ok(())
     }
 
     /// Removes an attribute with the `name` if it is present.
     #[inline]
     pub fn remove_attribute(&mut self, name: &str) {
-        self.start_tag.remove_attribute(name);
+        if self.start_tag.remove_attribute(name) {
+            self.mark_attribute_modified(name);
+        }
+    }
+
+    /// Retains only the attributes for which `predicate` returns `true`.
+    ///
+    /// The `predicate` receives the name and the value of each attribute. The attribute list
+    /// is filtered in a single pass, which is more efficient than calling [`remove_attribute`]
+    /// for each attribute that needs to be removed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lol_html::{rewrite_str, element, RewriteStrSettings};
+    ///
+    /// let html = rewrite_str(
+    ///     r#"<a href="/" style="color: red" data-foo="bar" title="baz">"#,
+    ///     RewriteStrSettings {
+    ///         element_content_handlers: vec![
+    ///             element!("a", |el| {
+    ///                 el.retain_attributes(|name, _| name == "href" || name == "title");
+    ///
+    ///                 Ok(())
+    ///             })
+    ///         ],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(html, r#"<a href="/" title="baz">"#);
+    /// ```
+    ///
+    /// [`remove_attribute`]: #method.remove_attribute
+    pub fn retain_attributes(&mut self, mut predicate: impl FnMut(&str, &str) -> bool) {
+        let removed = self
+            .start_tag
+            .remove_attributes_where(|attr| !predicate(&attr.name_str(), &attr.value()));
+
+        for attr in removed.iter() {
+            self.mark_attribute_modified(&attr.name_str());
+        }
+    }
+
+    /// Retains only the attributes for which `predicate` returns `true`, allowing the retained
+    /// attributes to be modified in place.
+    ///
+    /// The `predicate` receives each attribute and can rename it or change its value before
+    /// deciding whether the attribute should be kept. Like [`retain_attributes`], the attribute
+    /// list is processed in a single pass.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lol_html::{rewrite_str, element, RewriteStrSettings};
+    ///
+    /// let html = rewrite_str(
+    ///     r#"<img data-src="http://a.com/1.png" onload="track()" alt="http:">"#,
+    ///     RewriteStrSettings {
+    ///         element_content_handlers: vec![
+    ///             element!("img", |el| {
+    ///                 el.retain_attributes_mut(|attr| match attr.name().as_str() {
+    ///                     "onload" => false,
+    ///                     "data-src" => {
+    ///                         attr.set_value(&attr.value().replace("http:", "https:"));
+    ///                         attr.set_name("src").unwrap();
+    ///
+    ///                         true
+    ///                     }
+    ///                     _ => true,
+    ///                 });
+    ///
+    ///                 Ok(())
+    ///             })
+    ///         ],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(html, r#"<img src="https://a.com/1.png" alt="http:">"#);
+    /// ```
+    ///
+    /// [`retain_attributes`]: #method.retain_attributes
+    pub fn retain_attributes_mut(&mut self, predicate: impl FnMut(&mut Attribute<'t>) -> bool) {
+        for name in self.start_tag.retain_attributes_mut(predicate) {
+            self.mark_attribute_modified(&name);
+        }
+    }
+
+    /// Removes all the inline event handler attributes (e.g. `onclick` or `onload`) from the
+    /// element and returns them.
+    ///
+    /// Any attribute whose name consists of the `on` prefix followed by ASCII letters is
+    /// considered to be an event handler, so attributes for non-standard or future events are
+    /// removed as well, while e.g. `online-id` is kept. The attribute list is filtered in a
+    /// single pass.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lol_html::{rewrite_str, element, RewriteStrSettings};
+    ///
+    /// let mut removed = vec![];
+    ///
+    /// let html = rewrite_str(
+    ///     r#"<img src="a.png" onerror="alert(1)" OnFutureEvent="alert(2)">"#,
+    ///     RewriteStrSettings {
+    ///         element_content_handlers: vec![
+    ///             element!("*", |el| {
+    ///                 for attr in el.remove_event_handler_attributes() {
+    ///                     removed.push(attr.name());
+    ///                 }
+    ///
+    ///                 Ok(())
+    ///             })
+    ///         ],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(html, r#"<img src="a.png">"#);
+    /// assert_eq!(removed, ["onerror", "onfutureevent"]);
+    /// ```
+    pub fn remove_event_handler_attributes(&mut self) -> Vec<Attribute<'t>> {
+        self.remove_event_handler_attributes_except(&[])
+    }
+
+    /// Removes the inline event handler attributes, except for the ones with the `allowed`
+    /// lowercase names, and returns them.
+    pub(crate) fn remove_event_handler_attributes_except(
+        &mut self,
+        allowed: &[String],
+    ) -> Vec<Attribute<'t>> {
+        let removed = self.start_tag.remove_attributes_where(|attr| {
+            attr.is_event_handler() && !allowed.iter().any(|name| *name == attr.name_str())
+        });
+
+        for attr in removed.iter() {
+            self.mark_attribute_modified(&attr.name_str());
+        }
+
+        removed
+    }
+
+    /// Returns `true` if an attribute with the `name` has been set or removed by one of the
+    /// content handlers.
+    #[inline]
+    pub fn is_attribute_modified(&self, name: &str) -> bool {
+        self.modified_attributes
+            .iter()
+            .any(|n| n.eq_ignore_ascii_case(name))
+            || self
+                .attributes()
+                .iter()
+                .any(|attr| attr.is_modified() && attr.name_str().eq_ignore_ascii_case(name))
     }
 
     /// Inserts `content` before the element.
@@ -278,7 +931,9 @@ impl<'r, 't> Element<'r, 't> {
     #[inline]
     pub fn prepend(&mut self, content: &str, content_type: ContentType) {
         if self.can_have_content {
-            self.start_tag.mutations.after(content, content_type);
+            if let Some((content, content_type)) = self.checked_content(content, content_type) {
+                self.start_tag.mutations.after(&content, content_type);
+            }
         }
     }
 
@@ -288,6 +943,10 @@ impl<'r, 't> Element<'r, 't> {
     /// Consequent calls to the method append `content` to the previously inserted content.
     /// A call to the method doesn't make any effect if the element is an [empty element].
     ///
+    /// If the end tag of the element is omitted (e.g. `<p>` closed by the start tag of `<div>`
+    /// or by the end of the document), `content` is inserted where the element is closed
+    /// implicitly.
+    ///
     /// [empty element]: https://developer.mozilla.org/en-US/docs/Glossary/Empty_element
     ///
     /// # Example
@@ -320,7 +979,9 @@ impl<'r, 't> Element<'r, 't> {
     #[inline]
     pub fn append(&mut self, content: &str, content_type: ContentType) {
         if self.can_have_content {
-            self.end_tag_mutations_mut().before(content, content_type);
+            if let Some((content, content_type)) = self.checked_content(content, content_type) {
+                self.end_tag_mutations_mut().before(&content, content_type);
+            }
         }
     }
 
@@ -356,19 +1017,279 @@ impl<'r, 't> Element<'r, 't> {
     ///     }
     /// ).unwrap();
     ///
-    /// assert_eq!(html, r#"<div id="foo"><!-- survive --></div><img>"#);
-    /// ```
+    /// assert_eq!(html, r#"<div id="foo"><!-- survive --></div><img>"#);
+    /// ```
+    #[inline]
+    pub fn set_inner_content(&mut self, content: &str, content_type: ContentType) {
+        if self.can_have_content {
+            if let Some((content, content_type)) = self.checked_content(content, content_type) {
+                self.remove_content();
+                self.start_tag.mutations.after(&content, content_type);
+            }
+        }
+    }
+
+    /// Replaces the element and its inner content with `content`.
+    ///
+    /// Consequent calls to the method overwrite previously inserted content.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lol_html::{rewrite_str, element, RewriteStrSettings};
+    /// use lol_html::html_content::ContentType;
+    ///
+    /// let html = rewrite_str(
+    ///     r#"<div id="foo"></div>"#,
+    ///     RewriteStrSettings {
+    ///         element_content_handlers: vec![
+    ///             element!("#foo", |el| {
+    ///                 el.replace("<span></span>", ContentType::Html);
+    ///                 el.replace("Hello", ContentType::Text);
+    ///
+    ///                 Ok(())
+    ///             })
+    ///         ],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(html, r#"Hello"#);
+    /// ```
+    #[inline]
+    pub fn replace(&mut self, content: &str, content_type: ContentType) {
+        self.start_tag.mutations.replace(content, content_type);
+
+        if self.can_have_content {
+            self.remove_content();
+            self.end_tag_mutations_mut().remove();
+        }
+    }
+
+    /// Inserts the content written by the streaming `handler` before the element.
+    ///
+    /// Same as [`before`], but the content is produced by the handler when the element is
+    /// serialized. Refer to [`StreamingHandler`] documentation for more information.
+    ///
+    /// [`before`]: #method.before
+    /// [`StreamingHandler`]: trait.StreamingHandler.html
+    #[inline]
+    pub fn streaming_before(&mut self, handler: Box<dyn StreamingHandler>) {
+        self.start_tag.mutations.streaming_before(handler);
+    }
+
+    /// Inserts the content written by the streaming `handler` after the element.
+    ///
+    /// Same as [`after`], but the content is produced by the handler when the element is
+    /// serialized. Refer to [`StreamingHandler`] documentation for more information.
+    ///
+    /// [`after`]: #method.after
+    /// [`StreamingHandler`]: trait.StreamingHandler.html
+    #[inline]
+    pub fn streaming_after(&mut self, handler: Box<dyn StreamingHandler>) {
+        if self.can_have_content {
+            self.end_tag_mutations_mut().streaming_after(handler);
+        } else {
+            self.start_tag.mutations.streaming_after(handler);
+        }
+    }
+
+    /// Prepends the content written by the streaming `handler` to the element's inner content.
+    ///
+    /// Same as [`prepend`], but the content is produced by the handler when the element is
+    /// serialized. The content policies are not applied to the streamed content. Refer to
+    /// [`StreamingHandler`] documentation for more information.
+    ///
+    /// [`prepend`]: #method.prepend
+    /// [`StreamingHandler`]: trait.StreamingHandler.html
+    #[inline]
+    pub fn streaming_prepend(&mut self, handler: Box<dyn StreamingHandler>) {
+        if self.can_have_content {
+            self.start_tag.mutations.streaming_after(handler);
+        }
+    }
+
+    /// Appends the content written by the streaming `handler` to the element's inner content.
+    ///
+    /// Same as [`append`], but the content is produced by the handler when the end tag of the
+    /// element is serialized. The content policies are not applied to the streamed content.
+    /// Refer to [`StreamingHandler`] documentation for more information.
+    ///
+    /// [`append`]: #method.append
+    /// [`StreamingHandler`]: trait.StreamingHandler.html
+    #[inline]
+    pub fn streaming_append(&mut self, handler: Box<dyn StreamingHandler>) {
+        if self.can_have_content {
+            self.end_tag_mutations_mut().streaming_before(handler);
+        }
+    }
+
+    /// Replaces inner content of the element with the content written by the streaming
+    /// `handler`.
+    ///
+    /// Same as [`set_inner_content`], but the content is produced by the handler when the
+    /// element is serialized. The content policies are not applied to the streamed content.
+    /// Refer to [`StreamingHandler`] documentation for more information.
+    ///
+    /// [`set_inner_content`]: #method.set_inner_content
+    /// [`StreamingHandler`]: trait.StreamingHandler.html
+    #[inline]
+    pub fn streaming_set_inner_content(&mut self, handler: Box<dyn StreamingHandler>) {
+        if self.can_have_content {
+            self.remove_content();
+            self.start_tag.mutations.streaming_after(handler);
+        }
+    }
+
+    /// Replaces the element and its inner content with the content written by the streaming
+    /// `handler`.
+    ///
+    /// Same as [`replace`], but the content is produced by the handler when the element is
+    /// serialized. Refer to [`StreamingHandler`] documentation for more information.
+    ///
+    /// [`replace`]: #method.replace
+    /// [`StreamingHandler`]: trait.StreamingHandler.html
+    #[inline]
+    pub fn streaming_replace(&mut self, handler: Box<dyn StreamingHandler>) {
+        self.start_tag.mutations.streaming_replace(handler);
+
+        if self.can_have_content {
+            self.remove_content();
+            self.end_tag_mutations_mut().remove();
+        }
+    }
+
+    /// Removes the element and its inner content.
+    #[inline]
+    pub fn remove(&mut self) {
+        self.start_tag.mutations.remove();
+
+        if self.can_have_content {
+            self.remove_content();
+            self.end_tag_mutations_mut().remove();
+        }
+    }
+
+    /// Removes the element, but keeps its content. I.e. remove start and end tags of the element.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lol_html::{rewrite_str, element, RewriteStrSettings};
+    ///
+    /// let html = rewrite_str(
+    ///     r#"<div><span><!-- 42 --></span></div>"#,
+    ///     RewriteStrSettings {
+    ///         element_content_handlers: vec![
+    ///             element!("div", |el| {
+    ///                 el.remove_and_keep_content();
+    ///
+    ///                 Ok(())
+    ///             })
+    ///         ],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(html, r#"<span><!-- 42 --></span>"#);
+    /// ```
+    #[inline]
+    pub fn remove_and_keep_content(&mut self) {
+        self.start_tag.mutations.remove();
+
+        if self.can_have_content {
+            self.end_tag_mutations_mut().remove();
+        }
+    }
+
+    /// Returns `true` if the element has been removed or replaced with some content.
+    #[inline]
+    pub fn removed(&self) -> bool {
+        self.start_tag.mutations.removed()
+    }
+
+    /// Registers a `handler` that is invoked for the end tag of the element.
+    ///
+    /// The handler can insert content right before or after the end tag when the whole content of
+    /// the element has been processed, e.g. depending on the elements the content contains. The
+    /// handlers are invoked in the order they have been registered, after the content inserted with
+    /// [`append`] has been added to the end tag. If the end tag is absent in the input and is
+    /// implied by the parser, the handler receives an [implicit] end tag.
+    ///
+    /// A call to the method doesn't make any effect if the element is an [empty element].
+    ///
+    /// [`append`]: #method.append
+    /// [implicit]: struct.EndTag.html#method.implicit
+    /// [empty element]: https://developer.mozilla.org/en-US/docs/Glossary/Empty_element
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lol_html::{rewrite_str, element, RewriteStrSettings};
+    /// use lol_html::html_content::ContentType;
+    ///
+    /// let html = rewrite_str(
+    ///     r#"<div id="foo"><span></span></div>"#,
+    ///     RewriteStrSettings {
+    ///         element_content_handlers: vec![
+    ///             element!("#foo", |el| {
+    ///                 el.append("<!-- appended -->", ContentType::Html);
+    ///
+    ///                 el.on_end_tag(|end| {
+    ///                     end.before("<!-- before end -->", ContentType::Html);
+    ///                     end.after("<!-- after end -->", ContentType::Html);
+    ///
+    ///                     Ok(())
+    ///                 });
+    ///
+    ///                 Ok(())
+    ///             })
+    ///         ],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(
+    ///     html,
+    ///     r#"<div id="foo"><span></span><!-- appended --><!-- before end --></div><!-- after end -->"#
+    /// );
+    /// ```
+    #[inline]
+    pub fn on_end_tag(
+        &mut self,
+        handler: impl FnOnce(&mut EndTag) -> Result<(), Box<dyn std::error::Error>>
+            + IntoHandler<H::EndTagHandler<'static>>,
+    ) {
+        self.add_end_tag_handler(handler.into_handler());
+    }
+
+    /// Same as [`on_end_tag`], but for the handler that has already been converted, e.g. by
+    /// a preset that is generic over the handler types.
+    ///
+    /// [`on_end_tag`]: #method.on_end_tag
     #[inline]
-    pub fn set_inner_content(&mut self, content: &str, content_type: ContentType) {
+    pub(crate) fn add_end_tag_handler(&mut self, handler: H::EndTagHandler<'static>) {
         if self.can_have_content {
-            self.remove_content();
-            self.start_tag.mutations.after(content, content_type);
+            self.end_tag_handlers.push(handler);
         }
     }
 
-    /// Replaces the element and its inner content with `content`.
+    /// Registers a `handler` that receives the inner HTML of the element once its end tag has
+    /// been reached.
     ///
-    /// Consequent calls to the method overwrite previously inserted content.
+    /// The inner content of the element is buffered instead of being written to the output, so
+    /// the handler can inspect the whole content and decide whether to [replace] it. The
+    /// buffered content counts towards the [`max_allowed_memory_usage`]. The handlers are
+    /// invoked in the order they have been registered, after the handlers registered with
+    /// [`on_end_tag`].
+    ///
+    /// A call to the method doesn't make any effect if the element is an [empty element] or its
+    /// inner content is removed or replaced.
+    ///
+    /// [replace]: struct.InnerHtml.html#method.set_inner_content
+    /// [`max_allowed_memory_usage`]: ../struct.MemorySettings.html#structfield.max_allowed_memory_usage
+    /// [`on_end_tag`]: #method.on_end_tag
+    /// [empty element]: https://developer.mozilla.org/en-US/docs/Glossary/Empty_element
     ///
     /// # Example
     ///
@@ -377,12 +1298,17 @@ impl<'r, 't> Element<'r, 't> {
     /// use lol_html::html_content::ContentType;
     ///
     /// let html = rewrite_str(
-    ///     r#"<div id="foo"></div>"#,
+    ///     r#"<div id="promo"><p>Sale!</p><img src="banner.png"></div>"#,
     ///     RewriteStrSettings {
     ///         element_content_handlers: vec![
-    ///             element!("#foo", |el| {
-    ///                 el.replace("<span></span>", ContentType::Html);
-    ///                 el.replace("Hello", ContentType::Text);
+    ///             element!("#promo", |el| {
+    ///                 el.on_inner_html(|inner| {
+    ///                     if inner.as_str().contains("<img") {
+    ///                         inner.set_inner_content("<p>Sale!</p>", ContentType::Html);
+    ///                     }
+    ///
+    ///                     Ok(())
+    ///                 });
     ///
     ///                 Ok(())
     ///             })
@@ -391,65 +1317,117 @@ impl<'r, 't> Element<'r, 't> {
     ///     }
     /// ).unwrap();
     ///
-    /// assert_eq!(html, r#"Hello"#);
+    /// assert_eq!(html, r#"<div id="promo"><p>Sale!</p></div>"#);
     /// ```
     #[inline]
-    pub fn replace(&mut self, content: &str, content_type: ContentType) {
-        self.start_tag.mutations.replace(content, content_type);
-
+    pub fn on_inner_html(
+        &mut self,
+        handler: impl FnOnce(&mut InnerHtml) -> Result<(), Box<dyn std::error::Error>>
+            + IntoHandler<H::InnerHtmlHandler<'static>>,
+    ) {
         if self.can_have_content {
-            self.remove_content();
-            self.end_tag_mutations_mut().remove();
+            self.inner_html_handlers.push(handler.into_handler());
         }
     }
 
-    /// Removes the element and its inner content.
+    /// Returns the information about the selector the element content handler is invoked for.
+    ///
+    /// Refer to [`MatchInfo`] for more information.
+    ///
+    /// [`MatchInfo`]: struct.MatchInfo.html
     #[inline]
-    pub fn remove(&mut self) {
-        self.start_tag.mutations.remove();
+    pub fn match_info(&self) -> Option<&MatchInfo> {
+        self.match_info.as_ref()
+    }
 
-        if self.can_have_content {
-            self.remove_content();
-            self.end_tag_mutations_mut().remove();
-        }
+    #[inline]
+    pub(crate) fn set_match_info(&mut self, match_info: Option<MatchInfo>) {
+        self.match_info = match_info;
     }
 
-    /// Removes the element, but keeps its content. I.e. remove start and end tags of the element.
+    /// Returns the path of the element in the document, e.g. `html > body > div#main > ul`.
+    ///
+    /// The path consists of the tag names of the element's ancestors and the element itself,
+    /// each followed by the element's `id`, if it has one. The `id`s of the ancestors are
+    /// included only if [`track_element_ids`] is enabled, since otherwise the rewriter doesn't
+    /// parse the attributes of the elements that can't be matched by any of the selectors.
+    /// The path is computed on demand, so it doesn't add any overhead unless called. It's mainly
+    /// useful for logging and debugging, e.g. to report where in the document the handler
+    /// has failed.
+    ///
+    /// Refer to [`HtmlRewriter::current_path`] for the details on how the open elements are
+    /// tracked.
     ///
     /// # Example
     ///
     /// ```
-    /// use lol_html::{rewrite_str, element, RewriteStrSettings};
+    /// use lol_html::{element, HtmlRewriter, Settings};
     ///
-    /// let html = rewrite_str(
-    ///     r#"<div><span><!-- 42 --></span></div>"#,
-    ///     RewriteStrSettings {
-    ///         element_content_handlers: vec![
-    ///             element!("div", |el| {
-    ///                 el.remove_and_keep_content();
+    /// let mut paths = vec![];
+    ///
+    /// {
+    ///     let mut rewriter = HtmlRewriter::try_new(
+    ///         Settings {
+    ///             element_content_handlers: vec![element!("li", |el| {
+    ///                 paths.push(el.current_path());
     ///
     ///                 Ok(())
-    ///             })
-    ///         ],
-    ///         ..RewriteStrSettings::default()
-    ///     }
-    /// ).unwrap();
+    ///             })],
+    ///             track_element_ids: true,
+    ///             ..Settings::default()
+    ///         },
+    ///         |_: &[u8]| {},
+    ///     )
+    ///     .unwrap();
     ///
-    /// assert_eq!(html, r#"<span><!-- 42 --></span>"#);
+    ///     rewriter.write(b"<html><body><div id=main><ul><li id=first>Foo</li>").unwrap();
+    ///     rewriter.end().unwrap();
+    /// }
+    ///
+    /// assert_eq!(paths, ["html > body > div#main > ul > li#first"]);
     /// ```
-    #[inline]
-    pub fn remove_and_keep_content(&mut self) {
-        self.start_tag.mutations.remove();
+    ///
+    /// [`track_element_ids`]: ../struct.Settings.html#structfield.track_element_ids
+    /// [`HtmlRewriter::current_path`]: ../struct.HtmlRewriter.html#method.current_path
+    pub fn current_path(&self) -> String {
+        let mut path = self
+            .ancestors_path
+            .map(ToString::to_string)
+            .unwrap_or_default();
+
+        if !path.is_empty() {
+            path.push_str(" > ");
+        }
 
-        if self.can_have_content {
-            self.end_tag_mutations_mut().remove();
+        path.push_str(&self.tag_name());
+
+        if let Some(id) = self.get_attribute("id") {
+            path.push('#');
+            path.push_str(&id);
         }
+
+        path
     }
 
-    /// Returns `true` if the element has been removed or replaced with some content.
     #[inline]
-    pub fn removed(&self) -> bool {
-        self.start_tag.mutations.removed()
+    pub(crate) fn set_ancestors_path(&mut self, path: &'r dyn Display) {
+        self.ancestors_path = Some(path);
+    }
+
+    /// Returns the location of the start tag of the element in the input.
+    ///
+    /// Refer to [`SourceLocation`] for more information.
+    ///
+    /// [`SourceLocation`]: struct.SourceLocation.html
+    #[inline]
+    pub fn source_location(&self) -> &SourceLocation {
+        self.start_tag.source_location()
+    }
+
+    #[cfg(feature = "selector_matching")]
+    #[inline]
+    pub(crate) fn can_have_content(&self) -> bool {
+        self.can_have_content
     }
 
     #[inline]
@@ -457,31 +1435,68 @@ impl<'r, 't> Element<'r, 't> {
         self.should_remove_content
     }
 
-    pub(crate) fn into_end_tag_handler(self) -> Option<EndTagHandler<'static>> {
-        let end_tag_mutations = self.end_tag_mutations;
-        let modified_end_tag_name = self.modified_end_tag_name;
-
-        if end_tag_mutations.is_some() || modified_end_tag_name.is_some() {
-            Some(Box::new(move |end_tag: &mut EndTag| {
-                if let Some(name) = modified_end_tag_name {
-                    end_tag.set_name(name);
-                }
+    /// Reports the content rejected by the content policies as an error of the content handler
+    /// that has inserted it.
+    #[inline]
+    pub(crate) fn check_content_model(&mut self) -> Result<(), ContentModelError> {
+        match self.content_model_error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
 
-                if let Some(mutations) = end_tag_mutations {
-                    end_tag.mutations = mutations;
-                }
+    #[inline]
+    pub(crate) fn take_inner_html_handlers(&mut self) -> Vec<H::InnerHtmlHandler<'static>> {
+        std::mem::take(&mut self.inner_html_handlers)
+    }
 
-                Ok(())
-            }))
+    pub(crate) fn into_end_tag_handlers(self) -> Option<EndTagHandlers<H>> {
+        if self.end_tag_mutations.is_some()
+            || self.modified_end_tag_name.is_some()
+            || !self.end_tag_handlers.is_empty()
+        {
+            Some(EndTagHandlers {
+                name: self.modified_end_tag_name,
+                mutations: self.end_tag_mutations,
+                handlers: self.end_tag_handlers,
+            })
         } else {
             None
         }
     }
 }
 
-impl_user_data!(Element<'_, '_>);
+/// The modifications of the end tag of an element and the handlers registered for it with
+/// [`Element::on_end_tag`], which are applied once the end tag has been reached.
+///
+/// [`Element::on_end_tag`]: struct.Element.html#method.on_end_tag
+pub(crate) struct EndTagHandlers<H: HandlerTypes> {
+    name: Option<Bytes<'static>>,
+    mutations: Option<Mutations>,
+    handlers: Vec<H::EndTagHandler<'static>>,
+}
+
+impl<H: HandlerTypes> EndTagHandlers<H> {
+    pub fn invoke(self, end_tag: &mut EndTag) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(name) = self.name {
+            end_tag.set_name(name);
+        }
+
+        if let Some(mutations) = self.mutations {
+            end_tag.mutations = mutations;
+        }
+
+        for handler in self.handlers {
+            handler(end_tag)?;
+        }
 
-impl Debug for Element<'_, '_> {
+        Ok(())
+    }
+}
+
+impl_user_data!(Element<'_, '_, H>);
+
+impl<H: HandlerTypes> Debug for Element<'_, '_, H> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Element")
             .field("tag_name", &self.tag_name())
@@ -496,7 +1511,10 @@ mod tests {
     use crate::html_content::*;
     use crate::rewritable_units::test_utils::*;
     use crate::*;
-    use encoding_rs::{Encoding, EUC_JP, UTF_8};
+    use encoding_rs::{Encoding, UTF_8};
+
+    #[cfg(feature = "all_encodings")]
+    use encoding_rs::EUC_JP;
 
     fn rewrite_element(
         html: &[u8],
@@ -551,6 +1569,7 @@ mod tests {
         });
     }
 
+    #[cfg(feature = "all_encodings")]
     #[test]
     fn encoding_unmappable_chars_in_tag_name() {
         rewrite_element(b"<div>", EUC_JP, "div", |el| {
@@ -591,6 +1610,41 @@ mod tests {
         rewrite_element(b"<math><script></script></math>", UTF_8, "script", |el| {
             assert_eq!(el.namespace_uri(), "http://www.w3.org/1998/Math/MathML");
         });
+
+        // NOTE: the content of an integration point is in the HTML namespace, but the
+        // element itself is not.
+        rewrite_element(b"<svg><title></title></svg>", UTF_8, "title", |el| {
+            assert_eq!(el.namespace_uri(), "http://www.w3.org/2000/svg");
+        });
+
+        rewrite_element(b"<math><mi></mi></math>", UTF_8, "mi", |el| {
+            assert_eq!(el.namespace_uri(), "http://www.w3.org/1998/Math/MathML");
+        });
+    }
+
+    #[test]
+    fn content_text_type() {
+        rewrite_element(b"<textarea></textarea>", UTF_8, "textarea", |el| {
+            assert_eq!(el.content_text_type(), TextType::RCData);
+        });
+
+        rewrite_element(b"<noscript></noscript>", UTF_8, "noscript", |el| {
+            assert_eq!(el.content_text_type(), TextType::RawText);
+        });
+
+        rewrite_element(b"<plaintext>", UTF_8, "plaintext", |el| {
+            assert_eq!(el.content_text_type(), TextType::PlainText);
+        });
+
+        rewrite_element(b"<svg><script></script></svg>", UTF_8, "script", |el| {
+            assert_eq!(el.content_text_type(), TextType::Data);
+        });
+
+        rewrite_element(b"<script></script>", UTF_8, "script", |el| {
+            el.set_tag_name("div").unwrap();
+
+            assert_eq!(el.content_text_type(), TextType::ScriptData);
+        });
     }
 
     #[test]
@@ -613,6 +1667,7 @@ mod tests {
         });
     }
 
+    #[cfg(feature = "all_encodings")]
     #[test]
     fn encoding_unmappable_character_in_attr_name() {
         rewrite_element(b"<div>", EUC_JP, "div", |el| {
@@ -672,6 +1727,16 @@ mod tests {
         }
     }
 
+    #[test]
+    fn attributes_are_send() {
+        fn assert_send<T: Send>() {}
+
+        // NOTE: the attributes borrow the outlines of the parsed start tag instead of sharing
+        // the parser's attribute buffer through `Rc`, so they can be sent to other threads.
+        assert_send::<crate::rewritable_units::tokens::Attributes>();
+        assert_send::<Attribute>();
+    }
+
     #[test]
     fn get_attrs() {
         for (html, enc) in encoded("<Foo Fooα1=Barβ1 Fooγ2=Barδ2>") {
@@ -767,6 +1832,192 @@ mod tests {
         }
     }
 
+    #[test]
+    fn modified_attrs() {
+        rewrite_element(b"<div foo=1 bar=2>", UTF_8, "div", |el| {
+            assert!(!el.is_attribute_modified("foo"));
+
+            el.set_attribute("FOO", "3").unwrap();
+            el.remove_attribute("baz");
+            el.remove_attribute("Bar");
+
+            assert!(el.is_attribute_modified("foo"));
+            assert!(el.is_attribute_modified("bar"));
+            assert!(!el.is_attribute_modified("baz"));
+        });
+    }
+
+    #[test]
+    fn retain_attrs() {
+        for (html, enc) in encoded("<Foo Fooα1=Barβ1 Fooγ2=Barδ2 Fooα3=Barβ3>") {
+            let output = rewrite_element(&html, enc, "foo", |el| {
+                el.retain_attributes(|name, value| name == "fooγ2" || value == "Barβ3");
+
+                assert_eq!(el.attributes().len(), 2, "Encoding: {}", enc.name());
+                assert!(
+                    el.is_attribute_modified("fooα1"),
+                    "Encoding: {}",
+                    enc.name()
+                );
+            });
+
+            assert_eq!(output, "<Foo Fooγ2=Barδ2 Fooα3=Barβ3>");
+        }
+    }
+
+    #[test]
+    fn mutable_attrs() {
+        for (html, enc) in encoded("<Foo Fooα1=Barβ1 Fooγ2=Barδ2>") {
+            let output = rewrite_element(&html, enc, "foo", |el| {
+                for attr in el.attributes_mut() {
+                    if attr.name() == "fooγ2" {
+                        attr.set_value("Bazδ3");
+                    }
+                }
+
+                assert!(
+                    el.is_attribute_modified("Fooγ2"),
+                    "Encoding: {}",
+                    enc.name()
+                );
+                assert!(
+                    !el.is_attribute_modified("Fooα1"),
+                    "Encoding: {}",
+                    enc.name()
+                );
+                assert_eq!(el.start_tag_raw_bytes(), None, "Encoding: {}", enc.name());
+
+                assert_eq!(
+                    el.get_attribute("fooγ2").unwrap(),
+                    "Bazδ3",
+                    "Encoding: {}",
+                    enc.name()
+                );
+            });
+
+            assert_eq!(output, r#"<Foo Fooα1=Barβ1 Fooγ2="Bazδ3">"#);
+        }
+    }
+
+    #[test]
+    fn retain_mutable_attrs() {
+        for (html, enc) in encoded("<Foo Fooα1=Barβ1 Fooγ2=Barδ2 Fooα3=Barβ3>") {
+            let output = rewrite_element(&html, enc, "foo", |el| {
+                el.retain_attributes_mut(|attr| match attr.name().as_str() {
+                    "fooα1" => {
+                        attr.set_name("Bazα1").unwrap();
+
+                        true
+                    }
+                    "fooγ2" => false,
+                    _ => {
+                        assert_eq!(
+                            attr.set_name("baz=").unwrap_err(),
+                            AttributeNameError::ForbiddenCharacter('=')
+                        );
+
+                        attr.set_value("Bazβ3");
+
+                        true
+                    }
+                });
+
+                assert!(
+                    el.is_attribute_modified("fooα1"),
+                    "Encoding: {}",
+                    enc.name()
+                );
+                assert!(
+                    el.is_attribute_modified("bazα1"),
+                    "Encoding: {}",
+                    enc.name()
+                );
+                assert!(
+                    el.is_attribute_modified("fooγ2"),
+                    "Encoding: {}",
+                    enc.name()
+                );
+                assert!(
+                    el.is_attribute_modified("fooα3"),
+                    "Encoding: {}",
+                    enc.name()
+                );
+                assert_eq!(el.get_attribute("fooα1"), None, "Encoding: {}", enc.name());
+
+                assert_eq!(
+                    el.get_attribute("bazα1").unwrap(),
+                    "Barβ1",
+                    "Encoding: {}",
+                    enc.name()
+                );
+            });
+
+            assert_eq!(output, r#"<Foo bazα1="Barβ1" Fooα3="Bazβ3">"#);
+        }
+    }
+
+    #[test]
+    fn patch_start_tag() {
+        for (html, enc) in encoded("<div><spanǼ foo=bar>Hi</spanǼ></div>") {
+            let output = rewrite_element(&html, enc, "spanǼ", |el| {
+                let raw = el.start_tag_raw_bytes().unwrap().to_vec();
+
+                assert_eq!(raw, html[5..raw.len() + 5].to_vec());
+
+                el.patch_start_tag(raw.len() - 1..raw.len(), b" baz>")
+                    .unwrap();
+
+                assert_eq!(
+                    el.patch_start_tag(0..raw.len() + 5, b""),
+                    Err(StartTagPatchError::OutOfBounds)
+                );
+
+                el.patch_start_tag(1..1, b"x-").unwrap();
+            });
+
+            assert_eq!(
+                output,
+                "<div><x-spanǼ foo=bar baz>Hi</spanǼ></div>",
+                "Encoding: {}",
+                enc.name()
+            );
+        }
+    }
+
+    #[test]
+    fn patch_modified_start_tag() {
+        rewrite_element(b"<div foo>", UTF_8, "div", |el| {
+            el.set_attribute("foo", "bar").unwrap();
+
+            assert_eq!(el.start_tag_raw_bytes(), None);
+
+            assert_eq!(
+                el.patch_start_tag(0..0, b""),
+                Err(StartTagPatchError::Modified)
+            );
+        });
+    }
+
+    #[test]
+    fn raw_bytes_of_modified_tags() {
+        let output = rewrite_element(b"<DIV foo>Hi</DIV >", UTF_8, "div", |el| {
+            el.set_attribute("foo", "bar").unwrap();
+            el.set_tag_name("span").unwrap();
+
+            assert_eq!(el.raw(), b"<DIV foo>");
+
+            el.on_end_tag(|end| {
+                assert_eq!(end.raw(), b"</DIV >");
+
+                end.after("!", ContentType::Text);
+
+                Ok(())
+            });
+        });
+
+        assert_eq!(output, r#"<span foo="bar">Hi</span>!"#);
+    }
+
     #[test]
     fn insert_content_before() {
         for (html, enc) in encoded("<div><span>ĥi</span></div>") {
@@ -815,6 +2066,84 @@ mod tests {
         }
     }
 
+    #[test]
+    fn streaming_content() {
+        for (html, enc) in encoded("<div><span>Ĥi<inner-remove-me>ŵ</inner-remove-me></span>") {
+            let output = rewrite_element(&html, enc, "span", |el| {
+                el.before("<b>", ContentType::Html);
+                el.streaming_before(streaming!(|sink| {
+                    sink.write_str("<imgĤ>", ContentType::Text);
+
+                    Ok(())
+                }));
+                el.streaming_after(streaming!(|sink| {
+                    sink.write_str("<after>", ContentType::Html);
+
+                    Ok(())
+                }));
+                el.after("<i>", ContentType::Html);
+                el.streaming_set_inner_content(streaming!(|sink| {
+                    sink.write_str("ŵ", ContentType::Text);
+                    sink.write_str("<br>", ContentType::Html);
+
+                    Ok(())
+                }));
+                el.streaming_prepend(streaming!(|sink| {
+                    sink.write_str("[prepend]", ContentType::Html);
+
+                    Ok(())
+                }));
+                el.streaming_append(streaming!(|sink| {
+                    sink.write_str("[append]", ContentType::Html);
+
+                    Ok(())
+                }));
+            });
+
+            assert_eq!(
+                output,
+                concat!(
+                    "<div><b>&lt;imgĤ&gt;<span>[prepend]ŵ<br>[append]</span>",
+                    "<i><after>"
+                )
+            );
+
+            let output = rewrite_element(&html, enc, "span", |el| {
+                el.streaming_replace(streaming!(|sink| {
+                    sink.write_str("<imgĤ>", ContentType::Html);
+
+                    Ok(())
+                }));
+            });
+
+            assert_eq!(output, "<div><imgĤ>");
+        }
+    }
+
+    #[test]
+    fn end_tag_handlers() {
+        for (html, enc) in encoded("<div><span>ĥi</span><p>1<p>2</p><img></div>") {
+            let output = rewrite_element(&html, enc, "span, p, img", |el| {
+                el.append("[append]", ContentType::Text);
+
+                el.on_end_tag(|end| {
+                    end.before(&format!("[{}]", end.name()), ContentType::Text);
+                    end.after("<!--after-->", ContentType::Html);
+
+                    Ok(())
+                });
+            });
+
+            assert_eq!(
+                output,
+                concat!(
+                    "<div><span>ĥi[append][span]</span><!--after-->",
+                    "<p>1[append][]<!--after--><p>2[append][p]</p><!--after--><img></div>"
+                )
+            );
+        }
+    }
+
     #[test]
     fn set_content_after() {
         for (html, enc) in
@@ -0,0 +1,79 @@
+use super::mutations::content_to_bytes;
+use super::ContentType;
+use crate::base::encoding::decode_without_bom_handling;
+use encoding_rs::Encoding;
+use std::fmt::{self, Debug};
+
+/// The buffered inner HTML of an element.
+///
+/// The inner HTML is provided to the handlers registered with [`Element::on_inner_html`] once
+/// the end tag of the element has been reached. It's the serialized content of the element, i.e.
+/// with all the modifications made to the content by the other handlers.
+///
+/// [`Element::on_inner_html`]: struct.Element.html#method.on_inner_html
+pub struct InnerHtml {
+    bytes: Vec<u8>,
+    html: String,
+    replacement: Option<Vec<u8>>,
+    encoding: &'static Encoding,
+}
+
+impl InnerHtml {
+    #[inline]
+    pub(crate) fn new(bytes: Vec<u8>, encoding: &'static Encoding) -> Self {
+        let html = decode_without_bom_handling(&bytes, encoding).into_owned();
+
+        InnerHtml {
+            bytes,
+            html,
+            replacement: None,
+            encoding,
+        }
+    }
+
+    /// Returns the buffered inner HTML of the element.
+    ///
+    /// The returned value doesn't reflect the content set with [`set_inner_content`].
+    ///
+    /// [`set_inner_content`]: #method.set_inner_content
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        &self.html
+    }
+
+    /// Replaces the inner content of the element with the `content`.
+    ///
+    /// Consequent calls to the method overwrite the previously set content.
+    #[inline]
+    pub fn set_inner_content(&mut self, content: &str, content_type: ContentType) {
+        let mut replacement = Vec::default();
+
+        content_to_bytes(content, content_type, self.encoding, &mut |c| {
+            replacement.extend_from_slice(c);
+        });
+
+        self.replacement = Some(replacement);
+    }
+
+    /// Returns `true` if the inner content of the element has been replaced.
+    #[inline]
+    pub fn modified(&self) -> bool {
+        self.replacement.is_some()
+    }
+
+    /// Returns the inner content of the element that is written to the output. The buffered
+    /// bytes are returned as is, unless the content has been replaced.
+    #[inline]
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        self.replacement.unwrap_or(self.bytes)
+    }
+}
+
+impl Debug for InnerHtml {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("InnerHtml")
+            .field("html", &self.html)
+            .field("modified", &self.modified())
+            .finish()
+    }
+}
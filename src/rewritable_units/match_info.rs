@@ -0,0 +1,91 @@
+use std::sync::Arc;
+
+/// The selector that has invoked an element content handler.
+///
+/// The same handler function can be registered for several selectors. The information about the
+/// selector is available to the handler through the `match_info` method of the [`Element`],
+/// [`TextChunk`] or [`Comment`] it is invoked for, so the handler can tell which of the
+/// selectors it is invoked for.
+///
+/// For the selector lists (e.g. `a, area`) the matched alternative of the list is reported.
+/// If several alternatives of the same list match an element, the handler is still invoked
+/// once for it and only one of the alternatives is reported.
+///
+/// # Example
+/// ```
+/// use lol_html::{rewrite_str, ElementContentHandlers, RewriteStrSettings, Selector};
+/// use lol_html::html_content::Element;
+///
+/// let links: Selector = "a[href], area[href]".parse().unwrap();
+/// let images: Selector = "img[src]".parse().unwrap();
+///
+/// let mark = |el: &mut Element| {
+///     let selector = el.match_info().unwrap().selector().to_owned();
+///
+///     el.set_attribute("data-matched", &selector)?;
+///
+///     Ok(())
+/// };
+///
+/// let html = rewrite_str(
+///     r#"<a href="/"><img src="a.png"></a><area href="/">"#,
+///     RewriteStrSettings {
+///         element_content_handlers: vec![
+///             (&links, ElementContentHandlers::default().element(mark)),
+///             (&images, ElementContentHandlers::default().element(mark)),
+///         ],
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(
+///     html,
+///     concat!(
+///         r#"<a href="/" data-matched="a[href]"><img src="a.png" data-matched="img[src]"></a>"#,
+///         r#"<area href="/" data-matched="area[href]">"#
+///     )
+/// );
+/// ```
+///
+/// [`Element`]: struct.Element.html#method.match_info
+/// [`TextChunk`]: struct.TextChunk.html#method.match_info
+/// [`Comment`]: struct.Comment.html#method.match_info
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchInfo {
+    selector_index: usize,
+    alternative_index: usize,
+    selector: Arc<str>,
+}
+
+impl MatchInfo {
+    #[inline]
+    pub(crate) fn new(selector_index: usize, alternative_index: usize, selector: Arc<str>) -> Self {
+        MatchInfo {
+            selector_index,
+            alternative_index,
+            selector,
+        }
+    }
+
+    /// Returns the index of the selector in the [`element_content_handlers`] of the rewriter.
+    ///
+    /// [`element_content_handlers`]: ../struct.Settings.html#structfield.element_content_handlers
+    #[inline]
+    pub fn selector_index(&self) -> usize {
+        self.selector_index
+    }
+
+    /// Returns the index of the matched alternative of the selector list, e.g. `1` for
+    /// the `area` matched by `a, area`. It's always `0` for the selectors that aren't lists.
+    #[inline]
+    pub fn alternative_index(&self) -> usize {
+        self.alternative_index
+    }
+
+    /// Returns the source of the matched alternative of the selector list, or the source of
+    /// the whole selector if it isn't a list.
+    #[inline]
+    pub fn selector(&self) -> &str {
+        &self.selector
+    }
+}
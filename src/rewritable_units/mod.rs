@@ -1,10 +1,23 @@
 use std::any::Any;
 
+pub use self::aria::*;
+pub use self::class_list::{ClassList, ClassNameError};
+pub use self::content_model::ContentModelError;
+pub use self::dataset::{Dataset, DatasetKeyError};
 pub use self::document_end::*;
 pub use self::element::*;
-pub use self::mutations::{ContentType, Mutations};
+pub use self::inner_html::InnerHtml;
+pub use self::match_info::MatchInfo;
+pub use self::mutations::{ContentType, Mutations, StreamingHandler, StreamingHandlerSink};
+pub use self::script_content::{ScriptContentError, ScriptType};
+pub use self::source_location::SourceLocation;
+pub use self::srcset::SrcsetCandidate;
 pub use self::tokens::*;
 
+pub(crate) use self::content_model::ContentPolicies;
+pub(crate) use self::mutations::InsertedContent;
+pub(crate) use self::source_location::SourceLocator;
+
 /// Data that can be attached to a rewritable unit by a user and shared between content handler
 /// invocations.
 ///
@@ -76,13 +89,43 @@ macro_rules! impl_user_data {
             }
         }
     };
+
+    ($Unit:ident<$($lt:lifetime),+, H>) => {
+        impl<H: crate::rewriter::HandlerTypes> crate::rewritable_units::UserData
+            for $Unit<$($lt),+, H>
+        {
+            #[inline]
+            fn user_data(&self) -> &dyn Any {
+                &*self.user_data
+            }
+
+            #[inline]
+            fn user_data_mut(&mut self) -> &mut dyn Any {
+                &mut *self.user_data
+            }
+
+            #[inline]
+            fn set_user_data(&mut self, data: impl Any){
+                self.user_data = Box::new(data);
+            }
+        }
+    };
 }
 
 #[macro_use]
 mod mutations;
 
+mod aria;
+mod class_list;
+mod content_model;
+mod dataset;
 mod document_end;
 mod element;
+mod inner_html;
+mod match_info;
+mod script_content;
+mod source_location;
+pub mod srcset;
 mod tokens;
 
 #[cfg(test)]
@@ -113,11 +156,11 @@ mod test_utils {
             .collect()
     }
 
-    pub fn rewrite_html(
+    pub fn rewrite_html<'h>(
         html: &[u8],
         encoding: &'static Encoding,
-        element_content_handlers: Vec<(&Selector, ElementContentHandlers)>,
-        document_content_handlers: Vec<DocumentContentHandlers>,
+        element_content_handlers: Vec<(&Selector, ElementContentHandlers<'h>)>,
+        document_content_handlers: Vec<DocumentContentHandlers<'h>>,
     ) -> String {
         let mut output = Output::new(encoding);
 
@@ -1,5 +1,6 @@
 use crate::base::Bytes;
 use encoding_rs::Encoding;
+use std::error::Error;
 
 /// The type of inserted content.
 pub enum ContentType {
@@ -32,10 +33,173 @@ pub(super) fn content_to_bytes(
     }
 }
 
+/// A handler that writes the inserted content to the output in chunks, once the rewritable
+/// unit it has been inserted into is serialized.
+///
+/// Streaming handlers allow to insert large generated fragments without building them in
+/// memory upfront. The trait is implemented for [`FnOnce`] closures, which can be converted
+/// to a boxed handler with the [`streaming`] macro.
+///
+/// The handlers are required to be `Send`, since the handlers inserted around the end tag of an
+/// element are held by the rewriter until the end tag is reached, and the rewriter can be sent
+/// to another thread in the meantime.
+///
+/// # Example
+/// ```
+/// use lol_html::{element, rewrite_str, streaming, RewriteStrSettings};
+/// use lol_html::html_content::ContentType;
+///
+/// let html = rewrite_str(
+///     r#"<ul id="recommendations"></ul>"#,
+///     RewriteStrSettings {
+///         element_content_handlers: vec![
+///             element!("#recommendations", |el| {
+///                 el.streaming_append(streaming!(|sink| {
+///                     for item in &["Foo", "Bar"] {
+///                         sink.write_str("<li>", ContentType::Html);
+///                         sink.write_str(item, ContentType::Text);
+///                         sink.write_str("</li>", ContentType::Html);
+///                     }
+///
+///                     Ok(())
+///                 }));
+///
+///                 Ok(())
+///             })
+///         ],
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(html, r#"<ul id="recommendations"><li>Foo</li><li>Bar</li></ul>"#);
+/// ```
+///
+/// [`FnOnce`]: https://doc.rust-lang.org/std/ops/trait.FnOnce.html
+/// [`streaming`]: ../macro.streaming.html
+pub trait StreamingHandler: Send {
+    /// Writes the content to the `sink`.
+    ///
+    /// An error returned by the handler stops the rewriting, as for any other content handler.
+    fn write_all(self: Box<Self>, sink: &mut StreamingHandlerSink) -> Result<(), Box<dyn Error>>;
+}
+
+impl<F> StreamingHandler for F
+where
+    F: FnOnce(&mut StreamingHandlerSink) -> Result<(), Box<dyn Error>> + Send,
+{
+    #[inline]
+    fn write_all(self: Box<Self>, sink: &mut StreamingHandlerSink) -> Result<(), Box<dyn Error>> {
+        self(sink)
+    }
+}
+
+/// The sink that the [`StreamingHandler`] writes the content to.
+///
+/// [`StreamingHandler`]: trait.StreamingHandler.html
+pub struct StreamingHandlerSink<'a> {
+    encoding: &'static Encoding,
+    output_handler: &'a mut dyn FnMut(&[u8]),
+}
+
+impl<'a> StreamingHandlerSink<'a> {
+    #[inline]
+    pub(crate) fn new(
+        encoding: &'static Encoding,
+        output_handler: &'a mut dyn FnMut(&[u8]),
+    ) -> Self {
+        StreamingHandlerSink {
+            encoding,
+            output_handler,
+        }
+    }
+
+    #[cfg(feature = "futures")]
+    #[inline]
+    pub(crate) fn encoding(&self) -> &'static Encoding {
+        self.encoding
+    }
+
+    /// Writes the `content` to the output, HTML-escaping it if the `content_type` is
+    /// [`ContentType::Text`].
+    ///
+    /// [`ContentType::Text`]: enum.ContentType.html#variant.Text
+    #[inline]
+    pub fn write_str(&mut self, content: &str, content_type: ContentType) {
+        content_to_bytes(content, content_type, self.encoding, self.output_handler);
+    }
+}
+
+enum ContentChunk {
+    Bytes(Vec<u8>),
+    Stream(Box<dyn StreamingHandler>),
+}
+
+/// The content inserted into the output around or in place of a rewritable unit.
+#[derive(Default)]
+pub struct InsertedContent {
+    chunks: Vec<ContentChunk>,
+}
+
+impl InsertedContent {
+    #[inline]
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+    }
+
+    #[inline]
+    pub fn push_bytes(&mut self, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+
+        match self.chunks.last_mut() {
+            Some(ContentChunk::Bytes(last)) => last.extend_from_slice(bytes),
+            _ => self.chunks.push(ContentChunk::Bytes(bytes.to_vec())),
+        }
+    }
+
+    #[inline]
+    pub fn push_stream(&mut self, handler: Box<dyn StreamingHandler>) {
+        self.chunks.push(ContentChunk::Stream(handler));
+    }
+
+    /// Inserts the `content` after the existing content.
+    #[inline]
+    pub fn append(&mut self, mut content: InsertedContent) {
+        self.chunks.append(&mut content.chunks);
+    }
+
+    /// Inserts the `content` before the existing content.
+    #[inline]
+    pub fn prepend(&mut self, mut content: InsertedContent) {
+        content.chunks.append(&mut self.chunks);
+        self.chunks = content.chunks;
+    }
+
+    /// Writes the content to the `output_handler`, invoking the streaming handlers. The content
+    /// is consumed, as the streaming handlers can be invoked only once.
+    pub fn write(
+        &mut self,
+        encoding: &'static Encoding,
+        output_handler: &mut dyn FnMut(&[u8]),
+    ) -> Result<(), Box<dyn Error>> {
+        for chunk in self.chunks.drain(..) {
+            match chunk {
+                ContentChunk::Bytes(bytes) => output_handler(&bytes),
+                ContentChunk::Stream(handler) => {
+                    handler.write_all(&mut StreamingHandlerSink::new(encoding, output_handler))?
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
 pub struct Mutations {
-    pub content_before: Vec<u8>,
-    pub replacement: Vec<u8>,
-    pub content_after: Vec<u8>,
+    pub content_before: InsertedContent,
+    pub replacement: InsertedContent,
+    pub content_after: InsertedContent,
     pub removed: bool,
     encoding: &'static Encoding,
 }
@@ -44,44 +208,81 @@ impl Mutations {
     #[inline]
     pub fn new(encoding: &'static Encoding) -> Self {
         Mutations {
-            content_before: Vec::default(),
-            replacement: Vec::default(),
-            content_after: Vec::default(),
+            content_before: InsertedContent::default(),
+            replacement: InsertedContent::default(),
+            content_after: InsertedContent::default(),
             removed: false,
             encoding,
         }
     }
 
+    #[inline]
+    pub fn encoding(&self) -> &'static Encoding {
+        self.encoding
+    }
+
     #[inline]
     pub fn before(&mut self, content: &str, content_type: ContentType) {
         content_to_bytes(content, content_type, self.encoding, &mut |c| {
-            self.content_before.extend_from_slice(c);
+            self.content_before.push_bytes(c);
         });
     }
 
     #[inline]
     pub fn after(&mut self, content: &str, content_type: ContentType) {
-        let mut pos = 0;
+        let mut inserted = InsertedContent::default();
 
         content_to_bytes(content, content_type, self.encoding, &mut |c| {
-            self.content_after.splice(pos..pos, c.iter().cloned());
-
-            pos += c.len();
+            inserted.push_bytes(c);
         });
+
+        self.content_after.prepend(inserted);
     }
 
     #[inline]
     pub fn replace(&mut self, content: &str, content_type: ContentType) {
-        let mut replacement = Vec::default();
+        let mut replacement = InsertedContent::default();
 
         content_to_bytes(content, content_type, self.encoding, &mut |c| {
-            replacement.extend_from_slice(c);
+            replacement.push_bytes(c);
         });
 
         self.replacement = replacement;
         self.remove();
     }
 
+    #[inline]
+    pub fn streaming_before(&mut self, handler: Box<dyn StreamingHandler>) {
+        self.content_before.push_stream(handler);
+    }
+
+    #[inline]
+    pub fn streaming_after(&mut self, handler: Box<dyn StreamingHandler>) {
+        let mut inserted = InsertedContent::default();
+
+        inserted.push_stream(handler);
+        self.content_after.prepend(inserted);
+    }
+
+    #[inline]
+    pub fn streaming_replace(&mut self, handler: Box<dyn StreamingHandler>) {
+        self.replacement.clear();
+        self.replacement.push_stream(handler);
+        self.remove();
+    }
+
+    /// Appends the raw `bytes` to the replacement, starting a new replacement if the content
+    /// hasn't been replaced or removed yet.
+    #[inline]
+    pub fn emit_raw(&mut self, bytes: &[u8]) {
+        if !self.removed {
+            self.replacement.clear();
+            self.remove();
+        }
+
+        self.replacement.push_bytes(bytes);
+    }
+
     #[inline]
     pub fn remove(&mut self) {
         self.removed = true;
@@ -0,0 +1,213 @@
+use std::borrow::Cow;
+use thiserror::Error;
+
+// NOTE: the JavaScript MIME type essence strings from the MIME Sniffing specification.
+const JAVASCRIPT_MIME_TYPES: &[&str] = &[
+    "application/ecmascript",
+    "application/javascript",
+    "application/x-ecmascript",
+    "application/x-javascript",
+    "text/ecmascript",
+    "text/javascript",
+    "text/javascript1.0",
+    "text/javascript1.1",
+    "text/javascript1.2",
+    "text/javascript1.3",
+    "text/javascript1.4",
+    "text/javascript1.5",
+    "text/jscript",
+    "text/livescript",
+    "text/x-ecmascript",
+    "text/x-javascript",
+];
+
+/// An error that occurs when the content inserted into a `<script>` element contains
+/// a sequence that would break out of the element and the sequence can't be escaped.
+///
+/// Refer to [`ScriptType::make_safe`] for more information.
+///
+/// [`ScriptType::make_safe`]: ../html_content/enum.ScriptType.html#method.make_safe
+#[derive(Error, Debug, Eq, PartialEq, Copy, Clone)]
+pub enum ScriptContentError {
+    /// The content contains the `</script` sequence, which would close the `<script>` element.
+    #[error("The script content contains `</script`, which would close the `<script>` element.")]
+    EndTag,
+
+    /// The content contains the `<!--` sequence, which would make the HTML parser ignore the
+    /// `</script>` end tag of the element if the content also contains `<script`.
+    #[error("The script content contains `<!--`, which can prevent the `<script>` element from closing.")]
+    CommentStart,
+}
+
+/// The type of the script in a `<script>` element.
+///
+/// The type determines how the content inserted into the element is made safe with
+/// [`make_safe`] or [`TextChunk::replace_script_safe`].
+///
+/// [`make_safe`]: #method.make_safe
+/// [`TextChunk::replace_script_safe`]: struct.TextChunk.html#method.replace_script_safe
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum ScriptType {
+    /// A classic script, e.g. `<script>` or `<script type="text/javascript">`.
+    Classic,
+    /// A module script, `<script type="module">`.
+    Module,
+    /// A JSON data block, e.g. `<script type="application/json">`,
+    /// `<script type="application/ld+json">` or `<script type="importmap">`.
+    Json,
+}
+
+impl ScriptType {
+    /// Returns the type of the script for the value of the `type` attribute of the `<script>`
+    /// element, or `None` if the element contains a data block of an unknown type (e.g.
+    /// `<script type="text/template">`).
+    ///
+    /// # Example
+    /// ```
+    /// use lol_html::html_content::ScriptType;
+    ///
+    /// assert_eq!(ScriptType::from_type_attribute(None), Some(ScriptType::Classic));
+    /// assert_eq!(ScriptType::from_type_attribute(Some("module")), Some(ScriptType::Module));
+    /// assert_eq!(
+    ///     ScriptType::from_type_attribute(Some("application/ld+json")),
+    ///     Some(ScriptType::Json)
+    /// );
+    /// assert_eq!(ScriptType::from_type_attribute(Some("text/template")), None);
+    /// ```
+    pub fn from_type_attribute(value: Option<&str>) -> Option<Self> {
+        let value = match value {
+            Some(value) => value.trim(),
+            None => return Some(ScriptType::Classic),
+        };
+
+        let essence = value.split(';').next().unwrap_or("").trim();
+
+        if value.is_empty()
+            || JAVASCRIPT_MIME_TYPES
+                .iter()
+                .any(|t| t.eq_ignore_ascii_case(essence))
+        {
+            Some(ScriptType::Classic)
+        } else if value.eq_ignore_ascii_case("module") {
+            Some(ScriptType::Module)
+        } else if value.eq_ignore_ascii_case("importmap")
+            || value.eq_ignore_ascii_case("speculationrules")
+            || essence.to_ascii_lowercase().ends_with("/json")
+            || essence.to_ascii_lowercase().ends_with("+json")
+        {
+            Some(ScriptType::Json)
+        } else {
+            None
+        }
+    }
+
+    /// Makes the `content` safe for the insertion into the `<script>` element of this type.
+    ///
+    /// The HTML parser closes the `<script>` element on the first `</script` sequence in it,
+    /// regardless of the script syntax. Also, once the `<!--` sequence is encountered, the
+    /// `<script` sequence that follows it makes the parser ignore the next `</script>` end tag.
+    /// So, the content with such sequences can break out of the element.
+    ///
+    /// `<` is a valid character only in the strings of JSON, so all of its occurrences are
+    /// escaped as `\u003c` for [`Json`] data blocks. The sequences can't be escaped in
+    /// [`Classic`] and [`Module`] scripts without parsing the script, so the content with
+    /// the `</script` or `<!--` sequences is rejected for them.
+    ///
+    /// # Example
+    /// ```
+    /// use lol_html::errors::ScriptContentError;
+    /// use lol_html::html_content::ScriptType;
+    ///
+    /// assert_eq!(
+    ///     ScriptType::Json.make_safe(r#"{"html":"</script>"}"#).unwrap(),
+    ///     r#"{"html":"\u003c/script>"}"#
+    /// );
+    ///
+    /// assert_eq!(
+    ///     ScriptType::Classic.make_safe(r#"alert("</SCRIPT>")"#).unwrap_err(),
+    ///     ScriptContentError::EndTag
+    /// );
+    /// ```
+    ///
+    /// [`Json`]: #variant.Json
+    /// [`Classic`]: #variant.Classic
+    /// [`Module`]: #variant.Module
+    pub fn make_safe(self, content: &str) -> Result<Cow<str>, ScriptContentError> {
+        match self {
+            ScriptType::Json if content.contains('<') => Ok(content.replace('<', "\\u003c").into()),
+            ScriptType::Json => Ok(content.into()),
+            ScriptType::Classic | ScriptType::Module => {
+                let lowercased = content.to_ascii_lowercase();
+
+                if lowercased.contains("</script") {
+                    Err(ScriptContentError::EndTag)
+                } else if lowercased.contains("<!--") {
+                    Err(ScriptContentError::CommentStart)
+                } else {
+                    Ok(content.into())
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_type_attribute() {
+        for &(value, expected) in &[
+            (None, Some(ScriptType::Classic)),
+            (Some(""), Some(ScriptType::Classic)),
+            (Some(" text/JavaScript "), Some(ScriptType::Classic)),
+            (
+                Some("application/javascript; charset=utf-8"),
+                Some(ScriptType::Classic),
+            ),
+            (Some("Module"), Some(ScriptType::Module)),
+            (Some("application/json"), Some(ScriptType::Json)),
+            (Some("application/ld+json"), Some(ScriptType::Json)),
+            (Some("importmap"), Some(ScriptType::Json)),
+            (Some("speculationrules"), Some(ScriptType::Json)),
+            (Some("text/template"), None),
+            (Some("text/babel"), None),
+        ] {
+            assert_eq!(
+                ScriptType::from_type_attribute(value),
+                expected,
+                "Value: {:?}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn make_safe() {
+        assert_eq!(
+            ScriptType::Json
+                .make_safe(r#"["<!--", "<script>"]"#)
+                .unwrap(),
+            r#"["\u003c!--", "\u003cscript>"]"#
+        );
+
+        assert!(matches!(
+            ScriptType::Json.make_safe("[1, 2]").unwrap(),
+            Cow::Borrowed("[1, 2]")
+        ));
+
+        for &script_type in &[ScriptType::Classic, ScriptType::Module] {
+            assert_eq!(script_type.make_safe("a < b").unwrap(), "a < b");
+
+            assert_eq!(
+                script_type.make_safe("'</Script >'").unwrap_err(),
+                ScriptContentError::EndTag
+            );
+
+            assert_eq!(
+                script_type.make_safe("'<!--<script>'").unwrap_err(),
+                ScriptContentError::CommentStart
+            );
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use crate::base::Range;
+use memchr::{memchr_iter, memrchr};
+use std::ops;
+
+/// The location of a rewritable unit in the input of the rewriter.
+///
+/// Byte offsets are counted from the start of the document, i.e. from the first byte written to
+/// the rewriter or the first byte written after [`start_new_document`]. Lines and columns start
+/// from 1, columns are counted in bytes.
+///
+/// # Example
+/// ```
+/// use lol_html::{element, rewrite_str, RewriteStrSettings};
+///
+/// rewrite_str(
+///     "<html>\n  <body></body>\n</html>",
+///     RewriteStrSettings {
+///         element_content_handlers: vec![
+///             element!("body", |el| {
+///                 let location = el.source_location();
+///
+///                 assert_eq!(location.bytes(), 9..15);
+///                 assert_eq!(location.line(), 2);
+///                 assert_eq!(location.column(), 3);
+///
+///                 Ok(())
+///             })
+///         ],
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+/// ```
+///
+/// [`start_new_document`]: ../struct.HtmlRewriter.html#method.start_new_document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    bytes: ops::Range<usize>,
+    line: usize,
+    column: usize,
+}
+
+impl SourceLocation {
+    #[inline]
+    pub(crate) fn new(bytes: ops::Range<usize>, line: usize, column: usize) -> Self {
+        SourceLocation {
+            bytes,
+            line,
+            column,
+        }
+    }
+
+    /// Returns the range of bytes of the unit in the input.
+    #[inline]
+    pub fn bytes(&self) -> ops::Range<usize> {
+        self.bytes.clone()
+    }
+
+    /// Returns the line the unit starts on.
+    #[inline]
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Returns the column the unit starts at.
+    #[inline]
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    /// Returns the location that starts at the start of this location and ends at the end of
+    /// the `other` one.
+    #[inline]
+    pub(crate) fn extend_to(&self, other: &SourceLocation) -> Self {
+        SourceLocation::new(self.bytes.start..other.bytes.end, self.line, self.column)
+    }
+}
+
+impl Default for SourceLocation {
+    #[inline]
+    fn default() -> Self {
+        SourceLocation::new(0..0, 1, 1)
+    }
+}
+
+/// Computes the locations of the ranges of the input chunks, counting lines in the bytes
+/// between the located ranges.
+pub(crate) struct SourceLocator {
+    input_offset: usize,
+    offset: usize,
+    line: usize,
+    line_start: usize,
+}
+
+impl SourceLocator {
+    pub fn new() -> Self {
+        SourceLocator {
+            input_offset: 0,
+            offset: 0,
+            line: 1,
+            line_start: 0,
+        }
+    }
+
+    fn advance_to(&mut self, input: &[u8], pos: usize) {
+        let start = self.offset - self.input_offset;
+
+        if pos <= start {
+            return;
+        }
+
+        let bytes = &input[start..pos];
+
+        self.line += memchr_iter(b'\n', bytes).count();
+
+        if let Some(idx) = memrchr(b'\n', bytes) {
+            self.line_start = self.offset + idx + 1;
+        }
+
+        self.offset += bytes.len();
+    }
+
+    /// Returns the location of the `range` of the current `input` chunk. Ranges must be
+    /// located in the order of their appearance in the input.
+    pub fn locate(&mut self, input: &[u8], range: Range) -> SourceLocation {
+        self.advance_to(input, range.start);
+
+        let start = self.input_offset + range.start;
+
+        SourceLocation::new(
+            start..self.input_offset + range.end,
+            self.line,
+            start - self.line_start + 1,
+        )
+    }
+
+    /// Moves the locator to the next input chunk, given the number of bytes consumed from the
+    /// current one. The unconsumed bytes are expected at the start of the next chunk.
+    pub fn next_input(&mut self, input: &[u8], consumed_byte_count: usize) {
+        self.advance_to(input, consumed_byte_count);
+        self.input_offset += consumed_byte_count;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locate_across_input_chunks() {
+        let mut locator = SourceLocator::new();
+        let input = b"<p>\nfoo\nb";
+
+        let location = locator.locate(input, Range { start: 0, end: 3 });
+
+        assert_eq!(location, SourceLocation::new(0..3, 1, 1));
+
+        let location = locator.locate(input, Range { start: 4, end: 7 });
+
+        assert_eq!(location, SourceLocation::new(4..7, 2, 1));
+
+        locator.next_input(input, 8);
+
+        let input = b"bar</p>";
+        let location = locator.locate(input, Range { start: 3, end: 7 });
+
+        assert_eq!(location, SourceLocation::new(11..15, 3, 4));
+    }
+}
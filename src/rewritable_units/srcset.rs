@@ -0,0 +1,316 @@
+//! Parsing and serialization of the [`srcset`] attribute values.
+//!
+//! [`srcset`]: https://html.spec.whatwg.org/multipage/images.html#srcset-attributes
+
+use crate::html::is_attr_whitespace;
+use std::fmt::{self, Display};
+
+/// An image candidate of the `srcset` attribute, i.e. the URL of the image and its descriptors.
+///
+/// # Example
+/// ```
+/// use lol_html::html_content::srcset;
+///
+/// let mut candidates = srcset::parse("/a.png 100w, /b.png 2x");
+///
+/// assert_eq!(candidates[0].url(), "/a.png");
+/// assert_eq!(candidates[0].width(), Some(100));
+/// assert_eq!(candidates[1].density(), Some(2.0));
+///
+/// candidates[1].set_url("https://cdn.example.com/b.png");
+/// candidates[1].set_density(1.5);
+///
+/// assert_eq!(
+///     srcset::serialize(&candidates),
+///     "/a.png 100w, https://cdn.example.com/b.png 1.5x"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrcsetCandidate {
+    url: String,
+    descriptors: Vec<String>,
+}
+
+impl SrcsetCandidate {
+    /// Creates an image candidate with the `url` and without descriptors.
+    #[inline]
+    pub fn new(url: &str) -> Self {
+        SrcsetCandidate {
+            url: url.to_owned(),
+            descriptors: Vec::default(),
+        }
+    }
+
+    /// Returns the URL of the image.
+    #[inline]
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Sets the URL of the image.
+    #[inline]
+    pub fn set_url(&mut self, url: &str) {
+        self.url = url.to_owned();
+    }
+
+    /// Returns the descriptors of the image in the order of their appearance, e.g. `["100w"]`.
+    #[inline]
+    pub fn descriptors(&self) -> &[String] {
+        &self.descriptors
+    }
+
+    #[inline]
+    fn descriptor_value(&self, suffix: char) -> Option<&str> {
+        self.descriptors
+            .iter()
+            .find(|d| d.ends_with(suffix))
+            .map(|d| &d[..d.len() - 1])
+    }
+
+    #[inline]
+    fn set_size_descriptor(&mut self, descriptor: String) {
+        self.descriptors
+            .retain(|d| !d.ends_with('w') && !d.ends_with('x'));
+
+        self.descriptors.push(descriptor);
+    }
+
+    /// Returns the value of the width descriptor, e.g. `100` for `100w`.
+    ///
+    /// Returns `None` if the candidate doesn't have the descriptor or its value is not a valid
+    /// non-negative integer.
+    pub fn width(&self) -> Option<u32> {
+        let value = self.descriptor_value('w')?;
+
+        if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) {
+            value.parse().ok()
+        } else {
+            None
+        }
+    }
+
+    /// Sets the width descriptor of the image, replacing the width or the pixel density
+    /// descriptor the candidate has.
+    #[inline]
+    pub fn set_width(&mut self, width: u32) {
+        self.set_size_descriptor(format!("{}w", width));
+    }
+
+    /// Returns the value of the pixel density descriptor, e.g. `1.5` for `1.5x`.
+    ///
+    /// Returns `None` if the candidate doesn't have the descriptor or its value is not a valid
+    /// non-negative number.
+    pub fn density(&self) -> Option<f64> {
+        let value = self.descriptor_value('x')?;
+
+        match value.parse::<f64>() {
+            Ok(density) if density.is_finite() && density >= 0. => Some(density),
+            _ => None,
+        }
+    }
+
+    /// Sets the pixel density descriptor of the image, replacing the width or the pixel density
+    /// descriptor the candidate has.
+    #[inline]
+    pub fn set_density(&mut self, density: f64) {
+        self.set_size_descriptor(format!("{}x", density));
+    }
+}
+
+impl Display for SrcsetCandidate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.url)?;
+
+        for descriptor in &self.descriptors {
+            write!(f, " {}", descriptor)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Collects the descriptors of the candidate that start at `pos` and returns the position
+/// after the candidate.
+fn parse_descriptors(value: &str, mut pos: usize, descriptors: &mut Vec<String>) -> usize {
+    let bytes = value.as_bytes();
+    let mut start = None;
+    let mut in_parens = false;
+
+    while pos < bytes.len() {
+        let b = bytes[pos];
+
+        if in_parens {
+            in_parens = b != b')';
+        } else if is_attr_whitespace(b) || b == b',' {
+            if let Some(start) = start.take() {
+                descriptors.push(value[start..pos].to_owned());
+            }
+
+            if b == b',' {
+                return pos + 1;
+            }
+        } else {
+            start = start.or(Some(pos));
+            in_parens = b == b'(';
+        }
+
+        pos += 1;
+    }
+
+    if let Some(start) = start {
+        descriptors.push(value[start..].to_owned());
+    }
+
+    pos
+}
+
+/// Parses the `srcset` attribute value into the image candidates, following the [parsing
+/// algorithm] of the HTML specification.
+///
+/// Unlike the specification, the candidates with unknown or invalid descriptors are not dropped,
+/// so the value can be rewritten without losing any of its content.
+///
+/// [parsing algorithm]: https://html.spec.whatwg.org/multipage/images.html#parse-a-srcset-attribute
+pub fn parse(value: &str) -> Vec<SrcsetCandidate> {
+    let bytes = value.as_bytes();
+    let mut candidates = Vec::default();
+    let mut pos = 0;
+
+    loop {
+        while pos < bytes.len() && (is_attr_whitespace(bytes[pos]) || bytes[pos] == b',') {
+            pos += 1;
+        }
+
+        if pos == bytes.len() {
+            return candidates;
+        }
+
+        let url_start = pos;
+
+        while pos < bytes.len() && !is_attr_whitespace(bytes[pos]) {
+            pos += 1;
+        }
+
+        let mut url = &value[url_start..pos];
+        let mut descriptors = Vec::default();
+
+        // NOTE: the URL that ends with a comma doesn't have descriptors.
+        if url.ends_with(',') {
+            url = url.trim_end_matches(',');
+        } else {
+            pos = parse_descriptors(value, pos, &mut descriptors);
+        }
+
+        candidates.push(SrcsetCandidate {
+            url: url.to_owned(),
+            descriptors,
+        });
+    }
+}
+
+/// Serializes the image candidates into the `srcset` attribute value.
+pub fn serialize(candidates: &[SrcsetCandidate]) -> String {
+    candidates
+        .iter()
+        .map(SrcsetCandidate::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rewritable_units::test_utils::*;
+    use encoding_rs::UTF_8;
+
+    fn candidate(url: &str, descriptors: &[&str]) -> SrcsetCandidate {
+        SrcsetCandidate {
+            url: url.to_owned(),
+            descriptors: descriptors.iter().map(|&d| d.to_owned()).collect(),
+        }
+    }
+
+    #[test]
+    fn parse_candidates() {
+        assert_eq!(
+            parse(" /a.png 100w,\n/b.png\t200w 300h , /c.png,,"),
+            [
+                candidate("/a.png", &["100w"]),
+                candidate("/b.png", &["200w", "300h"]),
+                candidate("/c.png", &[])
+            ]
+        );
+
+        assert_eq!(
+            parse("data:image/png;base64,iVBO 1x,/b.png, /c.png 2x"),
+            [
+                candidate("data:image/png;base64,iVBO", &["1x"]),
+                candidate("/b.png", &[]),
+                candidate("/c.png", &["2x"])
+            ]
+        );
+
+        assert_eq!(
+            parse("/a.png foo(1, 2) 1x, /b.png (x,"),
+            [
+                candidate("/a.png", &["foo(1, 2)", "1x"]),
+                candidate("/b.png", &["(x,"])
+            ]
+        );
+
+        assert!(parse(" , ").is_empty());
+    }
+
+    #[test]
+    fn descriptors() {
+        let mut candidates = parse("/a.png 100w, /b.png 1.5x, /c.png 1e1x, /d.png -1w, /e.png");
+
+        assert_eq!(candidates[0].width(), Some(100));
+        assert_eq!(candidates[0].density(), None);
+        assert_eq!(candidates[1].density(), Some(1.5));
+        assert_eq!(candidates[2].density(), Some(10.));
+        assert_eq!(candidates[3].width(), None);
+        assert_eq!(candidates[4].width(), None);
+
+        candidates[0].set_density(2.);
+        candidates[1].set_width(200);
+        candidates[4].set_width(300);
+
+        assert_eq!(
+            serialize(&candidates),
+            "/a.png 2x, /b.png 200w, /c.png 1e1x, /d.png -1w, /e.png 300w"
+        );
+    }
+
+    #[test]
+    fn rewrite_srcset() {
+        let rewrite = |html: &str| {
+            rewrite_html(
+                html.as_bytes(),
+                UTF_8,
+                vec![element!("img", |el| {
+                    el.rewrite_srcset(|candidate| {
+                        if candidate.url().starts_with('/') {
+                            let url = format!("https://cdn.example.com{}", candidate.url());
+
+                            candidate.set_url(&url);
+                        }
+                    });
+
+                    Ok(())
+                })],
+                vec![],
+            )
+        };
+
+        assert_eq!(
+            rewrite("<img srcset='/a.png 1x,https://b.com/b.png 2x'><img>"),
+            r#"<img srcset="https://cdn.example.com/a.png 1x, https://b.com/b.png 2x"><img>"#
+        );
+
+        assert_eq!(
+            rewrite("<img srcset='https://b.com/b.png  2x'>"),
+            "<img srcset='https://b.com/b.png  2x'>"
+        );
+    }
+}
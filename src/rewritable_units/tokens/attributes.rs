@@ -1,8 +1,8 @@
-use crate::base::Bytes;
-use crate::parser::SharedAttributeBuffer;
+use crate::base::{intern, Bytes};
+use crate::parser::AttributeOutline;
 use crate::rewritable_units::Serialize;
 use encoding_rs::Encoding;
-use lazycell::LazyCell;
+use std::borrow::Cow;
 use std::fmt::{self, Debug};
 use std::ops::Deref;
 use thiserror::Error;
@@ -29,10 +29,13 @@ pub enum AttributeNameError {
 
 /// An attribute of an [`Element`].
 ///
-/// This is an immutable representation of an attribute. To modify element's attributes use
-/// approriate [`Element`]'s methods.
+/// To add or remove element's attributes use approriate [`Element`]'s methods. Names and values
+/// of the existing attributes can be modified in place via [`Element::attributes_mut`] and
+/// [`Element::retain_attributes_mut`].
 ///
 /// [`Element`]: struct.Element.html
+/// [`Element::attributes_mut`]: struct.Element.html#method.attributes_mut
+/// [`Element::retain_attributes_mut`]: struct.Element.html#method.retain_attributes_mut
 pub struct Attribute<'i> {
     name: Bytes<'i>,
     value: Bytes<'i>,
@@ -62,6 +65,8 @@ impl<'i> Attribute<'i> {
             _ => false,
         }) {
             Err(AttributeNameError::ForbiddenCharacter(ch))
+        } else if let Some(name) = intern(name) {
+            Ok(Bytes::from(name.as_bytes()))
         } else {
             // NOTE: if character can't be represented in the given
             // encoding then encoding_rs replaces it with a numeric
@@ -91,7 +96,7 @@ impl<'i> Attribute<'i> {
     /// Returns the name of the attribute.
     #[inline]
     pub fn name(&self) -> String {
-        self.name.as_lowercase_string(self.encoding)
+        self.name_str().into_owned()
     }
 
     /// Returns the value of the attribute.
@@ -101,10 +106,58 @@ impl<'i> Attribute<'i> {
     }
 
     #[inline]
-    fn set_value(&mut self, value: &str) {
+    pub(crate) fn name_str(&self) -> Cow<'static, str> {
+        self.name.as_interned_lowercase_string(self.encoding)
+    }
+
+    /// Returns `true` if the attribute is an inline event handler attribute, i.e. its name
+    /// consists of the `on` prefix followed by ASCII letters (e.g. `onclick`).
+    ///
+    /// All the event handler attributes of the specification have such names, so attributes for
+    /// non-standard or future events are covered as well. Names with other characters after the
+    /// prefix (e.g. `online-id` or `on2`) can't be event handlers.
+    #[inline]
+    pub(crate) fn is_event_handler(&self) -> bool {
+        self.name.len() > 2
+            && self.name[..2].eq_ignore_ascii_case(b"on")
+            && self.name[2..].iter().all(u8::is_ascii_alphabetic)
+    }
+
+    /// Sets the value of the attribute.
+    #[inline]
+    pub fn set_value(&mut self, value: &str) {
         self.value = Bytes::from_str(value, self.encoding).into_owned();
         self.raw = None;
     }
+
+    /// Sets the name of the attribute.
+    ///
+    /// The name is lowercased, as it's done for the attributes added with
+    /// [`Element::set_attribute`].
+    ///
+    /// [`Element::set_attribute`]: struct.Element.html#method.set_attribute
+    #[inline]
+    pub fn set_name(&mut self, name: &str) -> Result<(), AttributeNameError> {
+        self.name = Attribute::name_from_str(&name.to_ascii_lowercase(), self.encoding)?;
+        self.raw = None;
+
+        Ok(())
+    }
+
+    #[inline]
+    pub(crate) fn is_modified(&self) -> bool {
+        self.raw.is_none()
+    }
+
+    /// Lowercases the name of the attribute and makes it serialized with a double-quoted value.
+    #[inline]
+    pub(crate) fn canonicalize(&mut self) {
+        if self.name.iter().any(u8::is_ascii_uppercase) {
+            self.name = Bytes::from(Cow::Owned(self.name.to_ascii_lowercase()));
+        }
+
+        self.raw = None;
+    }
 }
 
 impl Serialize for Attribute<'_> {
@@ -125,30 +178,38 @@ impl Serialize for Attribute<'_> {
 impl Debug for Attribute<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Attribute")
-            .field("name", &self.name())
+            .field("name", &self.name_str())
             .field("value", &self.value())
             .finish()
     }
 }
 
 pub struct Attributes<'i> {
-    input: &'i Bytes<'i>,
-    attribute_buffer: SharedAttributeBuffer,
-    items: LazyCell<Vec<Attribute<'i>>>,
-    encoding: &'static Encoding,
+    items: Vec<Attribute<'i>>,
+    #[cfg(test)]
+    raw: (&'i Bytes<'i>, &'i [AttributeOutline]),
 }
 
 impl<'i> Attributes<'i> {
     pub(super) fn new(
         input: &'i Bytes<'i>,
-        attribute_buffer: SharedAttributeBuffer,
+        attribute_buffer: &'i [AttributeOutline],
         encoding: &'static Encoding,
     ) -> Self {
         Attributes {
-            input,
-            attribute_buffer,
-            items: LazyCell::default(),
-            encoding,
+            items: attribute_buffer
+                .iter()
+                .map(|a| {
+                    Attribute::new(
+                        input.slice(a.name),
+                        input.slice(a.value),
+                        input.slice(a.raw_range),
+                        encoding,
+                    )
+                })
+                .collect(),
+            #[cfg(test)]
+            raw: (input, attribute_buffer),
         }
     }
 
@@ -159,9 +220,9 @@ impl<'i> Attributes<'i> {
         encoding: &'static Encoding,
     ) -> Result<(), AttributeNameError> {
         let name = name.to_ascii_lowercase();
-        let items = self.as_mut_vec();
+        let items = &mut self.items;
 
-        match items.iter_mut().find(|attr| attr.name() == name.as_str()) {
+        match items.iter_mut().find(|attr| attr.name_str() == name) {
             Some(attr) => attr.set_value(value),
             None => {
                 items.push(Attribute::try_from(&name, value, encoding)?);
@@ -173,11 +234,11 @@ impl<'i> Attributes<'i> {
 
     pub fn remove_attribute(&mut self, name: &str) -> bool {
         let name = name.to_ascii_lowercase();
-        let items = self.as_mut_vec();
+        let items = &mut self.items;
         let mut i = 0;
 
         while i < items.len() {
-            if items[i].name() == name.as_str() {
+            if items[i].name_str() == name {
                 items.remove(i);
                 return true;
             }
@@ -188,41 +249,83 @@ impl<'i> Attributes<'i> {
         false
     }
 
-    fn init_items(&self) -> Vec<Attribute<'i>> {
-        self.attribute_buffer
-            .borrow()
-            .iter()
-            .map(|a| {
-                Attribute::new(
-                    self.input.slice(a.name),
-                    self.input.slice(a.value),
-                    self.input.slice(a.raw_range),
-                    self.encoding,
-                )
-            })
-            .collect()
+    /// Removes all the attributes that satisfy the `predicate` in a single pass and returns them.
+    pub fn remove_where(
+        &mut self,
+        mut predicate: impl FnMut(&Attribute<'i>) -> bool,
+    ) -> Vec<Attribute<'i>> {
+        let items = &mut self.items;
+        let (removed, retained) = items.drain(..).partition(|attr| predicate(attr));
+
+        *items = retained;
+
+        removed
     }
 
-    #[inline]
-    fn as_mut_vec(&mut self) -> &mut Vec<Attribute<'i>> {
-        // NOTE: we can't use borrow_mut_with here as we'll need
-        // because `self` is a mutable reference and we'll have
-        // two mutable references by passing it to the initializer
-        // closure.
-        if !self.items.filled() {
-            self.items
-                .fill(self.init_items())
-                .expect("Cell should be empty at this point");
+    /// Retains only the attributes for which the `predicate` returns `true`. The `predicate` can
+    /// modify the attributes, the original names of the removed and renamed attributes are
+    /// returned.
+    pub fn retain_mut(
+        &mut self,
+        mut predicate: impl FnMut(&mut Attribute<'i>) -> bool,
+    ) -> Vec<String> {
+        let items = &mut self.items;
+        let mut retained = Vec::with_capacity(items.len());
+        let mut modified_names = Vec::default();
+
+        for mut attr in items.drain(..) {
+            // NOTE: names of the parsed attributes borrow the input,
+            // so the clone doesn't allocate.
+            let name = attr.name.clone();
+
+            if predicate(&mut attr) {
+                if attr.name[..] != name[..] {
+                    modified_names.push(name.as_string(attr.encoding));
+                }
+
+                retained.push(attr);
+            } else {
+                modified_names.push(name.as_string(attr.encoding));
+            }
         }
 
-        self.items
-            .borrow_mut()
-            .expect("Items should be initialized")
+        *items = retained;
+
+        modified_names
+    }
+
+    /// Sorts the attributes by name, removes the duplicates and canonicalizes the rest.
+    pub fn canonicalize(&mut self) {
+        let items = &mut self.items;
+
+        // NOTE: the sort is stable, so the first of the duplicate attributes is retained,
+        // as it's done by browsers.
+        items.sort_by(|a, b| a.name_str().cmp(&b.name_str()));
+        items.dedup_by(|a, b| a.name_str() == b.name_str());
+        items.iter_mut().for_each(Attribute::canonicalize);
+    }
+
+    #[inline]
+    pub fn as_mut_slice(&mut self) -> &mut [Attribute<'i>] {
+        &mut self.items
     }
 
     #[cfg(test)]
-    pub fn raw_attributes(&self) -> (&'i Bytes<'i>, SharedAttributeBuffer) {
-        (self.input, std::rc::Rc::clone(&self.attribute_buffer))
+    pub fn raw_attributes(
+        &self,
+    ) -> (
+        &'i Bytes<'i>,
+        &'i [AttributeOutline],
+        crate::parser::AttributeNamesBloom,
+    ) {
+        let (input, attribute_buffer) = self.raw;
+        let mut names_bloom = crate::parser::AttributeNamesBloom::default();
+
+        for attr in attribute_buffer {
+            names_bloom.add(&input.slice(attr.name));
+        }
+
+        (input, attribute_buffer, names_bloom)
     }
 }
 
@@ -231,7 +334,7 @@ impl<'i> Deref for Attributes<'i> {
 
     #[inline]
     fn deref(&self) -> &[Attribute<'i>] {
-        self.items.borrow_with(|| self.init_items())
+        &self.items
     }
 }
 
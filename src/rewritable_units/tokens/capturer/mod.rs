@@ -4,6 +4,7 @@ mod to_token;
 use self::text_decoder::TextDecoder;
 use super::*;
 use crate::parser::Lexeme;
+use crate::rewritable_units::SourceLocator;
 use crate::rewriter::RewritingError;
 use bitflags::bitflags;
 use encoding_rs::Encoding;
@@ -17,6 +18,7 @@ bitflags! {
         const NEXT_START_TAG = 0b0000_0100;
         const NEXT_END_TAG = 0b0000_1000;
         const DOCTYPES = 0b0001_0000;
+        const PROCESSING_INSTRUCTIONS = 0b0010_0000;
     }
 }
 
@@ -32,6 +34,7 @@ pub struct TokenCapturer {
     encoding: &'static Encoding,
     text_decoder: TextDecoder,
     capture_flags: TokenCaptureFlags,
+    source_locator: SourceLocator,
 }
 
 impl TokenCapturer {
@@ -40,6 +43,7 @@ impl TokenCapturer {
             encoding,
             text_decoder: TextDecoder::new(encoding),
             capture_flags,
+            source_locator: SourceLocator::new(),
         }
     }
 
@@ -48,11 +52,22 @@ impl TokenCapturer {
         !self.capture_flags.is_empty()
     }
 
+    #[inline]
+    pub fn capture_flags(&self) -> TokenCaptureFlags {
+        self.capture_flags
+    }
+
     #[inline]
     pub fn set_capture_flags(&mut self, flags: TokenCaptureFlags) {
         self.capture_flags = flags;
     }
 
+    /// Moves to the next input chunk, given the number of bytes consumed from the current one.
+    #[inline]
+    pub fn next_input(&mut self, input: &[u8], consumed_byte_count: usize) {
+        self.source_locator.next_input(input, consumed_byte_count);
+    }
+
     #[inline]
     pub fn flush_pending_text(
         &mut self,
@@ -70,7 +85,12 @@ impl TokenCapturer {
         Lexeme<'i, T>: ToToken,
     {
         match lexeme.to_token(&mut self.capture_flags, self.encoding) {
-            ToTokenResult::Token(token) => {
+            ToTokenResult::Token(mut token) => {
+                token.set_source_location(
+                    self.source_locator
+                        .locate(lexeme.input(), lexeme.raw_range()),
+                );
+
                 self.flush_pending_text(&mut event_handler)?;
                 event_handler(TokenCapturerEvent::LexemeConsumed)?;
                 event_handler(TokenCapturerEvent::TokenProduced(token))
@@ -79,8 +99,13 @@ impl TokenCapturer {
                 if self.capture_flags.contains(TokenCaptureFlags::TEXT) {
                     event_handler(TokenCapturerEvent::LexemeConsumed)?;
 
-                    self.text_decoder
-                        .feed_text(&lexeme.raw(), text_type, &mut event_handler)?;
+                    self.text_decoder.feed_text(
+                        lexeme.input(),
+                        lexeme.raw_range(),
+                        text_type,
+                        &mut self.source_locator,
+                        &mut event_handler,
+                    )?;
                 }
 
                 Ok(())
@@ -1,23 +1,183 @@
 use super::*;
+use crate::base::Range;
 use crate::html::TextType;
+use crate::rewritable_units::{SourceLocation, SourceLocator};
 use crate::rewriter::RewritingError;
-use encoding_rs::{CoderResult, Decoder, Encoding};
+use encoding_rs::Encoding;
+#[cfg(feature = "all_encodings")]
+use encoding_rs::{CoderResult, Decoder};
+#[cfg(not(feature = "all_encodings"))]
+use std::str;
 
 // NOTE: this can't be refactored into method, because we hold a mutable reference for `self`
 // during the decoding loop in `feed_text`.
 macro_rules! emit {
-    ($self:tt, $text:expr, $last:ident, $event_handler:ident) => {{
-        let token = TextChunk::new_token($text, $self.last_text_type, $last, $self.encoding);
+    ($self:tt, $text:expr, $last:ident, $location:expr, $event_handler:ident) => {{
+        let mut token = TextChunk::new_token($text, $self.last_text_type, $last, $self.encoding);
+
+        token.set_source_location($location);
 
         $event_handler(TokenCapturerEvent::TokenProduced(Box::new(token)))
     }};
 }
 
+/// A streaming UTF-8 decoder, which is used without the `all_encodings` feature instead of
+/// the decoders of `encoding_rs`. Unlike them, it emits the valid text right from the input
+/// instead of copying it to the buffer.
+#[cfg(not(feature = "all_encodings"))]
+#[derive(Default)]
+struct Decoder {
+    /// The incomplete sequence at the end of the previous chunk.
+    incomplete: [u8; 4],
+    incomplete_len: usize,
+}
+
+#[cfg(not(feature = "all_encodings"))]
+type EmitFn<'e> = dyn FnMut(&str, bool, Range) -> Result<(), RewritingError> + 'e;
+
+#[cfg(not(feature = "all_encodings"))]
+impl Decoder {
+    #[inline]
+    fn sequence_len(first_byte: u8) -> usize {
+        match first_byte {
+            0xF0..=0xFF => 4,
+            0xE0..=0xEF => 3,
+            _ => 2,
+        }
+    }
+
+    /// Decodes the `raw` chunk, passing the decoded text to `emit` along with the flag that
+    /// indicates whether it's the last text of the token and the range of the input it has
+    /// been decoded from.
+    fn decode(&mut self, raw: &[u8], last: bool, emit: &mut EmitFn) -> Result<(), RewritingError> {
+        let mut consumed = 0;
+
+        while self.incomplete_len > 0 && (consumed < raw.len() || last) {
+            let mut sequence = self.incomplete;
+            let len = self.incomplete_len;
+            let start = consumed;
+            let take = (Self::sequence_len(sequence[0]) - len).min(raw.len() - consumed);
+
+            sequence[len..len + take].copy_from_slice(&raw[consumed..consumed + take]);
+            self.incomplete_len = 0;
+            consumed += take;
+
+            let is_last = last && consumed == raw.len();
+
+            self.decode_chunk(&sequence[..len + take], is_last, &mut |text, last, _| {
+                emit(
+                    text,
+                    last,
+                    Range {
+                        start,
+                        end: consumed,
+                    },
+                )
+            })?;
+
+            if is_last {
+                return Ok(());
+            }
+        }
+
+        self.decode_chunk(&raw[consumed..], last, &mut |text, last, range| {
+            let range = Range {
+                start: consumed + range.start,
+                end: consumed + range.end,
+            };
+
+            emit(text, last, range)
+        })
+    }
+
+    fn decode_chunk(
+        &mut self,
+        bytes: &[u8],
+        last: bool,
+        emit: &mut EmitFn,
+    ) -> Result<(), RewritingError> {
+        let mut pos = 0;
+
+        loop {
+            let err = match str::from_utf8(&bytes[pos..]) {
+                Ok(text) => {
+                    if !text.is_empty() || last {
+                        emit(
+                            text,
+                            last,
+                            Range {
+                                start: pos,
+                                end: bytes.len(),
+                            },
+                        )?;
+                    }
+
+                    return Ok(());
+                }
+                Err(err) => err,
+            };
+
+            let valid_end = pos + err.valid_up_to();
+
+            if valid_end > pos {
+                // NOTE: the input is valid up to the error, so it's safe to unwrap here.
+                let text = str::from_utf8(&bytes[pos..valid_end]).unwrap();
+
+                emit(
+                    text,
+                    false,
+                    Range {
+                        start: pos,
+                        end: valid_end,
+                    },
+                )?;
+            }
+
+            match err.error_len() {
+                Some(error_len) => {
+                    pos = valid_end + error_len;
+
+                    emit(
+                        "\u{FFFD}",
+                        false,
+                        Range {
+                            start: valid_end,
+                            end: pos,
+                        },
+                    )?;
+                }
+                // NOTE: the chunk ends with an incomplete sequence, which is either completed
+                // by the next chunk or replaced if there is no more text.
+                None if last => {
+                    return emit(
+                        "\u{FFFD}",
+                        true,
+                        Range {
+                            start: valid_end,
+                            end: bytes.len(),
+                        },
+                    );
+                }
+                None => {
+                    let incomplete = &bytes[valid_end..];
+
+                    self.incomplete[..incomplete.len()].copy_from_slice(incomplete);
+                    self.incomplete_len = incomplete.len();
+
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
 pub struct TextDecoder {
     encoding: &'static Encoding,
     pending_text_streaming_decoder: Option<Decoder>,
+    #[cfg(feature = "all_encodings")]
     text_buffer: String,
     last_text_type: TextType,
+    text_end_location: SourceLocation,
 }
 
 impl TextDecoder {
@@ -26,8 +186,10 @@ impl TextDecoder {
             encoding,
             pending_text_streaming_decoder: None,
             // TODO make adjustable
+            #[cfg(feature = "all_encodings")]
             text_buffer: String::from_utf8(vec![0u8; 1024]).unwrap(),
             last_text_type: TextType::Data,
+            text_end_location: SourceLocation::default(),
         }
     }
 
@@ -37,16 +199,49 @@ impl TextDecoder {
         event_handler: CapturerEventHandler,
     ) -> Result<(), RewritingError> {
         if self.pending_text_streaming_decoder.is_some() {
-            self.decode_with_streaming_decoder(&[], true, event_handler)?;
+            let location = self.text_end_location.clone();
+
+            self.decode_with_streaming_decoder(
+                &[],
+                true,
+                &mut |_| location.clone(),
+                event_handler,
+            )?;
             self.pending_text_streaming_decoder = None;
         }
         Ok(())
     }
 
+    #[cfg(not(feature = "all_encodings"))]
     fn decode_with_streaming_decoder(
         &mut self,
         raw: &[u8],
         last: bool,
+        locate: &mut dyn FnMut(Range) -> SourceLocation,
+        event_handler: CapturerEventHandler,
+    ) -> Result<(), RewritingError> {
+        // NOTE: the decoder is taken out for the time of decoding, since the emitted tokens
+        // borrow the other fields of `self`.
+        let mut decoder = self
+            .pending_text_streaming_decoder
+            .take()
+            .unwrap_or_default();
+
+        let result = decoder.decode(raw, last, &mut |text, last, range| {
+            emit!(self, text, last, locate(range), event_handler)
+        });
+
+        self.pending_text_streaming_decoder = Some(decoder);
+
+        result
+    }
+
+    #[cfg(feature = "all_encodings")]
+    fn decode_with_streaming_decoder(
+        &mut self,
+        raw: &[u8],
+        last: bool,
+        locate: &mut dyn FnMut(Range) -> SourceLocation,
         event_handler: CapturerEventHandler,
     ) -> Result<(), RewritingError> {
         let encoding = self.encoding;
@@ -62,7 +257,12 @@ impl TextDecoder {
             let (status, read, written, ..) = decoder.decode_to_str(&raw[consumed..], buffer, last);
 
             if written > 0 || last {
-                emit!(self, &buffer[..written], last, event_handler)?;
+                let location = locate(Range {
+                    start: consumed,
+                    end: consumed + read,
+                });
+
+                emit!(self, &buffer[..written], last, location, event_handler)?;
             }
 
             if let CoderResult::InputEmpty = status {
@@ -78,11 +278,39 @@ impl TextDecoder {
     #[inline]
     pub fn feed_text(
         &mut self,
-        raw: &[u8],
+        input: &[u8],
+        raw_range: Range,
         text_type: TextType,
+        source_locator: &mut SourceLocator,
         event_handler: CapturerEventHandler,
     ) -> Result<(), RewritingError> {
+        let raw = &input[raw_range.start..raw_range.end];
+
         self.last_text_type = text_type;
-        self.decode_with_streaming_decoder(raw, false, event_handler)
+
+        self.decode_with_streaming_decoder(
+            raw,
+            false,
+            &mut |range| {
+                source_locator.locate(
+                    input,
+                    Range {
+                        start: raw_range.start + range.start,
+                        end: raw_range.start + range.end,
+                    },
+                )
+            },
+            event_handler,
+        )?;
+
+        self.text_end_location = source_locator.locate(
+            input,
+            Range {
+                start: raw_range.end,
+                end: raw_range.end,
+            },
+        );
+
+        Ok(())
     }
 }
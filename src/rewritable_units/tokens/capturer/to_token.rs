@@ -1,8 +1,8 @@
 use super::*;
+use crate::base::Range;
 use crate::html::TextType;
 use crate::parser::{NonTagContentLexeme, NonTagContentTokenOutline, TagLexeme, TagTokenOutline};
 use encoding_rs::Encoding;
-use std::rc::Rc;
 
 pub enum ToTokenResult<'i> {
     Token(Box<Token<'i>>),
@@ -17,6 +17,42 @@ impl<'i> From<Token<'i>> for ToTokenResult<'i> {
     }
 }
 
+/// Splits the `text` of the bogus comment that starts with `<?` into the target and the data of
+/// the processing instruction. The text starts with the `?` character, and the `?` character
+/// before the closing `>`, if any, is not a part of the data. Returns `true` as the last element
+/// if there is such a character.
+fn split_processing_instruction(input: &[u8], text: Range) -> (Range, Range, bool) {
+    let start = (text.start + 1).min(text.end);
+    let mut end = text.end;
+    let closed_with_question_mark = end > start && input[end - 1] == b'?';
+
+    if closed_with_question_mark {
+        end -= 1;
+    }
+
+    let target_end = input[start..end]
+        .iter()
+        .position(|ch| ch.is_ascii_whitespace())
+        .map_or(end, |idx| start + idx);
+
+    let data_start = input[target_end..end]
+        .iter()
+        .position(|ch| !ch.is_ascii_whitespace())
+        .map_or(end, |idx| target_end + idx);
+
+    (
+        Range {
+            start,
+            end: target_end,
+        },
+        Range {
+            start: data_start,
+            end,
+        },
+        closed_with_question_mark,
+    )
+}
+
 pub trait ToToken {
     fn to_token(
         &self,
@@ -44,7 +80,7 @@ impl ToToken for TagLexeme<'_> {
 
                 StartTag::new_token(
                     self.part(name),
-                    Attributes::new(self.input(), Rc::clone(attributes), encoding),
+                    Attributes::new(self.input(), attributes, encoding),
                     ns,
                     self_closing,
                     self.raw(),
@@ -79,6 +115,35 @@ impl ToToken for NonTagContentLexeme<'_> {
             {
                 Comment::new_token(self.part(text), self.raw(), encoding).into()
             }
+            Some(NonTagContentTokenOutline::CommentChunk { text, first, last })
+                if capture_flags.contains(TokenCaptureFlags::COMMENTS) =>
+            {
+                Comment::new_chunk_token(self.part(text), self.raw(), first, last, encoding).into()
+            }
+
+            Some(NonTagContentTokenOutline::ProcessingInstruction(text))
+                if capture_flags.contains(TokenCaptureFlags::PROCESSING_INSTRUCTIONS) =>
+            {
+                let (target, data, closed_with_question_mark) =
+                    split_processing_instruction(self.input(), text);
+
+                ProcessingInstruction::new_token(
+                    self.part(target),
+                    self.part(data),
+                    closed_with_question_mark,
+                    self.raw(),
+                    encoding,
+                )
+                .into()
+            }
+
+            // NOTE: processing instructions are bogus comments in HTML, so they are
+            // captured as comments unless processing instructions are captured.
+            Some(NonTagContentTokenOutline::ProcessingInstruction(text))
+                if capture_flags.contains(TokenCaptureFlags::COMMENTS) =>
+            {
+                Comment::new_token(self.part(text), self.raw(), encoding).into()
+            }
 
             Some(NonTagContentTokenOutline::Doctype {
                 name,
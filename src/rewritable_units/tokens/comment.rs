@@ -1,5 +1,6 @@
 use super::{Mutations, Token};
 use crate::base::Bytes;
+use crate::rewritable_units::{MatchInfo, SourceLocation};
 use encoding_rs::Encoding;
 use std::any::Any;
 use std::fmt::{self, Debug};
@@ -22,11 +23,26 @@ pub enum CommentTextError {
 /// An HTML comment rewritable unit.
 ///
 /// Exposes API for examination and modification of a parsed HTML comment.
+///
+/// If [`stream_comments`] is enabled, a comment can be represented by multiple chunks, similarly
+/// to a [`TextChunk`]. Each chunk contains a part of the comment text, and the last chunk of the
+/// comment can be determined by calling the [`last_in_comment`] method of the chunk. The methods
+/// of the chunk only apply to its part of the comment, e.g. a comment is removed only if all its
+/// chunks are removed.
+///
+/// [`stream_comments`]: ../struct.Settings.html#structfield.stream_comments
+/// [`TextChunk`]: struct.TextChunk.html
+/// [`last_in_comment`]: #method.last_in_comment
 pub struct Comment<'i> {
     text: Bytes<'i>,
     raw: Option<Bytes<'i>>,
+    input_raw: Bytes<'i>,
+    first_in_comment: bool,
+    last_in_comment: bool,
     encoding: &'static Encoding,
     mutations: Mutations,
+    match_info: Option<MatchInfo>,
+    source_location: SourceLocation,
     user_data: Box<dyn Any>,
 }
 
@@ -35,12 +51,27 @@ impl<'i> Comment<'i> {
         text: Bytes<'i>,
         raw: Bytes<'i>,
         encoding: &'static Encoding,
+    ) -> Token<'i> {
+        Self::new_chunk_token(text, raw, true, true, encoding)
+    }
+
+    pub(super) fn new_chunk_token(
+        text: Bytes<'i>,
+        raw: Bytes<'i>,
+        first_in_comment: bool,
+        last_in_comment: bool,
+        encoding: &'static Encoding,
     ) -> Token<'i> {
         Token::Comment(Comment {
             text,
-            raw: Some(raw),
+            raw: Some(raw.clone()),
+            input_raw: raw,
+            first_in_comment,
+            last_in_comment,
             encoding,
             mutations: Mutations::new(encoding),
+            match_info: None,
+            source_location: SourceLocation::default(),
             user_data: Box::new(()),
         })
     }
@@ -51,6 +82,28 @@ impl<'i> Comment<'i> {
         self.text.as_string(self.encoding)
     }
 
+    /// Returns the bytes of the comment exactly as they appear in the input, in the document's
+    /// [`encoding`]. The bytes are not affected by the modifications of the comment.
+    ///
+    /// If the comment is split into chunks, the bytes of the chunk are returned.
+    ///
+    /// [`encoding`]: ../struct.Settings.html#structfield.encoding
+    #[inline]
+    pub fn raw(&self) -> &[u8] {
+        &self.input_raw
+    }
+
+    /// Returns `true` if the comment is not split into chunks or the chunk is the last one in
+    /// the comment.
+    ///
+    /// Comments are split into chunks only if [`stream_comments`] is enabled.
+    ///
+    /// [`stream_comments`]: ../struct.Settings.html#structfield.stream_comments
+    #[inline]
+    pub fn last_in_comment(&self) -> bool {
+        self.last_in_comment
+    }
+
     /// Sets the text of the comment.
     #[inline]
     pub fn set_text(&mut self, text: &str) -> Result<(), CommentTextError> {
@@ -181,16 +234,55 @@ impl<'i> Comment<'i> {
         self.mutations.removed()
     }
 
+    /// Returns the information about the selector the content handler is invoked for, or `None`
+    /// if the handler is a document content handler.
+    ///
+    /// Refer to [`MatchInfo`] for more information.
+    ///
+    /// [`MatchInfo`]: struct.MatchInfo.html
+    #[inline]
+    pub fn match_info(&self) -> Option<&MatchInfo> {
+        self.match_info.as_ref()
+    }
+
     #[inline]
-    fn raw(&self) -> Option<&Bytes> {
+    pub(crate) fn set_match_info(&mut self, match_info: Option<MatchInfo>) {
+        self.match_info = match_info;
+    }
+
+    /// Returns the location of the comment in the input.
+    ///
+    /// Refer to [`SourceLocation`] for more information.
+    ///
+    /// If the comment is split into chunks, the location of the chunk is returned.
+    ///
+    /// [`SourceLocation`]: struct.SourceLocation.html
+    #[inline]
+    pub fn source_location(&self) -> &SourceLocation {
+        &self.source_location
+    }
+
+    #[inline]
+    pub(crate) fn set_source_location(&mut self, source_location: SourceLocation) {
+        self.source_location = source_location;
+    }
+
+    #[inline]
+    fn output_raw(&self) -> Option<&Bytes> {
         self.raw.as_ref()
     }
 
     #[inline]
     fn serialize_from_parts(&self, output_handler: &mut dyn FnMut(&[u8])) {
-        output_handler(b"<!--");
+        if self.first_in_comment {
+            output_handler(b"<!--");
+        }
+
         output_handler(&self.text);
-        output_handler(b"-->");
+
+        if self.last_in_comment {
+            output_handler(b"-->");
+        }
     }
 }
 
@@ -201,6 +293,7 @@ impl Debug for Comment<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Comment")
             .field("text", &self.text())
+            .field("last_in_comment", &self.last_in_comment())
             .finish()
     }
 }
@@ -211,7 +304,10 @@ mod tests {
     use crate::html_content::*;
     use crate::rewritable_units::test_utils::*;
     use crate::*;
-    use encoding_rs::{Encoding, EUC_JP, UTF_8};
+    use encoding_rs::{Encoding, UTF_8};
+
+    #[cfg(feature = "all_encodings")]
+    use encoding_rs::EUC_JP;
 
     fn rewrite_comment(
         html: &[u8],
@@ -245,6 +341,7 @@ mod tests {
         });
     }
 
+    #[cfg(feature = "all_encodings")]
     #[test]
     fn encoding_unmappable_chars_in_text() {
         rewrite_comment(b"<!-- foo -->", EUC_JP, |c| {
@@ -254,6 +351,15 @@ mod tests {
         });
     }
 
+    #[test]
+    fn raw_bytes_of_modified_comment() {
+        rewrite_comment(b"<!-- foo -->", UTF_8, |c| {
+            c.set_text("bar").unwrap();
+
+            assert_eq!(c.raw(), b"<!-- foo -->");
+        });
+    }
+
     #[test]
     fn user_data() {
         rewrite_comment(b"<!-- foo -->", UTF_8, |c| {
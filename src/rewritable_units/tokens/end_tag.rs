@@ -1,13 +1,22 @@
 use super::{Mutations, Token};
 use crate::base::Bytes;
+use crate::rewritable_units::{ContentType, InsertedContent};
 use encoding_rs::Encoding;
+use std::borrow::Cow;
 use std::fmt::{self, Debug};
 
+/// An HTML end tag rewritable unit.
+///
+/// End tags are provided to the handlers registered with [`Element::on_end_tag`].
+///
+/// [`Element::on_end_tag`]: struct.Element.html#method.on_end_tag
 pub struct EndTag<'i> {
     name: Bytes<'i>,
     raw: Option<Bytes<'i>>,
+    input_raw: Bytes<'i>,
     encoding: &'static Encoding,
-    pub mutations: Mutations,
+    implicit: bool,
+    pub(crate) mutations: Mutations,
 }
 
 impl<'i> EndTag<'i> {
@@ -18,30 +27,115 @@ impl<'i> EndTag<'i> {
     ) -> Token<'i> {
         Token::EndTag(EndTag {
             name,
-            raw: Some(raw),
+            raw: Some(raw.clone()),
+            input_raw: raw,
             encoding,
+            implicit: false,
             mutations: Mutations::new(encoding),
         })
     }
 
+    /// Creates an end tag for an element that has been closed implicitly, e.g. `<p>` closed
+    /// by the start tag of `<div>`. Such end tags are absent in the input.
+    pub(crate) fn new_implicit(encoding: &'static Encoding) -> EndTag<'static> {
+        EndTag {
+            name: Bytes::from(&b""[..]),
+            raw: None,
+            input_raw: Bytes::from(&b""[..]),
+            encoding,
+            implicit: true,
+            mutations: Mutations::new(encoding),
+        }
+    }
+
+    /// Returns the name of the end tag, or an empty string if the end tag is [implicit].
+    ///
+    /// [implicit]: #method.implicit
     #[inline]
     pub fn name(&self) -> String {
         self.name.as_lowercase_string(self.encoding)
     }
 
+    /// Returns the bytes of the end tag exactly as they appear in the input, in the document's
+    /// [`encoding`]. The bytes are not affected by the modifications of the end tag.
+    ///
+    /// Returns an empty slice if the end tag is [implicit].
+    ///
+    /// [`encoding`]: ../struct.Settings.html#structfield.encoding
+    /// [implicit]: #method.implicit
+    #[inline]
+    pub fn raw(&self) -> &[u8] {
+        &self.input_raw
+    }
+
+    /// Returns `true` if the end tag is not present in the input and has been implied by the
+    /// parser.
+    #[inline]
+    pub fn implicit(&self) -> bool {
+        self.implicit
+    }
+
+    #[doc(hidden)]
     #[inline]
     pub fn set_name(&mut self, name: Bytes<'static>) {
         self.name = name;
         self.raw = None;
     }
 
+    /// Lowercases the name of the end tag, so it's serialized in the canonical form.
+    pub(crate) fn canonicalize(&mut self) {
+        if !self.implicit {
+            self.name = Bytes::from(Cow::Owned(self.name.to_ascii_lowercase()));
+            self.raw = None;
+        }
+    }
+
+    /// Inserts `content` before the end tag.
+    ///
+    /// Consequent calls to the method append `content` to the previously inserted content.
     #[inline]
-    fn raw(&self) -> Option<&Bytes> {
+    pub fn before(&mut self, content: &str, content_type: ContentType) {
+        self.mutations.before(content, content_type);
+    }
+
+    /// Inserts `content` after the end tag.
+    ///
+    /// Consequent calls to the method prepend `content` to the previously inserted content.
+    #[inline]
+    pub fn after(&mut self, content: &str, content_type: ContentType) {
+        self.mutations.after(content, content_type);
+    }
+
+    /// Moves the end tag with its inserted content to the `content`, so the streaming handlers
+    /// of the inserted content are invoked once the `content` is written to the output.
+    pub(crate) fn serialize_into(mut self, content: &mut InsertedContent) {
+        content.append(std::mem::take(&mut self.mutations.content_before));
+
+        if !self.mutations.removed {
+            match self.output_raw() {
+                Some(raw) => content.push_bytes(raw),
+                None => self.serialize_from_parts(&mut |c| content.push_bytes(c)),
+            }
+        } else {
+            content.append(std::mem::take(&mut self.mutations.replacement));
+        }
+
+        content.append(std::mem::take(&mut self.mutations.content_after));
+    }
+
+    #[inline]
+    fn output_raw(&self) -> Option<&Bytes> {
         self.raw.as_ref()
     }
 
     #[inline]
     fn serialize_from_parts(&self, output_handler: &mut dyn FnMut(&[u8])) {
+        // NOTE: implicit end tags don't produce any output, unless the element has been
+        // renamed and, thus, its end can't be implied anymore.
+        if self.implicit && self.name.is_empty() {
+            return;
+        }
+
         output_handler(b"</");
         output_handler(&self.name);
         output_handler(b">");
@@ -54,6 +148,7 @@ impl Debug for EndTag<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("EndTag")
             .field("name", &self.name())
+            .field("implicit", &self.implicit())
             .finish()
     }
 }
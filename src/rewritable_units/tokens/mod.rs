@@ -1,7 +1,7 @@
 mod attributes;
 mod capturer;
 
-use super::Mutations;
+use super::{Mutations, SourceLocation};
 
 pub(super) use self::attributes::Attributes;
 pub use self::attributes::{Attribute, AttributeNameError};
@@ -13,33 +13,29 @@ pub trait Serialize {
 
 macro_rules! impl_serialize {
     ($Token:ident) => {
-        impl crate::rewritable_units::Serialize for $Token<'_> {
-            #[inline]
-            fn to_bytes(&self, output_handler: &mut dyn FnMut(&[u8])) {
-                let Mutations {
-                    content_before,
-                    replacement,
-                    content_after,
-                    removed,
-                    ..
-                } = &self.mutations;
+        impl $Token<'_> {
+            /// Serializes the token with its mutations, invoking the streaming handlers of the
+            /// inserted content.
+            pub(crate) fn serialize(
+                &mut self,
+                output_handler: &mut dyn FnMut(&[u8]),
+            ) -> Result<(), Box<dyn std::error::Error>> {
+                let encoding = self.mutations.encoding();
 
-                if !content_before.is_empty() {
-                    output_handler(content_before);
-                }
+                self.mutations
+                    .content_before
+                    .write(encoding, output_handler)?;
 
-                if !removed {
-                    match self.raw() {
+                if !self.mutations.removed {
+                    match self.output_raw() {
                         Some(raw) => output_handler(raw),
                         None => self.serialize_from_parts(output_handler),
                     }
-                } else if !replacement.is_empty() {
-                    output_handler(replacement);
+                } else {
+                    self.mutations.replacement.write(encoding, output_handler)?;
                 }
 
-                if !content_after.is_empty() {
-                    output_handler(content_after);
-                }
+                self.mutations.content_after.write(encoding, output_handler)
             }
         }
     };
@@ -48,12 +44,14 @@ macro_rules! impl_serialize {
 mod comment;
 mod doctype;
 mod end_tag;
+mod processing_instruction;
 mod start_tag;
 mod text_chunk;
 
 pub use self::comment::{Comment, CommentTextError};
 pub use self::doctype::Doctype;
 pub use self::end_tag::EndTag;
+pub use self::processing_instruction::{ProcessingInstruction, ProcessingInstructionDataError};
 pub use self::start_tag::StartTag;
 pub use self::text_chunk::TextChunk;
 
@@ -64,17 +62,40 @@ pub enum Token<'i> {
     StartTag(StartTag<'i>),
     EndTag(EndTag<'i>),
     Doctype(Doctype<'i>),
+    ProcessingInstruction(ProcessingInstruction<'i>),
 }
 
-impl Serialize for Token<'_> {
+impl Token<'_> {
+    /// Serializes the token with its mutations, invoking the streaming handlers of the inserted
+    /// content.
+    #[inline]
+    pub(crate) fn serialize(
+        &mut self,
+        output_handler: &mut dyn FnMut(&[u8]),
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            Token::TextChunk(t) => t.serialize(output_handler),
+            Token::Comment(t) => t.serialize(output_handler),
+            Token::StartTag(t) => t.serialize(output_handler),
+            Token::EndTag(t) => t.serialize(output_handler),
+            Token::ProcessingInstruction(t) => t.serialize(output_handler),
+            Token::Doctype(t) => {
+                t.to_bytes(output_handler);
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Sets the location of the token in the input. The location is only exposed for the tokens
+    /// that back the elements, text chunks and comments.
     #[inline]
-    fn to_bytes(&self, output_handler: &mut dyn FnMut(&[u8])) {
+    pub(crate) fn set_source_location(&mut self, source_location: SourceLocation) {
         match self {
-            Token::TextChunk(t) => t.to_bytes(output_handler),
-            Token::Comment(t) => t.to_bytes(output_handler),
-            Token::StartTag(t) => t.to_bytes(output_handler),
-            Token::EndTag(t) => t.to_bytes(output_handler),
-            Token::Doctype(t) => t.to_bytes(output_handler),
+            Token::TextChunk(t) => t.set_source_location(source_location),
+            Token::Comment(t) => t.set_source_location(source_location),
+            Token::StartTag(t) => t.set_source_location(source_location),
+            Token::EndTag(_) | Token::Doctype(_) | Token::ProcessingInstruction(_) => (),
         }
     }
 }
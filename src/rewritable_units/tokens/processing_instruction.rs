@@ -0,0 +1,371 @@
+use super::{Mutations, Token};
+use crate::base::Bytes;
+use encoding_rs::Encoding;
+use std::any::Any;
+use std::fmt::{self, Debug};
+use thiserror::Error;
+
+/// An error that occurs when invalid value is provided for the data of a processing instruction.
+#[derive(Error, Debug, PartialEq, Copy, Clone)]
+pub enum ProcessingInstructionDataError {
+    /// The provided value contains the `>` character that preemptively closes the processing
+    /// instruction.
+    #[error("Processing instruction data shouldn't contain the `>` character.")]
+    ClosingBracket,
+
+    /// The provided value contains a character that can't be represented in the document's [`encoding`].
+    ///
+    /// [`encoding`]: ../struct.Settings.html#structfield.encoding
+    #[error("Processing instruction data contains a character that can't be represented in the document's character encoding.")]
+    UnencodableCharacter,
+}
+
+/// A processing instruction, e.g. the `<?xml version="1.0"?>` XML declaration.
+///
+/// HTML doesn't have processing instructions and parses them as bogus comments, which are passed
+/// to the comment handlers. If any of the [`processing_instructions`] handlers are registered,
+/// such comments are passed to them instead, with the text of the comment split into the target
+/// and the data of the processing instruction.
+///
+/// # Example
+/// ```
+/// use lol_html::{rewrite_str, DocumentContentHandlers, RewriteStrSettings};
+///
+/// let html = rewrite_str(
+///     r#"<?xml version="1.0" encoding="ISO-8859-1"?><p>Hello</p>"#,
+///     RewriteStrSettings {
+///         document_content_handlers: vec![DocumentContentHandlers::default()
+///             .processing_instructions(|pi| {
+///                 if pi.target() == "xml" {
+///                     assert_eq!(pi.data(), r#"version="1.0" encoding="ISO-8859-1""#);
+///
+///                     pi.remove();
+///                 }
+///
+///                 Ok(())
+///             })],
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(html, "<p>Hello</p>");
+/// ```
+///
+/// [`processing_instructions`]: ../struct.DocumentContentHandlers.html#method.processing_instructions
+pub struct ProcessingInstruction<'i> {
+    target: Bytes<'i>,
+    data: Bytes<'i>,
+    closed_with_question_mark: bool,
+    raw: Option<Bytes<'i>>,
+    encoding: &'static Encoding,
+    mutations: Mutations,
+    user_data: Box<dyn Any>,
+}
+
+impl<'i> ProcessingInstruction<'i> {
+    pub(super) fn new_token(
+        target: Bytes<'i>,
+        data: Bytes<'i>,
+        closed_with_question_mark: bool,
+        raw: Bytes<'i>,
+        encoding: &'static Encoding,
+    ) -> Token<'i> {
+        Token::ProcessingInstruction(ProcessingInstruction {
+            target,
+            data,
+            closed_with_question_mark,
+            raw: Some(raw),
+            encoding,
+            mutations: Mutations::new(encoding),
+            user_data: Box::new(()),
+        })
+    }
+
+    /// Returns the target of the processing instruction, e.g. `xml` for the XML declaration.
+    #[inline]
+    pub fn target(&self) -> String {
+        self.target.as_string(self.encoding)
+    }
+
+    /// Returns the data of the processing instruction, i.e. everything after the target except
+    /// for the leading whitespace and the closing `?` character. Trailing whitespace is a part
+    /// of the data, e.g. the data of `<?php echo 1 ?>` is `echo 1 `.
+    #[inline]
+    pub fn data(&self) -> String {
+        self.data.as_string(self.encoding)
+    }
+
+    /// Sets the data of the processing instruction.
+    ///
+    /// The processing instruction is serialized with a single space between the target and the
+    /// data, and keeps the original closing sequence, i.e. `?>` or just `>`.
+    #[inline]
+    pub fn set_data(&mut self, data: &str) -> Result<(), ProcessingInstructionDataError> {
+        if data.contains('>') {
+            return Err(ProcessingInstructionDataError::ClosingBracket);
+        }
+
+        match Bytes::from_str_without_replacements(data, self.encoding) {
+            Ok(data) => {
+                self.data = data.into_owned();
+                self.raw = None;
+
+                Ok(())
+            }
+            Err(_) => Err(ProcessingInstructionDataError::UnencodableCharacter),
+        }
+    }
+
+    /// Inserts `content` before the processing instruction.
+    ///
+    /// Consequent calls to the method append `content` to the previously inserted content.
+    #[inline]
+    pub fn before(&mut self, content: &str, content_type: crate::rewritable_units::ContentType) {
+        self.mutations.before(content, content_type);
+    }
+
+    /// Inserts `content` after the processing instruction.
+    ///
+    /// Consequent calls to the method prepend `content` to the previously inserted content.
+    #[inline]
+    pub fn after(&mut self, content: &str, content_type: crate::rewritable_units::ContentType) {
+        self.mutations.after(content, content_type);
+    }
+
+    /// Replaces the processing instruction with the `content`.
+    ///
+    /// Consequent calls to the method overwrite previous replacement content.
+    #[inline]
+    pub fn replace(&mut self, content: &str, content_type: crate::rewritable_units::ContentType) {
+        self.mutations.replace(content, content_type);
+    }
+
+    /// Removes the processing instruction.
+    #[inline]
+    pub fn remove(&mut self) {
+        self.mutations.remove();
+    }
+
+    /// Returns `true` if the processing instruction has been replaced or removed.
+    #[inline]
+    pub fn removed(&self) -> bool {
+        self.mutations.removed()
+    }
+
+    #[inline]
+    fn output_raw(&self) -> Option<&Bytes> {
+        self.raw.as_ref()
+    }
+
+    #[inline]
+    fn serialize_from_parts(&self, output_handler: &mut dyn FnMut(&[u8])) {
+        output_handler(b"<?");
+        output_handler(&self.target);
+
+        if !self.data.is_empty() {
+            output_handler(b" ");
+            output_handler(&self.data);
+        }
+
+        output_handler(if self.closed_with_question_mark {
+            b"?>"
+        } else {
+            b">"
+        });
+    }
+}
+
+impl_serialize!(ProcessingInstruction);
+impl_user_data!(ProcessingInstruction<'_>);
+
+impl Debug for ProcessingInstruction<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProcessingInstruction")
+            .field("target", &self.target())
+            .field("data", &self.data())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::*;
+    use crate::html_content::*;
+    use crate::rewritable_units::test_utils::*;
+    use crate::*;
+    use encoding_rs::{Encoding, UTF_8};
+
+    #[cfg(feature = "all_encodings")]
+    use encoding_rs::EUC_JP;
+
+    fn rewrite_pi(
+        html: &[u8],
+        encoding: &'static Encoding,
+        mut handler: impl FnMut(&mut ProcessingInstruction),
+    ) -> String {
+        let mut handler_called = false;
+
+        let output = rewrite_html(
+            html,
+            encoding,
+            vec![],
+            vec![
+                DocumentContentHandlers::default().processing_instructions(|pi| {
+                    handler_called = true;
+                    handler(pi);
+                    Ok(())
+                }),
+            ],
+        );
+
+        assert!(handler_called);
+
+        output
+    }
+
+    #[test]
+    fn target_and_data() {
+        for (html, target, data) in &[
+            (r#"<?xml version="1.0"?>"#, "xml", r#"version="1.0""#),
+            (
+                "<?xml-stylesheet \t href=a.xsl ?>",
+                "xml-stylesheet",
+                "href=a.xsl ",
+            ),
+            ("<?php echo 1 >", "php", "echo 1 "),
+            ("<?foo?>", "foo", ""),
+            ("<?>", "", ""),
+        ] {
+            rewrite_pi(html.as_bytes(), UTF_8, |pi| {
+                assert_eq!(pi.target(), *target);
+                assert_eq!(pi.data(), *data);
+            });
+        }
+    }
+
+    #[test]
+    fn closing_bracket_in_data() {
+        rewrite_pi(b"<?xml version=1.0?>", UTF_8, |pi| {
+            let err = pi.set_data("foo > bar").unwrap_err();
+
+            assert_eq!(err, ProcessingInstructionDataError::ClosingBracket);
+        });
+    }
+
+    #[cfg(feature = "all_encodings")]
+    #[test]
+    fn encoding_unmappable_chars_in_data() {
+        rewrite_pi(b"<?xml version=1.0?>", EUC_JP, |pi| {
+            let err = pi.set_data("foo\u{00F8}bar").unwrap_err();
+
+            assert_eq!(err, ProcessingInstructionDataError::UnencodableCharacter);
+        });
+    }
+
+    #[test]
+    fn comment_handlers() {
+        let html = r#"<?xml version="1.0"?><!-- foo -->"#;
+        let mut comments = vec![];
+
+        let output = rewrite_html(
+            html.as_bytes(),
+            UTF_8,
+            vec![],
+            vec![doc_comments!(|c| {
+                comments.push(c.text());
+
+                Ok(())
+            })],
+        );
+
+        assert_eq!(output, html);
+        assert_eq!(comments, [r#"?xml version="1.0"?"#, " foo "]);
+    }
+
+    mod serialization {
+        use super::*;
+
+        const HTML: &str = r#"<?xml version="1.0" encoding="é"?>"#;
+
+        macro_rules! test {
+            ($handler:expr, $expected:expr) => {
+                for (html, enc) in encoded(HTML) {
+                    assert_eq!(rewrite_pi(&html, enc, $handler), $expected);
+                }
+            };
+        }
+
+        #[test]
+        fn parsed() {
+            test!(|_| {}, r#"<?xml version="1.0" encoding="é"?>"#);
+        }
+
+        #[test]
+        fn modified_data() {
+            test!(
+                |pi| {
+                    pi.set_data(r#"version="1.1""#).unwrap();
+                },
+                r#"<?xml version="1.1"?>"#
+            );
+
+            test!(
+                |pi| {
+                    pi.set_data("").unwrap();
+                },
+                "<?xml?>"
+            );
+        }
+
+        #[test]
+        fn modified_data_keeps_closing_sequence() {
+            for (html, data, expected) in &[
+                ("<?php echo 1 >", "echo 2 ", "<?php echo 2 >"),
+                ("<?php \t echo 1 ?>", "echo 2", "<?php echo 2?>"),
+                ("<?php?>", "echo 2 ", "<?php echo 2 ?>"),
+                ("<?>", "echo 2", "<? echo 2>"),
+            ] {
+                let output = rewrite_pi(html.as_bytes(), UTF_8, |pi| {
+                    pi.set_data(data).unwrap();
+                });
+
+                assert_eq!(output, *expected);
+            }
+        }
+
+        #[test]
+        fn with_prepends_and_appends() {
+            test!(
+                |pi| {
+                    pi.before("<!doctype html>", ContentType::Html);
+                    pi.after("<foo>", ContentType::Text);
+                },
+                r#"<!doctype html><?xml version="1.0" encoding="é"?>&lt;foo&gt;"#
+            );
+        }
+
+        #[test]
+        fn removed() {
+            test!(
+                |pi| {
+                    assert!(!pi.removed());
+
+                    pi.remove();
+
+                    assert!(pi.removed());
+                },
+                ""
+            );
+        }
+
+        #[test]
+        fn replaced() {
+            test!(
+                |pi| {
+                    pi.replace("<!-- xml -->", ContentType::Html);
+                },
+                "<!-- xml -->"
+            );
+        }
+    }
+}
@@ -1,9 +1,12 @@
 use super::{Attribute, AttributeNameError, Attributes};
 use super::{Mutations, Serialize, Token};
 use crate::base::Bytes;
-use crate::html::Namespace;
+use crate::html::{LocalNameHash, Namespace};
+use crate::rewritable_units::SourceLocation;
 use encoding_rs::Encoding;
+use std::borrow::Cow;
 use std::fmt::{self, Debug};
+use std::ops::Range;
 
 pub struct StartTag<'i> {
     name: Bytes<'i>,
@@ -11,7 +14,9 @@ pub struct StartTag<'i> {
     ns: Namespace,
     self_closing: bool,
     raw: Option<Bytes<'i>>,
+    input_raw: Bytes<'i>,
     encoding: &'static Encoding,
+    source_location: SourceLocation,
     pub mutations: Mutations,
 }
 
@@ -29,8 +34,10 @@ impl<'i> StartTag<'i> {
             attributes,
             ns,
             self_closing,
-            raw: Some(raw),
+            raw: Some(raw.clone()),
+            input_raw: raw,
             encoding,
+            source_location: SourceLocation::default(),
             mutations: Mutations::new(encoding),
         })
     }
@@ -42,7 +49,23 @@ impl<'i> StartTag<'i> {
 
     #[inline]
     pub fn name(&self) -> String {
-        self.name.as_lowercase_string(self.encoding)
+        self.name_str().into_owned()
+    }
+
+    #[inline]
+    pub fn name_str(&self) -> Cow<'static, str> {
+        self.name.as_interned_lowercase_string(self.encoding)
+    }
+
+    #[inline]
+    pub fn local_name_hash(&self) -> LocalNameHash {
+        let mut hash = LocalNameHash::new();
+
+        for &ch in self.name.iter() {
+            hash.update(ch);
+        }
+
+        hash
     }
 
     #[inline]
@@ -61,6 +84,53 @@ impl<'i> StartTag<'i> {
         &*self.attributes
     }
 
+    #[inline]
+    pub fn source_location(&self) -> &SourceLocation {
+        &self.source_location
+    }
+
+    #[inline]
+    pub(crate) fn set_source_location(&mut self, source_location: SourceLocation) {
+        self.source_location = source_location;
+    }
+
+    #[inline]
+    pub fn input_raw_bytes(&self) -> &[u8] {
+        &self.input_raw
+    }
+
+    #[inline]
+    pub fn raw_bytes(&self) -> Option<&[u8]> {
+        self.output_raw().map(|raw| &**raw)
+    }
+
+    /// Replaces the `range` of the raw tag bytes with the `replacement`. Returns `false` if
+    /// the tag doesn't have a raw representation anymore or the range is out of bounds.
+    pub fn patch_raw(&mut self, range: Range<usize>, replacement: &[u8]) -> bool {
+        let raw = match self.output_raw() {
+            Some(raw) if range.start <= range.end && range.end <= raw.len() => raw,
+            _ => return false,
+        };
+
+        let mut patched = Vec::with_capacity(raw.len() - range.len() + replacement.len());
+
+        patched.extend_from_slice(&raw[..range.start]);
+        patched.extend_from_slice(replacement);
+        patched.extend_from_slice(&raw[range.end..]);
+
+        self.raw = Some(Bytes::from(Cow::Owned(patched)));
+
+        true
+    }
+
+    #[inline]
+    pub fn attributes_mut(&mut self) -> &mut [Attribute<'i>] {
+        // NOTE: attribute values can be modified in place via the returned
+        // slice, so we can't rely on the raw bytes of the tag anymore.
+        self.raw = None;
+        self.attributes.as_mut_slice()
+    }
+
     #[inline]
     pub fn set_attribute(&mut self, name: &str, value: &str) -> Result<(), AttributeNameError> {
         self.attributes.set_attribute(name, value, self.encoding)?;
@@ -70,10 +140,54 @@ impl<'i> StartTag<'i> {
     }
 
     #[inline]
-    pub fn remove_attribute(&mut self, name: &str) {
-        if self.attributes.remove_attribute(name) {
+    pub fn remove_attribute(&mut self, name: &str) -> bool {
+        let removed = self.attributes.remove_attribute(name);
+
+        if removed {
+            self.raw = None;
+        }
+
+        removed
+    }
+
+    #[inline]
+    pub fn remove_attributes_where(
+        &mut self,
+        predicate: impl FnMut(&Attribute<'i>) -> bool,
+    ) -> Vec<Attribute<'i>> {
+        let removed = self.attributes.remove_where(predicate);
+
+        if !removed.is_empty() {
             self.raw = None;
         }
+
+        removed
+    }
+
+    #[inline]
+    pub fn retain_attributes_mut(
+        &mut self,
+        predicate: impl FnMut(&mut Attribute<'i>) -> bool,
+    ) -> Vec<String> {
+        let modified_names = self.attributes.retain_mut(predicate);
+
+        // NOTE: the predicate can modify attribute values in place.
+        if !modified_names.is_empty() || self.attributes.iter().any(Attribute::is_modified) {
+            self.raw = None;
+        }
+
+        modified_names
+    }
+
+    /// Lowercases the tag name and sorts the attributes, so the tag is serialized in the
+    /// canonical form.
+    pub fn canonicalize(&mut self) {
+        if self.name.iter().any(u8::is_ascii_uppercase) {
+            self.name = Bytes::from(Cow::Owned(self.name.to_ascii_lowercase()));
+        }
+
+        self.attributes.canonicalize();
+        self.raw = None;
     }
 
     #[inline]
@@ -83,7 +197,7 @@ impl<'i> StartTag<'i> {
     }
 
     #[inline]
-    fn raw(&self) -> Option<&Bytes> {
+    fn output_raw(&self) -> Option<&Bytes> {
         self.raw.as_ref()
     }
 
@@ -115,7 +229,13 @@ impl<'i> StartTag<'i> {
     }
 
     #[cfg(test)]
-    pub fn raw_attributes(&self) -> (&'i Bytes<'i>, crate::parser::SharedAttributeBuffer) {
+    pub fn raw_attributes(
+        &self,
+    ) -> (
+        &'i Bytes<'i>,
+        &'i [crate::parser::AttributeOutline],
+        crate::parser::AttributeNamesBloom,
+    ) {
         self.attributes.raw_attributes()
     }
 }
@@ -125,7 +245,7 @@ impl_serialize!(StartTag);
 impl Debug for StartTag<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("StartTag")
-            .field("name", &self.name())
+            .field("name", &self.name_str())
             .field("attributes", &self.attributes())
             .field("self_closing", &self.self_closing)
             .finish()
@@ -1,9 +1,13 @@
 use super::{Mutations, Token};
 use crate::base::Bytes;
 use crate::html::TextType;
+use crate::rewritable_units::{
+    InsertedContent, MatchInfo, ScriptContentError, ScriptType, SourceLocation,
+};
 use encoding_rs::Encoding;
 use std::any::Any;
 use std::borrow::Cow;
+use std::error::Error;
 use std::fmt::{self, Debug};
 
 /// An HTML text node chunk.
@@ -59,6 +63,8 @@ pub struct TextChunk<'i> {
     last_in_text_node: bool,
     encoding: &'static Encoding,
     mutations: Mutations,
+    match_info: Option<MatchInfo>,
+    source_location: SourceLocation,
     user_data: Box<dyn Any>,
 }
 
@@ -75,6 +81,8 @@ impl<'i> TextChunk<'i> {
             last_in_text_node,
             encoding,
             mutations: Mutations::new(encoding),
+            match_info: None,
+            source_location: SourceLocation::default(),
             user_data: Box::new(()),
         })
     }
@@ -233,6 +241,102 @@ impl<'i> TextChunk<'i> {
         self.mutations.replace(content, content_type);
     }
 
+    /// Replaces the text chunk with the `content` that is made safe for the `<script>` element of
+    /// the `script_type`.
+    ///
+    /// Script data is not HTML-escaped, so neither [`ContentType::Text`] nor
+    /// [`ContentType::Html`] can be used to safely insert arbitrary content into a script. The
+    /// method escapes `<` in JSON data blocks and rejects the content that would break out of
+    /// classic and module scripts. Refer to [`ScriptType::make_safe`] for the details.
+    ///
+    /// On error, the text chunk is left intact.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lol_html::{rewrite_str, text, RewriteStrSettings};
+    /// use lol_html::html_content::ScriptType;
+    ///
+    /// let html = rewrite_str(
+    ///     r#"<script type="application/json">{}</script>"#,
+    ///     RewriteStrSettings {
+    ///         element_content_handlers: vec![
+    ///             text!("script", |t| {
+    ///                 if !t.last_in_text_node() {
+    ///                     t.replace_script_safe(r#"{"html":"</script>"}"#, ScriptType::Json)?;
+    ///                 }
+    ///
+    ///                 Ok(())
+    ///             })
+    ///         ],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(
+    ///     html,
+    ///     r#"<script type="application/json">{"html":"\u003c/script>"}</script>"#
+    /// );
+    /// ```
+    ///
+    /// [`ContentType::Text`]: enum.ContentType.html#variant.Text
+    /// [`ContentType::Html`]: enum.ContentType.html#variant.Html
+    /// [`ScriptType::make_safe`]: enum.ScriptType.html#method.make_safe
+    #[inline]
+    pub fn replace_script_safe(
+        &mut self,
+        content: &str,
+        script_type: ScriptType,
+    ) -> Result<(), ScriptContentError> {
+        let content = script_type.make_safe(content)?;
+
+        self.mutations
+            .replace(&content, crate::rewritable_units::ContentType::Html);
+
+        Ok(())
+    }
+
+    /// Appends the raw `bytes` to the replacement of the text chunk.
+    ///
+    /// If the text chunk hasn't been replaced yet, the first call replaces it, and the consequent
+    /// calls append to the replacement. So, the replacement can be produced piece by piece without
+    /// allocating a string for the whole chunk. The bytes are written to the output as is: they are neither HTML-escaped nor
+    /// converted, so they should be in the encoding of the document.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lol_html::{rewrite_str, doc_text, RewriteStrSettings};
+    ///
+    /// let html = rewrite_str(
+    ///     r#"<div>Foo & Bar</div>"#,
+    ///     RewriteStrSettings {
+    ///         document_content_handlers: vec![
+    ///             doc_text!(|t| {
+    ///                 let text = t.as_str().to_owned();
+    ///
+    ///                 for (i, part) in text.split('&').enumerate() {
+    ///                     if i > 0 {
+    ///                         t.emit_raw(b"&amp;");
+    ///                     }
+    ///
+    ///                     t.emit_raw(part.as_bytes());
+    ///                 }
+    ///
+    ///                 Ok(())
+    ///             })
+    ///         ],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(html, r#"<div>Foo &amp; Bar</div>"#);
+    /// ```
+    #[inline]
+    pub fn emit_raw(&mut self, bytes: &[u8]) {
+        self.mutations.emit_raw(bytes);
+    }
+
     /// Removes the text chunk.
     #[inline]
     pub fn remove(&mut self) {
@@ -245,8 +349,97 @@ impl<'i> TextChunk<'i> {
         self.mutations.removed()
     }
 
+    /// Returns the information about the selector the content handler is invoked for, or `None`
+    /// if the handler is a document content handler.
+    ///
+    /// Refer to [`MatchInfo`] for more information.
+    ///
+    /// [`MatchInfo`]: struct.MatchInfo.html
     #[inline]
-    fn raw(&self) -> Option<&Bytes> {
+    pub fn match_info(&self) -> Option<&MatchInfo> {
+        self.match_info.as_ref()
+    }
+
+    #[inline]
+    pub(crate) fn set_match_info(&mut self, match_info: Option<MatchInfo>) {
+        self.match_info = match_info;
+    }
+
+    /// Returns the location of the chunk in the input.
+    ///
+    /// Refer to [`SourceLocation`] for more information.
+    ///
+    /// The last chunk in a text node has an empty range of bytes if it has empty textual content.
+    ///
+    /// [`SourceLocation`]: struct.SourceLocation.html
+    #[inline]
+    pub fn source_location(&self) -> &SourceLocation {
+        &self.source_location
+    }
+
+    #[inline]
+    pub(crate) fn set_source_location(&mut self, source_location: SourceLocation) {
+        self.source_location = source_location;
+    }
+
+    /// Invokes the `handler` for a chunk with the whole `text` of the text node the chunk is
+    /// the last one in, and applies the modifications made to it in place of the chunk.
+    ///
+    /// If the chunk has already been removed, the output of the coalesced chunk is inserted
+    /// before it. The coalesced chunk is located from the `start_location` of the text node to
+    /// the end of this chunk.
+    pub(crate) fn with_coalesced_text(
+        &mut self,
+        text: String,
+        start_location: &SourceLocation,
+        handler: impl FnOnce(&mut TextChunk) -> Result<(), Box<dyn Error>>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut coalesced = TextChunk {
+            text: text.into(),
+            text_type: self.text_type,
+            last_in_text_node: self.last_in_text_node,
+            encoding: self.encoding,
+            mutations: Mutations::new(self.encoding),
+            match_info: self.match_info.clone(),
+            source_location: start_location.extend_to(&self.source_location),
+            user_data: Box::new(()),
+        };
+
+        handler(&mut coalesced)?;
+
+        let Mutations {
+            content_before,
+            replacement,
+            content_after,
+            removed,
+            ..
+        } = coalesced.mutations;
+
+        let content = if removed {
+            replacement
+        } else {
+            let mut content = InsertedContent::default();
+
+            content.push_bytes(&Bytes::from_str(&coalesced.text, self.encoding));
+            content
+        };
+
+        self.mutations.content_before.append(content_before);
+
+        if self.mutations.removed {
+            self.mutations.content_before.append(content);
+            self.mutations.content_before.append(content_after);
+        } else {
+            self.mutations.replacement = content;
+            self.mutations.remove();
+            self.mutations.content_after.prepend(content_after);
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    fn output_raw(&self) -> Option<&Bytes> {
         None
     }
 
@@ -272,6 +465,7 @@ impl Debug for TextChunk<'_> {
 
 #[cfg(test)]
 mod tests {
+    use crate::errors::*;
     use crate::html_content::*;
     use crate::rewritable_units::test_utils::*;
     use crate::*;
@@ -313,6 +507,37 @@ mod tests {
         });
     }
 
+    #[test]
+    fn replace_script_safe() {
+        let output = rewrite_text_chunk(b"<script>foo()</script>", UTF_8, |c| {
+            if c.last_in_text_node() {
+                return;
+            }
+
+            assert_eq!(
+                c.replace_script_safe("'</script>'", ScriptType::Classic),
+                Err(ScriptContentError::EndTag)
+            );
+
+            assert!(!c.removed());
+
+            c.replace_script_safe("bar()", ScriptType::Module).unwrap();
+
+            assert!(c.removed());
+        });
+
+        assert_eq!(output, "<script>bar()</script>");
+
+        let output = rewrite_text_chunk(b"<script>[]</script>", UTF_8, |c| {
+            if !c.last_in_text_node() {
+                c.replace_script_safe(r#"["<!--<script>"]"#, ScriptType::Json)
+                    .unwrap();
+            }
+        });
+
+        assert_eq!(output, r#"<script>["\u003c!--\u003cscript>"]</script>"#);
+    }
+
     mod serialization {
         use super::*;
 
@@ -384,6 +609,24 @@ mod tests {
             );
         }
 
+        #[test]
+        fn emitted_raw() {
+            test!(
+                |c| {
+                    skip_eof_chunk!(c);
+                    c.before("<before>", ContentType::Html);
+                    c.after("<after>", ContentType::Html);
+                    c.replace("<div></div>", ContentType::Html);
+
+                    c.emit_raw(b"<foo>");
+                    c.emit_raw(b" & bar");
+
+                    assert!(c.removed());
+                },
+                "<before><div></div><foo> & bar<after>"
+            );
+        }
+
         #[test]
         fn replaced_with_text() {
             test!(
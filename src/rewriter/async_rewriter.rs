@@ -0,0 +1,440 @@
+use super::{
+    HandlerTypes, HtmlRewriter, LocalHandlerTypes, RewritingError, Settings, SettingsError,
+};
+use crate::base::{SendSharing, Shared};
+use crate::rewritable_units::{ContentType, StreamingHandler, StreamingHandlerSink};
+use crate::transform_stream::OutputSink;
+use encoding_rs::Encoding;
+use futures::future::{join_all, BoxFuture};
+use std::error::Error;
+use std::fmt::{self, Debug};
+use std::future::Future;
+
+type ContentResult = Result<String, Box<dyn Error + Send + Sync>>;
+
+struct PendingContent {
+    future: BoxFuture<'static, ContentResult>,
+    content_type: ContentType,
+    encoding: &'static Encoding,
+}
+
+enum Segment {
+    Bytes(Vec<u8>),
+    Pending(PendingContent),
+}
+
+/// Produces the content that is inserted into the output of an [`AsyncHtmlRewriter`] once a
+/// future resolves, e.g. a fragment fetched from a database or a cache.
+///
+/// Content handlers are invoked synchronously, as the rewritable units borrow the input of the
+/// rewriter and can't be held across the `.await` points. So, a handler examines the unit, starts
+/// the lookup and inserts the future content with one of the `streaming_*` methods of the
+/// [`Element`], using a handler constructed with [`handler`]. The futures are awaited
+/// concurrently by [`AsyncHtmlRewriter::write`], and the output that follows the content is held
+/// back until the content is available.
+///
+/// The content is shared by the handlers and the rewriter it's provided to, so it can be cloned
+/// into any number of handlers. The content handlers constructed with it only produce output
+/// when used with the rewriter. The content is `Send`, so it can be used by the `Send` handlers
+/// of the settings constructed with [`Settings::new_send`].
+///
+/// Note that there are no `async` variants of the element, text and comment content handlers:
+/// a content handler can't await anything itself, it can only insert the content produced by a
+/// future. The changes to the rewritable units that depend on the result of a lookup, e.g.
+/// setting an attribute or removing an element, are not supported.
+///
+/// Available with the `futures` feature.
+///
+/// # Example
+/// ```
+/// use futures::executor::block_on;
+/// use lol_html::html_content::ContentType;
+/// use lol_html::{element, AsyncContent, AsyncHtmlRewriter, Settings};
+/// use std::error::Error;
+///
+/// async fn fetch_recommendations(user: String) -> Result<String, Box<dyn Error + Send + Sync>> {
+///     Ok(format!("<li>Recommended for {}</li>", user))
+/// }
+///
+/// let content = AsyncContent::new();
+/// let mut output = vec![];
+///
+/// block_on(async {
+///     let mut rewriter = AsyncHtmlRewriter::try_new(
+///         Settings {
+///             element_content_handlers: vec![
+///                 element!("ul[data-user]", |el| {
+///                     let user = el.get_attribute("data-user").unwrap();
+///
+///                     el.streaming_append(
+///                         content.handler(fetch_recommendations(user), ContentType::Html)
+///                     );
+///
+///                     Ok(())
+///                 })
+///             ],
+///             ..Settings::default()
+///         },
+///         &content,
+///         |c: &[u8]| output.extend_from_slice(c)
+///     ).unwrap();
+///
+///     rewriter.write(br#"<ul data-user="alice"></ul>"#).await.unwrap();
+///     rewriter.end().await.unwrap();
+/// });
+///
+/// assert_eq!(
+///     String::from_utf8(output).unwrap(),
+///     r#"<ul data-user="alice"><li>Recommended for alice</li></ul>"#
+/// );
+/// ```
+///
+/// [`AsyncHtmlRewriter`]: struct.AsyncHtmlRewriter.html
+/// [`AsyncHtmlRewriter::write`]: struct.AsyncHtmlRewriter.html#method.write
+/// [`Element`]: html_content/struct.Element.html
+/// [`handler`]: #method.handler
+/// [`Settings::new_send`]: struct.Settings.html#method.new_send
+#[derive(Clone, Default)]
+pub struct AsyncContent {
+    // NOTE: the segments are pushed by the streaming handlers, which are always `Send`.
+    segments: Shared<Vec<Segment>, SendSharing>,
+}
+
+impl AsyncContent {
+    /// Creates a new shared content.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Constructs a streaming handler that inserts the output of the `content` future.
+    ///
+    /// The content is HTML-escaped if the `content_type` is [`ContentType::Text`]. An error
+    /// returned by the future stops the rewriting, as for any other content handler.
+    ///
+    /// The future is owned by the handler until the handler is invoked, so the future of a
+    /// handler that is never invoked, e.g. because the element has been removed, is dropped
+    /// along with the handler without being awaited.
+    ///
+    /// [`ContentType::Text`]: html_content/enum.ContentType.html#variant.Text
+    pub fn handler(
+        &self,
+        content: impl Future<Output = ContentResult> + Send + 'static,
+        content_type: ContentType,
+    ) -> Box<dyn StreamingHandler> {
+        let segments = Shared::clone(&self.segments);
+
+        Box::new(
+            move |sink: &mut StreamingHandlerSink| -> Result<(), Box<dyn Error>> {
+                segments.lock().push(Segment::Pending(PendingContent {
+                    future: Box::pin(content),
+                    content_type,
+                    encoding: sink.encoding(),
+                }));
+
+                Ok(())
+            },
+        )
+    }
+
+    fn push_bytes(&self, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+
+        let mut segments = self.segments.lock();
+
+        match segments.last_mut() {
+            Some(Segment::Bytes(last)) => last.extend_from_slice(bytes),
+            _ => segments.push(Segment::Bytes(bytes.to_vec())),
+        }
+    }
+
+    #[inline]
+    fn take_segments(&self) -> Vec<Segment> {
+        std::mem::take(&mut *self.segments.lock())
+    }
+}
+
+struct AsyncContentSink(AsyncContent);
+
+impl OutputSink for AsyncContentSink {
+    #[inline]
+    fn handle_chunk(&mut self, chunk: &[u8]) {
+        // NOTE: the finalizing chunk is written by the `AsyncHtmlRewriter` itself.
+        self.0.push_bytes(chunk);
+    }
+}
+
+/// A streaming HTML rewriter which content handlers can insert the content produced by futures.
+///
+/// The rewriter is driven the same way as the [`HtmlRewriter`], except that [`write`] and
+/// [`end`] are `async`. Refer to [`AsyncContent`] documentation for an example.
+///
+/// Same as the [`HtmlRewriter`], the rewriter constructed with the settings of
+/// [`Settings::new_send`] is `Send` as long as its output sink is, so its futures can be
+/// spawned on a multi-threaded executor.
+///
+/// Available with the `futures` feature.
+///
+/// [`HtmlRewriter`]: struct.HtmlRewriter.html
+/// [`write`]: #method.write
+/// [`end`]: #method.end
+/// [`AsyncContent`]: struct.AsyncContent.html
+/// [`Settings::new_send`]: struct.Settings.html#method.new_send
+pub struct AsyncHtmlRewriter<'h, O: OutputSink, H: HandlerTypes = LocalHandlerTypes> {
+    rewriter: HtmlRewriter<'h, AsyncContentSink, H>,
+    content: AsyncContent,
+    output_sink: O,
+    poisoned: bool,
+}
+
+impl<'h, O: OutputSink, H: HandlerTypes> AsyncHtmlRewriter<'h, O, H> {
+    /// Constructs a new rewriter with the provided `settings` that writes the output to the
+    /// `output_sink`. The content handlers of the `settings` insert the future content with
+    /// the handlers constructed by the `content`.
+    pub fn try_new<'s>(
+        settings: Settings<'h, 's, H>,
+        content: &AsyncContent,
+        output_sink: O,
+    ) -> Result<Self, SettingsError> {
+        let rewriter = HtmlRewriter::try_new(settings, AsyncContentSink(content.clone()))?;
+
+        Ok(AsyncHtmlRewriter {
+            rewriter,
+            content: content.clone(),
+            output_sink,
+            poisoned: false,
+        })
+    }
+
+    /// Writes a chunk of input data to the rewriter, awaiting the content inserted by the
+    /// content handlers before the output is written to the output sink.
+    ///
+    /// # Panics
+    ///  * If previous invocation of the method returned a [`RewritingError`]
+    ///    (these errors are unrecovarable).
+    ///  * If called after [`end`].
+    ///
+    /// [`RewritingError`]: errors/enum.RewritingError.html
+    /// [`end`]: #method.end
+    pub async fn write(&mut self, data: &[u8]) -> Result<(), RewritingError> {
+        assert!(
+            !self.poisoned,
+            "Attempt to use the AsyncHtmlRewriter after a fatal error."
+        );
+
+        self.rewriter.write(data)?;
+        self.flush().await
+    }
+
+    /// Finalizes the rewriting process, awaiting the remaining content.
+    ///
+    /// # Panics
+    ///  * If previous invocation of [`write`] returned a [`RewritingError`] (these errors
+    ///    are unrecovarable).
+    ///  * If called twice.
+    ///
+    /// [`RewritingError`]: errors/enum.RewritingError.html
+    /// [`write`]: #method.write
+    pub async fn end(&mut self) -> Result<(), RewritingError> {
+        assert!(
+            !self.poisoned,
+            "Attempt to use the AsyncHtmlRewriter after a fatal error."
+        );
+
+        self.rewriter.end()?;
+        self.flush().await?;
+
+        // NOTE: output the finalizing chunk.
+        self.output_sink.handle_chunk(&[]);
+
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), RewritingError> {
+        let mut segments = self.content.take_segments();
+
+        // NOTE: the futures are awaited concurrently, but their content is written in the order
+        // of the output.
+        let results = join_all(segments.iter_mut().filter_map(|segment| match segment {
+            Segment::Pending(pending) => Some(&mut pending.future),
+            Segment::Bytes(_) => None,
+        }))
+        .await;
+
+        let mut results = results.into_iter();
+
+        for segment in segments {
+            match segment {
+                Segment::Bytes(bytes) => self.output_sink.handle_chunk(&bytes),
+                Segment::Pending(pending) => {
+                    // NOTE: there is a result for each pending segment, so it's safe to unwrap.
+                    let content = match results.next().unwrap() {
+                        Ok(content) => content,
+                        Err(e) => {
+                            self.poisoned = true;
+
+                            return Err(RewritingError::ContentHandlerError(e));
+                        }
+                    };
+
+                    let output_sink = &mut self.output_sink;
+
+                    StreamingHandlerSink::new(pending.encoding, &mut |c| {
+                        output_sink.handle_chunk(c)
+                    })
+                    .write_str(&content, pending.content_type);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<O: OutputSink, H: HandlerTypes> Debug for AsyncHtmlRewriter<'_, O, H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AsyncHtmlRewriter")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::future::ready;
+
+    fn rewrite(
+        html: &[u8],
+        mut replacement: impl FnMut(String) -> ContentResult,
+    ) -> (String, Result<(), RewritingError>) {
+        let content = AsyncContent::new();
+        let mut output = vec![];
+
+        let res = block_on(async {
+            let mut rewriter = AsyncHtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: vec![element!("[data-id]", |el| {
+                        let id = el.get_attribute("data-id").unwrap();
+
+                        el.streaming_replace(
+                            content.handler(ready(replacement(id)), ContentType::Text),
+                        );
+
+                        Ok(())
+                    })],
+                    ..Settings::default()
+                },
+                &content,
+                |c: &[u8]| output.extend_from_slice(c),
+            )
+            .unwrap();
+
+            rewriter.write(html).await?;
+            rewriter.end().await
+        });
+
+        (String::from_utf8(output).unwrap(), res)
+    }
+
+    #[test]
+    fn content_is_written_in_order() {
+        let (output, res) = rewrite(
+            br#"<div><b data-id="1"></b>foo<i data-id="2"></i></div>"#,
+            |id| Ok(format!("<{}>", id)),
+        );
+
+        assert!(res.is_ok());
+        assert_eq!(output, "<div>&lt;1&gt;foo&lt;2&gt;</div>");
+    }
+
+    #[test]
+    fn content_error() {
+        let (output, res) = rewrite(br#"<div><b data-id="1"></b></div>"#, |_| {
+            Err("Lookup failed".into())
+        });
+
+        assert_eq!(output, "<div>");
+
+        match res {
+            Err(RewritingError::ContentHandlerError(e)) => {
+                assert_eq!(e.to_string(), "Lookup failed")
+            }
+            res => panic!("Expected content handler error, got: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn handler_is_not_invoked() {
+        let content = AsyncContent::new();
+        let mut output = vec![];
+
+        block_on(async {
+            let mut rewriter = AsyncHtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: vec![element!("b", |el| {
+                        el.streaming_append(content.handler(
+                            async { panic!("The future shouldn't be awaited") },
+                            ContentType::Text,
+                        ));
+
+                        el.remove();
+
+                        Ok(())
+                    })],
+                    ..Settings::default()
+                },
+                &content,
+                |c: &[u8]| output.extend_from_slice(c),
+            )
+            .unwrap();
+
+            rewriter.write(b"<div><b>foo</b></div>").await.unwrap();
+            rewriter.end().await.unwrap();
+        });
+
+        assert_eq!(String::from_utf8(output).unwrap(), "<div></div>");
+    }
+
+    #[test]
+    fn send_handler_types() {
+        use std::sync::{Arc, Mutex};
+
+        fn assert_send<T: Send>(_: &T) {}
+
+        let content = AsyncContent::new();
+        let handler_content = content.clone();
+        let output = Arc::new(Mutex::new(vec![]));
+        let sink_output = Arc::clone(&output);
+
+        let mut rewriter = AsyncHtmlRewriter::try_new(
+            Settings {
+                element_content_handlers: vec![element!("b", move |el| {
+                    el.streaming_append(
+                        handler_content.handler(ready(Ok("foo".into())), ContentType::Text),
+                    );
+
+                    Ok(())
+                })],
+                ..Settings::new_send()
+            },
+            &content,
+            move |c: &[u8]| sink_output.lock().unwrap().extend_from_slice(c),
+        )
+        .unwrap();
+
+        let rewriting = async move {
+            rewriter.write(b"<b></b>").await?;
+            rewriter.end().await
+        };
+
+        assert_send(&rewriting);
+        block_on(rewriting).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output.lock().unwrap().clone()).unwrap(),
+            "<b>foo</b>"
+        );
+    }
+}
@@ -0,0 +1,328 @@
+use super::{
+    DocumentContentHandlers, ElementContentHandlers, EncodingError, HandlerTypes, HtmlRewriter,
+    LocalHandlerTypes, MemorySettings, SendHandlerTypes, Settings, SettingsError,
+};
+use crate::selectors_vm::Selector;
+use crate::transform_stream::OutputSink;
+use thiserror::Error;
+
+#[cfg(feature = "basic_selectors")]
+use super::settings::{HandlerResult, IntoHandler};
+#[cfg(feature = "basic_selectors")]
+use crate::rewritable_units::Element;
+#[cfg(feature = "basic_selectors")]
+use crate::selectors_vm::SelectorError;
+
+/// An error that occurs when a rewriter is built with invalid options.
+#[derive(Error, Debug, PartialEq, Copy, Clone)]
+pub enum RewriterBuilderError {
+    /// One of the selectors is invalid. Refer to [`SelectorError`] for more information.
+    ///
+    /// [`SelectorError`]: enum.SelectorError.html
+    #[cfg(feature = "basic_selectors")]
+    #[error("{0}")]
+    Selector(SelectorError),
+
+    /// The encoding is invalid. Refer to [`EncodingError`] for more information.
+    ///
+    /// [`EncodingError`]: enum.EncodingError.html
+    #[error("{0}")]
+    Encoding(EncodingError),
+
+    /// The settings specify both the [`parse_ahead_queue_capacity`] and the
+    /// [`spill_settings`]. Refer to [`SettingsError::SpillingWithParseAhead`] for more
+    /// information.
+    ///
+    /// [`parse_ahead_queue_capacity`]: ../struct.Settings.html#structfield.parse_ahead_queue_capacity
+    /// [`spill_settings`]: ../struct.Settings.html#structfield.spill_settings
+    /// [`SettingsError::SpillingWithParseAhead`]: enum.SettingsError.html#variant.SpillingWithParseAhead
+    #[cfg(feature = "parse_ahead")]
+    #[error("Tokens can't be spilled when the input is parsed ahead.")]
+    SpillingWithParseAhead,
+}
+
+/// Builds an [`HtmlRewriter`] with fluent methods, as an alternative to constructing the
+/// [`Settings`] with a struct literal.
+///
+/// The selectors are parsed as the handlers are added. The first error is reported by
+/// [`build`], so the builder methods can be chained without checking each of them.
+///
+/// # Example
+/// ```
+/// use lol_html::{doc_comments, RewriterBuilder};
+///
+/// let mut output = vec![];
+///
+/// {
+///     let mut rewriter = RewriterBuilder::new()
+///         .on("a[href]", |el| {
+///             el.set_attribute("rel", "nofollow")?;
+///
+///             Ok(())
+///         })
+///         .on_document(doc_comments!(|c| {
+///             c.remove();
+///
+///             Ok(())
+///         }))
+///         .encoding("windows-1252")
+///         .memory_limit(64 * 1024)
+///         .build(|c: &[u8]| output.extend_from_slice(c))
+///         .unwrap();
+///
+///     rewriter.write(b"<!-- Links --><a href=/foo>Foo</a>").unwrap();
+///     rewriter.end().unwrap();
+/// }
+///
+/// assert_eq!(String::from_utf8(output).unwrap(), r#"<a href=/foo rel="nofollow">Foo</a>"#);
+/// ```
+///
+/// [`HtmlRewriter`]: struct.HtmlRewriter.html
+/// [`Settings`]: struct.Settings.html
+/// [`build`]: #method.build
+pub struct RewriterBuilder<'h, 's, H: HandlerTypes = LocalHandlerTypes> {
+    settings: Settings<'h, 's, H>,
+    element_content_handlers: Vec<(Selector, ElementContentHandlers<'h, H>)>,
+    error: Option<RewriterBuilderError>,
+}
+
+impl RewriterBuilder<'_, '_> {
+    /// Creates a builder with the default settings.
+    #[inline]
+    pub fn new() -> Self {
+        Self::from_settings(Settings::default())
+    }
+}
+
+impl RewriterBuilder<'_, '_, SendHandlerTypes> {
+    /// Creates a builder with the default settings for the handlers that are `Send`. Refer to
+    /// [`Settings::new_send`] for more information.
+    ///
+    /// [`Settings::new_send`]: struct.Settings.html#method.new_send
+    #[inline]
+    pub fn new_send() -> Self {
+        Self::from_settings(Settings::new_send())
+    }
+}
+
+impl<'h, 's, H: HandlerTypes> RewriterBuilder<'h, 's, H> {
+    /// Creates a builder that starts from the `settings`, e.g. to set the options that don't
+    /// have a builder method.
+    #[inline]
+    pub fn from_settings(settings: Settings<'h, 's, H>) -> Self {
+        RewriterBuilder {
+            settings,
+            element_content_handlers: Vec::default(),
+            error: None,
+        }
+    }
+
+    /// Adds a `handler` for the elements matched by the `selector`.
+    #[cfg(feature = "basic_selectors")]
+    #[inline]
+    pub fn on(
+        self,
+        selector: &str,
+        handler: impl FnMut(&mut Element<'_, '_, H>) -> HandlerResult
+            + IntoHandler<H::ElementHandler<'h>>,
+    ) -> Self {
+        self.on_content(selector, ElementContentHandlers::default().element(handler))
+    }
+
+    /// Adds the `handlers` for the elements matched by the `selector` and their inner content.
+    #[cfg(feature = "basic_selectors")]
+    pub fn on_content(mut self, selector: &str, handlers: ElementContentHandlers<'h, H>) -> Self {
+        match selector.parse() {
+            Ok(selector) => self.element_content_handlers.push((selector, handlers)),
+            Err(e) => {
+                self.error.get_or_insert(RewriterBuilderError::Selector(e));
+            }
+        }
+
+        self
+    }
+
+    /// Adds the `handlers` for the content of the whole document.
+    #[inline]
+    pub fn on_document(mut self, handlers: DocumentContentHandlers<'h, H>) -> Self {
+        self.settings.document_content_handlers.push(handlers);
+
+        self
+    }
+
+    /// Sets the [`encoding`] of the input.
+    ///
+    /// [`encoding`]: struct.Settings.html#structfield.encoding
+    #[inline]
+    pub fn encoding(mut self, encoding: &'s str) -> Self {
+        self.settings.encoding = encoding;
+
+        self
+    }
+
+    /// Sets the [`max_allowed_memory_usage`] of the rewriter.
+    ///
+    /// [`max_allowed_memory_usage`]: struct.MemorySettings.html#structfield.max_allowed_memory_usage
+    #[inline]
+    pub fn memory_limit(mut self, max_allowed_memory_usage: usize) -> Self {
+        self.settings.memory_settings.max_allowed_memory_usage = max_allowed_memory_usage;
+
+        self
+    }
+
+    /// Sets the [`memory_settings`] of the rewriter, overriding the previously set
+    /// [`memory_limit`].
+    ///
+    /// [`memory_settings`]: struct.Settings.html#structfield.memory_settings
+    /// [`memory_limit`]: #method.memory_limit
+    #[inline]
+    pub fn memory_settings(mut self, memory_settings: MemorySettings) -> Self {
+        self.settings.memory_settings = memory_settings;
+
+        self
+    }
+
+    /// Sets the [`strict`] mode of the rewriter.
+    ///
+    /// [`strict`]: struct.Settings.html#structfield.strict
+    #[inline]
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.settings.strict = strict;
+
+        self
+    }
+
+    /// Builds the rewriter that writes the output to the `output_sink`.
+    ///
+    /// Returns the first error encountered by the builder methods, or an error if the encoding
+    /// is invalid or some of the selectors require the lookahead, which is disabled.
+    pub fn build<O: OutputSink>(
+        self,
+        output_sink: O,
+    ) -> Result<HtmlRewriter<'h, O, H>, RewriterBuilderError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+
+        // NOTE: the selectors are only borrowed while the rewriter is constructed.
+        let (selectors, handlers): (Vec<_>, Vec<_>) =
+            self.element_content_handlers.into_iter().unzip();
+
+        let mut settings = self.settings;
+
+        settings
+            .element_content_handlers
+            .extend(selectors.iter().zip(handlers));
+
+        HtmlRewriter::try_new(settings, output_sink).map_err(|e| match e {
+            SettingsError::Encoding(e) => RewriterBuilderError::Encoding(e),
+            #[cfg(feature = "basic_selectors")]
+            SettingsError::Selector(e) => RewriterBuilderError::Selector(e),
+            #[cfg(feature = "parse_ahead")]
+            SettingsError::SpillingWithParseAhead => RewriterBuilderError::SpillingWithParseAhead,
+            SettingsError::TemplateMismatch => {
+                unreachable!("The template is compiled from the same settings")
+            }
+        })
+    }
+}
+
+impl Default for RewriterBuilder<'_, '_> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html_content::ContentType;
+
+    fn rewrite(builder: RewriterBuilder, html: &str) -> String {
+        let mut output = vec![];
+
+        {
+            let mut rewriter = builder
+                .build(|c: &[u8]| output.extend_from_slice(c))
+                .unwrap();
+
+            rewriter.write(html.as_bytes()).unwrap();
+            rewriter.end().unwrap();
+        }
+
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn handlers() {
+        let selector = "span".parse().unwrap();
+
+        let settings = Settings {
+            element_content_handlers: vec![(
+                &selector,
+                ElementContentHandlers::default().element(|el| {
+                    el.remove();
+
+                    Ok(())
+                }),
+            )],
+            ..Settings::default()
+        };
+
+        let builder = RewriterBuilder::from_settings(settings)
+            .on("div", |el| {
+                el.prepend("Foo", ContentType::Text);
+
+                Ok(())
+            })
+            .on_content(
+                "div",
+                ElementContentHandlers::default().text(|t| {
+                    let text = t.as_str().to_uppercase();
+
+                    t.replace(&text, ContentType::Text);
+
+                    Ok(())
+                }),
+            )
+            .on_document(doc_comments!(|c| {
+                c.remove();
+
+                Ok(())
+            }));
+
+        assert_eq!(
+            rewrite(builder, "<div><!-- Bar --><span>Baz</span>Qux</div>"),
+            "<div>FooQUX</div>"
+        );
+    }
+
+    #[test]
+    fn invalid_selector() {
+        let err = RewriterBuilder::new()
+            .on("div", |_| Ok(()))
+            .on("p:visited", |_| Ok(()))
+            .on("<", |_| Ok(()))
+            .build(|_: &[u8]| {})
+            .unwrap_err();
+
+        assert_eq!(
+            err,
+            RewriterBuilderError::Selector(SelectorError::UnsupportedPseudoClassOrElement)
+        );
+    }
+
+    #[test]
+    fn invalid_encoding() {
+        let err = RewriterBuilder::new()
+            .encoding("utf-16")
+            .build(|_: &[u8]| {})
+            .unwrap_err();
+
+        assert_eq!(
+            err,
+            RewriterBuilderError::Encoding(EncodingError::NonAsciiCompatibleEncoding)
+        );
+    }
+}
@@ -0,0 +1,299 @@
+use crate::transform_stream::OutputSink;
+
+/// A filter of the raw input bytes that runs before the input is parsed, e.g. to strip a
+/// server-injected preamble or an anti-hijacking prefix.
+///
+/// The filter is specified with [`Settings::input_filter`]. It receives the chunks of the
+/// input as they are written to the rewriter, so the filter is responsible for handling the
+/// sequences split between the chunks.
+///
+/// # Note
+///
+/// For the convenience the trait is implemented for closures that don't hold back any bytes.
+///
+/// # Example
+/// ```
+/// use lol_html::{HtmlRewriter, Settings};
+///
+/// // NOTE: removes the NUL bytes injected by a broken upstream.
+/// let strip_nul = |chunk: &[u8], output: &mut Vec<u8>| {
+///     output.extend(chunk.iter().filter(|&&b| b != 0));
+/// };
+///
+/// let mut output = vec![];
+///
+/// {
+///     let mut rewriter = HtmlRewriter::try_new(
+///         Settings {
+///             input_filter: Some(Box::new(strip_nul)),
+///             ..Settings::default()
+///         },
+///         |c: &[u8]| output.extend_from_slice(c)
+///     ).unwrap();
+///
+///     rewriter.write(b"<p>Foo\0</p>").unwrap();
+///     rewriter.end().unwrap();
+/// }
+///
+/// assert_eq!(String::from_utf8(output).unwrap(), "<p>Foo</p>");
+/// ```
+///
+/// [`Settings::input_filter`]: struct.Settings.html#structfield.input_filter
+pub trait InputFilter {
+    /// Filters the `chunk` of the input, appending the bytes that should be parsed to the
+    /// `output`.
+    fn filter(&mut self, chunk: &[u8], output: &mut Vec<u8>);
+
+    /// Appends the bytes held back by the filter to the `output` once the document has ended,
+    /// and resets the filter for the next document.
+    ///
+    /// Documents end when the rewriter is ended or a new document is started with
+    /// [`HtmlRewriter::start_new_document`].
+    ///
+    /// [`HtmlRewriter::start_new_document`]: struct.HtmlRewriter.html#method.start_new_document
+    #[inline]
+    fn end(&mut self, _output: &mut Vec<u8>) {}
+}
+
+impl<F: FnMut(&[u8], &mut Vec<u8>)> InputFilter for F {
+    #[inline]
+    fn filter(&mut self, chunk: &[u8], output: &mut Vec<u8>) {
+        self(chunk, output);
+    }
+}
+
+/// An [`InputFilter`] that strips the given prefix from the start of each document, e.g. the
+/// `)]}'` guard of the responses that are protected from JSON hijacking.
+///
+/// The documents that don't start with the prefix are left intact.
+///
+/// # Example
+/// ```
+/// use lol_html::{HtmlRewriter, Settings, StripPrefix};
+///
+/// let mut output = vec![];
+///
+/// {
+///     let mut rewriter = HtmlRewriter::try_new(
+///         Settings {
+///             input_filter: Some(Box::new(StripPrefix::new(b")]}'\n"))),
+///             ..Settings::default()
+///         },
+///         |c: &[u8]| output.extend_from_slice(c)
+///     ).unwrap();
+///
+///     rewriter.write(b")]").unwrap();
+///     rewriter.write(b"}'\n<!doctype html>").unwrap();
+///     rewriter.end().unwrap();
+/// }
+///
+/// assert_eq!(String::from_utf8(output).unwrap(), "<!doctype html>");
+/// ```
+///
+/// [`InputFilter`]: trait.InputFilter.html
+#[derive(Debug, Clone)]
+pub struct StripPrefix {
+    prefix: Vec<u8>,
+    /// The number of bytes of the prefix that have been matched so far, or `None` once the
+    /// input has diverged from the prefix or the prefix has been stripped.
+    matched: Option<usize>,
+}
+
+impl StripPrefix {
+    /// Creates the filter that strips the `prefix`.
+    #[inline]
+    pub fn new(prefix: &[u8]) -> Self {
+        StripPrefix {
+            prefix: prefix.to_vec(),
+            matched: Some(0),
+        }
+    }
+}
+
+impl InputFilter for StripPrefix {
+    fn filter(&mut self, chunk: &[u8], output: &mut Vec<u8>) {
+        let matched = match self.matched {
+            Some(matched) => matched,
+            None => return output.extend_from_slice(chunk),
+        };
+
+        let rest = &self.prefix[matched..];
+        let len = rest.len().min(chunk.len());
+
+        if chunk[..len] != rest[..len] {
+            // NOTE: the input is not prefixed, so the bytes held back are released.
+            output.extend_from_slice(&self.prefix[..matched]);
+            output.extend_from_slice(chunk);
+            self.matched = None;
+        } else if len == rest.len() {
+            output.extend_from_slice(&chunk[len..]);
+            self.matched = None;
+        } else {
+            self.matched = Some(matched + len);
+        }
+    }
+
+    fn end(&mut self, output: &mut Vec<u8>) {
+        if let Some(matched) = self.matched {
+            output.extend_from_slice(&self.prefix[..matched]);
+        }
+
+        self.matched = Some(0);
+    }
+}
+
+/// A filter of the output bytes that runs between the serializer and the output sink, e.g. to
+/// frame the output as server-sent events or to cap its size.
+///
+/// The filter is specified with [`Settings::output_filter`]. It receives the chunks of the
+/// output as they are produced by the rewriter. The output of all the documents rewritten with
+/// [`HtmlRewriter::start_new_document`] is filtered as a single stream.
+///
+/// # Note
+///
+/// For the convenience the trait is implemented for closures that don't hold back any bytes.
+///
+/// # Example
+/// ```
+/// use lol_html::{HtmlRewriter, OutputFilter, Settings};
+///
+/// // NOTE: caps the output at 16 bytes.
+/// struct Cap(usize);
+///
+/// impl OutputFilter for Cap {
+///     fn filter(&mut self, chunk: &[u8], output: &mut Vec<u8>) {
+///         let len = chunk.len().min(self.0);
+///
+///         output.extend_from_slice(&chunk[..len]);
+///         self.0 -= len;
+///     }
+/// }
+///
+/// let mut output = vec![];
+///
+/// {
+///     let mut rewriter = HtmlRewriter::try_new(
+///         Settings {
+///             output_filter: Some(Box::new(Cap(16))),
+///             ..Settings::default()
+///         },
+///         |c: &[u8]| output.extend_from_slice(c)
+///     ).unwrap();
+///
+///     rewriter.write(b"<p>Lorem ipsum dolor sit amet</p>").unwrap();
+///     rewriter.end().unwrap();
+/// }
+///
+/// assert_eq!(String::from_utf8(output).unwrap(), "<p>Lorem ipsum d");
+/// ```
+///
+/// [`Settings::output_filter`]: struct.Settings.html#structfield.output_filter
+/// [`HtmlRewriter::start_new_document`]: struct.HtmlRewriter.html#method.start_new_document
+pub trait OutputFilter {
+    /// Filters the `chunk` of the output, appending the bytes that should be passed to the
+    /// output sink to the `output`.
+    fn filter(&mut self, chunk: &[u8], output: &mut Vec<u8>);
+
+    /// Appends the bytes held back by the filter, e.g. the closing frame, to the `output` once
+    /// the rewriter has ended.
+    #[inline]
+    fn end(&mut self, _output: &mut Vec<u8>) {}
+}
+
+impl<F: FnMut(&[u8], &mut Vec<u8>)> OutputFilter for F {
+    #[inline]
+    fn filter(&mut self, chunk: &[u8], output: &mut Vec<u8>) {
+        self(chunk, output);
+    }
+}
+
+/// The output sink that passes the output through the `OutputFilter`, if it's specified.
+pub(super) struct FilteredSink<'h, O: OutputSink> {
+    sink: O,
+    filter: Option<Box<dyn OutputFilter + Send + 'h>>,
+    buffer: Vec<u8>,
+}
+
+impl<'h, O: OutputSink> FilteredSink<'h, O> {
+    #[inline]
+    pub fn new(
+        sink: O,
+        filter: Option<Box<dyn OutputFilter + Send + 'h>>,
+        preallocated_buffer_size: usize,
+    ) -> Self {
+        let buffer = if filter.is_some() {
+            Vec::with_capacity(preallocated_buffer_size)
+        } else {
+            Vec::new()
+        };
+
+        FilteredSink {
+            sink,
+            filter,
+            buffer,
+        }
+    }
+}
+
+impl<O: OutputSink> OutputSink for FilteredSink<'_, O> {
+    fn handle_chunk(&mut self, chunk: &[u8]) {
+        let filter = match self.filter {
+            Some(ref mut filter) => filter,
+            None => return self.sink.handle_chunk(chunk),
+        };
+
+        // NOTE: the last chunk of the output has zero length.
+        if chunk.is_empty() {
+            filter.end(&mut self.buffer);
+        } else {
+            filter.filter(chunk, &mut self.buffer);
+        }
+
+        if !self.buffer.is_empty() {
+            self.sink.handle_chunk(&self.buffer);
+            self.buffer.clear();
+        }
+
+        if chunk.is_empty() {
+            self.sink.handle_chunk(&[]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(filter: &mut impl InputFilter, chunks: &[&[u8]]) -> Vec<u8> {
+        let mut output = vec![];
+
+        for chunk in chunks {
+            filter.filter(chunk, &mut output);
+        }
+
+        filter.end(&mut output);
+
+        output
+    }
+
+    #[test]
+    fn strip_prefix() {
+        let mut strip_prefix = StripPrefix::new(b"abc");
+
+        for &(chunks, expected) in &[
+            (&[&b"abc<p>"[..]][..], &b"<p>"[..]),
+            (&[b"a", b"", b"bc", b"<p>"], b"<p>"),
+            (&[b"ab", b"d<p>"], b"abd<p>"),
+            (&[b"<p>abc"], b"<p>abc"),
+            (&[b"ab"], b"ab"),
+            (&[b"abc", b"abc"], b"abc"),
+        ] {
+            assert_eq!(
+                filter(&mut strip_prefix, chunks),
+                expected,
+                "Chunks: {:?}",
+                chunks
+            );
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use crate::html::TextType;
+use crate::rewritable_units::{ContentType, TextChunk, Token};
+
+/// Returns `true` if whitespace is significant in the content of the element.
+#[inline]
+fn is_preformatted(name: &str) -> bool {
+    name == "pre" || name == "listing"
+}
+
+/// Brings the tokens to the canonical form for the [`canonical_output`] mode.
+///
+/// Tags are canonicalized by the tokens themselves. The canonicalizer tracks whether the text is
+/// in a preformatted element and collapses whitespace in the rest of the text. Runs of whitespace
+/// can be split between the text chunks, so the canonicalizer remembers if the previous chunk of
+/// the text node ended with whitespace.
+///
+/// [`canonical_output`]: struct.Settings.html#structfield.canonical_output
+#[derive(Default)]
+pub struct Canonicalizer {
+    preformatted_depth: usize,
+    after_whitespace: bool,
+}
+
+impl Canonicalizer {
+    fn collapse_whitespace(&mut self, text: &str) -> String {
+        let mut collapsed = String::with_capacity(text.len());
+
+        for c in text.chars() {
+            if c.is_ascii_whitespace() {
+                if !self.after_whitespace {
+                    collapsed.push(' ');
+                }
+
+                self.after_whitespace = true;
+            } else {
+                collapsed.push(c);
+                self.after_whitespace = false;
+            }
+        }
+
+        collapsed
+    }
+
+    fn handle_text_chunk(&mut self, chunk: &mut TextChunk) {
+        if chunk.text_type() == TextType::Data && self.preformatted_depth == 0 && !chunk.removed() {
+            let collapsed = self.collapse_whitespace(chunk.as_str());
+
+            if collapsed != chunk.as_str() {
+                chunk.replace(&collapsed, ContentType::Html);
+            }
+        }
+
+        if chunk.last_in_text_node() {
+            self.after_whitespace = false;
+        }
+    }
+
+    pub fn handle_token(&mut self, token: &mut Token) {
+        match token {
+            Token::StartTag(start_tag) => {
+                if is_preformatted(&start_tag.name_str()) {
+                    self.preformatted_depth += 1;
+                }
+
+                start_tag.canonicalize();
+            }
+            Token::EndTag(end_tag) => {
+                if is_preformatted(&end_tag.name()) {
+                    self.preformatted_depth = self.preformatted_depth.saturating_sub(1);
+                }
+
+                end_tag.canonicalize();
+            }
+            Token::TextChunk(chunk) => self.handle_text_chunk(chunk),
+            _ => (),
+        }
+    }
+
+    #[inline]
+    pub fn reset(&mut self) {
+        *self = Canonicalizer::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collapse_whitespace() {
+        let mut canonicalizer = Canonicalizer::default();
+
+        assert_eq!(canonicalizer.collapse_whitespace("a \n\t b  "), "a b ");
+        assert_eq!(canonicalizer.collapse_whitespace("\r\nc"), "c");
+        assert_eq!(canonicalizer.collapse_whitespace(" "), " ");
+        assert_eq!(canonicalizer.collapse_whitespace(""), "");
+    }
+}
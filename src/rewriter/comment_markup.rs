@@ -0,0 +1,182 @@
+use super::{
+    ElementContentHandlers, HandlerResult, HandlerTypes, HtmlRewriter, MemorySettings, Settings,
+};
+use crate::base::encoding::{decode_without_bom_handling, encode};
+use crate::base::{Shared, Sharing};
+use crate::rewritable_units::Comment;
+use crate::selectors_vm::Selector;
+use crate::transform_stream::OutputSink;
+use encoding_rs::Encoding;
+
+struct MarkupSink<S: Sharing>(Shared<Vec<u8>, S>);
+
+impl<S: Sharing> OutputSink for MarkupSink<S> {
+    #[inline]
+    fn handle_chunk(&mut self, chunk: &[u8]) {
+        self.0.lock().extend_from_slice(chunk);
+    }
+}
+
+/// The comment handler that re-tokenizes the comments containing markup and invokes the element
+/// content handlers for the elements in them.
+///
+/// The markup of each comment is rewritten as a separate document by a nested rewriter, which
+/// is limited by the same `max_allowed_memory_usage` as the rewriter the handler is added to.
+pub(super) struct CommentMarkup<'h, H: HandlerTypes> {
+    rewriter: HtmlRewriter<'h, MarkupSink<H::Sharing>, H>,
+    output: Shared<Vec<u8>, H::Sharing>,
+    encoding: &'static Encoding,
+    // NOTE: the markup of the comments that are split into chunks can't be re-tokenized
+    // as a whole, so such comments are left intact.
+    in_streamed_comment: bool,
+}
+
+impl<'h, H: HandlerTypes> CommentMarkup<'h, H> {
+    pub fn new(
+        element_content_handlers: Vec<(&Selector, ElementContentHandlers<'h, H>)>,
+        encoding: &'static Encoding,
+        max_allowed_memory_usage: usize,
+        strict: bool,
+    ) -> Self {
+        let output = Shared::new(Vec::default());
+
+        // NOTE: the encoding has been validated for the rewriter the handler
+        // is added to, so it's safe to unwrap here.
+        let rewriter = HtmlRewriter::try_new(
+            Settings {
+                element_content_handlers,
+                encoding: encoding.name(),
+                memory_settings: MemorySettings {
+                    max_allowed_memory_usage,
+                    ..MemorySettings::default()
+                },
+                strict,
+                ..Settings::new_with_handler_types()
+            },
+            MarkupSink(Shared::clone(&output)),
+        )
+        .unwrap();
+
+        CommentMarkup {
+            rewriter,
+            output,
+            encoding,
+            in_streamed_comment: false,
+        }
+    }
+
+    pub fn handle_comment(&mut self, c: &mut Comment) -> HandlerResult {
+        if self.in_streamed_comment || !c.last_in_comment() {
+            self.in_streamed_comment = !c.last_in_comment();
+
+            return Ok(());
+        }
+
+        let text = c.text();
+
+        if !text.contains('<') {
+            return Ok(());
+        }
+
+        let (bytes, _) = encode(&text, self.encoding);
+
+        self.rewriter.write(&bytes)?;
+        self.rewriter.start_new_document()?;
+
+        let markup = {
+            let mut output = self.output.lock();
+            let markup = decode_without_bom_handling(&output, self.encoding).into_owned();
+
+            output.clear();
+
+            markup
+        };
+
+        // NOTE: the comments whose markup hasn't been modified are left intact.
+        if markup != text {
+            c.set_text(&markup)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::RewritingError;
+    use crate::html_content::ContentType;
+    use crate::*;
+
+    fn rewrite(html: &str) -> String {
+        let mut output = vec![];
+
+        {
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    comment_content_handlers: vec![
+                        element!(".ad", |el| {
+                            el.remove();
+
+                            Ok(())
+                        }),
+                        element!("a[href]", |el| {
+                            el.set_attribute("rel", "nofollow")?;
+
+                            Ok(())
+                        }),
+                    ],
+                    ..Settings::default()
+                },
+                |c: &[u8]| output.extend_from_slice(c),
+            )
+            .unwrap();
+
+            rewriter.write(html.as_bytes()).unwrap();
+            rewriter.end().unwrap();
+        }
+
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn rewrite_comment_markup() {
+        assert_eq!(
+            rewrite(concat!(
+                "<p class=ad><!--[if IE]><div class=ad>Foo</div>",
+                "<a href=/bar>Bar</a><![endif]--></p>"
+            )),
+            concat!(
+                "<p class=ad><!--[if IE]>",
+                r#"<a href=/bar rel="nofollow">Bar</a><![endif]--></p>"#
+            )
+        );
+    }
+
+    #[test]
+    fn comments_without_markup_are_intact() {
+        let html = "<!-- Foo --><!-- <p>Bar</p> -->";
+
+        assert_eq!(rewrite(html), html);
+    }
+
+    #[test]
+    fn comment_closing_sequence() {
+        let html = "<!-- <b>Foo</b> -->";
+        let mut rewriter = HtmlRewriter::try_new(
+            Settings {
+                comment_content_handlers: vec![text!("b", |t| {
+                    t.replace("-->", ContentType::Html);
+
+                    Ok(())
+                })],
+                ..Settings::default()
+            },
+            |_: &[u8]| {},
+        )
+        .unwrap();
+
+        let err = rewriter.write(html.as_bytes()).unwrap_err();
+
+        assert!(matches!(err, RewritingError::ContentHandlerError(_)));
+    }
+}
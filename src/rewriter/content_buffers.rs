@@ -0,0 +1,348 @@
+use super::settings::{HandlerResult, HandlerTypes};
+use crate::base::{Shared, Sharing};
+use crate::memory::{MemoryLimitExceededError, SharedMemoryLimiter};
+use crate::rewritable_units::{Element, InnerHtml, InsertedContent};
+use crate::transform_stream::OutputSink;
+use encoding_rs::Encoding;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+pub(super) type SharedContentBuffers<S> = Shared<ContentBuffers<S>, S>;
+
+/// The ids of the buffers whose elements' start tags have been written to the output.
+///
+/// The buffers are started by the streaming handlers, which are `Send` whatever the handler
+/// types of the rewriter are. So, the handlers share the requests instead of the buffers, and
+/// the buffers start the requested ones before their content is accessed.
+#[derive(Default)]
+struct StartRequests {
+    pending: AtomicBool,
+    ids: Mutex<Vec<usize>>,
+}
+
+impl StartRequests {
+    fn push(&self, id: usize) {
+        self.ids
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(id);
+
+        self.pending.store(true, Ordering::Release);
+    }
+}
+
+/// The buffers of the inner content of the elements that have requested their inner HTML with
+/// [`Element::on_inner_html`], from the outermost element to the innermost one.
+///
+/// [`Element::on_inner_html`]: ../html_content/struct.Element.html#method.on_inner_html
+pub(super) struct ContentBuffers<S: Sharing> {
+    buffers: Vec<(usize, Vec<u8>)>,
+    next_id: usize,
+    /// The content that is written to the output as is before the next chunk of the output, e.g.
+    /// the content of the buffers that have been abandoned.
+    released: Vec<u8>,
+    memory_limiter: SharedMemoryLimiter<S>,
+    error: Option<MemoryLimitExceededError>,
+    start_requests: Arc<StartRequests>,
+}
+
+impl<S: Sharing> ContentBuffers<S> {
+    #[inline]
+    pub fn new_shared(memory_limiter: SharedMemoryLimiter<S>) -> SharedContentBuffers<S> {
+        Shared::new(ContentBuffers {
+            buffers: Vec::default(),
+            next_id: 0,
+            released: Vec::default(),
+            memory_limiter,
+            error: None,
+            start_requests: Arc::default(),
+        })
+    }
+
+    #[inline]
+    fn next_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// Starts the buffers requested by the streaming handlers since the last call.
+    #[inline]
+    fn start_requested(&mut self) {
+        if !self.start_requests.pending.load(Ordering::Acquire) {
+            return;
+        }
+
+        self.start_requests.pending.store(false, Ordering::Relaxed);
+
+        let ids = mem::take(
+            &mut *self
+                .start_requests
+                .ids
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+
+        for id in ids {
+            self.buffers.push((id, Vec::default()));
+        }
+    }
+
+    /// Removes the buffer with the `id`, returning its content. The content of the buffers
+    /// started after it, whose elements' end tags haven't been reached, is a part of the
+    /// returned content.
+    ///
+    /// Returns `None` if the buffer hasn't been started.
+    fn finish(&mut self, id: usize) -> Option<Vec<u8>> {
+        self.start_requested();
+
+        let idx = self.buffers.iter().position(|&(i, _)| i == id)?;
+        let mut content = Vec::default();
+
+        for (_, buffer) in self.buffers.drain(idx..) {
+            content.extend_from_slice(&buffer);
+        }
+
+        self.memory_limiter.lock().decrease_usage(content.len());
+
+        Some(content)
+    }
+
+    /// Abandons all the buffers, e.g. when a new document is started. Their content is written
+    /// to the output as is.
+    pub fn release_all(&mut self) {
+        self.start_requested();
+
+        if let Some(&(id, _)) = self.buffers.first() {
+            // NOTE: the buffer exists, so it's safe to unwrap here.
+            let content = self.finish(id).unwrap();
+
+            self.released.extend_from_slice(&content);
+        }
+    }
+
+    #[inline]
+    pub fn take_error(&mut self) -> Option<MemoryLimitExceededError> {
+        self.error.take()
+    }
+
+    /// Appends the `chunk` to the innermost buffer or, if there are no buffers, to the content
+    /// that is written to the output before the next chunk of the output.
+    fn write_through(&mut self, chunk: &[u8]) {
+        if !self.write(chunk) {
+            self.released.extend_from_slice(chunk);
+        }
+    }
+
+    /// Appends the `chunk` to the innermost buffer. Returns `false` if there are no buffers.
+    fn write(&mut self, chunk: &[u8]) -> bool {
+        self.start_requested();
+
+        let buffer = match self.buffers.last_mut() {
+            Some((_, buffer)) => buffer,
+            None => return false,
+        };
+
+        if let Err(e) = self.memory_limiter.lock().increase_usage(chunk.len()) {
+            self.error.get_or_insert(e);
+        }
+
+        buffer.extend_from_slice(chunk);
+
+        true
+    }
+}
+
+/// Makes the inner content of the `element` buffered, if the element has inner HTML handlers.
+///
+/// The buffering is requested by the streaming handler inserted right after the start tag of
+/// the element, so the buffered content is exactly the content that is written to the output after
+/// it. The returned handlers finish the buffering once the end tag has been reached.
+pub(super) fn buffer_inner_html<H: HandlerTypes>(
+    element: &mut Element<'_, '_, H>,
+    buffers: &SharedContentBuffers<H::Sharing>,
+) -> Option<InnerHtmlHandlers<H>> {
+    let handlers = element.take_inner_html_handlers();
+
+    if handlers.is_empty() {
+        return None;
+    }
+
+    let (id, start_requests) = {
+        let mut buffers = buffers.lock();
+
+        (buffers.next_id(), Arc::clone(&buffers.start_requests))
+    };
+
+    element.streaming_prepend(streaming!(move |_| {
+        start_requests.push(id);
+
+        Ok(())
+    }));
+
+    Some(InnerHtmlHandlers {
+        id,
+        handlers,
+        buffers: SharedContentBuffers::clone(buffers),
+    })
+}
+
+/// The inner HTML handlers of an element whose inner content is being buffered.
+pub(super) struct InnerHtmlHandlers<H: HandlerTypes> {
+    id: usize,
+    handlers: Vec<H::InnerHtmlHandler<'static>>,
+    buffers: SharedContentBuffers<H::Sharing>,
+}
+
+impl<H: HandlerTypes> InnerHtmlHandlers<H> {
+    /// Finishes the buffering and invokes the handlers for the buffered content. The result is
+    /// written to the buffer of the enclosing element or to the output.
+    ///
+    /// The `preceding_content`, i.e. the content inserted before the end tag of the element,
+    /// is a part of the buffered content.
+    pub fn invoke(
+        self,
+        preceding_content: &mut InsertedContent,
+        encoding: &'static Encoding,
+    ) -> HandlerResult {
+        let buffers = &self.buffers;
+
+        preceding_content.write(encoding, &mut |c| buffers.lock().write_through(c))?;
+
+        let content = match buffers.lock().finish(self.id) {
+            Some(content) => content,
+            // NOTE: the start tag hasn't been written to the output, e.g. if it's in
+            // the removed content of another element.
+            None => return Ok(()),
+        };
+
+        let mut inner_html = InnerHtml::new(content, encoding);
+
+        for handler in self.handlers {
+            handler(&mut inner_html)?;
+        }
+
+        buffers.lock().write_through(&inner_html.into_bytes());
+
+        Ok(())
+    }
+}
+
+/// The output sink that writes the output to the innermost content buffer, if there is one.
+pub(super) struct BufferingSink<O: OutputSink, S: Sharing> {
+    sink: O,
+    buffers: SharedContentBuffers<S>,
+}
+
+impl<O: OutputSink, S: Sharing> BufferingSink<O, S> {
+    #[inline]
+    pub fn new(sink: O, buffers: SharedContentBuffers<S>) -> Self {
+        BufferingSink { sink, buffers }
+    }
+}
+
+impl<O: OutputSink, S: Sharing> OutputSink for BufferingSink<O, S> {
+    fn handle_chunk(&mut self, chunk: &[u8]) {
+        let mut buffers = self.buffers.lock();
+
+        // NOTE: the last chunk of the output has zero length. The buffers whose elements'
+        // end tags have never been reached are released at the end of the output.
+        if chunk.is_empty() {
+            buffers.release_all();
+        }
+
+        if !buffers.released.is_empty() {
+            self.sink.handle_chunk(&buffers.released);
+            buffers.released.clear();
+        }
+
+        if !buffers.write(chunk) {
+            self.sink.handle_chunk(chunk);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::RewritingError;
+    use crate::html_content::ContentType;
+    use crate::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn rewrite(html: &str, selector: &str) -> (String, Vec<String>) {
+        let inner_htmls = Rc::new(RefCell::new(Vec::default()));
+
+        let output = rewrite_str(
+            html,
+            RewriteStrSettings {
+                element_content_handlers: vec![element!(selector, |el| {
+                    let inner_htmls = Rc::clone(&inner_htmls);
+
+                    el.on_inner_html(move |inner| {
+                        inner_htmls.borrow_mut().push(inner.as_str().to_owned());
+
+                        if inner.as_str() == "Bar" {
+                            inner.set_inner_content("<Baz>", ContentType::Text);
+                        }
+
+                        Ok(())
+                    });
+
+                    Ok(())
+                })],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        let inner_htmls = inner_htmls.borrow().clone();
+
+        (output, inner_htmls)
+    }
+
+    #[test]
+    fn nested_elements() {
+        let (output, inner_htmls) = rewrite("<div><p>Foo</p><p>Bar</p></div><p></p>", "div, p");
+
+        assert_eq!(output, "<div><p>Foo</p><p>&lt;Baz&gt;</p></div><p></p>");
+        assert_eq!(
+            inner_htmls,
+            ["Foo", "Bar", "<p>Foo</p><p>&lt;Baz&gt;</p>", ""]
+        );
+    }
+
+    #[test]
+    fn implicitly_closed_elements() {
+        let (output, inner_htmls) = rewrite("<div><p>Foo<p>Bar", "div, p");
+
+        assert_eq!(output, "<div><p>Foo<p>&lt;Baz&gt;");
+        assert_eq!(inner_htmls, ["Foo", "Bar", "<p>Foo<p>&lt;Baz&gt;"]);
+    }
+
+    #[test]
+    fn memory_limit() {
+        let mut rewriter = HtmlRewriter::try_new(
+            Settings {
+                element_content_handlers: vec![element!("div", |el| {
+                    el.on_inner_html(|_| Ok(()));
+
+                    Ok(())
+                })],
+                memory_settings: MemorySettings {
+                    max_allowed_memory_usage: 4096,
+                    ..MemorySettings::default()
+                },
+                ..Settings::default()
+            },
+            |_: &[u8]| {},
+        )
+        .unwrap();
+
+        rewriter.write(b"<div>").unwrap();
+
+        let err = rewriter.write(&[b'a'; 8192]).unwrap_err();
+
+        assert!(matches!(err, RewritingError::MemoryLimitExceeded(_)));
+    }
+}
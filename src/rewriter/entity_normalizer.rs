@@ -0,0 +1,214 @@
+use super::settings::{EntityNormalization, HandlerResult, NumericCharRefs};
+use crate::rewritable_units::{ContentType, TextChunk};
+use std::fmt::Write;
+use std::mem;
+
+// NOTE: the longest meaningful reference is `&#x10FFFF;`, the references that
+// are longer than that are not normalized, so we don't buffer them.
+const MAX_NUMERIC_REF_LEN: usize = 16;
+
+enum NumericRef {
+    Complete { len: usize, code_point: u32 },
+    Incomplete,
+    Invalid,
+}
+
+fn parse_numeric_ref(text: &str) -> NumericRef {
+    let bytes = text.as_bytes();
+
+    debug_assert!(bytes.starts_with(b"&#"));
+
+    let (radix, digits_start) = match bytes.get(2) {
+        Some(b'x') | Some(b'X') => (16, 3),
+        Some(_) => (10, 2),
+        None => return NumericRef::Incomplete,
+    };
+
+    let digit_count = bytes[digits_start..]
+        .iter()
+        .take_while(|b| (radix == 16 && b.is_ascii_hexdigit()) || b.is_ascii_digit())
+        .count();
+
+    let digits_end = digits_start + digit_count;
+
+    if digits_end >= MAX_NUMERIC_REF_LEN {
+        return NumericRef::Invalid;
+    }
+
+    match bytes.get(digits_end) {
+        Some(b';') if digit_count > 0 => {
+            match u32::from_str_radix(&text[digits_start..digits_end], radix) {
+                Ok(code_point) => NumericRef::Complete {
+                    len: digits_end + 1,
+                    code_point,
+                },
+                Err(_) => NumericRef::Invalid,
+            }
+        }
+        Some(_) => NumericRef::Invalid,
+        None => NumericRef::Incomplete,
+    }
+}
+
+/// Normalizes character references in the text chunks as specified by the [`EntityNormalization`].
+///
+/// Character references can be split between the text chunks, so the normalizer holds back the
+/// trailing incomplete reference of a chunk and outputs it with the next chunk of the text node.
+///
+/// [`EntityNormalization`]: struct.EntityNormalization.html
+pub struct EntityNormalizer {
+    normalization: EntityNormalization,
+    pending: String,
+}
+
+impl EntityNormalizer {
+    #[inline]
+    pub fn new(normalization: EntityNormalization) -> Self {
+        EntityNormalizer {
+            normalization,
+            pending: String::default(),
+        }
+    }
+
+    /// Writes the normalized `text` to the `output`. Returns the position of the incomplete
+    /// reference at the end of the text, if there is one and the text is not the last in the
+    /// text node.
+    fn normalize(&self, text: &str, is_last: bool, output: &mut String) -> Option<usize> {
+        let mut rest = text;
+
+        while let Some(pos) = rest.find('&') {
+            output.push_str(&rest[..pos]);
+            rest = &rest[pos..];
+
+            match rest.as_bytes().get(1) {
+                None if !is_last => return Some(text.len() - rest.len()),
+                Some(b'#') if self.normalization.numeric_char_refs != NumericCharRefs::Preserve => {
+                    match parse_numeric_ref(rest) {
+                        NumericRef::Complete { len, code_point } => {
+                            // NOTE: writing to a string never fails.
+                            let _ = match self.normalization.numeric_char_refs {
+                                NumericCharRefs::Hexadecimal => {
+                                    write!(output, "&#x{:X};", code_point)
+                                }
+                                _ => write!(output, "&#{};", code_point),
+                            };
+
+                            rest = &rest[len..];
+
+                            continue;
+                        }
+                        NumericRef::Incomplete if !is_last => {
+                            return Some(text.len() - rest.len());
+                        }
+                        _ => output.push('&'),
+                    }
+                }
+                Some(&b) if b == b'#' || b.is_ascii_alphanumeric() => output.push('&'),
+                _ if self.normalization.escape_bare_ampersands => output.push_str("&amp;"),
+                _ => output.push('&'),
+            }
+
+            rest = &rest[1..];
+        }
+
+        output.push_str(rest);
+
+        None
+    }
+
+    pub fn handle_text_chunk(&mut self, chunk: &mut TextChunk) -> HandlerResult {
+        if chunk.removed() || !chunk.text_type().allows_html_entities() {
+            if !self.pending.is_empty() {
+                chunk.before(&self.pending, ContentType::Html);
+                self.pending.clear();
+            }
+
+            return Ok(());
+        }
+
+        let text = mem::take(&mut self.pending) + chunk.as_str();
+        let mut output = String::with_capacity(text.len());
+
+        if let Some(pending_start) = self.normalize(&text, chunk.last_in_text_node(), &mut output) {
+            self.pending.push_str(&text[pending_start..]);
+        }
+
+        if output != chunk.as_str() {
+            chunk.replace(&output, ContentType::Html);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(chunks: &[&str], normalization: EntityNormalization) -> String {
+        let mut normalizer = EntityNormalizer::new(normalization);
+        let mut output = String::new();
+
+        for (i, chunk) in chunks.iter().enumerate() {
+            let text = mem::take(&mut normalizer.pending) + chunk;
+
+            if let Some(pending_start) =
+                normalizer.normalize(&text, i == chunks.len() - 1, &mut output)
+            {
+                normalizer.pending.push_str(&text[pending_start..]);
+            }
+        }
+
+        output
+    }
+
+    #[test]
+    fn bare_ampersands() {
+        let normalization = EntityNormalization::default();
+
+        assert_eq!(
+            normalize(&["a & b &amp; c&copy d&#38; &"], normalization),
+            "a &amp; b &amp; c&copy d&#38; &amp;"
+        );
+
+        assert_eq!(
+            normalize(&["a &", " b &", ""], normalization),
+            "a &amp; b &amp;"
+        );
+        assert_eq!(normalize(&["a &", "amp; b"], normalization), "a &amp; b");
+
+        assert_eq!(
+            normalize(
+                &["a & b"],
+                EntityNormalization {
+                    escape_bare_ampersands: false,
+                    ..normalization
+                }
+            ),
+            "a & b"
+        );
+    }
+
+    #[test]
+    fn numeric_char_refs() {
+        let decimal = EntityNormalization {
+            escape_bare_ampersands: false,
+            numeric_char_refs: NumericCharRefs::Decimal,
+        };
+
+        let hexadecimal = EntityNormalization {
+            numeric_char_refs: NumericCharRefs::Hexadecimal,
+            ..decimal
+        };
+
+        assert_eq!(
+            normalize(&["&#x26; &#X3c; &#60; &#x; &#38 &#x110000000000;"], decimal),
+            "&#38; &#60; &#60; &#x; &#38 &#x110000000000;"
+        );
+
+        assert_eq!(
+            normalize(&["&#38; &#x3c; &#", "6", "0; &#x2", "6"], hexadecimal),
+            "&#x26; &#x3C; &#x3C; &#x26"
+        );
+    }
+}
@@ -1,22 +1,58 @@
+use super::canonicalizer::Canonicalizer;
+use super::comment_markup::CommentMarkup;
+use super::content_buffers::{buffer_inner_html, InnerHtmlHandlers, SharedContentBuffers};
 use super::settings::*;
 use super::ElementDescriptor;
-use crate::rewritable_units::{DocumentEnd, Element, StartTag, Token, TokenCaptureFlags};
-use crate::selectors_vm::MatchInfo;
+use crate::html::TextType;
+use crate::rewritable_units::{
+    Comment, ContentPolicies, DocumentEnd, Element, EndTag, EndTagHandlers, InsertedContent,
+    MatchInfo, SourceLocation, StartTag, TextChunk, Token, TokenCaptureFlags,
+};
+use crate::selectors_vm::{self, OpenElementsPath};
+use encoding_rs::Encoding;
+use std::error::Error;
+use std::mem;
+use std::sync::Arc;
 
 #[derive(Copy, Clone, Default, Debug, PartialEq, Eq, Hash)]
 pub struct SelectorHandlersLocator {
     pub element_handler_idx: Option<usize>,
     pub comment_handler_idx: Option<usize>,
     pub text_handler_idx: Option<usize>,
+    pub attribute_change_handler_idx: Option<usize>,
+    pub selector_idx: usize,
 }
 
 struct HandlerVecItem<H> {
     handler: H,
     user_count: usize,
+    always_active: bool,
+    priority: i32,
+    /// The information about each of the alternatives of the selector list that activates
+    /// the handler, empty if the handler isn't associated with a selector.
+    match_infos: Vec<MatchInfo>,
+    /// The indices of the matched alternatives for each of the activations of the handler,
+    /// the last one is reported to the handler.
+    active_alternatives: Vec<usize>,
+}
+
+/// Returns the information about the alternative that has activated the handler most recently.
+#[inline]
+fn active_match_info<'i>(
+    match_infos: &'i [MatchInfo],
+    active_alternatives: &[usize],
+) -> Option<&'i MatchInfo> {
+    active_alternatives
+        .last()
+        .map(|&alternative_idx| &match_infos[alternative_idx])
 }
 
 struct HandlerVec<H> {
     items: Vec<HandlerVecItem<H>>,
+    // NOTE: indices of the items in the invocation order. Items themselves
+    // are kept in the order they were added, so their indices can be used
+    // as handler locators.
+    order: Vec<usize>,
     user_count: usize,
 }
 
@@ -24,19 +60,53 @@ impl<H> Default for HandlerVec<H> {
     fn default() -> Self {
         HandlerVec {
             items: Vec::default(),
+            order: Vec::default(),
             user_count: 0,
         }
     }
 }
 
 impl<H> HandlerVec<H> {
+    /// Adds the handler that is invoked after all the handlers with the same or higher
+    /// `priority`, but before the handlers with lower priority.
     #[inline]
-    pub fn push(&mut self, handler: H, always_active: bool) {
-        let item = HandlerVecItem {
+    pub fn push(&mut self, handler: H, always_active: bool, priority: i32) {
+        self.push_item(HandlerVecItem {
             handler,
             user_count: if always_active { 1 } else { 0 },
-        };
+            always_active,
+            priority,
+            match_infos: Vec::default(),
+            active_alternatives: Vec::default(),
+        });
+    }
+
+    /// Adds the handler that is activated by the matches of the selector list, whose
+    /// alternatives are described by the `match_infos`.
+    #[inline]
+    pub fn push_for_selector(&mut self, handler: H, priority: i32, match_infos: Vec<MatchInfo>) {
+        self.push_item(HandlerVecItem {
+            handler,
+            user_count: 0,
+            always_active: false,
+            priority,
+            match_infos,
+            active_alternatives: Vec::default(),
+        });
+    }
+
+    #[inline]
+    fn push_item(&mut self, item: HandlerVecItem<H>) {
+        let priority = item.priority;
+        let items = &self.items;
+
+        let position = self
+            .order
+            .iter()
+            .position(|&idx| items[idx].priority < priority)
+            .unwrap_or(self.order.len());
 
+        self.order.insert(position, self.items.len());
         self.user_count += item.user_count;
         self.items.push(item);
     }
@@ -46,6 +116,28 @@ impl<H> HandlerVec<H> {
         self.items.len()
     }
 
+    #[inline]
+    fn remove_item(&mut self, idx: usize) -> HandlerVecItem<H> {
+        let item = self.items.remove(idx);
+
+        self.order.retain(|&i| i != idx);
+
+        for i in self.order.iter_mut() {
+            if *i > idx {
+                *i -= 1;
+            }
+        }
+
+        self.user_count -= item.user_count;
+
+        item
+    }
+
+    #[inline]
+    pub fn remove(&mut self, idx: usize) -> H {
+        self.remove_item(idx).handler
+    }
+
     #[inline]
     pub fn inc_user_count(&mut self, idx: usize) {
         self.items[idx].user_count += 1;
@@ -58,6 +150,21 @@ impl<H> HandlerVec<H> {
         self.user_count -= 1;
     }
 
+    /// Activates the selector-associated handler for the match of the alternative of
+    /// its selector list.
+    #[inline]
+    pub fn activate(&mut self, idx: usize, alternative_idx: usize) {
+        self.items[idx].active_alternatives.push(alternative_idx);
+        self.inc_user_count(idx);
+    }
+
+    /// Reverts the latest activation of the selector-associated handler.
+    #[inline]
+    pub fn deactivate(&mut self, idx: usize) {
+        self.items[idx].active_alternatives.pop();
+        self.dec_user_count(idx);
+    }
+
     #[inline]
     pub fn has_active(&self) -> bool {
         self.user_count > 0
@@ -66,11 +173,15 @@ impl<H> HandlerVec<H> {
     #[inline]
     pub fn for_each_active(
         &mut self,
-        mut cb: impl FnMut(&mut H) -> HandlerResult,
+        mut cb: impl FnMut(&mut H, Option<&MatchInfo>) -> HandlerResult,
     ) -> HandlerResult {
-        for item in self.items.iter_mut() {
+        for &idx in &self.order {
+            let item = &mut self.items[idx];
+
             if item.user_count > 0 {
-                cb(&mut item.handler)?;
+                let match_info = active_match_info(&item.match_infos, &item.active_alternatives);
+
+                cb(&mut item.handler, match_info)?;
             }
         }
 
@@ -80,19 +191,46 @@ impl<H> HandlerVec<H> {
     #[inline]
     pub fn do_for_each_active_and_deactivate(
         &mut self,
-        mut cb: impl FnMut(&mut H) -> HandlerResult,
+        mut cb: impl FnMut(&mut H, Option<&MatchInfo>) -> HandlerResult,
     ) -> HandlerResult {
-        for item in self.items.iter_mut() {
+        for &idx in &self.order {
+            let item = &mut self.items[idx];
+
             if item.user_count > 0 {
-                cb(&mut item.handler)?;
+                let match_info = active_match_info(&item.match_infos, &item.active_alternatives);
+
+                cb(&mut item.handler, match_info)?;
                 self.user_count -= item.user_count;
                 item.user_count = 0;
+                item.active_alternatives.clear();
             }
         }
 
         Ok(())
     }
 
+    #[inline]
+    pub fn deactivate_all(&mut self) {
+        for item in self.items.iter_mut() {
+            item.user_count = 0;
+            item.active_alternatives.clear();
+        }
+
+        self.user_count = 0;
+    }
+
+    /// Deactivates all the handlers, except those that are always active.
+    #[inline]
+    pub fn reset(&mut self) {
+        self.user_count = 0;
+
+        for item in self.items.iter_mut() {
+            item.user_count = if item.always_active { 1 } else { 0 };
+            item.active_alternatives.clear();
+            self.user_count += item.user_count;
+        }
+    }
+
     #[inline]
     pub fn do_for_each_active_and_remove(
         &mut self,
@@ -100,9 +238,7 @@ impl<H> HandlerVec<H> {
     ) -> HandlerResult {
         for i in (0..self.items.len()).rev() {
             if self.items[i].user_count > 0 {
-                let item = self.items.remove(i);
-
-                self.user_count -= item.user_count;
+                let item = self.remove_item(i);
 
                 cb(item.handler)?;
             }
@@ -112,80 +248,338 @@ impl<H> HandlerVec<H> {
     }
 }
 
+/// The text handler with the text types filter and the text chunk policy applied.
+struct TextHandlerItem<'h, H: HandlerTypes> {
+    handler: H::TextHandler<'h>,
+    /// The types of the text chunks the handler is invoked for, all the types if `None`.
+    text_types: Option<Vec<TextType>>,
+    /// The text of the current text node, if the handler is invoked once per text node with
+    /// the coalesced text of the node.
+    coalesced_text: Option<CoalescedText>,
+}
+
 #[derive(Default)]
-pub struct ContentHandlersDispatcher<'h> {
-    doctype_handlers: HandlerVec<DoctypeHandler<'h>>,
-    comment_handlers: HandlerVec<CommentHandler<'h>>,
-    text_handlers: HandlerVec<TextHandler<'h>>,
-    end_tag_handlers: HandlerVec<EndTagHandler<'h>>,
-    element_handlers: HandlerVec<ElementHandler<'h>>,
-    end_handlers: HandlerVec<EndHandler<'h>>,
+struct CoalescedText {
+    buffer: String,
+    start_location: Option<SourceLocation>,
+}
+
+impl<'h, H: HandlerTypes> TextHandlerItem<'h, H> {
+    #[inline]
+    fn new(
+        handler: H::TextHandler<'h>,
+        text_types: Option<Vec<TextType>>,
+        policy: TextChunkPolicy,
+    ) -> Self {
+        TextHandlerItem {
+            handler,
+            text_types,
+            coalesced_text: match policy {
+                TextChunkPolicy::Chunked => None,
+                _ => Some(CoalescedText::default()),
+            },
+        }
+    }
+
+    fn invoke(&mut self, text: &mut TextChunk) -> HandlerResult {
+        if let Some(ref text_types) = self.text_types {
+            if !text_types.contains(&text.text_type()) {
+                return Ok(());
+            }
+        }
+
+        let coalesced_text = match self.coalesced_text {
+            Some(ref mut coalesced_text) => coalesced_text,
+            None => return (self.handler)(text),
+        };
+
+        if !text.removed() {
+            coalesced_text.buffer.push_str(text.as_str());
+        }
+
+        if text.last_in_text_node() {
+            let location = coalesced_text
+                .start_location
+                .take()
+                .unwrap_or_else(|| text.source_location().clone());
+
+            text.with_coalesced_text(
+                mem::take(&mut coalesced_text.buffer),
+                &location,
+                &mut self.handler,
+            )
+        } else {
+            if coalesced_text.start_location.is_none() {
+                coalesced_text.start_location = Some(text.source_location().clone());
+            }
+
+            text.remove();
+
+            Ok(())
+        }
+    }
+}
+
+enum CommentHandlerItem<'h, H: HandlerTypes> {
+    User(H::CommentHandler<'h>),
+    Markup(Box<CommentMarkup<'h, H>>),
+}
+
+impl<H: HandlerTypes> CommentHandlerItem<'_, H> {
+    #[inline]
+    fn invoke(&mut self, comment: &mut Comment) -> HandlerResult {
+        match self {
+            CommentHandlerItem::User(handler) => handler(comment),
+            CommentHandlerItem::Markup(markup) => markup.handle_comment(comment),
+        }
+    }
+}
+
+/// The handlers that are invoked once the end tag of an element has been reached.
+struct ElementEndHandlers<H: HandlerTypes> {
+    end_tag: Option<EndTagHandlers<H>>,
+    inner_html: Option<InnerHtmlHandlers<H>>,
+}
+
+impl<H: HandlerTypes> ElementEndHandlers<H> {
+    /// Invokes the handlers for the `end_tag`. The `preceding_content` is written to the output
+    /// before the content inserted before the end tag.
+    fn invoke(
+        self,
+        end_tag: &mut EndTag,
+        preceding_content: &mut InsertedContent,
+    ) -> HandlerResult {
+        if let Some(handlers) = self.end_tag {
+            handlers.invoke(end_tag)?;
+        }
+
+        if let Some(handlers) = self.inner_html {
+            let encoding = end_tag.mutations.encoding();
+
+            preceding_content.append(mem::take(&mut end_tag.mutations.content_before));
+            handlers.invoke(preceding_content, encoding)?;
+        }
+
+        Ok(())
+    }
+}
+
+pub struct ContentHandlersDispatcher<'h, H: HandlerTypes> {
+    doctype_handlers: HandlerVec<H::DoctypeHandler<'h>>,
+    comment_handlers: HandlerVec<CommentHandlerItem<'h, H>>,
+    processing_instruction_handlers: HandlerVec<H::ProcessingInstructionHandler<'h>>,
+    text_handlers: HandlerVec<TextHandlerItem<'h, H>>,
+    end_tag_handlers: HandlerVec<ElementEndHandlers<H>>,
+    implicit_end_tag_handlers: Vec<ElementEndHandlers<H>>,
+    element_handlers: HandlerVec<H::ElementHandler<'h>>,
+    attribute_change_handlers: HandlerVec<AttributeChangeHandler<'h, H>>,
+    end_handlers: HandlerVec<H::EndHandler<'h>>,
     next_element_can_have_content: bool,
     matched_elements_with_removed_content: usize,
+    content_policies: ContentPolicies,
+    match_counts: Vec<usize>,
+    match_counting_enabled: bool,
+    selector_locators: Vec<SelectorHandlersLocator>,
+    canonicalizer: Option<Canonicalizer>,
+    content_buffers: Option<SharedContentBuffers<H::Sharing>>,
+}
+
+impl<H: HandlerTypes> Default for ContentHandlersDispatcher<'_, H> {
+    fn default() -> Self {
+        ContentHandlersDispatcher {
+            doctype_handlers: HandlerVec::default(),
+            comment_handlers: HandlerVec::default(),
+            processing_instruction_handlers: HandlerVec::default(),
+            text_handlers: HandlerVec::default(),
+            end_tag_handlers: HandlerVec::default(),
+            implicit_end_tag_handlers: Vec::default(),
+            element_handlers: HandlerVec::default(),
+            attribute_change_handlers: HandlerVec::default(),
+            end_handlers: HandlerVec::default(),
+            next_element_can_have_content: false,
+            matched_elements_with_removed_content: 0,
+            content_policies: ContentPolicies::default(),
+            match_counts: Vec::default(),
+            match_counting_enabled: false,
+            selector_locators: Vec::default(),
+            canonicalizer: None,
+            content_buffers: None,
+        }
+    }
 }
 
-impl<'h> ContentHandlersDispatcher<'h> {
+impl<'h, H: HandlerTypes> ContentHandlersDispatcher<'h, H> {
     #[inline]
-    pub fn add_document_content_handlers(&mut self, handlers: DocumentContentHandlers<'h>) {
+    pub fn add_document_content_handlers(&mut self, handlers: DocumentContentHandlers<'h, H>) {
         if let Some(handler) = handlers.doctype {
-            self.doctype_handlers.push(handler, true);
+            self.doctype_handlers.push(handler, true, handlers.priority);
         }
 
         if let Some(handler) = handlers.comments {
-            self.comment_handlers.push(handler, true);
+            self.comment_handlers
+                .push(CommentHandlerItem::User(handler), true, handlers.priority);
+        }
+
+        if let Some(handler) = handlers.processing_instructions {
+            self.processing_instruction_handlers
+                .push(handler, true, handlers.priority);
         }
 
         if let Some(handler) = handlers.text {
-            self.text_handlers.push(handler, true);
+            let handler =
+                TextHandlerItem::new(handler, handlers.text_types, handlers.text_chunk_policy);
+
+            self.text_handlers.push(handler, true, handlers.priority);
         }
 
         if let Some(handler) = handlers.end {
-            self.end_handlers.push(handler, true);
+            self.end_handlers.push(handler, true, handlers.priority);
         }
     }
 
+    /// Adds the handler that rewrites the markup in the comments, which is invoked after
+    /// the document-level comment handlers with the same or higher `priority`.
+    #[inline]
+    pub fn add_comment_markup(&mut self, markup: CommentMarkup<'h, H>, priority: i32) {
+        self.comment_handlers
+            .push(CommentHandlerItem::Markup(Box::new(markup)), true, priority);
+    }
+
     #[inline]
     pub fn add_selector_associated_handlers(
         &mut self,
-        handlers: ElementContentHandlers<'h>,
+        handlers: ElementContentHandlers<'h, H>,
+        alternatives: Vec<Arc<str>>,
     ) -> SelectorHandlersLocator {
-        SelectorHandlersLocator {
+        let selector_idx = self.match_counts.len();
+        let priority = handlers.priority;
+        let text_chunk_policy = handlers.text_chunk_policy;
+
+        let match_infos: Vec<_> = alternatives
+            .into_iter()
+            .enumerate()
+            .map(|(alternative_idx, selector)| {
+                MatchInfo::new(selector_idx, alternative_idx, selector)
+            })
+            .collect();
+
+        self.match_counts.push(0);
+
+        let locator = SelectorHandlersLocator {
             element_handler_idx: handlers.element.map(|h| {
-                self.element_handlers.push(h, false);
+                self.element_handlers
+                    .push_for_selector(h, priority, match_infos.clone());
                 self.element_handlers.len() - 1
             }),
             comment_handler_idx: handlers.comments.map(|h| {
-                self.comment_handlers.push(h, false);
+                self.comment_handlers.push_for_selector(
+                    CommentHandlerItem::User(h),
+                    priority,
+                    match_infos.clone(),
+                );
                 self.comment_handlers.len() - 1
             }),
             text_handler_idx: handlers.text.map(|h| {
-                self.text_handlers.push(h, false);
+                let h = TextHandlerItem::new(h, None, text_chunk_policy);
+
+                self.text_handlers
+                    .push_for_selector(h, priority, match_infos.clone());
                 self.text_handlers.len() - 1
             }),
+            attribute_change_handler_idx: handlers.attribute_change.map(|h| {
+                self.attribute_change_handlers
+                    .push_for_selector(h, priority, match_infos.clone());
+                self.attribute_change_handlers.len() - 1
+            }),
+            selector_idx,
+        };
+
+        self.selector_locators.push(locator);
+
+        locator
+    }
+
+    #[inline]
+    pub fn selector_locator(&self, selector_idx: usize) -> Option<SelectorHandlersLocator> {
+        self.selector_locators.get(selector_idx).copied()
+    }
+
+    #[inline]
+    pub fn has_element_handlers(&self) -> bool {
+        self.element_handlers.len() > 0 || self.attribute_change_handlers.len() > 0
+    }
+
+    #[inline]
+    pub fn set_content_policies(&mut self, content_policies: ContentPolicies) {
+        self.content_policies = content_policies;
+    }
+
+    #[inline]
+    pub fn enable_match_counting(&mut self) {
+        self.match_counting_enabled = true;
+    }
+
+    /// Makes the dispatcher bring all the tags and text to the canonical form after the
+    /// handlers have been invoked for them.
+    #[inline]
+    pub fn enable_canonical_output(&mut self) {
+        self.canonicalizer = Some(Canonicalizer::default());
+    }
+
+    /// Makes the elements that have requested their inner HTML buffer their content in the
+    /// `content_buffers`.
+    #[inline]
+    pub fn set_content_buffers(&mut self, content_buffers: SharedContentBuffers<H::Sharing>) {
+        self.content_buffers = Some(content_buffers);
+    }
+
+    /// Returns the number of matches of each of the selectors in the order they were added, if
+    /// match counting is enabled.
+    #[inline]
+    pub fn match_counts(&self) -> Option<&[usize]> {
+        if self.match_counting_enabled {
+            Some(&self.match_counts)
+        } else {
+            None
         }
     }
 
+    #[inline]
+    pub fn has_end_tag_handlers(&self) -> bool {
+        self.end_tag_handlers.len() > 0
+    }
+
     #[inline]
     pub fn has_matched_elements_with_removed_content(&self) -> bool {
         self.matched_elements_with_removed_content > 0
     }
 
     #[inline]
-    pub fn start_matching(&mut self, match_info: MatchInfo<SelectorHandlersLocator>) {
+    pub fn start_matching(&mut self, match_info: selectors_vm::MatchInfo<SelectorHandlersLocator>) {
         let locator = match_info.payload;
+        let alternative_idx = match_info.alternative_idx;
+
+        if self.match_counting_enabled {
+            self.match_counts[locator.selector_idx] += 1;
+        }
 
         if match_info.with_content {
             if let Some(idx) = locator.comment_handler_idx {
-                self.comment_handlers.inc_user_count(idx);
+                self.comment_handlers.activate(idx, alternative_idx);
             }
 
             if let Some(idx) = locator.text_handler_idx {
-                self.text_handlers.inc_user_count(idx);
+                self.text_handlers.activate(idx, alternative_idx);
             }
         }
 
         if let Some(idx) = locator.element_handler_idx {
-            self.element_handlers.inc_user_count(idx);
+            self.element_handlers.activate(idx, alternative_idx);
+        }
+
+        if let Some(idx) = locator.attribute_change_handler_idx {
+            self.attribute_change_handlers
+                .activate(idx, alternative_idx);
         }
 
         self.next_element_can_have_content = match_info.with_content;
@@ -195,11 +589,11 @@ impl<'h> ContentHandlersDispatcher<'h> {
     pub fn stop_matching(&mut self, elem_desc: ElementDescriptor) {
         for locator in elem_desc.matched_content_handlers {
             if let Some(idx) = locator.comment_handler_idx {
-                self.comment_handlers.dec_user_count(idx);
+                self.comment_handlers.deactivate(idx);
             }
 
             if let Some(idx) = locator.text_handler_idx {
-                self.text_handlers.dec_user_count(idx);
+                self.text_handlers.deactivate(idx);
             }
         }
 
@@ -212,19 +606,102 @@ impl<'h> ContentHandlersDispatcher<'h> {
         }
     }
 
+    /// Detaches the end tag handler of an element that has been closed implicitly (e.g. `<p>`
+    /// closed by the start tag of `<div>`), so the handler is invoked for the next tag instead
+    /// of the end tag that never comes.
+    #[inline]
+    pub fn close_implicitly(&mut self, elem_desc: &mut ElementDescriptor) {
+        // NOTE: content of the elements with removed content is removed up to their
+        // actual end, so we keep their end tag handlers intact.
+        if elem_desc.remove_content {
+            return;
+        }
+
+        if let Some(idx) = elem_desc.end_tag_handler_idx.take() {
+            let handler = self.end_tag_handlers.remove(idx);
+
+            self.implicit_end_tag_handlers.push(handler);
+        }
+    }
+
+    fn invoke_implicit_end_tag_handlers(
+        &mut self,
+        encoding: &'static Encoding,
+    ) -> Result<InsertedContent, Box<dyn Error>> {
+        let mut content = InsertedContent::default();
+
+        for handlers in self.implicit_end_tag_handlers.drain(..) {
+            let mut end_tag = EndTag::new_implicit(encoding);
+
+            handlers.invoke(&mut end_tag, &mut content)?;
+            end_tag.serialize_into(&mut content);
+        }
+
+        Ok(content)
+    }
+
+    fn handle_implicit_end_tags(&mut self, token: &mut Token) -> HandlerResult {
+        // NOTE: for the end tags, the handlers are invoked before the handlers of the end tag
+        // itself, see `handle_token`.
+        let mutations = match token {
+            Token::StartTag(start_tag) => &mut start_tag.mutations,
+            _ => return Ok(()),
+        };
+
+        let content = self.invoke_implicit_end_tag_handlers(mutations.encoding())?;
+
+        // NOTE: implicit end tags precede the tag that caused them.
+        mutations.content_before.prepend(content);
+
+        Ok(())
+    }
+
     pub fn handle_start_tag(
         &mut self,
         start_tag: &mut StartTag,
         current_element_data: Option<&mut ElementDescriptor>,
+        open_elements_path: Option<OpenElementsPath<ElementDescriptor>>,
     ) -> HandlerResult {
         if self.matched_elements_with_removed_content > 0 {
             start_tag.mutations.remove();
         }
 
-        let mut element = Element::new(start_tag, self.next_element_can_have_content);
+        // NOTE: elements that can't have content are not pushed to the open element stack.
+        let ancestors_path = open_elements_path.map(|path| {
+            if self.next_element_can_have_content {
+                path.ancestors()
+            } else {
+                path
+            }
+        });
+
+        let mut element = Element::new(
+            start_tag,
+            self.next_element_can_have_content,
+            self.content_policies,
+        );
+
+        if let Some(ref ancestors_path) = ancestors_path {
+            element.set_ancestors_path(ancestors_path);
+        }
 
         self.element_handlers
-            .do_for_each_active_and_deactivate(|h| h(&mut element))?;
+            .do_for_each_active_and_deactivate(|h, match_info| {
+                element.set_match_info(match_info.cloned());
+                h(&mut element)?;
+                element.check_content_model().map_err(Into::into)
+            })?;
+
+        self.attribute_change_handlers
+            .do_for_each_active_and_deactivate(|(name, h), match_info| {
+                if element.is_attribute_modified(name) {
+                    element.set_match_info(match_info.cloned());
+                    h(&mut element)?;
+                    element.check_content_model().map_err(Into::into)
+                } else {
+                    Ok(())
+                }
+            })?;
 
         if self.next_element_can_have_content {
             if let Some(elem_desc) = current_element_data {
@@ -233,10 +710,26 @@ impl<'h> ContentHandlersDispatcher<'h> {
                     self.matched_elements_with_removed_content += 1;
                 }
 
-                if let Some(handler) = element.into_end_tag_handler() {
+                let inner_html = match self.content_buffers {
+                    Some(ref content_buffers) if !element.should_remove_content() => {
+                        buffer_inner_html(&mut element, content_buffers)
+                    }
+                    _ => None,
+                };
+
+                let end_tag = element.into_end_tag_handlers();
+
+                if end_tag.is_some() || inner_html.is_some() {
                     elem_desc.end_tag_handler_idx = Some(self.end_tag_handlers.len());
 
-                    self.end_tag_handlers.push(handler, false);
+                    self.end_tag_handlers.push(
+                        ElementEndHandlers {
+                            end_tag,
+                            inner_html,
+                        },
+                        false,
+                        0,
+                    );
                 }
             }
         }
@@ -244,23 +737,85 @@ impl<'h> ContentHandlersDispatcher<'h> {
         Ok(())
     }
 
+    /// Discards the element handlers that were activated for a start tag that has been spilled
+    /// to the output and, thus, will never be produced as a token.
+    #[inline]
+    pub fn skip_start_tag(&mut self) {
+        self.element_handlers.deactivate_all();
+        self.attribute_change_handlers.deactivate_all();
+    }
+
+    /// Resets the state of the handlers that were activated by selector matching, so
+    /// the dispatcher can be used for a new document.
+    pub fn reset(&mut self) {
+        self.doctype_handlers.reset();
+        self.comment_handlers.reset();
+        self.processing_instruction_handlers.reset();
+        self.text_handlers.reset();
+        self.element_handlers.reset();
+        self.attribute_change_handlers.reset();
+        self.end_tag_handlers = HandlerVec::default();
+        self.implicit_end_tag_handlers.clear();
+        self.next_element_can_have_content = false;
+        self.matched_elements_with_removed_content = 0;
+
+        if let Some(ref mut canonicalizer) = self.canonicalizer {
+            canonicalizer.reset();
+        }
+    }
+
     pub fn handle_token(
         &mut self,
         token: &mut Token,
         current_element_data: Option<&mut ElementDescriptor>,
+        open_elements_path: Option<OpenElementsPath<ElementDescriptor>>,
     ) -> HandlerResult {
         match token {
-            Token::Doctype(doctype) => self.doctype_handlers.for_each_active(|h| h(doctype)),
-            Token::StartTag(start_tag) => self.handle_start_tag(start_tag, current_element_data),
-            Token::EndTag(end_tag) => self
-                .end_tag_handlers
-                .do_for_each_active_and_remove(|h| h(end_tag)),
-            Token::TextChunk(text) => self.text_handlers.for_each_active(|h| h(text)),
-            Token::Comment(comment) => self.comment_handlers.for_each_active(|h| h(comment)),
+            Token::Doctype(doctype) => self.doctype_handlers.for_each_active(|h, _| h(doctype))?,
+            Token::StartTag(start_tag) => {
+                self.handle_start_tag(start_tag, current_element_data, open_elements_path)?
+            }
+            Token::EndTag(end_tag) => {
+                // NOTE: the handlers of the elements closed implicitly by the end tag are
+                // invoked first, so their inner HTML is a part of the inner HTML of the element.
+                let mut content =
+                    self.invoke_implicit_end_tag_handlers(end_tag.mutations.encoding())?;
+
+                self.end_tag_handlers
+                    .do_for_each_active_and_remove(|h| h.invoke(end_tag, &mut content))?;
+
+                end_tag.mutations.content_before.prepend(content);
+            }
+            Token::TextChunk(text) => self.text_handlers.for_each_active(|h, match_info| {
+                text.set_match_info(match_info.cloned());
+                h.invoke(text)
+            })?,
+            Token::Comment(comment) => self.comment_handlers.for_each_active(|h, match_info| {
+                comment.set_match_info(match_info.cloned());
+                h.invoke(comment)
+            })?,
+            Token::ProcessingInstruction(pi) => self
+                .processing_instruction_handlers
+                .for_each_active(|h, _| h(pi))?,
+        }
+
+        if let Some(ref mut canonicalizer) = self.canonicalizer {
+            canonicalizer.handle_token(token);
         }
+
+        if !self.implicit_end_tag_handlers.is_empty() {
+            self.handle_implicit_end_tags(token)?;
+        }
+
+        Ok(())
     }
 
     pub fn handle_end(&mut self, document_end: &mut DocumentEnd) -> HandlerResult {
+        let encoding = document_end.encoding();
+
+        self.invoke_implicit_end_tag_handlers(encoding)?
+            .write(encoding, &mut |c| document_end.append_raw(c))?;
+
         self.end_handlers
             .do_for_each_active_and_remove(|h| h(document_end))
     }
@@ -277,6 +832,10 @@ impl<'h> ContentHandlersDispatcher<'h> {
             flags |= TokenCaptureFlags::COMMENTS;
         }
 
+        if self.processing_instruction_handlers.has_active() {
+            flags |= TokenCaptureFlags::PROCESSING_INSTRUCTIONS;
+        }
+
         if self.text_handlers.has_active() {
             flags |= TokenCaptureFlags::TEXT;
         }
@@ -285,10 +844,21 @@ impl<'h> ContentHandlersDispatcher<'h> {
             flags |= TokenCaptureFlags::NEXT_END_TAG;
         }
 
-        if self.element_handlers.has_active() {
+        // NOTE: handlers of the implicitly closed elements are invoked for the next tag.
+        if !self.implicit_end_tag_handlers.is_empty() {
+            flags |= TokenCaptureFlags::NEXT_START_TAG | TokenCaptureFlags::NEXT_END_TAG;
+        }
+
+        if self.element_handlers.has_active() || self.attribute_change_handlers.has_active() {
             flags |= TokenCaptureFlags::NEXT_START_TAG;
         }
 
+        if self.canonicalizer.is_some() {
+            flags |= TokenCaptureFlags::NEXT_START_TAG
+                | TokenCaptureFlags::NEXT_END_TAG
+                | TokenCaptureFlags::TEXT;
+        }
+
         flags
     }
 }
@@ -0,0 +1,563 @@
+use super::settings::{
+    DocumentContentHandlers, ElementContentHandlers, HandlerTypes, MemorySettings,
+    SendHandlerTypes, Settings,
+};
+use super::{HtmlRewriter, RewritingError, SettingsError};
+use crate::base::{SendSharing, Shared, Sharing};
+use crate::memory::{MemoryLimitExceededError, SharedMemoryLimiter};
+use crate::selectors_vm::{
+    last_sibling_key, HasCondition, LastSiblingCondition, Selector, SharedHasMatches,
+};
+use crate::transform_stream::OutputSink;
+use std::mem;
+
+/// A candidate element of a `:has()` pseudo-class that hasn't been resolved yet.
+struct PendingCandidate {
+    condition_idx: usize,
+    element_index: usize,
+    /// The offset of the element's start tag in the lookahead output.
+    offset: usize,
+}
+
+/// A candidate element of a pseudo-class that depends on the following siblings that hasn't
+/// been resolved yet.
+struct SiblingCandidate {
+    condition_idx: usize,
+    element_index: usize,
+    of_type: bool,
+    tag_name: String,
+}
+
+/// The unresolved sibling candidates that are children of an open element.
+struct SiblingLevel {
+    /// The index of the open element, or `None` for the top level of the document.
+    element_index: Option<usize>,
+    candidates: Vec<SiblingCandidate>,
+}
+
+impl SiblingLevel {
+    #[inline]
+    fn new(element_index: Option<usize>) -> Self {
+        SiblingLevel {
+            element_index,
+            candidates: Vec::default(),
+        }
+    }
+}
+
+struct LookaheadState {
+    element_count: usize,
+    /// The indices of the open candidate elements of each condition.
+    open_candidates: Vec<Vec<usize>>,
+    /// The sibling candidates of the open elements, the innermost open element is the last.
+    sibling_levels: Vec<SiblingLevel>,
+    /// The index of the level of the current element's parent in the `sibling_levels`.
+    parent_level: usize,
+    /// The pending candidates in the document order.
+    pending: Vec<PendingCandidate>,
+    /// The lookahead output that hasn't been released to the rewriter yet.
+    buffer: Vec<u8>,
+    /// The offset of the start of the buffer in the lookahead output.
+    buffer_offset: usize,
+    /// The resolved candidates that haven't been added to the matches of the rewriter yet.
+    matches: Vec<(String, usize)>,
+    /// Stops holding back the output for the new candidates, once the memory usage has
+    /// exceeded the soft limit.
+    disabled: bool,
+}
+
+impl LookaheadState {
+    #[inline]
+    fn output_len(&self) -> usize {
+        self.buffer_offset + self.buffer.len()
+    }
+
+    /// Returns the offset of the start tag of the first pending candidate. The rewriter can't
+    /// receive the output from this point until the candidate is resolved.
+    #[inline]
+    fn hold_offset(&self) -> usize {
+        self.pending
+            .first()
+            .map_or_else(|| self.output_len(), |c| c.offset)
+    }
+
+    fn add_candidate(&mut self, condition_idx: usize, element_index: usize) {
+        if self.disabled {
+            return;
+        }
+
+        let offset = self.output_len();
+
+        self.open_candidates[condition_idx].push(element_index);
+
+        self.pending.push(PendingCandidate {
+            condition_idx,
+            element_index,
+            offset,
+        });
+    }
+
+    fn resolve_candidate(&mut self, condition_idx: usize, element_index: usize) {
+        self.pending
+            .retain(|c| c.condition_idx != condition_idx || c.element_index != element_index);
+    }
+
+    fn close_candidate(&mut self, condition_idx: usize, element_index: usize) {
+        self.open_candidates[condition_idx].retain(|&idx| idx != element_index);
+        self.resolve_candidate(condition_idx, element_index);
+    }
+
+    fn add_sibling_candidate(
+        &mut self,
+        condition_idx: usize,
+        element_index: usize,
+        of_type: bool,
+        tag_name: String,
+    ) {
+        if self.disabled {
+            return;
+        }
+
+        let offset = self.output_len();
+
+        self.sibling_levels[self.parent_level]
+            .candidates
+            .push(SiblingCandidate {
+                condition_idx,
+                element_index,
+                of_type,
+                tag_name,
+            });
+
+        self.pending.push(PendingCandidate {
+            condition_idx,
+            element_index,
+            offset,
+        });
+    }
+
+    /// Resolves the sibling candidates of the current element's parent that have the element
+    /// as a following sibling, so they aren't the last siblings.
+    fn add_sibling(&mut self, tag_name: &str) {
+        let level = match self.sibling_levels.last_mut() {
+            Some(level) => level,
+            None => return,
+        };
+
+        let mut resolved = Vec::new();
+
+        level.candidates.retain(|c| {
+            let is_resolved = !c.of_type || c.tag_name == tag_name;
+
+            if is_resolved {
+                resolved.push((c.condition_idx, c.element_index));
+            }
+
+            !is_resolved
+        });
+
+        for (condition_idx, element_index) in resolved {
+            self.resolve_candidate(condition_idx, element_index);
+        }
+    }
+
+    /// Opens the level of the children of the element.
+    #[inline]
+    fn open_sibling_level(&mut self, element_index: usize) {
+        self.sibling_levels
+            .push(SiblingLevel::new(Some(element_index)));
+    }
+
+    /// Closes the level of the children of the element along with the levels of its open
+    /// descendants. The remaining sibling candidates of the closed levels are the last siblings.
+    fn close_sibling_level(&mut self, element_index: usize) {
+        let position = self
+            .sibling_levels
+            .iter()
+            .position(|l| l.element_index == Some(element_index));
+
+        if let Some(position) = position {
+            let levels: Vec<_> = self.sibling_levels.drain(position..).collect();
+
+            self.add_last_siblings(levels);
+        }
+    }
+
+    fn add_last_siblings(&mut self, levels: Vec<SiblingLevel>) {
+        for candidate in levels.into_iter().flat_map(|l| l.candidates) {
+            self.matches.push((
+                last_sibling_key(candidate.of_type).to_owned(),
+                candidate.element_index,
+            ));
+
+            self.resolve_candidate(candidate.condition_idx, candidate.element_index);
+        }
+    }
+
+    /// Resolves the candidates of the condition that have the element matching the relative
+    /// selector as a child or a descendant.
+    fn add_match(&mut self, condition_idx: usize, argument: &str, child_only: bool) {
+        let open_candidates = &self.open_candidates[condition_idx];
+
+        // NOTE: the open candidates are the ancestors of the matched element and the parent
+        // of the element is the innermost one.
+        let matched: Vec<usize> = if child_only {
+            open_candidates.last().copied().into_iter().collect()
+        } else {
+            open_candidates.to_owned()
+        };
+
+        for element_index in matched {
+            self.matches.push((argument.to_owned(), element_index));
+
+            self.resolve_candidate(condition_idx, element_index);
+        }
+    }
+
+    /// Resolves all the remaining candidates at the end of the document. The sibling candidates
+    /// that are still open are the last siblings.
+    fn finish_document(&mut self) {
+        let levels = mem::replace(&mut self.sibling_levels, vec![SiblingLevel::new(None)]);
+
+        self.add_last_siblings(levels);
+        self.parent_level = 0;
+        self.element_count = 0;
+        self.pending.clear();
+        self.open_candidates.iter_mut().for_each(Vec::clear);
+    }
+
+    /// Gives up all the pending candidates, so they don't match their `:has()` pseudo-classes,
+    /// and stops holding back the output.
+    fn disable(&mut self) {
+        self.disabled = true;
+        self.pending.clear();
+        self.open_candidates.iter_mut().for_each(Vec::clear);
+        self.sibling_levels
+            .iter_mut()
+            .for_each(|l| l.candidates.clear());
+    }
+
+    fn take_released(&mut self) -> Vec<u8> {
+        let released_len = self.hold_offset() - self.buffer_offset;
+
+        self.buffer_offset += released_len;
+        self.buffer.drain(..released_len).collect()
+    }
+}
+
+// NOTE: the lookahead rewriter has the `Send` handlers whatever the handler types of the
+// rewriter are, so the state is shared with them as `Send` as well.
+type SharedLookaheadState = Shared<LookaheadState, SendSharing>;
+
+struct LookaheadSink(SharedLookaheadState);
+
+impl OutputSink for LookaheadSink {
+    fn handle_chunk(&mut self, chunk: &[u8]) {
+        self.0.lock().buffer.extend_from_slice(chunk);
+    }
+}
+
+/// Resolves the `:has()` pseudo-classes for the rewriter.
+///
+/// The input is parsed by the lookahead rewriter first. It finds the candidates of the `:has()`
+/// pseudo-classes and holds back its output, starting from the start tag of the first
+/// unresolved candidate, until the candidate's descendant matches the relative selector or
+/// the candidate's end tag is reached. Once the candidate is resolved, the held back output
+/// is released to the rewriter, so the selector matching VM of the latter already knows the
+/// result by the time it reaches the candidate. The elements are identified by their indices
+/// in the document, which are the same for both rewriters. The held back output is accounted
+/// by the memory limiter of the rewriter.
+///
+/// The pseudo-classes that depend on the following siblings of the element (e.g.
+/// `:last-child`) are resolved the same way: the candidate is resolved once its following
+/// sibling starts or its parent's end tag is reached, in which case it's the last sibling.
+pub struct HasLookahead<S: Sharing> {
+    rewriter: HtmlRewriter<'static, LookaheadSink, SendHandlerTypes>,
+    state: SharedLookaheadState,
+    has_matches: SharedHasMatches<S>,
+    memory_limiter: SharedMemoryLimiter<S>,
+    /// The number of the held back bytes accounted by the memory limiter.
+    accounted_len: usize,
+}
+
+impl<S: Sharing> HasLookahead<S> {
+    pub fn try_new(
+        conditions: Vec<HasCondition>,
+        last_sibling_conditions: Vec<LastSiblingCondition>,
+        has_matches: SharedHasMatches<S>,
+        memory_limiter: SharedMemoryLimiter<S>,
+        settings: Settings<'static, '_, SendHandlerTypes>,
+    ) -> Result<Self, SettingsError> {
+        let state = SharedLookaheadState::new(LookaheadState {
+            element_count: 0,
+            open_candidates: vec![Vec::default(); conditions.len()],
+            sibling_levels: vec![SiblingLevel::new(None)],
+            parent_level: 0,
+            pending: Vec::default(),
+            buffer: Vec::default(),
+            buffer_offset: 0,
+            matches: Vec::default(),
+            disabled: false,
+        });
+
+        // NOTE: the selectors are built from the components of the valid selectors, so it's
+        // safe to unwrap here.
+        let all_selector: Selector = "*".parse().unwrap();
+
+        let selectors: Vec<(Selector, Selector)> = conditions
+            .iter()
+            .map(|c| {
+                (
+                    c.candidate.parse().unwrap(),
+                    c.relative_selector().parse().unwrap(),
+                )
+            })
+            .collect();
+
+        let last_sibling_selectors: Vec<Selector> = last_sibling_conditions
+            .iter()
+            .map(|c| c.candidate.parse().unwrap())
+            .collect();
+
+        let tracks_siblings = !last_sibling_conditions.is_empty();
+
+        let mut element_content_handlers = vec![(&all_selector, {
+            let state = Shared::clone(&state);
+
+            // NOTE: the elements are counted before any other handler is invoked for them.
+            ElementContentHandlers::new_send()
+                .element(move |el| {
+                    let mut state_ref = state.lock();
+
+                    state_ref.element_count += 1;
+
+                    if tracks_siblings {
+                        let element_index = state_ref.element_count - 1;
+
+                        state_ref.add_sibling(&el.tag_name());
+                        state_ref.parent_level = state_ref.sibling_levels.len() - 1;
+
+                        if el.can_have_content() {
+                            state_ref.open_sibling_level(element_index);
+
+                            let state = Shared::clone(&state);
+
+                            el.on_end_tag(move |_| {
+                                state.lock().close_sibling_level(element_index);
+
+                                Ok(())
+                            });
+                        }
+                    }
+
+                    Ok(())
+                })
+                .priority(i32::MAX)
+        })];
+
+        let condition_count = conditions.len();
+
+        for (idx, (condition, candidate_selector)) in last_sibling_conditions
+            .into_iter()
+            .zip(last_sibling_selectors.iter())
+            .enumerate()
+        {
+            // NOTE: the sibling conditions follow the `:has()` conditions in the pending
+            // candidates.
+            let condition_idx = condition_count + idx;
+            let of_type = condition.of_type;
+            let state = Shared::clone(&state);
+
+            element_content_handlers.push((
+                candidate_selector,
+                ElementContentHandlers::new_send().element(move |el| {
+                    let mut state = state.lock();
+                    let element_index = state.element_count - 1;
+
+                    state.add_sibling_candidate(
+                        condition_idx,
+                        element_index,
+                        of_type,
+                        el.tag_name(),
+                    );
+
+                    Ok(())
+                }),
+            ));
+        }
+
+        for (condition_idx, (condition, (candidate_selector, relative_selector))) in
+            conditions.into_iter().zip(selectors.iter()).enumerate()
+        {
+            element_content_handlers.push((candidate_selector, {
+                let state = Shared::clone(&state);
+
+                ElementContentHandlers::new_send().element(move |el| {
+                    // NOTE: the elements that can't have content never have descendants.
+                    if !el.can_have_content() {
+                        return Ok(());
+                    }
+
+                    let element_index = {
+                        let mut state = state.lock();
+                        let element_index = state.element_count - 1;
+
+                        state.add_candidate(condition_idx, element_index);
+
+                        element_index
+                    };
+
+                    let state = Shared::clone(&state);
+
+                    el.on_end_tag(move |_| {
+                        state.lock().close_candidate(condition_idx, element_index);
+
+                        Ok(())
+                    });
+
+                    Ok(())
+                })
+            }));
+
+            element_content_handlers.push((relative_selector, {
+                let state = Shared::clone(&state);
+                let argument = condition.argument.to_string();
+                let child_only = condition.argument.child_only;
+
+                ElementContentHandlers::new_send().element(move |_| {
+                    state.lock().add_match(condition_idx, &argument, child_only);
+
+                    Ok(())
+                })
+            }));
+        }
+
+        let document_content_handlers = vec![DocumentContentHandlers::new_send().end({
+            let state = Shared::clone(&state);
+
+            move |_| {
+                state.lock().finish_document();
+
+                Ok(())
+            }
+        })];
+
+        let rewriter = HtmlRewriter::try_new(
+            Settings {
+                element_content_handlers,
+                document_content_handlers,
+                ..settings
+            },
+            LookaheadSink(Shared::clone(&state)),
+        )?;
+
+        Ok(HasLookahead {
+            rewriter,
+            state,
+            has_matches,
+            memory_limiter,
+            accounted_len: 0,
+        })
+    }
+
+    /// Creates the settings of the lookahead rewriter that parses the input the same way as the
+    /// rewriter with the given settings.
+    pub fn settings<'s, H: HandlerTypes>(
+        settings: &Settings<'_, 's, H>,
+    ) -> Settings<'static, 's, SendHandlerTypes> {
+        let memory_settings = &settings.memory_settings;
+
+        Settings {
+            encoding: settings.encoding,
+            memory_settings: MemorySettings {
+                preallocated_parsing_buffer_size: memory_settings.preallocated_parsing_buffer_size,
+                max_allowed_memory_usage: memory_settings.max_allowed_memory_usage,
+                preallocated_attribute_buffer_capacity: memory_settings
+                    .preallocated_attribute_buffer_capacity,
+                preallocated_selector_matching_stack_depth: memory_settings
+                    .preallocated_selector_matching_stack_depth,
+                preallocated_output_buffer_size: memory_settings.preallocated_output_buffer_size,
+                soft_memory_usage_limit: memory_settings.soft_memory_usage_limit,
+            },
+            spill_settings: settings.spill_settings,
+            match_declarative_shadow_roots: settings.match_declarative_shadow_roots,
+            match_cache_capacity: settings.match_cache_capacity,
+            selector_context: settings.selector_context.clone(),
+            strict: settings.strict,
+            ..Settings::new_send()
+        }
+    }
+
+    fn account_held_bytes(&mut self, held_len: usize) -> Result<(), MemoryLimitExceededError> {
+        let mut limiter = self.memory_limiter.lock();
+
+        if held_len > self.accounted_len {
+            limiter.increase_usage(held_len - self.accounted_len)?;
+        } else {
+            limiter.decrease_usage(self.accounted_len - held_len);
+        }
+
+        self.accounted_len = held_len;
+
+        Ok(())
+    }
+
+    /// Adds the resolved candidates to the matches of the rewriter and takes the output that
+    /// can be passed to the rewriter.
+    fn take_released(&mut self) -> Result<Vec<u8>, RewritingError> {
+        let (released, held_len) = {
+            let mut state = self.state.lock();
+
+            if !state.matches.is_empty() {
+                let mut has_matches = self.has_matches.lock();
+
+                for (argument, element_index) in state.matches.drain(..) {
+                    has_matches.insert(&argument, element_index);
+                }
+            }
+
+            let released = state.take_released();
+
+            (released, state.buffer.len())
+        };
+
+        self.account_held_bytes(held_len)
+            .map_err(RewritingError::MemoryLimitExceeded)?;
+
+        Ok(released)
+    }
+
+    /// Writes the input chunk to the lookahead rewriter and returns the output that can be
+    /// passed to the rewriter.
+    pub fn write(&mut self, data: &[u8]) -> Result<Vec<u8>, RewritingError> {
+        self.rewriter.write(data)?;
+        self.take_released()
+    }
+
+    /// Ends the lookahead rewriter and returns the rest of its output.
+    pub fn end(&mut self) -> Result<Vec<u8>, RewritingError> {
+        self.rewriter.end()?;
+        self.take_released()
+    }
+
+    /// Finishes the current document of the lookahead rewriter and returns the rest of its
+    /// output.
+    pub fn start_new_document(&mut self) -> Result<Vec<u8>, RewritingError> {
+        self.rewriter.start_new_document()?;
+        self.state.lock().finish_document();
+        self.take_released()
+    }
+
+    /// Stops resolving the `:has()` pseudo-classes. The candidates that haven't been resolved
+    /// yet and all the following elements don't match the pseudo-classes.
+    #[inline]
+    pub fn disable(&mut self) {
+        self.state.lock().disable();
+    }
+
+    /// Forgets the resolved candidates, once the rewriter has finished the document.
+    #[inline]
+    pub fn clear_matches(&mut self) {
+        self.has_matches.lock().clear();
+    }
+}
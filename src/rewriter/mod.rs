@@ -1,32 +1,75 @@
+mod byte_filters;
+mod canonicalizer;
+mod entity_normalizer;
 mod handlers_dispatcher;
+#[cfg(feature = "selector_matching")]
+mod has_lookahead;
+#[cfg(feature = "basic_selectors")]
+mod obsolete_elements;
 mod rewrite_controller;
 
 #[macro_use]
 mod settings;
 
+#[cfg(feature = "futures")]
+mod async_rewriter;
+mod builder;
+mod comment_markup;
+mod content_buffers;
+mod sampling;
+mod selector_switch;
+mod template;
+mod text_pipeline;
+mod validator;
+
+use self::byte_filters::FilteredSink;
+use self::comment_markup::CommentMarkup;
+use self::content_buffers::{BufferingSink, ContentBuffers, SharedContentBuffers};
+use self::entity_normalizer::EntityNormalizer;
 use self::handlers_dispatcher::ContentHandlersDispatcher;
+#[cfg(feature = "selector_matching")]
+use self::has_lookahead::HasLookahead;
+#[cfg(feature = "basic_selectors")]
+use self::obsolete_elements::obsolete_elements_handlers;
 use self::rewrite_controller::*;
+use self::validator::Validator;
 use crate::memory::MemoryLimitExceededError;
-use crate::memory::MemoryLimiter;
+use crate::memory::{MemoryLimiter, SharedMemoryLimiter};
 use crate::parser::ParsingAmbiguityError;
-use crate::selectors_vm::{self, SelectorMatchingVm};
+use crate::rewritable_units::ContentPolicies;
+#[cfg(feature = "basic_selectors")]
+use crate::selectors_vm::SelectorError;
+use crate::selectors_vm::SelectorMatchingVm;
 use crate::transform_stream::*;
-use encoding_rs::Encoding;
+use encoding_rs::{Encoding, UTF_8};
 use std::error::Error as StdError;
 use std::fmt::{self, Debug};
-use std::rc::Rc;
+use std::ops::Range;
+use std::str::Utf8Error;
+use std::sync::Arc;
 use thiserror::Error;
 
+#[cfg(feature = "futures")]
+pub use self::async_rewriter::{AsyncContent, AsyncHtmlRewriter};
+pub use self::builder::{RewriterBuilder, RewriterBuilderError};
+pub use self::byte_filters::{InputFilter, OutputFilter, StripPrefix};
+pub use self::sampling::{sampled, Sampler};
+pub use self::selector_switch::SelectorSwitch;
 pub use self::settings::*;
+pub use self::template::RewriterTemplate;
+pub use self::text_pipeline::{TextFilter, TextPipeline};
+pub use self::validator::ValidationDiagnostic;
 
 fn try_encoding_from_str(encoding: &str) -> Result<&'static Encoding, EncodingError> {
     let encoding = Encoding::for_label_no_replacement(encoding.as_bytes())
         .ok_or(EncodingError::UnknownEncoding)?;
 
-    if encoding.is_ascii_compatible() {
-        Ok(encoding)
-    } else {
+    if !encoding.is_ascii_compatible() {
         Err(EncodingError::NonAsciiCompatibleEncoding)
+    } else if cfg!(not(feature = "all_encodings")) && encoding != UTF_8 {
+        Err(EncodingError::UnsupportedEncoding)
+    } else {
+        Ok(encoding)
     }
 }
 
@@ -46,6 +89,104 @@ pub enum EncodingError {
     /// `ISO-2022-JP` and `replacement`). These encodings are not supported.
     #[error("Expected ASCII-compatible encoding.")]
     NonAsciiCompatibleEncoding,
+
+    /// The provided label is for one of the encodings other than UTF-8, while only UTF-8 is
+    /// supported, e.g. if the crate is built without the `all_encodings` feature or by
+    /// [`rewrite_str_with_settings`], which rewrites a UTF-8 string.
+    ///
+    /// [`rewrite_str_with_settings`]: ../fn.rewrite_str_with_settings.html
+    #[error("Expected UTF-8 encoding, since the other encodings are not supported.")]
+    UnsupportedEncoding,
+}
+
+/// An error that occurs if invalid [`Settings`] were provided to construct a rewriter.
+///
+/// [`Settings`]: ../struct.Settings.html
+#[derive(Error, Debug, PartialEq, Copy, Clone)]
+pub enum SettingsError {
+    /// The encoding is invalid. Refer to [`EncodingError`] for more information.
+    ///
+    /// [`EncodingError`]: enum.EncodingError.html
+    #[error("{0}")]
+    Encoding(EncodingError),
+
+    /// One of the selectors can't be matched with the settings, e.g. it requires the lookahead,
+    /// while [`buffer_has_candidates`] is disabled. Refer to [`SelectorError`] for more
+    /// information.
+    ///
+    /// [`buffer_has_candidates`]: ../struct.Settings.html#structfield.buffer_has_candidates
+    /// [`SelectorError`]: enum.SelectorError.html
+    #[cfg(feature = "basic_selectors")]
+    #[error("{0}")]
+    Selector(SelectorError),
+
+    /// The settings don't correspond to the [`RewriterTemplate`] the rewriter is constructed
+    /// from. Refer to [`HtmlRewriter::from_template`] for more information.
+    ///
+    /// [`RewriterTemplate`]: ../struct.RewriterTemplate.html
+    /// [`HtmlRewriter::from_template`]: ../struct.HtmlRewriter.html#method.from_template
+    #[error("The settings don't match the template.")]
+    TemplateMismatch,
+
+    /// The [`parse_ahead_queue_capacity`] is specified along with the [`spill_settings`], while
+    /// the tokens can't be spilled by the parse-ahead thread.
+    ///
+    /// [`parse_ahead_queue_capacity`]: ../struct.Settings.html#structfield.parse_ahead_queue_capacity
+    /// [`spill_settings`]: ../struct.Settings.html#structfield.spill_settings
+    #[cfg(feature = "parse_ahead")]
+    #[error("Tokens can't be spilled when the input is parsed ahead.")]
+    SpillingWithParseAhead,
+}
+
+impl From<EncodingError> for SettingsError {
+    #[inline]
+    fn from(err: EncodingError) -> Self {
+        SettingsError::Encoding(err)
+    }
+}
+
+/// Optimizations engaged by the [`HtmlRewriter`] based on the registered content handlers and
+/// selectors.
+///
+/// Returned by [`HtmlRewriter::engaged_optimizations`].
+///
+/// [`HtmlRewriter`]: struct.HtmlRewriter.html
+/// [`HtmlRewriter::engaged_optimizations`]: struct.HtmlRewriter.html#method.engaged_optimizations
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct EngagedOptimizations {
+    /// Attributes of start tags are not tracked by the parser, since there are no selectors with
+    /// conditions on attributes and no element content handlers that could access them.
+    pub attribute_tracking_disabled: bool,
+    /// All the selectors can match only the `<head>` element and its descendants, so selector
+    /// matching is disabled after the `</head>` end tag.
+    pub head_only_matching: bool,
+    /// Selector matching has been disabled due to [`head_only_matching`], since the `</head>` end
+    /// tag has already been encountered.
+    ///
+    /// [`head_only_matching`]: #structfield.head_only_matching
+    pub matching_stopped: bool,
+    /// The [`matching_cutoff`] point has been reached, so the rest of the document is passed
+    /// through to the output without parsing.
+    ///
+    /// [`matching_cutoff`]: struct.Settings.html#structfield.matching_cutoff
+    pub passthrough: bool,
+}
+
+/// Indicates that the [`HtmlRewriter`] has finished before the end of the input.
+///
+/// Returned by [`HtmlRewriter::analyze`].
+///
+/// [`HtmlRewriter`]: struct.HtmlRewriter.html
+/// [`HtmlRewriter::analyze`]: struct.HtmlRewriter.html#method.analyze
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Finished {
+    /// The [`analysis_limit`] has been reached.
+    ///
+    /// `consumed` is the number of bytes of the written chunk that have been processed by the
+    /// rewriter. The rest of the chunk is left untouched and is not written to the output.
+    ///
+    /// [`analysis_limit`]: struct.Settings.html#structfield.analysis_limit
+    Early { consumed: usize },
 }
 
 /// A compound error type that can be returned by [`write`] and [`end`] methods of the rewriter.
@@ -73,8 +214,39 @@ pub enum RewritingError {
     /// An error that was propagated from one of the content handlers.
     #[error("{0}")]
     ContentHandlerError(Box<dyn StdError>),
+
+    /// The processing [`deadline`] has been exceeded and the [`deadline_policy`] is set to
+    /// [`DeadlinePolicy::Fail`].
+    ///
+    /// [`deadline`]: ../struct.Settings.html#structfield.deadline
+    /// [`deadline_policy`]: ../struct.Settings.html#structfield.deadline_policy
+    /// [`DeadlinePolicy::Fail`]: ../enum.DeadlinePolicy.html#variant.Fail
+    #[error("The document processing deadline has been exceeded.")]
+    DeadlineExceeded,
+
+    /// The settings provided to [`rewrite_str_with_settings`] are invalid. Refer to
+    /// [`SettingsError`] for more information.
+    ///
+    /// [`rewrite_str_with_settings`]: ../fn.rewrite_str_with_settings.html
+    /// [`SettingsError`]: enum.SettingsError.html
+    #[error("{0}")]
+    Settings(SettingsError),
+
+    /// The [`output_filter`] provided to [`rewrite_str_with_settings`] has produced output that
+    /// is not valid UTF-8.
+    ///
+    /// [`output_filter`]: ../struct.Settings.html#structfield.output_filter
+    /// [`rewrite_str_with_settings`]: ../fn.rewrite_str_with_settings.html
+    #[error("The output filter has produced invalid UTF-8: {0}")]
+    InvalidUtf8Output(Utf8Error),
 }
 
+type RewriterStream<'h, O, H> = TransformStream<
+    HtmlRewriteController<'h, H>,
+    BufferingSink<FilteredSink<'h, O>, <H as HandlerTypes>::Sharing>,
+    <H as HandlerTypes>::Sharing,
+>;
+
 /// A streaming HTML rewriter.
 ///
 /// # Example
@@ -115,10 +287,80 @@ pub enum RewritingError {
 ///     r#"<div><a href="https://example.com"></a></div>"#
 /// );
 /// ```
-pub struct HtmlRewriter<'h, O: OutputSink> {
-    stream: TransformStream<HtmlRewriteController<'h>, O>,
+///
+/// # Ownership
+///
+/// The selectors are only borrowed while the rewriter is constructed, so the rewriter is bound
+/// only by the lifetime `'h` of the handlers and by the output sink. A rewriter with `'static`
+/// handlers and an owned output sink, e.g. a boxed closure, can be returned from a function and
+/// stored in a struct field. [`RewriterBuilder`] owns the selectors it parses, so it can be used
+/// to construct such a rewriter from the selectors that are known only at runtime.
+///
+/// ```
+/// use lol_html::{HtmlRewriter, RewriterBuilder};
+/// use std::cell::RefCell;
+/// use std::rc::Rc;
+///
+/// struct Page {
+///     rewriter: HtmlRewriter<'static, Box<dyn FnMut(&[u8])>>,
+///     output: Rc<RefCell<Vec<u8>>>,
+/// }
+///
+/// fn page(hidden: &[&str]) -> Page {
+///     let output = Rc::new(RefCell::new(vec![]));
+///     let sink_output = Rc::clone(&output);
+///
+///     let sink: Box<dyn FnMut(&[u8])> = Box::new(move |c: &[u8]| {
+///         sink_output.borrow_mut().extend_from_slice(c);
+///     });
+///
+///     let rewriter = hidden
+///         .iter()
+///         .fold(RewriterBuilder::new(), |builder, selector| {
+///             builder.on(selector, |el| {
+///                 el.remove();
+///
+///                 Ok(())
+///             })
+///         })
+///         .build(sink)
+///         .unwrap();
+///
+///     Page { rewriter, output }
+/// }
+///
+/// let mut page = page(&["script", ".ad"]);
+///
+/// page.rewriter.write(b"<p>Foo</p><script>bar()</script><div class=ad></div>").unwrap();
+/// page.rewriter.end().unwrap();
+///
+/// assert_eq!(&*page.output.borrow(), b"<p>Foo</p>");
+/// ```
+///
+/// The rewriter constructed from the [`Settings::new_send`] or [`RewriterBuilder::new_send`]
+/// only accepts the handlers that are `Send`, so it's `Send` itself as long as its output sink
+/// is. Such a rewriter can be moved to another thread or held across the `.await` points of
+/// a future that is required to be `Send`.
+///
+/// [`RewriterBuilder`]: struct.RewriterBuilder.html
+/// [`RewriterBuilder::new_send`]: struct.RewriterBuilder.html#method.new_send
+/// [`Settings::new_send`]: struct.Settings.html#method.new_send
+pub struct HtmlRewriter<'h, O: OutputSink, H: HandlerTypes = LocalHandlerTypes> {
+    stream: RewriterStream<'h, O, H>,
+    bytes_before_early_finish: Option<usize>,
+    finished_early: bool,
     finished: bool,
     poisoned: bool,
+    memory_limiter: SharedMemoryLimiter<H::Sharing>,
+    content_buffers: SharedContentBuffers<H::Sharing>,
+    memory_usage_warning_handler: Option<Box<dyn FnMut(usize) + Send + 'h>>,
+    /// Set once the memory usage has exceeded the soft limit and the optional features have
+    /// been disabled.
+    degraded: bool,
+    input_filter: Option<Box<dyn InputFilter + Send + 'h>>,
+    filtered_input: Vec<u8>,
+    #[cfg(feature = "selector_matching")]
+    has_lookahead: Option<Box<HasLookahead<H::Sharing>>>,
 }
 
 macro_rules! guarded {
@@ -128,7 +370,18 @@ macro_rules! guarded {
             "Attempt to use the HtmlRewriter after a fatal error."
         );
 
-        let res = $expr;
+        let mut res = $expr;
+
+        // NOTE: the output sink can't report errors, so the memory limit exceeded by
+        // the buffered inner content of the elements is reported after the fact.
+        if res.is_ok() {
+            if let Some(e) = $self.content_buffers.lock().take_error() {
+                res = Err(RewritingError::MemoryLimitExceeded(e));
+            }
+        }
+
+        $self.report_memory_usage_warning();
+        $self.degrade_on_soft_memory_limit();
 
         if res.is_err() {
             $self.poisoned = true;
@@ -138,66 +391,440 @@ macro_rules! guarded {
     }};
 }
 
-impl<'h, O: OutputSink> HtmlRewriter<'h, O> {
+impl<'h, O: OutputSink, H: HandlerTypes> HtmlRewriter<'h, O, H> {
     /// Constructs a new rewriter with the provided `settings` that writes
     /// the output to the `output_sink`.
     ///
+    /// Fails if the [`encoding`] is invalid or if some of the selectors require the lookahead,
+    /// while [`buffer_has_candidates`] is disabled.
+    ///
     /// # Note
     ///
     /// For the convenience the [`OutputSink`] trait is implemented for closures.
     ///
+    /// [`encoding`]: struct.Settings.html#structfield.encoding
+    /// [`buffer_has_candidates`]: struct.Settings.html#structfield.buffer_has_candidates
     /// [`OutputSink`]: trait.OutputSink.html
-    pub fn try_new<'s>(settings: Settings<'h, 's>, output_sink: O) -> Result<Self, EncodingError> {
-        let encoding = try_encoding_from_str(settings.encoding)?;
-        let mut selectors_ast = selectors_vm::Ast::default();
+    pub fn try_new<'s>(
+        settings: Settings<'h, 's, H>,
+        output_sink: O,
+    ) -> Result<Self, SettingsError> {
+        let template = RewriterTemplate::try_new(&settings)?;
+
+        HtmlRewriter::from_template(&template, settings, output_sink)
+    }
+
+    /// Constructs a new rewriter from the precompiled `template` with the provided `settings`
+    /// that writes the output to the `output_sink`.
+    ///
+    /// The selectors of the element content handlers of the `settings` are not compiled: the
+    /// handlers are associated with the selectors of the template in order. The settings
+    /// captured by the template, e.g. the encoding, are taken from the template. Refer to
+    /// [`RewriterTemplate`] for more information.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`SettingsError::TemplateMismatch`] if the element content handlers of the
+    /// `settings` don't correspond to the ones the template has been created with, i.e. their
+    /// number or the kinds of the handlers attached to the selectors differ, or if the
+    /// `settings` specify a different encoding or obsolete elements policy. Fails with
+    /// [`SelectorError::LookaheadRequired`] if the `settings` disable the
+    /// [`buffer_has_candidates`] lookahead required by the selectors of the template. Fails
+    /// with [`SettingsError::SpillingWithParseAhead`] if the `settings` specify both the
+    /// [`parse_ahead_queue_capacity`] and the [`spill_settings`].
+    ///
+    /// [`RewriterTemplate`]: struct.RewriterTemplate.html
+    /// [`SettingsError::TemplateMismatch`]: errors/enum.SettingsError.html#variant.TemplateMismatch
+    /// [`SelectorError::LookaheadRequired`]: errors/enum.SelectorError.html#variant.LookaheadRequired
+    /// [`buffer_has_candidates`]: struct.Settings.html#structfield.buffer_has_candidates
+    /// [`SettingsError::SpillingWithParseAhead`]: errors/enum.SettingsError.html#variant.SpillingWithParseAhead
+    /// [`parse_ahead_queue_capacity`]: struct.Settings.html#structfield.parse_ahead_queue_capacity
+    /// [`spill_settings`]: struct.Settings.html#structfield.spill_settings
+    pub fn from_template<'s>(
+        template: &RewriterTemplate,
+        settings: Settings<'h, 's, H>,
+        output_sink: O,
+    ) -> Result<Self, SettingsError> {
+        let encoding = template.encoding;
         let mut dispatcher = ContentHandlersDispatcher::default();
-        let has_selectors = !settings.element_content_handlers.is_empty();
+        let mut match_limits = Vec::default();
+
+        if try_encoding_from_str(settings.encoding)? != encoding
+            || settings.element_content_handlers.len() != template.user_selector_count
+            || settings.obsolete_elements_policy != template.obsolete_elements_policy
+        {
+            return Err(SettingsError::TemplateMismatch);
+        }
+
+        #[cfg(feature = "parse_ahead")]
+        {
+            if settings.parse_ahead_queue_capacity.is_some()
+                && settings.spill_settings != SpillSettings::default()
+            {
+                return Err(SettingsError::SpillingWithParseAhead);
+            }
+        }
+
+        #[cfg(feature = "selector_matching")]
+        {
+            if !settings.buffer_has_candidates
+                && (!template.has_conditions.is_empty()
+                    || !template.last_sibling_conditions.is_empty())
+            {
+                return Err(SettingsError::Selector(SelectorError::LookaheadRequired));
+            }
+        }
+
+        #[cfg(feature = "selector_matching")]
+        let lookahead_settings = if settings.buffer_has_candidates {
+            Some(HasLookahead::<H::Sharing>::settings(&settings))
+        } else {
+            None
+        };
+
+        let mut locators = template.locators.iter();
+        let mut selector_sources = template.selector_sources.iter().cloned();
+        let mut disabled_locators = Vec::default();
+
+        for (_, handlers) in settings.element_content_handlers {
+            let match_limit = handlers.match_limit;
+            let disabled = handlers.disabled;
+            // NOTE: the number of the handlers is checked above, so it's safe to unwrap here.
+            let selector = selector_sources.next().unwrap();
+            let locator = dispatcher.add_selector_associated_handlers(handlers, selector);
+
+            if locators.next() != Some(&locator) {
+                return Err(SettingsError::TemplateMismatch);
+            }
+
+            if let Some(limit) = match_limit {
+                match_limits.push((locator, limit));
+            }
 
-        for (selector, handlers) in settings.element_content_handlers {
-            let locator = dispatcher.add_selector_associated_handlers(handlers);
+            if disabled {
+                disabled_locators.push(locator);
+            }
+        }
 
-            selectors_ast.add_selector(selector, locator);
+        #[cfg(feature = "basic_selectors")]
+        for ((_, handlers), selector) in
+            obsolete_elements_handlers::<H>(settings.obsolete_elements_policy)
+                .into_iter()
+                .zip(selector_sources)
+        {
+            dispatcher.add_selector_associated_handlers(handlers, selector);
         }
 
         for handlers in settings.document_content_handlers {
             dispatcher.add_document_content_handlers(handlers);
         }
 
-        let memory_limiter =
+        if !settings.comment_content_handlers.is_empty() {
+            let markup = CommentMarkup::new(
+                settings.comment_content_handlers,
+                encoding,
+                settings.memory_settings.max_allowed_memory_usage,
+                settings.strict,
+            );
+
+            dispatcher.add_comment_markup(markup, 0);
+        }
+
+        if let Some(normalization) = settings.entity_normalization {
+            let mut normalizer = EntityNormalizer::new(normalization);
+
+            // NOTE: the normalization is applied after all the other text handlers.
+            dispatcher.add_document_content_handlers(DocumentContentHandlers {
+                text: Some(H::new_text_handler(move |t| {
+                    normalizer.handle_text_chunk(t)
+                })),
+                priority: i32::MIN,
+                ..DocumentContentHandlers::default()
+            });
+        }
+
+        dispatcher.set_content_policies(ContentPolicies {
+            table: settings.table_content_policy,
+            select: settings.select_content_policy,
+        });
+
+        if settings.count_selector_matches {
+            dispatcher.enable_match_counting();
+        }
+
+        if settings.canonical_output {
+            dispatcher.enable_canonical_output();
+        }
+
+        let optimizations = EngagedOptimizations {
+            attribute_tracking_disabled: !template.has_attribute_exprs
+                && !dispatcher.has_element_handlers()
+                && !settings.canonical_output
+                && !settings.track_element_ids
+                && settings.match_declarative_shadow_roots,
+            head_only_matching: template.head_only_matching,
+            matching_stopped: false,
+            passthrough: false,
+        };
+
+        let memory_limiter: SharedMemoryLimiter<H::Sharing> =
             MemoryLimiter::new_shared(settings.memory_settings.max_allowed_memory_usage);
 
-        let selector_matching_vm = if has_selectors {
-            Some(SelectorMatchingVm::new(
-                selectors_ast,
-                encoding,
-                Rc::clone(&memory_limiter),
-            ))
-        } else {
-            None
+        if settings.memory_settings.soft_memory_usage_limit > 0 {
+            memory_limiter
+                .lock()
+                .set_soft_limit(settings.memory_settings.soft_memory_usage_limit);
+        }
+
+        let content_buffers =
+            ContentBuffers::new_shared(SharedMemoryLimiter::clone(&memory_limiter));
+
+        dispatcher.set_content_buffers(SharedContentBuffers::clone(&content_buffers));
+
+        let match_cache_capacity = settings.match_cache_capacity;
+        let track_element_ids = settings.track_element_ids;
+        let preallocated_stack_depth = settings
+            .memory_settings
+            .preallocated_selector_matching_stack_depth;
+
+        let memory_usage_warning_handler = settings.memory_usage_warning.map(|warning| {
+            memory_limiter
+                .lock()
+                .set_warning_threshold(warning.threshold);
+
+            warning.handler
+        });
+
+        let selector_matching_vm = template.program.as_ref().map(|program| {
+            let mut vm = SelectorMatchingVm::from_program(
+                Arc::clone(program),
+                SharedMemoryLimiter::clone(&memory_limiter),
+            );
+
+            for (locator, limit) in match_limits {
+                vm.set_match_limit(locator, limit);
+            }
+
+            for locator in disabled_locators {
+                vm.set_payload_enabled(locator, false);
+            }
+
+            if let Some(capacity) = match_cache_capacity {
+                vm.enable_match_cache(capacity);
+            }
+
+            if track_element_ids {
+                vm.enable_id_tracking();
+            }
+
+            vm.preallocate_stack(preallocated_stack_depth);
+
+            vm
+        });
+
+        #[cfg(feature = "selector_matching")]
+        let has_lookahead = match (lookahead_settings, &selector_matching_vm) {
+            (Some(lookahead_settings), Some(vm))
+                if !template.has_conditions.is_empty()
+                    || !template.last_sibling_conditions.is_empty() =>
+            {
+                Some(Box::new(HasLookahead::try_new(
+                    template.has_conditions.clone(),
+                    template.last_sibling_conditions.clone(),
+                    vm.has_matches(),
+                    SharedMemoryLimiter::clone(&memory_limiter),
+                    lookahead_settings,
+                )?))
+            }
+            _ => None,
         };
 
-        let controller = HtmlRewriteController::new(dispatcher, selector_matching_vm);
+        let mut controller = HtmlRewriteController::new(
+            dispatcher,
+            selector_matching_vm,
+            optimizations,
+            settings.head_only || settings.matching_cutoff == Some(MatchingCutoff::HeadEnd),
+            !settings.match_declarative_shadow_roots,
+            settings.deadline,
+            settings.deadline_policy,
+        );
+
+        if let Some(selector_switch) = settings.selector_switch {
+            controller.set_selector_switch(selector_switch);
+        }
+
+        if let Some(validation_handler) = settings.validation_handler {
+            controller.set_validator(Validator::new(validation_handler, encoding));
+        }
 
         let stream = TransformStream::new(TransformStreamSettings {
             transform_controller: controller,
-            output_sink,
+            output_sink: BufferingSink::new(
+                FilteredSink::new(
+                    output_sink,
+                    settings.output_filter,
+                    settings.memory_settings.preallocated_output_buffer_size,
+                ),
+                SharedContentBuffers::clone(&content_buffers),
+            ),
             preallocated_parsing_buffer_size: settings
                 .memory_settings
                 .preallocated_parsing_buffer_size,
-            memory_limiter,
+            preallocated_attribute_buffer_capacity: settings
+                .memory_settings
+                .preallocated_attribute_buffer_capacity,
+            memory_limiter: SharedMemoryLimiter::clone(&memory_limiter),
             encoding,
             strict: settings.strict,
+            spill_settings: settings.spill_settings,
+            stream_comments: settings.stream_comments,
+            passthrough_after: match settings.matching_cutoff {
+                Some(MatchingCutoff::Bytes(count)) => Some(count),
+                _ => None,
+            },
+            #[cfg(feature = "parse_ahead")]
+            parse_ahead_queue_capacity: settings.parse_ahead_queue_capacity,
         });
 
         Ok(HtmlRewriter {
             stream,
+            bytes_before_early_finish: settings.analysis_limit,
+            finished_early: false,
             finished: false,
             poisoned: false,
+            memory_limiter,
+            content_buffers,
+            memory_usage_warning_handler,
+            degraded: false,
+            input_filter: settings.input_filter,
+            filtered_input: Vec::new(),
+            #[cfg(feature = "selector_matching")]
+            has_lookahead,
         })
     }
 
+    fn report_memory_usage_warning(&mut self) {
+        if let Some(ref mut handler) = self.memory_usage_warning_handler {
+            if let Some(usage) = self.memory_limiter.lock().take_warning() {
+                handler(usage);
+            }
+        }
+    }
+
+    /// Gives up the optional features that consume memory, once the memory usage has exceeded
+    /// the soft limit.
+    fn degrade_on_soft_memory_limit(&mut self) {
+        if self.degraded || !self.memory_limiter.lock().is_soft_limit_exceeded() {
+            return;
+        }
+
+        self.degraded = true;
+        self.stream.transform_controller_mut().disable_match_cache();
+
+        #[cfg(feature = "selector_matching")]
+        {
+            if let Some(ref mut lookahead) = self.has_lookahead {
+                lookahead.disable();
+            }
+        }
+    }
+
+    /// Writes the data to the stream, passing it through the input filter first, if it's
+    /// specified.
+    fn write_to_stream(&mut self, data: &[u8]) -> Result<(), RewritingError> {
+        match self.input_filter {
+            Some(ref mut filter) => {
+                let mut filtered = std::mem::take(&mut self.filtered_input);
+
+                filter.filter(data, &mut filtered);
+
+                let res = self.write_filtered_to_stream(&filtered);
+
+                filtered.clear();
+                self.filtered_input = filtered;
+
+                res
+            }
+            None => self.write_filtered_to_stream(data),
+        }
+    }
+
+    /// Flushes the bytes held back by the input filter at the end of the document.
+    fn end_input_filter(&mut self) -> Result<(), RewritingError> {
+        if let Some(ref mut filter) = self.input_filter {
+            let mut filtered = std::mem::take(&mut self.filtered_input);
+
+            filter.end(&mut filtered);
+
+            let res = self.write_filtered_to_stream(&filtered);
+
+            filtered.clear();
+            self.filtered_input = filtered;
+
+            res?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the filtered data to the stream, passing it through the `:has()` lookahead first,
+    /// if it's enabled.
+    fn write_filtered_to_stream(&mut self, data: &[u8]) -> Result<(), RewritingError> {
+        #[cfg(feature = "selector_matching")]
+        {
+            if let Some(ref mut lookahead) = self.has_lookahead {
+                let released = lookahead.write(data)?;
+
+                return self.stream.write(&released);
+            }
+        }
+
+        self.stream.write(data)
+    }
+
+    fn end_stream(&mut self) -> Result<(), RewritingError> {
+        self.end_input_filter()?;
+
+        #[cfg(feature = "selector_matching")]
+        {
+            if let Some(ref mut lookahead) = self.has_lookahead {
+                let released = lookahead.end()?;
+
+                self.stream.write(&released)?;
+            }
+        }
+
+        self.stream.end()
+    }
+
+    fn start_new_document_in_stream(&mut self) -> Result<(), RewritingError> {
+        self.end_input_filter()?;
+
+        #[cfg(feature = "selector_matching")]
+        {
+            if let Some(ref mut lookahead) = self.has_lookahead {
+                let released = lookahead.start_new_document()?;
+
+                self.stream.write(&released)?;
+                self.stream.start_new_document()?;
+                lookahead.clear_matches();
+                self.content_buffers.lock().release_all();
+
+                return Ok(());
+            }
+        }
+
+        self.stream.start_new_document()?;
+        self.content_buffers.lock().release_all();
+
+        Ok(())
+    }
+
     /// Writes a chunk of input data to the rewriter.
     ///
+    /// If the [`analysis_limit`] is specified in the settings, the input that exceeds the limit
+    /// is ignored. Use [`analyze`] to find out which part of the input has been processed.
+    ///
     /// # Panics
     ///  * If previous invocation of the method returned a [`RewritingError`]
     ///    (these errors are unrecovarable).
@@ -205,42 +832,605 @@ impl<'h, O: OutputSink> HtmlRewriter<'h, O> {
     ///
     /// [`RewritingError`]: errors/enum.RewritingError.html
     /// [`end`]: struct.HtmlRewriter.html#method.end
+    /// [`analysis_limit`]: struct.Settings.html#structfield.analysis_limit
+    /// [`analyze`]: struct.HtmlRewriter.html#method.analyze
     #[inline]
     pub fn write(&mut self, data: &[u8]) -> Result<(), RewritingError> {
+        self.analyze(data).map(|_| ())
+    }
+
+    /// Writes a chunk of input data to the rewriter, processing at most [`analysis_limit`] bytes
+    /// of the input in total.
+    ///
+    /// Returns [`Finished::Early`] once the limit is reached. In this case, the rewriter is ended
+    /// as if [`end`] has been called with the processed part of the input and all the following
+    /// input is left untouched. Subsequent calls of the method don't process any input and
+    /// return [`Finished::Early`] with no bytes consumed, and calling [`end`] is not required.
+    ///
+    /// Returns `None` if the whole chunk has been processed or the limit is not specified.
+    ///
+    /// # Example
+    /// ```
+    /// use lol_html::{text, Finished, HtmlRewriter, Settings};
+    ///
+    /// let mut title = String::new();
+    ///
+    /// {
+    ///     let mut rewriter = HtmlRewriter::try_new(
+    ///         Settings {
+    ///             element_content_handlers: vec![
+    ///                 text!("title", |t| {
+    ///                     title += t.as_str();
+    ///
+    ///                     Ok(())
+    ///                 })
+    ///             ],
+    ///             analysis_limit: Some(32),
+    ///             ..Settings::default()
+    ///         },
+    ///         |_: &[u8]| {}
+    ///     ).unwrap();
+    ///
+    ///     let input = b"<head><title>Foo</title></head><body>...</body>";
+    ///
+    ///     assert_eq!(rewriter.analyze(input).unwrap(), Some(Finished::Early { consumed: 32 }));
+    /// }
+    ///
+    /// assert_eq!(title, "Foo");
+    /// ```
+    ///
+    /// # Panics
+    ///  * If previous invocation of the method returned a [`RewritingError`]
+    ///    (these errors are unrecovarable).
+    ///  * If called after [`end`].
+    ///
+    /// [`analysis_limit`]: struct.Settings.html#structfield.analysis_limit
+    /// [`Finished::Early`]: enum.Finished.html#variant.Early
+    /// [`RewritingError`]: errors/enum.RewritingError.html
+    /// [`end`]: struct.HtmlRewriter.html#method.end
+    pub fn analyze(&mut self, data: &[u8]) -> Result<Option<Finished>, RewritingError> {
+        if self.finished_early {
+            return Ok(Some(Finished::Early { consumed: 0 }));
+        }
+
         assert!(
             !self.finished,
             "Data was written into the stream after it has ended."
         );
 
-        guarded!(self, self.stream.write(data))
+        let data = match self.bytes_before_early_finish {
+            Some(ref mut remaining) => {
+                let len = data.len().min(*remaining);
+
+                *remaining -= len;
+                &data[..len]
+            }
+            None => data,
+        };
+
+        guarded!(self, self.write_to_stream(data))?;
+
+        if self.bytes_before_early_finish == Some(0) {
+            self.finished_early = true;
+            self.finished = true;
+
+            guarded!(self, self.end_stream())?;
+
+            return Ok(Some(Finished::Early {
+                consumed: data.len(),
+            }));
+        }
+
+        Ok(None)
     }
 
-    /// Finalizes the rewriting process.
+    /// Finishes the current document and starts a new one.
     ///
-    /// Should be called once the last chunk of the input is written.
+    /// Useful for streams that contain multiple concatenated HTML documents (e.g. WARC or MHTML
+    /// archives, or HTML fragments that are sent one after another over a single connection).
+    /// The caller is responsible for detecting the boundaries of the documents in the stream and
+    /// should call the method between the last chunk of a document and the first chunk of the
+    /// next one.
+    ///
+    /// The incomplete content of the current document (e.g. an unclosed tag) is finalized as if
+    /// the end of the input has been reached. Then, all the per-document state is reset: the
+    /// parser state, the open element stack used for selector matching, the match limits and
+    /// the handlers that have been activated by the matched elements, so the content of the next
+    /// document doesn't match selectors in the context of the previous one. The [document end
+    /// handlers] are not invoked at the boundary, they are invoked only once by [`end`].
+    ///
+    /// # Example
+    /// ```
+    /// use lol_html::{element, HtmlRewriter, Settings};
+    ///
+    /// let mut output = vec![];
+    ///
+    /// {
+    ///     let mut rewriter = HtmlRewriter::try_new(
+    ///         Settings {
+    ///             element_content_handlers: vec![
+    ///                 element!("div p", |el| {
+    ///                     el.set_attribute("class", "nested")?;
+    ///
+    ///                     Ok(())
+    ///                 })
+    ///             ],
+    ///             ..Settings::default()
+    ///         },
+    ///         |c: &[u8]| output.extend_from_slice(c)
+    ///     ).unwrap();
+    ///
+    ///     rewriter.write(b"<div><p>Foo</p>").unwrap();
+    ///     rewriter.start_new_document().unwrap();
+    ///     rewriter.write(b"<p>Bar</p>").unwrap();
+    ///     rewriter.end().unwrap();
+    /// }
+    ///
+    /// assert_eq!(
+    ///     String::from_utf8(output).unwrap(),
+    ///     r#"<div><p class="nested">Foo</p><p>Bar</p>"#
+    /// );
+    /// ```
     ///
     /// # Panics
     ///  * If previous invocation of [`write`] returned a [`RewritingError`] (these errors
     ///    are unrecovarable).
-    ///  * If called twice.
+    ///  * If called after [`end`].
     ///
+    /// [document end handlers]: struct.DocumentContentHandlers.html#method.end
     /// [`RewritingError`]: errors/enum.RewritingError.html
     /// [`write`]: struct.HtmlRewriter.html#method.write
-    #[inline]
-    pub fn end(&mut self) -> Result<(), RewritingError> {
-        assert!(!self.finished, "Stream was ended twice.");
-        self.finished = true;
+    /// [`end`]: struct.HtmlRewriter.html#method.end
+    pub fn start_new_document(&mut self) -> Result<(), RewritingError> {
+        if self.finished_early {
+            return Ok(());
+        }
 
-        guarded!(self, self.stream.end())
-    }
-}
+        assert!(
+            !self.finished,
+            "New document was started after the stream has ended."
+        );
 
-// NOTE: this opaque Debug implementation is required to make
-// `.unwrap()` and `.expect()` methods available on Result
-// returned by the `HtmlRewriterBuilder.build()` method.
-impl<O: OutputSink> Debug for HtmlRewriter<'_, O> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "HtmlRewriter")
+        guarded!(self, self.start_new_document_in_stream())
+    }
+
+    /// Writes the prefix of the document to the rewriter, memoizing the result of parsing it
+    /// in the `cache`.
+    ///
+    /// Documents produced from the same template often share the same head section. If the
+    /// `prefix` has been written with the `cache` before, it's not parsed again: the memoized
+    /// lexemes are replayed to the content handlers instead, so the output is the same as if
+    /// the `prefix` has been passed to [`write`]. The prefix is only memoized if it ends between
+    /// the tokens in the initial parsing context, e.g. not in the middle of a tag or in the
+    /// content of a `<script>` element.
+    ///
+    /// The method behaves exactly like [`write`] if it's not called at the start of the
+    /// document, or if [`analysis_limit`], [`input_filter`] or [`parse_ahead_queue_capacity`]
+    /// is specified or candidates of `:has()` are buffered.
+    ///
+    /// # Example
+    /// ```
+    /// use lol_html::{element, HtmlRewriter, PrefixCache, Settings};
+    ///
+    /// let mut cache = PrefixCache::new();
+    /// let prefix = b"<!DOCTYPE html><head><title>Blog</title></head>";
+    ///
+    /// for body in &[&b"<body>Foo</body>"[..], &b"<body>Bar</body>"[..]] {
+    ///     let mut output = vec![];
+    ///
+    ///     {
+    ///         let mut rewriter = HtmlRewriter::try_new(
+    ///             Settings {
+    ///                 element_content_handlers: vec![
+    ///                     element!("title", |el| {
+    ///                         el.set_attribute("lang", "en")?;
+    ///
+    ///                         Ok(())
+    ///                     })
+    ///                 ],
+    ///                 ..Settings::default()
+    ///             },
+    ///             |c: &[u8]| output.extend_from_slice(c)
+    ///         ).unwrap();
+    ///
+    ///         rewriter.write_prefix(prefix, &mut cache).unwrap();
+    ///         rewriter.write(body).unwrap();
+    ///         rewriter.end().unwrap();
+    ///     }
+    ///
+    ///     assert!(output.starts_with(br#"<!DOCTYPE html><head><title lang="en">Blog</title>"#));
+    /// }
+    ///
+    /// assert_eq!(cache.len(), 1);
+    /// ```
+    ///
+    /// # Panics
+    ///  * If previous invocation of [`write`] returned a [`RewritingError`] (these errors
+    ///    are unrecovarable).
+    ///  * If called after [`end`].
+    ///
+    /// [`analysis_limit`]: struct.Settings.html#structfield.analysis_limit
+    /// [`input_filter`]: struct.Settings.html#structfield.input_filter
+    /// [`parse_ahead_queue_capacity`]: struct.Settings.html#structfield.parse_ahead_queue_capacity
+    /// [`RewritingError`]: errors/enum.RewritingError.html
+    /// [`write`]: struct.HtmlRewriter.html#method.write
+    /// [`end`]: struct.HtmlRewriter.html#method.end
+    pub fn write_prefix(
+        &mut self,
+        prefix: &[u8],
+        cache: &mut PrefixCache,
+    ) -> Result<(), RewritingError> {
+        #[cfg(feature = "selector_matching")]
+        {
+            if self.has_lookahead.is_some() {
+                return self.write(prefix);
+            }
+        }
+
+        // NOTE: the prefixes are not memoized once the soft memory limit has been exceeded, and
+        // the filtered input doesn't necessarily match the cached prefix.
+        if self.finished_early
+            || self.bytes_before_early_finish.is_some()
+            || self.degraded
+            || self.input_filter.is_some()
+        {
+            return self.write(prefix);
+        }
+
+        assert!(
+            !self.finished,
+            "Data was written into the stream after it has ended."
+        );
+
+        guarded!(self, self.stream.write_prefix(prefix, cache))
+    }
+
+    /// Writes a chunk of input data to the rewriter, emitting it to the output verbatim without
+    /// parsing it.
+    ///
+    /// Useful for hybrid pipelines where the static parts of a document (e.g. cached fragments
+    /// of a template) are known to not require rewriting and only the dynamic parts need to be
+    /// parsed. The data is invisible to the content handlers and doesn't affect the parser
+    /// state, so parsing resumes after the data in the same context as before it. Therefore,
+    /// the data should contain complete markup that doesn't change the parsing context, e.g.
+    /// balanced elements. The data is still removed with the content of the element that it's
+    /// written into, if the element is removed.
+    ///
+    /// The data is parsed as if it has been passed to [`write`] if it follows an incomplete
+    /// token (e.g. an unclosed tag), or if [`analysis_limit`], [`input_filter`] or
+    /// [`parse_ahead_queue_capacity`] is specified or `:has()` selectors are used.
+    ///
+    /// # Example
+    /// ```
+    /// use lol_html::{element, HtmlRewriter, Settings};
+    ///
+    /// let mut output = vec![];
+    ///
+    /// {
+    ///     let mut rewriter = HtmlRewriter::try_new(
+    ///         Settings {
+    ///             element_content_handlers: vec![
+    ///                 element!("a", |el| {
+    ///                     el.set_attribute("rel", "nofollow")?;
+    ///
+    ///                     Ok(())
+    ///                 })
+    ///             ],
+    ///             ..Settings::default()
+    ///         },
+    ///         |c: &[u8]| output.extend_from_slice(c)
+    ///     ).unwrap();
+    ///
+    ///     rewriter.write(b"<div><a href='/foo'>Foo</a>").unwrap();
+    ///     rewriter.write_verbatim(b"<a href='/bar'>Bar</a>").unwrap();
+    ///     rewriter.write(b"</div>").unwrap();
+    ///     rewriter.end().unwrap();
+    /// }
+    ///
+    /// assert_eq!(
+    ///     String::from_utf8(output).unwrap(),
+    ///     r#"<div><a href='/foo' rel="nofollow">Foo</a><a href='/bar'>Bar</a></div>"#
+    /// );
+    /// ```
+    ///
+    /// # Panics
+    ///  * If previous invocation of [`write`] returned a [`RewritingError`] (these errors
+    ///    are unrecovarable).
+    ///  * If called after [`end`].
+    ///
+    /// [`analysis_limit`]: struct.Settings.html#structfield.analysis_limit
+    /// [`input_filter`]: struct.Settings.html#structfield.input_filter
+    /// [`parse_ahead_queue_capacity`]: struct.Settings.html#structfield.parse_ahead_queue_capacity
+    /// [`RewritingError`]: errors/enum.RewritingError.html
+    /// [`write`]: struct.HtmlRewriter.html#method.write
+    /// [`end`]: struct.HtmlRewriter.html#method.end
+    pub fn write_verbatim(&mut self, data: &[u8]) -> Result<(), RewritingError> {
+        #[cfg(feature = "selector_matching")]
+        {
+            if self.has_lookahead.is_some() {
+                return self.write(data);
+            }
+        }
+
+        if self.finished_early
+            || self.bytes_before_early_finish.is_some()
+            || self.input_filter.is_some()
+        {
+            return self.write(data);
+        }
+
+        assert!(
+            !self.finished,
+            "Data was written into the stream after it has ended."
+        );
+
+        guarded!(self, self.stream.write_verbatim(data))
+    }
+
+    /// Writes a chunk of input data to the rewriter, emitting the `verbatim_ranges` of the data
+    /// to the output without parsing them.
+    ///
+    /// The ranges are handled as if they have been passed to [`write_verbatim`] and the rest
+    /// of the data as if it has been passed to [`write`].
+    ///
+    /// # Panics
+    ///  * If the ranges are not sorted, overlap or are out of bounds of the data.
+    ///  * If previous invocation of [`write`] returned a [`RewritingError`] (these errors
+    ///    are unrecovarable).
+    ///  * If called after [`end`].
+    ///
+    /// [`RewritingError`]: errors/enum.RewritingError.html
+    /// [`write`]: struct.HtmlRewriter.html#method.write
+    /// [`write_verbatim`]: struct.HtmlRewriter.html#method.write_verbatim
+    /// [`end`]: struct.HtmlRewriter.html#method.end
+    pub fn write_with_verbatim_ranges(
+        &mut self,
+        data: &[u8],
+        verbatim_ranges: &[Range<usize>],
+    ) -> Result<(), RewritingError> {
+        let mut pos = 0;
+
+        for range in verbatim_ranges {
+            assert!(
+                pos <= range.start && range.start <= range.end && range.end <= data.len(),
+                "Verbatim ranges must be sorted, non-overlapping and within the data."
+            );
+
+            self.write(&data[pos..range.start])?;
+            self.write_verbatim(&data[range.clone()])?;
+
+            pos = range.end;
+        }
+
+        self.write(&data[pos..])
+    }
+
+    /// Returns the optimizations that the rewriter has engaged based on the registered content
+    /// handlers and selectors.
+    ///
+    /// # Example
+    /// ```
+    /// use lol_html::html_content::ContentType;
+    /// use lol_html::{element, HtmlRewriter, Settings};
+    ///
+    /// let mut rewriter = HtmlRewriter::try_new(
+    ///     Settings {
+    ///         element_content_handlers: vec![
+    ///             element!("head > title", |el| {
+    ///                 el.set_inner_content("Foo", ContentType::Text);
+    ///
+    ///                 Ok(())
+    ///             })
+    ///         ],
+    ///         ..Settings::default()
+    ///     },
+    ///     |_: &[u8]| {}
+    /// ).unwrap();
+    ///
+    /// assert!(rewriter.engaged_optimizations().head_only_matching);
+    /// assert!(!rewriter.engaged_optimizations().matching_stopped);
+    ///
+    /// rewriter.write(b"<head><title>Bar</title></head><body>").unwrap();
+    ///
+    /// assert!(rewriter.engaged_optimizations().matching_stopped);
+    /// ```
+    #[inline]
+    pub fn engaged_optimizations(&self) -> EngagedOptimizations {
+        EngagedOptimizations {
+            passthrough: self.stream.is_passthrough(),
+            ..self.stream.transform_controller().engaged_optimizations()
+        }
+    }
+
+    /// Disables or enables the selector with the given `index` in [`element_content_handlers`].
+    /// Returns `false` if there is no such selector.
+    ///
+    /// Selectors can be disabled and enabled on a live rewriter, e.g. to hot-update blocking
+    /// rules of a long-lived proxy, without recompilation of the selector matching program. A
+    /// disabled selector doesn't match any elements that follow in the input, including the
+    /// documents started with [`start_new_document`], while elements it has already matched
+    /// are processed by its handlers as usual. Once enabled again, the selector's [match limit]
+    /// is restored with the full quota.
+    ///
+    /// The selectors of the rewriter can't be changed otherwise. To add new selectors, e.g. new
+    /// blocking rules, only the changes can be compiled with [`RewriterTemplate::add_selectors`]
+    /// and [`RewriterTemplate::remove_selectors`] into a template the next rewriters are
+    /// instantiated from.
+    ///
+    /// # Example
+    /// ```
+    /// use lol_html::{element, HtmlRewriter, Settings};
+    ///
+    /// let mut output = vec![];
+    ///
+    /// {
+    ///     let mut rewriter = HtmlRewriter::try_new(
+    ///         Settings {
+    ///             element_content_handlers: vec![element!(".ad", |el| {
+    ///                 el.remove();
+    ///
+    ///                 Ok(())
+    ///             })],
+    ///             ..Settings::default()
+    ///         },
+    ///         |c: &[u8]| output.extend_from_slice(c)
+    ///     ).unwrap();
+    ///
+    ///     rewriter.write(br#"<div class="ad">Foo</div>"#).unwrap();
+    ///
+    ///     assert!(rewriter.set_selector_enabled(0, false));
+    ///
+    ///     rewriter.write(br#"<div class="ad">Bar</div>"#).unwrap();
+    ///     rewriter.end().unwrap();
+    /// }
+    ///
+    /// assert_eq!(String::from_utf8(output).unwrap(), r#"<div class="ad">Bar</div>"#);
+    /// ```
+    ///
+    /// [`element_content_handlers`]: struct.Settings.html#structfield.element_content_handlers
+    /// [`start_new_document`]: struct.HtmlRewriter.html#method.start_new_document
+    /// [match limit]: struct.ElementContentHandlers.html#method.match_limit
+    /// [`RewriterTemplate::add_selectors`]: struct.RewriterTemplate.html#method.add_selectors
+    /// [`RewriterTemplate::remove_selectors`]: struct.RewriterTemplate.html#method.remove_selectors
+    #[inline]
+    pub fn set_selector_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        self.stream
+            .transform_controller_mut()
+            .set_selector_enabled(index, enabled)
+    }
+
+    /// Returns the number of matches of each of the selectors in the order they are specified in
+    /// [`element_content_handlers`], or `None` if [`count_selector_matches`] is not enabled.
+    ///
+    /// Matches that exceed the [match limit] of the selector are not counted. The counts are
+    /// accumulated across the documents started with [`start_new_document`].
+    ///
+    /// # Example
+    /// ```
+    /// use lol_html::{element, HtmlRewriter, Settings};
+    ///
+    /// let mut rewriter = HtmlRewriter::try_new(
+    ///     Settings {
+    ///         element_content_handlers: vec![
+    ///             element!("p", |_| Ok(())),
+    ///             element!(".legacy-banner", |el| {
+    ///                 el.remove();
+    ///
+    ///                 Ok(())
+    ///             }),
+    ///         ],
+    ///         count_selector_matches: true,
+    ///         ..Settings::default()
+    ///     },
+    ///     |_: &[u8]| {}
+    /// ).unwrap();
+    ///
+    /// rewriter.write(b"<p>Foo</p><p>Bar</p>").unwrap();
+    /// rewriter.end().unwrap();
+    ///
+    /// assert_eq!(rewriter.selector_match_counts(), Some(vec![2, 0]));
+    /// ```
+    ///
+    /// [`element_content_handlers`]: struct.Settings.html#structfield.element_content_handlers
+    /// [`count_selector_matches`]: struct.Settings.html#structfield.count_selector_matches
+    /// [match limit]: struct.ElementContentHandlers.html#method.match_limit
+    /// [`start_new_document`]: struct.HtmlRewriter.html#method.start_new_document
+    #[inline]
+    pub fn selector_match_counts(&self) -> Option<Vec<usize>> {
+        self.stream.transform_controller().selector_match_counts()
+    }
+
+    /// Returns the path of the currently open elements, e.g. `html > body > div#main > ul`.
+    ///
+    /// The path is computed on demand from the open element stack of the selector matching
+    /// engine, so it doesn't add any overhead unless called. It's mainly useful for logging and
+    /// debugging, e.g. to report the location in the document where the rewriting has failed.
+    /// The path of the element that is being handled can be obtained in the element content
+    /// handlers with [`Element::current_path`].
+    ///
+    /// The tag names of the elements are followed by their `id`s only if [`track_element_ids`]
+    /// is enabled. The path reflects the stack as it's seen by the selector matching engine:
+    /// void elements are not included and
+    /// elements implicitly closed by the HTML parser (e.g. `<p>` followed by another `<p>`) stay
+    /// on the stack until their end tags or the end tags of their ancestors. The path is empty if
+    /// the rewriter doesn't have any element content handlers.
+    ///
+    /// # Example
+    /// ```
+    /// use lol_html::{element, HtmlRewriter, Settings};
+    ///
+    /// let mut rewriter = HtmlRewriter::try_new(
+    ///     Settings {
+    ///         element_content_handlers: vec![element!("li", |_| Ok(()))],
+    ///         track_element_ids: true,
+    ///         ..Settings::default()
+    ///     },
+    ///     |_: &[u8]| {}
+    /// ).unwrap();
+    ///
+    /// rewriter.write(b"<html><body><div id=main><ul><li>Foo</li>").unwrap();
+    ///
+    /// assert_eq!(rewriter.current_path(), "html > body > div#main > ul");
+    /// assert_eq!(rewriter.current_depth(), 4);
+    /// ```
+    ///
+    /// [`Element::current_path`]: html_content/struct.Element.html#method.current_path
+    /// [`track_element_ids`]: struct.Settings.html#structfield.track_element_ids
+    #[inline]
+    pub fn current_path(&self) -> String {
+        self.stream.transform_controller().current_path()
+    }
+
+    /// Returns the number of currently open elements.
+    ///
+    /// Refer to [`current_path`] for the details on how the open elements are tracked.
+    ///
+    /// [`current_path`]: struct.HtmlRewriter.html#method.current_path
+    #[inline]
+    pub fn current_depth(&self) -> usize {
+        self.stream.transform_controller().current_depth()
+    }
+
+    /// Returns the maximum nesting depth of elements that the selector matching engine has
+    /// tracked so far.
+    #[cfg(feature = "basic_selectors")]
+    #[inline]
+    fn max_selector_matching_stack_depth(&self) -> usize {
+        self.stream
+            .transform_controller()
+            .max_selector_matching_stack_depth()
+    }
+
+    /// Finalizes the rewriting process.
+    ///
+    /// Should be called once the last chunk of the input is written.
+    ///
+    /// # Panics
+    ///  * If previous invocation of [`write`] returned a [`RewritingError`] (these errors
+    ///    are unrecovarable).
+    ///  * If called twice.
+    ///
+    /// [`RewritingError`]: errors/enum.RewritingError.html
+    /// [`write`]: struct.HtmlRewriter.html#method.write
+    #[inline]
+    pub fn end(&mut self) -> Result<(), RewritingError> {
+        if self.finished_early {
+            return Ok(());
+        }
+
+        assert!(!self.finished, "Stream was ended twice.");
+        self.finished = true;
+
+        guarded!(self, self.end_stream())
+    }
+}
+
+// NOTE: this opaque Debug implementation is required to make
+// `.unwrap()` and `.expect()` methods available on Result
+// returned by the `HtmlRewriterBuilder.build()` method.
+impl<O: OutputSink, H: HandlerTypes> Debug for HtmlRewriter<'_, O, H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HtmlRewriter")
     }
 }
 
@@ -277,12 +1467,112 @@ pub fn rewrite_str<'h, 's>(
     html: &str,
     settings: RewriteStrSettings<'h, 's>,
 ) -> Result<String, RewritingError> {
+    rewrite_str_with_settings(html, settings.into())
+}
+
+/// Rewrites given `html` string with the full set of the rewriter [`Settings`].
+///
+/// Unlike [`rewrite_str`], the function accepts all the settings of the [`HtmlRewriter`], e.g.
+/// the [`memory_settings`] or the [`output_filter`]. Neither the handlers nor the selectors of
+/// the `settings` need to outlive the call.
+///
+/// Fails with [`EncodingError::UnsupportedEncoding`] if the [`encoding`] of the `settings` is not
+/// UTF-8, since the input is a string, and with [`RewritingError::InvalidUtf8Output`] if the
+/// [`output_filter`] produces output that is not valid UTF-8.
+///
+/// # Example
+///
+/// ```
+/// use lol_html::{element, rewrite_str_with_settings, MemorySettings, Settings};
+///
+/// let hidden = String::from(".ad");
+///
+/// let output = rewrite_str_with_settings(
+///     r#"<p>Foo</p><div class="ad"></div>"#,
+///     Settings {
+///         element_content_handlers: vec![
+///             element!(hidden, |el| {
+///                 el.remove();
+///
+///                 Ok(())
+///             })
+///         ],
+///         memory_settings: MemorySettings {
+///             max_allowed_memory_usage: 64 * 1024,
+///             ..MemorySettings::default()
+///         },
+///         ..Settings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(output, "<p>Foo</p>");
+/// ```
+///
+/// [`Settings`]: struct.Settings.html
+/// [`rewrite_str`]: fn.rewrite_str.html
+/// [`HtmlRewriter`]: struct.HtmlRewriter.html
+/// [`memory_settings`]: struct.Settings.html#structfield.memory_settings
+/// [`output_filter`]: struct.Settings.html#structfield.output_filter
+/// [`encoding`]: struct.Settings.html#structfield.encoding
+/// [`EncodingError::UnsupportedEncoding`]: errors/enum.EncodingError.html#variant.UnsupportedEncoding
+/// [`RewritingError::InvalidUtf8Output`]: errors/enum.RewritingError.html#variant.InvalidUtf8Output
+pub fn rewrite_str_with_settings<'h, 's>(
+    html: &str,
+    settings: Settings<'h, 's>,
+) -> Result<String, RewritingError> {
+    let encoding = try_encoding_from_str(settings.encoding)
+        .map_err(|e| RewritingError::Settings(SettingsError::Encoding(e)))?;
+
+    if encoding != UTF_8 {
+        return Err(RewritingError::Settings(SettingsError::Encoding(
+            EncodingError::UnsupportedEncoding,
+        )));
+    }
+
+    let mut output = Vec::with_capacity(html.len());
+    let mut rewriter = HtmlRewriter::try_new(settings, |c: &[u8]| output.extend_from_slice(c))
+        .map_err(RewritingError::Settings)?;
+
+    rewriter.write(html.as_bytes())?;
+    rewriter.end()?;
+
+    // NOTE: the output is valid UTF-8, unless it has been broken by the output filter.
+    String::from_utf8(output).map_err(|e| RewritingError::InvalidUtf8Output(e.utf8_error()))
+}
+
+/// Brings the `html` string to the canonical form, e.g. to compare the rewritten documents in
+/// snapshot tests.
+///
+/// Refer to [`canonical_output`] documentation for more information.
+///
+/// # Example
+///
+/// ```
+/// use lol_html::canonicalize;
+///
+/// assert_eq!(
+///     canonicalize("<DIV id=foo class='bar'>\n  Hello,   <B>world</B>!\n</DIV>").unwrap(),
+///     r#"<div class="bar" id="foo"> Hello, <b>world</b>! </div>"#
+/// );
+///
+/// assert_eq!(
+///     canonicalize("<p  hidden CLASS=\"a\"\n\tid=b></p >").unwrap(),
+///     canonicalize("<P class=a id=\"b\" hidden=\"\"></P>").unwrap()
+/// );
+/// ```
+///
+/// [`canonical_output`]: struct.Settings.html#structfield.canonical_output
+pub fn canonicalize(html: &str) -> Result<String, RewritingError> {
     let mut output = vec![];
 
     // NOTE: never panics because encoding is always "utf-8".
-    let mut rewriter = HtmlRewriter::try_new(settings.into(), |c: &[u8]| {
-        output.extend_from_slice(c);
-    })
+    let mut rewriter = HtmlRewriter::try_new(
+        Settings {
+            canonical_output: true,
+            ..Settings::default()
+        },
+        |c: &[u8]| output.extend_from_slice(c),
+    )
     .unwrap();
 
     rewriter.write(html.as_bytes())?;
@@ -292,13 +1582,95 @@ pub fn rewrite_str<'h, 's>(
     Ok(String::from_utf8(output).unwrap())
 }
 
+impl MemorySettings {
+    /// Derives the preallocation settings from the `sample` document.
+    ///
+    /// The sample is parsed and the buffer sizes that were required to process it are used
+    /// as the preallocation settings. If the sample is representative of the documents that
+    /// are going to be rewritten, rewriters constructed with the resulting settings should
+    /// not reallocate their internal buffers. [`max_allowed_memory_usage`] and
+    /// [`soft_memory_usage_limit`] are set to their default values.
+    ///
+    /// Requires the `basic_selectors` feature, which is enabled by default as a part of
+    /// the `selector_matching` feature.
+    ///
+    /// # Example
+    /// ```
+    /// use lol_html::MemorySettings;
+    ///
+    /// let settings = MemorySettings::from_sample(
+    ///     r#"<html><body><div><p class="foo" id="bar">Hello</p></div></body></html>"#
+    /// );
+    ///
+    /// assert_eq!(settings.preallocated_attribute_buffer_capacity, 2);
+    /// assert_eq!(settings.preallocated_selector_matching_stack_depth, 4);
+    /// ```
+    ///
+    /// [`max_allowed_memory_usage`]: #structfield.max_allowed_memory_usage
+    /// [`soft_memory_usage_limit`]: #structfield.soft_memory_usage_limit
+    #[cfg(feature = "basic_selectors")]
+    pub fn from_sample(sample: &str) -> Self {
+        let mut max_attr_count = 0;
+        let mut max_start_tag_len = 0;
+        let mut max_output_chunk_len = 0;
+
+        let max_stack_depth = {
+            // NOTE: the rewriter is not strict, doesn't have a memory limit
+            // and its handler never fails, so it's safe to unwrap here.
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: vec![element!("*", |el| {
+                        max_attr_count = max_attr_count.max(el.attributes().len());
+
+                        if let Some(raw) = el.start_tag_raw_bytes() {
+                            max_start_tag_len = max_start_tag_len.max(raw.len());
+                        }
+
+                        Ok(())
+                    })],
+                    strict: false,
+                    ..Settings::default()
+                },
+                |c: &[u8]| max_output_chunk_len = max_output_chunk_len.max(c.len()),
+            )
+            .unwrap();
+
+            rewriter.write(sample.as_bytes()).unwrap();
+
+            let max_stack_depth = rewriter.max_selector_matching_stack_depth();
+
+            rewriter.end().unwrap();
+
+            max_stack_depth
+        };
+
+        // NOTE: `next_power_of_two` returns 1 for 0, while nothing needs to be preallocated
+        // if the sample didn't need the buffer at all.
+        let buffer_size = |len: usize| if len == 0 { 0 } else { len.next_power_of_two() };
+
+        MemorySettings {
+            preallocated_parsing_buffer_size: buffer_size(max_start_tag_len),
+            preallocated_attribute_buffer_capacity: max_attr_count,
+            preallocated_selector_matching_stack_depth: max_stack_depth,
+            preallocated_output_buffer_size: buffer_size(max_output_chunk_len),
+            ..MemorySettings::default()
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::html_content::ContentType;
+    use crate::html_content::{ContentType, SourceLocation};
+    use crate::rewritable_units::ContentModelError;
+    use crate::selectors_vm::Selector;
     use crate::test_utils::{Output, ASCII_COMPATIBLE_ENCODINGS};
     use std::cell::RefCell;
     use std::rc::Rc;
+    use std::time::{Duration, Instant};
+
+    #[cfg(feature = "selector_matching")]
+    use crate::html_content::{Element, MatchInfo};
 
     fn write_chunks<O: OutputSink>(
         rewriter: &mut HtmlRewriter<O>,
@@ -338,50 +1710,166 @@ mod tests {
     }
 
     #[test]
-    fn unknown_encoding() {
-        let err = HtmlRewriter::try_new(
+    fn rewrite_html_str_with_settings() {
+        let selector = String::from("div");
+
+        let res = rewrite_str_with_settings(
+            "<div>Привет</div><p>мир</p>",
             Settings {
-                encoding: "hey-yo",
+                element_content_handlers: vec![element!(selector, |el| {
+                    el.remove_and_keep_content();
+                    Ok(())
+                })],
+                output_filter: Some(Box::new(|c: &[u8], output: &mut Vec<u8>| {
+                    output.extend(c.iter().map(u8::to_ascii_uppercase));
+                })),
                 ..Settings::default()
             },
-            |_: &[u8]| {},
         )
-        .unwrap_err();
+        .unwrap();
 
-        assert_eq!(err, EncodingError::UnknownEncoding);
+        assert_eq!(res, "Привет<P>мир</P>");
     }
 
     #[test]
-    fn non_ascii_compatible_encoding() {
-        let err = HtmlRewriter::try_new(
+    fn rewrite_html_str_with_non_utf8_encoding() {
+        let err = rewrite_str_with_settings(
+            "<div>Привет</div>",
             Settings {
-                encoding: "utf-16be",
+                encoding: "windows-1251",
                 ..Settings::default()
             },
-            |_: &[u8]| {},
         )
         .unwrap_err();
 
-        assert_eq!(err, EncodingError::NonAsciiCompatibleEncoding);
+        assert!(matches!(
+            err,
+            RewritingError::Settings(SettingsError::Encoding(EncodingError::UnsupportedEncoding))
+        ));
     }
 
     #[test]
-    fn doctype_info() {
-        for enc in ASCII_COMPATIBLE_ENCODINGS.iter() {
-            let mut doctypes = Vec::default();
+    fn send_handler_types() {
+        use std::sync::{Arc, Mutex};
+        use std::thread;
 
-            {
-                let mut rewriter = HtmlRewriter::try_new(
-                    Settings {
-                        document_content_handlers: vec![doctype!(|d| {
-                            doctypes.push((d.name(), d.public_id(), d.system_id()));
-                            Ok(())
-                        })],
-                        encoding: enc.name(),
-                        ..Settings::default()
-                    },
-                    |_: &[u8]| {},
-                )
+        let output = Arc::new(Mutex::new(vec![]));
+        let sink_output = Arc::clone(&output);
+        let inner_htmls = Arc::new(Mutex::new(vec![]));
+        let handler_inner_htmls = Arc::clone(&inner_htmls);
+
+        let mut rewriter = HtmlRewriter::try_new(
+            Settings {
+                element_content_handlers: vec![element!("div", move |el| {
+                    let inner_htmls = Arc::clone(&handler_inner_htmls);
+
+                    el.on_end_tag(|end_tag| {
+                        end_tag.after("<!--div-->", ContentType::Html);
+
+                        Ok(())
+                    });
+
+                    el.on_inner_html(move |inner| {
+                        inner_htmls.lock().unwrap().push(inner.as_str().to_owned());
+
+                        Ok(())
+                    });
+
+                    Ok(())
+                })],
+                comment_content_handlers: vec![element!("b", |el| {
+                    el.remove();
+
+                    Ok(())
+                })],
+                ..Settings::new_send()
+            },
+            move |c: &[u8]| sink_output.lock().unwrap().extend_from_slice(c),
+        )
+        .unwrap();
+
+        rewriter.write(b"<div><p>Foo").unwrap();
+
+        // NOTE: the handlers of the end tag of the div are held by the rewriter.
+        thread::spawn(move || {
+            rewriter.write(b"<!--<b>Bar</b>--></p></div>").unwrap();
+            rewriter.end().unwrap();
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output.lock().unwrap().clone()).unwrap(),
+            "<div><p>Foo<!----></p></div><!--div-->"
+        );
+
+        assert_eq!(*inner_htmls.lock().unwrap(), ["<p>Foo<!----></p>"]);
+    }
+
+    #[test]
+    fn rewrite_html_str_with_invalid_utf8_output() {
+        let err = rewrite_str_with_settings(
+            "<div>Привет</div>",
+            Settings {
+                output_filter: Some(Box::new(|c: &[u8], output: &mut Vec<u8>| {
+                    output.extend(c.iter().filter(|&&b| b != 0xD0));
+                })),
+                ..Settings::default()
+            },
+        )
+        .unwrap_err();
+
+        assert!(matches!(err, RewritingError::InvalidUtf8Output(_)));
+    }
+
+    #[test]
+    fn unknown_encoding() {
+        let err = HtmlRewriter::try_new(
+            Settings {
+                encoding: "hey-yo",
+                ..Settings::default()
+            },
+            |_: &[u8]| {},
+        )
+        .unwrap_err();
+
+        assert_eq!(err, SettingsError::Encoding(EncodingError::UnknownEncoding));
+    }
+
+    #[test]
+    fn non_ascii_compatible_encoding() {
+        let err = HtmlRewriter::try_new(
+            Settings {
+                encoding: "utf-16be",
+                ..Settings::default()
+            },
+            |_: &[u8]| {},
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            err,
+            SettingsError::Encoding(EncodingError::NonAsciiCompatibleEncoding)
+        );
+    }
+
+    #[test]
+    fn doctype_info() {
+        for enc in ASCII_COMPATIBLE_ENCODINGS.iter() {
+            let mut doctypes = Vec::default();
+
+            {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        document_content_handlers: vec![doctype!(|d| {
+                            doctypes.push((d.name(), d.public_id(), d.system_id()));
+                            Ok(())
+                        })],
+                        encoding: enc.name(),
+                        ..Settings::default()
+                    },
+                    |_: &[u8]| {},
+                )
                 .unwrap();
 
                 write_chunks(
@@ -564,45 +2052,2867 @@ mod tests {
         assert_eq!(*handlers_executed.borrow(), vec![0, 1, 2, 3, 4]);
     }
 
-    mod fatal_errors {
-        use super::*;
-        use crate::errors::MemoryLimitExceededError;
+    #[test]
+    fn match_limit() {
+        let res = rewrite_str(
+            "<div><p>1</p><p>2</p></div><p>3</p><p>4</p>",
+            RewriteStrSettings {
+                element_content_handlers: vec![
+                    element_limited!("p", 3, |el| {
+                        el.set_attribute("matched", "").unwrap();
+                        Ok(())
+                    }),
+                    (
+                        &"div p".parse().unwrap(),
+                        ElementContentHandlers::default()
+                            .text(|t| {
+                                if !t.last_in_text_node() {
+                                    t.replace("x", ContentType::Text);
+                                }
 
-        fn create_rewriter<O: OutputSink>(
-            max_allowed_memory_usage: usize,
-            output_sink: O,
-        ) -> HtmlRewriter<'static, O> {
-            HtmlRewriter::try_new(
+                                Ok(())
+                            })
+                            .match_limit(1),
+                    ),
+                ],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            res,
+            concat!(
+                r#"<div><p matched="">x</p><p matched="">2</p></div>"#,
+                r#"<p matched="">3</p><p>4</p>"#
+            )
+        );
+    }
+
+    #[test]
+    fn attribute_tracking_optimization() {
+        let mut text = String::new();
+
+        {
+            let mut rewriter = HtmlRewriter::try_new(
                 Settings {
-                    element_content_handlers: vec![element!("*", |_| Ok(()))],
-                    memory_settings: MemorySettings {
-                        max_allowed_memory_usage,
-                        preallocated_parsing_buffer_size: 0,
-                    },
+                    element_content_handlers: vec![text!("style", |t| {
+                        text.push_str(t.as_str());
+
+                        Ok(())
+                    })],
                     ..Settings::default()
                 },
-                output_sink,
+                |_: &[u8]| {},
             )
-            .unwrap()
+            .unwrap();
+
+            assert_eq!(
+                rewriter.engaged_optimizations(),
+                EngagedOptimizations {
+                    attribute_tracking_disabled: true,
+                    ..EngagedOptimizations::default()
+                }
+            );
+
+            // NOTE: attributes are still tracked in the foreign content, since
+            // <font> with `color` attribute breaks out of it.
+            rewriter
+                .write(br#"<p class=foo><svg><font color=red><style>a<b</style>"#)
+                .unwrap();
+
+            rewriter.end().unwrap();
         }
 
-        #[test]
-        fn buffer_capacity_limit() {
-            const MAX: usize = 100;
+        assert_eq!(text, "a<b");
 
-            let mut rewriter = create_rewriter(MAX, |_: &[u8]| {});
+        let rewriter = HtmlRewriter::try_new(
+            Settings {
+                element_content_handlers: vec![element!("p", |_| Ok(()))],
+                ..Settings::default()
+            },
+            |_: &[u8]| {},
+        )
+        .unwrap();
 
-            // Use two chunks for the stream to force the usage of the buffer and
-            // make sure to overflow it.
-            let chunk_1 = format!("<img alt=\"{}", "l".repeat(MAX / 2));
-            let chunk_2 = format!("{}\" />", "r".repeat(MAX / 2));
+        assert!(!rewriter.engaged_optimizations().attribute_tracking_disabled);
+    }
 
-            rewriter.write(chunk_1.as_bytes()).unwrap();
+    #[test]
+    fn head_only_matching_optimization() {
+        let mut output = vec![];
 
-            let write_err = rewriter.write(chunk_2.as_bytes()).unwrap_err();
+        {
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: vec![
+                        element!("head > meta", |el| {
+                            el.set_attribute("matched", "")?;
 
-            match write_err {
-                RewritingError::MemoryLimitExceeded(e) => assert_eq!(e, MemoryLimitExceededError),
+                            Ok(())
+                        }),
+                        element!("head", |_| Ok(())),
+                    ],
+                    ..Settings::default()
+                },
+                |c: &[u8]| output.extend_from_slice(c),
+            )
+            .unwrap();
+
+            assert!(rewriter.engaged_optimizations().head_only_matching);
+
+            rewriter
+                .write(b"<head><meta charset=utf-8></head>")
+                .unwrap();
+
+            assert!(rewriter.engaged_optimizations().matching_stopped);
+
+            // NOTE: the parser ignores <head> after the end of the actual <head>.
+            rewriter.write(b"<body><head><meta charset=utf-8>").unwrap();
+            rewriter.end().unwrap();
+        }
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            concat!(
+                r#"<head><meta charset=utf-8 matched=""></head>"#,
+                r#"<body><head><meta charset=utf-8>"#
+            )
+        );
+
+        let rewriter = HtmlRewriter::try_new(
+            Settings {
+                element_content_handlers: vec![
+                    element!("head > meta", |_| Ok(())),
+                    element!("body", |_| Ok(())),
+                ],
+                ..Settings::default()
+            },
+            |_: &[u8]| {},
+        )
+        .unwrap();
+
+        assert!(!rewriter.engaged_optimizations().head_only_matching);
+    }
+
+    fn rewrite_with_cutoff(chunks: &[&str], matching_cutoff: MatchingCutoff) -> (String, bool) {
+        let mut output = vec![];
+        let passthrough;
+
+        {
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: vec![
+                        element!("title, p", |el| {
+                            el.set_attribute("matched", "")?;
+
+                            Ok(())
+                        }),
+                        element!("head", |el| {
+                            el.append("<meta>", ContentType::Html);
+
+                            Ok(())
+                        }),
+                    ],
+                    document_content_handlers: vec![end!(|end| {
+                        end.append("<!-- end -->", ContentType::Html);
+
+                        Ok(())
+                    })],
+                    matching_cutoff: Some(matching_cutoff),
+                    ..Settings::default()
+                },
+                |c: &[u8]| output.extend_from_slice(c),
+            )
+            .unwrap();
+
+            for chunk in chunks {
+                rewriter.write(chunk.as_bytes()).unwrap();
+            }
+
+            passthrough = rewriter.engaged_optimizations().passthrough;
+
+            rewriter.end().unwrap();
+        }
+
+        (String::from_utf8(output).unwrap(), passthrough)
+    }
+
+    #[test]
+    fn matching_cutoff_at_head_end() {
+        assert_eq!(
+            rewrite_with_cutoff(
+                &["<head><title>1</title></he", "ad><p>2</p>", "<p>3</p>"],
+                MatchingCutoff::HeadEnd
+            ),
+            (
+                concat!(
+                    r#"<head><title matched="">1</title><meta></head>"#,
+                    "<p>2</p><p>3</p><!-- end -->"
+                )
+                .into(),
+                true
+            )
+        );
+
+        assert_eq!(
+            rewrite_with_cutoff(
+                &["<title>1</title><body><p>2</p>", "<p>3</p>"],
+                MatchingCutoff::HeadEnd
+            ),
+            (
+                r#"<title matched="">1</title><body><p>2</p><p>3</p><!-- end -->"#.into(),
+                true
+            )
+        );
+
+        assert_eq!(
+            rewrite_with_cutoff(&["<title>1</title><p>2</p>"], MatchingCutoff::HeadEnd),
+            (
+                r#"<title matched="">1</title><p matched="">2</p><!-- end -->"#.into(),
+                false
+            )
+        );
+    }
+
+    #[test]
+    fn matching_cutoff_after_bytes() {
+        assert_eq!(
+            rewrite_with_cutoff(&["<p>1</p><p", ">2</p>"], MatchingCutoff::Bytes(10)),
+            (r#"<p matched="">1</p><p>2</p><!-- end -->"#.into(), true)
+        );
+
+        assert_eq!(
+            rewrite_with_cutoff(&["<p>1</p>", "<p>2</p>"], MatchingCutoff::Bytes(17)),
+            (
+                r#"<p matched="">1</p><p matched="">2</p><!-- end -->"#.into(),
+                false
+            )
+        );
+    }
+
+    #[test]
+    fn head_only_mode() {
+        let mut output = vec![];
+        let mut matched_body_elements = 0;
+
+        {
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: vec![
+                        element!("head", |el| {
+                            el.prepend("<meta>", ContentType::Html);
+
+                            Ok(())
+                        }),
+                        element!("body *", |_| {
+                            matched_body_elements += 1;
+
+                            Ok(())
+                        }),
+                    ],
+                    head_only: true,
+                    ..Settings::default()
+                },
+                |c: &[u8]| output.extend_from_slice(c),
+            )
+            .unwrap();
+
+            rewriter.write(b"<html><head><title>1</title>").unwrap();
+            rewriter.write(b"</head><body><div>").unwrap();
+
+            assert!(rewriter.engaged_optimizations().passthrough);
+
+            rewriter.write(b"<p>2</p></div>").unwrap();
+            rewriter.end().unwrap();
+        }
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "<html><head><meta><title>1</title></head><body><div><p>2</p></div>"
+        );
+
+        assert_eq!(matched_body_elements, 0);
+    }
+
+    #[test]
+    fn analysis_limit() {
+        let mut output = vec![];
+        let mut titles = vec![];
+
+        {
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: vec![text!("title", |t| {
+                        if !t.last_in_text_node() {
+                            titles.push(t.as_str().to_string());
+                        }
+
+                        Ok(())
+                    })],
+                    document_content_handlers: vec![end!(|end| {
+                        end.append("<!-- end -->", ContentType::Html);
+
+                        Ok(())
+                    })],
+                    analysis_limit: Some(20),
+                    ..Settings::default()
+                },
+                |c: &[u8]| output.extend_from_slice(c),
+            )
+            .unwrap();
+
+            assert_eq!(rewriter.analyze(b"<title>1</title>").unwrap(), None);
+
+            assert_eq!(
+                rewriter.analyze(b"<p>2</p><title>3</title>").unwrap(),
+                Some(Finished::Early { consumed: 4 })
+            );
+
+            assert_eq!(
+                rewriter.analyze(b"<title>4</title>").unwrap(),
+                Some(Finished::Early { consumed: 0 })
+            );
+
+            rewriter.write(b"<title>5</title>").unwrap();
+            rewriter.end().unwrap();
+        }
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "<title>1</title><p>2<!-- end -->"
+        );
+
+        assert_eq!(titles, vec!["1"]);
+    }
+
+    #[test]
+    fn multiple_documents() {
+        let mut output = vec![];
+        let mut texts = vec![];
+        let mut end_count = 0;
+
+        {
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: vec![
+                        element_limited!("p", 1, |el| {
+                            el.set_attribute("first", "")?;
+
+                            Ok(())
+                        }),
+                        element!("div", |el| {
+                            el.append("!", ContentType::Text);
+
+                            Ok(())
+                        }),
+                        text!("div span", |t| {
+                            if !t.last_in_text_node() {
+                                texts.push(t.as_str().to_string());
+                            }
+
+                            Ok(())
+                        }),
+                    ],
+                    document_content_handlers: vec![end!(|_| {
+                        end_count += 1;
+
+                        Ok(())
+                    })],
+                    ..Settings::default()
+                },
+                |c: &[u8]| output.extend_from_slice(c),
+            )
+            .unwrap();
+
+            rewriter
+                .write(b"<p>1</p><p>2</p><div><span>3</span> 4<di")
+                .unwrap();
+            rewriter.start_new_document().unwrap();
+            rewriter
+                .write(b"<p>5</p><span>6</span><div>7</div>")
+                .unwrap();
+            rewriter.end().unwrap();
+        }
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            concat!(
+                r#"<p first="">1</p><p>2</p><div><span>3</span> 4<di"#,
+                r#"<p first="">5</p><span>6</span><div>7!</div>"#
+            )
+        );
+
+        assert_eq!(texts, vec!["3"]);
+        assert_eq!(end_count, 1);
+    }
+
+    #[test]
+    fn prefix_cache() {
+        let rewrite = |prefix: &[u8], body: &[u8], cache: Option<&mut PrefixCache>| {
+            let mut output = vec![];
+
+            {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        element_content_handlers: vec![
+                            element!("head > meta", |el| {
+                                el.set_attribute("charset", "utf-8")?;
+
+                                Ok(())
+                            }),
+                            text!("title", |t| {
+                                if t.as_str() == "Foo" {
+                                    t.replace("Bar", ContentType::Text);
+                                }
+
+                                Ok(())
+                            }),
+                            element!("body p", |el| {
+                                el.set_inner_content("42", ContentType::Text);
+
+                                Ok(())
+                            }),
+                        ],
+                        ..Settings::default()
+                    },
+                    |c: &[u8]| output.extend_from_slice(c),
+                )
+                .unwrap();
+
+                match cache {
+                    Some(cache) => rewriter.write_prefix(prefix, cache).unwrap(),
+                    None => rewriter.write(prefix).unwrap(),
+                }
+
+                rewriter.write(body).unwrap();
+                rewriter.end().unwrap();
+            }
+
+            String::from_utf8(output).unwrap()
+        };
+
+        let prefix = b"<!DOCTYPE html><html><head><meta><title>Foo</title></head>";
+        let bodies: &[&[u8]] = &[b"<body><p>1</p></body>", b"<body><div><p>2</p></div>"];
+        let mut cache = PrefixCache::new();
+
+        for body in bodies {
+            assert_eq!(
+                rewrite(prefix, body, Some(&mut cache)),
+                rewrite(prefix, body, None)
+            );
+        }
+
+        assert_eq!(cache.len(), 1);
+
+        // NOTE: the prefix that ends in the middle of the script
+        // content can't be memoized.
+        let prefix = b"<head><script>var a = '";
+
+        assert_eq!(
+            rewrite(prefix, b"<p>';</script>", Some(&mut cache)),
+            rewrite(prefix, b"<p>';</script>", None)
+        );
+
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn declarative_shadow_roots() {
+        let rewrite = |match_declarative_shadow_roots| {
+            let mut output = vec![];
+
+            {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        element_content_handlers: vec![element!("p, template", |el| {
+                            el.set_attribute("matched", "")?;
+
+                            Ok(())
+                        })],
+                        match_declarative_shadow_roots,
+                        ..Settings::default()
+                    },
+                    |c: &[u8]| output.extend_from_slice(c),
+                )
+                .unwrap();
+
+                rewriter
+                    .write(b"<template shadowrootmode=open><p>1</p><template>")
+                    .unwrap();
+
+                rewriter
+                    .write(b"<p>2</p></template><p>3</p></template><p>4</p>")
+                    .unwrap();
+
+                rewriter.end().unwrap();
+            }
+
+            String::from_utf8(output).unwrap()
+        };
+
+        assert_eq!(
+            rewrite(true),
+            concat!(
+                r#"<template shadowrootmode=open matched=""><p matched="">1</p>"#,
+                r#"<template matched=""><p matched="">2</p></template><p matched="">3</p>"#,
+                r#"</template><p matched="">4</p>"#
+            )
+        );
+
+        assert_eq!(
+            rewrite(false),
+            concat!(
+                r#"<template shadowrootmode=open matched=""><p>1</p>"#,
+                r#"<template><p>2</p></template><p>3</p>"#,
+                r#"</template><p matched="">4</p>"#
+            )
+        );
+    }
+
+    #[test]
+    fn implicitly_closed_elements() {
+        let res = rewrite_str(
+            "<ul><li>1<li>2</ul><p>3<div>4</div><dl><dt>5<dd>6</dl><p>7",
+            RewriteStrSettings {
+                element_content_handlers: vec![element!("li, p, dt, dd", |el| {
+                    el.append("!", ContentType::Text);
+
+                    Ok(())
+                })],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            res,
+            "<ul><li>1!<li>2!</ul><p>3!<div>4</div><dl><dt>5!<dd>6!</dl><p>7!"
+        );
+
+        let res = rewrite_str(
+            "<div><p>1<p>2</div>",
+            RewriteStrSettings {
+                element_content_handlers: vec![element!("p", |el| {
+                    el.set_tag_name("span")?;
+
+                    Ok(())
+                })],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(res, "<div><span>1</span><span>2</span></div>");
+
+        let res = rewrite_str(
+            "<head><title>1</title><body>2",
+            RewriteStrSettings {
+                element_content_handlers: vec![element!("head", |el| {
+                    el.append("!", ContentType::Text);
+
+                    Ok(())
+                })],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(res, "<head><title>1</title>!<body>2");
+    }
+
+    #[cfg(feature = "selector_matching")]
+    #[test]
+    fn positional_pseudo_classes() {
+        let res = rewrite_str(
+            "<ul><li>1</li><p></p><li>2</li><li>3</li></ul><ul><li>4</li></ul>",
+            RewriteStrSettings {
+                element_content_handlers: vec![
+                    element!("li:nth-child(3)", |el| {
+                        el.append("[child]", ContentType::Text);
+
+                        Ok(())
+                    }),
+                    element!("li:nth-of-type(3), li:nth-of-type(-n+1)", |el| {
+                        el.append("[type]", ContentType::Text);
+
+                        Ok(())
+                    }),
+                ],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            res,
+            concat!(
+                "<ul><li>1[type]</li><p></p><li>2[child]</li><li>3[type]</li></ul>",
+                "<ul><li>4[type]</li></ul>"
+            )
+        );
+    }
+
+    #[cfg(feature = "selector_matching")]
+    #[test]
+    fn complex_negation() {
+        let res = rewrite_str(
+            concat!(
+                "<nav><a href=1>1</a><p><a href=2>2</a></p></nav>",
+                "<article class=post><p><a href=3>3</a></p></article>",
+                "<a href=4>4</a><img><img alt>"
+            ),
+            RewriteStrSettings {
+                element_content_handlers: vec![
+                    element!("a:not(nav a, .post a)", |el| {
+                        el.set_attribute("target", "_blank")?;
+
+                        Ok(())
+                    }),
+                    element!("a:not(nav > a)", |el| {
+                        el.append("!", ContentType::Text);
+
+                        Ok(())
+                    }),
+                    element!("img:not([alt] ~ img):not(a + img)", |el| {
+                        el.set_attribute("first", "")?;
+
+                        Ok(())
+                    }),
+                ],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            res,
+            concat!(
+                "<nav><a href=1>1</a><p><a href=2>2!</a></p></nav>",
+                "<article class=post><p><a href=3>3!</a></p></article>",
+                r#"<a href=4 target="_blank">4!</a><img><img alt first="">"#
+            )
+        );
+    }
+
+    #[cfg(feature = "selector_matching")]
+    #[test]
+    fn is_pseudo_class() {
+        let res = rewrite_str(
+            concat!(
+                "<article><h2>1</h2></article><section><h2>2</h2></section>",
+                "<aside><h2>3</h2></aside><ul><li><p>4</p></li></ul><ol><li><p>5</p></li></ol>"
+            ),
+            RewriteStrSettings {
+                element_content_handlers: vec![
+                    element!(":is(article, section) h2", |el| {
+                        el.set_attribute("class", "heading")?;
+
+                        Ok(())
+                    }),
+                    element!(":where(ul li) > p", |el| {
+                        el.append("!", ContentType::Text);
+
+                        Ok(())
+                    }),
+                    element!("li :is(ul > li p, aside)", |el| {
+                        el.set_attribute("nested", "")?;
+
+                        Ok(())
+                    }),
+                ],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            res,
+            concat!(
+                r#"<article><h2 class="heading">1</h2></article>"#,
+                r#"<section><h2 class="heading">2</h2></section><aside><h2>3</h2></aside>"#,
+                r#"<ul><li><p nested="">4!</p></li></ul><ol><li><p>5</p></li></ol>"#
+            )
+        );
+    }
+
+    #[cfg(feature = "selector_matching")]
+    #[test]
+    fn namespace_prefixes() {
+        let res = rewrite_str(
+            "<title>1</title><svg><title>2</title><path/></svg><math><mi>x</mi></math>",
+            RewriteStrSettings {
+                element_content_handlers: vec![
+                    element!("svg|title", |el| {
+                        el.set_attribute("class", "svg")?;
+
+                        Ok(())
+                    }),
+                    element!("html|title", |el| {
+                        el.set_attribute("class", "html")?;
+
+                        Ok(())
+                    }),
+                    element!("title:not(svg|title)", |el| {
+                        el.set_attribute("plain", "")?;
+
+                        Ok(())
+                    }),
+                    element!("math|*", |el| {
+                        el.set_attribute("m", "")?;
+
+                        Ok(())
+                    }),
+                ],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            res,
+            concat!(
+                r#"<title class="html" plain="">1</title><svg><title class="svg">2</title>"#,
+                r#"<path/></svg><math m=""><mi m="">x</mi></math>"#
+            )
+        );
+    }
+
+    #[cfg(feature = "selector_matching")]
+    #[test]
+    fn namespaced_attributes() {
+        let res = rewrite_str(
+            r##"<svg><use xlink:href="#a"></use><a href="/b"></a></svg>"##,
+            RewriteStrSettings {
+                element_content_handlers: vec![
+                    element!("[xlink|href]", |el| {
+                        el.set_attribute("xlink", "")?;
+
+                        Ok(())
+                    }),
+                    element!(r#"[*|href^="/"]"#, |el| {
+                        el.set_attribute("any", "")?;
+
+                        Ok(())
+                    }),
+                    element!("[svg|href]", |el| {
+                        el.set_attribute("never", "")?;
+
+                        Ok(())
+                    }),
+                ],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            res,
+            r##"<svg><use xlink:href="#a" xlink=""></use><a href="/b" any=""></a></svg>"##
+        );
+    }
+
+    #[cfg(feature = "selector_matching")]
+    #[test]
+    fn custom_pseudo_classes() {
+        let builder = Selector::with_custom_pseudo("visible", |el| !el.has_attribute("hidden"))
+            .with_custom_pseudo("external", |el| {
+                el.get_attribute("HREF")
+                    .is_some_and(|href| href.starts_with("https://"))
+            });
+
+        let visible = builder.parse("p:visible").unwrap();
+        let hidden = builder.parse("p:not(:visible)").unwrap();
+        let external = builder.parse("p:visible > a:EXTERNAL").unwrap();
+
+        let res = rewrite_str(
+            concat!(
+                r#"<p><a href="https://example.com">1</a></p><p hidden>2</p>"#,
+                r#"<p><a href="/foo">3</a></p>"#
+            ),
+            RewriteStrSettings {
+                element_content_handlers: vec![
+                    (
+                        &visible,
+                        ElementContentHandlers::default().element(|el| {
+                            el.set_attribute("class", "visible")?;
+
+                            Ok(())
+                        }),
+                    ),
+                    (
+                        &hidden,
+                        ElementContentHandlers::default().element(|el| {
+                            el.remove();
+
+                            Ok(())
+                        }),
+                    ),
+                    (
+                        &external,
+                        ElementContentHandlers::default().element(|el| {
+                            el.set_attribute("rel", "noopener")?;
+
+                            Ok(())
+                        }),
+                    ),
+                ],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            res,
+            concat!(
+                r#"<p class="visible"><a href="https://example.com" rel="noopener">1</a></p>"#,
+                r#"<p class="visible"><a href="/foo">3</a></p>"#
+            )
+        );
+
+        for selector in &[
+            ":is(:visible)",
+            "div :not(p :visible)",
+            ":has(:visible)",
+            ":hidden",
+        ] {
+            assert!(builder.parse(selector).is_err(), "{}", selector);
+        }
+    }
+
+    #[cfg(feature = "selector_matching")]
+    #[test]
+    fn has_pseudo_class() {
+        let rewrite = |chunk_size: usize| {
+            let mut output = vec![];
+
+            {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        element_content_handlers: vec![
+                            element!("div:has(> img.hero)", |el| {
+                                el.set_attribute("class", "banner")?;
+
+                                Ok(())
+                            }),
+                            element!("section:has(img.hero)", |el| {
+                                el.append("!", ContentType::Text);
+
+                                Ok(())
+                            }),
+                            element!("div:not(:has(img))", |el| {
+                                el.set_attribute("empty", "")?;
+
+                                Ok(())
+                            }),
+                        ],
+                        buffer_has_candidates: true,
+                        ..Settings::default()
+                    },
+                    |c: &[u8]| output.extend_from_slice(c),
+                )
+                .unwrap();
+
+                let html = concat!(
+                    "<div><img class=hero></div>",
+                    "<div><p><img class=hero></p></div>",
+                    "<section><div><img class=hero></div></section>",
+                    "<div><img></div>",
+                    "<div><p></p></div>"
+                );
+
+                for chunk in html.as_bytes().chunks(chunk_size) {
+                    rewriter.write(chunk).unwrap();
+                }
+
+                rewriter.end().unwrap();
+            }
+
+            String::from_utf8(output).unwrap()
+        };
+
+        for &chunk_size in &[1, 7, 1024] {
+            assert_eq!(
+                rewrite(chunk_size),
+                concat!(
+                    r#"<div class="banner"><img class=hero></div>"#,
+                    "<div><p><img class=hero></p></div>",
+                    r#"<section><div class="banner"><img class=hero></div>!</section>"#,
+                    "<div><img></div>",
+                    r#"<div empty=""><p></p></div>"#
+                ),
+                "Chunk size: {}",
+                chunk_size
+            );
+        }
+    }
+
+    #[cfg(feature = "selector_matching")]
+    #[test]
+    fn has_pseudo_class_in_multiple_documents() {
+        let mut output = vec![];
+
+        {
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: vec![element!("p:has(b)", |el| {
+                        el.set_attribute("bold", "")?;
+
+                        Ok(())
+                    })],
+                    buffer_has_candidates: true,
+                    ..Settings::default()
+                },
+                |c: &[u8]| output.extend_from_slice(c),
+            )
+            .unwrap();
+
+            rewriter.write(b"<p><b>1</b></p><p>2").unwrap();
+            rewriter.start_new_document().unwrap();
+            rewriter.write(b"<p>3<p><b>4</b>").unwrap();
+            rewriter.end().unwrap();
+        }
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r#"<p bold=""><b>1</b></p><p>2<p>3<p bold=""><b>4</b>"#
+        );
+    }
+
+    #[cfg(feature = "selector_matching")]
+    #[test]
+    fn structural_pseudo_classes() {
+        let rewrite = |chunk_size: usize| {
+            let mut output = vec![];
+
+            {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        element_content_handlers: vec![
+                            element!(":root", |el| {
+                                el.set_attribute("root", "")?;
+
+                                Ok(())
+                            }),
+                            element!("li:last-child", |el| {
+                                el.set_attribute("last", "")?;
+
+                                Ok(())
+                            }),
+                            element!("li:only-child", |el| {
+                                el.set_attribute("only", "")?;
+
+                                Ok(())
+                            }),
+                            element!("p:last-of-type", |el| {
+                                el.set_attribute("last-of-type", "")?;
+
+                                Ok(())
+                            }),
+                            element!("span:only-of-type", |el| {
+                                el.set_attribute("single", "")?;
+
+                                Ok(())
+                            }),
+                        ],
+                        buffer_has_candidates: true,
+                        ..Settings::default()
+                    },
+                    |c: &[u8]| output.extend_from_slice(c),
+                )
+                .unwrap();
+
+                let html = concat!(
+                    "<html><body>",
+                    "<ul><li>1</li><li>2</li></ul>",
+                    "<ol><li>3</li></ol>",
+                    "<div><p>4</p><p>5</p><span>6</span></div>",
+                    "<div><span>7</span><span>8</span><img></div>",
+                    "</body></html>"
+                );
+
+                for chunk in html.as_bytes().chunks(chunk_size) {
+                    rewriter.write(chunk).unwrap();
+                }
+
+                rewriter.end().unwrap();
+            }
+
+            String::from_utf8(output).unwrap()
+        };
+
+        for &chunk_size in &[1, 7, 1024] {
+            assert_eq!(
+                rewrite(chunk_size),
+                concat!(
+                    r#"<html root=""><body>"#,
+                    r#"<ul><li>1</li><li last="">2</li></ul>"#,
+                    r#"<ol><li last="" only="">3</li></ol>"#,
+                    r#"<div><p>4</p><p last-of-type="">5</p><span single="">6</span></div>"#,
+                    "<div><span>7</span><span>8</span><img></div>",
+                    "</body></html>"
+                ),
+                "Chunk size: {}",
+                chunk_size
+            );
+        }
+    }
+
+    #[cfg(feature = "selector_matching")]
+    #[test]
+    fn lookahead_required() {
+        let try_new = |selector: &str, buffer_has_candidates: bool| {
+            HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: vec![element!(selector, |_| Ok(()))],
+                    buffer_has_candidates,
+                    ..Settings::default()
+                },
+                |_: &[u8]| {},
+            )
+            .map(|_| ())
+        };
+
+        for selector in &[
+            "div:has(img)",
+            "div:not(:has(> img))",
+            "p, li:last-child",
+            "li:last-of-type",
+            "ul > li:only-child",
+            "span:not(:only-of-type)",
+        ] {
+            assert_eq!(
+                try_new(selector, false),
+                Err(SettingsError::Selector(SelectorError::LookaheadRequired)),
+                "Selector: {}",
+                selector
+            );
+
+            assert_eq!(try_new(selector, true), Ok(()), "Selector: {}", selector);
+        }
+
+        for selector in &["li:first-child", "p:first-of-type", ":root"] {
+            assert_eq!(try_new(selector, false), Ok(()), "Selector: {}", selector);
+        }
+
+        let err = rewrite_str_with_settings(
+            "<p><b>1</b></p>",
+            Settings {
+                element_content_handlers: vec![element!("p:has(b)", |_| Ok(()))],
+                ..Settings::default()
+            },
+        )
+        .unwrap_err();
+
+        assert!(matches!(
+            err,
+            RewritingError::Settings(SettingsError::Selector(SelectorError::LookaheadRequired))
+        ));
+
+        // NOTE: rewrite_str always enables the lookahead, the whole input is in memory anyway.
+        let output = rewrite_str(
+            "<p><b>1</b></p><p>2</p>",
+            RewriteStrSettings {
+                element_content_handlers: vec![element!("p:has(b)", |el| {
+                    el.set_attribute("bold", "")?;
+
+                    Ok(())
+                })],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(output, r#"<p bold=""><b>1</b></p><p>2</p>"#);
+    }
+
+    #[test]
+    fn canonical_output() {
+        let mut output = vec![];
+        let mut changed_count = 0;
+
+        {
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: vec![
+                        element!("a", |el| {
+                            el.set_attribute("REL", "nofollow")?;
+
+                            Ok(())
+                        }),
+                        (
+                            &"*".parse().unwrap(),
+                            ElementContentHandlers::default().attribute_change("id", |_| {
+                                changed_count += 1;
+
+                                Ok(())
+                            }),
+                        ),
+                    ],
+                    canonical_output: true,
+                    ..Settings::default()
+                },
+                |c: &[u8]| output.extend_from_slice(c),
+            )
+            .unwrap();
+
+            let chunks = [
+                "<!DOCTYPE html><A id=1 HREF='/'  href=/b>  Foo",
+                "  \n Bar  </A  ><PRE>  a\n  b </pre>",
+                "<script> var a  =  1; </script><!--  c  --><BR/>",
+            ];
+
+            for chunk in &chunks {
+                rewriter.write(chunk.as_bytes()).unwrap();
+            }
+
+            rewriter.end().unwrap();
+        }
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            concat!(
+                r#"<!DOCTYPE html><a href="/" id="1" rel="nofollow"> Foo Bar </a>"#,
+                "<pre>  a\n  b </pre><script> var a  =  1; </script><!--  c  --><br/>"
+            )
+        );
+
+        assert_eq!(changed_count, 0);
+    }
+
+    #[test]
+    fn obsolete_elements_policy() {
+        let rewrite = |html: &str, obsolete_elements_policy| {
+            let mut output = vec![];
+
+            {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        element_content_handlers: vec![element!("listing", |el| {
+                            el.set_attribute("foo", "bar")?;
+
+                            Ok(())
+                        })],
+                        obsolete_elements_policy,
+                        ..Settings::default()
+                    },
+                    |c: &[u8]| output.extend_from_slice(c),
+                )
+                .unwrap();
+
+                rewriter.write(html.as_bytes()).unwrap();
+                rewriter.end().unwrap();
+            }
+
+            String::from_utf8(output).unwrap()
+        };
+
+        let html = concat!(
+            "<p>1</p><xmp><b>2</b></xmp><marquee><style>a>b{}</style>3</marquee>",
+            "<listing>4</listing><plaintext><i>5</i></plaintext>"
+        );
+
+        assert_eq!(
+            rewrite(html, ObsoleteElementsPolicy::Preserve),
+            concat!(
+                "<p>1</p><xmp><b>2</b></xmp><marquee><style>a>b{}</style>3</marquee>",
+                r#"<listing foo="bar">4</listing><plaintext><i>5</i></plaintext>"#
+            )
+        );
+
+        assert_eq!(
+            rewrite(html, ObsoleteElementsPolicy::Normalize),
+            concat!(
+                "<p>1</p><pre>&lt;b&gt;2&lt;/b&gt;</pre><div><style>a>b{}</style>3</div>",
+                r#"<pre foo="bar">4</pre><pre>&lt;i&gt;5&lt;/i&gt;&lt;/plaintext&gt;</pre>"#
+            )
+        );
+
+        assert_eq!(rewrite(html, ObsoleteElementsPolicy::Strip), "<p>1</p>");
+    }
+
+    #[test]
+    fn table_content_policy() {
+        let rewrite = |table_content_policy| -> Result<String, RewritingError> {
+            let mut output = vec![];
+
+            {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        element_content_handlers: vec![
+                            element!("table, tr", |el| {
+                                el.prepend("<!-- prepended -->", ContentType::Html);
+                                el.append("Foo", ContentType::Text);
+
+                                Ok(())
+                            }),
+                            element!("div", |el| {
+                                el.append("Bar", ContentType::Text);
+
+                                Ok(())
+                            }),
+                        ],
+                        table_content_policy,
+                        ..Settings::default()
+                    },
+                    |c: &[u8]| output.extend_from_slice(c),
+                )
+                .unwrap();
+
+                rewriter.write(b"<div><table><tr><td>1</td></tr></table></div>")?;
+                rewriter.end()?;
+            }
+
+            Ok(String::from_utf8(output).unwrap())
+        };
+
+        assert_eq!(
+            rewrite(TableContentPolicy::Ignore).unwrap(),
+            concat!(
+                "<div><table><!-- prepended --><tr><!-- prepended --><td>1</td>Foo</tr>Foo",
+                "</table>Bar</div>"
+            )
+        );
+
+        assert_eq!(
+            rewrite(TableContentPolicy::Wrap).unwrap(),
+            concat!(
+                "<div><table><!-- prepended --><tr><!-- prepended --><td>1</td><td>Foo</td></tr>",
+                "<tbody><tr><td>Foo</td></tr></tbody></table>Bar</div>"
+            )
+        );
+
+        match rewrite(TableContentPolicy::Reject) {
+            Err(RewritingError::ContentHandlerError(e)) => assert_eq!(
+                e.downcast_ref::<ContentModelError>(),
+                Some(&ContentModelError::ForeignTableContent("table".into()))
+            ),
+            res => panic!("Expected content handler error, got: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn deadline() {
+        let rewrite = |deadline, deadline_policy| -> Result<String, RewritingError> {
+            let mut output = vec![];
+
+            {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        element_content_handlers: vec![element!("p", |el| {
+                            el.set_inner_content("Bar", ContentType::Text);
+
+                            Ok(())
+                        })],
+                        document_content_handlers: vec![end!(|end| {
+                            end.append("<!-- end -->", ContentType::Html);
+
+                            Ok(())
+                        })],
+                        deadline: Some(deadline),
+                        deadline_policy,
+                        ..Settings::default()
+                    },
+                    |c: &[u8]| output.extend_from_slice(c),
+                )
+                .unwrap();
+
+                rewriter.write(b"<div><p>Foo</p>")?;
+                rewriter.write(b"<p>Foo</p></div>")?;
+                rewriter.end()?;
+            }
+
+            Ok(String::from_utf8(output).unwrap())
+        };
+
+        let future = Instant::now() + Duration::from_secs(3600);
+        let past = Instant::now();
+
+        for &policy in &[DeadlinePolicy::Passthrough, DeadlinePolicy::Fail] {
+            assert_eq!(
+                rewrite(future, policy).unwrap(),
+                "<div><p>Bar</p><p>Bar</p></div><!-- end -->"
+            );
+        }
+
+        assert_eq!(
+            rewrite(past, DeadlinePolicy::Passthrough).unwrap(),
+            "<div><p>Foo</p><p>Foo</p></div><!-- end -->"
+        );
+
+        assert!(matches!(
+            rewrite(past, DeadlinePolicy::Fail),
+            Err(RewritingError::DeadlineExceeded)
+        ));
+    }
+
+    #[test]
+    fn disable_selectors() {
+        let mut output = vec![];
+
+        {
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: vec![
+                        element!("div", |el| {
+                            el.set_attribute("foo", "1")?;
+
+                            Ok(())
+                        }),
+                        element_limited!("div", 1, |el| {
+                            el.set_attribute("bar", "2")?;
+
+                            Ok(())
+                        }),
+                    ],
+                    ..Settings::default()
+                },
+                |c: &[u8]| output.extend_from_slice(c),
+            )
+            .unwrap();
+
+            rewriter.write(b"<div></div>").unwrap();
+
+            assert!(rewriter.set_selector_enabled(0, false));
+            assert!(rewriter.set_selector_enabled(1, false));
+            assert!(!rewriter.set_selector_enabled(2, false));
+
+            rewriter.write(b"<div></div>").unwrap();
+            rewriter.start_new_document().unwrap();
+            rewriter.write(b"<div></div>").unwrap();
+
+            assert!(rewriter.set_selector_enabled(1, true));
+
+            rewriter.write(b"<div></div><div></div>").unwrap();
+            rewriter.end().unwrap();
+        }
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            concat!(
+                r#"<div foo="1" bar="2"></div>"#,
+                "<div></div>",
+                "<div></div>",
+                r#"<div bar="2"></div><div></div>"#
+            )
+        );
+    }
+
+    #[test]
+    fn selector_switch() {
+        let mut output = vec![];
+
+        {
+            let switch = SelectorSwitch::new();
+            let enable_switch = switch.clone();
+            let disable_switch = switch.clone();
+
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: vec![
+                        element!("b", move |_| {
+                            enable_switch.enable(2);
+
+                            Ok(())
+                        }),
+                        element!("i", move |_| {
+                            disable_switch.disable(2);
+
+                            Ok(())
+                        }),
+                        (
+                            &"p".parse().unwrap(),
+                            ElementContentHandlers::default()
+                                .text(|t| {
+                                    if !t.last_in_text_node() {
+                                        t.replace("x", ContentType::Text);
+                                    }
+
+                                    Ok(())
+                                })
+                                .disabled(),
+                        ),
+                    ],
+                    selector_switch: Some(switch),
+                    ..Settings::default()
+                },
+                |c: &[u8]| output.extend_from_slice(c),
+            )
+            .unwrap();
+
+            rewriter
+                .write(b"<p>1</p><b></b><p>2</p><p>3<i></i>4</p><p>5</p>")
+                .unwrap();
+            rewriter.end().unwrap();
+        }
+
+        // NOTE: the paragraph that is open when the selector is disabled is still processed.
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "<p>1</p><b></b><p>x</p><p>x<i></i>x</p><p>5</p>"
+        );
+    }
+
+    #[test]
+    fn input_filter() {
+        let mut output = vec![];
+
+        {
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: vec![element!("p", |el| {
+                        el.set_attribute("foo", "bar")?;
+
+                        Ok(())
+                    })],
+                    input_filter: Some(Box::new(StripPrefix::new(b")]}'\n"))),
+                    ..Settings::default()
+                },
+                |c: &[u8]| output.extend_from_slice(c),
+            )
+            .unwrap();
+
+            rewriter.write(b")]").unwrap();
+            rewriter.write(b"}'\n<p>1</p>").unwrap();
+            rewriter.start_new_document().unwrap();
+            rewriter.write(b")]").unwrap();
+            rewriter.start_new_document().unwrap();
+            rewriter.write(b")]}'<p>2</p>").unwrap();
+            rewriter.end().unwrap();
+        }
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r#"<p foo="bar">1</p>)])]}'<p foo="bar">2</p>"#
+        );
+    }
+
+    #[test]
+    fn output_filter() {
+        struct EventStream;
+
+        impl OutputFilter for EventStream {
+            fn filter(&mut self, chunk: &[u8], output: &mut Vec<u8>) {
+                output.extend_from_slice(b"data: ");
+                output.extend_from_slice(chunk);
+                output.extend_from_slice(b"\n\n");
+            }
+
+            fn end(&mut self, output: &mut Vec<u8>) {
+                output.extend_from_slice(b"event: end\n\n");
+            }
+        }
+
+        let mut chunks = vec![];
+
+        {
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: vec![element!("p", |el| {
+                        el.remove();
+
+                        Ok(())
+                    })],
+                    output_filter: Some(Box::new(EventStream)),
+                    ..Settings::default()
+                },
+                |c: &[u8]| chunks.push(String::from_utf8(c.to_vec()).unwrap()),
+            )
+            .unwrap();
+
+            rewriter.write(b"<div><p>1</p></div>").unwrap();
+            rewriter.start_new_document().unwrap();
+            rewriter.write(b"<b>2</b>").unwrap();
+            rewriter.end().unwrap();
+        }
+
+        // NOTE: the sink still receives the finalizing chunk after the output of the filter.
+        assert_eq!(chunks.pop().unwrap(), "");
+        assert_eq!(chunks.pop().unwrap(), "event: end\n\n");
+
+        let content = chunks
+            .iter()
+            .map(|c| {
+                assert!(c.starts_with("data: ") && c.ends_with("\n\n"));
+
+                &c[6..c.len() - 2]
+            })
+            .collect::<String>();
+
+        assert_eq!(content, "<div></div><b>2</b>");
+    }
+
+    #[cfg(feature = "selector_matching")]
+    #[test]
+    fn rewriter_template() {
+        let item: Selector = "li:last-child".parse().unwrap();
+        let list: Selector = "ul:has(> li.new)".parse().unwrap();
+
+        let settings = |mark: &'static str| Settings {
+            element_content_handlers: vec![
+                (
+                    &item,
+                    ElementContentHandlers::default().element(move |el| {
+                        el.set_attribute("last", mark)?;
+
+                        Ok(())
+                    }),
+                ),
+                (
+                    &list,
+                    ElementContentHandlers::default().text(move |t| {
+                        if !t.last_in_text_node() {
+                            t.replace(mark, ContentType::Text);
+                        }
+
+                        Ok(())
+                    }),
+                ),
+            ],
+            buffer_has_candidates: true,
+            ..Settings::default()
+        };
+
+        let template = RewriterTemplate::try_new(&settings("")).unwrap();
+
+        for &(mark, html, expected) in &[
+            (
+                "1",
+                "<ul><li>a</li><li>b</li></ul>",
+                r#"<ul><li>a</li><li last="1">b</li></ul>"#,
+            ),
+            (
+                "2",
+                r#"<ul><li class="new">a</li></ul>"#,
+                r#"<ul><li class="new" last="2">2</li></ul>"#,
+            ),
+        ] {
+            let mut output = vec![];
+
+            {
+                let mut rewriter =
+                    HtmlRewriter::from_template(&template, settings(mark), |c: &[u8]| {
+                        output.extend_from_slice(c)
+                    })
+                    .unwrap();
+
+                rewriter.write(html.as_bytes()).unwrap();
+                rewriter.end().unwrap();
+            }
+
+            assert_eq!(String::from_utf8(output).unwrap(), expected);
+        }
+    }
+
+    #[cfg(feature = "selector_matching")]
+    #[test]
+    fn rewriter_template_added_and_removed_selectors() {
+        let link: Selector = "a[href]".parse().unwrap();
+        let image: Selector = "img".parse().unwrap();
+        let first_item: Selector = "ul > li:first-child".parse().unwrap();
+        let has_items: Selector = "ul:has(> li)".parse().unwrap();
+
+        let mark = |name: &'static str| {
+            ElementContentHandlers::default().element(move |el| {
+                el.set_attribute(name, "")?;
+
+                Ok(())
+            })
+        };
+
+        let html = r#"<ul><li><a href="/">1</a></li><li><img></li></ul>"#;
+
+        let rewrite =
+            |template: &RewriterTemplate,
+             element_content_handlers: Vec<(&Selector, ElementContentHandlers)>| {
+                let mut output = vec![];
+
+                {
+                    let mut rewriter = HtmlRewriter::from_template(
+                        template,
+                        Settings {
+                            element_content_handlers,
+                            buffer_has_candidates: true,
+                            ..Settings::default()
+                        },
+                        |c: &[u8]| output.extend_from_slice(c),
+                    )
+                    .unwrap();
+
+                    rewriter.write(html.as_bytes()).unwrap();
+                    rewriter.end().unwrap();
+                }
+
+                String::from_utf8(output).unwrap()
+            };
+
+        let template = RewriterTemplate::try_new(&Settings {
+            element_content_handlers: vec![(&link, mark("link")), (&image, mark("image"))],
+            buffer_has_candidates: true,
+            ..Settings::default()
+        })
+        .unwrap();
+
+        let extended = template
+            .add_selectors(&[(&first_item, mark("first")), (&has_items, mark("list"))])
+            .unwrap();
+
+        let reduced = extended.remove_selectors(&[0, 3, 42]);
+
+        assert_eq!(
+            rewrite(
+                &template,
+                vec![(&link, mark("link")), (&image, mark("image"))]
+            ),
+            r#"<ul><li><a href="/" link="">1</a></li><li><img image=""></li></ul>"#
+        );
+
+        assert_eq!(
+            rewrite(
+                &extended,
+                vec![
+                    (&link, mark("link")),
+                    (&image, mark("image")),
+                    (&first_item, mark("first")),
+                    (&has_items, mark("list")),
+                ]
+            ),
+            r#"<ul list=""><li first=""><a href="/" link="">1</a></li><li><img image=""></li></ul>"#
+        );
+
+        assert_eq!(
+            rewrite(
+                &reduced,
+                vec![(&image, mark("image")), (&first_item, mark("first"))]
+            ),
+            r#"<ul><li first=""><a href="/">1</a></li><li><img image=""></li></ul>"#
+        );
+
+        let empty = reduced.remove_selectors(&[0, 1]);
+
+        assert_eq!(rewrite(&empty, vec![]), html);
+
+        assert_eq!(
+            rewrite(
+                &empty.add_selectors(&[(&link, mark("link"))]).unwrap(),
+                vec![(&link, mark("link"))]
+            ),
+            r#"<ul><li><a href="/" link="">1</a></li><li><img></li></ul>"#
+        );
+    }
+
+    #[cfg(feature = "selector_matching")]
+    #[test]
+    fn rewriter_template_added_selectors_require_lookahead() {
+        let template = RewriterTemplate::try_new(&Settings::default()).unwrap();
+        let selector: Selector = "ul:has(li)".parse().unwrap();
+
+        assert!(matches!(
+            template.add_selectors(&[(
+                &selector,
+                ElementContentHandlers::<LocalHandlerTypes>::default()
+            )]),
+            Err(SettingsError::Selector(SelectorError::LookaheadRequired))
+        ));
+    }
+
+    #[test]
+    fn rewriter_template_mismatch() {
+        fn from_template(template: &RewriterTemplate, settings: Settings) -> Option<SettingsError> {
+            HtmlRewriter::from_template(template, settings, |_: &[u8]| {}).err()
+        }
+
+        let template = RewriterTemplate::try_new(&Settings {
+            element_content_handlers: vec![element!("div", |_| Ok(()))],
+            ..Settings::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            from_template(
+                &template,
+                Settings {
+                    element_content_handlers: vec![text!("div", |_| Ok(()))],
+                    ..Settings::default()
+                }
+            ),
+            Some(SettingsError::TemplateMismatch)
+        );
+
+        assert_eq!(
+            from_template(&template, Settings::default()),
+            Some(SettingsError::TemplateMismatch)
+        );
+
+        assert!(from_template(
+            &template,
+            Settings {
+                element_content_handlers: vec![element!("div", |_| Ok(()))],
+                encoding: "windows-1252",
+                ..Settings::default()
+            }
+        )
+        .is_some());
+
+        assert_eq!(
+            from_template(
+                &template,
+                Settings {
+                    element_content_handlers: vec![element!("div", |_| Ok(()))],
+                    ..Settings::default()
+                }
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn selector_match_counts() {
+        let mut rewriter = HtmlRewriter::try_new(
+            Settings {
+                element_content_handlers: vec![
+                    element!("div", |_| Ok(())),
+                    (&"span".parse().unwrap(), ElementContentHandlers::default()),
+                    (&"span".parse().unwrap(), ElementContentHandlers::default()),
+                    element_limited!("div > p", 1, |_| Ok(())),
+                    element!("table", |_| Ok(())),
+                ],
+                count_selector_matches: true,
+                ..Settings::default()
+            },
+            |_: &[u8]| {},
+        )
+        .unwrap();
+
+        rewriter
+            .write(b"<div><p>1</p><span>2</span><p>3</p></div>")
+            .unwrap();
+        rewriter.start_new_document().unwrap();
+        rewriter.write(b"<div><p>4</p></div>").unwrap();
+        rewriter.end().unwrap();
+
+        assert_eq!(rewriter.selector_match_counts(), Some(vec![2, 1, 1, 2, 0]));
+    }
+
+    #[cfg(feature = "selector_matching")]
+    #[test]
+    fn match_info() {
+        let matches = Rc::new(RefCell::new(Vec::new()));
+
+        let record = |kind: &'static str| {
+            let matches = Rc::clone(&matches);
+
+            move |match_info: Option<&MatchInfo>| {
+                matches.borrow_mut().push(format!(
+                    "{} {:?}",
+                    kind,
+                    match_info.map(|i| (
+                        i.selector_index(),
+                        i.alternative_index(),
+                        i.selector().to_owned()
+                    ))
+                ));
+            }
+        };
+
+        let element_match = record("element");
+        let text_match = record("text");
+        let comment_match = record("comment");
+
+        let element_handler = move |el: &mut Element| {
+            element_match(el.match_info());
+
+            Ok(())
+        };
+
+        let mut rewriter = HtmlRewriter::try_new(
+            Settings {
+                element_content_handlers: vec![
+                    element!("div.a, p", element_handler.clone()),
+                    element!("div", element_handler),
+                    text!("div:is(.b, .c), p", move |t| {
+                        if !t.last_in_text_node() {
+                            text_match(t.match_info());
+                        }
+
+                        Ok(())
+                    }),
+                ],
+                document_content_handlers: vec![doc_comments!(move |c| {
+                    comment_match(c.match_info());
+
+                    Ok(())
+                })],
+                ..Settings::default()
+            },
+            |_: &[u8]| {},
+        )
+        .unwrap();
+
+        rewriter
+            .write(b"<div class=a><!-- 1 --><p>2</p></div><div class=c>3<p>4</p>5</div>")
+            .unwrap();
+        rewriter.end().unwrap();
+
+        assert_eq!(
+            *matches.borrow(),
+            vec![
+                r#"element Some((0, 0, "div.a"))"#,
+                r#"element Some((1, 0, "div"))"#,
+                "comment None",
+                r#"element Some((0, 1, "p"))"#,
+                r#"text Some((2, 1, "p"))"#,
+                r#"element Some((1, 0, "div"))"#,
+                r#"text Some((2, 0, "div:is(.b, .c)"))"#,
+                r#"element Some((0, 1, "p"))"#,
+                r#"text Some((2, 1, "p"))"#,
+                r#"text Some((2, 0, "div:is(.b, .c)"))"#,
+            ]
+        );
+    }
+
+    #[cfg(feature = "selector_matching")]
+    #[test]
+    fn match_cache() {
+        let rewrite = |match_cache_capacity| {
+            let mut output = vec![];
+
+            {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        element_content_handlers: vec![
+                            element!("td.cell:not([hidden])", |el| {
+                                el.set_attribute("visible", "")?;
+
+                                Ok(())
+                            }),
+                            element!("tr > td:first-child[class]", |el| {
+                                el.set_attribute("first", "")?;
+
+                                Ok(())
+                            }),
+                            element!("td:lang(fr)", |el| {
+                                el.set_attribute("title", "fr")?;
+
+                                Ok(())
+                            }),
+                        ],
+                        match_cache_capacity,
+                        ..Settings::default()
+                    },
+                    |c: &[u8]| output.extend_from_slice(c),
+                )
+                .unwrap();
+
+                let row = r#"<tr><td class="cell">1<td class="cell">2<td class="cell" hidden>3"#;
+
+                rewriter.write(b"<table>").unwrap();
+                rewriter.write(row.as_bytes()).unwrap();
+                rewriter.write(b"<tbody lang=fr>").unwrap();
+                rewriter.write(row.as_bytes()).unwrap();
+                rewriter.write(row.as_bytes()).unwrap();
+                rewriter.write(b"</table>").unwrap();
+                rewriter.end().unwrap();
+            }
+
+            String::from_utf8(output).unwrap()
+        };
+
+        let expected = rewrite(None);
+
+        assert_eq!(rewrite(Some(1)), expected);
+        assert_eq!(rewrite(Some(16)), expected);
+
+        assert_eq!(
+            expected,
+            concat!(
+                r#"<table><tr><td class="cell" visible="" first="">1"#,
+                r#"<td class="cell" visible="">2<td class="cell" hidden>3<tbody lang=fr>"#,
+                r#"<tr><td class="cell" visible="" first="" title="fr">1"#,
+                r#"<td class="cell" visible="" title="fr">2<td class="cell" hidden title="fr">3"#,
+                r#"<tr><td class="cell" visible="" first="" title="fr">1"#,
+                r#"<td class="cell" visible="" title="fr">2<td class="cell" hidden title="fr">3"#,
+                "</table>"
+            )
+        );
+    }
+
+    #[test]
+    fn handler_priority() {
+        let order = RefCell::new(vec![]);
+
+        {
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: vec![
+                        element!("p", |_| {
+                            order.borrow_mut().push("element1");
+                            Ok(())
+                        }),
+                        (
+                            &"p".parse().unwrap(),
+                            ElementContentHandlers::default()
+                                .element(|_| {
+                                    order.borrow_mut().push("element2");
+                                    Ok(())
+                                })
+                                .priority(1),
+                        ),
+                        comments!("p", |_| {
+                            order.borrow_mut().push("comment1");
+                            Ok(())
+                        }),
+                    ],
+                    document_content_handlers: vec![
+                        doc_comments!(|_| {
+                            order.borrow_mut().push("comment2");
+                            Ok(())
+                        }),
+                        DocumentContentHandlers::default()
+                            .comments(|_| {
+                                order.borrow_mut().push("comment3");
+                                Ok(())
+                            })
+                            .priority(1),
+                        DocumentContentHandlers::default()
+                            .comments(|_| {
+                                order.borrow_mut().push("comment4");
+                                Ok(())
+                            })
+                            .priority(-1),
+                    ],
+                    ..Settings::default()
+                },
+                |_: &[u8]| {},
+            )
+            .unwrap();
+
+            rewriter.write(b"<p><!-- foo --></p>").unwrap();
+            rewriter.end().unwrap();
+        }
+
+        assert_eq!(
+            order.into_inner(),
+            ["element2", "element1", "comment3", "comment1", "comment2", "comment4"]
+        );
+    }
+
+    #[test]
+    fn selector_match_counts_disabled() {
+        let mut rewriter = HtmlRewriter::try_new(
+            Settings {
+                element_content_handlers: vec![element!("div", |_| Ok(()))],
+                ..Settings::default()
+            },
+            |_: &[u8]| {},
+        )
+        .unwrap();
+
+        rewriter.write(b"<div></div>").unwrap();
+        rewriter.end().unwrap();
+
+        assert_eq!(rewriter.selector_match_counts(), None);
+    }
+
+    #[test]
+    fn current_path() {
+        let mut rewriter = HtmlRewriter::try_new(
+            Settings {
+                element_content_handlers: vec![element!("p", |_| Ok(()))],
+                ..Settings::default()
+            },
+            |_: &[u8]| {},
+        )
+        .unwrap();
+
+        assert_eq!(rewriter.current_path(), "");
+        assert_eq!(rewriter.current_depth(), 0);
+
+        rewriter
+            .write(b"<div><svg><foreignObject><my-element><br>")
+            .unwrap();
+
+        assert_eq!(
+            rewriter.current_path(),
+            "div > svg > foreignobject > my-element"
+        );
+        assert_eq!(rewriter.current_depth(), 4);
+
+        rewriter.write(b"</foreignObject><circle/><G>").unwrap();
+
+        assert_eq!(rewriter.current_path(), "div > svg > g");
+
+        rewriter.end().unwrap();
+    }
+
+    #[test]
+    fn current_path_without_element_handlers() {
+        let mut rewriter = HtmlRewriter::try_new(Settings::default(), |_: &[u8]| {}).unwrap();
+
+        rewriter.write(b"<div><p>").unwrap();
+
+        assert_eq!(rewriter.current_path(), "");
+        assert_eq!(rewriter.current_depth(), 0);
+    }
+
+    #[test]
+    fn current_path_with_ids() {
+        let mut rewriter = HtmlRewriter::try_new(
+            Settings {
+                element_content_handlers: vec![element!("p", |_| Ok(()))],
+                track_element_ids: true,
+                ..Settings::default()
+            },
+            |_: &[u8]| {},
+        )
+        .unwrap();
+
+        rewriter
+            .write(b"<div id=main><section><svg ID='pic'><g id=\"\">")
+            .unwrap();
+
+        assert_eq!(rewriter.current_path(), "div#main > section > svg#pic > g#");
+
+        rewriter.end().unwrap();
+    }
+
+    #[test]
+    fn element_current_path() {
+        for &(track_element_ids, expected_paths) in &[
+            (
+                false,
+                [
+                    "div > ul > li#first",
+                    "div > ul > li > img#pic",
+                    "div > ul > li > li",
+                ],
+            ),
+            (
+                true,
+                [
+                    "div#main > ul > li#first",
+                    "div#main > ul > li#first > img#pic",
+                    "div#main > ul > li#first > li",
+                ],
+            ),
+        ] {
+            let mut paths = vec![];
+
+            {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        element_content_handlers: vec![element!("li, img", |el| {
+                            paths.push(el.current_path());
+
+                            Ok(())
+                        })],
+                        track_element_ids,
+                        ..Settings::default()
+                    },
+                    |_: &[u8]| {},
+                )
+                .unwrap();
+
+                rewriter
+                    .write(b"<div id=main><ul><li id=first><img id=pic><li>")
+                    .unwrap();
+                rewriter.end().unwrap();
+            }
+
+            assert_eq!(paths, expected_paths);
+        }
+    }
+
+    #[test]
+    fn select_content_policy() {
+        let rewrite = |select_content_policy| -> Result<String, RewritingError> {
+            let mut output = vec![];
+
+            {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        element_content_handlers: vec![element!("select, div", |el| {
+                            el.append(r#"<option><input name="foo"></option>"#, ContentType::Html);
+
+                            Ok(())
+                        })],
+                        select_content_policy,
+                        ..Settings::default()
+                    },
+                    |c: &[u8]| output.extend_from_slice(c),
+                )
+                .unwrap();
+
+                rewriter.write(b"<div><select></select></div>")?;
+                rewriter.end()?;
+            }
+
+            Ok(String::from_utf8(output).unwrap())
+        };
+
+        assert_eq!(
+            rewrite(SelectContentPolicy::Ignore).unwrap(),
+            concat!(
+                r#"<div><select><option><input name="foo"></option></select>"#,
+                r#"<option><input name="foo"></option></div>"#
+            )
+        );
+
+        assert_eq!(
+            rewrite(SelectContentPolicy::Strip).unwrap(),
+            concat!(
+                r#"<div><select><option></option></select>"#,
+                r#"<option><input name="foo"></option></div>"#
+            )
+        );
+
+        match rewrite(SelectContentPolicy::Reject) {
+            Err(RewritingError::ContentHandlerError(e)) => assert_eq!(
+                e.downcast_ref::<ContentModelError>(),
+                Some(&ContentModelError::DisallowedSelectContent("select".into()))
+            ),
+            res => panic!("Expected content handler error, got: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn coalesced_text_chunks() {
+        let texts = RefCell::new(vec![]);
+
+        let rewrite = |chunks: &[&str], document_content_handlers| {
+            let mut output = vec![];
+
+            {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        element_content_handlers: vec![(
+                            &"div".parse().unwrap(),
+                            ElementContentHandlers::default()
+                                .text(|t| {
+                                    let text = t.as_str().to_uppercase();
+
+                                    texts
+                                        .borrow_mut()
+                                        .push((t.as_str().to_owned(), t.last_in_text_node()));
+
+                                    t.replace(&text, ContentType::Html);
+
+                                    Ok(())
+                                })
+                                .text_chunk_policy(TextChunkPolicy::Coalesced),
+                        )],
+                        document_content_handlers,
+                        ..Settings::default()
+                    },
+                    |c: &[u8]| output.extend_from_slice(c),
+                )
+                .unwrap();
+
+                write_chunks(&mut rewriter, encoding_rs::UTF_8, chunks);
+            }
+
+            (String::from_utf8(output).unwrap(), texts.take())
+        };
+
+        assert_eq!(
+            rewrite(&["<div>Hel", "lo <b>wo", "rld</b></div>"], vec![]),
+            (
+                "<div>HELLO <b>WORLD</b></div>".into(),
+                vec![("Hello ".into(), true), ("world".into(), true)]
+            )
+        );
+
+        assert_eq!(
+            rewrite(
+                &["<div>foo", "bar</div>"],
+                vec![DocumentContentHandlers::default()
+                    .text(|t| {
+                        if t.last_in_text_node() {
+                            t.replace("baz", ContentType::Text);
+                        }
+
+                        Ok(())
+                    })
+                    .priority(1)]
+            ),
+            ("<div>FOOBARbaz</div>".into(), vec![("foobar".into(), true)])
+        );
+    }
+
+    #[test]
+    fn stream_comments() {
+        let text = "foo bar ".repeat(32);
+
+        let rewrite = |stream_comments| -> Result<(String, Vec<(usize, bool)>), RewritingError> {
+            let mut chunks = vec![];
+            let mut output = vec![];
+
+            {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        document_content_handlers: vec![doc_comments!(|c| {
+                            let text = c.text();
+
+                            chunks.push((text.len(), c.last_in_comment()));
+                            c.set_text(&text.to_uppercase())?;
+
+                            Ok(())
+                        })],
+                        memory_settings: MemorySettings {
+                            max_allowed_memory_usage: 2048,
+                            preallocated_parsing_buffer_size: 0,
+                            ..MemorySettings::default()
+                        },
+                        stream_comments,
+                        ..Settings::default()
+                    },
+                    |c: &[u8]| output.extend_from_slice(c),
+                )
+                .unwrap();
+
+                rewriter.write(b"<div><!--")?;
+
+                for _ in 0..32 {
+                    rewriter.write(text.as_bytes())?;
+                }
+
+                rewriter.write(b"--></div>")?;
+                rewriter.end()?;
+            }
+
+            Ok((String::from_utf8(output).unwrap(), chunks))
+        };
+
+        let (output, chunks) = rewrite(true).unwrap();
+        let mut expected_chunks = vec![(text.len(), false); 32];
+
+        expected_chunks.push((0, true));
+
+        assert_eq!(
+            output,
+            format!("<div><!--{}--></div>", text.repeat(32).to_uppercase())
+        );
+        assert_eq!(chunks, expected_chunks);
+
+        assert!(matches!(
+            rewrite(false),
+            Err(RewritingError::MemoryLimitExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn source_locations() {
+        let locations = RefCell::new(vec![]);
+
+        let push_location = |unit, location: &SourceLocation| {
+            locations.borrow_mut().push((
+                unit,
+                location.bytes(),
+                location.line(),
+                location.column(),
+            ));
+        };
+
+        {
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: vec![
+                        element!("*", |el| {
+                            push_location("element", el.source_location());
+
+                            Ok(())
+                        }),
+                        (
+                            &"p".parse().unwrap(),
+                            ElementContentHandlers::default()
+                                .text(|t| {
+                                    push_location("text", t.source_location());
+
+                                    Ok(())
+                                })
+                                .text_chunk_policy(TextChunkPolicy::Coalesced),
+                        ),
+                    ],
+                    document_content_handlers: vec![doc_comments!(|c| {
+                        push_location("comment", c.source_location());
+
+                        Ok(())
+                    })],
+                    ..Settings::default()
+                },
+                |_: &[u8]| {},
+            )
+            .unwrap();
+
+            write_chunks(
+                &mut rewriter,
+                encoding_rs::UTF_8,
+                &["<div>\n  <p class=x>He", "llo</p>\n<!-- c -->", "\n</div>"],
+            );
+        }
+
+        assert_eq!(
+            locations.into_inner(),
+            vec![
+                ("element", 0..5, 1, 1),
+                ("element", 8..19, 2, 3),
+                ("text", 19..24, 2, 14),
+                ("comment", 29..39, 3, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn memory_settings_from_sample() {
+        let settings = MemorySettings::from_sample(r#"<div><img src="a.png" alt="">Foo</div>"#);
+
+        assert_eq!(settings.preallocated_parsing_buffer_size, 32);
+        assert_eq!(settings.preallocated_attribute_buffer_capacity, 2);
+        assert_eq!(settings.preallocated_selector_matching_stack_depth, 1);
+        assert!(settings.preallocated_output_buffer_size > 0);
+
+        let settings = MemorySettings::from_sample("Foo");
+
+        assert_eq!(settings.preallocated_parsing_buffer_size, 0);
+        assert_eq!(settings.preallocated_attribute_buffer_capacity, 0);
+        assert_eq!(settings.preallocated_selector_matching_stack_depth, 0);
+    }
+
+    #[test]
+    fn rewrite_in_all_encodings() {
+        let selector = "div".parse::<Selector>().unwrap();
+
+        crate::test_utils::assert_rewrite_in_all_encodings(
+            r#"<div title="café">ü<!--ö--></div>"#,
+            r#"<div title="café" lang="é">Ü<!--Ö--></div>"#,
+            || Settings {
+                element_content_handlers: vec![(
+                    &selector,
+                    ElementContentHandlers::default()
+                        .element(|el| {
+                            el.set_attribute("lang", "é")?;
+
+                            Ok(())
+                        })
+                        .text(|t| {
+                            t.replace(&t.as_str().to_uppercase(), ContentType::Text);
+
+                            Ok(())
+                        }),
+                )],
+                document_content_handlers: vec![doc_comments!(|c| {
+                    c.set_text(&c.text().to_uppercase())?;
+
+                    Ok(())
+                })],
+                ..Settings::default()
+            },
+        );
+    }
+
+    #[test]
+    fn write_verbatim() {
+        let mut output = vec![];
+        let mut locations = vec![];
+
+        {
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: vec![
+                        element!("a", |el| {
+                            el.set_attribute("rel", "nofollow")?;
+                            locations.push(el.source_location().bytes());
+
+                            Ok(())
+                        }),
+                        element!("div", |el| {
+                            el.remove();
+
+                            Ok(())
+                        }),
+                    ],
+                    ..Settings::default()
+                },
+                |c: &[u8]| output.extend_from_slice(c),
+            )
+            .unwrap();
+
+            rewriter
+                .write_with_verbatim_ranges(
+                    b"<a>1</a><a>2</a><div id=foo><a>3</a></div><a>4</a>",
+                    &[8..16, 28..36],
+                )
+                .unwrap();
+
+            rewriter.end().unwrap();
+        }
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r#"<a rel="nofollow">1</a><a>2</a><a rel="nofollow">4</a>"#
+        );
+
+        assert_eq!(locations, [0..3, 42..45]);
+    }
+
+    #[test]
+    fn write_verbatim_after_incomplete_token() {
+        let mut output = vec![];
+
+        {
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: vec![element!("div", |el| {
+                        el.set_attribute("id", "foo")?;
+
+                        Ok(())
+                    })],
+                    ..Settings::default()
+                },
+                |c: &[u8]| output.extend_from_slice(c),
+            )
+            .unwrap();
+
+            rewriter.write(b"<div").unwrap();
+            rewriter.write_verbatim(b" class=bar>").unwrap();
+            rewriter.write_verbatim(b"<div>").unwrap();
+            rewriter.end().unwrap();
+        }
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r#"<div class=bar id="foo"><div>"#
+        );
+    }
+
+    #[cfg(feature = "parse_ahead")]
+    #[test]
+    fn parse_ahead() {
+        let html = concat!(
+            "<!DOCTYPE html><head><title>Foo</title><script>if (a < b) {}</script></head>",
+            "<body><div class=x><p>Bar<!-- baz --></p></div><svg><![CDATA[<a>]]></svg></body>"
+        );
+
+        let rewrite = |parse_ahead_queue_capacity, head_only, chunk_size| {
+            let mut output = vec![];
+
+            {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        element_content_handlers: vec![
+                            element!("title, div[class]", |el| {
+                                el.set_attribute("data-foo", "bar")?;
+
+                                Ok(())
+                            }),
+                            text!("p", |t| {
+                                t.replace(&t.as_str().to_uppercase(), ContentType::Text);
+
+                                Ok(())
+                            }),
+                            comments!("div", |c| {
+                                c.set_text("qux")?;
+
+                                Ok(())
+                            }),
+                        ],
+                        head_only,
+                        parse_ahead_queue_capacity,
+                        ..Settings::default()
+                    },
+                    |c: &[u8]| output.extend_from_slice(c),
+                )
+                .unwrap();
+
+                for chunk in html.as_bytes().chunks(chunk_size) {
+                    rewriter.write(chunk).unwrap();
+                }
+
+                rewriter.start_new_document().unwrap();
+
+                for chunk in html.as_bytes().chunks(chunk_size) {
+                    rewriter.write(chunk).unwrap();
+                }
+
+                rewriter.end().unwrap();
+            }
+
+            String::from_utf8(output).unwrap()
+        };
+
+        for &head_only in &[false, true] {
+            let expected = rewrite(None, head_only, html.len());
+
+            for &chunk_size in &[1, 7, html.len()] {
+                assert_eq!(
+                    rewrite(Some(2), head_only, chunk_size),
+                    expected,
+                    "Head only: {}, chunk size: {}",
+                    head_only,
+                    chunk_size
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "parse_ahead")]
+    #[test]
+    fn parse_ahead_with_spilling() {
+        let err = HtmlRewriter::try_new(
+            Settings {
+                parse_ahead_queue_capacity: Some(2),
+                spill_settings: SpillSettings::all(),
+                ..Settings::default()
+            },
+            |_: &[u8]| {},
+        )
+        .unwrap_err();
+
+        assert_eq!(err, SettingsError::SpillingWithParseAhead);
+    }
+
+    mod fatal_errors {
+        use super::*;
+        use crate::errors::BufferedTokenType;
+
+        fn create_rewriter<O: OutputSink>(
+            max_allowed_memory_usage: usize,
+            output_sink: O,
+        ) -> HtmlRewriter<'static, O> {
+            HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: vec![element!("*", |_| Ok(()))],
+                    memory_settings: MemorySettings {
+                        max_allowed_memory_usage,
+                        preallocated_parsing_buffer_size: 0,
+                        ..MemorySettings::default()
+                    },
+                    ..Settings::default()
+                },
+                output_sink,
+            )
+            .unwrap()
+        }
+
+        #[test]
+        fn buffer_capacity_limit() {
+            const MAX: usize = 100;
+
+            let mut rewriter = create_rewriter(MAX, |_: &[u8]| {});
+
+            // Use two chunks for the stream to force the usage of the buffer and
+            // make sure to overflow it.
+            let chunk_1 = format!("<img alt=\"{}", "l".repeat(MAX / 2));
+            let chunk_2 = format!("{}\" />", "r".repeat(MAX / 2));
+
+            rewriter.write(chunk_1.as_bytes()).unwrap();
+
+            let write_err = rewriter.write(chunk_2.as_bytes()).unwrap_err();
+
+            match write_err {
+                RewritingError::MemoryLimitExceeded(e) => {
+                    assert_eq!(e.limit(), MAX);
+                    assert!(e.requested_usage() > MAX);
+                    assert_eq!(e.token_type(), Some(BufferedTokenType::StartTag));
+                    assert_eq!(e.tag_name(), Some("img"));
+                }
+                _ => panic!("{}", write_err),
+            }
+        }
+
+        #[cfg(feature = "parse_ahead")]
+        #[test]
+        fn parse_ahead_buffer_capacity_limit() {
+            const MAX: usize = 100;
+
+            let input = format!("<img alt=\"{}", "l".repeat(MAX * 2));
+
+            // NOTE: the incomplete token is accumulated by the parse-ahead thread from
+            // the small chunks, while the big chunk exceeds the limit on its own.
+            for &chunk_size in &[10, input.len()] {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        element_content_handlers: vec![element!("*", |_| Ok(()))],
+                        memory_settings: MemorySettings {
+                            max_allowed_memory_usage: MAX,
+                            preallocated_parsing_buffer_size: 0,
+                            ..MemorySettings::default()
+                        },
+                        parse_ahead_queue_capacity: Some(1),
+                        ..Settings::default()
+                    },
+                    |_: &[u8]| {},
+                )
+                .unwrap();
+
+                let err = input
+                    .as_bytes()
+                    .chunks(chunk_size)
+                    .try_for_each(|chunk| rewriter.write(chunk))
+                    .and_then(|_| rewriter.end())
+                    .unwrap_err();
+
+                match err {
+                    RewritingError::MemoryLimitExceeded(e) => {
+                        assert_eq!(e.limit(), MAX);
+                        assert!(e.requested_usage() > MAX);
+                    }
+                    _ => panic!("{}", err),
+                }
+            }
+        }
+
+        #[test]
+        fn memory_usage_warning() {
+            const MAX: usize = 100;
+
+            let mut warnings = vec![];
+
+            {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        element_content_handlers: vec![element!("*", |_| Ok(()))],
+                        memory_settings: MemorySettings {
+                            max_allowed_memory_usage: MAX,
+                            preallocated_parsing_buffer_size: 0,
+                            ..MemorySettings::default()
+                        },
+                        memory_usage_warning: Some(MemoryUsageWarning::new(MAX / 2, |usage| {
+                            warnings.push(usage);
+                        })),
+                        ..Settings::default()
+                    },
+                    |_: &[u8]| {},
+                )
+                .unwrap();
+
+                rewriter.write(b"<img alt=\"").unwrap();
+                rewriter.write("l".repeat(MAX / 2).as_bytes()).unwrap();
+                rewriter.write(b"\">").unwrap();
+
+                let chunk = format!("<img alt=\"{}", "r".repeat(MAX));
+                let write_err = rewriter.write(chunk.as_bytes()).unwrap_err();
+
+                assert!(matches!(write_err, RewritingError::MemoryLimitExceeded(_)));
+            }
+
+            assert_eq!(warnings.len(), 1);
+            assert!(warnings[0] > MAX / 2 && warnings[0] <= MAX);
+        }
+
+        #[cfg(feature = "selector_matching")]
+        #[test]
+        fn soft_memory_limit() {
+            let rewrite = |soft_memory_usage_limit| {
+                let mut output = vec![];
+
+                {
+                    let mut rewriter = HtmlRewriter::try_new(
+                        Settings {
+                            element_content_handlers: vec![element!("div:has(p)", |el| {
+                                el.set_attribute("matched", "")?;
+
+                                Ok(())
+                            })],
+                            memory_settings: MemorySettings {
+                                max_allowed_memory_usage: 1000,
+                                soft_memory_usage_limit,
+                                preallocated_parsing_buffer_size: 0,
+                                ..MemorySettings::default()
+                            },
+                            buffer_has_candidates: true,
+                            match_cache_capacity: Some(16),
+                            ..Settings::default()
+                        },
+                        |c: &[u8]| output.extend_from_slice(c),
+                    )
+                    .unwrap();
+
+                    rewriter.write(b"<div>").unwrap();
+                    rewriter.write("a".repeat(100).as_bytes()).unwrap();
+                    rewriter.write(b"<p></p></div><div><p></p></div>").unwrap();
+                    rewriter.end().unwrap();
+                }
+
+                String::from_utf8(output).unwrap()
+            };
+
+            let text = "a".repeat(100);
+
+            assert_eq!(
+                rewrite(0),
+                format!(
+                    r#"<div matched="">{}<p></p></div><div matched=""><p></p></div>"#,
+                    text
+                )
+            );
+
+            assert_eq!(
+                rewrite(50),
+                format!("<div>{}<p></p></div><div><p></p></div>", text)
+            );
+        }
+
+        #[cfg(feature = "selector_matching")]
+        #[test]
+        fn has_candidates_memory_limit() {
+            const MAX: usize = 1024;
+
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: vec![element!("body:has(footer)", |_| Ok(()))],
+                    memory_settings: MemorySettings {
+                        max_allowed_memory_usage: MAX,
+                        preallocated_parsing_buffer_size: 0,
+                        ..MemorySettings::default()
+                    },
+                    buffer_has_candidates: true,
+                    ..Settings::default()
+                },
+                |_: &[u8]| {},
+            )
+            .unwrap();
+
+            rewriter.write(b"<body><footer></footer>").unwrap();
+            rewriter.write(b"</body><body>").unwrap();
+
+            // NOTE: the second `<body>` is a candidate again, so its content is held back.
+            let write_err = rewriter.write("a".repeat(MAX).as_bytes()).unwrap_err();
+
+            match write_err {
+                RewritingError::MemoryLimitExceeded(e) => {
+                    assert_eq!(e.limit(), MAX);
+                    assert_eq!(e.token_type(), None);
+                }
+                _ => panic!("{}", write_err),
+            }
+        }
+
+        #[test]
+        fn spill_oversized_tokens() {
+            const MAX: usize = 100;
+
+            let mut output = vec![];
+
+            {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        element_content_handlers: vec![element!("img", |el| {
+                            el.set_attribute("foo", "bar")?;
+
+                            Ok(())
+                        })],
+                        memory_settings: MemorySettings {
+                            max_allowed_memory_usage: MAX,
+                            preallocated_parsing_buffer_size: 0,
+                            ..MemorySettings::default()
+                        },
+                        spill_settings: SpillSettings {
+                            start_tags: true,
+                            ..SpillSettings::default()
+                        },
+                        ..Settings::default()
+                    },
+                    |c: &[u8]| output.extend_from_slice(c),
+                )
+                .unwrap();
+
+                let chunk_1 = format!("Hi<img alt=\"{}", "l".repeat(MAX / 2));
+                let chunk_2 = format!("{}\" />", "r".repeat(MAX));
+
+                rewriter.write(chunk_1.as_bytes()).unwrap();
+                rewriter.write(chunk_2.as_bytes()).unwrap();
+                rewriter.write(b"<img src=a>").unwrap();
+                rewriter.end().unwrap();
+            }
+
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                format!(
+                    "Hi<img alt=\"{}{}\" /><img src=a foo=\"bar\">",
+                    "l".repeat(MAX / 2),
+                    "r".repeat(MAX)
+                )
+            );
+        }
+
+        #[test]
+        fn spill_tokens_unobservable_by_handlers() {
+            const MAX: usize = 1024;
+
+            let mut output = vec![];
+            let mut text = String::new();
+
+            let chunks = [
+                format!("<div><img alt=\"{}", "l".repeat(MAX / 2)),
+                format!("{}\"><!-- {}", "r".repeat(MAX), "l".repeat(MAX / 2)),
+                format!("{} -->text</div>", "r".repeat(MAX)),
+            ];
+
+            {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        element_content_handlers: vec![text!("div", |t| {
+                            text.push_str(t.as_str());
+
+                            Ok(())
+                        })],
+                        memory_settings: MemorySettings {
+                            max_allowed_memory_usage: MAX,
+                            preallocated_parsing_buffer_size: 0,
+                            ..MemorySettings::default()
+                        },
+                        ..Settings::default()
+                    },
+                    |c: &[u8]| output.extend_from_slice(c),
+                )
+                .unwrap();
+
+                for chunk in chunks.iter() {
+                    rewriter.write(chunk.as_bytes()).unwrap();
+                }
+
+                rewriter.end().unwrap();
+            }
+
+            assert_eq!(String::from_utf8(output).unwrap(), chunks.concat());
+            assert_eq!(text, "text");
+        }
+
+        #[test]
+        fn oversized_comment_is_not_spilled_for_start_tags_only() {
+            const MAX: usize = 100;
+
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    document_content_handlers: vec![doc_comments!(|_| Ok(()))],
+                    memory_settings: MemorySettings {
+                        max_allowed_memory_usage: MAX,
+                        preallocated_parsing_buffer_size: 0,
+                        ..MemorySettings::default()
+                    },
+                    spill_settings: SpillSettings {
+                        start_tags: true,
+                        ..SpillSettings::default()
+                    },
+                    ..Settings::default()
+                },
+                |_: &[u8]| {},
+            )
+            .unwrap();
+
+            let chunk_1 = format!("<!-- {}", "l".repeat(MAX / 2));
+            let chunk_2 = format!("{} -->", "r".repeat(MAX));
+
+            rewriter.write(chunk_1.as_bytes()).unwrap();
+
+            let write_err = rewriter.write(chunk_2.as_bytes()).unwrap_err();
+
+            match write_err {
+                RewritingError::MemoryLimitExceeded(e) => {
+                    assert_eq!(e.token_type(), Some(BufferedTokenType::Comment));
+                    assert_eq!(e.tag_name(), None);
+                }
                 _ => panic!("{}", write_err),
             }
         }
@@ -639,9 +4949,9 @@ mod tests {
 
         #[test]
         fn content_handler_error_propagation() {
-            fn assert_err(
-                element_handlers: ElementContentHandlers,
-                document_handlers: DocumentContentHandlers,
+            fn assert_err<'h>(
+                element_handlers: ElementContentHandlers<'h>,
+                document_handlers: DocumentContentHandlers<'h>,
                 expected_err: &'static str,
             ) {
                 let mut rewriter = HtmlRewriter::try_new(
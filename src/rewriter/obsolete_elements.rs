@@ -0,0 +1,68 @@
+use super::settings::{ElementContentHandlers, HandlerTypes, ObsoleteElementsPolicy};
+use crate::html::TextType;
+use crate::rewritable_units::ContentType;
+
+const OBSOLETE_ELEMENTS_SELECTOR: &str = "plaintext, xmp, listing, marquee";
+
+// NOTE: the content of these elements is parsed as text, so they can't have any descendant
+// elements whose text shouldn't be escaped.
+const TEXT_ELEMENTS_SELECTOR: &str = "plaintext, xmp";
+
+/// Returns the selectors and the handlers that apply the [`ObsoleteElementsPolicy`].
+///
+/// [`ObsoleteElementsPolicy`]: ../enum.ObsoleteElementsPolicy.html
+pub fn obsolete_elements_handlers<'h, H: HandlerTypes>(
+    policy: ObsoleteElementsPolicy,
+) -> Vec<(&'static str, ElementContentHandlers<'h, H>)> {
+    match policy {
+        ObsoleteElementsPolicy::Preserve => vec![],
+        ObsoleteElementsPolicy::Strip => vec![(
+            OBSOLETE_ELEMENTS_SELECTOR,
+            ElementContentHandlers {
+                element: Some(H::new_element_handler(|el| {
+                    el.remove();
+
+                    Ok(())
+                })),
+                ..ElementContentHandlers::default()
+            },
+        )],
+        ObsoleteElementsPolicy::Normalize => vec![
+            (
+                OBSOLETE_ELEMENTS_SELECTOR,
+                ElementContentHandlers {
+                    element: Some(H::new_element_handler(|el| {
+                        let name = if el.tag_name() == "marquee" {
+                            "div"
+                        } else {
+                            "pre"
+                        };
+
+                        el.set_tag_name(name)?;
+
+                        Ok(())
+                    })),
+                    ..ElementContentHandlers::default()
+                },
+            ),
+            (
+                TEXT_ELEMENTS_SELECTOR,
+                ElementContentHandlers {
+                    text: Some(H::new_text_handler(|t| {
+                        let is_text_content =
+                            matches!(t.text_type(), TextType::PlainText | TextType::RawText);
+
+                        if is_text_content && !t.removed() {
+                            let text = t.as_str().to_owned();
+
+                            t.replace(&text, ContentType::Text);
+                        }
+
+                        Ok(())
+                    })),
+                    ..ElementContentHandlers::default()
+                },
+            ),
+        ],
+    }
+}
@@ -1,12 +1,14 @@
 use super::handlers_dispatcher::{ContentHandlersDispatcher, SelectorHandlersLocator};
-use super::RewritingError;
-use crate::html::{LocalName, Namespace};
+use super::validator::Validator;
+use super::{DeadlinePolicy, EngagedOptimizations, HandlerTypes, RewritingError, SelectorSwitch};
+use crate::html::{LocalName, Namespace, Tag};
 use crate::rewritable_units::{DocumentEnd, Token, TokenCaptureFlags};
-use crate::selectors_vm::{AuxStartTagInfoRequest, ElementData, SelectorMatchingVm, VmError};
+#[cfg(feature = "basic_selectors")]
+use crate::selectors_vm::{AuxStartTagInfoRequest, VmError};
+use crate::selectors_vm::{ElementData, SelectorMatchingVm};
 use crate::transform_stream::*;
 use hashbrown::HashSet;
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::time::Instant;
 
 #[derive(Default)]
 pub struct ElementDescriptor {
@@ -18,44 +20,201 @@ pub struct ElementDescriptor {
 impl ElementData for ElementDescriptor {
     type MatchPayload = SelectorHandlersLocator;
 
+    #[cfg(feature = "basic_selectors")]
     #[inline]
     fn matched_payload_mut(&mut self) -> &mut HashSet<SelectorHandlersLocator> {
         &mut self.matched_content_handlers
     }
 }
 
-pub struct HtmlRewriteController<'h> {
-    handlers_dispatcher: Rc<RefCell<ContentHandlersDispatcher<'h>>>,
-    selector_matching_vm: Option<SelectorMatchingVm<ElementDescriptor>>,
+pub struct HtmlRewriteController<'h, H: HandlerTypes> {
+    handlers_dispatcher: ContentHandlersDispatcher<'h, H>,
+    selector_matching_vm: Option<SelectorMatchingVm<ElementDescriptor, H::Sharing>>,
+    optimizations: EngagedOptimizations,
+    cutoff_at_head_end: bool,
+    passthrough_requested: bool,
+    skip_shadow_roots: bool,
+    shadow_root_depth: usize,
+    implicitly_closed_elements: Vec<ElementDescriptor>,
+    deadline: Option<Instant>,
+    deadline_policy: DeadlinePolicy,
+    selector_switch: Option<SelectorSwitch<H>>,
+    validator: Option<Validator<'h>>,
 }
 
-impl<'h> HtmlRewriteController<'h> {
+impl<'h, H: HandlerTypes> HtmlRewriteController<'h, H> {
     #[inline]
     pub fn new(
-        handlers_dispatcher: ContentHandlersDispatcher<'h>,
-        selector_matching_vm: Option<SelectorMatchingVm<ElementDescriptor>>,
+        handlers_dispatcher: ContentHandlersDispatcher<'h, H>,
+        selector_matching_vm: Option<SelectorMatchingVm<ElementDescriptor, H::Sharing>>,
+        optimizations: EngagedOptimizations,
+        cutoff_at_head_end: bool,
+        skip_shadow_roots: bool,
+        deadline: Option<Instant>,
+        deadline_policy: DeadlinePolicy,
     ) -> Self {
         HtmlRewriteController {
-            handlers_dispatcher: Rc::new(RefCell::new(handlers_dispatcher)),
+            handlers_dispatcher,
             selector_matching_vm,
+            optimizations,
+            cutoff_at_head_end,
+            passthrough_requested: false,
+            skip_shadow_roots,
+            shadow_root_depth: 0,
+            implicitly_closed_elements: Vec::default(),
+            deadline,
+            deadline_policy,
+            selector_switch: None,
+            validator: None,
+        }
+    }
+
+    #[inline]
+    pub fn set_validator(&mut self, validator: Validator<'h>) {
+        self.validator = Some(validator);
+    }
+}
+
+impl<H: HandlerTypes> HtmlRewriteController<'_, H> {
+    /// Returns the maximum nesting depth of elements tracked by the selector matching VM so far.
+    #[cfg(feature = "basic_selectors")]
+    #[inline]
+    pub fn max_selector_matching_stack_depth(&self) -> usize {
+        self.selector_matching_vm
+            .as_ref()
+            .map_or(0, SelectorMatchingVm::max_stack_depth)
+    }
+
+    #[inline]
+    pub fn engaged_optimizations(&self) -> EngagedOptimizations {
+        self.optimizations
+    }
+
+    #[inline]
+    fn is_past_deadline(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Disables or enables the selector with the given index. Returns `false` if there is no
+    /// such selector.
+    pub fn set_selector_enabled(&mut self, selector_idx: usize, enabled: bool) -> bool {
+        let locator = self.handlers_dispatcher.selector_locator(selector_idx);
+
+        match (locator, &mut self.selector_matching_vm) {
+            (Some(locator), Some(vm)) => {
+                vm.set_payload_enabled(locator, enabled);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    #[inline]
+    pub fn set_selector_switch(&mut self, selector_switch: SelectorSwitch<H>) {
+        self.selector_switch = Some(selector_switch);
+    }
+
+    /// Applies the changes requested by the content handlers with the selector switch.
+    #[inline]
+    fn apply_selector_switch(&mut self) {
+        let changes = match self.selector_switch {
+            Some(ref selector_switch) => selector_switch.take_changes(),
+            None => return,
+        };
+
+        for (selector_idx, enabled) in changes {
+            self.set_selector_enabled(selector_idx, enabled);
+        }
+    }
+
+    #[inline]
+    pub fn disable_match_cache(&mut self) {
+        if let Some(ref mut vm) = self.selector_matching_vm {
+            vm.disable_match_cache();
+        }
+    }
+
+    #[inline]
+    pub fn selector_match_counts(&self) -> Option<Vec<usize>> {
+        self.handlers_dispatcher
+            .match_counts()
+            .map(<[usize]>::to_vec)
+    }
+
+    #[inline]
+    pub fn current_depth(&self) -> usize {
+        self.selector_matching_vm
+            .as_ref()
+            .map_or(0, SelectorMatchingVm::stack_depth)
+    }
+
+    pub fn current_path(&self) -> String {
+        match self.selector_matching_vm {
+            Some(ref vm) => vm.open_elements_path().to_string(),
+            None => String::new(),
         }
     }
 }
 
 // NOTE: it's a macro instead of an instance method, so it can be executed
-// when we hold a mutable reference for the selector matching VM.
+// when we hold a mutable reference for the selector matching VM. The handler
+// borrows only the dispatcher field, so the borrows don't overlap.
 macro_rules! create_match_handler {
     ($self:tt) => {{
-        let handlers_dispatcher = Rc::clone(&$self.handlers_dispatcher);
+        let handlers_dispatcher = &mut $self.handlers_dispatcher;
 
-        move |m| handlers_dispatcher.borrow_mut().start_matching(m)
+        move |m| handlers_dispatcher.start_matching(m)
     }};
 }
 
-impl<'h> HtmlRewriteController<'h> {
+#[inline]
+fn declares_shadow_root(aux_info: &AuxStartTagInfo) -> bool {
+    aux_info.attr_buffer.iter().any(|attr| {
+        let value = aux_info.input.slice(attr.value);
+
+        aux_info
+            .input
+            .slice(attr.name)
+            .eq_ignore_ascii_case(b"shadowrootmode")
+            && (value.eq_ignore_ascii_case(b"open") || value.eq_ignore_ascii_case(b"closed"))
+    })
+}
+
+/// Returns `true` if the open HTML element is implicitly closed by the HTML start tag, e.g.
+/// `<p>` by `<div>`, `<li>` by another `<li>` or `<head>` by `<body>`.
+#[inline]
+pub(super) fn is_closed_by_start_tag(open_element: &LocalName, start_tag: &LocalName) -> bool {
+    if *open_element == Tag::P {
+        tag_is_one_of!(
+            *start_tag,
+            [
+                Address, Article, Aside, Blockquote, Center, Dd, Details, Dialog, Dir, Div, Dl, Dt,
+                Fieldset, Figcaption, Figure, Footer, Form, H1, H2, H3, H4, H5, H6, Header, Hgroup,
+                Hr, Li, Listing, Main, Menu, Nav, Ol, P, Plaintext, Pre, Search, Section, Summary,
+                Table, Ul, Xmp
+            ]
+        )
+    } else if *open_element == Tag::Li {
+        *start_tag == Tag::Li
+    } else if tag_is_one_of!(*open_element, [Dd, Dt]) {
+        tag_is_one_of!(*start_tag, [Dd, Dt])
+    } else if *open_element == Tag::Head {
+        *start_tag == Tag::Body
+    } else {
+        false
+    }
+}
+
+impl<'h, H: HandlerTypes> HtmlRewriteController<'h, H> {
+    #[cfg(feature = "basic_selectors")]
     #[inline]
     fn respond_to_aux_info_request(
-        aux_info_req: AuxStartTagInfoRequest<ElementDescriptor, SelectorHandlersLocator>,
+        aux_info_req: AuxStartTagInfoRequest<
+            ElementDescriptor,
+            H::Sharing,
+            SelectorHandlersLocator,
+        >,
     ) -> StartTagHandlingResult<Self> {
         Err(DispatcherError::InfoRequest(Box::new(
             move |this, aux_info| {
@@ -71,69 +230,247 @@ impl<'h> HtmlRewriteController<'h> {
         )))
     }
 
+    /// Matches the `<template>` start tag once its attributes are available and starts skipping
+    /// its content if the element declares a shadow root.
+    fn handle_template_start_tag(
+        local_name: LocalName<'static>,
+        ns: Namespace,
+    ) -> StartTagHandlingResult<Self> {
+        Err(DispatcherError::InfoRequest(Box::new(
+            move |this, aux_info| {
+                let declares_shadow_root = declares_shadow_root(&aux_info);
+                let mut match_handler = create_match_handler!(this);
+
+                if let Some(ref mut vm) = this.selector_matching_vm {
+                    let res = match vm.exec_for_start_tag(local_name, ns, &mut match_handler) {
+                        Ok(_) => Ok(()),
+                        #[cfg(feature = "basic_selectors")]
+                        Err(VmError::InfoRequest(req)) => req(vm, aux_info, &mut match_handler),
+                        #[cfg(feature = "basic_selectors")]
+                        Err(VmError::MemoryLimitExceeded(e)) => Err(e),
+                        #[cfg(not(feature = "basic_selectors"))]
+                        Err(e) => match e.0 {},
+                    };
+
+                    res.map_err(RewritingError::MemoryLimitExceeded)?;
+                }
+
+                if declares_shadow_root {
+                    this.shadow_root_depth = 1;
+                }
+
+                Ok(this.get_capture_flags())
+            },
+        )))
+    }
+
     #[inline]
     fn get_capture_flags(&self) -> TokenCaptureFlags {
-        self.handlers_dispatcher.borrow().get_token_capture_flags()
+        self.handlers_dispatcher.get_token_capture_flags()
     }
 }
 
-impl TransformController for HtmlRewriteController<'_> {
+impl<H: HandlerTypes> TransformController for HtmlRewriteController<'_, H> {
     #[inline]
     fn initial_capture_flags(&self) -> TokenCaptureFlags {
         self.get_capture_flags()
     }
 
+    #[inline]
+    fn needs_attributes(&self) -> bool {
+        !self.optimizations.attribute_tracking_disabled
+    }
+
     fn handle_start_tag(
         &mut self,
         local_name: LocalName,
         ns: Namespace,
     ) -> StartTagHandlingResult<Self> {
+        if let Some(ref mut validator) = self.validator {
+            validator.handle_start_tag(&local_name, ns);
+        }
+
+        if self.passthrough_requested {
+            return Ok(TokenCaptureFlags::empty());
+        }
+
+        if self.is_past_deadline() {
+            return match self.deadline_policy {
+                DeadlinePolicy::Passthrough => {
+                    self.passthrough_requested = true;
+
+                    Ok(TokenCaptureFlags::empty())
+                }
+                DeadlinePolicy::Fail => Err(DispatcherError::RewritingError(
+                    RewritingError::DeadlineExceeded,
+                )),
+            };
+        }
+
+        // NOTE: `<body>` start tag implicitly closes `<head>`.
+        if self.cutoff_at_head_end && local_name == Tag::Body {
+            self.passthrough_requested = true;
+
+            return Ok(TokenCaptureFlags::empty());
+        }
+
+        let is_html_template = local_name == Tag::Template && ns == Namespace::Html;
+
+        // NOTE: elements in declarative shadow roots are not pushed to the open element
+        // stack, we just need to find the end of the shadow root.
+        if self.shadow_root_depth > 0 {
+            if is_html_template {
+                self.shadow_root_depth += 1;
+            }
+
+            return Ok(self.get_capture_flags());
+        }
+
+        self.apply_selector_switch();
+
         match self.selector_matching_vm {
-            Some(ref mut vm) => {
+            Some(ref mut vm) if !self.optimizations.matching_stopped => {
+                // NOTE: we don't maintain the complete tree builder state, so only the current
+                // element and its ancestors that would be closed along with it are considered.
+                // The elements are kept on the stack for the purpose of selector matching.
+                if ns == Namespace::Html && self.handlers_dispatcher.has_end_tag_handlers() {
+                    for (open_element, elem_desc) in vm.open_elements_mut() {
+                        if !is_closed_by_start_tag(open_element, &local_name) {
+                            break;
+                        }
+
+                        self.handlers_dispatcher.close_implicitly(elem_desc);
+                    }
+                }
+
+                if is_html_template && self.skip_shadow_roots {
+                    return Self::handle_template_start_tag(local_name.into_owned(), ns);
+                }
+
                 let mut match_handler = create_match_handler!(self);
 
                 match vm.exec_for_start_tag(local_name, ns, &mut match_handler) {
                     Ok(_) => Ok(self.get_capture_flags()),
+                    #[cfg(feature = "basic_selectors")]
                     Err(VmError::InfoRequest(req)) => Self::respond_to_aux_info_request(req),
+                    #[cfg(feature = "basic_selectors")]
                     Err(VmError::MemoryLimitExceeded(e)) => Err(DispatcherError::RewritingError(
                         RewritingError::MemoryLimitExceeded(e),
                     )),
+                    #[cfg(not(feature = "basic_selectors"))]
+                    Err(e) => match e.0 {},
                 }
             }
             // NOTE: fast path - we can skip executing selector matching VM completely
-            // and don't need to maintain open element stack if we don't have any selectors.
-            None => Ok(self.get_capture_flags()),
+            // and don't need to maintain open element stack if we don't have any selectors
+            // or none of them can match anymore.
+            _ => Ok(self.get_capture_flags()),
         }
     }
 
     fn handle_end_tag(&mut self, local_name: LocalName) -> TokenCaptureFlags {
+        if let Some(ref mut validator) = self.validator {
+            validator.handle_end_tag(&local_name);
+        }
+
+        if self.passthrough_requested {
+            return TokenCaptureFlags::empty();
+        } else if self.optimizations.matching_stopped {
+            return self.get_capture_flags();
+        }
+
+        if self.shadow_root_depth > 0 {
+            if local_name == Tag::Template {
+                self.shadow_root_depth -= 1;
+            }
+
+            // NOTE: the end tag of the shadow root's `<template>` element itself
+            // needs to be processed by the VM.
+            if self.shadow_root_depth > 0 {
+                return self.get_capture_flags();
+            }
+        }
+
+        let is_head = local_name == Tag::Head;
+
+        // NOTE: once `<head>` is closed, the parser never puts elements into it
+        // again, so selectors that match only the content of `<head>` can't
+        // produce any matches after its end tag.
+        let stop_matching = self.optimizations.head_only_matching && is_head;
+
         if let Some(ref mut vm) = self.selector_matching_vm {
-            let handlers_dispatcher = Rc::clone(&self.handlers_dispatcher);
+            let handlers_dispatcher = &mut self.handlers_dispatcher;
+            let implicitly_closed_elements = &mut self.implicitly_closed_elements;
+            let mut is_first = true;
 
-            vm.exec_for_end_tag(local_name, move |elem_desc| {
-                handlers_dispatcher.borrow_mut().stop_matching(elem_desc);
+            // NOTE: the first popped element is the one that is closed by the end tag,
+            // the rest are its descendants that haven't been closed explicitly.
+            vm.exec_for_end_tag(local_name, |elem_desc| {
+                if is_first {
+                    handlers_dispatcher.stop_matching(elem_desc);
+                    is_first = false;
+                } else {
+                    implicitly_closed_elements.push(elem_desc);
+                }
             });
+
+            while let Some(mut elem_desc) = self.implicitly_closed_elements.pop() {
+                self.handlers_dispatcher.close_implicitly(&mut elem_desc);
+                self.handlers_dispatcher.stop_matching(elem_desc);
+            }
         }
 
+        if stop_matching {
+            self.optimizations.matching_stopped = true;
+        }
+
+        if self.cutoff_at_head_end && is_head {
+            self.passthrough_requested = true;
+
+            // NOTE: the end tag itself still needs to be captured
+            // if there are end tag handlers for `<head>`.
+            return self.get_capture_flags() & TokenCaptureFlags::NEXT_END_TAG;
+        }
+
+        self.get_capture_flags()
+    }
+
+    #[inline]
+    fn handle_spilled_start_tag(&mut self) -> TokenCaptureFlags {
+        self.handlers_dispatcher.skip_start_tag();
+
         self.get_capture_flags()
     }
 
     #[inline]
     fn handle_token(&mut self, token: &mut Token) -> Result<(), RewritingError> {
-        let current_element_data = self
-            .selector_matching_vm
-            .as_mut()
-            .and_then(SelectorMatchingVm::current_element_data_mut);
+        let (current_element_data, open_elements_path) = match self.selector_matching_vm {
+            Some(ref mut vm) => {
+                let (current_element_data, path) = vm.current_element_data_mut_with_path();
+
+                (current_element_data, Some(path))
+            }
+            None => (None, None),
+        };
 
         self.handlers_dispatcher
-            .borrow_mut()
-            .handle_token(token, current_element_data)
+            .handle_token(token, current_element_data, open_elements_path)
             .map_err(RewritingError::ContentHandlerError)
     }
 
     fn handle_end(&mut self, document_end: &mut DocumentEnd) -> Result<(), RewritingError> {
-        self.handlers_dispatcher
-            .borrow_mut()
+        let handlers_dispatcher = &mut self.handlers_dispatcher;
+
+        // NOTE: elements that are still open at the end of the document are closed implicitly.
+        if !self.passthrough_requested && !self.optimizations.matching_stopped {
+            if let Some(ref mut vm) = self.selector_matching_vm {
+                vm.exec_for_end(|mut elem_desc| {
+                    handlers_dispatcher.close_implicitly(&mut elem_desc);
+                });
+            }
+        }
+
+        handlers_dispatcher
             .handle_end(document_end)
             .map_err(RewritingError::ContentHandlerError)
     }
@@ -142,7 +479,28 @@ impl TransformController for HtmlRewriteController<'_> {
     fn should_emit_content(&self) -> bool {
         !self
             .handlers_dispatcher
-            .borrow()
             .has_matched_elements_with_removed_content()
     }
+
+    #[inline]
+    fn should_pass_through(&self) -> bool {
+        self.passthrough_requested
+            || (self.deadline_policy == DeadlinePolicy::Passthrough && self.is_past_deadline())
+    }
+
+    fn reset(&mut self) {
+        if let Some(ref mut vm) = self.selector_matching_vm {
+            vm.reset();
+        }
+
+        if let Some(ref mut validator) = self.validator {
+            validator.reset();
+        }
+
+        self.handlers_dispatcher.reset();
+        self.optimizations.matching_stopped = false;
+        self.passthrough_requested = false;
+        self.shadow_root_depth = 0;
+        self.implicitly_closed_elements.clear();
+    }
 }
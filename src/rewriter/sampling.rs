@@ -0,0 +1,198 @@
+use super::settings::HandlerResult;
+use crate::rewritable_units::Element;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+/// Decides which of the matched elements are passed to a sampled element content handler.
+///
+/// The sampler uses a small non-cryptographic pseudorandom number generator. Samplers created
+/// with the same seed make the same sequence of decisions, so sampled handlers behave
+/// deterministically in tests.
+///
+/// # Example
+/// ```
+/// use lol_html::{element, rewrite_str, RewriteStrSettings, Sampler};
+///
+/// let mut count = 0;
+///
+/// rewrite_str(
+///     &"<a href='#'></a>".repeat(1000),
+///     RewriteStrSettings {
+///         element_content_handlers: vec![element!(
+///             "a[href]",
+///             Sampler::with_seed(0.1, 42).wrap(|_| {
+///                 count += 1;
+///
+///                 Ok(())
+///             })
+///         )],
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert!(count > 50 && count < 150);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Sampler {
+    rate: f64,
+    state: u64,
+}
+
+impl Sampler {
+    /// Creates a sampler that selects the given `rate` (from `0.0` to `1.0`) of the matched
+    /// elements. The sampler is seeded randomly.
+    #[inline]
+    pub fn new(rate: f64) -> Self {
+        let seed = RandomState::new().build_hasher().finish();
+
+        Sampler::with_seed(rate, seed)
+    }
+
+    /// Creates a sampler that selects the given `rate` (from `0.0` to `1.0`) of the matched
+    /// elements using the given `seed`.
+    #[inline]
+    pub fn with_seed(rate: f64, seed: u64) -> Self {
+        Sampler { rate, state: seed }
+    }
+
+    // NOTE: SplitMix64, it produces well distributed values even for the sequential seeds.
+    #[inline]
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+        let mut z = self.state;
+
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+
+        z ^ (z >> 31)
+    }
+
+    /// Returns `true` if the next matched element should be passed to the handler.
+    #[inline]
+    pub fn sample(&mut self) -> bool {
+        if self.rate >= 1.0 {
+            true
+        } else if self.rate <= 0.0 {
+            false
+        } else {
+            // NOTE: use the upper 53 bits to get a uniformly distributed `f64` in `[0, 1)`.
+            ((self.next_u64() >> 11) as f64) / ((1u64 << 53) as f64) < self.rate
+        }
+    }
+
+    /// Wraps the element content `handler`, so it's invoked only for the sampled elements. The
+    /// sampling decision is made after the element is matched, but before the handler is
+    /// invoked.
+    #[inline]
+    pub fn wrap<'h>(
+        mut self,
+        mut handler: impl FnMut(&mut Element) -> HandlerResult + 'h,
+    ) -> impl FnMut(&mut Element) -> HandlerResult + 'h {
+        move |el: &mut Element| {
+            if self.sample() {
+                handler(el)
+            } else {
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Wraps the element content `handler`, so it's invoked only for the given `rate` (from `0.0` to
+/// `1.0`) of the matched elements.
+///
+/// This is a shorthand for `Sampler::new(rate).wrap(handler)`. Use [`Sampler::with_seed`] to
+/// make the sampling deterministic.
+///
+/// # Example
+/// ```
+/// use lol_html::{element, rewrite_str, sampled, RewriteStrSettings};
+///
+/// let html = rewrite_str(
+///     r#"<a href="/foo">Foo</a>"#,
+///     RewriteStrSettings {
+///         element_content_handlers: vec![element!(
+///             "a[href]",
+///             sampled(0.01, |_| {
+///                 // Expensive analytics...
+///
+///                 Ok(())
+///             })
+///         )],
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(html, r#"<a href="/foo">Foo</a>"#);
+/// ```
+///
+/// [`Sampler::with_seed`]: struct.Sampler.html#method.with_seed
+#[inline]
+pub fn sampled<'h>(
+    rate: f64,
+    handler: impl FnMut(&mut Element) -> HandlerResult + 'h,
+) -> impl FnMut(&mut Element) -> HandlerResult + 'h {
+    Sampler::new(rate).wrap(handler)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rewrite_str, RewriteStrSettings};
+
+    fn sample(sampler: &mut Sampler, count: usize) -> Vec<bool> {
+        (0..count).map(|_| sampler.sample()).collect()
+    }
+
+    #[test]
+    fn deterministic_with_seed() {
+        let decisions = sample(&mut Sampler::with_seed(0.5, 7), 100);
+
+        assert_eq!(sample(&mut Sampler::with_seed(0.5, 7), 100), decisions);
+        assert_ne!(sample(&mut Sampler::with_seed(0.5, 8), 100), decisions);
+    }
+
+    #[test]
+    fn sampling_rate() {
+        let sampled_count = |rate| {
+            sample(&mut Sampler::with_seed(rate, 1), 10_000)
+                .into_iter()
+                .filter(|&s| s)
+                .count()
+        };
+
+        assert_eq!(sampled_count(0.0), 0);
+        assert_eq!(sampled_count(1.0), 10_000);
+
+        let count = sampled_count(0.25);
+
+        assert!(count > 2_250 && count < 2_750, "{}", count);
+    }
+
+    #[test]
+    fn sampled_handler() {
+        let mut invoked = 0;
+
+        let html = rewrite_str(
+            &"<b>1</b>".repeat(100),
+            RewriteStrSettings {
+                element_content_handlers: vec![element!(
+                    "b",
+                    Sampler::with_seed(0.5, 3).wrap(|el| {
+                        invoked += 1;
+                        el.set_tag_name("i")?;
+
+                        Ok(())
+                    })
+                )],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(html.matches("<i>").count(), invoked);
+        assert_eq!(html.matches("<b>").count(), 100 - invoked);
+        assert!(invoked > 0 && invoked < 100);
+    }
+}
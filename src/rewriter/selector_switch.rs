@@ -0,0 +1,129 @@
+use super::{HandlerTypes, LocalHandlerTypes};
+use crate::base::Shared;
+use std::fmt::{self, Debug, Formatter};
+
+/// Enables and disables the selectors of a rewriter from its content handlers.
+///
+/// The selector matching program can't be extended once the rewriting has started. So, the
+/// selectors that are needed only for some documents, e.g. the rules for a particular site
+/// generator, are registered upfront with [`ElementContentHandlers::disabled`] and enabled by a
+/// handler once it has seen enough of the document. The switch is shared by the handlers and
+/// the rewriter it's provided to with [`Settings::selector_switch`], so it can be cloned into
+/// any number of handlers. The switch of the settings with the `Send` handlers is `Send` as
+/// well.
+///
+/// The changes take effect from the next start tag in the input. Same as with
+/// [`HtmlRewriter::set_selector_enabled`], the elements a selector has already matched are
+/// processed by its handlers as usual.
+///
+/// # Example
+/// ```
+/// use lol_html::{element, ElementContentHandlers, HtmlRewriter, SelectorSwitch, Settings};
+///
+/// let mut output = vec![];
+///
+/// {
+///     let switch = SelectorSwitch::new();
+///     let handler_switch = switch.clone();
+///
+///     let mut rewriter = HtmlRewriter::try_new(
+///         Settings {
+///             element_content_handlers: vec![
+///                 element!(r#"meta[name="generator"][content^="Hugo"]"#, move |_| {
+///                     handler_switch.enable(1);
+///
+///                     Ok(())
+///                 }),
+///                 (
+///                     &"a.hugo-ref".parse().unwrap(),
+///                     ElementContentHandlers::default()
+///                         .element(|el| {
+///                             el.set_attribute("rel", "nofollow")?;
+///
+///                             Ok(())
+///                         })
+///                         .disabled(),
+///                 ),
+///             ],
+///             selector_switch: Some(switch),
+///             ..Settings::default()
+///         },
+///         |c: &[u8]| output.extend_from_slice(c)
+///     ).unwrap();
+///
+///     rewriter.write(br#"<a class="hugo-ref"></a>"#).unwrap();
+///     rewriter.write(br#"<meta name="generator" content="Hugo 0.80">"#).unwrap();
+///     rewriter.write(br#"<a class="hugo-ref"></a>"#).unwrap();
+///     rewriter.end().unwrap();
+/// }
+///
+/// assert_eq!(
+///     String::from_utf8(output).unwrap(),
+///     concat!(
+///         r#"<a class="hugo-ref"></a>"#,
+///         r#"<meta name="generator" content="Hugo 0.80">"#,
+///         r#"<a class="hugo-ref" rel="nofollow"></a>"#
+///     )
+/// );
+/// ```
+///
+/// [`ElementContentHandlers::disabled`]: struct.ElementContentHandlers.html#method.disabled
+/// [`Settings::selector_switch`]: struct.Settings.html#structfield.selector_switch
+/// [`HtmlRewriter::set_selector_enabled`]: struct.HtmlRewriter.html#method.set_selector_enabled
+pub struct SelectorSwitch<H: HandlerTypes = LocalHandlerTypes>(
+    Shared<Vec<(usize, bool)>, H::Sharing>,
+);
+
+impl<H: HandlerTypes> SelectorSwitch<H> {
+    /// Creates a new switch.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enables the selector with the given `index` in the [`element_content_handlers`] of the
+    /// rewriter. Indices of non-existent selectors are ignored.
+    ///
+    /// [`element_content_handlers`]: struct.Settings.html#structfield.element_content_handlers
+    #[inline]
+    pub fn enable(&self, index: usize) {
+        self.0.lock().push((index, true));
+    }
+
+    /// Disables the selector with the given `index` in the [`element_content_handlers`] of the
+    /// rewriter. Indices of non-existent selectors are ignored.
+    ///
+    /// [`element_content_handlers`]: struct.Settings.html#structfield.element_content_handlers
+    #[inline]
+    pub fn disable(&self, index: usize) {
+        self.0.lock().push((index, false));
+    }
+
+    /// Takes the changes requested since the last call, in the order they were requested.
+    #[inline]
+    pub(super) fn take_changes(&self) -> Vec<(usize, bool)> {
+        std::mem::take(&mut *self.0.lock())
+    }
+}
+
+impl<H: HandlerTypes> Default for SelectorSwitch<H> {
+    #[inline]
+    fn default() -> Self {
+        SelectorSwitch(Shared::default())
+    }
+}
+
+impl<H: HandlerTypes> Clone for SelectorSwitch<H> {
+    #[inline]
+    fn clone(&self) -> Self {
+        SelectorSwitch(self.0.clone())
+    }
+}
+
+impl<H: HandlerTypes> Debug for SelectorSwitch<H> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SelectorSwitch")
+            .field(&*self.0.lock())
+            .finish()
+    }
+}
@@ -1,44 +1,505 @@
-use crate::rewritable_units::{Comment, Doctype, DocumentEnd, Element, EndTag, TextChunk};
+use super::{InputFilter, OutputFilter, SelectorSwitch, ValidationDiagnostic};
+use crate::base::{LocalSharing, SendSharing, Sharing};
+use crate::html::TextType;
+use crate::rewritable_units::{
+    Comment, Doctype, DocumentEnd, Element, EndTag, InnerHtml, ProcessingInstruction, TextChunk,
+};
 use crate::selectors_vm::Selector;
 use std::error::Error;
+use std::time::Instant;
 
 pub(super) type HandlerResult = Result<(), Box<dyn Error>>;
 pub type DoctypeHandler<'h> = Box<dyn FnMut(&mut Doctype) -> HandlerResult + 'h>;
 pub type CommentHandler<'h> = Box<dyn FnMut(&mut Comment) -> HandlerResult + 'h>;
+pub type ProcessingInstructionHandler<'h> =
+    Box<dyn FnMut(&mut ProcessingInstruction) -> HandlerResult + 'h>;
 pub type TextHandler<'h> = Box<dyn FnMut(&mut TextChunk) -> HandlerResult + 'h>;
 pub type ElementHandler<'h> = Box<dyn FnMut(&mut Element) -> HandlerResult + 'h>;
+pub type AttributeChangeHandler<'h, H = LocalHandlerTypes> =
+    (String, <H as HandlerTypes>::ElementHandler<'h>);
 pub type EndTagHandler<'h> = Box<dyn FnOnce(&mut EndTag) -> HandlerResult + 'h>;
+pub type InnerHtmlHandler<'h> = Box<dyn FnOnce(&mut InnerHtml) -> HandlerResult + 'h>;
 pub type EndHandler<'h> = Box<dyn FnOnce(&mut DocumentEnd) -> HandlerResult + 'h>;
 
+pub type DoctypeHandlerSend<'h> = Box<dyn FnMut(&mut Doctype) -> HandlerResult + Send + 'h>;
+pub type CommentHandlerSend<'h> = Box<dyn FnMut(&mut Comment) -> HandlerResult + Send + 'h>;
+pub type ProcessingInstructionHandlerSend<'h> =
+    Box<dyn FnMut(&mut ProcessingInstruction) -> HandlerResult + Send + 'h>;
+pub type TextHandlerSend<'h> = Box<dyn FnMut(&mut TextChunk) -> HandlerResult + Send + 'h>;
+pub type ElementHandlerSend<'h> =
+    Box<dyn FnMut(&mut Element<'_, '_, SendHandlerTypes>) -> HandlerResult + Send + 'h>;
+pub type EndTagHandlerSend<'h> = Box<dyn FnOnce(&mut EndTag) -> HandlerResult + Send + 'h>;
+pub type InnerHtmlHandlerSend<'h> = Box<dyn FnOnce(&mut InnerHtml) -> HandlerResult + Send + 'h>;
+pub type EndHandlerSend<'h> = Box<dyn FnOnce(&mut DocumentEnd) -> HandlerResult + Send + 'h>;
+
+/// Specifies the types of the content handlers of [`HtmlRewriter`], which determine whether
+/// the rewriter can be sent to another thread.
+///
+/// The handlers of [`LocalHandlerTypes`], which are used by default, can capture anything,
+/// e.g. an `Rc<RefCell<T>>` shared by several handlers. The handlers of [`SendHandlerTypes`]
+/// are required to be `Send`, so the rewriter is `Send` as well if its output sink is. Such a
+/// rewriter can be returned from a function in a `Box`, stored in a struct field and held
+/// across the `.await` points of a future that is spawned on a multi-threaded executor.
+///
+/// The settings with the `Send` handlers are constructed with [`Settings::new_send`], and the
+/// handlers are registered the same way as for the default settings.
+///
+/// [`HtmlRewriter`]: struct.HtmlRewriter.html
+/// [`LocalHandlerTypes`]: struct.LocalHandlerTypes.html
+/// [`SendHandlerTypes`]: struct.SendHandlerTypes.html
+/// [`Settings::new_send`]: struct.Settings.html#method.new_send
+pub trait HandlerTypes: Sized + 'static {
+    /// The handler of the document type declarations.
+    type DoctypeHandler<'h>: FnMut(&mut Doctype) -> HandlerResult + 'h;
+    /// The handler of the HTML comments.
+    type CommentHandler<'h>: FnMut(&mut Comment) -> HandlerResult + 'h;
+    /// The handler of the processing instructions.
+    type ProcessingInstructionHandler<'h>: FnMut(&mut ProcessingInstruction) -> HandlerResult + 'h;
+    /// The handler of the text chunks.
+    type TextHandler<'h>: FnMut(&mut TextChunk) -> HandlerResult + 'h;
+    /// The handler of the elements.
+    type ElementHandler<'h>: FnMut(&mut Element<'_, '_, Self>) -> HandlerResult + 'h;
+    /// The handler of the end tag of an element.
+    type EndTagHandler<'h>: FnOnce(&mut EndTag) -> HandlerResult + 'h;
+    /// The handler of the inner HTML of an element.
+    type InnerHtmlHandler<'h>: FnOnce(&mut InnerHtml) -> HandlerResult + 'h;
+    /// The handler of the document end.
+    type EndHandler<'h>: FnOnce(&mut DocumentEnd) -> HandlerResult + 'h;
+    /// Shares the state between the parts of the rewriter, so it's `Send` only if the handlers
+    /// are `Send`.
+    #[doc(hidden)]
+    type Sharing: Sharing;
+
+    /// Converts the `Send` element handler into the element handler of these types.
+    fn new_element_handler<'h>(
+        handler: impl FnMut(&mut Element<'_, '_, Self>) -> HandlerResult + Send + 'h,
+    ) -> Self::ElementHandler<'h>;
+
+    /// Converts the `Send` text handler into the text handler of these types.
+    fn new_text_handler<'h>(
+        handler: impl FnMut(&mut TextChunk) -> HandlerResult + Send + 'h,
+    ) -> Self::TextHandler<'h>;
+
+    /// Converts the `Send` end tag handler into the end tag handler of these types.
+    fn new_end_tag_handler<'h>(
+        handler: impl FnOnce(&mut EndTag) -> HandlerResult + Send + 'h,
+    ) -> Self::EndTagHandler<'h>;
+
+    /// Converts the `Send` document end handler into the document end handler of these types.
+    fn new_end_handler<'h>(
+        handler: impl FnOnce(&mut DocumentEnd) -> HandlerResult + Send + 'h,
+    ) -> Self::EndHandler<'h>;
+}
+
+/// The [`HandlerTypes`] of the handlers that don't have to be `Send`.
+///
+/// [`HandlerTypes`]: trait.HandlerTypes.html
+#[derive(Debug)]
+pub struct LocalHandlerTypes(());
+
+impl HandlerTypes for LocalHandlerTypes {
+    type DoctypeHandler<'h> = DoctypeHandler<'h>;
+    type CommentHandler<'h> = CommentHandler<'h>;
+    type ProcessingInstructionHandler<'h> = ProcessingInstructionHandler<'h>;
+    type TextHandler<'h> = TextHandler<'h>;
+    type ElementHandler<'h> = ElementHandler<'h>;
+    type EndTagHandler<'h> = EndTagHandler<'h>;
+    type InnerHtmlHandler<'h> = InnerHtmlHandler<'h>;
+    type EndHandler<'h> = EndHandler<'h>;
+    type Sharing = LocalSharing;
+
+    #[inline]
+    fn new_element_handler<'h>(
+        handler: impl FnMut(&mut Element) -> HandlerResult + Send + 'h,
+    ) -> ElementHandler<'h> {
+        Box::new(handler)
+    }
+
+    #[inline]
+    fn new_text_handler<'h>(
+        handler: impl FnMut(&mut TextChunk) -> HandlerResult + Send + 'h,
+    ) -> TextHandler<'h> {
+        Box::new(handler)
+    }
+
+    #[inline]
+    fn new_end_tag_handler<'h>(
+        handler: impl FnOnce(&mut EndTag) -> HandlerResult + Send + 'h,
+    ) -> EndTagHandler<'h> {
+        Box::new(handler)
+    }
+
+    #[inline]
+    fn new_end_handler<'h>(
+        handler: impl FnOnce(&mut DocumentEnd) -> HandlerResult + Send + 'h,
+    ) -> EndHandler<'h> {
+        Box::new(handler)
+    }
+}
+
+/// The [`HandlerTypes`] of the handlers that are `Send`.
+///
+/// [`HandlerTypes`]: trait.HandlerTypes.html
+#[derive(Debug)]
+pub struct SendHandlerTypes(());
+
+impl HandlerTypes for SendHandlerTypes {
+    type DoctypeHandler<'h> = DoctypeHandlerSend<'h>;
+    type CommentHandler<'h> = CommentHandlerSend<'h>;
+    type ProcessingInstructionHandler<'h> = ProcessingInstructionHandlerSend<'h>;
+    type TextHandler<'h> = TextHandlerSend<'h>;
+    type ElementHandler<'h> = ElementHandlerSend<'h>;
+    type EndTagHandler<'h> = EndTagHandlerSend<'h>;
+    type InnerHtmlHandler<'h> = InnerHtmlHandlerSend<'h>;
+    type EndHandler<'h> = EndHandlerSend<'h>;
+    type Sharing = SendSharing;
+
+    #[inline]
+    fn new_element_handler<'h>(
+        handler: impl FnMut(&mut Element<'_, '_, Self>) -> HandlerResult + Send + 'h,
+    ) -> ElementHandlerSend<'h> {
+        Box::new(handler)
+    }
+
+    #[inline]
+    fn new_text_handler<'h>(
+        handler: impl FnMut(&mut TextChunk) -> HandlerResult + Send + 'h,
+    ) -> TextHandlerSend<'h> {
+        Box::new(handler)
+    }
+
+    #[inline]
+    fn new_end_tag_handler<'h>(
+        handler: impl FnOnce(&mut EndTag) -> HandlerResult + Send + 'h,
+    ) -> EndTagHandlerSend<'h> {
+        Box::new(handler)
+    }
+
+    #[inline]
+    fn new_end_handler<'h>(
+        handler: impl FnOnce(&mut DocumentEnd) -> HandlerResult + Send + 'h,
+    ) -> EndHandlerSend<'h> {
+        Box::new(handler)
+    }
+}
+
+/// Converts a closure into a boxed content handler of one of the [`HandlerTypes`].
+///
+/// The trait is implemented for the closures with the signature of the handler, which are
+/// also required to be `Send` for the [`SendHandlerTypes`].
+///
+/// [`HandlerTypes`]: trait.HandlerTypes.html
+/// [`SendHandlerTypes`]: struct.SendHandlerTypes.html
+pub trait IntoHandler<T> {
+    /// Boxes the handler.
+    fn into_handler(self) -> T;
+}
+
+macro_rules! impl_into_handler {
+    ($Fn:ident($Unit:ty), $Handler:ident, $HandlerSend:ident) => {
+        impl<'h, F> IntoHandler<$Handler<'h>> for F
+        where
+            F: $Fn(&mut $Unit) -> HandlerResult + 'h,
+        {
+            #[inline]
+            fn into_handler(self) -> $Handler<'h> {
+                Box::new(self)
+            }
+        }
+
+        impl<'h, F> IntoHandler<$HandlerSend<'h>> for F
+        where
+            F: $Fn(&mut $Unit) -> HandlerResult + Send + 'h,
+        {
+            #[inline]
+            fn into_handler(self) -> $HandlerSend<'h> {
+                Box::new(self)
+            }
+        }
+    };
+}
+
+impl_into_handler!(FnMut(Doctype), DoctypeHandler, DoctypeHandlerSend);
+impl_into_handler!(FnMut(Comment), CommentHandler, CommentHandlerSend);
+impl_into_handler!(
+    FnMut(ProcessingInstruction),
+    ProcessingInstructionHandler,
+    ProcessingInstructionHandlerSend
+);
+impl_into_handler!(FnMut(TextChunk), TextHandler, TextHandlerSend);
+impl_into_handler!(FnOnce(EndTag), EndTagHandler, EndTagHandlerSend);
+impl_into_handler!(FnOnce(InnerHtml), InnerHtmlHandler, InnerHtmlHandlerSend);
+impl_into_handler!(FnOnce(DocumentEnd), EndHandler, EndHandlerSend);
+
+impl<'h, F> IntoHandler<ElementHandler<'h>> for F
+where
+    F: FnMut(&mut Element) -> HandlerResult + 'h,
+{
+    #[inline]
+    fn into_handler(self) -> ElementHandler<'h> {
+        Box::new(self)
+    }
+}
+
+impl<'h, F> IntoHandler<ElementHandlerSend<'h>> for F
+where
+    F: FnMut(&mut Element<'_, '_, SendHandlerTypes>) -> HandlerResult + Send + 'h,
+{
+    #[inline]
+    fn into_handler(self) -> ElementHandlerSend<'h> {
+        Box::new(self)
+    }
+}
+
 /// Specifies element content handlers associated with a selector.
-#[derive(Default)]
-pub struct ElementContentHandlers<'h> {
-    pub(super) element: Option<ElementHandler<'h>>,
-    pub(super) comments: Option<CommentHandler<'h>>,
-    pub(super) text: Option<TextHandler<'h>>,
+pub struct ElementContentHandlers<'h, H: HandlerTypes = LocalHandlerTypes> {
+    pub(crate) element: Option<H::ElementHandler<'h>>,
+    pub(crate) comments: Option<H::CommentHandler<'h>>,
+    pub(crate) text: Option<H::TextHandler<'h>>,
+    pub(crate) text_chunk_policy: TextChunkPolicy,
+    pub(crate) attribute_change: Option<AttributeChangeHandler<'h, H>>,
+    pub(crate) match_limit: Option<usize>,
+    pub(crate) priority: i32,
+    pub(crate) disabled: bool,
+}
+
+impl<H: HandlerTypes> Default for ElementContentHandlers<'_, H> {
+    #[inline]
+    fn default() -> Self {
+        ElementContentHandlers {
+            element: None,
+            comments: None,
+            text: None,
+            text_chunk_policy: TextChunkPolicy::default(),
+            attribute_change: None,
+            match_limit: None,
+            priority: 0,
+            disabled: false,
+        }
+    }
+}
+
+impl<'h> ElementContentHandlers<'h, SendHandlerTypes> {
+    /// Creates the handlers that are `Send`, for the [`Settings::new_send`].
+    ///
+    /// [`Settings::new_send`]: struct.Settings.html#method.new_send
+    #[inline]
+    pub fn new_send() -> Self {
+        Self::default()
+    }
 }
 
-impl<'h> ElementContentHandlers<'h> {
+impl<'h, H: HandlerTypes> ElementContentHandlers<'h, H> {
     /// Sets a handler for elements matched by a selector.
     #[inline]
-    pub fn element(mut self, handler: impl FnMut(&mut Element) -> HandlerResult + 'h) -> Self {
-        self.element = Some(Box::new(handler));
+    pub fn element(
+        mut self,
+        handler: impl FnMut(&mut Element<'_, '_, H>) -> HandlerResult
+            + IntoHandler<H::ElementHandler<'h>>,
+    ) -> Self {
+        self.element = Some(handler.into_handler());
 
         self
     }
 
     /// Sets a handler for HTML comments in the inner content of elements matched by a selector.
     #[inline]
-    pub fn comments(mut self, handler: impl FnMut(&mut Comment) -> HandlerResult + 'h) -> Self {
-        self.comments = Some(Box::new(handler));
+    pub fn comments(
+        mut self,
+        handler: impl FnMut(&mut Comment) -> HandlerResult + IntoHandler<H::CommentHandler<'h>>,
+    ) -> Self {
+        self.comments = Some(handler.into_handler());
 
         self
     }
 
     /// Sets a handler for text chunks in the inner content of elements matched by a selector.
+    ///
+    /// The way the text is split into the chunks can be specified with [`text_chunk_policy`].
+    ///
+    /// [`text_chunk_policy`]: #method.text_chunk_policy
+    #[inline]
+    pub fn text(
+        mut self,
+        handler: impl FnMut(&mut TextChunk) -> HandlerResult + IntoHandler<H::TextHandler<'h>>,
+    ) -> Self {
+        self.text = Some(handler.into_handler());
+
+        self
+    }
+
+    /// Sets the [`TextChunkPolicy`] of the [`text`] handler.
+    ///
+    /// # Example
+    /// ```
+    /// use lol_html::html_content::ContentType;
+    /// use lol_html::{ElementContentHandlers, HtmlRewriter, Settings, TextChunkPolicy};
+    ///
+    /// let mut output = vec![];
+    ///
+    /// {
+    ///     let mut rewriter = HtmlRewriter::try_new(
+    ///         Settings {
+    ///             element_content_handlers: vec![(
+    ///                 &"p".parse().unwrap(),
+    ///                 ElementContentHandlers::default()
+    ///                     .text(|t| {
+    ///                         let text = t.as_str().replace("world", "everyone");
+    ///
+    ///                         t.replace(&text, ContentType::Html);
+    ///
+    ///                         Ok(())
+    ///                     })
+    ///                     .text_chunk_policy(TextChunkPolicy::Coalesced),
+    ///             )],
+    ///             ..Settings::default()
+    ///         },
+    ///         |c: &[u8]| output.extend_from_slice(c),
+    ///     ).unwrap();
+    ///
+    ///     rewriter.write(b"<p>Hello wo").unwrap();
+    ///     rewriter.write(b"rld</p>").unwrap();
+    ///     rewriter.end().unwrap();
+    /// }
+    ///
+    /// assert_eq!(String::from_utf8(output).unwrap(), "<p>Hello everyone</p>");
+    /// ```
+    ///
+    /// [`TextChunkPolicy`]: enum.TextChunkPolicy.html
+    /// [`text`]: #method.text
+    #[inline]
+    pub fn text_chunk_policy(mut self, policy: TextChunkPolicy) -> Self {
+        self.text_chunk_policy = policy;
+
+        self
+    }
+
+    /// Sets a handler for elements matched by a selector which is invoked only if the attribute
+    /// with the `name` has been set or removed by one of the element handlers.
+    ///
+    /// Attribute change handlers are invoked after all the element handlers of the element, so
+    /// dependent rules don't need to rely on the order in which handlers were registered.
+    ///
+    /// # Example
+    /// ```
+    /// use lol_html::{element, rewrite_str, ElementContentHandlers, RewriteStrSettings};
+    ///
+    /// let html = rewrite_str(
+    ///     r#"<img src="/a.png" srcset="/a.png 1x"><img src="/b.png" srcset="/b.png 1x">"#,
+    ///     RewriteStrSettings {
+    ///         element_content_handlers: vec![
+    ///             (
+    ///                 &"img[srcset]".parse().unwrap(),
+    ///                 ElementContentHandlers::default().attribute_change("src", |el| {
+    ///                     el.remove_attribute("srcset");
+    ///
+    ///                     Ok(())
+    ///                 }),
+    ///             ),
+    ///             element!(r#"img[src="/a.png"]"#, |el| {
+    ///                 el.set_attribute("src", "/c.png").unwrap();
+    ///
+    ///                 Ok(())
+    ///             }),
+    ///         ],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(html, r#"<img src="/c.png"><img src="/b.png" srcset="/b.png 1x">"#);
+    /// ```
+    #[inline]
+    pub fn attribute_change(
+        mut self,
+        name: &str,
+        handler: impl FnMut(&mut Element<'_, '_, H>) -> HandlerResult
+            + IntoHandler<H::ElementHandler<'h>>,
+    ) -> Self {
+        self.attribute_change = Some((name.to_ascii_lowercase(), handler.into_handler()));
+
+        self
+    }
+
+    /// Limits the handlers to the first `limit` elements matched by a selector in the document.
+    ///
+    /// Match counting is performed by the selector matching engine, so once the limit is reached
+    /// the rewriter stops spending time on matching the selector.
+    ///
+    /// ### Hint
+    ///
+    /// [`element_limited`] convenience macro can be used to construct a limited element handler.
+    ///
+    /// [`element_limited`]: macro.element_limited.html
+    #[inline]
+    pub fn match_limit(mut self, limit: usize) -> Self {
+        self.match_limit = Some(limit);
+
+        self
+    }
+
+    /// Registers the handlers with the selector disabled, so the selector doesn't match any
+    /// elements until it's enabled with a [`SelectorSwitch`] or
+    /// [`HtmlRewriter::set_selector_enabled`].
+    ///
+    /// The selector is compiled along with the rest of the selectors, so it can be enabled in
+    /// the middle of a document.
+    ///
+    /// [`SelectorSwitch`]: struct.SelectorSwitch.html
+    /// [`HtmlRewriter::set_selector_enabled`]: struct.HtmlRewriter.html#method.set_selector_enabled
+    #[inline]
+    pub fn disabled(mut self) -> Self {
+        self.disabled = true;
+
+        self
+    }
+
+    /// Sets the priority of the handlers, `0` by default.
+    ///
+    /// # Invocation order
+    ///
+    /// For a single token the handlers of the same kind (e.g. text handlers of the matched
+    /// elements and document-level text handlers) are invoked from the highest priority to the
+    /// lowest. Handlers with equal priority are invoked in the order they were registered, with
+    /// all the [`element_content_handlers`] registered before the [`document_content_handlers`].
+    ///
+    /// For a start tag all the element handlers are invoked before the [attribute change
+    /// handlers], and the priority only orders handlers within each of the groups.
+    ///
+    /// # Example
+    /// ```
+    /// use lol_html::{rewrite_str, text, DocumentContentHandlers, RewriteStrSettings};
+    /// use std::cell::RefCell;
+    ///
+    /// let order = RefCell::new(vec![]);
+    ///
+    /// rewrite_str(
+    ///     r#"<p>foo</p>"#,
+    ///     RewriteStrSettings {
+    ///         element_content_handlers: vec![text!("p", |_| {
+    ///             order.borrow_mut().push("injector");
+    ///
+    ///             Ok(())
+    ///         })],
+    ///         document_content_handlers: vec![DocumentContentHandlers::default()
+    ///             .text(|_| {
+    ///                 order.borrow_mut().push("sanitizer");
+    ///
+    ///                 Ok(())
+    ///             })
+    ///             .priority(1)],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(order.borrow()[..2], ["sanitizer", "injector"]);
+    /// ```
+    ///
+    /// [`element_content_handlers`]: struct.Settings.html#structfield.element_content_handlers
+    /// [`document_content_handlers`]: struct.Settings.html#structfield.document_content_handlers
+    /// [attribute change handlers]: #method.attribute_change
     #[inline]
-    pub fn text(mut self, handler: impl FnMut(&mut TextChunk) -> HandlerResult + 'h) -> Self {
-        self.text = Some(Box::new(handler));
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
 
         self
     }
@@ -59,50 +520,215 @@ impl<'h> ElementContentHandlers<'h> {
 /// <!-- I can be captured with a selector -->
 /// </html>
 /// ```
-#[derive(Default)]
-pub struct DocumentContentHandlers<'h> {
-    pub(super) doctype: Option<DoctypeHandler<'h>>,
-    pub(super) comments: Option<CommentHandler<'h>>,
-    pub(super) text: Option<TextHandler<'h>>,
-    pub(super) end: Option<EndHandler<'h>>,
+pub struct DocumentContentHandlers<'h, H: HandlerTypes = LocalHandlerTypes> {
+    pub(crate) doctype: Option<H::DoctypeHandler<'h>>,
+    pub(crate) comments: Option<H::CommentHandler<'h>>,
+    pub(crate) processing_instructions: Option<H::ProcessingInstructionHandler<'h>>,
+    pub(crate) text: Option<H::TextHandler<'h>>,
+    pub(crate) text_types: Option<Vec<TextType>>,
+    pub(crate) text_chunk_policy: TextChunkPolicy,
+    pub(crate) end: Option<H::EndHandler<'h>>,
+    pub(crate) priority: i32,
+}
+
+impl<H: HandlerTypes> Default for DocumentContentHandlers<'_, H> {
+    #[inline]
+    fn default() -> Self {
+        DocumentContentHandlers {
+            doctype: None,
+            comments: None,
+            processing_instructions: None,
+            text: None,
+            text_types: None,
+            text_chunk_policy: TextChunkPolicy::default(),
+            end: None,
+            priority: 0,
+        }
+    }
+}
+
+impl<'h> DocumentContentHandlers<'h, SendHandlerTypes> {
+    /// Creates the handlers that are `Send`, for the [`Settings::new_send`].
+    ///
+    /// [`Settings::new_send`]: struct.Settings.html#method.new_send
+    #[inline]
+    pub fn new_send() -> Self {
+        Self::default()
+    }
 }
 
-impl<'h> DocumentContentHandlers<'h> {
+impl<'h, H: HandlerTypes> DocumentContentHandlers<'h, H> {
     /// Sets a handler for the [document type declaration].
     ///
     /// [document type declaration]: https://developer.mozilla.org/en-US/docs/Glossary/Doctype
     #[inline]
-    pub fn doctype(mut self, handler: impl FnMut(&mut Doctype) -> HandlerResult + 'h) -> Self {
-        self.doctype = Some(Box::new(handler));
+    pub fn doctype(
+        mut self,
+        handler: impl FnMut(&mut Doctype) -> HandlerResult + IntoHandler<H::DoctypeHandler<'h>>,
+    ) -> Self {
+        self.doctype = Some(handler.into_handler());
 
         self
     }
 
     /// Sets a handler for all HTML comments present in the input HTML markup.
     #[inline]
-    pub fn comments(mut self, handler: impl FnMut(&mut Comment) -> HandlerResult + 'h) -> Self {
-        self.comments = Some(Box::new(handler));
+    pub fn comments(
+        mut self,
+        handler: impl FnMut(&mut Comment) -> HandlerResult + IntoHandler<H::CommentHandler<'h>>,
+    ) -> Self {
+        self.comments = Some(handler.into_handler());
+
+        self
+    }
+
+    /// Sets a handler for all [processing instructions] present in the input HTML markup, e.g.
+    /// for the XML declaration.
+    ///
+    /// While the handler is registered, processing instructions are not passed to the comment
+    /// handlers as bogus comments.
+    ///
+    /// [processing instructions]: html_content/struct.ProcessingInstruction.html
+    #[inline]
+    pub fn processing_instructions(
+        mut self,
+        handler: impl FnMut(&mut ProcessingInstruction) -> HandlerResult
+            + IntoHandler<H::ProcessingInstructionHandler<'h>>,
+    ) -> Self {
+        self.processing_instructions = Some(handler.into_handler());
 
         self
     }
 
     /// Sets a handler for all text chunks present in the input HTML markup.
+    ///
+    /// The text handler can be restricted to certain types of text with [`text_types`], and the
+    /// way the text is split into the chunks can be specified with [`text_chunk_policy`].
+    ///
+    /// [`text_types`]: #method.text_types
+    /// [`text_chunk_policy`]: #method.text_chunk_policy
     #[inline]
-    pub fn text(mut self, handler: impl FnMut(&mut TextChunk) -> HandlerResult + 'h) -> Self {
-        self.text = Some(Box::new(handler));
+    pub fn text(
+        mut self,
+        handler: impl FnMut(&mut TextChunk) -> HandlerResult + IntoHandler<H::TextHandler<'h>>,
+    ) -> Self {
+        self.text = Some(handler.into_handler());
+
+        self
+    }
+
+    /// Restricts the [`text`] handler to the text chunks of the given types, so the handler is
+    /// not invoked for the rest of the text.
+    ///
+    /// E.g. the content of the `<script>` and `<style>` elements, which is parsed as
+    /// [`TextType::ScriptData`] and [`TextType::RawText`] respectively, can be excluded from the
+    /// processing, so the handler doesn't accidentally corrupt the scripts and stylesheets in the
+    /// document. The text of particular elements can be handled with the [`text!`] handlers
+    /// and CSS selectors instead.
+    ///
+    /// # Example
+    /// ```
+    /// use lol_html::html_content::{ContentType, TextType};
+    /// use lol_html::{rewrite_str, DocumentContentHandlers, RewriteStrSettings};
+    ///
+    /// let html = rewrite_str(
+    ///     r#"<p>foo</p><script>foo()</script>"#,
+    ///     RewriteStrSettings {
+    ///         document_content_handlers: vec![DocumentContentHandlers::default()
+    ///             .text(|t| {
+    ///                 if t.as_str() == "foo" {
+    ///                     t.replace("bar", ContentType::Text);
+    ///                 }
+    ///
+    ///                 Ok(())
+    ///             })
+    ///             .text_types(&[TextType::Data, TextType::RCData])],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(html, r#"<p>bar</p><script>foo()</script>"#);
+    /// ```
+    ///
+    /// [`text`]: #method.text
+    /// [`TextType::ScriptData`]: html_content/enum.TextType.html#variant.ScriptData
+    /// [`TextType::RawText`]: html_content/enum.TextType.html#variant.RawText
+    /// [`text!`]: macro.text.html
+    #[inline]
+    pub fn text_types(mut self, text_types: &[TextType]) -> Self {
+        self.text_types = Some(text_types.to_vec());
+
+        self
+    }
+
+    /// Sets the [`TextChunkPolicy`] of the [`text`] handler.
+    ///
+    /// [`TextChunkPolicy`]: enum.TextChunkPolicy.html
+    /// [`text`]: #method.text
+    #[inline]
+    pub fn text_chunk_policy(mut self, policy: TextChunkPolicy) -> Self {
+        self.text_chunk_policy = policy;
+
+        self
+    }
+
+    /// Sets the priority of the handlers, `0` by default.
+    ///
+    /// Refer to [`ElementContentHandlers::priority`] documentation for the order in which the
+    /// handlers are invoked.
+    ///
+    /// [`ElementContentHandlers::priority`]: struct.ElementContentHandlers.html#method.priority
+    #[inline]
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
 
         self
     }
 
     /// Sets a handler for the document end, which is called after the last chunk is processed.
     #[inline]
-    pub fn end(mut self, handler: impl FnMut(&mut DocumentEnd) -> HandlerResult + 'h) -> Self {
-        self.end = Some(Box::new(handler));
+    pub fn end(
+        mut self,
+        handler: impl FnOnce(&mut DocumentEnd) -> HandlerResult + IntoHandler<H::EndHandler<'h>>,
+    ) -> Self {
+        self.end = Some(handler.into_handler());
 
         self
     }
 }
 
+/// Specifies how the text is delivered to a text handler.
+///
+/// [`ElementContentHandlers::text_chunk_policy`] and
+/// [`DocumentContentHandlers::text_chunk_policy`] set the policy of a particular handler.
+///
+/// [`ElementContentHandlers::text_chunk_policy`]: struct.ElementContentHandlers.html#method.text_chunk_policy
+/// [`DocumentContentHandlers::text_chunk_policy`]: struct.DocumentContentHandlers.html#method.text_chunk_policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextChunkPolicy {
+    /// The handler is invoked for each [`TextChunk`] as soon as it's parsed, so a text node can
+    /// be split into the chunks at arbitrary positions.
+    ///
+    /// [`TextChunk`]: html_content/struct.TextChunk.html
+    Chunked,
+    /// The consecutive chunks of a text node are buffered, and the handler is invoked once per
+    /// text node with a single chunk containing the whole text of the node. The chunk is the
+    /// last one in the text node, and its modifications are applied in place of the whole node.
+    ///
+    /// The buffered chunks are removed from the output before the handlers with lower priority
+    /// are invoked for them, and the text of the chunks removed by the handlers with higher
+    /// priority is not a part of the text node.
+    Coalesced,
+}
+
+impl Default for TextChunkPolicy {
+    #[inline]
+    fn default() -> Self {
+        TextChunkPolicy::Chunked
+    }
+}
+
+#[cfg(feature = "basic_selectors")]
 #[doc(hidden)]
 #[macro_export]
 macro_rules! __element_content_handler {
@@ -138,10 +764,50 @@ macro_rules! __element_content_handler {
 ///
 /// assert_eq!(html, r#"<span id="foo">Hello!</span>"#);
 /// ```
+#[cfg(feature = "basic_selectors")]
 #[macro_export(local_inner_macros)]
 macro_rules! element {
     ($selector:expr, $handler:expr) => {
-        __element_content_handler!($selector, element, $handler);
+        __element_content_handler!($selector, element, $handler)
+    };
+}
+
+/// A convenience macro to construct a rewriting handler for the first `N` elements that can be
+/// matched by the specified CSS selector.
+///
+/// # Example
+/// ```
+/// use lol_html::{rewrite_str, element_limited, RewriteStrSettings};
+///
+/// let html = rewrite_str(
+///     r#"<img src="1.png"><img src="2.png"><img src="3.png">"#,
+///     RewriteStrSettings {
+///         element_content_handlers: vec![
+///             element_limited!("img", 2, |el| {
+///                 el.set_attribute("loading", "eager").unwrap();
+///
+///                 Ok(())
+///             })
+///         ],
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(
+///     html,
+///     r#"<img src="1.png" loading="eager"><img src="2.png" loading="eager"><img src="3.png">"#
+/// );
+/// ```
+#[cfg(feature = "basic_selectors")]
+#[macro_export]
+macro_rules! element_limited {
+    ($selector:expr, $limit:expr, $handler:expr) => {
+        (
+            &$selector.parse::<$crate::Selector>().unwrap(),
+            $crate::ElementContentHandlers::default()
+                .element($handler)
+                .match_limit($limit),
+        )
     };
 }
 
@@ -171,10 +837,11 @@ macro_rules! element {
 ///
 /// assert_eq!(html, r#"<span>Hello world</span>"#);
 /// ```
+#[cfg(feature = "basic_selectors")]
 #[macro_export(local_inner_macros)]
 macro_rules! text {
     ($selector:expr, $handler:expr) => {
-        __element_content_handler!($selector, text, $handler);
+        __element_content_handler!($selector, text, $handler)
     };
 }
 
@@ -202,10 +869,11 @@ macro_rules! text {
 ///
 /// assert_eq!(html, r#"<span><!--Hello!--></span>"#);
 /// ```
+#[cfg(feature = "basic_selectors")]
 #[macro_export(local_inner_macros)]
 macro_rules! comments {
     ($selector:expr, $handler:expr) => {
-        __element_content_handler!($selector, comments, $handler);
+        __element_content_handler!($selector, comments, $handler)
     };
 }
 
@@ -243,7 +911,7 @@ macro_rules! __document_content_handler {
 #[macro_export(local_inner_macros)]
 macro_rules! doctype {
     ($handler:expr) => {
-        __document_content_handler!(doctype, $handler);
+        __document_content_handler!(doctype, $handler)
     };
 }
 
@@ -275,7 +943,7 @@ macro_rules! doctype {
 #[macro_export(local_inner_macros)]
 macro_rules! doc_text {
     ($handler:expr) => {
-        __document_content_handler!(text, $handler);
+        __document_content_handler!(text, $handler)
     };
 }
 
@@ -305,7 +973,7 @@ macro_rules! doc_text {
 #[macro_export(local_inner_macros)]
 macro_rules! doc_comments {
     ($handler:expr) => {
-        __document_content_handler!(comments, $handler);
+        __document_content_handler!(comments, $handler)
     };
 }
 
@@ -344,13 +1012,43 @@ macro_rules! doc_comments {
 #[macro_export(local_inner_macros)]
 macro_rules! end {
     ($handler:expr) => {
-        __document_content_handler!(end, $handler);
+        __document_content_handler!(end, $handler)
     };
 }
 
+/// Converts a closure into a boxed [`StreamingHandler`].
+///
+/// The closure receives a [`StreamingHandlerSink`] that the content is written to. Refer to
+/// [`StreamingHandler`] documentation for an example.
+///
+/// [`StreamingHandler`]: html_content/trait.StreamingHandler.html
+/// [`StreamingHandlerSink`]: html_content/struct.StreamingHandlerSink.html
+#[macro_export]
+macro_rules! streaming {
+    ($handler:expr) => {{
+        use ::std::error::Error;
+        use $crate::html_content::{StreamingHandler, StreamingHandlerSink};
+
+        // NOTE: the function pins the signature of the closure, so the types of its argument and
+        // of its result can be inferred.
+        #[inline(always)]
+        fn streaming_handler<F>(handler: F) -> Box<dyn StreamingHandler>
+        where
+            F: FnOnce(&mut StreamingHandlerSink) -> Result<(), Box<dyn Error>> + Send + 'static,
+        {
+            Box::new(handler)
+        }
+
+        streaming_handler($handler)
+    }};
+}
+
 /// Specifies the memory settings for [`HtmlRewriter`].
 ///
+/// Preallocation settings can be derived from a sample document with [`from_sample`].
+///
 /// [`HtmlRewriter`]: struct.HtmlRewriter.html
+/// [`from_sample`]: #method.from_sample
 // NOTE: exposed in C API as well, thus repr(C).
 #[repr(C)]
 pub struct MemorySettings {
@@ -392,36 +1090,538 @@ pub struct MemorySettings {
     /// [`write`]: struct.HtmlRewriter.html#method.write
     /// [`end`]: struct.HtmlRewriter.html#method.end
     pub max_allowed_memory_usage: usize,
-}
-
-impl Default for MemorySettings {
-    #[inline]
-    fn default() -> Self {
-        MemorySettings {
-            preallocated_parsing_buffer_size: 1024,
-            max_allowed_memory_usage: std::usize::MAX,
-        }
-    }
-}
 
-/// Specifies settings for [`HtmlRewriter`].
-///
-/// [`HtmlRewriter`]: struct.HtmlRewriter.html
-pub struct Settings<'h, 's> {
-    /// Specifies CSS selectors and rewriting handlers for elements and their inner content.
+    /// Specifies the number of attributes for which the space should be preallocated on
+    /// [`HtmlRewriter`] instantiation in the internal attribute buffer.
     ///
-    /// ### Hint
+    /// The buffer holds the positions of the attributes of the start tag that is currently being
+    /// parsed. So, reallocations can be avoided by setting the value to the largest number of
+    /// attributes that a start tag in the document can have.
     ///
-    /// [`element`], [`comments`] and [`text`] convenience macros can be used to construct a
-    /// `(Selector, ElementContentHandlers)` tuple.
+    /// ### Default
     ///
-    /// ### Example
-    /// ```
-    /// use lol_html::{ElementContentHandlers, Settings};
+    /// `256` when constructed with `MemorySettings::default()`.
     ///
-    /// let settings = Settings {
-    ///     element_content_handlers: vec! [
-    ///         (
+    /// [`HtmlRewriter`]: struct.HtmlRewriter.html
+    pub preallocated_attribute_buffer_capacity: usize,
+
+    /// Specifies the depth of the element stack of the selector matching engine that should
+    /// be preallocated on [`HtmlRewriter`] instantiation.
+    ///
+    /// The stack holds the information about the currently open elements and grows with the
+    /// nesting depth of the document. Note that the preallocated space is accounted towards
+    /// [`max_allowed_memory_usage`] only when it's occupied by the elements.
+    ///
+    /// ### Default
+    ///
+    /// `0` when constructed with `MemorySettings::default()`.
+    ///
+    /// [`HtmlRewriter`]: struct.HtmlRewriter.html
+    /// [`max_allowed_memory_usage`]: #structfield.max_allowed_memory_usage
+    pub preallocated_selector_matching_stack_depth: usize,
+
+    /// Specifies the number of bytes that should be preallocated on [`HtmlRewriter`] instantiation
+    /// for the internal output buffer.
+    ///
+    /// The buffer holds the bytes produced by the [`output_filter`] for a chunk of the output
+    /// before they are passed to the output sink, so it's not allocated at all if there is no
+    /// output filter. Reallocations can be avoided by setting the value to the largest size of
+    /// the filtered output chunk.
+    ///
+    /// ### Default
+    ///
+    /// `0` when constructed with `MemorySettings::default()`.
+    ///
+    /// [`HtmlRewriter`]: struct.HtmlRewriter.html
+    /// [`output_filter`]: struct.Settings.html#structfield.output_filter
+    pub preallocated_output_buffer_size: usize,
+
+    /// Sets a soft limit in bytes on memory consumption of a [`HtmlRewriter`] instance, or `0`
+    /// for no soft limit.
+    ///
+    /// Unlike [`max_allowed_memory_usage`], exceeding this limit doesn't make the rewriter error.
+    /// Instead, the rewriter degrades gracefully by giving up the optional features that consume
+    /// memory for the rest of its lifetime:
+    ///  * the cache of the selector matching results enabled with [`match_cache_capacity`] is
+    ///    dropped;
+    ///  * [`write_prefix`] doesn't memoize the prefixes in the [`PrefixCache`] anymore;
+    ///  * the output held back to resolve the `:has()` pseudo-classes is released, so the
+    ///    elements that haven't been resolved yet and all the following elements don't match
+    ///    them.
+    ///
+    /// The soft limit should be lower than [`max_allowed_memory_usage`] to have any effect.
+    ///
+    /// ### Default
+    ///
+    /// `0` when constructed with `MemorySettings::default()`.
+    ///
+    /// [`HtmlRewriter`]: struct.HtmlRewriter.html
+    /// [`max_allowed_memory_usage`]: #structfield.max_allowed_memory_usage
+    /// [`match_cache_capacity`]: struct.Settings.html#structfield.match_cache_capacity
+    /// [`write_prefix`]: struct.HtmlRewriter.html#method.write_prefix
+    /// [`PrefixCache`]: struct.PrefixCache.html
+    pub soft_memory_usage_limit: usize,
+}
+
+impl Default for MemorySettings {
+    #[inline]
+    fn default() -> Self {
+        MemorySettings {
+            preallocated_parsing_buffer_size: 1024,
+            max_allowed_memory_usage: std::usize::MAX,
+            preallocated_attribute_buffer_capacity: 256,
+            preallocated_selector_matching_stack_depth: 0,
+            preallocated_output_buffer_size: 0,
+            soft_memory_usage_limit: 0,
+        }
+    }
+}
+
+/// A handler that is invoked when the memory usage of [`HtmlRewriter`] rises above the
+/// `threshold`, before it reaches the [`max_allowed_memory_usage`].
+///
+/// The handler receives the memory usage in bytes. It helps to tune the limit for the real world
+/// documents before they start to fail with [`MemoryLimitExceededError`]. The handler is invoked
+/// once each time the usage crosses the threshold, after the input chunk that caused it is
+/// processed.
+///
+/// # Example
+/// ```
+/// use lol_html::{element, HtmlRewriter, MemorySettings, MemoryUsageWarning, Settings};
+///
+/// let mut warnings = vec![];
+///
+/// {
+///     let mut rewriter = HtmlRewriter::try_new(
+///         Settings {
+///             element_content_handlers: vec![element!("img", |_| Ok(()))],
+///             memory_settings: MemorySettings {
+///                 max_allowed_memory_usage: 1024,
+///                 preallocated_parsing_buffer_size: 0,
+///                 ..MemorySettings::default()
+///             },
+///             memory_usage_warning: Some(MemoryUsageWarning::new(512, |usage| {
+///                 warnings.push(usage);
+///             })),
+///             ..Settings::default()
+///         },
+///         |_: &[u8]| {},
+///     )
+///     .unwrap();
+///
+///     rewriter.write(format!("<img alt=\"{}", "a".repeat(600)).as_bytes()).unwrap();
+///     rewriter.write(b"\">").unwrap();
+///     rewriter.end().unwrap();
+/// }
+///
+/// assert_eq!(warnings.len(), 1);
+/// ```
+///
+/// [`HtmlRewriter`]: struct.HtmlRewriter.html
+/// [`max_allowed_memory_usage`]: struct.MemorySettings.html#structfield.max_allowed_memory_usage
+/// [`MemoryLimitExceededError`]: errors/struct.MemoryLimitExceededError.html
+pub struct MemoryUsageWarning<'h> {
+    /// The memory usage in bytes above which the handler is invoked.
+    pub threshold: usize,
+    /// The handler that receives the memory usage in bytes.
+    pub handler: Box<dyn FnMut(usize) + Send + 'h>,
+}
+
+impl<'h> MemoryUsageWarning<'h> {
+    /// Creates a warning with the `threshold` and the `handler`.
+    #[inline]
+    pub fn new(threshold: usize, handler: impl FnMut(usize) + Send + 'h) -> Self {
+        MemoryUsageWarning {
+            threshold,
+            handler: Box::new(handler),
+        }
+    }
+}
+
+/// Specifies which kinds of tokens should be streamed through verbatim ("spilled") if they can't
+/// be buffered without exceeding the [`max_allowed_memory_usage`].
+///
+/// Normally, tokens that span multiple input chunks are buffered, so they can be matched and
+/// passed to the content handlers. A gigantic token (e.g. a start tag with a multi-megabyte
+/// `data:` URI attribute or an enormous comment) can exceed the memory limit, in which case
+/// the rewriter errors. For the kinds of tokens enabled in these settings the rewriter gives up
+/// on matching and mutating the token instead, and streams it to the output as is.
+///
+/// Note that spilled tokens are invisible to the content handlers. A spilled start tag is still
+/// taken into account by the selector matching engine, so the element's content can be matched,
+/// but selectors that depend on the attributes of the spilled tag may produce inaccurate results.
+/// Text is never buffered as a whole, so it doesn't need to be spilled. End tags can't be
+/// spilled, since the end tag handlers of the matched elements rely on them.
+///
+/// Regardless of these settings, tokens that can't be observed by any of the registered handlers
+/// are always spilled instead of being buffered past the memory limit. Which tokens those are is
+/// determined from the compiled selectors: e.g. a comment is spilled if no comment handlers are
+/// active for it, and a start tag is spilled if no element handlers match it and none of the
+/// selectors need its attributes to decide on the match.
+///
+/// [`max_allowed_memory_usage`]: struct.MemorySettings.html#structfield.max_allowed_memory_usage
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SpillSettings {
+    /// Spill start tags.
+    pub start_tags: bool,
+    /// Spill comments, including processing instructions.
+    pub comments: bool,
+    /// Spill doctypes.
+    pub doctypes: bool,
+}
+
+impl SpillSettings {
+    /// Settings that enable spilling for all kinds of tokens.
+    #[inline]
+    pub fn all() -> Self {
+        SpillSettings {
+            start_tags: true,
+            comments: true,
+            doctypes: true,
+        }
+    }
+}
+
+/// Specifies the point in the document after which none of the content handlers can produce any
+/// matches.
+///
+/// Once the point is reached, the [`HtmlRewriter`] stops parsing the document and passes the rest
+/// of it through to the output as is. The content handlers don't receive any content that follows
+/// the point, even if they were activated before it (e.g. text handlers of an element that is
+/// still open). [Document end handlers] are still invoked at the end of the document.
+///
+/// [`HtmlRewriter`]: struct.HtmlRewriter.html
+/// [Document end handlers]: struct.DocumentContentHandlers.html#method.end
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchingCutoff {
+    /// The end of the `<head>` element, i.e. right after the `</head>` end tag or right before
+    /// the `<body>` start tag, whichever comes first.
+    HeadEnd,
+    /// The given number of bytes from the start of the input.
+    Bytes(usize),
+}
+
+/// Specifies what the [`HtmlRewriter`] does once the processing [`deadline`] is exceeded.
+///
+/// [`HtmlRewriter`]: struct.HtmlRewriter.html
+/// [`deadline`]: struct.Settings.html#structfield.deadline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeadlinePolicy {
+    /// The rewriter stops parsing the document and passes the rest of it through to the output
+    /// as is, similarly to the [`MatchingCutoff`]. [Document end handlers] are still invoked at
+    /// the end of the document.
+    ///
+    /// [`MatchingCutoff`]: enum.MatchingCutoff.html
+    /// [Document end handlers]: struct.DocumentContentHandlers.html#method.end
+    Passthrough,
+    /// The rewriter fails with the [`RewritingError::DeadlineExceeded`] error.
+    ///
+    /// [`RewritingError::DeadlineExceeded`]: errors/enum.RewritingError.html#variant.DeadlineExceeded
+    Fail,
+}
+
+impl Default for DeadlinePolicy {
+    #[inline]
+    fn default() -> Self {
+        DeadlinePolicy::Passthrough
+    }
+}
+
+/// Specifies how the numeric character references are normalized by the
+/// [`EntityNormalization`].
+///
+/// [`EntityNormalization`]: struct.EntityNormalization.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumericCharRefs {
+    /// The references are left as is.
+    Preserve,
+    /// The references are converted to the decimal form, e.g. `&#x26;` to `&#38;`.
+    Decimal,
+    /// The references are converted to the hexadecimal form, e.g. `&#38;` to `&#x26;`.
+    Hexadecimal,
+}
+
+impl Default for NumericCharRefs {
+    #[inline]
+    fn default() -> Self {
+        NumericCharRefs::Preserve
+    }
+}
+
+/// Specifies the normalization of the character references in the text of the document.
+///
+/// The normalization is applied to the regular text and to the text of the `<title>` and
+/// `<textarea>` elements, i.e. to the [types of text] that can contain character references.
+/// It's performed after all the text handlers, so the text handlers receive the text as it is
+/// in the input, and the text chunks replaced or removed by the handlers are left intact.
+///
+/// # Example
+/// ```
+/// use lol_html::{EntityNormalization, HtmlRewriter, NumericCharRefs, Settings};
+///
+/// let mut output = vec![];
+///
+/// {
+///     let mut rewriter = HtmlRewriter::try_new(
+///         Settings {
+///             entity_normalization: Some(EntityNormalization {
+///                 escape_bare_ampersands: true,
+///                 numeric_char_refs: NumericCharRefs::Decimal,
+///             }),
+///             ..Settings::default()
+///         },
+///         |c: &[u8]| output.extend_from_slice(c)
+///     ).unwrap();
+///
+///     rewriter.write(b"<p>Fish & Chips &#x26; &amp; <a href='/?a=1&b=2'>").unwrap();
+///     rewriter.end().unwrap();
+/// }
+///
+/// assert_eq!(
+///     String::from_utf8(output).unwrap(),
+///     "<p>Fish &amp; Chips &#38; &amp; <a href='/?a=1&b=2'>"
+/// );
+/// ```
+///
+/// [types of text]: html_content/enum.TextType.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntityNormalization {
+    /// If set to `true`, the ampersands that can't start a character reference (i.e. that are
+    /// followed neither by `#` nor by an ASCII alphanumeric character) are replaced with `&amp;`.
+    ///
+    /// The ampersands followed by alphanumeric characters are left as is, since browsers treat
+    /// some of the named character references without the trailing `;` (e.g. `&copy`) as
+    /// references.
+    pub escape_bare_ampersands: bool,
+    /// Specifies how the numeric character references are normalized.
+    ///
+    /// Only the references terminated by `;` are normalized.
+    pub numeric_char_refs: NumericCharRefs,
+}
+
+impl Default for EntityNormalization {
+    #[inline]
+    fn default() -> Self {
+        EntityNormalization {
+            escape_bare_ampersands: true,
+            numeric_char_refs: NumericCharRefs::default(),
+        }
+    }
+}
+
+/// Specifies how the content inserted into the table elements outside of the table cells is
+/// handled.
+///
+/// Browsers move non-table content (e.g. text or `<div>` elements) that appears in `<table>`,
+/// `<thead>`, `<tbody>`, `<tfoot>` or `<tr>` elements outside of the cells right before the
+/// table (this is called [foster parenting]). So, for example, a banner appended to a `<table>`
+/// element ends up above the table rather than inside of it.
+///
+/// The policy is applied to the content inserted into such elements with
+/// [`Element::prepend`], [`Element::append`] and [`Element::set_inner_content`]. Only the top
+/// level of the inserted content is examined: the content of the table cells and other nested
+/// elements is not restricted.
+///
+/// ### Example
+/// ```
+/// use lol_html::html_content::ContentType;
+/// use lol_html::{element, HtmlRewriter, Settings, TableContentPolicy};
+///
+/// let mut output = vec![];
+///
+/// {
+///     let mut rewriter = HtmlRewriter::try_new(
+///         Settings {
+///             element_content_handlers: vec![element!("table", |el| {
+///                 el.append("<b>Sale!</b>", ContentType::Html);
+///
+///                 Ok(())
+///             })],
+///             table_content_policy: TableContentPolicy::Wrap,
+///             ..Settings::default()
+///         },
+///         |c: &[u8]| output.extend_from_slice(c)
+///     ).unwrap();
+///
+///     rewriter.write(b"<table><tr><td>Foo</td></tr></table>").unwrap();
+///     rewriter.end().unwrap();
+/// }
+///
+/// assert_eq!(
+///     String::from_utf8(output).unwrap(),
+///     "<table><tr><td>Foo</td></tr><tbody><tr><td><b>Sale!</b></td></tr></tbody></table>"
+/// );
+/// ```
+///
+/// [foster parenting]: https://html.spec.whatwg.org/multipage/parsing.html#foster-parent
+/// [`Element::prepend`]: html_content/struct.Element.html#method.prepend
+/// [`Element::append`]: html_content/struct.Element.html#method.append
+/// [`Element::set_inner_content`]: html_content/struct.Element.html#method.set_inner_content
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableContentPolicy {
+    /// The content is inserted as is.
+    Ignore,
+    /// The content is wrapped into the elements required by the table content model, e.g. into
+    /// `<tr><td>` for the content inserted into a `<tbody>` element.
+    Wrap,
+    /// The content is not inserted and the content handler fails with the
+    /// [`ContentModelError::ForeignTableContent`] error.
+    ///
+    /// [`ContentModelError::ForeignTableContent`]: errors/enum.ContentModelError.html#variant.ForeignTableContent
+    Reject,
+}
+
+impl Default for TableContentPolicy {
+    #[inline]
+    fn default() -> Self {
+        TableContentPolicy::Ignore
+    }
+}
+
+/// Specifies how the content inserted into the `<select>` and `<optgroup>` elements is handled.
+///
+/// The HTML parser ignores the tags of all the elements in `<select>` except `<option>`,
+/// `<optgroup>`, `<hr>`, `<script>` and `<template>`, and some of the ignored tags (e.g.
+/// `<input>`) close the `<select>` element altogether. So, markup naively inserted into a form
+/// control can silently break it.
+///
+/// The policy is applied to the content inserted into such elements with
+/// [`Element::prepend`], [`Element::append`] and [`Element::set_inner_content`]. Unlike
+/// [`TableContentPolicy`], the policy examines all the tags of the inserted content, including
+/// the nested ones, since the parser ignores them regardless of the nesting level. Text content
+/// is always allowed.
+///
+/// ### Example
+/// ```
+/// use lol_html::html_content::ContentType;
+/// use lol_html::{element, HtmlRewriter, SelectContentPolicy, Settings};
+///
+/// let mut output = vec![];
+///
+/// {
+///     let mut rewriter = HtmlRewriter::try_new(
+///         Settings {
+///             element_content_handlers: vec![element!("select", |el| {
+///                 el.append("<option><b>Baz</b></option>", ContentType::Html);
+///
+///                 Ok(())
+///             })],
+///             select_content_policy: SelectContentPolicy::Strip,
+///             ..Settings::default()
+///         },
+///         |c: &[u8]| output.extend_from_slice(c)
+///     ).unwrap();
+///
+///     rewriter.write(b"<select><option>Foo</option></select>").unwrap();
+///     rewriter.end().unwrap();
+/// }
+///
+/// assert_eq!(
+///     String::from_utf8(output).unwrap(),
+///     "<select><option>Foo</option><option>Baz</option></select>"
+/// );
+/// ```
+///
+/// [`Element::prepend`]: html_content/struct.Element.html#method.prepend
+/// [`Element::append`]: html_content/struct.Element.html#method.append
+/// [`Element::set_inner_content`]: html_content/struct.Element.html#method.set_inner_content
+/// [`TableContentPolicy`]: enum.TableContentPolicy.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectContentPolicy {
+    /// The content is inserted as is.
+    Ignore,
+    /// The tags of the disallowed elements are removed from the content, while their text is
+    /// preserved.
+    Strip,
+    /// The content is not inserted and the content handler fails with the
+    /// [`ContentModelError::DisallowedSelectContent`] error.
+    ///
+    /// [`ContentModelError::DisallowedSelectContent`]: errors/enum.ContentModelError.html#variant.DisallowedSelectContent
+    Reject,
+}
+
+impl Default for SelectContentPolicy {
+    #[inline]
+    fn default() -> Self {
+        SelectContentPolicy::Ignore
+    }
+}
+
+/// Specifies how the obsolete elements with special parsing rules are handled.
+///
+/// The content of the `<plaintext>` element is parsed as text up to the end of the document and
+/// the content of the `<xmp>` element is parsed as text up to its end tag. The `<listing>`
+/// element is an obsolete synonym of `<pre>`, and the `<marquee>` element is an obsolete
+/// presentational element. Such elements are rarely used intentionally, so they are often
+/// either normalized or removed by sanitizing proxies.
+///
+/// The policy requires the `basic_selectors` feature and is ignored without it.
+///
+/// ### Example
+/// ```
+/// use lol_html::{HtmlRewriter, ObsoleteElementsPolicy, Settings};
+///
+/// let mut output = vec![];
+///
+/// {
+///     let mut rewriter = HtmlRewriter::try_new(
+///         Settings {
+///             obsolete_elements_policy: ObsoleteElementsPolicy::Normalize,
+///             ..Settings::default()
+///         },
+///         |c: &[u8]| output.extend_from_slice(c)
+///     ).unwrap();
+///
+///     rewriter.write(b"<marquee>Hi</marquee><plaintext><b>&amp;").unwrap();
+///     rewriter.end().unwrap();
+/// }
+///
+/// assert_eq!(
+///     String::from_utf8(output).unwrap(),
+///     "<div>Hi</div><pre>&lt;b&gt;&amp;amp;</pre>"
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObsoleteElementsPolicy {
+    /// The elements are parsed and left intact as specified by the HTML standard.
+    Preserve,
+    /// The `<plaintext>`, `<xmp>` and `<listing>` elements are renamed to `<pre>` and the
+    /// `<marquee>` element is renamed to `<div>`. The text content of the `<plaintext>` and
+    /// `<xmp>` elements is escaped, so it's still rendered as text.
+    Normalize,
+    /// The elements are removed along with their content.
+    Strip,
+}
+
+impl Default for ObsoleteElementsPolicy {
+    #[inline]
+    fn default() -> Self {
+        ObsoleteElementsPolicy::Preserve
+    }
+}
+
+/// Specifies settings for [`HtmlRewriter`].
+///
+/// [`HtmlRewriter`]: struct.HtmlRewriter.html
+pub struct Settings<'h, 's, H: HandlerTypes = LocalHandlerTypes> {
+    /// Specifies CSS selectors and rewriting handlers for elements and their inner content.
+    ///
+    /// ### Hint
+    ///
+    /// [`element`], [`comments`] and [`text`] convenience macros can be used to construct a
+    /// `(Selector, ElementContentHandlers)` tuple.
+    ///
+    /// Selectors can only be parsed with the `selector_matching` feature, which is enabled by
+    /// default, or with the `basic_selectors` feature, which supports only the basic syntax.
+    /// Without them the list is always empty.
+    ///
+    /// ### Example
+    /// ```
+    /// use lol_html::{ElementContentHandlers, Settings};
+    ///
+    /// let settings = Settings {
+    ///     element_content_handlers: vec! [
+    ///         (
     ///             &"div[foo]".parse().unwrap(),
     ///             ElementContentHandlers::default().element(|el| {
     ///                 // ...
@@ -445,7 +1645,7 @@ pub struct Settings<'h, 's> {
     /// [`element`]: macro.element.html
     /// [`comments`]: macro.comments.html
     /// [`text`]: macro.text.html
-    pub element_content_handlers: Vec<(&'s Selector, ElementContentHandlers<'h>)>,
+    pub element_content_handlers: Vec<(&'s Selector, ElementContentHandlers<'h, H>)>,
 
     /// Specifies rewriting handlers for the content without associating it to a particular
     /// CSS selector.
@@ -460,13 +1660,67 @@ pub struct Settings<'h, 's> {
     /// [`doctype`]: macro.doctype.html
     /// [`doc_comments`]: macro.doc_comments.html
     /// [`doc_text`]: macro.doc_text.html
-    pub document_content_handlers: Vec<DocumentContentHandlers<'h>>,
+    pub document_content_handlers: Vec<DocumentContentHandlers<'h, H>>,
+
+    /// Specifies CSS selectors and rewriting handlers for the elements in the markup of HTML
+    /// comments, e.g. of the downlevel-hidden conditional comments (`<!--[if IE]>...<![endif]-->`)
+    /// or of the markup commented out by templating systems.
+    ///
+    /// The comments that contain markup are re-tokenized, the handlers are invoked for the
+    /// elements in them and the rewritten markup is spliced back into the comments. The markup of
+    /// each comment is rewritten separately from the document and from the other comments, so
+    /// the selectors can't match the elements outside of the comment. The end tag handlers of the
+    /// elements that are left open at the end of the comment are not invoked. The comments whose
+    /// markup is not modified are left intact, as well as the comments that are split into
+    /// chunks when [`stream_comments`] is enabled.
+    ///
+    /// The rewriting fails if the handlers produce markup that can't be put into a comment, i.e.
+    /// that contains the `-->` sequence.
+    ///
+    /// ### Example
+    /// ```
+    /// use lol_html::{element, HtmlRewriter, Settings};
+    ///
+    /// let mut output = vec![];
+    ///
+    /// {
+    ///     let mut rewriter = HtmlRewriter::try_new(
+    ///         Settings {
+    ///             comment_content_handlers: vec![
+    ///                 element!("img[src]", |el| {
+    ///                     el.set_attribute("loading", "lazy")?;
+    ///
+    ///                     Ok(())
+    ///                 })
+    ///             ],
+    ///             ..Settings::default()
+    ///         },
+    ///         |c: &[u8]| output.extend_from_slice(c)
+    ///     ).unwrap();
+    ///
+    ///     rewriter.write(b"<!--[if IE]><img src=ie.png><![endif]-->").unwrap();
+    ///     rewriter.end().unwrap();
+    /// }
+    ///
+    /// assert_eq!(
+    ///     String::from_utf8(output).unwrap(),
+    ///     r#"<!--[if IE]><img src=ie.png loading="lazy"><![endif]-->"#
+    /// );
+    /// ```
+    ///
+    /// ### Default
+    ///
+    /// Empty vector when constructed with `Settings::default()`.
+    ///
+    /// [`stream_comments`]: #structfield.stream_comments
+    pub comment_content_handlers: Vec<(&'s Selector, ElementContentHandlers<'h, H>)>,
 
     /// Specifies the [character encoding] for the input and the output of the rewriter.
     ///
     /// Can be a [label] for any of the web-compatible encodings with an exception for `UTF-16LE`,
     /// `UTF-16BE`, `ISO-2022-JP` and `replacement` (these non-ASCII-compatible encodings
-    /// are not supported).
+    /// are not supported). Without the `all_encodings` feature, which is enabled by default,
+    /// only the labels of UTF-8 are accepted.
     ///
     /// [character encoding]: https://developer.mozilla.org/en-US/docs/Glossary/character_encoding
     /// [label]: https://encoding.spec.whatwg.org/#names-and-labels
@@ -479,6 +1733,613 @@ pub struct Settings<'h, 's> {
     /// Specifies the memory settings.
     pub memory_settings: MemorySettings,
 
+    /// Specifies the handler that is invoked when the memory usage approaches the limit.
+    ///
+    /// Refer to [`MemoryUsageWarning`] documentation for more information.
+    ///
+    /// ### Default
+    ///
+    /// `None` when constructed with `Settings::default()`.
+    ///
+    /// [`MemoryUsageWarning`]: struct.MemoryUsageWarning.html
+    pub memory_usage_warning: Option<MemoryUsageWarning<'h>>,
+
+    /// Specifies the filter that is applied to the raw input bytes before they are parsed.
+    ///
+    /// Refer to [`InputFilter`] documentation for more information.
+    ///
+    /// ### Default
+    ///
+    /// `None` when constructed with `Settings::default()`.
+    ///
+    /// [`InputFilter`]: trait.InputFilter.html
+    pub input_filter: Option<Box<dyn InputFilter + Send + 'h>>,
+
+    /// Specifies the filter that is applied to the output bytes before they are passed to the
+    /// output sink.
+    ///
+    /// Refer to [`OutputFilter`] documentation for more information.
+    ///
+    /// ### Default
+    ///
+    /// `None` when constructed with `Settings::default()`.
+    ///
+    /// [`OutputFilter`]: trait.OutputFilter.html
+    pub output_filter: Option<Box<dyn OutputFilter + Send + 'h>>,
+
+    /// Specifies which kinds of tokens should be streamed through verbatim instead of erroring
+    /// if they don't fit into the memory limit.
+    ///
+    /// Refer to [`SpillSettings`] documentation for more information.
+    ///
+    /// ### Default
+    ///
+    /// No tokens are spilled when constructed with `Settings::default()`.
+    ///
+    /// [`SpillSettings`]: struct.SpillSettings.html
+    pub spill_settings: SpillSettings,
+
+    /// Makes the rewriter deliver the comments to the comment handlers in chunks as they are
+    /// parsed, instead of buffering each comment as a whole.
+    ///
+    /// Useful for documents with huge comments, e.g. inlined source maps, which otherwise need
+    /// to fit into the [`max_allowed_memory_usage`] if there are comment handlers for them.
+    /// The comment is split at the boundaries of the input chunks, and the last chunk of the
+    /// comment can be determined with [`Comment::last_in_comment`].
+    ///
+    /// ### Example
+    /// ```
+    /// use lol_html::{doc_comments, HtmlRewriter, Settings};
+    ///
+    /// let mut chunks = vec![];
+    ///
+    /// {
+    ///     let mut rewriter = HtmlRewriter::try_new(
+    ///         Settings {
+    ///             document_content_handlers: vec![doc_comments!(|c| {
+    ///                 chunks.push((c.text(), c.last_in_comment()));
+    ///
+    ///                 Ok(())
+    ///             })],
+    ///             stream_comments: true,
+    ///             ..Settings::default()
+    ///         },
+    ///         |_: &[u8]| {}
+    ///     ).unwrap();
+    ///
+    ///     rewriter.write(b"<!-- Hello ").unwrap();
+    ///     rewriter.write(b"world -->").unwrap();
+    ///     rewriter.end().unwrap();
+    /// }
+    ///
+    /// assert_eq!(
+    ///     chunks,
+    ///     [(" Hello ".to_string(), false), ("world ".to_string(), true)]
+    /// );
+    /// ```
+    ///
+    /// ### Default
+    ///
+    /// `false` when constructed with `Settings::default()`.
+    ///
+    /// [`max_allowed_memory_usage`]: struct.MemorySettings.html#structfield.max_allowed_memory_usage
+    /// [`Comment::last_in_comment`]: html_content/struct.Comment.html#method.last_in_comment
+    pub stream_comments: bool,
+
+    /// Specifies the point in the document after which the rest of it is passed through to the
+    /// output without parsing.
+    ///
+    /// Useful for documents that need to be modified only in the beginning, e.g. for injections
+    /// into the `<head>` element of huge pages.
+    ///
+    /// Refer to [`MatchingCutoff`] documentation for more information.
+    ///
+    /// ### Default
+    ///
+    /// `None` when constructed with `Settings::default()`.
+    ///
+    /// [`MatchingCutoff`]: enum.MatchingCutoff.html
+    pub matching_cutoff: Option<MatchingCutoff>,
+
+    /// Specifies the point in time after which the rewriter stops matching and modifying the
+    /// document, bounding the worst-case latency added by rewriting of pathological documents.
+    ///
+    /// The deadline is checked on each start tag and, with [`DeadlinePolicy::Passthrough`],
+    /// after each written chunk. What happens once it's exceeded is controlled by
+    /// [`deadline_policy`].
+    ///
+    /// ### Example
+    /// ```
+    /// use lol_html::html_content::ContentType;
+    /// use lol_html::{element, HtmlRewriter, Settings};
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let mut output = vec![];
+    ///
+    /// {
+    ///     let mut rewriter = HtmlRewriter::try_new(
+    ///         Settings {
+    ///             element_content_handlers: vec![element!("p", |el| {
+    ///                 el.set_inner_content("Bar", ContentType::Text);
+    ///
+    ///                 Ok(())
+    ///             })],
+    ///             deadline: Some(Instant::now() + Duration::from_secs(1)),
+    ///             ..Settings::default()
+    ///         },
+    ///         |c: &[u8]| output.extend_from_slice(c)
+    ///     ).unwrap();
+    ///
+    ///     rewriter.write(b"<p>Foo</p>").unwrap();
+    ///     rewriter.end().unwrap();
+    /// }
+    ///
+    /// assert_eq!(String::from_utf8(output).unwrap(), "<p>Bar</p>");
+    /// ```
+    ///
+    /// ### Default
+    ///
+    /// `None` when constructed with `Settings::default()`.
+    ///
+    /// [`DeadlinePolicy::Passthrough`]: enum.DeadlinePolicy.html#variant.Passthrough
+    /// [`deadline_policy`]: #structfield.deadline_policy
+    pub deadline: Option<Instant>,
+
+    /// Specifies what the rewriter does once the [`deadline`] is exceeded.
+    ///
+    /// Refer to [`DeadlinePolicy`] documentation for more information.
+    ///
+    /// ### Default
+    ///
+    /// `DeadlinePolicy::Passthrough` when constructed with `Settings::default()`.
+    ///
+    /// [`deadline`]: #structfield.deadline
+    /// [`DeadlinePolicy`]: enum.DeadlinePolicy.html
+    pub deadline_policy: DeadlinePolicy,
+
+    /// Specifies the maximum number of input bytes processed by the rewriter.
+    ///
+    /// Useful for extraction of information from the beginning of large documents (e.g. of the
+    /// title or metadata): once the limit is reached, the rewriter finishes as if the end of the
+    /// input has been reached and the rest of the input is left untouched.
+    ///
+    /// Refer to [`HtmlRewriter::analyze`] documentation for more information.
+    ///
+    /// ### Default
+    ///
+    /// `None` when constructed with `Settings::default()`.
+    ///
+    /// [`HtmlRewriter::analyze`]: struct.HtmlRewriter.html#method.analyze
+    pub analysis_limit: Option<usize>,
+
+    /// If set to `true` the rewriter operates in the head-only mode: the document is fully parsed
+    /// and matched against the selectors only until the end of the `<head>` element (i.e. until
+    /// the `</head>` end tag or the `<body>` start tag), and the rest of the document is passed
+    /// through to the output as is.
+    ///
+    /// This is a shorthand for the [`MatchingCutoff::HeadEnd`] cutoff point which is meant for
+    /// injections into the `<head>` element (e.g. of scripts, stylesheets or `<meta>` tags).
+    /// Content handlers for the elements in the document body won't be invoked in this mode.
+    ///
+    /// ### Throughput
+    ///
+    /// The `<head>` element is processed at the regular rewriting speed. Once its end is reached,
+    /// each subsequent input chunk is handed to the output sink without being buffered, parsed
+    /// or copied, so the cost of the rest of the document is dominated by the output sink itself.
+    /// The chunk that contains the end of the `<head>` element is still parsed to its end.
+    /// For typical pages, where the `<head>` element takes just a small fraction of the document,
+    /// the rewriting throughput is expected to be an order of magnitude higher than for the full
+    /// document rewriting (see the "Head-only rewriting" benchmark group).
+    ///
+    /// ### Example
+    /// ```
+    /// use lol_html::html_content::ContentType;
+    /// use lol_html::{element, HtmlRewriter, Settings};
+    ///
+    /// let mut output = vec![];
+    ///
+    /// {
+    ///     let mut rewriter = HtmlRewriter::try_new(
+    ///         Settings {
+    ///             element_content_handlers: vec![
+    ///                 element!("head", |el| {
+    ///                     el.append(r#"<script src="/foo.js"></script>"#, ContentType::Html);
+    ///
+    ///                     Ok(())
+    ///                 }),
+    ///                 element!("p", |el| {
+    ///                     el.remove();
+    ///
+    ///                     Ok(())
+    ///                 })
+    ///             ],
+    ///             head_only: true,
+    ///             ..Settings::default()
+    ///         },
+    ///         |c: &[u8]| output.extend_from_slice(c)
+    ///     ).unwrap();
+    ///
+    ///     rewriter.write(b"<head><title>Foo</title></head>").unwrap();
+    ///     rewriter.write(b"<body><p>Bar</p></body>").unwrap();
+    ///
+    ///     assert!(rewriter.engaged_optimizations().passthrough);
+    ///
+    ///     rewriter.end().unwrap();
+    /// }
+    ///
+    /// assert_eq!(
+    ///     String::from_utf8(output).unwrap(),
+    ///     r#"<head><title>Foo</title><script src="/foo.js"></script></head><body><p>Bar</p></body>"#
+    /// );
+    /// ```
+    ///
+    /// ### Default
+    ///
+    /// `false` when constructed with `Settings::default()`.
+    ///
+    /// [`MatchingCutoff::HeadEnd`]: enum.MatchingCutoff.html#variant.HeadEnd
+    pub head_only: bool,
+
+    /// If set to `false` the selectors are not matched against the content of [declarative shadow
+    /// roots], i.e. of the `<template>` elements with the `shadowrootmode` attribute.
+    ///
+    /// Declarative shadow roots are emitted heavily by server-side rendering frameworks and
+    /// their content is encapsulated from the document in a browser, so selectors that target
+    /// the document usually shouldn't affect it. The `<template>` elements themselves are still
+    /// matched and [`Element::shadow_root_mode`] can be used to examine them.
+    ///
+    /// ### Default
+    ///
+    /// `true` when constructed with `Settings::default()`.
+    ///
+    /// [declarative shadow roots]: https://html.spec.whatwg.org/multipage/scripting.html#attr-template-shadowrootmode
+    /// [`Element::shadow_root_mode`]: html_content/struct.Element.html#method.shadow_root_mode
+    pub match_declarative_shadow_roots: bool,
+
+    /// Specifies how the content inserted into the table elements outside of the table cells is
+    /// handled.
+    ///
+    /// Refer to [`TableContentPolicy`] documentation for more information.
+    ///
+    /// ### Default
+    ///
+    /// `TableContentPolicy::Ignore` when constructed with `Settings::default()`.
+    ///
+    /// [`TableContentPolicy`]: enum.TableContentPolicy.html
+    pub table_content_policy: TableContentPolicy,
+
+    /// Specifies how the content inserted into the `<select>` and `<optgroup>` elements is
+    /// handled.
+    ///
+    /// Refer to [`SelectContentPolicy`] documentation for more information.
+    ///
+    /// ### Default
+    ///
+    /// `SelectContentPolicy::Ignore` when constructed with `Settings::default()`.
+    ///
+    /// [`SelectContentPolicy`]: enum.SelectContentPolicy.html
+    pub select_content_policy: SelectContentPolicy,
+
+    /// Specifies how the obsolete elements with special parsing rules (e.g. `<plaintext>`) are
+    /// handled.
+    ///
+    /// Refer to [`ObsoleteElementsPolicy`] documentation for more information.
+    ///
+    /// ### Default
+    ///
+    /// `ObsoleteElementsPolicy::Preserve` when constructed with `Settings::default()`.
+    ///
+    /// [`ObsoleteElementsPolicy`]: enum.ObsoleteElementsPolicy.html
+    pub obsolete_elements_policy: ObsoleteElementsPolicy,
+
+    /// If set to `true` the rewriter counts the matches of each of the selectors of the
+    /// [`element_content_handlers`].
+    ///
+    /// The counts can be obtained with [`HtmlRewriter::selector_match_counts`], e.g. after the
+    /// rewriter has been ended, to find the rules that never match and can be pruned.
+    ///
+    /// ### Default
+    ///
+    /// `false` when constructed with `Settings::default()`.
+    ///
+    /// [`element_content_handlers`]: #structfield.element_content_handlers
+    /// [`HtmlRewriter::selector_match_counts`]: struct.HtmlRewriter.html#method.selector_match_counts
+    pub count_selector_matches: bool,
+
+    /// If set to `true` the rewriter records the `id`s of the open elements, so they are
+    /// included in the paths returned by [`HtmlRewriter::current_path`] and
+    /// [`Element::current_path`], e.g. `html > body > div#main > ul`.
+    ///
+    /// Note that the ids can only be obtained from the attributes, so the rewriter parses
+    /// the attributes of all the start tags, which makes the rewriting slower.
+    ///
+    /// ### Default
+    ///
+    /// `false` when constructed with `Settings::default()`.
+    ///
+    /// [`HtmlRewriter::current_path`]: struct.HtmlRewriter.html#method.current_path
+    /// [`Element::current_path`]: html_content/struct.Element.html#method.current_path
+    pub track_element_ids: bool,
+
+    /// Specifies the switch that the content handlers can use to enable and disable the
+    /// selectors of the [`element_content_handlers`] during the rewriting.
+    ///
+    /// Refer to [`SelectorSwitch`] documentation for more information.
+    ///
+    /// ### Default
+    ///
+    /// `None` when constructed with `Settings::default()`.
+    ///
+    /// [`element_content_handlers`]: #structfield.element_content_handlers
+    /// [`SelectorSwitch`]: struct.SelectorSwitch.html
+    pub selector_switch: Option<SelectorSwitch<H>>,
+
+    /// Specifies the capacity of the selector match cache.
+    ///
+    /// Many documents repeat identical start tags thousands of times (e.g. `<td class="cell">`
+    /// in large tables). If the capacity is specified, the rewriter memoizes the results of the
+    /// attribute conditions of the selectors of the [`element_content_handlers`] for the given
+    /// number of the most recently seen distinct attribute sets, so the conditions are not
+    /// evaluated again for the repeated start tags. The conditions that depend on the ancestors
+    /// of the element, like `:lang()`, are always evaluated.
+    ///
+    /// ### Default
+    ///
+    /// `None` when constructed with `Settings::default()`.
+    ///
+    /// [`element_content_handlers`]: #structfield.element_content_handlers
+    pub match_cache_capacity: Option<usize>,
+
+    /// Specifies the capacity, in chunks, of the queues that connect the rewriter to
+    /// the parse-ahead thread. If the capacity is specified, the input is tokenized on
+    /// a separate thread, while the content handlers are invoked and the output is serialized
+    /// on the thread that writes to the rewriter.
+    ///
+    /// **Experimental.** Parsing ahead improves the throughput of CPU-bound rewriting on
+    /// multi-core machines, if the input is written in chunks that are large enough to outweigh
+    /// the cost of the synchronization. The parse-ahead thread always runs the full lexer, since
+    /// it doesn't know which content can be skipped by the content handlers, and the selectors
+    /// are still matched on the rewriter's thread. The output is the same as without the
+    /// parse-ahead thread.
+    ///
+    /// The input is accounted in the memory usage of the rewriter until its parsed part is
+    /// handled, including the chunks in the queues and the incomplete token buffered by
+    /// the parse-ahead thread. The tokens can't be spilled by the parse-ahead thread, so
+    /// the capacity can't be combined with the [`spill_settings`]. The parse-ahead thread is
+    /// not used if the [`matching_cutoff`] is specified in bytes.
+    ///
+    /// Requires the `parse_ahead` feature.
+    ///
+    /// ### Example
+    /// ```
+    /// use lol_html::{element, HtmlRewriter, Settings};
+    ///
+    /// let mut output = vec![];
+    ///
+    /// {
+    ///     let mut rewriter = HtmlRewriter::try_new(
+    ///         Settings {
+    ///             element_content_handlers: vec![element!("a[href]", |el| {
+    ///                 el.set_attribute("rel", "noopener")?;
+    ///
+    ///                 Ok(())
+    ///             })],
+    ///             parse_ahead_queue_capacity: Some(16),
+    ///             ..Settings::default()
+    ///         },
+    ///         |c: &[u8]| output.extend_from_slice(c)
+    ///     ).unwrap();
+    ///
+    ///     rewriter.write(b"<p><a href='/foo'>Foo</a>").unwrap();
+    ///     rewriter.write(b"<a href='/bar'>Bar</a></p>").unwrap();
+    ///     rewriter.end().unwrap();
+    /// }
+    ///
+    /// assert_eq!(
+    ///     String::from_utf8(output).unwrap(),
+    ///     r#"<p><a href='/foo' rel="noopener">Foo</a><a href='/bar' rel="noopener">Bar</a></p>"#
+    /// );
+    /// ```
+    ///
+    /// ### Default
+    ///
+    /// `None` when constructed with `Settings::default()`.
+    ///
+    /// [`spill_settings`]: #structfield.spill_settings
+    /// [`matching_cutoff`]: #structfield.matching_cutoff
+    #[cfg(feature = "parse_ahead")]
+    pub parse_ahead_queue_capacity: Option<usize>,
+
+    /// If set to `true` the `:has()` pseudo-classes in the selectors of the
+    /// [`element_content_handlers`] are resolved by looking ahead in the document.
+    ///
+    /// Whether an element satisfies `:has()` is known only once its descendant matches the
+    /// argument or the element's end tag is reached. So, the output of the rewriter is held back
+    /// starting from the start tag of the first unresolved candidate element. Once the candidate
+    /// is resolved, the content handlers are invoked and the output is flushed. The held back
+    /// content is accounted towards the [`max_allowed_memory_usage`], so a large candidate
+    /// element (e.g. `body:has(...)`) can make the rewriter fail with the
+    /// [`MemoryLimitExceeded`] error.
+    ///
+    /// The `:last-child`, `:last-of-type`, `:only-child` and `:only-of-type` pseudo-classes are
+    /// resolved the same way, the candidate element is held back until its following sibling
+    /// starts or its parent's end tag is reached.
+    ///
+    /// If set to `false`, the rewriter can't be constructed with the selectors that have these
+    /// pseudo-classes, it fails with the [`SelectorError::LookaheadRequired`] error instead.
+    ///
+    /// # Example
+    /// ```
+    /// use lol_html::{element, HtmlRewriter, Settings};
+    ///
+    /// let mut output = vec![];
+    ///
+    /// {
+    ///     let mut rewriter = HtmlRewriter::try_new(
+    ///         Settings {
+    ///             element_content_handlers: vec![
+    ///                 element!("div:has(> img.hero)", |el| {
+    ///                     el.set_attribute("class", "banner")?;
+    ///
+    ///                     Ok(())
+    ///                 })
+    ///             ],
+    ///             buffer_has_candidates: true,
+    ///             ..Settings::default()
+    ///         },
+    ///         |c: &[u8]| output.extend_from_slice(c)
+    ///     ).unwrap();
+    ///
+    ///     rewriter.write(b"<div><img class=hero></div>").unwrap();
+    ///     rewriter.write(b"<div><p><img class=hero></p></div>").unwrap();
+    ///     rewriter.end().unwrap();
+    /// }
+    ///
+    /// assert_eq!(
+    ///     String::from_utf8(output).unwrap(),
+    ///     concat!(
+    ///         r#"<div class="banner"><img class=hero></div>"#,
+    ///         r#"<div><p><img class=hero></p></div>"#
+    ///     )
+    /// );
+    /// ```
+    ///
+    /// ### Default
+    ///
+    /// `false` when constructed with `Settings::default()`.
+    ///
+    /// [`element_content_handlers`]: #structfield.element_content_handlers
+    /// [`max_allowed_memory_usage`]: struct.MemorySettings.html#structfield.max_allowed_memory_usage
+    /// [`MemoryLimitExceeded`]: errors/enum.RewritingError.html#variant.MemoryLimitExceeded
+    /// [`SelectorError::LookaheadRequired`]: errors/enum.SelectorError.html#variant.LookaheadRequired
+    pub buffer_has_candidates: bool,
+
+    /// Specifies the keys of the context the rewriter operates in (e.g. the device class of
+    /// the client or the experiments it's enrolled in).
+    ///
+    /// The `:ctx(key)` pseudo-class in the selectors of the [`element_content_handlers`] matches
+    /// any element if the `key` is in the context and doesn't match otherwise, so a single rule
+    /// set can contain the rules conditional on the request. The context is resolved once when
+    /// the rewriter is constructed, so such rules add no overhead to the matching.
+    ///
+    /// ### Example
+    /// ```
+    /// use lol_html::{element, HtmlRewriter, Settings};
+    ///
+    /// let mut output = vec![];
+    ///
+    /// {
+    ///     let mut rewriter = HtmlRewriter::try_new(
+    ///         Settings {
+    ///             element_content_handlers: vec![
+    ///                 element!(".sidebar:ctx(mobile)", |el| {
+    ///                     el.remove();
+    ///
+    ///                     Ok(())
+    ///                 }),
+    ///                 element!("img:ctx(lazy-images)", |el| {
+    ///                     el.set_attribute("loading", "lazy")?;
+    ///
+    ///                     Ok(())
+    ///                 })
+    ///             ],
+    ///             selector_context: vec!["mobile"],
+    ///             ..Settings::default()
+    ///         },
+    ///         |c: &[u8]| output.extend_from_slice(c)
+    ///     ).unwrap();
+    ///
+    ///     rewriter.write(b"<div class='sidebar'></div><img src='foo.png'>").unwrap();
+    ///     rewriter.end().unwrap();
+    /// }
+    ///
+    /// assert_eq!(String::from_utf8(output).unwrap(), "<img src='foo.png'>");
+    /// ```
+    ///
+    /// ### Default
+    ///
+    /// Empty when constructed with `Settings::default()`.
+    ///
+    /// [`element_content_handlers`]: #structfield.element_content_handlers
+    pub selector_context: Vec<&'s str>,
+
+    /// Specifies the normalization of the character references in the text of the document,
+    /// e.g. for the rewritten documents to satisfy strict validators.
+    ///
+    /// Refer to [`EntityNormalization`] documentation for more information.
+    ///
+    /// ### Default
+    ///
+    /// `None` when constructed with `Settings::default()`.
+    ///
+    /// [`EntityNormalization`]: struct.EntityNormalization.html
+    pub entity_normalization: Option<EntityNormalization>,
+
+    /// If set to `true` the rewriter produces the output in the canonical form, so the rewritten
+    /// documents can be compared with each other or with snapshots in tests regardless of the
+    /// formatting of the markup.
+    ///
+    /// The output is brought to the canonical form after all the content handlers have been
+    /// invoked:
+    ///  * Tag and attribute names are lowercased.
+    ///  * Attributes are sorted by name and only the first of the duplicate attributes is kept.
+    ///  * Attribute values are always enclosed in double quotes.
+    ///  * Runs of whitespace in text are collapsed into a single space, except for the text of
+    ///    the `<pre>` and `<listing>` elements and the text that is not parsed as regular text
+    ///    (e.g. the content of `<script>`, `<style>` or `<textarea>`).
+    ///
+    /// Comments, doctypes and the content inserted by the handlers are emitted as is.
+    ///
+    /// ### Hint
+    ///
+    /// [`canonicalize`] function can be used to canonicalize an HTML string.
+    ///
+    /// ### Default
+    ///
+    /// `false` when constructed with `Settings::default()`.
+    ///
+    /// [`canonicalize`]: fn.canonicalize.html
+    pub canonical_output: bool,
+
+    /// A handler that receives the diagnostics for the common content model violations in the
+    /// document, e.g. a `<li>` element outside of a list, so the rewriter can lint the documents
+    /// it rewrites.
+    ///
+    /// The checks are based on the same approximation of the tree builder that the rewriter uses
+    /// for selector matching, so they are limited to the violations that can be detected from
+    /// the tags themselves. The content of the foreign elements (`<svg>` and `<math>`) is not
+    /// checked. Refer to [`ValidationDiagnostic`] for the list of the violations.
+    ///
+    /// ### Example
+    /// ```
+    /// use lol_html::{HtmlRewriter, Settings, ValidationDiagnostic};
+    ///
+    /// let mut diagnostics = vec![];
+    ///
+    /// {
+    ///     let mut rewriter = HtmlRewriter::try_new(
+    ///         Settings {
+    ///             validation_handler: Some(Box::new(|diagnostic| diagnostics.push(diagnostic))),
+    ///             ..Settings::default()
+    ///         },
+    ///         |_: &[u8]| {},
+    ///     )
+    ///     .unwrap();
+    ///
+    ///     rewriter.write(b"<title>Foo</title><title>Bar</title>").unwrap();
+    ///     rewriter.end().unwrap();
+    /// }
+    ///
+    /// assert_eq!(diagnostics, [ValidationDiagnostic::DuplicateTitle]);
+    /// ```
+    ///
+    /// ### Default
+    ///
+    /// `None` when constructed with `Settings::default()`.
+    ///
+    /// [`ValidationDiagnostic`]: enum.ValidationDiagnostic.html
+    pub validation_handler: Option<Box<dyn FnMut(ValidationDiagnostic) + Send + 'h>>,
+
     /// If set to `true` the rewriter bails out if it encounters markup that drives the HTML parser
     /// into ambigious state.
     ///
@@ -517,11 +2378,96 @@ pub struct Settings<'h, 's> {
 impl Default for Settings<'_, '_> {
     #[inline]
     fn default() -> Self {
+        Self::new_with_handler_types()
+    }
+}
+
+impl Settings<'_, '_, SendHandlerTypes> {
+    /// Creates the default settings of a rewriter whose content handlers are `Send`.
+    ///
+    /// The rewriter constructed with these settings is `Send` if its output sink is, so it can
+    /// be moved to another thread or held across an `.await` point. The fields have the same
+    /// values as the ones of `Settings::default()`.
+    ///
+    /// ### Example
+    /// ```
+    /// use lol_html::{element, HtmlRewriter, Settings};
+    /// use std::sync::{Arc, Mutex};
+    /// use std::thread;
+    ///
+    /// let output = Arc::new(Mutex::new(vec![]));
+    /// let sink_output = Arc::clone(&output);
+    ///
+    /// let mut rewriter = HtmlRewriter::try_new(
+    ///     Settings {
+    ///         element_content_handlers: vec![element!("a[href]", |el| {
+    ///             el.set_attribute("rel", "noopener")?;
+    ///
+    ///             Ok(())
+    ///         })],
+    ///         ..Settings::new_send()
+    ///     },
+    ///     move |c: &[u8]| sink_output.lock().unwrap().extend_from_slice(c),
+    /// )
+    /// .unwrap();
+    ///
+    /// rewriter.write(b"<a href=/>Home").unwrap();
+    ///
+    /// thread::spawn(move || {
+    ///     rewriter.write(b"</a>").unwrap();
+    ///     rewriter.end().unwrap();
+    /// })
+    /// .join()
+    /// .unwrap();
+    ///
+    /// assert_eq!(
+    ///     String::from_utf8(output.lock().unwrap().clone()).unwrap(),
+    ///     r#"<a href=/ rel="noopener">Home</a>"#
+    /// );
+    /// ```
+    #[inline]
+    pub fn new_send() -> Self {
+        Self::new_with_handler_types()
+    }
+}
+
+impl<H: HandlerTypes> Settings<'_, '_, H> {
+    /// Creates the default settings for the handlers of any [`HandlerTypes`].
+    ///
+    /// [`HandlerTypes`]: trait.HandlerTypes.html
+    #[inline]
+    pub(super) fn new_with_handler_types() -> Self {
         Settings {
             element_content_handlers: vec![],
             document_content_handlers: vec![],
+            comment_content_handlers: vec![],
             encoding: "utf-8",
             memory_settings: MemorySettings::default(),
+            memory_usage_warning: None,
+            input_filter: None,
+            output_filter: None,
+            spill_settings: SpillSettings::default(),
+            stream_comments: false,
+            matching_cutoff: None,
+            deadline: None,
+            deadline_policy: DeadlinePolicy::default(),
+            head_only: false,
+            analysis_limit: None,
+            match_declarative_shadow_roots: true,
+            table_content_policy: TableContentPolicy::default(),
+            select_content_policy: SelectContentPolicy::default(),
+            obsolete_elements_policy: ObsoleteElementsPolicy::default(),
+            count_selector_matches: false,
+            track_element_ids: false,
+            selector_switch: None,
+            match_cache_capacity: None,
+            #[cfg(feature = "parse_ahead")]
+            parse_ahead_queue_capacity: None,
+            buffer_has_candidates: false,
+            selector_context: vec![],
+            entity_normalization: None,
+            canonical_output: false,
+            validation_handler: None,
             strict: true,
         }
     }
@@ -534,6 +2480,9 @@ impl<'h, 's> From<RewriteStrSettings<'h, 's>> for Settings<'h, 's> {
             element_content_handlers: settings.element_content_handlers,
             document_content_handlers: settings.document_content_handlers,
             strict: settings.strict,
+            // NOTE: the whole document is in memory anyway, so the candidates of the
+            // pseudo-classes that require the lookahead can be held back without a limit.
+            buffer_has_candidates: true,
             ..Settings::default()
         }
     }
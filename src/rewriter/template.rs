@@ -0,0 +1,620 @@
+use super::handlers_dispatcher::SelectorHandlersLocator;
+#[cfg(feature = "basic_selectors")]
+use super::obsolete_elements::obsolete_elements_handlers;
+use super::settings::*;
+use super::{try_encoding_from_str, SettingsError};
+use crate::selectors_vm::{self, Compiler, Program};
+#[cfg(feature = "selector_matching")]
+use crate::selectors_vm::{HasCondition, LastSiblingCondition, SelectorError};
+use encoding_rs::Encoding;
+use hashbrown::HashMap;
+use std::fmt::{self, Debug};
+use std::sync::Arc;
+
+/// Assigns the handler locators to the selectors the same way the content handlers dispatcher
+/// does, so the program can be compiled without the handlers themselves.
+#[derive(Default)]
+struct LocatorAllocator {
+    element_handler_count: usize,
+    comment_handler_count: usize,
+    text_handler_count: usize,
+    attribute_change_handler_count: usize,
+    selector_count: usize,
+}
+
+/// The kinds of the handlers attached to a selector.
+#[derive(Copy, Clone)]
+struct HandlerKinds {
+    element: bool,
+    comments: bool,
+    text: bool,
+    attribute_change: bool,
+}
+
+impl<H: HandlerTypes> From<&ElementContentHandlers<'_, H>> for HandlerKinds {
+    #[inline]
+    fn from(handlers: &ElementContentHandlers<'_, H>) -> Self {
+        HandlerKinds {
+            element: handlers.element.is_some(),
+            comments: handlers.comments.is_some(),
+            text: handlers.text.is_some(),
+            attribute_change: handlers.attribute_change.is_some(),
+        }
+    }
+}
+
+impl From<&SelectorHandlersLocator> for HandlerKinds {
+    #[inline]
+    fn from(locator: &SelectorHandlersLocator) -> Self {
+        HandlerKinds {
+            element: locator.element_handler_idx.is_some(),
+            comments: locator.comment_handler_idx.is_some(),
+            text: locator.text_handler_idx.is_some(),
+            attribute_change: locator.attribute_change_handler_idx.is_some(),
+        }
+    }
+}
+
+impl LocatorAllocator {
+    fn next_idx(count: &mut usize, has_handler: bool) -> Option<usize> {
+        if has_handler {
+            *count += 1;
+
+            Some(*count - 1)
+        } else {
+            None
+        }
+    }
+
+    fn allocate(&mut self, kinds: HandlerKinds) -> SelectorHandlersLocator {
+        let locator = SelectorHandlersLocator {
+            element_handler_idx: Self::next_idx(&mut self.element_handler_count, kinds.element),
+            comment_handler_idx: Self::next_idx(&mut self.comment_handler_count, kinds.comments),
+            text_handler_idx: Self::next_idx(&mut self.text_handler_count, kinds.text),
+            attribute_change_handler_idx: Self::next_idx(
+                &mut self.attribute_change_handler_count,
+                kinds.attribute_change,
+            ),
+            selector_idx: self.selector_count,
+        };
+
+        self.selector_count += 1;
+
+        locator
+    }
+}
+
+#[inline]
+fn alternative_sources(selector: &selectors_vm::Selector) -> Vec<Arc<str>> {
+    selector
+        .alternatives()
+        .into_iter()
+        .map(Into::into)
+        .collect()
+}
+
+/// The conditions of a selector that are resolved by the lookahead.
+#[cfg(feature = "selector_matching")]
+#[derive(Default, Clone)]
+struct LookaheadConditions {
+    has: Vec<HasCondition>,
+    last_sibling: Vec<LastSiblingCondition>,
+}
+
+#[cfg(feature = "selector_matching")]
+impl LookaheadConditions {
+    fn try_new(
+        selector: &selectors_vm::Selector,
+        lookahead_enabled: bool,
+    ) -> Result<Self, SettingsError> {
+        let conditions = LookaheadConditions {
+            has: selector.has_conditions(),
+            last_sibling: selector.last_sibling_conditions(),
+        };
+
+        // NOTE: without the lookahead these pseudo-classes would never match, so
+        // the handlers of the selector would silently never be invoked.
+        if !lookahead_enabled && (!conditions.has.is_empty() || !conditions.last_sibling.is_empty())
+        {
+            return Err(SettingsError::Selector(SelectorError::LookaheadRequired));
+        }
+
+        Ok(conditions)
+    }
+
+    /// Merges the conditions of several selectors, so each condition is resolved once.
+    fn merge<'c>(conditions: impl IntoIterator<Item = &'c Self>) -> Self {
+        let mut merged = LookaheadConditions::default();
+
+        for conditions in conditions {
+            for condition in &conditions.has {
+                if !merged.has.contains(condition) {
+                    merged.has.push(condition.clone());
+                }
+            }
+
+            for condition in &conditions.last_sibling {
+                if !merged.last_sibling.contains(condition) {
+                    merged.last_sibling.push(condition.clone());
+                }
+            }
+        }
+
+        merged
+    }
+}
+
+/// A precompiled selector matching program that can be shared by any number of rewriters.
+///
+/// Constructing an [`HtmlRewriter`] compiles the selectors of its element content handlers. If
+/// a lot of rewriters with the same selectors are constructed, e.g. one for each response passing
+/// through a proxy, the compilation can be done once with the template and the rewriters can be
+/// instantiated from it with [`HtmlRewriter::from_template`].
+///
+/// The template is [`Send`] and [`Sync`], so it can be shared across threads in an [`Arc`].
+/// Selectors can be added to and removed from a template, mostly without recompilation of the
+/// rest of them, with [`add_selectors`] and [`remove_selectors`].
+///
+/// # Example
+/// ```
+/// use lol_html::{ElementContentHandlers, HtmlRewriter, RewriterTemplate, Selector, Settings};
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// fn settings<'h>(selector: &'h Selector) -> Settings<'h, 'h> {
+///     Settings {
+///         element_content_handlers: vec![(
+///             selector,
+///             ElementContentHandlers::default().element(|el| {
+///                 el.set_attribute("rel", "noopener")?;
+///
+///                 Ok(())
+///             }),
+///         )],
+///         ..Settings::default()
+///     }
+/// }
+///
+/// let selector: Selector = "a[target=_blank]".parse().unwrap();
+/// let template = Arc::new(RewriterTemplate::try_new(&settings(&selector)).unwrap());
+///
+/// let threads: Vec<_> = (0..4)
+///     .map(|_| {
+///         let template = Arc::clone(&template);
+///
+///         thread::spawn(move || {
+///             let selector: Selector = "a[target=_blank]".parse().unwrap();
+///             let mut output = vec![];
+///
+///             {
+///                 let mut rewriter = HtmlRewriter::from_template(
+///                     &template,
+///                     settings(&selector),
+///                     |c: &[u8]| output.extend_from_slice(c),
+///                 )
+///                 .unwrap();
+///
+///                 rewriter.write(br#"<a href="/" target=_blank>"#).unwrap();
+///                 rewriter.end().unwrap();
+///             }
+///
+///             String::from_utf8(output).unwrap()
+///         })
+///     })
+///     .collect();
+///
+/// for thread in threads {
+///     assert_eq!(
+///         thread.join().unwrap(),
+///         r#"<a href="/" target=_blank rel="noopener">"#
+///     );
+/// }
+/// ```
+///
+/// [`HtmlRewriter`]: struct.HtmlRewriter.html
+/// [`HtmlRewriter::from_template`]: struct.HtmlRewriter.html#method.from_template
+/// [`Send`]: https://doc.rust-lang.org/std/marker/trait.Send.html
+/// [`Sync`]: https://doc.rust-lang.org/std/marker/trait.Sync.html
+/// [`Arc`]: https://doc.rust-lang.org/std/sync/struct.Arc.html
+/// [`add_selectors`]: #method.add_selectors
+/// [`remove_selectors`]: #method.remove_selectors
+pub struct RewriterTemplate {
+    pub(super) encoding: &'static Encoding,
+    /// The compiled program, or `None` if there is nothing to match selectors for.
+    pub(super) program: Option<Arc<Program<SelectorHandlersLocator>>>,
+    /// The locators of the handlers of the user-provided selectors followed by the ones of
+    /// the built-in selectors.
+    pub(super) locators: Vec<SelectorHandlersLocator>,
+    /// The sources of the alternatives of the selector lists in the same order as their
+    /// locators.
+    pub(super) selector_sources: Vec<Vec<Arc<str>>>,
+    pub(super) user_selector_count: usize,
+    pub(super) obsolete_elements_policy: ObsoleteElementsPolicy,
+    pub(super) has_attribute_exprs: bool,
+    pub(super) head_only_matching: bool,
+    selector_context: Vec<String>,
+    /// The selectors of the template merged into a single AST, which the program is recompiled
+    /// from once it has too many stale selectors.
+    ast: selectors_vm::Ast<SelectorHandlersLocator>,
+    /// The number of the selectors that have been added to or removed from the template since
+    /// its program has been compiled from scratch.
+    stale_selector_count: usize,
+    #[cfg(feature = "selector_matching")]
+    lookahead_enabled: bool,
+    /// The lookahead conditions of the user-provided selectors in the same order as their
+    /// locators.
+    #[cfg(feature = "selector_matching")]
+    selector_lookahead_conditions: Vec<LookaheadConditions>,
+    #[cfg(feature = "selector_matching")]
+    pub(super) has_conditions: Vec<HasCondition>,
+    #[cfg(feature = "selector_matching")]
+    pub(super) last_sibling_conditions: Vec<LastSiblingCondition>,
+}
+
+impl RewriterTemplate {
+    /// Compiles the selectors of the element content handlers of the `settings`.
+    ///
+    /// Besides the selectors, the template captures the kinds of the handlers attached to each
+    /// selector, the [`encoding`], the [`selector_context`] and the
+    /// [`obsolete_elements_policy`] of the `settings`. The rest of the settings, including the
+    /// handlers themselves, are provided for each rewriter instantiated from the template.
+    ///
+    /// Fails the same way as [`HtmlRewriter::try_new`].
+    ///
+    /// [`HtmlRewriter::try_new`]: struct.HtmlRewriter.html#method.try_new
+    /// [`encoding`]: struct.Settings.html#structfield.encoding
+    /// [`selector_context`]: struct.Settings.html#structfield.selector_context
+    /// [`obsolete_elements_policy`]: struct.Settings.html#structfield.obsolete_elements_policy
+    pub fn try_new<H: HandlerTypes>(settings: &Settings<'_, '_, H>) -> Result<Self, SettingsError> {
+        let encoding = try_encoding_from_str(settings.encoding)?;
+        let mut ast = selectors_vm::Ast::default();
+        let mut allocator = LocatorAllocator::default();
+        let mut locators = Vec::with_capacity(settings.element_content_handlers.len());
+        let mut selector_sources = Vec::with_capacity(settings.element_content_handlers.len());
+
+        #[cfg(feature = "selector_matching")]
+        let mut selector_lookahead_conditions =
+            Vec::with_capacity(settings.element_content_handlers.len());
+
+        for (selector, handlers) in &settings.element_content_handlers {
+            #[cfg(feature = "selector_matching")]
+            selector_lookahead_conditions.push(LookaheadConditions::try_new(
+                selector,
+                settings.buffer_has_candidates,
+            )?);
+
+            let locator = allocator.allocate(handlers.into());
+
+            locators.push(locator);
+            selector_sources.push(alternative_sources(selector));
+            ast.add_selector(selector, locator);
+        }
+
+        #[cfg(feature = "basic_selectors")]
+        for (selector, handlers) in
+            obsolete_elements_handlers::<H>(settings.obsolete_elements_policy)
+        {
+            // NOTE: the selectors are constants, so it's safe to unwrap here.
+            let parsed_selector: selectors_vm::Selector = selector.parse().unwrap();
+            let locator = allocator.allocate((&handlers).into());
+
+            locators.push(locator);
+            selector_sources.push(alternative_sources(&parsed_selector));
+            ast.add_selector(&parsed_selector, locator);
+        }
+
+        let has_attribute_exprs = ast.has_attribute_exprs();
+        let head_only_matching = ast.is_scoped_to("head");
+
+        let program = if locators.is_empty() {
+            None
+        } else {
+            let program = Compiler::new(encoding, &settings.selector_context).compile(ast.clone());
+
+            Some(Arc::new(program))
+        };
+
+        #[cfg(feature = "selector_matching")]
+        let lookahead_conditions = LookaheadConditions::merge(&selector_lookahead_conditions);
+
+        Ok(RewriterTemplate {
+            encoding,
+            program,
+            locators,
+            selector_sources,
+            user_selector_count: settings.element_content_handlers.len(),
+            obsolete_elements_policy: settings.obsolete_elements_policy,
+            has_attribute_exprs,
+            head_only_matching,
+            selector_context: settings
+                .selector_context
+                .iter()
+                .map(|&name| name.to_owned())
+                .collect(),
+            ast,
+            stale_selector_count: 0,
+            #[cfg(feature = "selector_matching")]
+            lookahead_enabled: settings.buffer_has_candidates,
+            #[cfg(feature = "selector_matching")]
+            selector_lookahead_conditions,
+            #[cfg(feature = "selector_matching")]
+            has_conditions: lookahead_conditions.has,
+            #[cfg(feature = "selector_matching")]
+            last_sibling_conditions: lookahead_conditions.last_sibling,
+        })
+    }
+
+    /// Returns a template with the selectors of the `element_content_handlers` added after the
+    /// selectors of this template.
+    ///
+    /// Usually only the added selectors are compiled, the already compiled selectors are shared
+    /// with this template. So, e.g. a long-lived proxy can update its selectors on the fly
+    /// without pausing for the recompilation of all of them. Once the selectors that have been
+    /// added and removed since the last full compilation outnumber a quarter of the selectors of
+    /// the template, all of them are compiled from scratch, so the matching doesn't slow down
+    /// with the updates. The element content handlers of the rewriters
+    /// instantiated from the returned template are the handlers for the selectors of this
+    /// template followed by the handlers for the added selectors. The rewriters instantiated from
+    /// this template aren't affected.
+    ///
+    /// Fails if some of the added selectors require the lookahead, while it has been disabled by
+    /// the [`buffer_has_candidates`] of the settings this template has been compiled for.
+    ///
+    /// # Example
+    /// ```
+    /// use lol_html::{ElementContentHandlers, HtmlRewriter, RewriterTemplate, Selector, Settings};
+    ///
+    /// let blocked_scripts: Selector = "script[src*=tracker]".parse().unwrap();
+    /// let blocked_frames: Selector = "iframe[src*=ads]".parse().unwrap();
+    /// let remove = || ElementContentHandlers::default().element(|el| Ok(el.remove()));
+    ///
+    /// let template = RewriterTemplate::try_new(&Settings {
+    ///     element_content_handlers: vec![(&blocked_scripts, remove())],
+    ///     ..Settings::default()
+    /// })
+    /// .unwrap();
+    ///
+    /// let template = template
+    ///     .add_selectors(&[(&blocked_frames, remove())])
+    ///     .unwrap();
+    ///
+    /// let mut output = vec![];
+    ///
+    /// {
+    ///     let mut rewriter = HtmlRewriter::from_template(
+    ///         &template,
+    ///         Settings {
+    ///             element_content_handlers: vec![
+    ///                 (&blocked_scripts, remove()),
+    ///                 (&blocked_frames, remove()),
+    ///             ],
+    ///             ..Settings::default()
+    ///         },
+    ///         |c: &[u8]| output.extend_from_slice(c),
+    ///     )
+    ///     .unwrap();
+    ///
+    ///     rewriter
+    ///         .write(b"<script src=/tracker.js></script><iframe src=/ads></iframe><p>")
+    ///         .unwrap();
+    ///     rewriter.end().unwrap();
+    /// }
+    ///
+    /// assert_eq!(String::from_utf8(output).unwrap(), "<p>");
+    /// ```
+    ///
+    /// [`buffer_has_candidates`]: struct.Settings.html#structfield.buffer_has_candidates
+    pub fn add_selectors<H: HandlerTypes>(
+        &self,
+        element_content_handlers: &[(&selectors_vm::Selector, ElementContentHandlers<'_, H>)],
+    ) -> Result<Self, SettingsError> {
+        self.update(&[], element_content_handlers)
+    }
+
+    /// Returns a template without the selectors with the given `indices` in the element content
+    /// handlers of this template. The indices that are out of bounds are ignored.
+    ///
+    /// As with [`add_selectors`], the selectors are usually not recompiled. The removed selectors
+    /// are still matched by the rewriters instantiated from the returned template, but they no
+    /// longer invoke any handlers, until the selectors are compiled from scratch. The element
+    /// content handlers of these rewriters are the handlers of this template without the
+    /// handlers for the removed selectors.
+    ///
+    /// [`add_selectors`]: #method.add_selectors
+    pub fn remove_selectors(&self, indices: &[usize]) -> Self {
+        // NOTE: nothing is added, so the lookahead can't be required by the added selectors.
+        self.update::<LocalHandlerTypes>(indices, &[]).unwrap()
+    }
+
+    fn update<H: HandlerTypes>(
+        &self,
+        removed_indices: &[usize],
+        added_handlers: &[(&selectors_vm::Selector, ElementContentHandlers<'_, H>)],
+    ) -> Result<Self, SettingsError> {
+        let mut ast = selectors_vm::Ast::default();
+        let mut allocator = LocatorAllocator::default();
+        let mut locators = Vec::with_capacity(self.locators.len() + added_handlers.len());
+        let mut selector_sources = Vec::with_capacity(locators.capacity());
+        let mut locator_map = HashMap::new();
+        let mut removed_selector_count = 0;
+
+        #[cfg(feature = "selector_matching")]
+        let mut selector_lookahead_conditions = Vec::with_capacity(locators.capacity());
+
+        let mut existing_selectors = self.locators.iter().zip(&self.selector_sources);
+
+        macro_rules! keep_existing {
+            ($locator:expr, $sources:expr) => {
+                let new_locator = allocator.allocate($locator.into());
+
+                locator_map.insert(*$locator, new_locator);
+                locators.push(new_locator);
+                selector_sources.push($sources.to_owned());
+            };
+        }
+
+        for (idx, (locator, sources)) in existing_selectors
+            .by_ref()
+            .take(self.user_selector_count)
+            .enumerate()
+        {
+            if removed_indices.contains(&idx) {
+                removed_selector_count += 1;
+            } else {
+                keep_existing!(locator, sources);
+
+                #[cfg(feature = "selector_matching")]
+                selector_lookahead_conditions.push(self.selector_lookahead_conditions[idx].clone());
+            }
+        }
+
+        let kept_selector_count = locators.len();
+
+        for (selector, handlers) in added_handlers {
+            #[cfg(feature = "selector_matching")]
+            selector_lookahead_conditions.push(LookaheadConditions::try_new(
+                selector,
+                self.lookahead_enabled,
+            )?);
+
+            let locator = allocator.allocate(handlers.into());
+
+            locators.push(locator);
+            selector_sources.push(alternative_sources(selector));
+            ast.add_selector(selector, locator);
+        }
+
+        let user_selector_count = locators.len();
+
+        // NOTE: the built-in selectors follow the user-provided ones.
+        for (locator, sources) in existing_selectors {
+            keep_existing!(locator, sources);
+        }
+
+        let mut merged_ast = self.ast.clone();
+
+        merged_ast.map_payload(|locator| locator_map.get(&locator).copied());
+
+        for ((selector, _), &locator) in added_handlers
+            .iter()
+            .zip(&locators[kept_selector_count..user_selector_count])
+        {
+            merged_ast.add_selector(selector, locator);
+        }
+
+        let mut stale_selector_count =
+            self.stale_selector_count + removed_selector_count + added_handlers.len();
+
+        let mut has_attribute_exprs = merged_ast.has_attribute_exprs();
+        let mut head_only_matching = merged_ast.is_scoped_to("head");
+
+        let program = match self.program {
+            _ if locators.is_empty() => None,
+            // NOTE: the program is extended until the stale selectors, i.e. the ones that have
+            // been added to or removed from it since its compilation, outnumber a quarter of
+            // the live ones. Then it's recompiled from the merged AST, so it doesn't grow
+            // without bound.
+            Some(ref program) if stale_selector_count <= locators.len() / 4 => {
+                // NOTE: the instructions of the removed selectors stay in the extended program,
+                // so they are still executed, but without any payloads.
+                has_attribute_exprs |= self.has_attribute_exprs;
+                head_only_matching &= self.head_only_matching;
+
+                Some(Arc::new(Compiler::extend(program, ast, |locator| {
+                    locator_map.get(&locator).copied()
+                })))
+            }
+            _ => {
+                let selector_context: Vec<_> =
+                    self.selector_context.iter().map(String::as_str).collect();
+
+                stale_selector_count = 0;
+
+                Some(Arc::new(
+                    Compiler::new(self.encoding, &selector_context).compile(merged_ast.clone()),
+                ))
+            }
+        };
+
+        #[cfg(feature = "selector_matching")]
+        let lookahead_conditions = LookaheadConditions::merge(&selector_lookahead_conditions);
+
+        Ok(RewriterTemplate {
+            encoding: self.encoding,
+            program,
+            locators,
+            selector_sources,
+            user_selector_count,
+            obsolete_elements_policy: self.obsolete_elements_policy,
+            has_attribute_exprs,
+            head_only_matching,
+            selector_context: self.selector_context.clone(),
+            ast: merged_ast,
+            stale_selector_count,
+            #[cfg(feature = "selector_matching")]
+            lookahead_enabled: self.lookahead_enabled,
+            #[cfg(feature = "selector_matching")]
+            selector_lookahead_conditions,
+            #[cfg(feature = "selector_matching")]
+            has_conditions: lookahead_conditions.has,
+            #[cfg(feature = "selector_matching")]
+            last_sibling_conditions: lookahead_conditions.last_sibling,
+        })
+    }
+}
+
+// NOTE: this opaque Debug implementation is required to make
+// `.unwrap()` and `.expect()` methods available on Result
+// returned by the `RewriterTemplate::try_new()` method.
+impl Debug for RewriterTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RewriterTemplate")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn send_and_sync() {
+        fn assert_send_and_sync<T: Send + Sync>() {}
+
+        assert_send_and_sync::<RewriterTemplate>();
+    }
+
+    #[cfg(feature = "selector_matching")]
+    #[test]
+    fn stale_selectors() {
+        let selectors: Vec<selectors_vm::Selector> = (0..8)
+            .map(|i| format!("#id{}", i).parse().unwrap())
+            .collect();
+
+        let has_items: selectors_vm::Selector = "ul:has(li)".parse().unwrap();
+        let handlers = ElementContentHandlers::<LocalHandlerTypes>::default;
+
+        let template = RewriterTemplate::try_new(&Settings {
+            element_content_handlers: selectors.iter().map(|s| (s, handlers())).collect(),
+            buffer_has_candidates: true,
+            ..Settings::default()
+        })
+        .unwrap();
+
+        let extended = template.add_selectors(&[(&has_items, handlers())]).unwrap();
+
+        assert_eq!(extended.stale_selector_count, 1);
+        assert_eq!(extended.ast.cumulative_node_count, 9);
+        assert_eq!(extended.has_conditions.len(), 1);
+
+        let reduced = extended.remove_selectors(&[8]);
+
+        assert_eq!(reduced.stale_selector_count, 2);
+        assert_eq!(reduced.ast.cumulative_node_count, 8);
+        assert!(reduced.has_conditions.is_empty());
+
+        let recompiled = reduced.remove_selectors(&[0]);
+
+        assert_eq!(recompiled.stale_selector_count, 0);
+        assert_eq!(recompiled.ast.cumulative_node_count, 7);
+    }
+}
@@ -0,0 +1,239 @@
+use super::settings::{DocumentContentHandlers, HandlerResult};
+use crate::html::TextType;
+use crate::rewritable_units::{ContentType, TextChunk};
+use crate::text_filters::{filter_cow, Chain};
+use std::borrow::Cow;
+
+/// A transformation of the text nodes of the document, e.g. a typographic one.
+///
+/// Filters are plugged into a [`TextPipeline`] which runs them on each text node of the document.
+/// The filters can be combined with the [`then`] method, and the built-in filters and the other
+/// combinators can be found in the [`text_filters`] module.
+/// The filter receives the text as it appears in the markup, i.e. the character references in it
+/// are not decoded, and its output is inserted into the document as is.
+///
+/// # Example
+/// ```
+/// use lol_html::{rewrite_str, RewriteStrSettings, TextFilter, TextPipeline};
+/// use std::borrow::Cow;
+///
+/// struct SmartQuotes;
+///
+/// impl TextFilter for SmartQuotes {
+///     fn filter<'t>(&mut self, text: &'t str) -> Cow<'t, str> {
+///         if !text.contains('"') {
+///             return Cow::Borrowed(text);
+///         }
+///
+///         let mut output = String::with_capacity(text.len());
+///         let mut is_open = false;
+///
+///         for c in text.chars() {
+///             if c == '"' {
+///                 is_open = !is_open;
+///                 output.push(if is_open { '“' } else { '”' });
+///             } else {
+///                 output.push(c);
+///             }
+///         }
+///
+///         Cow::Owned(output)
+///     }
+/// }
+///
+/// let html = rewrite_str(
+///     r#"<p>"Hello" <b>"world"</b></p><script>alert("Hi")</script>"#,
+///     RewriteStrSettings {
+///         document_content_handlers: vec![TextPipeline::default()
+///             .with_filter(SmartQuotes)
+///             .into()],
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(html, r#"<p>“Hello” <b>“world”</b></p><script>alert("Hi")</script>"#);
+/// ```
+///
+/// [`TextPipeline`]: struct.TextPipeline.html
+/// [`then`]: #method.then
+/// [`text_filters`]: text_filters/index.html
+pub trait TextFilter {
+    /// Transforms the `text` of a text node.
+    fn filter<'t>(&mut self, text: &'t str) -> Cow<'t, str>;
+
+    /// Combines the filter with the `next` one, which is run on the output of the filter.
+    #[inline]
+    fn then<F: TextFilter>(self, next: F) -> Chain<Self, F>
+    where
+        Self: Sized,
+    {
+        Chain {
+            first: self,
+            second: next,
+        }
+    }
+}
+
+/// A sequence of [`TextFilter`]s applied to each text node of the document.
+///
+/// The pipeline buffers the chunks of a text node and runs the filters once the whole text node
+/// has been received, so the filters don't need to deal with the text split between the chunks.
+/// The filtered text replaces the last chunk of the text node.
+///
+/// The pipeline only processes the regular text: the content of the elements with special
+/// parsing rules (e.g. `<script>`, `<style>`, `<title>` or `<textarea>`) is left intact. The
+/// pipeline can be converted into the [`DocumentContentHandlers`], so it can be combined with the
+/// other handlers and its [priority] can be specified.
+///
+/// [`TextFilter`]: trait.TextFilter.html
+/// [`DocumentContentHandlers`]: struct.DocumentContentHandlers.html
+/// [priority]: struct.DocumentContentHandlers.html#method.priority
+#[derive(Default)]
+pub struct TextPipeline<'f> {
+    filters: Vec<Box<dyn TextFilter + 'f>>,
+    buffer: String,
+}
+
+impl<'f> TextPipeline<'f> {
+    /// Adds the `filter` to the end of the pipeline.
+    #[inline]
+    pub fn with_filter(mut self, filter: impl TextFilter + 'f) -> Self {
+        self.filters.push(Box::new(filter));
+
+        self
+    }
+
+    fn handle_text_chunk(&mut self, chunk: &mut TextChunk) -> HandlerResult {
+        if !chunk.removed() {
+            self.buffer.push_str(chunk.as_str());
+        }
+
+        if !chunk.last_in_text_node() {
+            chunk.remove();
+
+            return Ok(());
+        }
+
+        if chunk.removed() {
+            // NOTE: the text node has been replaced by another handler, we still need to
+            // output the preceding text though.
+            if !self.buffer.is_empty() {
+                chunk.before(&self.buffer, ContentType::Html);
+            }
+        } else {
+            let mut text = Cow::Borrowed(self.buffer.as_str());
+
+            for filter in self.filters.iter_mut() {
+                text = filter_cow(filter, text);
+            }
+
+            chunk.replace(&text, ContentType::Html);
+        }
+
+        self.buffer.clear();
+
+        Ok(())
+    }
+}
+
+impl<'f> From<TextPipeline<'f>> for DocumentContentHandlers<'f> {
+    #[inline]
+    fn from(mut pipeline: TextPipeline<'f>) -> Self {
+        DocumentContentHandlers::default()
+            .text(move |t| pipeline.handle_text_chunk(t))
+            .text_types(&[TextType::Data])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HtmlRewriter, Settings};
+
+    struct Uppercase;
+
+    impl TextFilter for Uppercase {
+        fn filter<'t>(&mut self, text: &'t str) -> Cow<'t, str> {
+            Cow::Owned(text.to_uppercase())
+        }
+    }
+
+    struct Exclaim;
+
+    impl TextFilter for Exclaim {
+        fn filter<'t>(&mut self, text: &'t str) -> Cow<'t, str> {
+            if text.is_empty() {
+                Cow::Borrowed(text)
+            } else {
+                Cow::Owned(format!("{}!", text))
+            }
+        }
+    }
+
+    fn rewrite(chunks: &[&str], handlers: Vec<DocumentContentHandlers>) -> String {
+        let mut output = vec![];
+
+        {
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    document_content_handlers: handlers,
+                    ..Settings::default()
+                },
+                |c: &[u8]| output.extend_from_slice(c),
+            )
+            .unwrap();
+
+            for chunk in chunks {
+                rewriter.write(chunk.as_bytes()).unwrap();
+            }
+
+            rewriter.end().unwrap();
+        }
+
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn filters_whole_text_nodes() {
+        let pipeline = TextPipeline::default()
+            .with_filter(Uppercase)
+            .with_filter(Exclaim);
+
+        assert_eq!(
+            rewrite(
+                &[
+                    "<div>He",
+                    "llo <b>wor",
+                    "ld</b></div><style>a",
+                    "{}</style>"
+                ],
+                vec![pipeline.into()]
+            ),
+            "<div>HELLO !<b>WORLD!</b></div><style>a{}</style>"
+        );
+    }
+
+    #[test]
+    fn text_replaced_by_other_handlers() {
+        let pipeline = TextPipeline::default().with_filter(Uppercase);
+
+        assert_eq!(
+            rewrite(
+                &["<div>foo", "bar</div>"],
+                vec![
+                    pipeline.into(),
+                    DocumentContentHandlers::default()
+                        .text(|t| {
+                            if t.last_in_text_node() {
+                                t.replace("baz", ContentType::Text);
+                            }
+
+                            Ok(())
+                        })
+                        .priority(1)
+                ]
+            ),
+            "<div>foobarbaz</div>"
+        );
+    }
+}
@@ -0,0 +1,250 @@
+use super::rewrite_controller::is_closed_by_start_tag;
+use crate::html::{is_void_element, LocalName, Namespace, Tag};
+use encoding_rs::Encoding;
+use std::fmt::{self, Display};
+
+/// A content model violation reported by the [`validation_handler`].
+///
+/// [`validation_handler`]: struct.Settings.html#structfield.validation_handler
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ValidationDiagnostic {
+    /// An open `<p>` element is implicitly closed by a start tag of an element that can't be
+    /// contained in a paragraph, e.g. `<p>Foo<div>Bar</div></p>`. The end tag of the paragraph
+    /// can be omitted, but such markup is commonly a mistake: the element is placed after the
+    /// paragraph rather than in it, and the stray `</p>` end tag creates an empty paragraph.
+    /// Paragraphs closed by the start tag of the next paragraph are not reported.
+    UnclosedParagraph {
+        /// The lowercased tag name of the element that closes the paragraph.
+        closed_by: String,
+    },
+
+    /// A `<li>` element is not contained in any of the `<ul>`, `<ol>` or `<menu>` lists.
+    ListItemOutsideList,
+
+    /// The document contains more than one `<title>` element.
+    DuplicateTitle,
+}
+
+impl Display for ValidationDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValidationDiagnostic::UnclosedParagraph { closed_by } => write!(
+                f,
+                "`<p>` element is implicitly closed by the `<{}>` start tag.",
+                closed_by
+            ),
+            ValidationDiagnostic::ListItemOutsideList => {
+                f.write_str("`<li>` element is not contained in a list.")
+            }
+            ValidationDiagnostic::DuplicateTitle => {
+                f.write_str("Document contains more than one `<title>` element.")
+            }
+        }
+    }
+}
+
+/// Returns `true` if the element is a boundary of the [button scope], i.e. the `<p>` elements
+/// outside of it are not closed by the start tags inside of it.
+///
+/// [button scope]: https://html.spec.whatwg.org/multipage/parsing.html#has-an-element-in-button-scope
+#[inline]
+fn is_button_scope_boundary(local_name: &LocalName) -> bool {
+    tag_is_one_of!(
+        *local_name,
+        [Applet, Button, Caption, Html, Marquee, Object, Table, Td, Template, Th]
+    )
+}
+
+/// Checks the HTML elements of the document against the common content model rules.
+///
+/// Only the start and end tags of the elements are considered, so, like the rest of the
+/// rewriter, the validator doesn't maintain the complete tree builder state. The open element
+/// stack is approximated with the explicit end tags and the elements that are implicitly closed
+/// by the start tags.
+pub struct Validator<'h> {
+    handler: Box<dyn FnMut(ValidationDiagnostic) + Send + 'h>,
+    encoding: &'static Encoding,
+    open_elements: Vec<LocalName<'static>>,
+    has_title: bool,
+}
+
+impl<'h> Validator<'h> {
+    pub fn new(
+        handler: Box<dyn FnMut(ValidationDiagnostic) + Send + 'h>,
+        encoding: &'static Encoding,
+    ) -> Self {
+        Validator {
+            handler,
+            encoding,
+            open_elements: Vec::default(),
+            has_title: false,
+        }
+    }
+
+    fn close_paragraph(&mut self, local_name: &LocalName) {
+        let idx = self
+            .open_elements
+            .iter()
+            .rposition(|e| *e == Tag::P || is_button_scope_boundary(e));
+
+        if let Some(idx) = idx {
+            let open_element = &self.open_elements[idx];
+
+            if *open_element == Tag::P && is_closed_by_start_tag(open_element, local_name) {
+                self.open_elements.truncate(idx);
+
+                if *local_name != Tag::P {
+                    (self.handler)(ValidationDiagnostic::UnclosedParagraph {
+                        closed_by: local_name.as_lowercase_string(self.encoding),
+                    });
+                }
+            }
+        }
+    }
+
+    pub fn handle_start_tag(&mut self, local_name: &LocalName, ns: Namespace) {
+        // NOTE: the content model of the foreign elements is not checked.
+        if ns != Namespace::Html {
+            return;
+        }
+
+        self.close_paragraph(local_name);
+
+        while let Some(open_element) = self.open_elements.last() {
+            if !is_closed_by_start_tag(open_element, local_name) {
+                break;
+            }
+
+            self.open_elements.pop();
+        }
+
+        if *local_name == Tag::Li
+            && !self
+                .open_elements
+                .iter()
+                .any(|e| tag_is_one_of!(*e, [Ul, Ol, Menu]))
+        {
+            (self.handler)(ValidationDiagnostic::ListItemOutsideList);
+        } else if *local_name == Tag::Title {
+            if self.has_title {
+                (self.handler)(ValidationDiagnostic::DuplicateTitle);
+            }
+
+            self.has_title = true;
+        }
+
+        if !is_void_element(local_name) {
+            self.open_elements.push(local_name.clone().into_owned());
+        }
+    }
+
+    pub fn handle_end_tag(&mut self, local_name: &LocalName) {
+        // NOTE: end tags without a matching open element are ignored by the parser.
+        if let Some(idx) = self.open_elements.iter().rposition(|e| e == local_name) {
+            self.open_elements.truncate(idx);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.open_elements.clear();
+        self.has_title = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn validate(chunks: &[&str]) -> Vec<ValidationDiagnostic> {
+        let mut diagnostics = vec![];
+
+        {
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    validation_handler: Some(Box::new(|d: ValidationDiagnostic| {
+                        diagnostics.push(d)
+                    })),
+                    ..Settings::default()
+                },
+                |_: &[u8]| {},
+            )
+            .unwrap();
+
+            for chunk in chunks {
+                rewriter.write(chunk.as_bytes()).unwrap();
+            }
+
+            rewriter.end().unwrap();
+        }
+
+        diagnostics
+    }
+
+    #[test]
+    fn unclosed_paragraph() {
+        for (html, closed_by) in &[
+            ("<p>Foo<div>Bar</div></p>", "div"),
+            ("<P>Foo<SPAN><UL><li>Bar</ul>", "ul"),
+            ("<p>Foo<table></table>", "table"),
+        ] {
+            assert_eq!(
+                validate(&[*html]),
+                [ValidationDiagnostic::UnclosedParagraph {
+                    closed_by: (*closed_by).to_owned()
+                }],
+                "Input: {}",
+                html
+            );
+        }
+
+        for html in &[
+            "<p>Foo</p><div>Bar</div>",
+            "<p>Foo<p>Bar",
+            "<p>Foo<button><div>Bar</div></button>",
+            "<div><p>Foo</div><div>Bar</div>",
+        ] {
+            assert!(validate(&[*html]).is_empty(), "Input: {}", html);
+        }
+    }
+
+    #[test]
+    fn list_item_outside_list() {
+        assert_eq!(
+            validate(&["<ul><li>1<li>2</ul>", "<ol><li><div><li>3</div></ol><li>4"]),
+            [ValidationDiagnostic::ListItemOutsideList]
+        );
+    }
+
+    #[test]
+    fn duplicate_title() {
+        assert_eq!(
+            validate(&["<title>1</title><svg><title>2</title></svg><title>3</title>"]),
+            [ValidationDiagnostic::DuplicateTitle]
+        );
+    }
+
+    #[test]
+    fn reset_for_new_document() {
+        let mut diagnostics = vec![];
+
+        {
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    validation_handler: Some(Box::new(|d: ValidationDiagnostic| {
+                        diagnostics.push(d)
+                    })),
+                    ..Settings::default()
+                },
+                |_: &[u8]| {},
+            )
+            .unwrap();
+
+            rewriter.write(b"<ul><title>1</title>").unwrap();
+            rewriter.start_new_document().unwrap();
+            rewriter.write(b"<li><title>2</title>").unwrap();
+            rewriter.end().unwrap();
+        }
+
+        assert_eq!(diagnostics, [ValidationDiagnostic::ListItemOutsideList]);
+    }
+}
@@ -1,9 +1,22 @@
-use super::parser::{Selector, SelectorImplDescriptor};
-use selectors::attr::{AttrSelectorOperator, ParsedCaseSensitivity};
-use selectors::parser::{Combinator, Component};
-use hashbrown::HashSet;
+use super::attr::{AttrSelectorOperator, ParsedCaseSensitivity};
+#[cfg(any(test, not(feature = "selector_matching")))]
+use super::basic_parser::Selector as BasicSelector;
+#[cfg(feature = "selector_matching")]
+use super::custom_pseudo_class::CustomPseudoClass;
+#[cfg(feature = "selector_matching")]
+use super::parser::{NonTSPseudoClassDescriptor, SelectorImplDescriptor};
+use super::Selector;
+use crate::html::Namespace;
+#[cfg(feature = "selector_matching")]
+use cssparser::ToCss;
+#[cfg(feature = "selector_matching")]
+use selectors::attr::{NamespaceConstraint, ParsedAttrSelectorOperation};
+#[cfg(feature = "selector_matching")]
+use selectors::parser::{Combinator as GenericCombinator, Component, Selector as GenericSelector};
+use hashbrown::HashMap;
 use std::fmt::{self, Formatter, Debug};
 use std::hash::Hash;
+use std::mem;
 
 #[derive(PartialEq, Eq, Debug, Copy, Clone)]
 pub struct NthChild {
@@ -42,16 +55,49 @@ impl NthChild {
     }
 }
 
-#[derive(PartialEq, Eq, Debug)]
+// NOTE: the basic selector parser produces only some of the expressions.
+#[cfg_attr(not(feature = "selector_matching"), allow(dead_code))]
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub enum OnTagNameExpr {
     ExplicitAny,
     Unmatchable,
     LocalName(String),
     NthChild(NthChild),
     NthOfType(NthChild),
+    /// A key of the context the rewriter is constructed with, so
+    /// the expression doesn't depend on the element at all.
+    Ctx(String),
+    /// The `:has()` pseudo-class with the relative selector. The VM can't look ahead, so
+    /// the expression is resolved with the matches reported by the lookahead rewriter
+    /// before the element reaches the VM.
+    Has(String),
+    /// The selector list of the `:not()` or `:is()` pseudo-class that the predicate can't
+    /// express, e.g. a complex selector. The expression matches if the element matches the
+    /// selector list, it's evaluated by a separate program against the open element stack.
+    Matches(String),
+    /// The namespace of the element, e.g. `svg|path`.
+    Namespace(Namespace),
+    /// The `:root` pseudo-class.
+    Root,
+    /// The `:last-child` or `:last-of-type` pseudo-class, or the `:only-child` or `:only-of-type`
+    /// one if `only` is set. Like `:has()`, the expression is resolved with the matches reported
+    /// by the lookahead rewriter, which finds out whether the element has the following
+    /// siblings.
+    LastSibling { of_type: bool, only: bool },
 }
 
-#[derive(Eq, PartialEq)]
+/// Returns the key of the `:last-child` or `:last-of-type` candidates that turn out to be
+/// the last siblings in the matches reported by the lookahead rewriter.
+#[inline]
+pub fn last_sibling_key(of_type: bool) -> &'static str {
+    if of_type {
+        ":last-of-type"
+    } else {
+        ":last-child"
+    }
+}
+
+#[derive(Eq, PartialEq, Clone)]
 pub struct AttributeComparisonExpr {
     pub name: String,
     pub value: String,
@@ -90,22 +136,65 @@ impl Debug for AttributeComparisonExpr {
 }
 
 /// An attribute check when attributes are received and parsed.
-#[derive(PartialEq, Eq, Debug)]
+#[cfg_attr(not(feature = "selector_matching"), allow(dead_code))]
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub enum OnAttributesExpr {
     Id(String),
     Class(String),
     AttributeExists(String),
+    /// A case-sensitive equality check of the `is` attribute (e.g. `button[is="fancy-button"]`).
+    /// Customized built-in elements are widely used by design systems, so the check has a fast
+    /// path with a memoized attribute value, similar to `id` and `class`.
+    CustomizedBuiltIn(String),
     AttributeComparisonExpr(AttributeComparisonExpr),
+    /// The `:lang()` pseudo-class. The language of an element is specified by its own `lang`
+    /// attribute or inherited from the nearest ancestor that has one, so the expression requires
+    /// the VM to track languages of the open elements.
+    Lang(String),
+    /// The user-defined pseudo-class.
+    #[cfg(feature = "selector_matching")]
+    Custom(CustomPseudoClass),
+}
+
+/// Returns the name of the attribute that is matched by the attribute selector with the
+/// namespace. The rewriter doesn't resolve the namespaces of the attributes, so the attributes
+/// of the foreign elements are matched by their prefixed names (e.g. `[xlink|href]` matches the
+/// `xlink:href` attribute) and the wildcard namespace matches the attribute without a prefix.
+/// Returns `None` if the namespace can't contain attributes, so the selector never matches.
+#[cfg(feature = "selector_matching")]
+fn attribute_name(
+    namespace: Option<&NamespaceConstraint<(String, Namespace)>>,
+    local_name: &str,
+) -> Option<String> {
+    match namespace {
+        None | Some(NamespaceConstraint::Any) => Some(local_name.to_owned()),
+        Some(&NamespaceConstraint::Specific((_, Namespace::XmlNs))) if local_name == "xmlns" => {
+            Some(local_name.to_owned())
+        }
+        Some(&NamespaceConstraint::Specific((_, ns))) => ns
+            .attribute_prefix()
+            .map(|prefix| format!("{}:{}", prefix, local_name)),
+    }
 }
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone)]
 /// Conditions executed as part of a predicate, or an "expect" in pseudo instructions.
 /// These are executed in order of definition.
-enum Condition {
+pub enum Condition {
     OnTagName(OnTagNameExpr),
     OnAttributes(OnAttributesExpr),
 }
 
+/// The combinator that joins a compound selector with the next one.
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum Combinator {
+    Child,
+    Descendant,
+    NextSibling,
+    LaterSibling,
+}
+
+#[cfg(feature = "selector_matching")]
 impl From<&Component<SelectorImplDescriptor>> for Condition {
     #[inline]
     fn from(component: &Component<SelectorImplDescriptor>) -> Self {
@@ -119,6 +208,7 @@ impl From<&Component<SelectorImplDescriptor>> for Condition {
             Component::ExplicitNoNamespace => {
                 Condition::OnTagName(OnTagNameExpr::Unmatchable)
             }
+            &Component::Namespace(_, ns) => Condition::OnTagName(OnTagNameExpr::Namespace(ns)),
             Component::ID(id) => Condition::OnAttributes(OnAttributesExpr::Id(id.to_owned())),
             Component::Class(c) => Condition::OnAttributes(OnAttributesExpr::Class(c.to_owned())),
             Component::AttributeInNoNamespaceExists { local_name, .. } => {
@@ -131,8 +221,18 @@ impl From<&Component<SelectorImplDescriptor>> for Condition {
                 case_sensitivity,
                 never_matches,
             } => {
+                let is_customized_built_in_expr = operator == AttrSelectorOperator::Equal
+                    && local_name.eq_ignore_ascii_case("is")
+                    && matches!(
+                        case_sensitivity,
+                        ParsedCaseSensitivity::CaseSensitive
+                            | ParsedCaseSensitivity::ExplicitCaseSensitive
+                    );
+
                 if never_matches {
                     Condition::OnTagName(OnTagNameExpr::Unmatchable)
+                } else if is_customized_built_in_expr {
+                    Condition::OnAttributes(OnAttributesExpr::CustomizedBuiltIn(value.to_owned()))
                 } else {
                     Condition::OnAttributes(OnAttributesExpr::AttributeComparisonExpr(AttributeComparisonExpr::new(
                         local_name.to_owned(),
@@ -142,6 +242,28 @@ impl From<&Component<SelectorImplDescriptor>> for Condition {
                     )))
                 }
             }
+            Component::AttributeOther(selector) => {
+                match attribute_name(selector.namespace.as_ref(), &selector.local_name_lower) {
+                    Some(name) if !selector.never_matches => match selector.operation {
+                        ParsedAttrSelectorOperation::Exists => {
+                            Condition::OnAttributes(OnAttributesExpr::AttributeExists(name))
+                        }
+                        ParsedAttrSelectorOperation::WithValue {
+                            operator,
+                            case_sensitivity,
+                            ref expected_value,
+                        } => Condition::OnAttributes(OnAttributesExpr::AttributeComparisonExpr(
+                            AttributeComparisonExpr::new(
+                                name,
+                                expected_value.to_owned(),
+                                case_sensitivity,
+                                operator,
+                            ),
+                        )),
+                    },
+                    _ => Condition::OnTagName(OnTagNameExpr::Unmatchable),
+                }
+            }
             Component::FirstChild => {
                 Condition::OnTagName(OnTagNameExpr::NthChild(NthChild::first()))
             }
@@ -154,6 +276,39 @@ impl From<&Component<SelectorImplDescriptor>> for Condition {
             &Component::NthOfType(a, b) => {
                 Condition::OnTagName(OnTagNameExpr::NthOfType(NthChild::new(a, b)))
             }
+            Component::LastChild => Condition::OnTagName(OnTagNameExpr::LastSibling {
+                of_type: false,
+                only: false,
+            }),
+            Component::LastOfType => Condition::OnTagName(OnTagNameExpr::LastSibling {
+                of_type: true,
+                only: false,
+            }),
+            Component::OnlyChild => Condition::OnTagName(OnTagNameExpr::LastSibling {
+                of_type: false,
+                only: true,
+            }),
+            Component::OnlyOfType => Condition::OnTagName(OnTagNameExpr::LastSibling {
+                of_type: true,
+                only: true,
+            }),
+            Component::Root => Condition::OnTagName(OnTagNameExpr::Root),
+            Component::NonTSPseudoClass(NonTSPseudoClassDescriptor::Lang(lang)) => {
+                Condition::OnAttributes(OnAttributesExpr::Lang(lang.to_owned()))
+            }
+            Component::NonTSPseudoClass(NonTSPseudoClassDescriptor::Ctx(key)) => {
+                Condition::OnTagName(OnTagNameExpr::Ctx(key.to_owned()))
+            }
+            Component::NonTSPseudoClass(NonTSPseudoClassDescriptor::Has(argument)) => {
+                Condition::OnTagName(OnTagNameExpr::Has(argument.to_string()))
+            }
+            Component::NonTSPseudoClass(NonTSPseudoClassDescriptor::ComplexNegation(selector))
+            | Component::NonTSPseudoClass(NonTSPseudoClassDescriptor::Is(selector)) => {
+                Condition::OnTagName(OnTagNameExpr::Matches(selector.to_owned()))
+            }
+            Component::NonTSPseudoClass(NonTSPseudoClassDescriptor::Custom(pseudo_class)) => {
+                Condition::OnAttributes(OnAttributesExpr::Custom(pseudo_class.to_owned()))
+            }
             // NOTE: the rest of the components are explicit namespace or
             // pseudo class-related. Ideally none of them should appear in
             // the parsed selector as we should bail earlier in the parser.
@@ -165,7 +320,7 @@ impl From<&Component<SelectorImplDescriptor>> for Condition {
     }
 }
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub struct Expr<E>
 where
     E: PartialEq + Eq + Debug,
@@ -187,7 +342,7 @@ where
     }
 }
 
-#[derive(PartialEq, Eq, Debug, Default)]
+#[derive(PartialEq, Eq, Debug, Default, Clone)]
 pub struct Predicate {
     pub on_tag_name_exprs: Vec<Expr<OnTagNameExpr>>,
     pub on_attr_exprs: Vec<Expr<OnAttributesExpr>>,
@@ -203,17 +358,103 @@ where
 
 impl Predicate {
     #[inline]
-    fn add_component(&mut self, component: &Component<SelectorImplDescriptor>, negation: bool) {
-        match Condition::from(component) {
+    pub fn add_condition(&mut self, condition: Condition, negation: bool) {
+        match condition {
             Condition::OnTagName(e) =>
                 add_expr_to_list(&mut self.on_tag_name_exprs, e, negation),
             Condition::OnAttributes(e) =>
                 add_expr_to_list(&mut self.on_attr_exprs, e, negation),
         }
     }
+
+    #[cfg(feature = "selector_matching")]
+    #[inline]
+    fn add_component(&mut self, component: &Component<SelectorImplDescriptor>, negation: bool) {
+        // NOTE: the complex negation is the negated expression on its selector list.
+        let negation = match component {
+            Component::NonTSPseudoClass(NonTSPseudoClassDescriptor::ComplexNegation(_)) => {
+                !negation
+            }
+            _ => negation,
+        };
+
+        self.add_condition(Condition::from(component), negation);
+    }
 }
 
-#[derive(PartialEq, Eq, Debug)]
+#[cfg(feature = "selector_matching")]
+#[inline]
+fn is_combinator(component: &Component<SelectorImplDescriptor>) -> bool {
+    matches!(component, Component::Combinator(_))
+}
+
+/// Expands the `:is()` pseudo-classes of the selector, so each of the returned component
+/// sequences matches one of the alternatives of their selector lists, e.g. `:is(a, b) > c`
+/// is expanded into `a > c` and `b > c`.
+///
+/// The alternative with combinators can be expanded only if the pseudo-class is in the leftmost
+/// compound selector: the other compound selectors of the alternative then precede the one with
+/// the pseudo-class. Otherwise, the ancestors or the preceding siblings of the element can match
+/// the alternative in any order with the rest of the selector, so the pseudo-class is left as is
+/// and matched by a separate program, like the complex negation.
+#[cfg(feature = "selector_matching")]
+fn expand_is_pseudo_classes(
+    selector_item: &GenericSelector<SelectorImplDescriptor>,
+) -> Vec<Vec<Component<SelectorImplDescriptor>>> {
+    let mut expanded = vec![Vec::default()];
+
+    for component in selector_item.iter_raw_parse_order_from(0) {
+        let alternatives = match component {
+            Component::NonTSPseudoClass(NonTSPseudoClassDescriptor::Is(selector)) => {
+                // NOTE: the selector list has been parsed by the parser of the
+                // pseudo-class, so it's safe to unwrap here.
+                let selector: Selector = selector.parse().unwrap();
+
+                (selector.0)
+                    .0
+                    .iter()
+                    .flat_map(expand_is_pseudo_classes)
+                    .collect::<Vec<_>>()
+            }
+            _ => {
+                expanded
+                    .iter_mut()
+                    .for_each(|components| components.push(component.clone()));
+
+                continue;
+            }
+        };
+
+        let has_complex_alternatives = alternatives
+            .iter()
+            .any(|alternative| alternative.iter().any(is_combinator));
+
+        expanded = expanded
+            .into_iter()
+            .flat_map(|mut components| {
+                if !has_complex_alternatives {
+                    alternatives
+                        .iter()
+                        .map(|alternative| components.iter().chain(alternative).cloned().collect())
+                        .collect()
+                } else if !components.iter().any(is_combinator) {
+                    alternatives
+                        .iter()
+                        .map(|alternative| alternative.iter().chain(&components).cloned().collect())
+                        .collect()
+                } else {
+                    components.push(component.clone());
+
+                    vec![components]
+                }
+            })
+            .collect();
+    }
+
+    expanded
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub struct AstNode<P>
 where
     P: Hash + Eq,
@@ -221,7 +462,14 @@ where
     pub predicate: Predicate,
     pub children: Vec<AstNode<P>>,
     pub descendants: Vec<AstNode<P>>,
-    pub payload: HashSet<P>,
+    /// The nodes matched against the element that immediately follows the matched one.
+    pub next_siblings: Vec<AstNode<P>>,
+    /// The nodes matched against all the elements that follow the matched one.
+    pub later_siblings: Vec<AstNode<P>>,
+    /// The payloads of the selectors matched by the node, each along with the index of
+    /// the alternative of the selector list that is matched. If several alternatives of
+    /// the same list are matched by the node, the first one is recorded.
+    pub payload: HashMap<P, usize>,
 }
 
 impl<P> AstNode<P>
@@ -233,12 +481,14 @@ where
             predicate,
             children: Vec::default(),
             descendants: Vec::default(),
-            payload: HashSet::default(),
+            next_siblings: Vec::default(),
+            later_siblings: Vec::default(),
+            payload: HashMap::default(),
         }
     }
 }
 
-#[derive(Default, PartialEq, Eq, Debug)]
+#[derive(Default, PartialEq, Eq, Debug, Clone)]
 pub struct Ast<P>
 where
     P: PartialEq + Eq + Copy + Debug + Hash,
@@ -273,33 +523,113 @@ where
         }
     }
 
+    /// Adds the complex selector, whose compound selectors are given in the parse order along
+    /// with the combinators that follow them. The payload is attached to the last compound
+    /// selector, which doesn't have a combinator, along with the index of the alternative of
+    /// the selector list the complex selector comes from.
+    fn add_compound_selectors(
+        &mut self,
+        compound_selectors: impl IntoIterator<Item = (Predicate, Option<Combinator>)>,
+        payload: P,
+        alternative_idx: usize,
+    ) {
+        let mut branches = &mut self.root;
+
+        for (predicate, combinator) in compound_selectors {
+            let node_idx =
+                Self::host_expressions(predicate, branches, &mut self.cumulative_node_count);
+
+            let node = &mut branches[node_idx];
+
+            branches = match combinator {
+                Some(Combinator::Child) => &mut node.children,
+                Some(Combinator::Descendant) => &mut node.descendants,
+                Some(Combinator::NextSibling) => &mut node.next_siblings,
+                Some(Combinator::LaterSibling) => &mut node.later_siblings,
+                None => {
+                    node.payload.entry(payload).or_insert(alternative_idx);
+
+                    return;
+                }
+            };
+        }
+    }
+
+    #[cfg(not(feature = "selector_matching"))]
+    #[inline]
     pub fn add_selector(&mut self, selector: &Selector, payload: P) {
-        for selector_item in &(selector.0).0 {
-            let mut predicate = Predicate::default();
-            let mut branches = &mut self.root;
-
-            macro_rules! host_and_switch_branch_vec {
-                ($branches:ident) => {{
-                    let node_idx = Self::host_expressions(
-                        predicate,
-                        branches,
-                        &mut self.cumulative_node_count,
-                    );
+        self.add_basic_selector(selector, payload);
+    }
 
-                    branches = &mut branches[node_idx].$branches;
-                    predicate = Predicate::default();
-                }};
-            }
+    /// Adds the selector parsed by the basic parser. With the `selector_matching` feature it's
+    /// used only in the tests to check that both parsers produce the same AST.
+    #[cfg(any(test, not(feature = "selector_matching")))]
+    pub fn add_basic_selector(&mut self, selector: &BasicSelector, payload: P) {
+        let complex_selectors = selector.complex_selectors().iter().enumerate();
 
-            for component in selector_item.iter_raw_parse_order_from(0) {
+        for (alternative_idx, compound_selectors) in complex_selectors {
+            let compound_selectors = compound_selectors.iter().map(|(conditions, combinator)| {
+                let mut predicate = Predicate::default();
+
+                for condition in conditions {
+                    predicate.add_condition(condition.clone(), false);
+                }
+
+                (predicate, *combinator)
+            });
+
+            self.add_compound_selectors(compound_selectors, payload, alternative_idx);
+        }
+    }
+
+    #[cfg(feature = "selector_matching")]
+    pub fn add_selector(&mut self, selector: &Selector, payload: P) {
+        let expanded = (selector.0)
+            .0
+            .iter()
+            .enumerate()
+            .flat_map(|(alternative_idx, item)| {
+                expand_is_pseudo_classes(item)
+                    .into_iter()
+                    .map(move |components| (alternative_idx, components))
+            });
+
+        for (alternative_idx, components) in expanded {
+            let mut compound_selectors = Vec::new();
+            let mut predicate = Predicate::default();
+
+            for component in &components {
                 match component {
-                    Component::Combinator(c) => match c {
-                        Combinator::Child => host_and_switch_branch_vec!(children),
-                        Combinator::Descendant => host_and_switch_branch_vec!(descendants),
-                        _ => unreachable!(
-                            "Unsupported selector components should be filtered out by the parser."
-                        ),
-                    },
+                    Component::Combinator(c) => {
+                        let combinator = match c {
+                            GenericCombinator::Child => Combinator::Child,
+                            GenericCombinator::Descendant => Combinator::Descendant,
+                            GenericCombinator::NextSibling => Combinator::NextSibling,
+                            GenericCombinator::LaterSibling => Combinator::LaterSibling,
+                            _ => unreachable!(
+                                "Unsupported selector components should be filtered out by the parser."
+                            ),
+                        };
+
+                        compound_selectors.push((predicate, Some(combinator)));
+                        predicate = Predicate::default();
+                    }
+                    // NOTE: a type selector with a namespace prefix is a single simple selector
+                    // that consists of two components, so it can only be negated as a whole.
+                    Component::Negation(c) if c.len() > 1 => {
+                        let mut selector = String::new();
+
+                        for c in c.iter() {
+                            // NOTE: writing to a string never fails.
+                            let _ = c.to_css(&mut selector);
+                        }
+
+                        add_expr_to_list(
+                            &mut predicate.on_tag_name_exprs,
+                            OnTagNameExpr::Matches(selector),
+                            true,
+                        );
+                    }
                     Component::Negation(c) => {
                         c.iter().for_each(|c| predicate.add_component(c, true))
                     }
@@ -307,11 +637,96 @@ where
                 }
             }
 
-            let node_idx =
-                Self::host_expressions(predicate, branches, &mut self.cumulative_node_count);
+            compound_selectors.push((predicate, None));
+
+            self.add_compound_selectors(compound_selectors, payload, alternative_idx);
+        }
+    }
 
-            branches[node_idx].payload.insert(payload);
+    /// Returns `true` if any of the selectors has conditions on element's attributes.
+    pub fn has_attribute_exprs(&self) -> bool {
+        fn check<P: Hash + Eq>(nodes: &[AstNode<P>]) -> bool {
+            nodes.iter().any(|node| {
+                !node.predicate.on_attr_exprs.is_empty()
+                    // NOTE: the negated selectors can have conditions on attributes as well.
+                    || node.predicate.on_tag_name_exprs.iter().any(|expr| {
+                        matches!(expr.simple_expr, OnTagNameExpr::Matches(_))
+                    })
+                    || check(&node.children)
+                    || check(&node.descendants)
+                    || check(&node.next_siblings)
+                    || check(&node.later_siblings)
+            })
+        }
+
+        check(&self.root)
+    }
+
+    /// Replaces the payloads of the selectors with the ones returned by `map_payload`. The payloads
+    /// it maps to `None` are removed along with the nodes that are left without any payloads.
+    pub fn map_payload(&mut self, map_payload: impl Fn(P) -> Option<P>) {
+        fn map_nodes<P: Hash + Eq>(
+            nodes: Vec<AstNode<P>>,
+            map_payload: &dyn Fn(P) -> Option<P>,
+            node_count: &mut usize,
+        ) -> Vec<AstNode<P>> {
+            nodes
+                .into_iter()
+                .filter_map(|node| {
+                    let node = AstNode {
+                        predicate: node.predicate,
+                        children: map_nodes(node.children, map_payload, node_count),
+                        descendants: map_nodes(node.descendants, map_payload, node_count),
+                        next_siblings: map_nodes(node.next_siblings, map_payload, node_count),
+                        later_siblings: map_nodes(node.later_siblings, map_payload, node_count),
+                        payload: node
+                            .payload
+                            .into_iter()
+                            .filter_map(|(payload, alternative_idx)| {
+                                map_payload(payload).map(|payload| (payload, alternative_idx))
+                            })
+                            .collect(),
+                    };
+
+                    let is_dead = node.payload.is_empty()
+                        && node.children.is_empty()
+                        && node.descendants.is_empty()
+                        && node.next_siblings.is_empty()
+                        && node.later_siblings.is_empty();
+
+                    if is_dead {
+                        None
+                    } else {
+                        *node_count += 1;
+                        Some(node)
+                    }
+                })
+                .collect()
         }
+
+        let root = mem::take(&mut self.root);
+
+        self.cumulative_node_count = 0;
+        self.root = map_nodes(root, &map_payload, &mut self.cumulative_node_count);
+    }
+
+    /// Returns `true` if all the selectors can match only the element with
+    /// the given local name and its descendants.
+    pub fn is_scoped_to(&self, local_name: &str) -> bool {
+        // NOTE: the siblings of the element are outside of it, so the selectors
+        // with sibling combinators in the root nodes aren't scoped to the element.
+        let requires_local_name = |node: &AstNode<P>| {
+            node.next_siblings.is_empty()
+                && node.later_siblings.is_empty()
+                && node.predicate.on_tag_name_exprs.iter().any(|expr| {
+                    !expr.negation && match expr.simple_expr {
+                        OnTagNameExpr::LocalName(ref name) => name.eq_ignore_ascii_case(local_name),
+                        _ => false,
+                    }
+                })
+        };
+
+        !self.root.is_empty() && self.root.iter().all(requires_local_name)
     }
 }
 
@@ -320,26 +735,48 @@ mod tests {
     use super::*;
     use crate::selectors_vm::SelectorError;
 
-    macro_rules! set {
-        ($($items:expr),*) => {
-            vec![$($items),*].into_iter().collect::<HashSet<_>>()
+    macro_rules! payload {
+        ($($payload:expr => $alternative_idx:expr),*) => {
+            vec![$(($payload, $alternative_idx)),*].into_iter().collect::<HashMap<_, _>>()
+        };
+        ($($payload:expr),*) => {
+            payload![$($payload => 0),*]
         };
     }
 
-    fn assert_ast(selectors: &[&str], expected: Ast<usize>) {
+    fn create_ast(selectors: &[&str]) -> Ast<usize> {
         let mut ast = Ast::default();
 
         for (idx, selector) in selectors.iter().enumerate() {
             ast.add_selector(&selector.parse().unwrap(), idx);
         }
 
-        assert_eq!(ast, expected);
+        ast
+    }
+
+    /// Resets the indices of the matched alternatives, so the AST of a selector can be compared
+    /// with the AST of the selector list it's equivalent to.
+    #[cfg(feature = "selector_matching")]
+    fn reset_alternative_indices(nodes: &mut [AstNode<usize>]) {
+        for node in nodes {
+            node.payload.values_mut().for_each(|idx| *idx = 0);
+
+            reset_alternative_indices(&mut node.children);
+            reset_alternative_indices(&mut node.descendants);
+            reset_alternative_indices(&mut node.next_siblings);
+            reset_alternative_indices(&mut node.later_siblings);
+        }
+    }
+
+    fn assert_ast(selectors: &[&str], expected: Ast<usize>) {
+        assert_eq!(create_ast(selectors), expected);
     }
 
     fn assert_err(selector: &str, expected_err: SelectorError) {
         assert_eq!(selector.parse::<Selector>().unwrap_err(), expected_err);
     }
 
+    #[cfg(feature = "selector_matching")]
     #[test]
     fn simple_non_attr_expression() {
         vec![
@@ -364,6 +801,13 @@ mod tests {
                     negation: false,
                 },
             ),
+            (
+                "[svg|href]",
+                Expr {
+                    simple_expr: OnTagNameExpr::Unmatchable,
+                    negation: false,
+                },
+            ),
             (
                 ":not(div)",
                 Expr {
@@ -371,6 +815,89 @@ mod tests {
                     negation: true,
                 },
             ),
+            (
+                ":ctx(mobile)",
+                Expr {
+                    simple_expr: OnTagNameExpr::Ctx("mobile".into()),
+                    negation: false,
+                },
+            ),
+            (
+                ":not(:ctx(mobile))",
+                Expr {
+                    simple_expr: OnTagNameExpr::Ctx("mobile".into()),
+                    negation: true,
+                },
+            ),
+            (
+                ":has(> img.hero)",
+                Expr {
+                    simple_expr: OnTagNameExpr::Has("> img.hero".into()),
+                    negation: false,
+                },
+            ),
+            (
+                ":not(:has( img ))",
+                Expr {
+                    simple_expr: OnTagNameExpr::Has("img".into()),
+                    negation: true,
+                },
+            ),
+            (
+                ":not( div > p )",
+                Expr {
+                    simple_expr: OnTagNameExpr::Matches("div > p".into()),
+                    negation: true,
+                },
+            ),
+            (
+                ":not(.foo :not(.bar), span)",
+                Expr {
+                    simple_expr: OnTagNameExpr::Matches(".foo :not(.bar), span".into()),
+                    negation: true,
+                },
+            ),
+            (
+                ":not(:is(.foo, .bar))",
+                Expr {
+                    simple_expr: OnTagNameExpr::Matches(".foo, .bar".into()),
+                    negation: true,
+                },
+            ),
+            (
+                ":not(svg|path)",
+                Expr {
+                    simple_expr: OnTagNameExpr::Matches("svg|path".into()),
+                    negation: true,
+                },
+            ),
+            (
+                ":root",
+                Expr {
+                    simple_expr: OnTagNameExpr::Root,
+                    negation: false,
+                },
+            ),
+            (
+                ":not(:last-child)",
+                Expr {
+                    simple_expr: OnTagNameExpr::LastSibling {
+                        of_type: false,
+                        only: false,
+                    },
+                    negation: true,
+                },
+            ),
+            (
+                ":only-of-type",
+                Expr {
+                    simple_expr: OnTagNameExpr::LastSibling {
+                        of_type: true,
+                        only: true,
+                    },
+                    negation: false,
+                },
+            ),
         ]
         .into_iter()
         .for_each(|(selector, expected)| {
@@ -384,7 +911,9 @@ mod tests {
                         },
                         children: vec![],
                         descendants: vec![],
-                        payload: set![0],
+                        next_siblings: vec![],
+                        later_siblings: vec![],
+                        payload: payload![0],
                     }],
                     cumulative_node_count: 1,
                 },
@@ -392,6 +921,7 @@ mod tests {
         });
     }
 
+    #[cfg(feature = "selector_matching")]
     #[test]
     fn simple_attr_expression() {
         vec![
@@ -409,6 +939,20 @@ mod tests {
                     negation: false,
                 },
             ),
+            (
+                ":lang(en-GB)",
+                Expr {
+                    simple_expr: OnAttributesExpr::Lang("en-GB".into()),
+                    negation: false,
+                },
+            ),
+            (
+                ":not(:lang(fr))",
+                Expr {
+                    simple_expr: OnAttributesExpr::Lang("fr".into()),
+                    negation: true,
+                },
+            ),
             (
                 "[foo]",
                 Expr {
@@ -416,6 +960,32 @@ mod tests {
                     negation: false,
                 },
             ),
+            (
+                "[xlink|href]",
+                Expr {
+                    simple_expr: OnAttributesExpr::AttributeExists("xlink:href".into()),
+                    negation: false,
+                },
+            ),
+            (
+                "[*|foo]",
+                Expr {
+                    simple_expr: OnAttributesExpr::AttributeExists("foo".into()),
+                    negation: false,
+                },
+            ),
+            (
+                r#"[xml|lang="en"]"#,
+                Expr {
+                    simple_expr: OnAttributesExpr::AttributeComparisonExpr(AttributeComparisonExpr {
+                        name: "xml:lang".into(),
+                        value: "en".into(),
+                        case_sensitivity: ParsedCaseSensitivity::CaseSensitive,
+                        operator: AttrSelectorOperator::Equal,
+                    }),
+                    negation: false,
+                },
+            ),
             (
                 r#"[foo="bar"]"#,
                 Expr {
@@ -428,6 +998,25 @@ mod tests {
                     negation: false,
                 },
             ),
+            (
+                r#"[is="fancy-button"]"#,
+                Expr {
+                    simple_expr: OnAttributesExpr::CustomizedBuiltIn("fancy-button".into()),
+                    negation: false,
+                },
+            ),
+            (
+                r#"[is="fancy-button" i]"#,
+                Expr {
+                    simple_expr: OnAttributesExpr::AttributeComparisonExpr(AttributeComparisonExpr {
+                        name: "is".into(),
+                        value: "fancy-button".into(),
+                        case_sensitivity: ParsedCaseSensitivity::AsciiCaseInsensitive,
+                        operator: AttrSelectorOperator::Equal,
+                    }),
+                    negation: false,
+                },
+            ),
             (
                 r#"[foo~="bar" i]"#,
                 Expr {
@@ -513,7 +1102,9 @@ mod tests {
                         },
                         children: vec![],
                         descendants: vec![],
-                        payload: set![0],
+                        next_siblings: vec![],
+                        later_siblings: vec![],
+                        payload: payload![0],
                     }],
                     cumulative_node_count: 1,
                 },
@@ -521,6 +1112,7 @@ mod tests {
         });
     }
 
+    #[cfg(feature = "selector_matching")]
     #[test]
     fn compound_selectors() {
         assert_ast(
@@ -549,7 +1141,9 @@ mod tests {
                     },
                     children: vec![],
                     descendants: vec![],
-                    payload: set![0],
+                    next_siblings: vec![],
+                    later_siblings: vec![],
+                    payload: payload![0],
                 }],
                 cumulative_node_count: 1,
             },
@@ -571,7 +1165,9 @@ mod tests {
                     },
                     children: vec![],
                     descendants: vec![],
-                    payload: set![0, 1],
+                    next_siblings: vec![],
+                    later_siblings: vec![],
+                    payload: payload![0, 1],
                 }],
                 cumulative_node_count: 1,
             },
@@ -602,7 +1198,9 @@ mod tests {
                             },
                             children: vec![],
                             descendants: vec![],
-                            payload: set![0],
+                            next_siblings: vec![],
+                            later_siblings: vec![],
+                            payload: payload![0],
                         },
                         AstNode {
                             predicate: Predicate {
@@ -614,7 +1212,9 @@ mod tests {
                             },
                             children: vec![],
                             descendants: vec![],
-                            payload: set![0],
+                            next_siblings: vec![],
+                            later_siblings: vec![],
+                            payload: payload![0 => 1],
                         },
                         AstNode {
                             predicate: Predicate {
@@ -626,7 +1226,9 @@ mod tests {
                             },
                             children: vec![],
                             descendants: vec![],
-                            payload: set![1],
+                            next_siblings: vec![],
+                            later_siblings: vec![],
+                            payload: payload![1],
                         },
                         AstNode {
                             predicate: Predicate {
@@ -638,11 +1240,15 @@ mod tests {
                             },
                             children: vec![],
                             descendants: vec![],
-                            payload: set![1],
+                            next_siblings: vec![],
+                            later_siblings: vec![],
+                            payload: payload![1 => 1],
                         },
                     ],
                     descendants: vec![],
-                    payload: set![],
+                    next_siblings: vec![],
+                    later_siblings: vec![],
+                    payload: payload![],
                 }],
                 cumulative_node_count: 5,
             },
@@ -700,9 +1306,13 @@ mod tests {
                                             },
                                             children: vec![],
                                             descendants: vec![],
-                                            payload: set![0],
+                                            next_siblings: vec![],
+                                            later_siblings: vec![],
+                                            payload: payload![0],
                                         }],
-                                        payload: set![],
+                                        next_siblings: vec![],
+                                        later_siblings: vec![],
+                                        payload: payload![],
                                     },
                                     AstNode {
                                         predicate: Predicate {
@@ -714,10 +1324,14 @@ mod tests {
                                         },
                                         children: vec![],
                                         descendants: vec![],
-                                        payload: set![1],
+                                        next_siblings: vec![],
+                                        later_siblings: vec![],
+                                        payload: payload![1],
                                     },
                                 ],
-                                payload: set![],
+                                next_siblings: vec![],
+                                later_siblings: vec![],
+                                payload: payload![],
                             },
                             AstNode {
                                 predicate: Predicate {
@@ -729,7 +1343,9 @@ mod tests {
                                 },
                                 children: vec![],
                                 descendants: vec![],
-                                payload: set![2],
+                                next_siblings: vec![],
+                                later_siblings: vec![],
+                                payload: payload![2],
                             },
                         ],
                         descendants: vec![
@@ -743,7 +1359,9 @@ mod tests {
                                 },
                                 children: vec![],
                                 descendants: vec![],
-                                payload: set![3],
+                                next_siblings: vec![],
+                                later_siblings: vec![],
+                                payload: payload![3],
                             },
                             AstNode {
                                 predicate: Predicate {
@@ -766,12 +1384,18 @@ mod tests {
                                     },
                                     children: vec![],
                                     descendants: vec![],
-                                    payload: set![4],
+                                    next_siblings: vec![],
+                                    later_siblings: vec![],
+                                    payload: payload![4],
                                 }],
-                                payload: set![],
+                                next_siblings: vec![],
+                                later_siblings: vec![],
+                                payload: payload![],
                             },
                         ],
-                        payload: set![],
+                        next_siblings: vec![],
+                        later_siblings: vec![],
+                        payload: payload![],
                     },
                     AstNode {
                         predicate: Predicate {
@@ -783,7 +1407,9 @@ mod tests {
                         },
                         children: vec![],
                         descendants: vec![],
-                        payload: set![5],
+                        next_siblings: vec![],
+                        later_siblings: vec![],
+                        payload: payload![5],
                     },
                 ],
                 cumulative_node_count: 10,
@@ -791,6 +1417,144 @@ mod tests {
         );
     }
 
+    #[test]
+    fn sibling_combinators() {
+        let tag_name_predicate = |name: &str| Predicate {
+            on_tag_name_exprs: vec![Expr {
+                simple_expr: OnTagNameExpr::LocalName(name.into()),
+                negation: false,
+            }],
+            ..Default::default()
+        };
+
+        assert_ast(
+            &["h2 + p", "h2 ~ p > a", "h2 ~ ul"],
+            Ast {
+                root: vec![AstNode {
+                    predicate: tag_name_predicate("h2"),
+                    children: vec![],
+                    descendants: vec![],
+                    next_siblings: vec![AstNode {
+                        predicate: tag_name_predicate("p"),
+                        children: vec![],
+                        descendants: vec![],
+                        next_siblings: vec![],
+                        later_siblings: vec![],
+                        payload: payload![0],
+                    }],
+                    later_siblings: vec![
+                        AstNode {
+                            predicate: tag_name_predicate("p"),
+                            children: vec![AstNode {
+                                predicate: tag_name_predicate("a"),
+                                children: vec![],
+                                descendants: vec![],
+                                next_siblings: vec![],
+                                later_siblings: vec![],
+                                payload: payload![1],
+                            }],
+                            descendants: vec![],
+                            next_siblings: vec![],
+                            later_siblings: vec![],
+                            payload: payload![],
+                        },
+                        AstNode {
+                            predicate: tag_name_predicate("ul"),
+                            children: vec![],
+                            descendants: vec![],
+                            next_siblings: vec![],
+                            later_siblings: vec![],
+                            payload: payload![2],
+                        },
+                    ],
+                    payload: payload![],
+                }],
+                cumulative_node_count: 5,
+            },
+        );
+    }
+
+    #[cfg(feature = "selector_matching")]
+    #[test]
+    fn is_pseudo_class_expansion() {
+        vec![
+            (":is(article, section) h2", "article h2, section h2"),
+            ("div:where(.foo, #bar) > p", "div.foo > p, div#bar > p"),
+            (":is(a, b) :IS(c, d)", "a c, a d, b c, b d"),
+            (":is(ul li, ol > li).item", "ul li.item, ol > li.item"),
+        ]
+        .into_iter()
+        .for_each(|(selector, expanded)| {
+            let mut expected = create_ast(&[expanded]);
+
+            reset_alternative_indices(&mut expected.root);
+
+            assert_eq!(create_ast(&[selector]), expected);
+        });
+
+        // NOTE: the complex selectors can't be expanded after the combinator.
+        let ast = create_ast(&["div :is(ul li, ol > li)"]);
+
+        assert_eq!(
+            ast.root[0].descendants[0].predicate.on_tag_name_exprs,
+            vec![Expr {
+                simple_expr: OnTagNameExpr::Matches("ul li, ol > li".into()),
+                negation: false,
+            }]
+        );
+    }
+
+    #[cfg(feature = "selector_matching")]
+    #[test]
+    fn mapped_payloads() {
+        let mut ast = create_ast(&["div > span", "div > p", "a + b", "a"]);
+
+        ast.map_payload(|payload| match payload {
+            1 | 2 => None,
+            3 => Some(1),
+            payload => Some(payload),
+        });
+
+        assert_eq!(ast, create_ast(&["div > span", "a"]));
+    }
+
+    #[cfg(feature = "selector_matching")]
+    #[test]
+    fn namespace_prefixes() {
+        for &(selector, ns) in &[
+            ("svg|path", Namespace::Svg),
+            ("math|path", Namespace::MathML),
+        ] {
+            assert_ast(
+                &[selector],
+                Ast {
+                    root: vec![AstNode {
+                        predicate: Predicate {
+                            on_tag_name_exprs: vec![
+                                Expr {
+                                    simple_expr: OnTagNameExpr::LocalName("path".into()),
+                                    negation: false,
+                                },
+                                Expr {
+                                    simple_expr: OnTagNameExpr::Namespace(ns),
+                                    negation: false,
+                                },
+                            ],
+                            ..Default::default()
+                        },
+                        children: vec![],
+                        descendants: vec![],
+                        next_siblings: vec![],
+                        later_siblings: vec![],
+                        payload: payload![0],
+                    }],
+                    cumulative_node_count: 1,
+                },
+            );
+        }
+    }
+
+    #[cfg(feature = "selector_matching")]
     #[test]
     fn parse_errors() {
         assert_err("div@", SelectorError::UnexpectedToken);
@@ -803,11 +1567,10 @@ mod tests {
             SelectorError::UnexpectedTokenInAttribute,
         );
         assert_err(":not(:not(p))", SelectorError::NestedNegation);
-        assert_err("svg|img", SelectorError::NamespacedSelector);
+        assert_err("foo|img", SelectorError::NamespacedSelector);
+        assert_err("[foo|href]", SelectorError::NamespacedSelector);
         assert_err(".foo()", SelectorError::InvalidClassName);
         assert_err(":not()", SelectorError::EmptyNegation);
-        assert_err("div + span", SelectorError::UnsupportedCombinator('+'));
-        assert_err("div ~ span", SelectorError::UnsupportedCombinator('~'));
     }
 
     #[test]
@@ -831,7 +1594,12 @@ mod tests {
             ":focus",
             ":focus-visible",
             ":focus-within",
-            ":has(div)",
+            ":has(div p)",
+            ":has(+ div)",
+            ":has(div, p)",
+            ":has(:has(div))",
+            ":has(:last-child)",
+            ":has()",
             ":host",
             ":host(h1)",
             ":host-context(h1)",
@@ -839,10 +1607,8 @@ mod tests {
             ":indeterminate",
             ":in-range",
             ":invalid",
-            ":is(header)",
-            ":lang(en)",
-            ":last-child",
-            ":last-of-type",
+            ":is(:has(div))",
+            ":is(:only-child)",
             ":left",
             ":link",
             ":local-link",
@@ -850,8 +1616,6 @@ mod tests {
             ":nth-last-child(1)",
             ":nth-last-col(1)",
             ":nth-last-of-type(1)",
-            ":only-child",
-            ":only-of-type",
             ":optional",
             ":out-of-range",
             ":past",
@@ -860,14 +1624,13 @@ mod tests {
             ":read-write",
             ":required",
             ":right",
-            ":root",
             ":scope",
             ":target",
             ":target-within",
             ":user-invalid",
             ":valid",
             ":visited",
-            ":where(p)",
+            ":where(div:has(p))",
         ]
         .iter()
         .for_each(|s| assert_err(s, SelectorError::UnsupportedPseudoClassOrElement));
@@ -899,6 +1662,77 @@ mod tests {
             ":not(:nth-last-child(even))",
             SelectorError::UnsupportedPseudoClassOrElement,
         );
+
+        assert_err(
+            ":not(div :has(p))",
+            SelectorError::UnsupportedPseudoClassOrElement,
+        );
+    }
+
+    #[cfg(feature = "selector_matching")]
+    #[test]
+    fn has_conditions() {
+        let assert_conditions = |selector: &str, expected: &[(&str, &str)]| {
+            let conditions: Vec<_> = selector
+                .parse::<Selector>()
+                .unwrap()
+                .has_conditions()
+                .iter()
+                .map(|c| (c.candidate.to_owned(), c.relative_selector()))
+                .collect();
+
+            let expected: Vec<_> = expected
+                .iter()
+                .map(|&(candidate, relative)| (candidate.to_owned(), relative.to_owned()))
+                .collect();
+
+            assert_eq!(conditions, expected, "Selector: {}", selector);
+        };
+
+        assert_conditions("div.foo", &[]);
+        assert_conditions("div:has(> img.hero)", &[("div", "div > img.hero")]);
+        assert_conditions(
+            "body div.foo:has(img):not(.bar) > p",
+            &[("div.foo:not(.bar)", "div.foo:not(.bar) img")],
+        );
+        assert_conditions(":has(img)", &[("*", "* img")]);
+        assert_conditions(":not(:has(img))", &[("*", "* img")]);
+        assert_conditions(
+            "div:has(img), p:has(> a) span:has(b)",
+            &[("div", "div img"), ("p", "p > a"), ("span", "span b")],
+        );
+        assert_conditions("li:last-child:has(a)", &[("li", "li a")]);
+    }
+
+    #[cfg(feature = "selector_matching")]
+    #[test]
+    fn last_sibling_conditions() {
+        let assert_conditions = |selector: &str, expected: &[(&str, bool)]| {
+            let conditions: Vec<_> = selector
+                .parse::<Selector>()
+                .unwrap()
+                .last_sibling_conditions()
+                .iter()
+                .map(|c| (c.candidate.to_owned(), c.of_type))
+                .collect();
+
+            let expected: Vec<_> = expected
+                .iter()
+                .map(|&(candidate, of_type)| (candidate.to_owned(), of_type))
+                .collect();
+
+            assert_eq!(conditions, expected, "Selector: {}", selector);
+        };
+
+        assert_conditions("li:first-child", &[]);
+        assert_conditions("li:last-child", &[("li", false)]);
+        assert_conditions("li:only-child:last-child", &[("li", false)]);
+        assert_conditions("ul > li.foo:not(:last-of-type)", &[("li.foo", true)]);
+        assert_conditions(":only-of-type", &[("*", true)]);
+        assert_conditions(
+            "p:last-child span:only-of-type:has(b)",
+            &[("p", false), ("span", true)],
+        );
     }
 
     #[test]
@@ -917,4 +1751,28 @@ mod tests {
         assert!(!first.has_index(2));
         assert!(!first.has_index(3));
     }
+
+    #[cfg(feature = "selector_matching")]
+    #[test]
+    fn attribute_exprs_detection() {
+        assert!(!create_ast(&["div", "div > span *", ":not(p)"]).has_attribute_exprs());
+        assert!(create_ast(&["div", "div > span.foo"]).has_attribute_exprs());
+        assert!(create_ast(&["[foo] p"]).has_attribute_exprs());
+        assert!(create_ast(&["p:not(#foo)"]).has_attribute_exprs());
+        assert!(create_ast(&["p:lang(en)"]).has_attribute_exprs());
+        assert!(create_ast(&["p:not(div > span)"]).has_attribute_exprs());
+    }
+
+    #[cfg(feature = "selector_matching")]
+    #[test]
+    fn scoped_to_element_detection() {
+        assert!(create_ast(&["head", "head > meta", "HEAD link[rel]"]).is_scoped_to("head"));
+        assert!(create_ast(&["head.foo title"]).is_scoped_to("head"));
+        assert!(!create_ast(&["head meta", "title"]).is_scoped_to("head"));
+        assert!(!create_ast(&[":not(head) meta"]).is_scoped_to("head"));
+        assert!(!create_ast(&["* > head"]).is_scoped_to("head"));
+        assert!(!create_ast(&["head + body"]).is_scoped_to("head"));
+        assert!(create_ast(&["head meta ~ link"]).is_scoped_to("head"));
+        assert!(!Ast::<usize>::default().is_scoped_to("head"));
+    }
 }
@@ -0,0 +1,57 @@
+//! The operators and the case sensitivities of the attribute selectors.
+//!
+//! With the full CSS selector parser they are re-exported from the `selectors` crate. Otherwise
+//! the basic selector parser uses the copies of them, so the VM doesn't depend on the crate.
+
+use cfg_if::cfg_if;
+
+cfg_if! {
+    if #[cfg(feature = "selector_matching")] {
+        pub use selectors::attr::{AttrSelectorOperator, CaseSensitivity, ParsedCaseSensitivity};
+    } else {
+        #[derive(Clone, Copy, Eq, PartialEq)]
+        pub enum AttrSelectorOperator {
+            Equal,
+            Includes,
+            DashMatch,
+            Prefix,
+            Substring,
+            Suffix,
+        }
+
+        #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+        pub enum ParsedCaseSensitivity {
+            /// The `s` flag was specified.
+            ExplicitCaseSensitive,
+            /// The `i` flag was specified.
+            AsciiCaseInsensitive,
+            /// No flags were specified and HTML says this is a case-sensitive attribute.
+            CaseSensitive,
+            /// No flags were specified and HTML says this is a case-insensitive attribute.
+            AsciiCaseInsensitiveIfInHtmlElementInHtmlDocument,
+        }
+
+        impl ParsedCaseSensitivity {
+            #[inline]
+            pub fn to_unconditional(self, is_html_element_in_html_document: bool) -> CaseSensitivity {
+                match self {
+                    ParsedCaseSensitivity::AsciiCaseInsensitiveIfInHtmlElementInHtmlDocument
+                        if is_html_element_in_html_document =>
+                    {
+                        CaseSensitivity::AsciiCaseInsensitive
+                    }
+                    ParsedCaseSensitivity::AsciiCaseInsensitiveIfInHtmlElementInHtmlDocument
+                    | ParsedCaseSensitivity::CaseSensitive
+                    | ParsedCaseSensitivity::ExplicitCaseSensitive => CaseSensitivity::CaseSensitive,
+                    ParsedCaseSensitivity::AsciiCaseInsensitive => CaseSensitivity::AsciiCaseInsensitive,
+                }
+            }
+        }
+
+        #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+        pub enum CaseSensitivity {
+            CaseSensitive,
+            AsciiCaseInsensitive,
+        }
+    }
+}
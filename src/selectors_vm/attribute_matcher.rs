@@ -1,49 +1,122 @@
+use super::attr::CaseSensitivity;
 use super::compiler::AttrExprOperands;
+use super::match_cache::InstrResults;
 use crate::base::Bytes;
-use crate::html::Namespace;
-use crate::parser::{AttributeOutline, SharedAttributeBuffer};
-use encoding_rs::UTF_8;
-use lazy_static::lazy_static;
-use lazycell::LazyCell;
-use memchr::{memchr, memchr2};
-use selectors::attr::CaseSensitivity;
-
-lazy_static! {
-    static ref ID_ATTR: Bytes<'static> = Bytes::from_str("id", UTF_8);
-    static ref CLASS_ATTR: Bytes<'static> = Bytes::from_str("class", UTF_8);
-}
-
+use crate::html::{is_attr_whitespace, Namespace};
+use crate::parser::{AttributeNamesBloom, AttributeOutline};
+use hashbrown::HashMap;
+use memchr::memchr;
+
+const ID_ATTR: Bytes<'static> = Bytes::from_static(b"id");
+const CLASS_ATTR: Bytes<'static> = Bytes::from_static(b"class");
+const IS_ATTR: Bytes<'static> = Bytes::from_static(b"is");
+const LANG_ATTR: Bytes<'static> = Bytes::from_static(b"lang");
+
+/// Returns `true` if the language is equal to the language range or starts with the range
+/// followed by `-` (e.g. `en-GB` matches `en`). The comparison is ASCII case-insensitive.
 #[inline]
-fn is_attr_whitespace(b: u8) -> bool {
-    b == b' ' || b == b'\n' || b == b'\r' || b == b'\t' || b == b'\x0c'
+pub fn lang_matches(lang: &[u8], range: &[u8]) -> bool {
+    lang.len() >= range.len()
+        && lang[..range.len()].eq_ignore_ascii_case(range)
+        && (lang.len() == range.len() || lang[range.len()] == b'-')
 }
 
-type MemoizedAttrValue<'i> = LazyCell<Option<Bytes<'i>>>;
+type MemoizedAttrValue<'i> = Option<Option<Bytes<'i>>>;
 
 pub struct AttributeMatcher<'i> {
     input: &'i Bytes<'i>,
-    attributes: SharedAttributeBuffer,
+    attributes: &'i [AttributeOutline],
     id: MemoizedAttrValue<'i>,
     class: MemoizedAttrValue<'i>,
+    is: MemoizedAttrValue<'i>,
+    lang: MemoizedAttrValue<'i>,
     is_html_element: bool,
+    names_bloom: AttributeNamesBloom,
+    comparison_results: HashMap<usize, bool>,
+    lowercased_values: HashMap<usize, Option<Vec<u8>>>,
+    instr_results: Option<InstrResults>,
 }
 
 impl<'i> AttributeMatcher<'i> {
     #[inline]
-    pub fn new(input: &'i Bytes<'i>, attributes: SharedAttributeBuffer, ns: Namespace) -> Self {
+    pub fn new(
+        input: &'i Bytes<'i>,
+        attributes: &'i [AttributeOutline],
+        names_bloom: AttributeNamesBloom,
+        ns: Namespace,
+    ) -> Self {
         AttributeMatcher {
             input,
             attributes,
-            id: LazyCell::default(),
-            class: LazyCell::default(),
+            id: None,
+            class: None,
+            is: None,
+            lang: None,
             is_html_element: ns == Namespace::Html,
+            names_bloom,
+            comparison_results: HashMap::default(),
+            lowercased_values: HashMap::default(),
+            instr_results: None,
         }
     }
 
+    /// Returns the raw bytes of the attributes of the start tag, from the start of the first
+    /// attribute to the end of the last one.
+    #[inline]
+    pub fn raw_attributes(&self) -> &'i [u8] {
+        let input: &'i Bytes<'i> = self.input;
+
+        match (self.attributes.first(), self.attributes.last()) {
+            (Some(first), Some(last)) => &input[first.raw_range.start..last.raw_range.end],
+            _ => &[],
+        }
+    }
+
+    /// Enables memoization of the results of the instructions' attribute expressions for
+    /// the start tag, starting with the `results` memoized for the same attributes before.
+    #[inline]
+    pub fn memoize_instr_results(&mut self, results: InstrResults) {
+        self.instr_results = Some(results);
+    }
+
+    /// Returns the memoized results of the instructions' attribute expressions, if the
+    /// memoization is enabled.
+    #[inline]
+    pub fn into_instr_results(self) -> Option<InstrResults> {
+        self.instr_results
+    }
+
+    /// Returns the memoized result of the attribute expressions of the instruction at the
+    /// `addr` if the memoization is enabled, otherwise just runs the expressions.
+    #[inline]
+    pub fn memoized_instr(&mut self, addr: usize, exprs: impl FnOnce(&mut Self) -> bool) -> bool {
+        match self.instr_results {
+            Some(ref results) => {
+                if let Some(&result) = results.get(&addr) {
+                    return result;
+                }
+            }
+            None => return exprs(self),
+        }
+
+        let result = exprs(self);
+
+        if let Some(ref mut results) = self.instr_results {
+            results.insert(addr, result);
+        }
+
+        result
+    }
+
     #[inline]
     fn find(&self, lowercased_name: &Bytes) -> Option<AttributeOutline> {
+        // NOTE: the bloom filter of the attribute names is filled in by the lexer, so
+        // the selectors that require absent attributes don't scan the attribute buffer.
+        if !self.names_bloom.may_contain(lowercased_name) {
+            return None;
+        }
+
         self.attributes
-            .borrow()
             .iter()
             .find(|a| {
                 if lowercased_name.len() != a.name.end - a.name.start {
@@ -64,7 +137,7 @@ impl<'i> AttributeMatcher<'i> {
     }
 
     #[inline]
-    fn get_value(&self, lowercased_name: &Bytes) -> Option<Bytes<'i>> {
+    pub fn get_value(&self, lowercased_name: &Bytes) -> Option<Bytes<'i>> {
         self.find(lowercased_name)
             .map(|a| self.input.slice(a.value))
     }
@@ -75,127 +148,173 @@ impl<'i> AttributeMatcher<'i> {
     }
 
     #[inline]
-    pub fn has_id(&self, id: &Bytes) -> bool {
-        match self.id.borrow_with(|| self.get_value(&ID_ATTR)) {
+    pub fn has_id(&mut self, id: &Bytes) -> bool {
+        match self.id() {
             Some(actual_id) => actual_id == id,
             None => false,
         }
     }
 
     #[inline]
-    pub fn has_class(&self, class_name: &Bytes) -> bool {
-        match self.class.borrow_with(|| self.get_value(&CLASS_ATTR)) {
-            Some(class) => class
+    pub fn has_class(&mut self, class_name: &Bytes) -> bool {
+        if self.class.is_none() {
+            self.class = Some(self.get_value(&CLASS_ATTR));
+        }
+
+        match self.class {
+            Some(Some(ref class)) => class
                 .split(|&b| is_attr_whitespace(b))
                 .any(|actual_class_name| actual_class_name == &**class_name),
-            None => false,
+            _ => false,
         }
     }
 
     #[inline]
-    fn value_matches(&self, name: &Bytes, matcher: impl Fn(Bytes) -> bool) -> bool {
-        match self.get_value(name) {
-            Some(value) => matcher(value),
-            None => false,
+    pub fn is_customized_built_in(&mut self, name: &Bytes) -> bool {
+        if self.is.is_none() {
+            self.is = Some(self.get_value(&IS_ATTR));
+        }
+
+        match self.is {
+            Some(Some(ref actual_name)) => actual_name == name,
+            _ => false,
         }
     }
 
+    /// Returns the value of the element's `id` attribute.
     #[inline]
-    pub fn attr_eq(&self, operand: &AttrExprOperands) -> bool {
-        self.value_matches(&operand.name, |actual_value| {
-            operand
-                .case_sensitivity
-                .to_unconditional(self.is_html_element)
-                .eq(&actual_value, &operand.value)
-        })
+    pub fn id(&mut self) -> Option<&Bytes<'i>> {
+        if self.id.is_none() {
+            self.id = Some(self.get_value(&ID_ATTR));
+        }
+
+        self.id.as_ref().and_then(Option::as_ref)
     }
 
+    /// Returns the value of the element's own `lang` attribute.
     #[inline]
-    pub fn matches_splitted_by_whitespace(&self, operand: &AttrExprOperands) -> bool {
-        self.value_matches(&operand.name, |actual_value| {
-            let case_sensitivity = operand
-                .case_sensitivity
-                .to_unconditional(self.is_html_element);
+    pub fn lang(&mut self) -> Option<&Bytes<'i>> {
+        if self.lang.is_none() {
+            self.lang = Some(self.get_value(&LANG_ATTR));
+        }
 
-            actual_value
-                .split(|&b| is_attr_whitespace(b))
-                .any(|part| case_sensitivity.eq(part, &operand.value))
-        })
+        self.lang.as_ref().and_then(Option::as_ref)
     }
 
+    /// Returns `true` if the language of the element matches the language `range`. The element's
+    /// own `lang` attribute takes precedence over the `inherited_lang` of its parent.
     #[inline]
-    pub fn has_attr_with_prefix(&self, operand: &AttrExprOperands) -> bool {
-        self.value_matches(&operand.name, |actual_value| {
-            let case_sensitivity = operand
-                .case_sensitivity
-                .to_unconditional(self.is_html_element);
+    pub fn has_lang(&mut self, inherited_lang: Option<&[u8]>, range: &Bytes) -> bool {
+        match self.lang().map(|lang| &**lang).or(inherited_lang) {
+            Some(lang) => lang_matches(lang, range),
+            None => false,
+        }
+    }
 
-            let prefix_len = operand.value.len();
+    /// Returns the memoized result of the attribute comparison if it's shared by multiple
+    /// expressions, otherwise just runs the comparison.
+    #[inline]
+    pub fn memoized(
+        &mut self,
+        operand: &AttrExprOperands,
+        comparison: impl FnOnce(&mut Self) -> bool,
+    ) -> bool {
+        if !operand.is_shared() {
+            return comparison(self);
+        }
 
-            actual_value.len() >= prefix_len
-                && case_sensitivity.eq(&actual_value[..prefix_len], &operand.value)
-        })
+        if let Some(&result) = self.comparison_results.get(&operand.id) {
+            return result;
+        }
+
+        let result = comparison(self);
+
+        self.comparison_results.insert(operand.id, result);
+
+        result
     }
 
+    /// Runs the `matcher` with the actual attribute value and the expected value. For the ASCII
+    /// case-insensitive comparisons both values are lowercased, the attribute value is lowercased
+    /// once per start tag and shared by all the comparisons, so the matchers can compare bytes
+    /// directly.
     #[inline]
-    pub fn has_dash_matching_attr(&self, operand: &AttrExprOperands) -> bool {
-        self.value_matches(&operand.name, |actual_value| {
-            let case_sensitivity = operand
-                .case_sensitivity
-                .to_unconditional(self.is_html_element);
+    fn value_matches(
+        &mut self,
+        operand: &AttrExprOperands,
+        matcher: impl Fn(&[u8], &[u8]) -> bool,
+    ) -> bool {
+        match operand
+            .case_sensitivity
+            .to_unconditional(self.is_html_element)
+        {
+            CaseSensitivity::CaseSensitive => match self.get_value(&operand.name) {
+                Some(value) => matcher(&value, &operand.value),
+                None => false,
+            },
+            CaseSensitivity::AsciiCaseInsensitive => {
+                if !self.lowercased_values.contains_key(&operand.name_id) {
+                    let value = self
+                        .get_value(&operand.name)
+                        .map(|value| value.to_ascii_lowercase());
+
+                    self.lowercased_values.insert(operand.name_id, value);
+                }
 
-            if case_sensitivity.eq(&actual_value, &operand.value) {
-                return true;
+                match self.lowercased_values[&operand.name_id] {
+                    Some(ref value) => matcher(value, &operand.lowercased_value),
+                    None => false,
+                }
             }
+        }
+    }
 
-            let prefix_len = operand.value.len();
+    #[inline]
+    pub fn attr_eq(&mut self, operand: &AttrExprOperands) -> bool {
+        self.value_matches(operand, |actual_value, value| actual_value == value)
+    }
 
-            actual_value.get(prefix_len) == Some(&b'-')
-                && case_sensitivity.eq(&actual_value[..prefix_len], &operand.value)
+    #[inline]
+    pub fn matches_splitted_by_whitespace(&mut self, operand: &AttrExprOperands) -> bool {
+        self.value_matches(operand, |actual_value, value| {
+            actual_value
+                .split(|&b| is_attr_whitespace(b))
+                .any(|part| part == value)
         })
     }
 
     #[inline]
-    pub fn has_attr_with_suffix(&self, operand: &AttrExprOperands) -> bool {
-        self.value_matches(&operand.name, |actual_value| {
-            let case_sensitivity = operand
-                .case_sensitivity
-                .to_unconditional(self.is_html_element);
-
-            let suffix_len = operand.value.len();
-            let value_len = actual_value.len();
+    pub fn has_attr_with_prefix(&mut self, operand: &AttrExprOperands) -> bool {
+        self.value_matches(operand, |actual_value, value| {
+            actual_value.starts_with(value)
+        })
+    }
 
-            value_len >= suffix_len
-                && case_sensitivity.eq(&actual_value[value_len - suffix_len..], &operand.value)
+    #[inline]
+    pub fn has_dash_matching_attr(&mut self, operand: &AttrExprOperands) -> bool {
+        self.value_matches(operand, |actual_value, value| {
+            actual_value == value
+                || (actual_value.starts_with(value) && actual_value.get(value.len()) == Some(&b'-'))
         })
     }
 
     #[inline]
-    pub fn has_attr_with_substring(&self, operand: &AttrExprOperands) -> bool {
-        self.value_matches(&operand.name, |actual_value| {
-            let case_sensitivity = operand
-                .case_sensitivity
-                .to_unconditional(self.is_html_element);
+    pub fn has_attr_with_suffix(&mut self, operand: &AttrExprOperands) -> bool {
+        self.value_matches(operand, |actual_value, value| actual_value.ends_with(value))
+    }
 
-            let (first_byte, rest) = match operand.value.split_first() {
-                Some((&f, r)) => (f, r),
+    #[inline]
+    pub fn has_attr_with_substring(&mut self, operand: &AttrExprOperands) -> bool {
+        self.value_matches(operand, |actual_value, value| {
+            let (&first_byte, rest) = match value.split_first() {
+                Some(split) => split,
                 None => return false,
             };
 
-            let first_byte_searcher: Box<dyn Fn(_) -> _> = match case_sensitivity {
-                CaseSensitivity::CaseSensitive => Box::new(|h| memchr(first_byte, h)),
-                CaseSensitivity::AsciiCaseInsensitive => {
-                    let lo = first_byte.to_ascii_lowercase();
-                    let up = first_byte.to_ascii_uppercase();
-
-                    Box::new(move |h| memchr2(lo, up, h))
-                }
-            };
-
-            let mut haystack = &*actual_value;
+            let mut haystack = actual_value;
 
             loop {
-                match first_byte_searcher(&haystack) {
+                match memchr(first_byte, haystack) {
                     Some(pos) => {
                         haystack = &haystack[pos + 1..];
 
@@ -203,7 +322,7 @@ impl<'i> AttributeMatcher<'i> {
                             return false;
                         }
 
-                        if case_sensitivity.eq(&haystack[..rest.len()], rest) {
+                        if haystack.starts_with(rest) {
                             return true;
                         }
                     }
@@ -213,3 +332,38 @@ impl<'i> AttributeMatcher<'i> {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{rewrite_str, RewriteStrSettings};
+
+    #[test]
+    fn names_bloom() {
+        // NOTE: the bloom filter of the attribute names is filled in by the lexer,
+        // so the test goes through the whole rewriter.
+        let output = rewrite_str(
+            "<div FOO=1 data-bar></div><span data-barr></span>",
+            RewriteStrSettings {
+                element_content_handlers: vec![
+                    element!("[foo][data-bar]", |el| {
+                        el.set_attribute("matched", "")?;
+
+                        Ok(())
+                    }),
+                    element!("[baz], [data-ba], span[data-bar]", |el| {
+                        el.remove();
+
+                        Ok(())
+                    }),
+                ],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            output,
+            r#"<div FOO=1 data-bar matched=""></div><span data-barr></span>"#
+        );
+    }
+}
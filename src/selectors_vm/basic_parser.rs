@@ -0,0 +1,650 @@
+use super::ast::{AttributeComparisonExpr, Combinator, Condition, NthChild};
+use super::ast::{OnAttributesExpr, OnTagNameExpr};
+use super::attr::{AttrSelectorOperator, ParsedCaseSensitivity};
+use super::SelectorError;
+use std::ops::Range;
+use std::str::FromStr;
+
+// NOTE: https://html.spec.whatwg.org/multipage/semantics-other.html#case-sensitivity-of-selectors
+const ASCII_CASE_INSENSITIVE_HTML_ATTRIBUTES: &[&str] = &[
+    "accept",
+    "accept-charset",
+    "align",
+    "alink",
+    "axis",
+    "bgcolor",
+    "charset",
+    "checked",
+    "clear",
+    "codetype",
+    "color",
+    "compact",
+    "declare",
+    "defer",
+    "dir",
+    "direction",
+    "disabled",
+    "enctype",
+    "face",
+    "frame",
+    "hreflang",
+    "http-equiv",
+    "lang",
+    "language",
+    "link",
+    "media",
+    "method",
+    "multiple",
+    "nohref",
+    "noresize",
+    "noshade",
+    "nowrap",
+    "readonly",
+    "rel",
+    "rev",
+    "rules",
+    "scope",
+    "scrolling",
+    "selected",
+    "shape",
+    "target",
+    "text",
+    "type",
+    "valign",
+    "valuetype",
+    "vlink",
+];
+
+/// The compound selectors of a complex selector in the parse order, each along with the
+/// combinator that follows it.
+type ComplexSelector = Vec<(Vec<Condition>, Option<Combinator>)>;
+
+#[inline]
+fn is_whitespace(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '\r' | '\x0C')
+}
+
+#[inline]
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || !c.is_ascii()
+}
+
+#[inline]
+fn is_name(c: char) -> bool {
+    is_name_start(c) || c.is_ascii_digit() || c == '-'
+}
+
+/// A parser of the basic CSS selectors, which is used instead of the full CSS selector parser
+/// if the `selector_matching` feature is disabled.
+struct BasicSelectorParser<'i> {
+    input: &'i str,
+    pos: usize,
+}
+
+impl<'i> BasicSelectorParser<'i> {
+    #[inline]
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    #[inline]
+    fn peek_second(&self) -> Option<char> {
+        self.input[self.pos..].chars().nth(1)
+    }
+
+    #[inline]
+    fn bump(&mut self) {
+        if let Some(c) = self.peek() {
+            self.pos += c.len_utf8();
+        }
+    }
+
+    #[inline]
+    fn eat(&mut self, expected: char) -> bool {
+        let eaten = self.peek() == Some(expected);
+
+        if eaten {
+            self.bump();
+        }
+
+        eaten
+    }
+
+    /// Skips the whitespace and returns `true` if there was any.
+    fn skip_whitespace(&mut self) -> bool {
+        let start = self.pos;
+
+        while self.peek().is_some_and(is_whitespace) {
+            self.bump();
+        }
+
+        self.pos > start
+    }
+
+    fn is_at_ident(&self) -> bool {
+        match self.peek() {
+            Some('-') => self
+                .peek_second()
+                .is_some_and(|c| is_name_start(c) || c == '-'),
+            Some(c) => is_name_start(c),
+            None => false,
+        }
+    }
+
+    /// Returns the error for the unexpected character or the end of the selector.
+    #[inline]
+    fn unexpected(&self, error: SelectorError) -> SelectorError {
+        match self.peek() {
+            Some(_) => error,
+            None => SelectorError::UnexpectedEnd,
+        }
+    }
+
+    fn parse_ident(&mut self, error: SelectorError) -> Result<&'i str, SelectorError> {
+        if !self.is_at_ident() {
+            return Err(self.unexpected(error));
+        }
+
+        let start = self.pos;
+
+        while self.peek().is_some_and(is_name) {
+            self.bump();
+        }
+
+        // NOTE: the escapes are not supported by the basic parser.
+        if self.peek() == Some('\\') {
+            return Err(SelectorError::UnsupportedSyntax);
+        }
+
+        Ok(&self.input[start..self.pos])
+    }
+
+    fn parse_string(&mut self, quote: char) -> Result<&'i str, SelectorError> {
+        self.bump();
+
+        let start = self.pos;
+
+        loop {
+            match self.peek() {
+                // NOTE: same as in the full CSS selector parser, the string is implicitly
+                // closed at the end of the selector.
+                Some(c) if c == quote => break,
+                None => break,
+                Some('\\') => return Err(SelectorError::UnsupportedSyntax),
+                Some('\n') => return Err(SelectorError::UnexpectedTokenInAttribute),
+                Some(_) => self.bump(),
+            }
+        }
+
+        let value = &self.input[start..self.pos];
+
+        self.bump();
+
+        Ok(value)
+    }
+
+    fn parse_attribute_operator(&mut self) -> Option<AttrSelectorOperator> {
+        let operator = match self.peek()? {
+            '=' => {
+                self.bump();
+
+                return Some(AttrSelectorOperator::Equal);
+            }
+            '~' => AttrSelectorOperator::Includes,
+            '|' => AttrSelectorOperator::DashMatch,
+            '^' => AttrSelectorOperator::Prefix,
+            '$' => AttrSelectorOperator::Suffix,
+            '*' => AttrSelectorOperator::Substring,
+            _ => return None,
+        };
+
+        if self.peek_second() == Some('=') {
+            self.bump();
+            self.bump();
+
+            Some(operator)
+        } else {
+            None
+        }
+    }
+
+    fn parse_attribute(&mut self) -> Result<Condition, SelectorError> {
+        self.bump();
+        self.skip_whitespace();
+
+        if matches!(self.peek(), Some('=') | Some(']')) {
+            return Err(SelectorError::MissingAttributeName);
+        }
+
+        if matches!(self.peek(), Some('*') | Some('|')) {
+            return Err(SelectorError::NamespacedSelector);
+        }
+
+        let name = self.parse_ident(SelectorError::UnexpectedTokenInAttribute)?;
+
+        if self.peek() == Some('|') && self.peek_second() != Some('=') {
+            return Err(SelectorError::NamespacedSelector);
+        }
+
+        self.skip_whitespace();
+
+        if self.eat(']') {
+            return Ok(Condition::OnAttributes(OnAttributesExpr::AttributeExists(
+                name.to_owned(),
+            )));
+        }
+
+        // NOTE: same as in the full CSS selector parser, only the names of the compared
+        // attributes are lowercased.
+        let name = name.to_ascii_lowercase();
+
+        let operator = self
+            .parse_attribute_operator()
+            .ok_or_else(|| self.unexpected(SelectorError::UnexpectedTokenInAttribute))?;
+
+        self.skip_whitespace();
+
+        let value = match self.peek() {
+            Some(']') | None => return Err(SelectorError::UnexpectedEnd),
+            Some(quote @ '"') | Some(quote @ '\'') => self.parse_string(quote)?,
+            _ => self.parse_ident(SelectorError::UnexpectedTokenInAttribute)?,
+        };
+
+        self.skip_whitespace();
+
+        let case_sensitivity = if self.is_at_ident() {
+            let flag = self.parse_ident(SelectorError::UnexpectedTokenInAttribute)?;
+
+            self.skip_whitespace();
+
+            if flag.eq_ignore_ascii_case("i") {
+                ParsedCaseSensitivity::AsciiCaseInsensitive
+            } else if flag.eq_ignore_ascii_case("s") {
+                ParsedCaseSensitivity::ExplicitCaseSensitive
+            } else {
+                return Err(SelectorError::UnexpectedToken);
+            }
+        } else if ASCII_CASE_INSENSITIVE_HTML_ATTRIBUTES.contains(&&*name) {
+            ParsedCaseSensitivity::AsciiCaseInsensitiveIfInHtmlElementInHtmlDocument
+        } else {
+            ParsedCaseSensitivity::CaseSensitive
+        };
+
+        // NOTE: same as in the full CSS selector parser, the block is implicitly closed at the
+        // end of the selector.
+        if !self.eat(']') && self.peek().is_some() {
+            return Err(SelectorError::UnexpectedToken);
+        }
+
+        // NOTE: same as in the full CSS selector parser, the operators that can't match
+        // anything with the given value produce the unmatchable expression.
+        let never_matches = match operator {
+            AttrSelectorOperator::Equal | AttrSelectorOperator::DashMatch => false,
+            AttrSelectorOperator::Includes => value.is_empty() || value.contains(is_whitespace),
+            AttrSelectorOperator::Prefix
+            | AttrSelectorOperator::Substring
+            | AttrSelectorOperator::Suffix => value.is_empty(),
+        };
+
+        let is_customized_built_in_expr = operator == AttrSelectorOperator::Equal
+            && name == "is"
+            && matches!(
+                case_sensitivity,
+                ParsedCaseSensitivity::CaseSensitive | ParsedCaseSensitivity::ExplicitCaseSensitive
+            );
+
+        Ok(if never_matches {
+            Condition::OnTagName(OnTagNameExpr::Unmatchable)
+        } else if is_customized_built_in_expr {
+            Condition::OnAttributes(OnAttributesExpr::CustomizedBuiltIn(value.to_owned()))
+        } else {
+            Condition::OnAttributes(OnAttributesExpr::AttributeComparisonExpr(
+                AttributeComparisonExpr::new(name, value.to_owned(), case_sensitivity, operator),
+            ))
+        })
+    }
+
+    fn parse_pseudo_class(&mut self) -> Result<Condition, SelectorError> {
+        self.bump();
+
+        if self.peek() == Some(':') {
+            return Err(SelectorError::UnsupportedPseudoClassOrElement);
+        }
+
+        let name = self.parse_ident(SelectorError::UnexpectedToken)?;
+
+        let expr = if self.peek() == Some('(') {
+            None
+        } else if name.eq_ignore_ascii_case("first-child") {
+            Some(OnTagNameExpr::NthChild(NthChild::first()))
+        } else if name.eq_ignore_ascii_case("first-of-type") {
+            Some(OnTagNameExpr::NthOfType(NthChild::first()))
+        } else if name.eq_ignore_ascii_case("root") {
+            Some(OnTagNameExpr::Root)
+        } else {
+            None
+        };
+
+        expr.map(Condition::OnTagName)
+            .ok_or(SelectorError::UnsupportedPseudoClassOrElement)
+    }
+
+    fn parse_compound_selector(&mut self) -> Result<Vec<Condition>, SelectorError> {
+        let mut conditions = Vec::new();
+
+        if self.eat('*') {
+            conditions.push(Condition::OnTagName(OnTagNameExpr::ExplicitAny));
+        } else if self.is_at_ident() {
+            let name = self.parse_ident(SelectorError::UnexpectedToken)?;
+
+            conditions.push(Condition::OnTagName(OnTagNameExpr::LocalName(
+                name.to_owned(),
+            )));
+        }
+
+        if self.peek() == Some('|') {
+            return Err(SelectorError::NamespacedSelector);
+        }
+
+        loop {
+            let condition = match self.peek() {
+                Some('#') => {
+                    self.bump();
+
+                    let id = self.parse_ident(SelectorError::UnexpectedToken)?;
+
+                    Condition::OnAttributes(OnAttributesExpr::Id(id.to_owned()))
+                }
+                Some('.') => {
+                    self.bump();
+
+                    let class = self.parse_ident(SelectorError::InvalidClassName)?;
+
+                    if self.peek() == Some('(') {
+                        return Err(SelectorError::InvalidClassName);
+                    }
+
+                    Condition::OnAttributes(OnAttributesExpr::Class(class.to_owned()))
+                }
+                Some('[') => self.parse_attribute()?,
+                Some(':') => self.parse_pseudo_class()?,
+                _ => break,
+            };
+
+            conditions.push(condition);
+        }
+
+        // NOTE: the full CSS selector parser yields the simple selectors in the reverse order,
+        // so the same selectors produce the same AST with either of the parsers.
+        conditions.reverse();
+
+        if conditions.is_empty() {
+            Err(match self.peek() {
+                None | Some(',') | Some('>') | Some('+') | Some('~') => {
+                    SelectorError::EmptySelector
+                }
+                Some(_) => SelectorError::UnexpectedToken,
+            })
+        } else {
+            Ok(conditions)
+        }
+    }
+
+    fn parse_complex_selector(&mut self) -> Result<ComplexSelector, SelectorError> {
+        let mut compound_selectors = Vec::new();
+
+        loop {
+            let conditions = self.parse_compound_selector()?;
+            let had_whitespace = self.skip_whitespace();
+
+            let combinator = match self.peek() {
+                None | Some(',') => {
+                    compound_selectors.push((conditions, None));
+
+                    return Ok(compound_selectors);
+                }
+                Some('>') => Combinator::Child,
+                Some('+') => Combinator::NextSibling,
+                Some('~') => Combinator::LaterSibling,
+                Some(_) if had_whitespace => Combinator::Descendant,
+                Some(_) => return Err(SelectorError::UnexpectedToken),
+            };
+
+            if combinator != Combinator::Descendant {
+                self.bump();
+                self.skip_whitespace();
+
+                if matches!(self.peek(), None | Some(',')) {
+                    return Err(SelectorError::DanglingCombinator);
+                }
+            }
+
+            compound_selectors.push((conditions, Some(combinator)));
+        }
+    }
+
+    /// Returns the complex selectors of the list along with the ranges of their sources.
+    fn parse(mut self) -> Result<(Vec<ComplexSelector>, Vec<Range<usize>>), SelectorError> {
+        let mut complex_selectors = Vec::new();
+        let mut ranges = Vec::new();
+
+        loop {
+            self.skip_whitespace();
+
+            let start = self.pos;
+
+            complex_selectors.push(self.parse_complex_selector()?);
+
+            let end = start
+                + self.input[start..self.pos]
+                    .trim_end_matches(is_whitespace)
+                    .len();
+
+            ranges.push(start..end);
+
+            if !self.eat(',') {
+                return Ok((complex_selectors, ranges));
+            }
+        }
+    }
+}
+
+/// Parsed CSS selector.
+///
+/// Parsed selector can be used for different [element content handlers] without a necessity
+/// to re-parse CSS selector string for each of them.
+///
+/// The structure implements the [`FromStr`] trait, so it can be constructed through
+/// [`str`]'s [`parse`] method.
+///
+/// # Supported selector
+///
+/// Without the `selector_matching` feature the selectors are parsed by the basic parser, which
+/// supports the type (`E`), universal (`*`), class (`.warning`), ID (`#myid`) and attribute
+/// selectors (`[foo]`, `[foo="bar"]`, `[foo~="bar"]`, <code>[foo&#124;="en"]</code>,
+/// `[foo^="bar"]`, `[foo$="bar"]` and `[foo*="bar"]` with the optional `i` and `s` flags),
+/// the `:first-child`, `:first-of-type` and `:root` pseudo-classes and the descendant (`E F`),
+/// child (`E > F`), next-sibling (`E + F`) and subsequent-sibling (`E ~ F`) combinators.
+/// The escapes and the namespaces are not supported.
+///
+/// [`str`]: https://doc.rust-lang.org/std/primitive.str.html
+/// [`parse`]: https://doc.rust-lang.org/std/primitive.str.html#method.parse
+/// [element content handlers]: struct.Settings.html#structfield.element_content_handlers
+/// [`FromStr`]: https://doc.rust-lang.org/std/str/trait.FromStr.html
+#[derive(Debug)]
+pub struct Selector(Vec<ComplexSelector>, Box<str>, Vec<Range<usize>>);
+
+impl Selector {
+    /// Returns the source of the selector as it has been parsed.
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        &self.1
+    }
+
+    #[inline]
+    pub(crate) fn complex_selectors(&self) -> &[ComplexSelector] {
+        &self.0
+    }
+
+    /// Returns the sources of the alternatives of the selector list (e.g. `a` and `area` for
+    /// `a, area`) in the order of the list.
+    #[inline]
+    pub(crate) fn alternatives(&self) -> Vec<&str> {
+        self.2.iter().map(|range| &self.1[range.clone()]).collect()
+    }
+}
+
+impl FromStr for Selector {
+    type Err = SelectorError;
+
+    #[inline]
+    fn from_str(selector: &str) -> Result<Self, Self::Err> {
+        let parser = BasicSelectorParser {
+            input: selector,
+            pos: 0,
+        };
+
+        let (complex_selectors, ranges) = parser.parse()?;
+
+        Ok(Selector(complex_selectors, selector.into(), ranges))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::selectors_vm::{Ast, Selector as FullSelector};
+
+    fn assert_same_ast(selector: &str) {
+        let mut basic_ast = Ast::default();
+        let mut full_ast = Ast::default();
+
+        let basic_selector = selector.parse::<Selector>().unwrap();
+        let full_selector = selector.parse::<FullSelector>().unwrap();
+
+        assert_eq!(basic_selector.as_str(), selector);
+
+        assert_eq!(
+            basic_selector.alternatives(),
+            full_selector.alternatives(),
+            "Selector: {}",
+            selector
+        );
+
+        basic_ast.add_basic_selector(&basic_selector, 0);
+        full_ast.add_selector(&full_selector, 0);
+
+        assert_eq!(basic_ast, full_ast, "Selector: {}", selector);
+    }
+
+    fn assert_err(selector: &str, expected_err: SelectorError) {
+        assert_eq!(
+            selector.parse::<Selector>().unwrap_err(),
+            expected_err,
+            "Selector: {}",
+            selector
+        );
+    }
+
+    #[test]
+    fn same_ast_as_full_parser() {
+        for selector in &[
+            "*",
+            "div",
+            "DIV",
+            "#foo",
+            ".bar",
+            "div#foo.bar.baz",
+            "*.foo:first-child[bar]#baz:root",
+            "[foo=bar",
+            "[foo=\"bar]",
+            "[foo]",
+            "[ FOO ]",
+            "[foo=bar]",
+            "[foo = 'bar baz']",
+            "[foo~=bar]",
+            "[foo~='bar baz']",
+            "[foo|=en]",
+            "[foo^=bar]",
+            "[foo^='']",
+            "[foo$=bar]",
+            "[foo*=bar]",
+            "[FOO=Bar i]",
+            "[foo=bar s]",
+            "[type=text]",
+            "[type=text s]",
+            "[is=foo]",
+            "[is=foo i]",
+            "div:first-child",
+            ":first-of-type",
+            ":root",
+            "div > p + span ~ a b",
+            "  div  >  p  ",
+            "div,p.foo , [bar]",
+        ] {
+            assert_same_ast(selector);
+        }
+    }
+
+    #[test]
+    fn same_errors_as_full_parser() {
+        for (selector, expected_err) in vec![
+            ("", SelectorError::EmptySelector),
+            ("div >", SelectorError::DanglingCombinator),
+            ("div,", SelectorError::EmptySelector),
+            ("div.", SelectorError::UnexpectedEnd),
+            ("div@", SelectorError::UnexpectedToken),
+            ("[=\"foo\"]", SelectorError::MissingAttributeName),
+            ("[foo=\"bar\" x]", SelectorError::UnexpectedToken),
+            ("[foo=\"bar\" 1]", SelectorError::UnexpectedToken),
+            ("[foo=]", SelectorError::UnexpectedEnd),
+            ("[foo bar]", SelectorError::UnexpectedTokenInAttribute),
+            ("[foo=\"bar\n\"]", SelectorError::UnexpectedTokenInAttribute),
+            (".foo()", SelectorError::InvalidClassName),
+            ("div:hover", SelectorError::UnsupportedPseudoClassOrElement),
+            (
+                "div::before",
+                SelectorError::UnsupportedPseudoClassOrElement,
+            ),
+        ] {
+            assert_eq!(
+                selector.parse::<FullSelector>().unwrap_err(),
+                expected_err,
+                "Selector: {}",
+                selector
+            );
+
+            assert_err(selector, expected_err);
+        }
+    }
+
+    #[test]
+    fn unsupported_syntax() {
+        for selector in &[
+            "d\\69 v",
+            "[foo='b\\61r']",
+            ":nth-child(2)",
+            ":not(div)",
+            ":has(div)",
+            ":last-child",
+            "svg|circle",
+        ] {
+            #[cfg(feature = "selector_matching")]
+            assert!(selector.parse::<FullSelector>().is_ok());
+
+            assert!(
+                selector.parse::<Selector>().is_err(),
+                "Selector: {}",
+                selector
+            );
+        }
+
+        assert_err("d\\69 v", SelectorError::UnsupportedSyntax);
+        assert_err(
+            ":nth-child(2)",
+            SelectorError::UnsupportedPseudoClassOrElement,
+        );
+        assert_err("svg|circle", SelectorError::NamespacedSelector);
+        assert_err("[svg|foo]", SelectorError::NamespacedSelector);
+    }
+}
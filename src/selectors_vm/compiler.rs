@@ -1,20 +1,30 @@
 use super::attribute_matcher::AttributeMatcher;
+use super::attr::{AttrSelectorOperator, ParsedCaseSensitivity};
+#[cfg(feature = "selector_matching")]
+use super::custom_pseudo_class::ElementInfo;
 use super::program::{
     AddressRange, ExecutionBranch, Program, Instruction
 };
-use super::{Ast, AstNode, Expr, AttributeComparisonExpr, OnTagNameExpr, OnAttributesExpr, Predicate, SelectorState};
+use super::{last_sibling_key, Ast, AstNode, Expr, AttributeComparisonExpr, NthChild, OnTagNameExpr, OnAttributesExpr, Predicate, SelectorState};
 use crate::base::{Bytes, HasReplacementsError};
-use crate::html::LocalName;
+use crate::html::{LocalName, Namespace, Tag};
 use encoding_rs::Encoding;
-use selectors::attr::{ParsedCaseSensitivity, AttrSelectorOperator};
+use hashbrown::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::iter;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 /// An expression using only the tag name of an element.
-pub type CompiledLocalNameExpr = Box<dyn Fn(&SelectorState, &LocalName) -> bool>;
+///
+/// NOTE: the expressions are shared by the copies of the program the selectors are
+/// added to, so they don't need to be recompiled.
+pub type CompiledLocalNameExpr = Arc<dyn Fn(&SelectorState, &LocalName) -> bool + Send + Sync>;
 /// An expression using the attributes of an element.
-pub type CompiledAttributeExpr = Box<dyn Fn(&SelectorState, &AttributeMatcher) -> bool>;
+pub type CompiledAttributeExpr =
+    Arc<dyn Fn(&SelectorState, &mut AttributeMatcher) -> bool + Send + Sync>;
 
 #[derive(Default)]
 struct ExprSet {
@@ -25,22 +35,112 @@ struct ExprSet {
 pub struct AttrExprOperands {
     pub name: Bytes<'static>,
     pub value: Bytes<'static>,
+    /// The value compared with the pre-lowered attribute value if the comparison
+    /// is ASCII case-insensitive.
+    pub lowercased_value: Bytes<'static>,
     pub case_sensitivity: ParsedCaseSensitivity,
+    /// Identifies the attribute name, so its lowercased value can be shared by all
+    /// the case-insensitive comparisons for the start tag.
+    pub name_id: usize,
+    /// Identifies the comparison: all the expressions with the same operands and
+    /// operator share the id.
+    pub id: usize,
+    /// Set once the comparison is used by more than one expression, so its result
+    /// is worth memoizing for the start tag.
+    pub is_shared: AtomicBool,
+}
+
+impl AttrExprOperands {
+    #[inline]
+    pub fn is_shared(&self) -> bool {
+        self.is_shared.load(Ordering::Relaxed)
+    }
+}
+
+type OperandsKey = (String, String);
+type OperandsEntry = (ParsedCaseSensitivity, AttrSelectorOperator, Arc<AttrExprOperands>);
+
+/// Deduplicates attribute comparisons across all the predicates of the program, so
+/// large rule sets that test the same attribute value don't repeat the scans.
+#[derive(Default, Clone)]
+pub(super) struct OperandsRegistry {
+    operands: HashMap<OperandsKey, Vec<OperandsEntry>>,
+    name_ids: HashMap<String, usize>,
+    next_id: usize,
+}
+
+impl OperandsRegistry {
+    fn get_or_add(
+        &mut self,
+        encoding: &'static Encoding,
+        expr: &AttributeComparisonExpr,
+    ) -> Result<Arc<AttrExprOperands>, HasReplacementsError> {
+        let lowercased_name = expr.name.to_ascii_lowercase();
+        let next_name_id = self.name_ids.len();
+
+        let name_id = *self
+            .name_ids
+            .entry(lowercased_name.to_owned())
+            .or_insert(next_name_id);
+
+        let entries = self
+            .operands
+            .entry((lowercased_name, expr.value.to_owned()))
+            .or_default();
+
+        let existing = entries.iter().find(|(case_sensitivity, operator, _)| {
+            *case_sensitivity == expr.case_sensitivity && *operator == expr.operator
+        });
+
+        if let Some((_, _, operands)) = existing {
+            operands.is_shared.store(true, Ordering::Relaxed);
+
+            return Ok(Arc::clone(operands));
+        }
+
+        let (name, value) = compile_operands(encoding, &expr.name, &expr.value)?;
+        // NOTE: the value is lowercased after encoding, the same way as the attribute value,
+        // since bytes of multi-byte characters can fall into the ASCII range in some encodings.
+        let lowercased_value = Bytes::from(value.to_ascii_lowercase().as_slice()).into_owned();
+
+        let operands = Arc::new(AttrExprOperands {
+            name,
+            value,
+            lowercased_value,
+            case_sensitivity: expr.case_sensitivity,
+            name_id,
+            id: self.next_id,
+            is_shared: AtomicBool::new(false),
+        });
+
+        self.next_id += 1;
+        entries.push((expr.case_sensitivity, expr.operator, Arc::clone(&operands)));
+
+        Ok(operands)
+    }
 }
 
 impl Expr<OnTagNameExpr> {
     #[inline]
-    pub fn compile_expr<F: Fn(&SelectorState, &LocalName) -> bool + 'static>(&self, f: F) -> CompiledLocalNameExpr {
+    pub fn compile_expr<F: Fn(&SelectorState, &LocalName) -> bool + Send + Sync + 'static>(&self, f: F) -> CompiledLocalNameExpr {
         if self.negation {
-            Box::new(move |s, a| !f(s, a))
+            Arc::new(move |s, a| !f(s, a))
         } else {
-            Box::new(f)
+            Arc::new(f)
         }
     }
 }
 
 trait Compilable {
-    fn compile(&self, encoding: &'static Encoding, exprs: &mut ExprSet, enable_nth_of_type: &mut bool);
+    fn compile(
+        &self,
+        encoding: &'static Encoding,
+        exprs: &mut ExprSet,
+        operands: &mut OperandsRegistry,
+        context: &HashSet<String>,
+        negated_selectors: &mut Vec<String>,
+        enable_nth_of_type: &mut bool,
+    );
 }
 
 impl Compilable for Expr<OnTagNameExpr> {
@@ -48,6 +148,9 @@ impl Compilable for Expr<OnTagNameExpr> {
         &self,
         encoding: &'static Encoding,
         exprs: &mut ExprSet,
+        _: &mut OperandsRegistry,
+        context: &HashSet<String>,
+        negated_selectors: &mut Vec<String>,
         enable_nth_of_type: &mut bool,
     ) {
         let expr = match &self.simple_expr {
@@ -72,6 +175,56 @@ impl Compilable for Expr<OnTagNameExpr> {
                 *enable_nth_of_type = true;
                 self.compile_expr(move |state, _| state.typed.expect("Counter for type required at this point").is_nth(nth))
             }
+            OnTagNameExpr::Ctx(key) => {
+                // NOTE: the context is fixed for the lifetime of the program, so
+                // the expression is resolved upfront.
+                let is_active = context.contains(key);
+
+                self.compile_expr(move |_, _| is_active)
+            }
+            OnTagNameExpr::Has(argument) => {
+                let argument = argument.to_owned();
+
+                self.compile_expr(move |state, _| {
+                    state.has_matches.contains(&argument, state.element_index)
+                })
+            }
+            OnTagNameExpr::Matches(selector) => {
+                // NOTE: the selector lists are matched by the separate program, which reports
+                // the matches by their indices in the program's list.
+                let idx = match negated_selectors.iter().position(|s| s == selector) {
+                    Some(idx) => idx,
+                    None => {
+                        negated_selectors.push(selector.to_owned());
+                        negated_selectors.len() - 1
+                    }
+                };
+
+                self.compile_expr(move |state, _| state.negated_selector_matches.contains(&idx))
+            }
+            &OnTagNameExpr::Namespace(ns) => self.compile_expr(move |state, _| state.ns == ns),
+            OnTagNameExpr::Root => self.compile_expr(|state, actual| {
+                state.is_root && state.ns == Namespace::Html && *actual == Tag::Html
+            }),
+            &OnTagNameExpr::LastSibling { of_type, only } => {
+                let key = last_sibling_key(of_type);
+                let first = NthChild::first();
+
+                if only && of_type {
+                    *enable_nth_of_type = true;
+                }
+
+                self.compile_expr(move |state, _| {
+                    // NOTE: the only sibling is the first sibling that is the last one as well.
+                    let is_first = match (only, of_type) {
+                        (false, _) => true,
+                        (true, false) => state.cumulative.is_nth(first),
+                        (true, true) => state.typed.expect("Counter for type required at this point").is_nth(first),
+                    };
+
+                    is_first && state.has_matches.contains(key, state.element_index)
+                })
+            }
         };
 
         exprs.local_name_exprs.push(expr);
@@ -80,11 +233,11 @@ impl Compilable for Expr<OnTagNameExpr> {
 
 impl Expr<OnAttributesExpr> {
     #[inline]
-    pub fn compile_expr<F: Fn(&SelectorState, &AttributeMatcher) -> bool + 'static>(&self, f: F) -> CompiledAttributeExpr {
+    pub fn compile_expr<F: Fn(&SelectorState, &mut AttributeMatcher) -> bool + Send + Sync + 'static>(&self, f: F) -> CompiledAttributeExpr {
         if self.negation {
-            Box::new(move |s, a| !f(s, a))
+            Arc::new(move |s, a| !f(s, a))
         } else {
-            Box::new(f)
+            Arc::new(f)
         }
     }
 }
@@ -109,6 +262,9 @@ impl Compilable for Expr<OnAttributesExpr> {
         &self,
         encoding: &'static Encoding,
         exprs: &mut ExprSet,
+        operands: &mut OperandsRegistry,
+        _: &HashSet<String>,
+        _: &mut Vec<String>,
         _: &mut bool,
     ) {
         let expr_result = match &self.simple_expr {
@@ -124,24 +280,31 @@ impl Compilable for Expr<OnAttributesExpr> {
                 compile_literal(encoding, name)
                     .map(|name| self.compile_expr(move |_, m| m.has_attribute(&name))),
 
-            &OnAttributesExpr::AttributeComparisonExpr(
-                AttributeComparisonExpr {
-                    ref name,
-                    ref value,
-                    case_sensitivity,
-                    operator
-                }
-            ) => {
-                compile_operands(encoding, name, value)
-                    .map(move |(name, value)| {
-                        let operands = AttrExprOperands { name, value, case_sensitivity };
-                        match operator {
-                            AttrSelectorOperator::Equal     => self.compile_expr(move |_, m| m.attr_eq(&operands)),
-                            AttrSelectorOperator::Includes  => self.compile_expr(move |_, m| m.matches_splitted_by_whitespace(&operands)),
-                            AttrSelectorOperator::DashMatch => self.compile_expr(move |_, m| m.has_dash_matching_attr(&operands)),
-                            AttrSelectorOperator::Prefix    => self.compile_expr(move |_, m| m.has_attr_with_prefix(&operands)),
-                            AttrSelectorOperator::Suffix    => self.compile_expr(move |_, m| m.has_attr_with_suffix(&operands)),
-                            AttrSelectorOperator::Substring => self.compile_expr(move |_, m| m.has_attr_with_substring(&operands)),
+            OnAttributesExpr::CustomizedBuiltIn(name) =>
+                compile_literal(encoding, name)
+                    .map(|name| self.compile_expr(move |_, m| m.is_customized_built_in(&name))),
+
+            OnAttributesExpr::Lang(lang) =>
+                compile_literal_lowercase(encoding, lang)
+                    .map(|lang| self.compile_expr(move |state, m| m.has_lang(state.lang, &lang))),
+
+            #[cfg(feature = "selector_matching")]
+            OnAttributesExpr::Custom(pseudo_class) => {
+                let predicate = Arc::clone(&pseudo_class.predicate);
+
+                Ok(self.compile_expr(move |_, m| predicate(&ElementInfo::new(m, encoding))))
+            }
+
+            OnAttributesExpr::AttributeComparisonExpr(expr) => {
+                operands.get_or_add(encoding, expr)
+                    .map(move |o| {
+                        match expr.operator {
+                            AttrSelectorOperator::Equal     => self.compile_expr(move |_, m| m.memoized(&o, |m| m.attr_eq(&o))),
+                            AttrSelectorOperator::Includes  => self.compile_expr(move |_, m| m.memoized(&o, |m| m.matches_splitted_by_whitespace(&o))),
+                            AttrSelectorOperator::DashMatch => self.compile_expr(move |_, m| m.memoized(&o, |m| m.has_dash_matching_attr(&o))),
+                            AttrSelectorOperator::Prefix    => self.compile_expr(move |_, m| m.memoized(&o, |m| m.has_attr_with_prefix(&o))),
+                            AttrSelectorOperator::Suffix    => self.compile_expr(move |_, m| m.memoized(&o, |m| m.has_attr_with_suffix(&o))),
+                            AttrSelectorOperator::Substring => self.compile_expr(move |_, m| m.memoized(&o, |m| m.has_attr_with_substring(&o))),
                         }
                     })
             }
@@ -158,33 +321,55 @@ where
     encoding: &'static Encoding,
     instructions: Box<[Option<Instruction<P>>]>,
     free_space_start: usize,
+    operands: OperandsRegistry,
+    context: HashSet<String>,
+    negated_selectors: Vec<String>,
+    enable_lang_tracking: bool,
 }
 
 impl<P: 'static> Compiler<P>
 where
     P: PartialEq + Eq + Copy + Debug + Hash,
 {
-    pub fn new(encoding: &'static Encoding) -> Self {
+    pub fn new(encoding: &'static Encoding, context: &[&str]) -> Self {
         Compiler {
             encoding,
             instructions: Default::default(),
             free_space_start: 0,
+            operands: OperandsRegistry::default(),
+            context: context.iter().map(|&key| key.to_owned()).collect(),
+            negated_selectors: Vec::default(),
+            enable_lang_tracking: false,
         }
     }
 
     fn compile_predicate(
-        &self,
+        &mut self,
         Predicate {
             on_tag_name_exprs,
             on_attr_exprs,
         }: &Predicate,
         branch: ExecutionBranch<P>,
+        addr: usize,
         enable_nth_of_type: &mut bool,
     ) -> Instruction<P> {
         let mut exprs = ExprSet::default();
 
-        on_tag_name_exprs.iter().for_each(|c| c.compile(self.encoding, &mut exprs, enable_nth_of_type));
-        on_attr_exprs.iter().for_each(|c| c.compile(self.encoding, &mut exprs, enable_nth_of_type));
+        let encoding = self.encoding;
+        let operands = &mut self.operands;
+        let context = &self.context;
+        let negated_selectors = &mut self.negated_selectors;
+
+        // NOTE: the language of the element can be inherited from its ancestors, so the results
+        // of the `:lang()` expressions can't be memoized for the attributes of the start tag.
+        let has_lang_exprs = on_attr_exprs.iter().any(|e| matches!(e.simple_expr, OnAttributesExpr::Lang(_)));
+
+        if has_lang_exprs {
+            self.enable_lang_tracking = true;
+        }
+
+        on_tag_name_exprs.iter().for_each(|c| c.compile(encoding, &mut exprs, operands, context, negated_selectors, enable_nth_of_type));
+        on_attr_exprs.iter().for_each(|c| c.compile(encoding, &mut exprs, operands, context, negated_selectors, enable_nth_of_type));
 
         let ExprSet {
             local_name_exprs,
@@ -197,6 +382,7 @@ where
             associated_branch: branch,
             local_name_exprs: local_name_exprs.into(),
             attribute_exprs: attribute_exprs.into(),
+            attr_results_key: if has_lang_exprs { None } else { Some(addr) },
         }
     }
 
@@ -231,25 +417,158 @@ where
                 matched_payload: node.payload,
                 jumps: self.compile_descendants(node.children, enable_nth_of_type),
                 hereditary_jumps: self.compile_descendants(node.descendants, enable_nth_of_type),
+                next_sibling_jumps: self.compile_descendants(node.next_siblings, enable_nth_of_type),
+                later_sibling_jumps: self.compile_descendants(node.later_siblings, enable_nth_of_type),
             };
 
-            self.instructions[position] = Some(self.compile_predicate(&node.predicate, branch, enable_nth_of_type));
+            let instruction = self.compile_predicate(&node.predicate, branch, position, enable_nth_of_type);
+
+            self.instructions[position] = Some(instruction);
         }
 
         addr_range
     }
 
-    pub fn compile(mut self, ast: Ast<P>) -> Program<P> {
-        let mut enable_nth_of_type = false;
+    /// Compiles the nodes of the `ast` into the instructions, with the entry points placed
+    /// at the start.
+    fn compile_ast(&mut self, ast: Ast<P>, enable_nth_of_type: &mut bool) -> (Vec<Instruction<P>>, AddressRange) {
         self.instructions = iter::repeat_with(|| None).take(ast.cumulative_node_count).collect();
+        self.free_space_start = 0;
 
-        let entry_points = self.compile_nodes(ast.root, &mut enable_nth_of_type);
+        let entry_points = self.compile_nodes(ast.root, enable_nth_of_type);
+        let instructions = mem::take(&mut self.instructions).into_vec().into_iter().map(|o| o.unwrap()).collect();
 
+        (instructions, entry_points)
+    }
+
+    /// Compiles the selector lists of the `:not()` and `:is()` pseudo-classes that follow
+    /// the first `base_count` ones, which are already matched by the `base` program.
+    fn compile_negated_selectors(&self, base: Option<&Arc<Program<usize>>>, base_count: usize) -> Option<Arc<Program<usize>>> {
+        if self.negated_selectors.len() == base_count {
+            return base.cloned();
+        }
+
+        let mut ast = Ast::default();
+
+        // NOTE: the selector lists are the arguments of the valid selectors, so it's
+        // safe to unwrap here.
+        for (idx, selector) in self.negated_selectors.iter().enumerate().skip(base_count) {
+            ast.add_selector(&selector.parse().unwrap(), idx);
+        }
+
+        let program = match base {
+            Some(base) => Compiler::extend(base, ast, Some),
+            None => {
+                let compiler = Compiler {
+                    encoding: self.encoding,
+                    instructions: Default::default(),
+                    free_space_start: 0,
+                    operands: OperandsRegistry::default(),
+                    context: self.context.clone(),
+                    negated_selectors: Vec::default(),
+                    enable_lang_tracking: false,
+                };
+
+                compiler.compile(ast)
+            }
+        };
+
+        Some(Arc::new(program))
+    }
+
+    fn into_program(
+        self,
+        instructions: Vec<Instruction<P>>,
+        entry_points: AddressRange,
+        enable_nth_of_type: bool,
+        negated_program: Option<Arc<Program<usize>>>,
+    ) -> Program<P> {
         Program {
-            instructions: self.instructions.into_vec().into_iter().map(|o| o.unwrap()).collect(),
+            instructions: instructions.into(),
             entry_points,
             enable_nth_of_type,
+            enable_lang_tracking: self.enable_lang_tracking,
+            encoding: self.encoding,
+            negated_program,
+            operands: self.operands,
+            context: self.context,
+            negated_selectors: self.negated_selectors,
+        }
+    }
+
+    pub fn compile(mut self, ast: Ast<P>) -> Program<P> {
+        let mut enable_nth_of_type = false;
+        let (instructions, entry_points) = self.compile_ast(ast, &mut enable_nth_of_type);
+        let negated_program = self.compile_negated_selectors(None, 0);
+
+        self.into_program(instructions, entry_points, enable_nth_of_type, negated_program)
+    }
+
+    /// Compiles the selectors of the `ast` into a copy of the `program`, so the selectors can be
+    /// added to the program without recompilation of the selectors it already has. The compiled
+    /// expressions of the program are shared by the copy, with the payloads replaced by
+    /// `map_payload`. The payloads it maps to `None` are removed from the copy.
+    pub fn extend(program: &Program<P>, ast: Ast<P>, map_payload: impl Fn(P) -> Option<P>) -> Program<P> {
+        let mut compiler = Compiler {
+            encoding: program.encoding,
+            instructions: Default::default(),
+            free_space_start: 0,
+            operands: program.operands.clone(),
+            context: program.context.clone(),
+            negated_selectors: program.negated_selectors.clone(),
+            enable_lang_tracking: program.enable_lang_tracking,
+        };
+
+        let mut enable_nth_of_type = program.enable_nth_of_type;
+        let (added_instructions, added_entry_points) = compiler.compile_ast(ast, &mut enable_nth_of_type);
+
+        debug_assert_eq!(program.entry_points.start, 0);
+
+        // NOTE: the entry points need to be in a contiguous region, so the added entry points
+        // are placed right after the ones of the program. They are followed by the rest of
+        // the instructions of the program and then by the rest of the added instructions.
+        let entry_point_count = program.entry_points.end;
+        let added_entry_point_count = added_entry_points.end;
+        let instruction_count = program.instructions.len();
+
+        let relocate = |addr| {
+            if addr < entry_point_count { addr } else { addr + added_entry_point_count }
+        };
+
+        let relocate_added = |addr| {
+            if addr < added_entry_point_count { entry_point_count + addr } else { instruction_count + addr }
+        };
+
+        let mut instructions: Vec<_> = iter::repeat_with(|| None)
+            .take(instruction_count + added_instructions.len())
+            .collect();
+
+        for (addr, instr) in program.instructions.iter().enumerate() {
+            let mut instr = instr.clone();
+            let branch = &mut instr.associated_branch;
+
+            branch.matched_payload = branch
+                .matched_payload
+                .iter()
+                .filter_map(|(&payload, &alternative_idx)| map_payload(payload).map(|p| (p, alternative_idx)))
+                .collect();
+
+            instructions[relocate(addr)] = Some(instr.relocate(relocate(addr), relocate));
+        }
+
+        for (addr, instr) in added_instructions.into_iter().enumerate() {
+            instructions[relocate_added(addr)] = Some(instr.relocate(relocate_added(addr), relocate_added));
         }
+
+        let negated_program = compiler.compile_negated_selectors(program.negated_program.as_ref(), program.negated_selectors.len());
+        let instructions = instructions.into_iter().map(|o| o.unwrap()).collect();
+
+        compiler.into_program(
+            instructions,
+            0..entry_point_count + added_entry_point_count,
+            enable_nth_of_type,
+            negated_program,
+        )
     }
 }
 
@@ -258,11 +577,14 @@ mod tests {
     use super::*;
     use crate::html::Namespace;
     use crate::rewritable_units::Token;
-    use crate::selectors_vm::{TryExecResult, tests::test_with_token};
-    use crate::test_utils::ASCII_COMPATIBLE_ENCODINGS;
+    use crate::selectors_vm::{SharedHasMatches, TryExecResult, tests::test_with_token};
     use encoding_rs::UTF_8;
     use hashbrown::HashSet;
 
+    #[cfg(feature = "selector_matching")]
+    use crate::test_utils::ASCII_COMPATIBLE_ENCODINGS;
+
+    #[cfg(feature = "selector_matching")]
     macro_rules! assert_instr_res {
         ($res:expr, $should_match:expr, $selector:expr, $input:expr, $encoding:expr) => {{
             let expected_payload = if *$should_match {
@@ -272,7 +594,7 @@ mod tests {
             };
 
             assert_eq!(
-                $res.map(|b| b.matched_payload.to_owned()),
+                $res.map(|b| b.matched_payload.keys().copied().collect::<HashSet<_>>()),
                 expected_payload,
                 "Instruction didn't produce expected matching result\n\
                  selector: {:#?}\n\
@@ -286,6 +608,8 @@ mod tests {
         }};
     }
 
+    const TEST_CONTEXT: &[&str] = &["mobile"];
+
     fn compile(
         selectors: &[&str],
         encoding: &'static Encoding,
@@ -297,7 +621,7 @@ mod tests {
             ast.add_selector(&selector.parse().unwrap(), idx);
         }
 
-        let program = Compiler::new(encoding).compile(ast);
+        let program = Compiler::new(encoding, TEST_CONTEXT).compile(ast);
 
         assert_eq!(
             program.entry_points.end - program.entry_points.start,
@@ -307,6 +631,7 @@ mod tests {
         program
     }
 
+    #[cfg(feature = "selector_matching")]
     fn with_negated<'i>(
         selector: &str,
         test_cases: &[(&'i str, bool)],
@@ -330,9 +655,10 @@ mod tests {
     ) {
         test_with_token(html, encoding, |t| match t {
             Token::StartTag(t) => {
-                let (input, attrs) = t.raw_attributes();
+                let (input, attrs, names_bloom) = t.raw_attributes();
                 let tag_name = t.name();
-                let attr_matcher = AttributeMatcher::new(input, attrs, Namespace::Html);
+                let attr_matcher =
+                    AttributeMatcher::new(input, attrs, names_bloom, Namespace::Html);
                 let local_name =
                     LocalName::from_str_without_replacements(&tag_name, encoding).unwrap();
 
@@ -342,6 +668,22 @@ mod tests {
         });
     }
 
+    fn with_selector_state<R>(action: impl FnOnce(&SelectorState) -> R) -> R {
+        let counter = Default::default();
+        let has_matches: SharedHasMatches = Default::default();
+
+        action(&SelectorState {
+            ns: Namespace::Html,
+            cumulative: &counter,
+            typed: None,
+            lang: None,
+            is_root: false,
+            element_index: 0,
+            negated_selector_matches: &[],
+            has_matches: &has_matches,
+        })
+    }
+
     fn for_each_test_case<T>(
         test_cases: &[(&str, T)],
         encoding: &'static Encoding,
@@ -349,13 +691,14 @@ mod tests {
     ) {
         for (input, matching_data) in test_cases.iter() {
             with_start_tag(input, encoding, |local_name, attr_matcher| {
-                let counter = Default::default();
-                let state = SelectorState { cumulative: &counter, typed: None };
-                action(input, matching_data, &state, local_name, attr_matcher);
+                with_selector_state(|state| {
+                    action(input, matching_data, state, local_name, attr_matcher)
+                });
             });
         }
     }
 
+    #[cfg(feature = "selector_matching")]
     fn assert_attr_expr_matches(
         selector: &str,
         encoding: &'static Encoding,
@@ -367,11 +710,11 @@ mod tests {
         for_each_test_case(
             test_cases,
             encoding,
-            |input, should_match, state, local_name, attr_matcher| {
+            |input, should_match, state, local_name, mut attr_matcher| {
                 assert!(matches!(instr.try_exec_without_attrs(state, &local_name), TryExecResult::AttributesRequired), "Instruction should not execute without attributes");
 
-                let multi_step_res = instr.complete_exec_with_attrs(&*state, &attr_matcher);
-                let res = instr.exec(state, &local_name, &attr_matcher);
+                let multi_step_res = instr.complete_exec_with_attrs(state, &mut attr_matcher);
+                let res = instr.exec(state, &local_name, &mut attr_matcher);
 
                 assert_eq!(multi_step_res, res);
                 assert_instr_res!(res, should_match, selector, input, encoding);
@@ -379,6 +722,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "selector_matching")]
     fn assert_non_attr_expr_matches_and_negation_reverses_match(
         selector: &str,
         encoding: &'static Encoding,
@@ -391,7 +735,7 @@ mod tests {
             for_each_test_case(
                 &test_cases,
                 encoding,
-                |input, should_match, state, local_name, attr_matcher| {
+                |input, should_match, state, local_name, mut attr_matcher| {
                     #[allow(clippy::match_wild_err_arm)]
                     let multi_step_res = match instr.try_exec_without_attrs(state, &local_name) {
                         TryExecResult::Branch(b) => Some(b),
@@ -399,7 +743,7 @@ mod tests {
                         TryExecResult::AttributesRequired => panic!("Should match without attribute request"),
                     };
 
-                    let res = instr.exec(state, &local_name, &attr_matcher);
+                    let res = instr.exec(state, &local_name, &mut attr_matcher);
 
                     assert_eq!(multi_step_res, res);
 
@@ -409,6 +753,7 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "selector_matching")]
     fn assert_attr_expr_matches_and_negation_reverses_match(
         selector: &str,
         encoding: &'static Encoding,
@@ -421,12 +766,12 @@ mod tests {
 
     macro_rules! exec_generic_instr {
         ($instr:expr, $state:expr, $local_name:expr, $attr_matcher:expr) => {{
-            let res = $instr.exec($state, &$local_name, &$attr_matcher);
+            let res = $instr.exec($state, &$local_name, &mut $attr_matcher);
 
             let multi_step_res = match $instr.try_exec_without_attrs($state, &$local_name) {
                 TryExecResult::Branch(b) => Some(b),
                 TryExecResult::Fail => None,
-                TryExecResult::AttributesRequired => $instr.complete_exec_with_attrs(&*$state, &$attr_matcher),
+                TryExecResult::AttributesRequired => $instr.complete_exec_with_attrs(&*$state, &mut $attr_matcher),
             };
 
             assert_eq!(res, multi_step_res);
@@ -435,6 +780,7 @@ mod tests {
         }};
     }
 
+    #[cfg(feature = "selector_matching")]
     fn assert_generic_expr_matches(
         selector: &str,
         encoding: &'static Encoding,
@@ -446,7 +792,7 @@ mod tests {
         for_each_test_case(
             &test_cases,
             encoding,
-            |input, should_match, state, local_name, attr_matcher| {
+            |input, should_match, state, local_name, mut attr_matcher| {
                 let res = exec_generic_instr!(instr, state, local_name, attr_matcher);
 
                 assert_instr_res!(res, should_match, selector, input, encoding);
@@ -465,7 +811,7 @@ mod tests {
                     exec_generic_instr!($program.instructions[addr], $state, $local_name, $attr_matcher);
 
                 if let Some(res) = res {
-                    for &p in res.matched_payload.iter() {
+                    for &p in res.matched_payload.keys() {
                         matched_payload.insert(p);
                     }
 
@@ -510,7 +856,7 @@ mod tests {
         for_each_test_case(
             &test_cases,
             UTF_8,
-            |input, expected_payload, state, local_name, attr_matcher| {
+            |input, expected_payload, state, local_name, mut attr_matcher| {
                 let (matched_payload, _, _) =
                     exec_instr_range!(program.entry_points, program, state, local_name, attr_matcher);
 
@@ -519,6 +865,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "selector_matching")]
     #[test]
     fn compiled_non_attr_expression() {
         for encoding in ASCII_COMPATIBLE_ENCODINGS.iter() {
@@ -582,9 +929,22 @@ mod tests {
                     ("<anything-else>", true),
                 ],
             );
+
+            assert_non_attr_expr_matches_and_negation_reverses_match(
+                ":ctx(mobile)",
+                encoding,
+                &[("<div>", true), ("<span>", true)],
+            );
+
+            assert_non_attr_expr_matches_and_negation_reverses_match(
+                ":ctx(desktop)",
+                encoding,
+                &[("<div>", false), ("<span>", false)],
+            );
         }
     }
 
+    #[cfg(feature = "selector_matching")]
     #[test]
     fn compiled_attr_expression() {
         for encoding in ASCII_COMPATIBLE_ENCODINGS.iter() {
@@ -621,6 +981,18 @@ mod tests {
                 ],
             );
 
+            assert_attr_expr_matches_and_negation_reverses_match(
+                r#"[is="fancy-buttonα"]"#,
+                encoding,
+                &[
+                    ("<button is='fancy-buttonα'>", true),
+                    ("<button IS=fancy-buttonα disabled>", true),
+                    ("<button is='Fancy-Buttonα'>", false),
+                    ("<button is='fancy'>", false),
+                    ("<button>", false),
+                ],
+            );
+
             assert_attr_expr_matches_and_negation_reverses_match(
                 r#"[foo="barα"]"#,
                 encoding,
@@ -804,6 +1176,7 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "selector_matching")]
     #[test]
     fn generic_expressions() {
         for encoding in ASCII_COMPATIBLE_ENCODINGS.iter() {
@@ -846,6 +1219,18 @@ mod tests {
                     ("<span lang='en-GB'", false),
                 ],
             );
+
+            assert_attr_expr_matches_and_negation_reverses_match(
+                ":lang(en)",
+                encoding,
+                &[
+                    ("<div lang='en'>", true),
+                    ("<div lang='EN-gb'>", true),
+                    ("<div lang='english'>", false),
+                    ("<div lang='fr'>", false),
+                    ("<div>", false),
+                ],
+            );
         }
     }
 
@@ -890,16 +1275,16 @@ mod tests {
             ($html:expr, $add_range:expr, $expected_payload:expr) => {{
                 let mut jumps = Vec::default();
                 let mut hereditary_jumps = Vec::default();
-                let counter = Default::default();
-                let state = SelectorState { cumulative: &counter, typed: None };
 
-                with_start_tag($html, UTF_8, |local_name, attr_matcher| {
-                    let res = exec_instr_range!($add_range, program, &state, local_name, attr_matcher);
+                with_selector_state(|state| {
+                    with_start_tag($html, UTF_8, |local_name, mut attr_matcher| {
+                        let res = exec_instr_range!($add_range, program, state, local_name, attr_matcher);
 
-                    assert_payload!(res.0, $expected_payload, selectors, $html);
+                        assert_payload!(res.0, $expected_payload, selectors, $html);
 
-                    jumps = res.1;
-                    hereditary_jumps = res.2;
+                        jumps = res.1;
+                        hereditary_jumps = res.2;
+                    });
                 });
 
                 (jumps, hereditary_jumps)
@@ -963,4 +1348,78 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn shared_attr_comparisons() {
+        let mut registry = OperandsRegistry::default();
+
+        let mut add = |name: &str, value: &str, operator| {
+            let expr = AttributeComparisonExpr::new(
+                name.to_string(),
+                value.to_string(),
+                ParsedCaseSensitivity::CaseSensitive,
+                operator,
+            );
+
+            registry.get_or_add(UTF_8, &expr).unwrap()
+        };
+
+        let prefix = add("href", "http", AttrSelectorOperator::Prefix);
+
+        assert!(!prefix.is_shared());
+
+        let suffix = add("href", "http", AttrSelectorOperator::Suffix);
+        let other_value = add("href", "https", AttrSelectorOperator::Prefix);
+        let same_prefix = add("HREF", "http", AttrSelectorOperator::Prefix);
+
+        assert_eq!(same_prefix.id, prefix.id);
+        assert_ne!(suffix.id, prefix.id);
+        assert_ne!(other_value.id, prefix.id);
+        assert!(prefix.is_shared());
+        assert!(!suffix.is_shared());
+        assert!(!other_value.is_shared());
+        assert_eq!(suffix.name_id, prefix.name_id);
+
+        let program = compile(&["a[href^=http]", "div[href^=http]"], UTF_8, 2);
+        let start = program.entry_points.start;
+
+        let exec = |instr: &Instruction<usize>, input| {
+            let mut res = None;
+
+            with_start_tag(input, UTF_8, |local_name, mut attr_matcher| {
+                res = with_selector_state(|state| {
+                    instr
+                        .exec(state, &local_name, &mut attr_matcher)
+                        .map(|b| b.matched_payload.keys().copied().collect::<HashSet<_>>())
+                });
+            });
+
+            res
+        };
+
+        for (input, expected) in [
+            ("<a href='http://example.com'>", [Some(0), None]),
+            ("<div href='http://example.com'>", [None, Some(1)]),
+            ("<div href='ftp://example.com'>", [None, None]),
+        ]
+        .iter()
+        {
+            for (offset, expected) in expected.iter().enumerate() {
+                assert_eq!(
+                    exec(&program.instructions[start + offset], input),
+                    expected.map(|p| vec![p].into_iter().collect::<HashSet<_>>()),
+                    "Input: {}",
+                    input
+                );
+            }
+        }
+
+        let program = compile(&["[href^=HTTP i]", "[href$='.com' i]"], UTF_8, 2);
+        let start = program.entry_points.start;
+
+        for input in ["<a href='Http://Example.COM'>", "<a HREF='hTTp://example.com'>"].iter() {
+            assert!(exec(&program.instructions[start], input).is_some());
+            assert!(exec(&program.instructions[start + 1], input).is_some());
+        }
+    }
 }
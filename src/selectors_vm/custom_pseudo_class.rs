@@ -0,0 +1,91 @@
+use super::attribute_matcher::AttributeMatcher;
+use crate::base::Bytes;
+use encoding_rs::Encoding;
+use std::fmt::{self, Debug};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+/// The predicate of a user-defined pseudo-class.
+pub type CustomPseudoClassPredicate = Arc<dyn Fn(&ElementInfo) -> bool + Send + Sync>;
+
+/// The element a user-defined pseudo-class is matched against.
+///
+/// The pseudo-classes are matched once the start tag of the element is parsed, so only the
+/// attributes of the element in the source document are available to the predicates.
+pub struct ElementInfo<'a, 'i> {
+    attr_matcher: &'a AttributeMatcher<'i>,
+    encoding: &'static Encoding,
+}
+
+impl<'a, 'i> ElementInfo<'a, 'i> {
+    #[inline]
+    pub(crate) fn new(attr_matcher: &'a AttributeMatcher<'i>, encoding: &'static Encoding) -> Self {
+        ElementInfo {
+            attr_matcher,
+            encoding,
+        }
+    }
+
+    /// Returns the value of the element's attribute with the `name`, if it has one. The name
+    /// is matched ASCII case-insensitively.
+    #[inline]
+    pub fn get_attribute(&self, name: &str) -> Option<String> {
+        let name = name.to_ascii_lowercase();
+
+        self.attr_matcher
+            .get_value(&Bytes::from_str(&name, self.encoding))
+            .map(|value| value.as_string(self.encoding))
+    }
+
+    /// Returns `true` if the element has the attribute with the `name`. The name is matched
+    /// ASCII case-insensitively.
+    #[inline]
+    pub fn has_attribute(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+
+        self.attr_matcher
+            .has_attribute(&Bytes::from_str(&name, self.encoding))
+    }
+}
+
+/// A user-defined pseudo-class registered with [`Selector::with_custom_pseudo`].
+///
+/// [`Selector::with_custom_pseudo`]: struct.Selector.html#method.with_custom_pseudo
+#[derive(Clone)]
+pub struct CustomPseudoClass {
+    pub name: String,
+    pub predicate: CustomPseudoClassPredicate,
+}
+
+impl CustomPseudoClass {
+    #[inline]
+    fn predicate_ptr(&self) -> *const () {
+        &*self.predicate as *const _ as *const ()
+    }
+}
+
+// NOTE: the pseudo-classes registered with the same name, but different predicates should
+// produce different expressions, so the predicates are compared by their addresses.
+impl PartialEq for CustomPseudoClass {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.predicate_ptr() == other.predicate_ptr()
+    }
+}
+
+impl Eq for CustomPseudoClass {}
+
+impl Hash for CustomPseudoClass {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+    }
+}
+
+impl Debug for CustomPseudoClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CustomPseudoClass")
+            .field("name", &self.name)
+            .finish()
+    }
+}
@@ -1,4 +1,6 @@
+#[cfg(feature = "selector_matching")]
 use cssparser::{BasicParseErrorKind, ParseErrorKind};
+#[cfg(feature = "selector_matching")]
 use selectors::parser::{SelectorParseError, SelectorParseErrorKind};
 use thiserror::Error;
 
@@ -56,8 +58,16 @@ pub enum SelectorError {
     /// CSS syntax in the selector which is yet unsupported.
     #[error("Unsupported syntax in selector.")]
     UnsupportedSyntax,
+
+    /// The selector has pseudo-classes that require the [`buffer_has_candidates`] lookahead
+    /// (e.g. `:has()` or `:last-child`), while the lookahead is disabled.
+    ///
+    /// [`buffer_has_candidates`]: ../struct.Settings.html#structfield.buffer_has_candidates
+    #[error("The selector requires the lookahead, which is disabled.")]
+    LookaheadRequired,
 }
 
+#[cfg(feature = "selector_matching")]
 impl From<SelectorParseError<'_>> for SelectorError {
     fn from(err: SelectorParseError) -> Self {
         // NOTE: always use explicit variants in this match, so we
@@ -0,0 +1,119 @@
+use hashbrown::HashMap;
+use std::mem;
+
+/// The results of the attribute expressions of the instructions, keyed by the instruction
+/// addresses.
+pub type InstrResults = HashMap<usize, bool>;
+
+struct MatchCacheEntry {
+    last_used: u64,
+    results: InstrResults,
+}
+
+/// The least recently used cache of the results of the attribute expressions of the program,
+/// keyed by the raw bytes of the start tag attributes.
+///
+/// Documents often repeat identical start tags thousands of times (e.g. `<td class="cell">`).
+/// The attribute expressions of such tags always produce the same results, so they are
+/// evaluated once and the results are reused for the following tags.
+pub struct MatchCache {
+    capacity: usize,
+    entries: HashMap<Box<[u8]>, MatchCacheEntry>,
+    clock: u64,
+}
+
+impl MatchCache {
+    #[inline]
+    pub fn new(capacity: usize) -> Self {
+        MatchCache {
+            capacity,
+            entries: HashMap::default(),
+            clock: 0,
+        }
+    }
+
+    /// Takes the results memoized for the attributes, so the execution of the program for
+    /// the start tag can add new results to them. The results should be returned with
+    /// [`put`] once the execution is finished.
+    ///
+    /// [`put`]: #method.put
+    pub fn take(&mut self, attributes: &[u8]) -> InstrResults {
+        self.clock += 1;
+
+        match self.entries.get_mut(attributes) {
+            Some(entry) => {
+                entry.last_used = self.clock;
+                mem::take(&mut entry.results)
+            }
+            None => InstrResults::default(),
+        }
+    }
+
+    pub fn put(&mut self, attributes: &[u8], results: InstrResults) {
+        if let Some(entry) = self.entries.get_mut(attributes) {
+            entry.results = results;
+
+            return;
+        }
+
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.entries.len() >= self.capacity {
+            self.evict_least_recently_used();
+        }
+
+        self.entries.insert(
+            attributes.into(),
+            MatchCacheEntry {
+                last_used: self.clock,
+                results,
+            },
+        );
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        let key = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| key.clone());
+
+        if let Some(key) = key {
+            self.entries.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn least_recently_used_eviction() {
+        let mut cache = MatchCache::new(2);
+        let results = |addr| std::iter::once((addr, true)).collect::<InstrResults>();
+
+        for &(attributes, addr) in &[(&b"class=a"[..], 0), (&b"class=b"[..], 1)] {
+            let taken = cache.take(attributes);
+
+            assert!(taken.is_empty());
+
+            cache.put(attributes, results(addr));
+        }
+
+        let taken = cache.take(b"class=a");
+
+        assert_eq!(taken, results(0));
+
+        cache.put(b"class=a", taken);
+
+        assert!(cache.take(b"id=c").is_empty());
+
+        cache.put(b"id=c", results(2));
+
+        assert!(cache.take(b"class=b").is_empty());
+        assert_eq!(cache.take(b"class=a"), results(0));
+    }
+}
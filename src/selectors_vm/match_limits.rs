@@ -0,0 +1,270 @@
+use super::program::ExecutionBranch;
+use hashbrown::HashMap;
+use std::cell::Cell;
+use std::hash::Hash;
+
+struct Quota {
+    limit: usize,
+    remaining: Cell<usize>,
+}
+
+/// Tracks the remaining number of matches for selectors that are limited to their first N
+/// matches in the document.
+///
+/// Disabled selectors are tracked as selectors with zero quota, so they are skipped by the VM the
+/// same way as the selectors that have used up their quota.
+pub struct MatchLimits<P>
+where
+    P: Hash + Eq,
+{
+    quotas: HashMap<P, Quota>,
+    exhausted_count: Cell<usize>,
+    // NOTE: original limits of the disabled payloads.
+    disabled: HashMap<P, Option<usize>>,
+}
+
+impl<P> Default for MatchLimits<P>
+where
+    P: Hash + Eq,
+{
+    fn default() -> Self {
+        MatchLimits {
+            quotas: HashMap::default(),
+            exhausted_count: Cell::new(0),
+            disabled: HashMap::default(),
+        }
+    }
+}
+
+impl<P> MatchLimits<P>
+where
+    P: Hash + Eq,
+{
+    #[inline]
+    pub fn set(&mut self, payload: P, limit: usize) {
+        if limit == 0 {
+            self.exhausted_count.set(self.exhausted_count.get() + 1);
+        }
+
+        self.quotas.insert(
+            payload,
+            Quota {
+                limit,
+                remaining: Cell::new(limit),
+            },
+        );
+    }
+
+    fn recount_exhausted(&mut self) {
+        let exhausted_count = self
+            .quotas
+            .values()
+            .filter(|quota| quota.remaining.get() == 0)
+            .count();
+
+        self.exhausted_count.set(exhausted_count);
+    }
+
+    /// Prevents the `payload` from being matched until it's enabled again.
+    pub fn disable(&mut self, payload: P)
+    where
+        P: Clone,
+    {
+        if self.disabled.contains_key(&payload) {
+            return;
+        }
+
+        let limit = self.quotas.get(&payload).map(|quota| quota.limit);
+
+        self.disabled.insert(payload.clone(), limit);
+
+        self.quotas.insert(
+            payload,
+            Quota {
+                limit: 0,
+                remaining: Cell::new(0),
+            },
+        );
+
+        self.recount_exhausted();
+    }
+
+    /// Enables the previously disabled `payload`. The original limit of the payload is restored
+    /// with the full quota.
+    pub fn enable(&mut self, payload: &P)
+    where
+        P: Clone,
+    {
+        match self.disabled.remove(payload) {
+            Some(Some(limit)) => {
+                self.quotas.insert(
+                    payload.clone(),
+                    Quota {
+                        limit,
+                        remaining: Cell::new(limit),
+                    },
+                );
+            }
+            Some(None) => {
+                self.quotas.remove(payload);
+            }
+            None => return,
+        }
+
+        self.recount_exhausted();
+    }
+
+    /// Restores the initial quotas of all the payloads, e.g. when a new document starts.
+    pub fn reset(&mut self) {
+        let mut exhausted_count = 0;
+
+        for quota in self.quotas.values() {
+            quota.remaining.set(quota.limit);
+
+            if quota.limit == 0 {
+                exhausted_count += 1;
+            }
+        }
+
+        self.exhausted_count.set(exhausted_count);
+    }
+
+    /// Accounts a match of the `payload`. Returns `false` if the payload has already used up its
+    /// quota and, thus, shouldn't be matched anymore.
+    #[inline]
+    pub fn try_consume(&self, payload: &P) -> bool {
+        match self.quotas.get(payload) {
+            Some(quota) => match quota.remaining.get() {
+                0 => false,
+                n => {
+                    quota.remaining.set(n - 1);
+
+                    if n == 1 {
+                        self.exhausted_count.set(self.exhausted_count.get() + 1);
+                    }
+
+                    true
+                }
+            },
+            None => true,
+        }
+    }
+
+    #[inline]
+    fn is_exhausted(&self, payload: &P) -> bool {
+        match self.quotas.get(payload) {
+            Some(quota) => quota.remaining.get() == 0,
+            None => false,
+        }
+    }
+
+    /// Returns `true` if all the payload of the branch has used up its quota and the branch
+    /// doesn't lead to any other instructions. Such branches can't produce any matches, so
+    /// execution of the corresponding instruction can be skipped completely.
+    #[inline]
+    pub fn is_branch_exhausted(&self, branch: &ExecutionBranch<P>) -> bool {
+        self.exhausted_count.get() > 0
+            && branch.jumps.is_none()
+            && branch.hereditary_jumps.is_none()
+            && branch.next_sibling_jumps.is_none()
+            && branch.later_sibling_jumps.is_none()
+            && !branch.matched_payload.is_empty()
+            && branch.matched_payload.keys().all(|p| self.is_exhausted(p))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn branch(payload: &[usize]) -> ExecutionBranch<usize> {
+        ExecutionBranch {
+            matched_payload: payload.iter().map(|&p| (p, 0)).collect(),
+            jumps: None,
+            hereditary_jumps: None,
+            next_sibling_jumps: None,
+            later_sibling_jumps: None,
+        }
+    }
+
+    #[test]
+    fn consume_quota() {
+        let mut limits = MatchLimits::default();
+
+        limits.set(0, 2);
+
+        assert!(limits.try_consume(&0));
+        assert!(!limits.is_branch_exhausted(&branch(&[0])));
+        assert!(limits.try_consume(&0));
+        assert!(!limits.try_consume(&0));
+        assert!(limits.is_branch_exhausted(&branch(&[0])));
+
+        // NOTE: payload without a limit is never exhausted.
+        assert!(limits.try_consume(&1));
+        assert!(!limits.is_branch_exhausted(&branch(&[0, 1])));
+    }
+
+    #[test]
+    fn zero_limit() {
+        let mut limits = MatchLimits::default();
+
+        limits.set(0, 0);
+
+        assert!(!limits.try_consume(&0));
+        assert!(limits.is_branch_exhausted(&branch(&[0])));
+    }
+
+    #[test]
+    fn reset_quota() {
+        let mut limits = MatchLimits::default();
+
+        limits.set(0, 1);
+        limits.set(1, 0);
+
+        assert!(limits.try_consume(&0));
+        assert!(limits.is_branch_exhausted(&branch(&[0])));
+
+        limits.reset();
+
+        assert!(!limits.is_branch_exhausted(&branch(&[0])));
+        assert!(limits.is_branch_exhausted(&branch(&[1])));
+        assert!(limits.try_consume(&0));
+        assert!(!limits.try_consume(&0));
+    }
+
+    #[test]
+    fn disable_and_enable() {
+        let mut limits = MatchLimits::default();
+
+        limits.set(0, 2);
+        limits.disable(0);
+        limits.disable(1);
+        limits.reset();
+
+        assert!(limits.is_branch_exhausted(&branch(&[0, 1])));
+        assert!(!limits.try_consume(&0));
+        assert!(!limits.try_consume(&1));
+
+        limits.enable(&0);
+        limits.enable(&1);
+
+        assert!(!limits.is_branch_exhausted(&branch(&[0])));
+        assert!(!limits.is_branch_exhausted(&branch(&[1])));
+        assert!(limits.try_consume(&0));
+        assert!(limits.try_consume(&0));
+        assert!(!limits.try_consume(&0));
+        assert!(limits.try_consume(&1));
+        assert!(limits.try_consume(&1));
+    }
+
+    #[test]
+    fn branch_with_jumps_is_never_exhausted() {
+        let mut limits = MatchLimits::default();
+        let mut branch = branch(&[0]);
+
+        limits.set(0, 0);
+        branch.jumps = Some(0..1);
+
+        assert!(!limits.is_branch_exhausted(&branch));
+    }
+}
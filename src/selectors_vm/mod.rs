@@ -1,48 +1,70 @@
 mod ast;
+mod attr;
 mod attribute_matcher;
+#[cfg(any(test, not(feature = "selector_matching")))]
+mod basic_parser;
 mod compiler;
+#[cfg(feature = "selector_matching")]
+mod custom_pseudo_class;
 mod error;
+mod match_cache;
+mod match_limits;
+#[cfg(feature = "selector_matching")]
 mod parser;
 mod program;
 mod stack;
 
+use self::match_cache::MatchCache;
+use self::match_limits::MatchLimits;
 use self::program::AddressRange;
 use self::stack::StackDirective;
+use crate::base::{Bytes, LocalSharing, Shared, Sharing};
 use crate::html::{LocalName, Namespace};
 use crate::memory::{MemoryLimitExceededError, SharedMemoryLimiter};
 use crate::transform_stream::AuxStartTagInfo;
 use encoding_rs::Encoding;
+use hashbrown::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
 
 pub use self::ast::*;
 pub use self::attribute_matcher::AttributeMatcher;
+#[cfg(not(feature = "selector_matching"))]
+pub use self::basic_parser::Selector;
 pub use self::compiler::Compiler;
+#[cfg(feature = "selector_matching")]
+pub use self::custom_pseudo_class::ElementInfo;
 pub use self::error::SelectorError;
-pub use self::parser::Selector;
+#[cfg(feature = "selector_matching")]
+pub use self::parser::{HasCondition, LastSiblingCondition, Selector, SelectorBuilder};
 pub use self::program::{ExecutionBranch, Program, TryExecResult};
 pub use self::stack::{ElementData, Stack, StackItem, ChildCounter};
 
 pub struct MatchInfo<P> {
     pub payload: P,
+    /// The index of the matched alternative of the selector list.
+    pub alternative_idx: usize,
     pub with_content: bool,
 }
 
-pub type AuxStartTagInfoRequest<E, P> = Box<
+pub type AuxStartTagInfoRequest<E, S, P> = Box<
     dyn FnOnce(
-        &mut SelectorMatchingVm<E>,
+        &mut SelectorMatchingVm<E, S>,
         AuxStartTagInfo,
         &mut dyn FnMut(MatchInfo<P>),
-    ) -> Result<(), MemoryLimitExceededError>,
+    ) -> Result<(), MemoryLimitExceededError>
+        + Send,
 >;
 
-pub enum VmError<E: ElementData, MatchPayload> {
-    InfoRequest(AuxStartTagInfoRequest<E, MatchPayload>),
+pub enum VmError<E: ElementData, S: Sharing, MatchPayload> {
+    InfoRequest(AuxStartTagInfoRequest<E, S, MatchPayload>),
     MemoryLimitExceeded(MemoryLimitExceededError),
 }
 
-type RecoveryPointHandler<T, E, P> = fn(
-    &mut SelectorMatchingVm<E>,
+type RecoveryPointHandler<T, E, S, P> = fn(
+    &mut SelectorMatchingVm<E, S>,
     &mut ExecutionCtx<'static, E>,
-    &AttributeMatcher,
+    &mut AttributeMatcher,
     T,
     &mut dyn FnMut(MatchInfo<P>),
 );
@@ -67,12 +89,76 @@ struct Bailout<T> {
 
 /// A container for tracking state from various places on the stack.
 pub struct SelectorState<'i> {
+    pub ns: Namespace,
     pub cumulative: &'i ChildCounter,
     pub typed: Option<&'i ChildCounter>,
+    /// The language inherited from the parent element, if languages are tracked.
+    pub lang: Option<&'i [u8]>,
+    /// `true` if the element is at the top level of the document.
+    pub is_root: bool,
+    /// The index of the element among all the elements of the document.
+    pub element_index: usize,
+    /// The indices of the negated selector lists of the program that the element matches.
+    pub negated_selector_matches: &'i [usize],
+    /// The elements of the document that are known to satisfy the `:has()` pseudo-classes.
+    pub has_matches: &'i dyn HasMatchesLookup,
+}
+
+pub type SharedHasMatches<S = LocalSharing> = Shared<HasMatches, S>;
+
+/// Looks up the elements that satisfy the `:has()` pseudo-classes, so the compiled programs
+/// don't depend on how the matches are shared with the lookahead rewriter.
+pub trait HasMatchesLookup {
+    fn contains(&self, argument: &str, element_index: usize) -> bool;
+}
+
+impl<S: Sharing> HasMatchesLookup for SharedHasMatches<S> {
+    #[inline]
+    fn contains(&self, argument: &str, element_index: usize) -> bool {
+        self.lock().contains(argument, element_index)
+    }
+}
+
+/// The elements that are known to satisfy the relative selectors of the `:has()` pseudo-classes,
+/// identified by their indices in the document.
+#[derive(Default, Debug)]
+pub struct HasMatches(HashMap<String, HashSet<usize>>);
+
+impl HasMatches {
+    #[cfg(feature = "selector_matching")]
+    #[inline]
+    pub fn insert(&mut self, argument: &str, element_index: usize) {
+        match self.0.get_mut(argument) {
+            Some(indices) => {
+                indices.insert(element_index);
+            }
+            None => {
+                self.0
+                    .insert(argument.to_owned(), std::iter::once(element_index).collect());
+            }
+        }
+    }
+
+    #[inline]
+    pub fn contains(&self, argument: &str, element_index: usize) -> bool {
+        self.0
+            .get(argument)
+            .is_some_and(|indices| indices.contains(&element_index))
+    }
+
+    #[cfg(feature = "selector_matching")]
+    #[inline]
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
 }
 
 struct ExecutionCtx<'i, E: ElementData> {
     stack_item: StackItem<'i, E>,
+    // NOTE: unlike other jumps, the sibling jumps produced by the element are recorded
+    // for its parent, so they are kept separately from the stack item.
+    next_sibling_jumps: Vec<AddressRange>,
+    later_sibling_jumps: Vec<AddressRange>,
     with_content: bool,
     ns: Namespace,
 }
@@ -82,6 +168,8 @@ impl<'i, E: ElementData> ExecutionCtx<'i, E> {
     pub fn new(local_name: LocalName<'i>, ns: Namespace) -> Self {
         ExecutionCtx {
             stack_item: StackItem::new(local_name),
+            next_sibling_jumps: Vec::default(),
+            later_sibling_jumps: Vec::default(),
             with_content: true,
             ns,
         }
@@ -90,14 +178,16 @@ impl<'i, E: ElementData> ExecutionCtx<'i, E> {
     pub fn add_execution_branch(
         &mut self,
         branch: &ExecutionBranch<E::MatchPayload>,
+        match_limits: &MatchLimits<E::MatchPayload>,
         match_handler: &mut dyn FnMut(MatchInfo<E::MatchPayload>),
     ) {
-        for &payload in branch.matched_payload.iter() {
+        for (&payload, &alternative_idx) in branch.matched_payload.iter() {
             let element_payload = self.stack_item.element_data.matched_payload_mut();
 
-            if !element_payload.contains(&payload) {
+            if !element_payload.contains(&payload) && match_limits.try_consume(&payload) {
                 match_handler(MatchInfo {
                     payload,
+                    alternative_idx,
                     with_content: self.with_content,
                 });
 
@@ -116,42 +206,280 @@ impl<'i, E: ElementData> ExecutionCtx<'i, E> {
                     .push(hereditary_jumps.to_owned());
             }
         }
+
+        // NOTE: siblings of the element are reachable even if it doesn't have content.
+        if let Some(ref next_sibling_jumps) = branch.next_sibling_jumps {
+            self.next_sibling_jumps.push(next_sibling_jumps.to_owned());
+        }
+
+        if let Some(ref later_sibling_jumps) = branch.later_sibling_jumps {
+            self.later_sibling_jumps
+                .push(later_sibling_jumps.to_owned());
+        }
     }
 
     #[inline]
     pub fn into_owned(self) -> ExecutionCtx<'static, E> {
         ExecutionCtx {
             stack_item: self.stack_item.into_owned(),
+            next_sibling_jumps: self.next_sibling_jumps,
+            later_sibling_jumps: self.later_sibling_jumps,
             with_content: self.with_content,
             ns: self.ns,
         }
     }
 }
 
+/// The element data of the VM that matches the negated selector lists.
+#[derive(Default)]
+pub struct NegatedSelectorElementData(HashSet<usize>);
+
+impl ElementData for NegatedSelectorElementData {
+    type MatchPayload = usize;
+
+    #[inline]
+    fn matched_payload_mut(&mut self) -> &mut HashSet<usize> {
+        &mut self.0
+    }
+}
+
 macro_rules! aux_info_request {
     ($req:expr) => {
         Err(VmError::InfoRequest(Box::new($req)))
     };
 }
 
-pub struct SelectorMatchingVm<E: ElementData> {
-    program: Program<E::MatchPayload>,
-    stack: Stack<E>,
+/// The path of the open elements, e.g. `html > body > div#main > ul`, starting from
+/// the outermost one. Elements are represented by their lowercase tag names followed by
+/// their `id`s, if the ids are tracked.
+pub struct OpenElementsPath<'s, E: ElementData> {
+    ancestors: &'s [StackItem<'static, E>],
+    current: Option<(&'s LocalName<'static>, Option<&'s [u8]>)>,
+    encoding: &'static Encoding,
 }
 
-impl<E: ElementData> SelectorMatchingVm<E> {
+impl<'s, E: ElementData> OpenElementsPath<'s, E> {
+    /// Returns the path without the current element.
+    #[inline]
+    pub fn ancestors(&self) -> OpenElementsPath<'s, E> {
+        OpenElementsPath {
+            ancestors: self.ancestors,
+            current: None,
+            encoding: self.encoding,
+        }
+    }
+
+    fn fmt_element(
+        &self,
+        f: &mut Formatter,
+        local_name: &LocalName,
+        id: Option<&[u8]>,
+    ) -> fmt::Result {
+        f.write_str(&local_name.as_lowercase_string(self.encoding))?;
+
+        match id {
+            Some(id) => write!(f, "#{}", Bytes::from(id).as_string(self.encoding)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<E: ElementData> Display for OpenElementsPath<'_, E> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let ancestors = self
+            .ancestors
+            .iter()
+            .map(|item| (&item.local_name, item.id.as_deref()));
+
+        for (idx, (local_name, id)) in ancestors.chain(self.current).enumerate() {
+            if idx > 0 {
+                f.write_str(" > ")?;
+            }
+
+            self.fmt_element(f, local_name, id)?;
+        }
+
+        Ok(())
+    }
+}
+
+pub struct SelectorMatchingVm<E: ElementData, S: Sharing = LocalSharing> {
+    program: Arc<Program<E::MatchPayload>>,
+    stack: Stack<E, S>,
+    match_limits: MatchLimits<E::MatchPayload>,
+    encoding: &'static Encoding,
+    /// Matches the selector lists of the `:not()` pseudo-classes with complex selectors
+    /// against its own open element stack. All the start tags are executed with their
+    /// attributes if the program has such pseudo-classes.
+    negated_selectors_vm: Option<Box<SelectorMatchingVm<NegatedSelectorElementData, S>>>,
+    match_cache: Option<MatchCache>,
+    /// All the pushed start tags are executed with their attributes if the ids are tracked.
+    track_ids: bool,
+}
+
+impl<E: ElementData, S: Sharing> SelectorMatchingVm<E, S> {
+    #[cfg(test)]
     #[inline]
     pub fn new(
         ast: Ast<E::MatchPayload>,
         encoding: &'static Encoding,
-        memory_limiter: SharedMemoryLimiter,
+        context: &[&str],
+        memory_limiter: SharedMemoryLimiter<S>,
     ) -> Self {
-        let program = Compiler::new(encoding).compile(ast);
-        let enable_nth_of_type = program.enable_nth_of_type;
+        let program = Compiler::new(encoding, context).compile(ast);
+
+        SelectorMatchingVm::from_program(Arc::new(program), memory_limiter)
+    }
+
+    /// Creates the VM for the precompiled `program`. The program holds no matching state, so
+    /// it can be shared by any number of VMs, including the ones on other threads.
+    pub fn from_program(
+        program: Arc<Program<E::MatchPayload>>,
+        memory_limiter: SharedMemoryLimiter<S>,
+    ) -> Self {
+        let negated_selectors_vm = program.negated_program.as_ref().map(|negated_program| {
+            Box::new(SelectorMatchingVm::from_program(
+                Arc::clone(negated_program),
+                SharedMemoryLimiter::clone(&memory_limiter),
+            ))
+        });
 
         SelectorMatchingVm {
+            stack: Stack::new(memory_limiter, program.enable_nth_of_type),
+            match_limits: MatchLimits::default(),
+            encoding: program.encoding,
+            negated_selectors_vm,
+            match_cache: None,
+            track_ids: false,
             program,
-            stack: Stack::new(memory_limiter, enable_nth_of_type),
+        }
+    }
+
+    /// Enables the cache of the results of the attribute expressions for the last `capacity`
+    /// distinct attribute sets of the start tags.
+    #[inline]
+    pub fn enable_match_cache(&mut self, capacity: usize) {
+        self.match_cache = Some(MatchCache::new(capacity));
+    }
+
+    /// Drops the cache of the results of the attribute expressions, if it's enabled.
+    #[inline]
+    pub fn disable_match_cache(&mut self) {
+        self.match_cache = None;
+    }
+
+    /// Creates the attribute matcher for the start tag, restoring the results of the attribute
+    /// expressions memoized for the same attributes.
+    fn create_attr_matcher<'i>(
+        &mut self,
+        aux_info: AuxStartTagInfo<'i>,
+        ns: Namespace,
+    ) -> AttributeMatcher<'i> {
+        let mut attr_matcher = AttributeMatcher::new(
+            aux_info.input,
+            aux_info.attr_buffer,
+            aux_info.attr_names_bloom,
+            ns,
+        );
+
+        // NOTE: the comparisons of the attribute values can depend on the namespace,
+        // so only the results for the HTML elements are cached.
+        if let Some(cache) = &mut self.match_cache {
+            if ns == Namespace::Html {
+                let results = cache.take(attr_matcher.raw_attributes());
+
+                attr_matcher.memoize_instr_results(results);
+            }
+        }
+
+        attr_matcher
+    }
+
+    /// Stores the results of the attribute expressions memoized for the start tag in the cache.
+    fn cache_attr_matcher_results(&mut self, attr_matcher: AttributeMatcher) {
+        if let Some(cache) = &mut self.match_cache {
+            let attributes = attr_matcher.raw_attributes();
+
+            if let Some(results) = attr_matcher.into_instr_results() {
+                cache.put(attributes, results);
+            }
+        }
+    }
+
+    /// Limits the number of matches for the `payload` to the first `limit` matches in the
+    /// document. Once the limit is reached the instructions that can only produce the
+    /// `payload` are not executed anymore.
+    #[inline]
+    pub fn set_match_limit(&mut self, payload: E::MatchPayload, limit: usize) {
+        self.match_limits.set(payload, limit);
+    }
+
+    /// Disables or enables matching of the `payload`. Disabled payloads are skipped by the VM
+    /// without recompilation of the program.
+    #[inline]
+    pub fn set_payload_enabled(&mut self, payload: E::MatchPayload, enabled: bool) {
+        if enabled {
+            self.match_limits.enable(&payload);
+        } else {
+            self.match_limits.disable(payload);
+        }
+    }
+
+    /// Preallocates the space for the `depth` of the open element stack.
+    #[inline]
+    pub fn preallocate_stack(&mut self, depth: usize) {
+        self.stack.reserve(depth);
+
+        if let Some(vm) = &mut self.negated_selectors_vm {
+            vm.preallocate_stack(depth);
+        }
+    }
+
+    /// Returns the maximum depth the open element stack has reached so far.
+    #[inline]
+    pub fn max_stack_depth(&self) -> usize {
+        self.stack.max_depth()
+    }
+
+    /// Returns the current depth of the open element stack.
+    #[inline]
+    pub fn stack_depth(&self) -> usize {
+        self.stack.items().len()
+    }
+
+    /// Makes the VM record the `id`s of the open elements, so they are included in
+    /// the [`OpenElementsPath`].
+    #[inline]
+    pub fn enable_id_tracking(&mut self) {
+        self.track_ids = true;
+    }
+
+    /// Returns the path of the open elements.
+    #[inline]
+    pub fn open_elements_path(&self) -> OpenElementsPath<E> {
+        OpenElementsPath {
+            ancestors: self.stack.items(),
+            current: None,
+            encoding: self.encoding,
+        }
+    }
+
+    /// Returns the storage of the elements that satisfy the `:has()` pseudo-classes of the program.
+    #[cfg(feature = "selector_matching")]
+    #[inline]
+    pub fn has_matches(&self) -> SharedHasMatches<S> {
+        SharedHasMatches::clone(self.stack.has_matches())
+    }
+
+    /// Resets the matching state, so the VM can be used for a new document. The compiled program
+    /// is preserved.
+    #[inline]
+    pub fn reset(&mut self) {
+        self.stack.clear();
+        self.match_limits.reset();
+
+        if let Some(vm) = &mut self.negated_selectors_vm {
+            vm.reset();
         }
     }
 
@@ -160,25 +488,31 @@ impl<E: ElementData> SelectorMatchingVm<E> {
         local_name: LocalName,
         ns: Namespace,
         match_handler: &mut dyn FnMut(MatchInfo<E::MatchPayload>),
-    ) -> Result<(), VmError<E, E::MatchPayload>> {
+    ) -> Result<(), VmError<E, S, E::MatchPayload>> {
         use StackDirective::*;
 
         self.stack.add_child(&local_name);
 
         let mut ctx = ExecutionCtx::new(local_name, ns);
 
-        match Stack::get_stack_directive(&ctx.stack_item, ns) {
+        let attrs_required = match Stack::<E, S>::get_stack_directive(&ctx.stack_item, ns) {
             PopImmediately => {
                 ctx.with_content = false;
-                self.exec_without_attrs(ctx, match_handler)
+                false
             }
-            PushIfNotSelfClosing => {
-                let ctx = ctx.into_owned();
+            PushIfNotSelfClosing => true,
+            // NOTE: the language of the pushed element needs to be resolved from its
+            // attributes, so its descendants can inherit it.
+            Push => self.program.enable_lang_tracking || self.track_ids,
+        } || self.negated_selectors_vm.is_some();
 
-                aux_info_request!(move |this, aux_info, match_handler| this
-                    .exec_after_immediate_aux_info_request(ctx, ns, aux_info, match_handler))
-            }
-            Push => self.exec_without_attrs(ctx, match_handler),
+        if attrs_required {
+            let ctx = ctx.into_owned();
+
+            aux_info_request!(move |this, aux_info, match_handler| this
+                .exec_after_immediate_aux_info_request(ctx, ns, aux_info, match_handler))
+        } else {
+            self.exec_without_attrs(ctx, match_handler)
         }
     }
 
@@ -188,13 +522,83 @@ impl<E: ElementData> SelectorMatchingVm<E> {
         local_name: LocalName,
         unmatched_element_data_handler: impl FnMut(E),
     ) {
+        if let Some(vm) = &mut self.negated_selectors_vm {
+            // NOTE: a closure defined here would have a distinct type for each instantiation
+            // of the method, so the instantiation of the nested call would never terminate.
+            vm.exec_for_end_tag(local_name.clone(), drop);
+        }
+
         self.stack
             .pop_up_to(local_name, unmatched_element_data_handler);
     }
 
+    /// Pops all the elements from the open element stack, e.g. at the end of the document.
+    /// The elements are popped starting from the innermost one.
     #[inline]
-    pub fn current_element_data_mut(&mut self) -> Option<&mut E> {
-        self.stack.current_element_data_mut()
+    pub fn exec_for_end(&mut self, unmatched_element_data_handler: impl FnMut(E)) {
+        if let Some(vm) = &mut self.negated_selectors_vm {
+            vm.exec_for_end(drop);
+        }
+
+        self.stack.pop_all(unmatched_element_data_handler);
+    }
+
+    /// Returns an iterator over the names and the data of the open elements, starting from the
+    /// current element.
+    #[inline]
+    pub fn open_elements_mut(
+        &mut self,
+    ) -> impl Iterator<Item = (&LocalName<'static>, &mut E)> + '_ {
+        self.stack
+            .items_mut()
+            .iter_mut()
+            .rev()
+            .map(|item| (&item.local_name, &mut item.element_data))
+    }
+
+    /// Returns the data of the current element along with the path of the open elements.
+    #[inline]
+    pub fn current_element_data_mut_with_path(&mut self) -> (Option<&mut E>, OpenElementsPath<E>) {
+        let encoding = self.encoding;
+
+        match self.stack.items_mut().split_last_mut() {
+            Some((current, ancestors)) => (
+                Some(&mut current.element_data),
+                OpenElementsPath {
+                    ancestors,
+                    current: Some((&current.local_name, current.id.as_deref())),
+                    encoding,
+                },
+            ),
+            None => (
+                None,
+                OpenElementsPath {
+                    ancestors: &[],
+                    current: None,
+                    encoding,
+                },
+            ),
+        }
+    }
+
+    /// Executes the program for the start tag, providing the attributes on request, and
+    /// returns the matched payloads.
+    fn exec_for_start_tag_with_aux_info(
+        &mut self,
+        local_name: LocalName,
+        ns: Namespace,
+        aux_info: AuxStartTagInfo,
+    ) -> Result<Vec<E::MatchPayload>, MemoryLimitExceededError> {
+        let mut matched = Vec::new();
+        let mut match_handler = |m: MatchInfo<E::MatchPayload>| matched.push(m.payload);
+
+        match self.exec_for_start_tag(local_name, ns, &mut match_handler) {
+            Ok(()) => (),
+            Err(VmError::InfoRequest(req)) => req(self, aux_info, &mut match_handler)?,
+            Err(VmError::MemoryLimitExceeded(e)) => return Err(e),
+        }
+
+        Ok(matched)
     }
 
     fn exec_after_immediate_aux_info_request(
@@ -204,47 +608,98 @@ impl<E: ElementData> SelectorMatchingVm<E> {
         aux_info: AuxStartTagInfo,
         match_handler: &mut dyn FnMut(MatchInfo<E::MatchPayload>),
     ) -> Result<(), MemoryLimitExceededError> {
-        let attr_matcher = AttributeMatcher::new(aux_info.input, aux_info.attr_buffer, ns);
+        if let Some(vm) = &mut self.negated_selectors_vm {
+            let matches = vm.exec_for_start_tag_with_aux_info(
+                ctx.stack_item.local_name.clone(),
+                ns,
+                AuxStartTagInfo {
+                    input: aux_info.input,
+                    attr_buffer: aux_info.attr_buffer,
+                    attr_names_bloom: aux_info.attr_names_bloom,
+                    self_closing: aux_info.self_closing,
+                },
+            )?;
+
+            self.stack.set_negated_selector_matches(matches);
+        }
+
+        if ns != Namespace::Html && aux_info.self_closing {
+            ctx.with_content = false;
+        }
 
-        ctx.with_content = !aux_info.self_closing;
+        let mut attr_matcher = self.create_attr_matcher(aux_info, ns);
+
+        if self.program.enable_lang_tracking {
+            ctx.stack_item.lang = match attr_matcher.lang() {
+                Some(lang) => Some(Arc::from(&**lang)),
+                None => self.stack.items().last().and_then(|i| i.lang.clone()),
+            };
+        }
+
+        if self.track_ids {
+            ctx.stack_item.id = attr_matcher.id().map(|id| Box::from(&**id));
+        }
 
         self.exec_instr_set_with_attrs(
             &self.program.entry_points,
-            &attr_matcher,
+            &mut attr_matcher,
             &mut ctx,
             0,
             match_handler,
         );
 
-        self.exec_jumps_with_attrs(&attr_matcher, &mut ctx, JumpPtr::default(), match_handler);
+        self.exec_jumps_with_attrs(
+            &mut attr_matcher,
+            &mut ctx,
+            JumpPtr::default(),
+            match_handler,
+        );
+
+        self.exec_sibling_jumps_with_attrs(
+            &mut attr_matcher,
+            &mut ctx,
+            JumpPtr::default(),
+            match_handler,
+        );
 
         self.exec_hereditary_jumps_with_attrs(
-            &attr_matcher,
+            &mut attr_matcher,
             &mut ctx,
             HereditaryJumpPtr::default(),
             match_handler,
         );
 
+        self.cache_attr_matcher_results(attr_matcher);
+        self.complete_execution(ctx)
+    }
+
+    /// Records the sibling jumps produced by the element and pushes the element to the stack
+    /// if it has content.
+    #[inline]
+    fn complete_execution(&mut self, ctx: ExecutionCtx<E>) -> Result<(), MemoryLimitExceededError> {
+        self.stack
+            .add_sibling_jumps(ctx.next_sibling_jumps, ctx.later_sibling_jumps);
+
         if ctx.with_content {
-            self.stack.push_item(ctx.stack_item)?;
+            self.stack.push_item(ctx.stack_item.into_owned())
+        } else {
+            Ok(())
         }
-
-        Ok(())
     }
 
-    fn bailout<T: 'static>(
+    fn bailout<T: Send + 'static>(
         ctx: ExecutionCtx<E>,
         bailout: Bailout<T>,
-        recovery_point_handler: RecoveryPointHandler<T, E, E::MatchPayload>,
-    ) -> Result<(), VmError<E, E::MatchPayload>> {
+        recovery_point_handler: RecoveryPointHandler<T, E, S, E::MatchPayload>,
+    ) -> Result<(), VmError<E, S, E::MatchPayload>> {
         let mut ctx = ctx.into_owned();
 
         aux_info_request!(move |this, aux_info, match_handler| {
-            let attr_matcher = AttributeMatcher::new(aux_info.input, aux_info.attr_buffer, ctx.ns);
+            let mut attr_matcher = this.create_attr_matcher(aux_info, ctx.ns);
 
             this.complete_instr_execution_with_attrs(
                 bailout.at_addr,
-                &attr_matcher,
+                &mut attr_matcher,
                 &mut ctx,
                 match_handler,
             );
@@ -252,23 +707,20 @@ impl<E: ElementData> SelectorMatchingVm<E> {
             recovery_point_handler(
                 this,
                 &mut ctx,
-                &attr_matcher,
+                &mut attr_matcher,
                 bailout.recovery_point,
                 match_handler,
             );
 
-            if ctx.with_content {
-                this.stack.push_item(ctx.stack_item)?;
-            }
-
-            Ok(())
+            this.cache_attr_matcher_results(attr_matcher);
+            this.complete_execution(ctx)
         })
     }
 
     fn recover_after_bailout_in_entry_points(
         &mut self,
         ctx: &mut ExecutionCtx<'static, E>,
-        attr_matcher: &AttributeMatcher,
+        attr_matcher: &mut AttributeMatcher,
         recovery_point: usize,
         match_handler: &mut dyn FnMut(MatchInfo<E::MatchPayload>),
     ) {
@@ -281,6 +733,7 @@ impl<E: ElementData> SelectorMatchingVm<E> {
         );
 
         self.exec_jumps_with_attrs(attr_matcher, ctx, JumpPtr::default(), match_handler);
+        self.exec_sibling_jumps_with_attrs(attr_matcher, ctx, JumpPtr::default(), match_handler);
 
         self.exec_hereditary_jumps_with_attrs(
             attr_matcher,
@@ -293,11 +746,29 @@ impl<E: ElementData> SelectorMatchingVm<E> {
     fn recover_after_bailout_in_jumps(
         &mut self,
         ctx: &mut ExecutionCtx<'static, E>,
-        attr_matcher: &AttributeMatcher,
+        attr_matcher: &mut AttributeMatcher,
         recovery_point: JumpPtr,
         match_handler: &mut dyn FnMut(MatchInfo<E::MatchPayload>),
     ) {
         self.exec_jumps_with_attrs(attr_matcher, ctx, recovery_point, match_handler);
+        self.exec_sibling_jumps_with_attrs(attr_matcher, ctx, JumpPtr::default(), match_handler);
+
+        self.exec_hereditary_jumps_with_attrs(
+            attr_matcher,
+            ctx,
+            HereditaryJumpPtr::default(),
+            match_handler,
+        );
+    }
+
+    fn recover_after_bailout_in_sibling_jumps(
+        &mut self,
+        ctx: &mut ExecutionCtx<'static, E>,
+        attr_matcher: &mut AttributeMatcher,
+        recovery_point: JumpPtr,
+        match_handler: &mut dyn FnMut(MatchInfo<E::MatchPayload>),
+    ) {
+        self.exec_sibling_jumps_with_attrs(attr_matcher, ctx, recovery_point, match_handler);
 
         self.exec_hereditary_jumps_with_attrs(
             attr_matcher,
@@ -311,7 +782,7 @@ impl<E: ElementData> SelectorMatchingVm<E> {
     fn recover_after_bailout_in_hereditary_jumps(
         &mut self,
         ctx: &mut ExecutionCtx<'static, E>,
-        attr_matcher: &AttributeMatcher,
+        attr_matcher: &mut AttributeMatcher,
         recovery_point: HereditaryJumpPtr,
         match_handler: &mut dyn FnMut(MatchInfo<E::MatchPayload>),
     ) {
@@ -322,7 +793,7 @@ impl<E: ElementData> SelectorMatchingVm<E> {
         &mut self,
         mut ctx: ExecutionCtx<E>,
         match_handler: &mut dyn FnMut(MatchInfo<E::MatchPayload>),
-    ) -> Result<(), VmError<E, E::MatchPayload>> {
+    ) -> Result<(), VmError<E, S, E::MatchPayload>> {
         if let Err(b) = self.try_exec_instr_set_without_attrs(
             self.program.entry_points.clone(),
             &mut ctx,
@@ -335,32 +806,31 @@ impl<E: ElementData> SelectorMatchingVm<E> {
             return Self::bailout(ctx, b, Self::recover_after_bailout_in_jumps);
         }
 
+        if let Err(b) = self.try_exec_sibling_jumps_without_attrs(&mut ctx, match_handler) {
+            return Self::bailout(ctx, b, Self::recover_after_bailout_in_sibling_jumps);
+        }
+
         if let Err(b) = self.try_exec_hereditary_jumps_without_attrs(&mut ctx, match_handler) {
             return Self::bailout(ctx, b, Self::recover_after_bailout_in_hereditary_jumps);
         }
 
-        if ctx.with_content {
-            self.stack
-                .push_item(ctx.stack_item.into_owned())
-                .map_err(VmError::MemoryLimitExceeded)
-        } else {
-            Ok(())
-        }
+        self.complete_execution(ctx)
+            .map_err(VmError::MemoryLimitExceeded)
     }
 
     #[inline]
     fn complete_instr_execution_with_attrs(
         &self,
         addr: usize,
-        attr_matcher: &AttributeMatcher,
+        attr_matcher: &mut AttributeMatcher,
         ctx: &mut ExecutionCtx<E>,
         match_handler: &mut dyn FnMut(MatchInfo<E::MatchPayload>),
     ) {
-        let state = self.stack.build_state(&ctx.stack_item.local_name);
+        let state = self.stack.build_state(&ctx.stack_item.local_name, ctx.ns);
         if let Some(branch) =
-            self.program.instructions[addr].complete_exec_with_attrs(&state, &attr_matcher)
+            self.program.instructions[addr].complete_exec_with_attrs(&state, attr_matcher)
         {
-            ctx.add_execution_branch(branch, match_handler);
+            ctx.add_execution_branch(branch, &self.match_limits, match_handler);
         }
     }
 
@@ -372,12 +842,18 @@ impl<E: ElementData> SelectorMatchingVm<E> {
         match_handler: &mut dyn FnMut(MatchInfo<E::MatchPayload>),
     ) -> Result<(), Bailout<usize>> {
         let start = addr_range.start;
-        let state = self.stack.build_state(&ctx.stack_item.local_name);
+        let state = self.stack.build_state(&ctx.stack_item.local_name, ctx.ns);
 
         for addr in addr_range {
-            match self.program.instructions[addr].try_exec_without_attrs(&state, &ctx.stack_item.local_name) {
+            let instr = &self.program.instructions[addr];
+
+            if self.match_limits.is_branch_exhausted(&instr.associated_branch) {
+                continue;
+            }
+
+            match instr.try_exec_without_attrs(&state, &ctx.stack_item.local_name) {
                 TryExecResult::Branch(branch) => {
-                    ctx.add_execution_branch(branch, match_handler)
+                    ctx.add_execution_branch(branch, &self.match_limits, match_handler)
                 }
                 TryExecResult::AttributesRequired => {
                     return Err(Bailout {
@@ -396,17 +872,21 @@ impl<E: ElementData> SelectorMatchingVm<E> {
     fn exec_instr_set_with_attrs(
         &self,
         addr_range: &AddressRange,
-        attr_matcher: &AttributeMatcher,
+        attr_matcher: &mut AttributeMatcher,
         ctx: &mut ExecutionCtx<E>,
         offset: usize,
         match_handler: &mut dyn FnMut(MatchInfo<E::MatchPayload>),
     ) {
-        let state = self.stack.build_state(&ctx.stack_item.local_name);
+        let state = self.stack.build_state(&ctx.stack_item.local_name, ctx.ns);
         for addr in addr_range.start + offset..addr_range.end {
             let instr = &self.program.instructions[addr];
 
+            if self.match_limits.is_branch_exhausted(&instr.associated_branch) {
+                continue;
+            }
+
             if let Some(branch) = instr.exec(&state, &ctx.stack_item.local_name, attr_matcher) {
-                ctx.add_execution_branch(branch, match_handler);
+                ctx.add_execution_branch(branch, &self.match_limits, match_handler);
             }
         }
     }
@@ -434,7 +914,7 @@ impl<E: ElementData> SelectorMatchingVm<E> {
 
     fn exec_jumps_with_attrs(
         &self,
-        attr_matcher: &AttributeMatcher,
+        attr_matcher: &mut AttributeMatcher,
         ctx: &mut ExecutionCtx<E>,
         ptr: JumpPtr,
         match_handler: &mut dyn FnMut(MatchInfo<E::MatchPayload>),
@@ -458,6 +938,45 @@ impl<E: ElementData> SelectorMatchingVm<E> {
         }
     }
 
+    fn try_exec_sibling_jumps_without_attrs(
+        &self,
+        ctx: &mut ExecutionCtx<E>,
+        match_handler: &mut dyn FnMut(MatchInfo<E::MatchPayload>),
+    ) -> Result<(), Bailout<JumpPtr>> {
+        for (i, jumps) in self.stack.sibling_jumps().iter().enumerate() {
+            self.try_exec_instr_set_without_attrs(jumps.clone(), ctx, match_handler)
+                .map_err(|b| Bailout {
+                    at_addr: b.at_addr,
+                    recovery_point: JumpPtr {
+                        instr_set_idx: i,
+                        offset: b.recovery_point,
+                    },
+                })?;
+        }
+
+        Ok(())
+    }
+
+    fn exec_sibling_jumps_with_attrs(
+        &self,
+        attr_matcher: &mut AttributeMatcher,
+        ctx: &mut ExecutionCtx<E>,
+        ptr: JumpPtr,
+        match_handler: &mut dyn FnMut(MatchInfo<E::MatchPayload>),
+    ) {
+        let sibling_jumps = self.stack.sibling_jumps();
+
+        // NOTE: find pointed jumps instruction set and execute it with the offset.
+        if let Some(ptr_jumps) = sibling_jumps.iter().nth(ptr.instr_set_idx) {
+            self.exec_instr_set_with_attrs(ptr_jumps, attr_matcher, ctx, ptr.offset, match_handler);
+
+            // NOTE: execute remaining jumps instruction sets as usual.
+            for jumps in sibling_jumps.iter().skip(ptr.instr_set_idx + 1) {
+                self.exec_instr_set_with_attrs(jumps, attr_matcher, ctx, 0, match_handler);
+            }
+        }
+    }
+
     fn try_exec_hereditary_jumps_without_attrs(
         &self,
         ctx: &mut ExecutionCtx<E>,
@@ -486,7 +1005,7 @@ impl<E: ElementData> SelectorMatchingVm<E> {
 
     fn exec_hereditary_jumps_with_attrs(
         &self,
-        attr_matcher: &AttributeMatcher,
+        attr_matcher: &mut AttributeMatcher,
         ctx: &mut ExecutionCtx<E>,
         ptr: HereditaryJumpPtr,
         match_handler: &mut dyn FnMut(MatchInfo<E::MatchPayload>),
@@ -593,6 +1112,10 @@ mod tests {
                 TokenCaptureFlags::all()
             }
 
+            fn needs_attributes(&self) -> bool {
+                true
+            }
+
             fn handle_start_tag(
                 &mut self,
                 _: LocalName,
@@ -605,6 +1128,10 @@ mod tests {
                 TokenCaptureFlags::all()
             }
 
+            fn handle_spilled_start_tag(&mut self) -> TokenCaptureFlags {
+                TokenCaptureFlags::all()
+            }
+
             fn handle_end(&mut self, _: &mut DocumentEnd) -> Result<(), RewritingError> {
                 Ok(())
             }
@@ -617,15 +1144,27 @@ mod tests {
             fn should_emit_content(&self) -> bool {
                 true
             }
+
+            fn should_pass_through(&self) -> bool {
+                false
+            }
+
+            fn reset(&mut self) {}
         }
 
-        let mut transform_stream = TransformStream::new(TransformStreamSettings {
+        let mut transform_stream: TransformStream<_, _> = TransformStream::new(TransformStreamSettings {
             transform_controller: TestTransformController(test_fn),
             output_sink: |_: &[u8]| {},
             preallocated_parsing_buffer_size: 0,
+            preallocated_attribute_buffer_capacity: 0,
             encoding,
             memory_limiter: MemoryLimiter::new_shared(2048),
             strict: true,
+            spill_settings: Default::default(),
+            stream_comments: false,
+            passthrough_after: None,
+            #[cfg(feature = "parse_ahead")]
+            parse_ahead_queue_capacity: None,
         });
 
         transform_stream.write(&*html).unwrap();
@@ -661,7 +1200,7 @@ mod tests {
 
             let memory_limiter = MemoryLimiter::new_shared(2048);
             let vm: SelectorMatchingVm<TestElementData> =
-                SelectorMatchingVm::new(ast, UTF_8, memory_limiter);
+                SelectorMatchingVm::new(ast, UTF_8, &[], memory_limiter);
 
             vm
         }};
@@ -685,7 +1224,7 @@ mod tests {
 
                             if $expectation.should_bailout {
                                 let aux_info_req = result.expect_err("Bailout expected");
-                                let (input, attr_buffer) = t.raw_attributes();
+                                let (input, attr_buffer, attr_names_bloom) = t.raw_attributes();
 
                                 match aux_info_req {
                                     VmError::InfoRequest(f) => f(
@@ -693,6 +1232,7 @@ mod tests {
                                         AuxStartTagInfo {
                                             input,
                                             attr_buffer,
+                                            attr_names_bloom,
                                             self_closing: t.self_closing(),
                                         },
                                         &mut match_handler,
@@ -741,6 +1281,7 @@ mod tests {
         };
     }
 
+    #[cfg(feature = "selector_matching")]
     #[test]
     fn html_elements() {
         let mut vm = create_vm!(&["a", "img.c1", ":not(a).c2"]);
@@ -979,6 +1520,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "selector_matching")]
     #[test]
     fn nth_child() {
         let mut vm = create_vm!(&["div:first-child", "div:nth-child(2n+1)"]);
@@ -1074,6 +1616,7 @@ mod tests {
         exec_for_end_tag_and_assert!(vm, "</html>", map![]);
     }
 
+    #[cfg(feature = "selector_matching")]
     #[test]
     fn nth_of_type() {
         let mut vm = create_vm!(&["div:first-of-type", "div:nth-of-type(2n+1)"]);
@@ -1207,6 +1750,78 @@ mod tests {
         exec_for_end_tag_and_assert!(vm, "</html>", map![]);
     }
 
+    #[cfg(feature = "selector_matching")]
+    #[test]
+    fn lang() {
+        let mut vm = create_vm!(&["p:lang(en)", "span:lang(fr)"]);
+
+        macro_rules! assert_start_tag {
+            ($tag_html:expr, $matched_payload:expr) => {
+                // NOTE: languages of all the pushed elements are resolved from attributes.
+                exec_for_start_tag_and_assert!(
+                    vm,
+                    $tag_html,
+                    Namespace::Html,
+                    Expectation {
+                        should_bailout: true,
+                        should_match_with_content: true,
+                        matched_payload: $matched_payload,
+                    }
+                );
+            };
+        }
+
+        // Stack:
+        // 0: div (en-US)
+        assert_start_tag!("<div lang='en-US'>", set![]);
+
+        // Stack:
+        // 0: div (en-US)
+        // 1: p (en-US)
+        assert_start_tag!("<p>", set![0]);
+
+        // Stack:
+        // 0: div (en-US)
+        // 1: p (en-US)
+        // 2: span (fr)
+        assert_start_tag!("<span lang='FR'>", set![1]);
+
+        // Stack:
+        // 0: div (en-US)
+        // 1: p (en-US)
+        // 2: span (fr)
+        // 3: p (fr)
+        assert_start_tag!("<p>", set![]);
+
+        // Stack:
+        // 0: div (en-US)
+        // 1: p (en-US)
+        // 2: span (fr)
+        exec_for_end_tag_and_assert!(vm, "</p>", map![]);
+
+        // Stack:
+        // 0: div (en-US)
+        // 1: p (en-US)
+        exec_for_end_tag_and_assert!(vm, "</span>", map![(1, 1)]);
+
+        // Stack:
+        // 0: div (en-US)
+        exec_for_end_tag_and_assert!(vm, "</p>", map![(0, 1)]);
+
+        // Stack:
+        // 0: div (en-US)
+        // 1: span (english)
+        assert_start_tag!("<span lang='english'>", set![]);
+
+        // Stack:
+        exec_for_end_tag_and_assert!(vm, "</div>", map![]);
+
+        // Stack:
+        // 0: p
+        assert_start_tag!("<p>", set![]);
+    }
+
+    #[cfg(feature = "selector_matching")]
     #[test]
     fn jumps() {
         let mut vm = create_vm!(&["div > span", "div > #foo", ":not(span) > .c2 > .c3"]);
@@ -1613,6 +2228,55 @@ mod tests {
         exec_for_end_tag_and_assert!(vm, "</body>", map![(0, 3), (1, 1), (2, 2)]);
     }
 
+    #[test]
+    fn descendant_exprs_without_matched_ancestor() {
+        let mut vm = create_vm!(&["div.sidebar a[href]", "div a[href]"]);
+
+        // NOTE: the attributes of the element are not required, since
+        // the descendant expressions are not reachable.
+        // Stack after:
+        // - <a href>
+        exec_for_start_tag_and_assert!(
+            vm,
+            "<a href='/foo'>",
+            Namespace::Html,
+            Expectation {
+                should_bailout: false,
+                should_match_with_content: true,
+                matched_payload: set![],
+            }
+        );
+
+        exec_for_end_tag_and_assert!(vm, "</a>", map![]);
+
+        // Stack after:
+        // - <div>
+        exec_for_start_tag_and_assert!(
+            vm,
+            "<div>",
+            Namespace::Html,
+            Expectation {
+                should_bailout: true,
+                should_match_with_content: true,
+                matched_payload: set![],
+            }
+        );
+
+        // Stack after:
+        // - <div>
+        // - <a href> (1)
+        exec_for_start_tag_and_assert!(
+            vm,
+            "<a href='/foo'>",
+            Namespace::Html,
+            Expectation {
+                should_bailout: true,
+                should_match_with_content: true,
+                matched_payload: set![1],
+            }
+        );
+    }
+
     #[test]
     fn compound_selector() {
         let mut vm = create_vm!(&["body > span#foo .c1 .c2"]);
@@ -1728,4 +2392,150 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn sibling_jumps() {
+        let mut vm = create_vm!(&["h2 + p", "h2 ~ ul", "img + span", "h2 ~ .c1"]);
+
+        macro_rules! assert_start_tag {
+            ($tag_html:expr, $should_bailout:expr, $matched_payload:expr) => {
+                exec_for_start_tag_and_assert!(
+                    vm,
+                    $tag_html,
+                    Namespace::Html,
+                    Expectation {
+                        should_bailout: $should_bailout,
+                        should_match_with_content: true,
+                        matched_payload: $matched_payload,
+                    }
+                );
+            };
+        }
+
+        assert_start_tag!("<h2>", false, set![]);
+        exec_for_end_tag_and_assert!(vm, "</h2>", map![]);
+
+        assert_start_tag!("<p>", true, set![0]);
+        exec_for_end_tag_and_assert!(vm, "</p>", map![(0, 1)]);
+
+        // NOTE: the next sibling jumps are not applicable to the following elements.
+        assert_start_tag!("<p class=c1>", true, set![3]);
+        exec_for_end_tag_and_assert!(vm, "</p>", map![(3, 1)]);
+
+        // Stack after:
+        // - <ul> (1)
+        assert_start_tag!("<ul>", true, set![1]);
+
+        // NOTE: void elements don't have content, but have siblings.
+        assert_start_tag!("<img>", false, set![]);
+
+        // Stack after:
+        // - <ul> (1)
+        // - <span> (2)
+        assert_start_tag!("<span>", false, set![2]);
+        exec_for_end_tag_and_assert!(vm, "</ul>", map![(1, 1), (2, 1)]);
+
+        // NOTE: the sibling jumps of the children of <ul> are gone along with it.
+        assert_start_tag!("<span>", true, set![]);
+    }
+
+    #[cfg(feature = "selector_matching")]
+    #[test]
+    fn negated_selectors() {
+        let mut vm = create_vm!(&["p:not(.foo p)", "span:not(div > span)"]);
+
+        macro_rules! assert_start_tag {
+            ($tag_html:expr, $matched_payload:expr) => {
+                // NOTE: all the start tags require attributes, since they are matched
+                // against the negated selectors.
+                exec_for_start_tag_and_assert!(
+                    vm,
+                    $tag_html,
+                    Namespace::Html,
+                    Expectation {
+                        should_bailout: true,
+                        should_match_with_content: true,
+                        matched_payload: $matched_payload,
+                    }
+                );
+            };
+        }
+
+        // Stack after:
+        // - <div class=foo>
+        // - <p>
+        assert_start_tag!("<div class=foo>", set![]);
+        assert_start_tag!("<p>", set![]);
+
+        // Stack after:
+        // - <div class=foo>
+        // - <p>
+        // - <span> (1)
+        assert_start_tag!("<span>", set![1]);
+        exec_for_end_tag_and_assert!(vm, "</span>", map![(1, 1)]);
+        exec_for_end_tag_and_assert!(vm, "</p>", map![]);
+
+        assert_start_tag!("<span>", set![]);
+        exec_for_end_tag_and_assert!(vm, "</div>", map![]);
+
+        // Stack after:
+        // - <p> (0)
+        assert_start_tag!("<p>", set![0]);
+    }
+
+    #[cfg(feature = "selector_matching")]
+    #[test]
+    fn extended_program() {
+        let vm = create_vm!(&["p:not(.foo p)", "div > span"]);
+        let mut ast = Ast::default();
+
+        for (i, selector) in ["span:not(div > span)", "div p"].iter().enumerate() {
+            ast.add_selector(&selector.parse().unwrap(), i + 2);
+        }
+
+        // NOTE: the selectors of the program with the odd payloads are removed.
+        let program = Compiler::extend(&vm.program, ast, |payload| {
+            Some(payload).filter(|p| p % 2 == 0)
+        });
+
+        let memory_limiter = MemoryLimiter::new_shared(2048);
+        let mut vm: SelectorMatchingVm<TestElementData> =
+            SelectorMatchingVm::from_program(Arc::new(program), memory_limiter);
+
+        macro_rules! assert_start_tag {
+            ($tag_html:expr, $matched_payload:expr) => {
+                exec_for_start_tag_and_assert!(
+                    vm,
+                    $tag_html,
+                    Namespace::Html,
+                    Expectation {
+                        should_bailout: true,
+                        should_match_with_content: true,
+                        matched_payload: $matched_payload,
+                    }
+                );
+            };
+        }
+
+        // Stack after:
+        // - <div class=foo>
+        // - <p> (3)
+        assert_start_tag!("<div class=foo>", set![]);
+        assert_start_tag!("<p>", set![3]);
+
+        // Stack after:
+        // - <div class=foo>
+        // - <p> (3)
+        // - <span> (2)
+        assert_start_tag!("<span>", set![2]);
+        exec_for_end_tag_and_assert!(vm, "</span>", map![(2, 1)]);
+        exec_for_end_tag_and_assert!(vm, "</p>", map![(3, 1)]);
+
+        assert_start_tag!("<span>", set![]);
+        exec_for_end_tag_and_assert!(vm, "</div>", map![]);
+
+        // Stack after:
+        // - <p> (0)
+        assert_start_tag!("<p>", set![0]);
+    }
 }
@@ -1,12 +1,17 @@
+use super::custom_pseudo_class::{CustomPseudoClass, ElementInfo};
 use super::SelectorError;
 use crate::html::Namespace;
-use cssparser::{Parser as CssParser, ParserInput, ToCss};
+use cssparser::{
+    CowRcStr, ParseError, Parser as CssParser, ParserInput, SourceLocation, ToCss, Token,
+};
 use selectors::parser::{
     Combinator, Component, NonTSPseudoClass, Parser, PseudoElement, SelectorImpl, SelectorList,
     SelectorParseErrorKind,
 };
+use std::borrow::Cow;
 use std::fmt;
 use std::str::FromStr;
+use std::sync::Arc;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct SelectorImplDescriptor;
@@ -21,7 +26,7 @@ impl SelectorImpl for SelectorImplDescriptor {
     type BorrowedNamespaceUrl = Namespace;
     type BorrowedLocalName = String;
 
-    type NonTSPseudoClass = NonTSPseudoClassStub;
+    type NonTSPseudoClass = NonTSPseudoClassDescriptor;
     type PseudoElement = PseudoElementStub;
 
     type ExtraMatchingData = ();
@@ -41,26 +46,207 @@ impl PseudoElement for PseudoElementStub {
 }
 
 #[derive(PartialEq, Eq, Clone, Debug, Hash)]
-pub enum NonTSPseudoClassStub {}
+pub enum NonTSPseudoClassDescriptor {
+    /// The `:lang()` pseudo-class with the language range.
+    Lang(String),
+    /// The `:ctx()` pseudo-class with the key of the rewriter context.
+    Ctx(String),
+    /// The `:has()` pseudo-class with the relative selector.
+    Has(HasArgument),
+    /// The `:not()` pseudo-class with the selector list containing complex selectors.
+    ComplexNegation(String),
+    /// The `:is()` or `:where()` pseudo-class with the selector list. The pseudo-classes
+    /// match the same elements, they only differ in specificity.
+    Is(String),
+    /// The user-defined pseudo-class.
+    Custom(CustomPseudoClass),
+}
+
+/// The relative selector of the `:has()` pseudo-class. Only a compound selector, optionally
+/// preceded by the child combinator, is supported.
+#[derive(PartialEq, Eq, Clone, Debug, Hash)]
+pub struct HasArgument {
+    pub child_only: bool,
+    pub compound: String,
+}
+
+impl HasArgument {
+    fn parse(argument: &str) -> Option<Self> {
+        let argument = argument.trim();
+
+        let (child_only, compound) = if let Some(compound) = argument.strip_prefix('>') {
+            (true, compound.trim_start())
+        } else {
+            (false, argument)
+        };
+
+        let selector_list = SelectorsParser::parse(compound).ok()?;
+
+        let is_compound = selector_list.0.len() == 1
+            && selector_list.0[0].iter_raw_match_order().all(|c| match c {
+                Component::Combinator(_) => false,
+                Component::Negation(components) => components.iter().all(|c| !needs_lookahead(c)),
+                c => !needs_lookahead(c),
+            });
+
+        if is_compound {
+            Some(HasArgument {
+                child_only,
+                compound: compound.to_owned(),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for HasArgument {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.child_only {
+            write!(f, "> {}", self.compound)
+        } else {
+            write!(f, "{}", self.compound)
+        }
+    }
+}
+
+#[inline]
+fn is_custom(component: &Component<SelectorImplDescriptor>) -> bool {
+    matches!(
+        component,
+        Component::NonTSPseudoClass(NonTSPseudoClassDescriptor::Custom(_))
+    )
+}
+
+#[inline]
+fn is_has(component: &Component<SelectorImplDescriptor>) -> bool {
+    matches!(
+        component,
+        Component::NonTSPseudoClass(NonTSPseudoClassDescriptor::Has(_))
+    )
+}
 
-impl NonTSPseudoClass for NonTSPseudoClassStub {
+/// Returns `true` for the pseudo-classes that depend on the following siblings of the element.
+#[inline]
+fn is_last_sibling(component: &Component<SelectorImplDescriptor>) -> bool {
+    matches!(
+        component,
+        Component::LastChild | Component::LastOfType | Component::OnlyChild | Component::OnlyOfType
+    )
+}
+
+/// Returns `true` for the pseudo-classes that are resolved by the lookahead rewriter.
+#[inline]
+fn needs_lookahead(component: &Component<SelectorImplDescriptor>) -> bool {
+    is_has(component) || is_last_sibling(component)
+}
+
+impl NonTSPseudoClass for NonTSPseudoClassDescriptor {
     type Impl = SelectorImplDescriptor;
 
     fn is_active_or_hover(&self) -> bool {
-        match *self {}
+        false
     }
 }
 
-impl ToCss for NonTSPseudoClassStub {
-    fn to_css<W: fmt::Write>(&self, _dest: &mut W) -> fmt::Result {
-        match *self {}
+impl ToCss for NonTSPseudoClassDescriptor {
+    fn to_css<W: fmt::Write>(&self, dest: &mut W) -> fmt::Result {
+        match self {
+            NonTSPseudoClassDescriptor::Lang(lang) => write!(dest, ":lang({})", lang),
+            NonTSPseudoClassDescriptor::Ctx(key) => write!(dest, ":ctx({})", key),
+            NonTSPseudoClassDescriptor::Has(argument) => write!(dest, ":has({})", argument),
+            NonTSPseudoClassDescriptor::ComplexNegation(selector) => {
+                write!(dest, ":not({})", selector)
+            }
+            NonTSPseudoClassDescriptor::Is(selector) => write!(dest, ":is({})", selector),
+            NonTSPseudoClassDescriptor::Custom(pseudo_class) => {
+                write!(dest, ":{}", pseudo_class.name)
+            }
+        }
+    }
+}
+
+/// The name of the internal pseudo-class the `:not()` pseudo-classes with complex selectors are
+/// rewritten to, since the `selectors` crate accepts only simple selectors in negations.
+const COMPLEX_NEGATION: &str = "-lol-html-complex-not";
+
+/// Returns `true` if the argument of the `:not()` pseudo-class is a valid selector list with
+/// a complex selector.
+fn is_complex_negation(argument: &str) -> bool {
+    SelectorsParser::parse(argument).is_ok_and(|selector_list| {
+        selector_list.0.iter().any(|selector| {
+            selector
+                .iter_raw_match_order()
+                .any(|c| matches!(c, Component::Combinator(_)))
+        })
+    })
+}
+
+/// Rewrites the top level `:not()` pseudo-classes with complex selectors to the internal
+/// pseudo-class. The nested ones are rewritten once their enclosing pseudo-class parses its
+/// argument.
+fn desugar_complex_negations(selector: &str) -> Cow<str> {
+    let mut input = ParserInput::new(selector);
+    let mut parser = CssParser::new(&mut input);
+    let mut desugared = String::new();
+    let mut copied_until = parser.position();
+    let mut after_colon = false;
+
+    loop {
+        let token_start = parser.position();
+
+        let is_negation = match parser.next_including_whitespace() {
+            Ok(Token::Function(name)) => after_colon && name.eq_ignore_ascii_case("not"),
+            Ok(token) => {
+                after_colon = *token == Token::Colon;
+                continue;
+            }
+            Err(_) => break,
+        };
+
+        after_colon = false;
+
+        // NOTE: the parser skips the arguments of the other functions on the next call.
+        if !is_negation {
+            continue;
+        }
+
+        let argument_start = parser.position();
+
+        let argument: Result<&str, ParseError<()>> = parser.parse_nested_block(|arguments| {
+            while arguments.next_including_whitespace().is_ok() {}
+
+            Ok(arguments.slice_from(argument_start))
+        });
+
+        if let Ok(argument) = argument {
+            if is_complex_negation(argument) {
+                desugared.push_str(parser.slice(copied_until..token_start));
+                desugared.push_str(COMPLEX_NEGATION);
+                desugared.push('(');
+                desugared.push_str(argument);
+                desugared.push(')');
+
+                copied_until = parser.position();
+            }
+        }
+    }
+
+    if desugared.is_empty() {
+        Cow::Borrowed(selector)
+    } else {
+        desugared.push_str(parser.slice_from(copied_until));
+
+        Cow::Owned(desugared)
     }
 }
 
 #[allow(dead_code)]
-struct SelectorsParser;
+struct SelectorsParser<'p> {
+    custom_pseudo_classes: &'p [CustomPseudoClass],
+}
 
-impl SelectorsParser {
+impl<'p> SelectorsParser<'p> {
     fn validate_component(
         component: &Component<SelectorImplDescriptor>,
     ) -> Result<(), SelectorError> {
@@ -71,11 +257,11 @@ impl SelectorsParser {
         match component {
             Component::Combinator(combinator) => match combinator {
                 // Supported
-                Combinator::Child | Combinator::Descendant => Ok(()),
+                Combinator::Child
+                | Combinator::Descendant
+                | Combinator::NextSibling
+                | Combinator::LaterSibling => Ok(()),
 
-                // Unsupported
-                Combinator::NextSibling => Err(SelectorError::UnsupportedCombinator('+')),
-                Combinator::LaterSibling => Err(SelectorError::UnsupportedCombinator('~')),
                 Combinator::PseudoElement | Combinator::SlotAssignment => {
                     unreachable!("Pseudo element combinators should be filtered out at this point")
                 }
@@ -92,8 +278,16 @@ impl SelectorsParser {
             | Component::NthChild(_, _)
             | Component::FirstOfType
             | Component::NthOfType(_, _)
+            | Component::LastChild
+            | Component::LastOfType
+            | Component::OnlyChild
+            | Component::OnlyOfType
+            | Component::Root
             | Component::AttributeInNoNamespaceExists { .. }
-            | Component::AttributeInNoNamespace { .. } => Ok(()),
+            | Component::AttributeInNoNamespace { .. }
+            | Component::AttributeOther(_)
+            | Component::Namespace(_, _)
+            | Component::NonTSPseudoClass(_) => Ok(()),
 
             Component::Negation(components) => {
                 components.iter().map(Self::validate_component).collect()
@@ -102,21 +296,13 @@ impl SelectorsParser {
             // Unsupported
             Component::Empty
             | Component::Host(_)
-            | Component::LastChild
-            | Component::LastOfType
             | Component::NthLastChild(_, _)
             | Component::NthLastOfType(_, _)
-            | Component::OnlyChild
-            | Component::OnlyOfType
-            | Component::Root
             | Component::Scope
             | Component::PseudoElement(_)
-            | Component::NonTSPseudoClass(_)
             | Component::Slotted(_) => Err(SelectorError::UnsupportedPseudoClassOrElement),
 
-            Component::DefaultNamespace(_)
-            | Component::Namespace(_, _)
-            | Component::AttributeOther(_) => Err(SelectorError::NamespacedSelector),
+            Component::DefaultNamespace(_) => Err(SelectorError::NamespacedSelector),
         }
     }
 
@@ -134,18 +320,123 @@ impl SelectorsParser {
 
     #[inline]
     pub fn parse(selector: &str) -> Result<SelectorList<SelectorImplDescriptor>, SelectorError> {
-        let mut input = ParserInput::new(selector);
+        Self::parse_with_custom_pseudo_classes(selector, &[])
+    }
+
+    // NOTE: the nested selector lists (e.g. the arguments of `:is()` or `:has()`) are parsed
+    // without the user-defined pseudo-classes, so they are rejected there.
+    pub fn parse_with_custom_pseudo_classes(
+        selector: &str,
+        custom_pseudo_classes: &'p [CustomPseudoClass],
+    ) -> Result<SelectorList<SelectorImplDescriptor>, SelectorError> {
+        let selector = desugar_complex_negations(selector);
+        let mut input = ParserInput::new(&selector);
         let mut css_parser = CssParser::new(&mut input);
+        let parser = SelectorsParser {
+            custom_pseudo_classes,
+        };
 
-        SelectorList::parse(&Self, &mut css_parser)
+        SelectorList::parse(&parser, &mut css_parser)
             .map_err(SelectorError::from)
             .and_then(Self::validate)
     }
 }
 
-impl<'i> Parser<'i> for SelectorsParser {
+impl<'i> Parser<'i> for SelectorsParser<'_> {
     type Impl = SelectorImplDescriptor;
     type Error = SelectorParseErrorKind<'i>;
+
+    // NOTE: there are no `@namespace` rules, so the prefixes of the namespaces that the
+    // HTML parser places elements and attributes of the foreign elements into are predefined.
+    fn namespace_for_prefix(&self, prefix: &String) -> Option<Namespace> {
+        match prefix.as_str() {
+            "html" => Some(Namespace::Html),
+            "svg" => Some(Namespace::Svg),
+            "math" => Some(Namespace::MathML),
+            "xlink" => Some(Namespace::XLink),
+            "xml" => Some(Namespace::Xml),
+            "xmlns" => Some(Namespace::XmlNs),
+            _ => None,
+        }
+    }
+
+    fn parse_non_ts_pseudo_class(
+        &self,
+        location: SourceLocation,
+        name: CowRcStr<'i>,
+    ) -> Result<NonTSPseudoClassDescriptor, ParseError<'i, Self::Error>> {
+        self.custom_pseudo_classes
+            .iter()
+            .find(|pseudo_class| name.eq_ignore_ascii_case(&pseudo_class.name))
+            .map(|pseudo_class| NonTSPseudoClassDescriptor::Custom(pseudo_class.clone()))
+            .ok_or_else(|| {
+                location.new_custom_error(SelectorParseErrorKind::UnsupportedPseudoClassOrElement(
+                    name,
+                ))
+            })
+    }
+
+    fn parse_non_ts_functional_pseudo_class<'t>(
+        &self,
+        name: CowRcStr<'i>,
+        arguments: &mut CssParser<'i, 't>,
+    ) -> Result<NonTSPseudoClassDescriptor, ParseError<'i, Self::Error>> {
+        if name.eq_ignore_ascii_case("lang") {
+            let lang = arguments.expect_ident_or_string()?;
+
+            Ok(NonTSPseudoClassDescriptor::Lang(lang.as_ref().to_owned()))
+        } else if name.eq_ignore_ascii_case("ctx") {
+            let key = arguments.expect_ident()?;
+
+            Ok(NonTSPseudoClassDescriptor::Ctx(key.as_ref().to_owned()))
+        } else if name.eq_ignore_ascii_case("has") {
+            let start = arguments.position();
+
+            while arguments.next().is_ok() {}
+
+            match HasArgument::parse(arguments.slice_from(start)) {
+                Some(argument) => Ok(NonTSPseudoClassDescriptor::Has(argument)),
+                None => Err(arguments.new_custom_error(
+                    SelectorParseErrorKind::NonCompoundSelector,
+                )),
+            }
+        } else if name.eq_ignore_ascii_case(COMPLEX_NEGATION)
+            || name.eq_ignore_ascii_case("is")
+            || name.eq_ignore_ascii_case("where")
+        {
+            let start = arguments.position();
+
+            while arguments.next().is_ok() {}
+
+            let selector = arguments.slice_from(start).trim();
+
+            // NOTE: the `:has()` pseudo-classes and the pseudo-classes that depend on the
+            // following siblings are resolved by the lookahead rewriter only at the top level
+            // of the selector, it doesn't look into the nested selector lists.
+            let is_supported = SelectorsParser::parse(selector).is_ok_and(|selector_list| {
+                selector_list
+                    .0
+                    .iter()
+                    .all(|selector| !selector.iter_raw_match_order().any(needs_lookahead))
+            });
+
+            if !is_supported {
+                Err(arguments.new_custom_error(
+                    SelectorParseErrorKind::UnsupportedPseudoClassOrElement(name),
+                ))
+            } else if name.eq_ignore_ascii_case(COMPLEX_NEGATION) {
+                Ok(NonTSPseudoClassDescriptor::ComplexNegation(
+                    selector.to_owned(),
+                ))
+            } else {
+                Ok(NonTSPseudoClassDescriptor::Is(selector.to_owned()))
+            }
+        } else {
+            Err(arguments.new_custom_error(
+                SelectorParseErrorKind::UnsupportedPseudoClassOrElement(name),
+            ))
+        }
+    }
 }
 
 /// Parsed CSS selector.
@@ -172,11 +463,24 @@ impl<'i> Parser<'i> for SelectorsParser {
 /// ------------------------------ | --------------------------------------------------------------------------------------------------------------------------- |
 /// `*`                            | any element                                                                                                                 |
 /// `E`                            | any element of type `E`                                                                                                     |
+/// <code>svg&#124;E</code>        | an `E` element in the SVG namespace, <code>math&#124;E</code> and <code>html&#124;E</code> match MathML and HTML elements   |
 /// `E:nth-child(n)`               | an `E` element, the n-th child of its parent                                                                                |
 /// `E:first-child`                | an `E` element, first child of its parent                                                                                   |
 /// `E:nth-of-type(n)`             | an `E` element, the n-th sibling of its type                                                                                |
 /// `E:first-of-type`              | an `E` element, first sibling of its type                                                                                   |
+/// `E:last-child`                 | an `E` element, last child of its parent, requires the [`:has()` lookahead]                                                 |
+/// `E:last-of-type`               | an `E` element, last sibling of its type, requires the [`:has()` lookahead]                                                 |
+/// `E:only-child`                 | an `E` element, only child of its parent, requires the [`:has()` lookahead]                                                 |
+/// `E:only-of-type`               | an `E` element, only sibling of its type, requires the [`:has()` lookahead]                                                 |
+/// `:root`                        | the `html` element at the top level of the document                                                                         |
 /// `E:not(s)`                     | an `E` element that does not match either compound selector `s`                                                             |
+/// `E:not(F G)`                   | an `E` element that does not match the complex selector `F G`, e.g. isn't a `G` descendant of an `F` element                |
+/// `E:is(s1, s2)`                 | an `E` element that matches either selector `s1` or `s2`, `:where()` is matched the same way                                |
+/// `E:lang(fr)`                   | an `E` element in the language `fr` or its sublanguage (e.g. `fr-CA`) per the nearest `lang` attribute                      |
+/// `E:ctx(mobile)`                | an `E` element, if `mobile` is one of the rewriter's [context keys]                                                         |
+/// `E:has(> F)`                   | an `E` element with a child that matches the compound selector `F`, requires the [`:has()` lookahead]                      |
+/// `E:has(F)`                     | an `E` element with a descendant that matches the compound selector `F`, requires the [`:has()` lookahead]                 |
+/// `E:visible`                    | an `E` element that satisfies the predicate of the [user-defined pseudo-class] `visible`                           |
 /// `E.warning`                    | an `E` element belonging to the class `warning`                                                                             |
 /// `E#myid`                       | an `E` element with `ID` equal to `"myid"`.                                                                                 |
 /// `E[foo]`                       | an `E` element with a `foo` attribute                                                                                       |
@@ -190,19 +494,306 @@ impl<'i> Parser<'i> for SelectorsParser {
 /// <code>E[foo&#124;="en"]</code> | an `E` element whose foo attribute value is a hyphen-separated list of values beginning with `"en"`                         |
 /// `E F`                          | an `F` element descendant of an `E` element                                                                                 |
 /// `E > F`                        | an `F` element child of an `E` element                                                                                      |
+/// `E + F`                        | an `F` element immediately preceded by a sibling `E` element                                                                |
+/// `E ~ F`                        | an `F` element preceded by a sibling `E` element                                                                            |
 ///
 /// [`str`]: https://doc.rust-lang.org/std/primitive.str.html
 /// [`parse`]: https://doc.rust-lang.org/std/primitive.str.html#method.parse
 /// [element content handlers]: struct.Settings.html#structfield.element_content_handlers
+/// [context keys]: struct.Settings.html#structfield.selector_context
+/// [`:has()` lookahead]: struct.Settings.html#structfield.buffer_has_candidates
+/// [user-defined pseudo-class]: #method.with_custom_pseudo
 /// [`FromStr`]: https://doc.rust-lang.org/std/str/trait.FromStr.html
 #[derive(Debug)]
-pub struct Selector(pub(crate) SelectorList<SelectorImplDescriptor>);
+pub struct Selector(pub(crate) SelectorList<SelectorImplDescriptor>, Box<str>);
+
+/// A `:has()` pseudo-class of a selector along with the compound selector it's attached to.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct HasCondition {
+    /// The compound selector without the `:has()` pseudo-classes, or `*` if nothing else is left.
+    /// It matches all the elements that need to be checked for the relative selector.
+    pub candidate: String,
+    pub argument: HasArgument,
+}
+
+impl HasCondition {
+    /// Returns the selector that matches the elements that satisfy the relative selector for
+    /// the candidates.
+    #[inline]
+    pub fn relative_selector(&self) -> String {
+        format!("{} {}", self.candidate, self.argument)
+    }
+}
+
+/// A `:last-child`, `:last-of-type`, `:only-child` or `:only-of-type` pseudo-class of a
+/// selector along with the compound selector it's attached to.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct LastSiblingCondition {
+    /// The compound selector without the pseudo-classes that require the lookahead, or `*` if
+    /// nothing else is left. It matches all the elements that need to be checked for the
+    /// following siblings.
+    pub candidate: String,
+    /// If `true`, only the following siblings of the same type as the candidate are considered.
+    pub of_type: bool,
+}
+
+/// Returns the candidate selector of the lookahead rewriter for the compound selector.
+fn candidate(compound: &[&Component<SelectorImplDescriptor>]) -> String {
+    // NOTE: it's safe to match more elements than the compound selector does, so we fall back
+    // to the universal selector if the compound selector can't be serialized back.
+    candidate_css(compound)
+        .ok()
+        .filter(|css| !css.is_empty() && SelectorsParser::parse(css).is_ok())
+        .unwrap_or_else(|| "*".into())
+}
+
+/// Serializes the compound selector for the lookahead rewriter. The user-defined pseudo-classes
+/// and the pseudo-classes resolved by the lookahead rewriter itself are skipped.
+fn candidate_css(compound: &[&Component<SelectorImplDescriptor>]) -> Result<String, fmt::Error> {
+    let mut css = String::new();
+
+    // NOTE: the components of a compound selector are iterated in the reverse order.
+    for &component in compound.iter().rev() {
+        match component {
+            // NOTE: the user-defined pseudo-classes can't be parsed back, so they are skipped
+            // along with the negations that contain them.
+            Component::Negation(components) if components.iter().any(is_custom) => (),
+            Component::Negation(components) => {
+                let components: Vec<_> =
+                    components.iter().filter(|c| !needs_lookahead(c)).collect();
+
+                if !components.is_empty() {
+                    css.push_str(":not(");
+
+                    for c in components {
+                        c.to_css(&mut css)?;
+                    }
+
+                    css.push(')');
+                }
+            }
+            c if needs_lookahead(c) || is_custom(c) => (),
+            c => c.to_css(&mut css)?,
+        }
+    }
+
+    Ok(css)
+}
+
+impl Selector {
+    /// Returns the source of the selector as it has been parsed.
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        &self.1
+    }
+
+    /// Returns the sources of the alternatives of the selector list (e.g. `a` and `area` for
+    /// `a, area`) in the order of the list.
+    pub(crate) fn alternatives(&self) -> Vec<&str> {
+        let mut input = ParserInput::new(&self.1);
+        let mut css_parser = CssParser::new(&mut input);
+
+        // NOTE: the commas in the arguments of the functional pseudo-classes are inside
+        // the nested blocks, which are skipped by the parser, so only the commas that
+        // separate the alternatives are taken into account.
+        let alternatives = css_parser.parse_comma_separated(|css_parser| {
+            let start = css_parser.position();
+
+            while css_parser.next().is_ok() {}
+
+            Ok::<_, ParseError<()>>(css_parser.slice_from(start).trim())
+        });
+
+        match alternatives {
+            Ok(alternatives) if alternatives.len() == (self.0).0.len() => alternatives,
+            _ => vec![self.as_str(); (self.0).0.len()],
+        }
+    }
+
+    /// Returns a [`SelectorBuilder`] with the user-defined pseudo-class `name`, which matches
+    /// the elements that satisfy the `predicate`.
+    ///
+    /// The predicate is invoked with the [`ElementInfo`] of the element. It should only depend
+    /// on the element's attributes, since the results of the predicate can be reused for the
+    /// elements with the same attributes if the [`match_cache_capacity`] is set.
+    ///
+    /// The user-defined pseudo-classes can be used in the compound selectors and in the simple
+    /// `:not()` pseudo-classes, but not in the selector lists of `:is()`, `:where()`, `:has()`
+    /// or `:not()` with complex selectors.
+    ///
+    /// # Example
+    /// ```
+    /// use lol_html::{rewrite_str, ElementContentHandlers, RewriteStrSettings, Selector};
+    ///
+    /// let selector = Selector::with_custom_pseudo("visible", |el| !el.has_attribute("hidden"))
+    ///     .parse("p:visible")
+    ///     .unwrap();
+    ///
+    /// let html = rewrite_str(
+    ///     r#"<p>Foo</p><p hidden>Bar</p>"#,
+    ///     RewriteStrSettings {
+    ///         element_content_handlers: vec![(
+    ///             &selector,
+    ///             ElementContentHandlers::default().element(|el| {
+    ///                 el.set_attribute("class", "shown")?;
+    ///
+    ///                 Ok(())
+    ///             }),
+    ///         )],
+    ///         ..RewriteStrSettings::default()
+    ///     },
+    /// )
+    /// .unwrap();
+    ///
+    /// assert_eq!(html, r#"<p class="shown">Foo</p><p hidden>Bar</p>"#);
+    /// ```
+    ///
+    /// [`SelectorBuilder`]: struct.SelectorBuilder.html
+    /// [`ElementInfo`]: struct.ElementInfo.html
+    /// [`match_cache_capacity`]: struct.Settings.html#structfield.match_cache_capacity
+    #[inline]
+    pub fn with_custom_pseudo(
+        name: &str,
+        predicate: impl Fn(&ElementInfo) -> bool + Send + Sync + 'static,
+    ) -> SelectorBuilder {
+        SelectorBuilder::default().with_custom_pseudo(name, predicate)
+    }
+
+    /// Returns the `:has()` pseudo-classes of the selector.
+    pub(crate) fn has_conditions(&self) -> Vec<HasCondition> {
+        let mut conditions = Vec::new();
+
+        for selector in (self.0).0.iter() {
+            let components: Vec<_> = selector.iter_raw_parse_order_from(0).collect();
+
+            for compound in components.split(|c| matches!(c, Component::Combinator(_))) {
+                let mut arguments = Vec::new();
+
+                for &component in compound {
+                    match component {
+                        Component::Negation(components) => arguments.extend(
+                            components.iter().filter(|c| is_has(c)),
+                        ),
+                        c if is_has(c) => arguments.push(c),
+                        _ => (),
+                    }
+                }
+
+                if arguments.is_empty() {
+                    continue;
+                }
+
+                let candidate = candidate(compound);
+
+                for argument in arguments {
+                    if let Component::NonTSPseudoClass(NonTSPseudoClassDescriptor::Has(argument)) =
+                        argument
+                    {
+                        conditions.push(HasCondition {
+                            candidate: candidate.clone(),
+                            argument: argument.clone(),
+                        });
+                    }
+                }
+            }
+        }
+
+        conditions
+    }
+
+    /// Returns the `:last-child`, `:last-of-type`, `:only-child` and `:only-of-type`
+    /// pseudo-classes of the selector.
+    pub(crate) fn last_sibling_conditions(&self) -> Vec<LastSiblingCondition> {
+        let mut conditions = Vec::new();
+
+        for selector in (self.0).0.iter() {
+            let components: Vec<_> = selector.iter_raw_parse_order_from(0).collect();
+
+            for compound in components.split(|c| matches!(c, Component::Combinator(_))) {
+                let mut kinds = Vec::new();
+
+                for &component in compound {
+                    match component {
+                        Component::Negation(components) => {
+                            kinds.extend(components.iter().filter(|c| is_last_sibling(c)))
+                        }
+                        c if is_last_sibling(c) => kinds.push(c),
+                        _ => (),
+                    }
+                }
+
+                if kinds.is_empty() {
+                    continue;
+                }
+
+                let candidate = candidate(compound);
+
+                for kind in kinds {
+                    let of_type = matches!(kind, Component::LastOfType | Component::OnlyOfType);
+
+                    let condition = LastSiblingCondition {
+                        candidate: candidate.clone(),
+                        of_type,
+                    };
+
+                    if !conditions.contains(&condition) {
+                        conditions.push(condition);
+                    }
+                }
+            }
+        }
+
+        conditions
+    }
+}
 
 impl FromStr for Selector {
     type Err = SelectorError;
 
     #[inline]
     fn from_str(selector: &str) -> Result<Self, Self::Err> {
-        Ok(Selector(SelectorsParser::parse(selector)?))
+        Ok(Selector(SelectorsParser::parse(selector)?, selector.into()))
+    }
+}
+
+/// A parser of the selectors with the user-defined pseudo-classes.
+///
+/// The builder is created with [`Selector::with_custom_pseudo`].
+///
+/// [`Selector::with_custom_pseudo`]: struct.Selector.html#method.with_custom_pseudo
+#[derive(Clone, Default)]
+pub struct SelectorBuilder {
+    custom_pseudo_classes: Vec<CustomPseudoClass>,
+}
+
+impl SelectorBuilder {
+    /// Adds the user-defined pseudo-class `name`, which matches the elements that satisfy the
+    /// `predicate`. If a pseudo-class with the same name has already been added, it's replaced.
+    pub fn with_custom_pseudo(
+        mut self,
+        name: &str,
+        predicate: impl Fn(&ElementInfo) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.custom_pseudo_classes
+            .retain(|pseudo_class| !pseudo_class.name.eq_ignore_ascii_case(name));
+
+        self.custom_pseudo_classes.push(CustomPseudoClass {
+            name: name.to_owned(),
+            predicate: Arc::new(predicate),
+        });
+
+        self
+    }
+
+    /// Parses the `selector` with the user-defined pseudo-classes.
+    #[inline]
+    pub fn parse(&self, selector: &str) -> Result<Selector, SelectorError> {
+        Ok(Selector(
+            SelectorsParser::parse_with_custom_pseudo_classes(
+                selector,
+                &self.custom_pseudo_classes,
+            )?,
+            selector.into(),
+        ))
     }
 }
@@ -1,21 +1,29 @@
 use super::SelectorState;
 use super::attribute_matcher::AttributeMatcher;
-use super::compiler::{CompiledAttributeExpr, CompiledLocalNameExpr};
+use super::compiler::{CompiledAttributeExpr, CompiledLocalNameExpr, OperandsRegistry};
 use crate::html::LocalName;
-use hashbrown::HashSet;
+use encoding_rs::Encoding;
+use hashbrown::{HashMap, HashSet};
 use std::hash::Hash;
 use std::ops::Range;
+use std::sync::Arc;
 
 pub type AddressRange = Range<usize>;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct ExecutionBranch<P>
 where
     P: Hash + Eq,
 {
-    pub matched_payload: HashSet<P>,
+    /// The matched payloads along with the indices of the matched alternatives of their
+    /// selector lists.
+    pub matched_payload: HashMap<P, usize>,
     pub jumps: Option<AddressRange>,
     pub hereditary_jumps: Option<AddressRange>,
+    /// The selectors that become reachable for the next sibling of the matched element.
+    pub next_sibling_jumps: Option<AddressRange>,
+    /// The selectors that become reachable for all the following siblings of the matched element.
+    pub later_sibling_jumps: Option<AddressRange>,
 }
 
 /// The result of trying to execute an instruction without having parsed all attributes
@@ -31,6 +39,7 @@ where
     Fail,
 }
 
+#[derive(Clone)]
 pub struct Instruction<P>
 where
     P: Hash + Eq,
@@ -38,12 +47,41 @@ where
     pub associated_branch: ExecutionBranch<P>,
     pub local_name_exprs: Box<[CompiledLocalNameExpr]>,
     pub attribute_exprs: Box<[CompiledAttributeExpr]>,
+    /// The key of the memoized result of the attribute expressions, or `None` if the result
+    /// can't be memoized, because it depends on the ancestors of the element (e.g. `:lang()`).
+    pub attr_results_key: Option<usize>,
 }
 
 impl<P> Instruction<P>
 where
     P: Hash + Eq,
 {
+    /// Moves the instruction to the `addr`, with the addresses it jumps to changed by
+    /// the `relocate` function.
+    pub fn relocate(mut self, addr: usize, relocate: impl Fn(usize) -> usize) -> Self {
+        let relocate_range = |range: &mut Option<AddressRange>| {
+            if let Some(range) = range {
+                let start = relocate(range.start);
+
+                *range = start..start + range.len();
+            }
+        };
+
+        let branch = &mut self.associated_branch;
+
+        relocate_range(&mut branch.jumps);
+        relocate_range(&mut branch.hereditary_jumps);
+        relocate_range(&mut branch.next_sibling_jumps);
+        relocate_range(&mut branch.later_sibling_jumps);
+
+        // NOTE: the results are memoized by the address of the instruction.
+        if self.attr_results_key.is_some() {
+            self.attr_results_key = Some(addr);
+        }
+
+        self
+    }
+
     pub fn try_exec_without_attrs<'i>(
         &'i self,
         state: &SelectorState,
@@ -60,12 +98,22 @@ where
         }
     }
 
+    #[inline]
+    fn exec_attribute_exprs(&self, state: &SelectorState, attr_matcher: &mut AttributeMatcher) -> bool {
+        let exec = |m: &mut AttributeMatcher| self.attribute_exprs.iter().all(|e| e(state, m));
+
+        match self.attr_results_key {
+            Some(key) => attr_matcher.memoized_instr(key, exec),
+            None => exec(attr_matcher),
+        }
+    }
+
     pub fn complete_exec_with_attrs<'i>(
         &'i self,
         state: &SelectorState,
-        attr_matcher: &AttributeMatcher,
+        attr_matcher: &mut AttributeMatcher,
     ) -> Option<&'i ExecutionBranch<P>> {
-        if self.attribute_exprs.iter().all(|e| e(state, attr_matcher)) {
+        if self.exec_attribute_exprs(state, attr_matcher) {
             Some(&self.associated_branch)
         } else {
             None
@@ -76,15 +124,13 @@ where
         &'i self,
         state: &SelectorState,
         local_name: &LocalName,
-        attr_matcher: &AttributeMatcher,
+        attr_matcher: &mut AttributeMatcher,
     ) -> Option<&'i ExecutionBranch<P>> {
         let is_match =
             self.local_name_exprs
                 .iter()
                 .all(|e| e(&*state, local_name)) &&
-            self.attribute_exprs
-                .iter()
-                .all(|e| e(&*state, attr_matcher));
+            self.exec_attribute_exprs(state, attr_matcher);
 
         if is_match {
             Some(&self.associated_branch)
@@ -103,4 +149,20 @@ where
     /// Enables tracking child types for nth-of-type selectors.
     /// This is disabled if no nth-of-type selectors are used in the program.
     pub enable_nth_of_type: bool,
+    /// Enables tracking languages of the open elements for `:lang()` selectors.
+    /// This is disabled if no `:lang()` selectors are used in the program.
+    pub enable_lang_tracking: bool,
+    /// The encoding the literals of the program are compiled for.
+    pub encoding: &'static Encoding,
+    /// The program that matches the selector lists of the `:not()` and `:is()` pseudo-classes
+    /// that can't be expressed by the instructions, e.g. with complex selectors. The selector
+    /// lists are matched against the open element stack and reported by their indices.
+    pub negated_program: Option<Arc<Program<usize>>>,
+    /// The attribute comparisons of the program, so they can be shared with the selectors
+    /// that are added to the program later.
+    pub(super) operands: OperandsRegistry,
+    /// The context keys the program is compiled for.
+    pub(super) context: HashSet<String>,
+    /// The selector lists matched by the `negated_program` in the order of their indices.
+    pub(super) negated_selectors: Vec<String>,
 }
\ No newline at end of file
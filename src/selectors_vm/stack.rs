@@ -1,30 +1,16 @@
-use super::SelectorState;
+use super::{SelectorState, SharedHasMatches};
 use super::program::AddressRange;
 use super::ast::NthChild;
-use crate::html::{LocalName, Namespace, Tag};
+use crate::html::{is_void_element, LocalName, Namespace};
+use crate::base::{LocalSharing, Sharing};
 use crate::memory::{LimitedVec, MemoryLimitExceededError, SharedMemoryLimiter};
 // use hashbrown for raw entry, switch back to std once it stablizes there
 use hashbrown::{HashSet, HashMap, hash_map::RawEntryMut};
 use std::fmt::Debug;
 use std::hash::{Hash, Hasher, BuildHasher};
+use std::sync::Arc;
 
-#[inline]
-fn is_void_element(local_name: &LocalName) -> bool {
-    // NOTE: fast path for the most commonly used elements
-    if tag_is_one_of!(*local_name, [Div, A, Span, Li]) {
-        return false;
-    }
-
-    tag_is_one_of!(
-        *local_name,
-        [
-            Area, Base, Basefont, Bgsound, Br, Col, Embed, Hr, Img, Input, Keygen, Link, Meta,
-            Param, Source, Track, Wbr
-        ]
-    )
-}
-
-pub trait ElementData: Default + 'static {
+pub trait ElementData: Default + Send + 'static {
     type MatchPayload: PartialEq + Eq + Copy + Debug + Hash + 'static;
 
     fn matched_payload_mut(&mut self) -> &mut HashSet<Self::MatchPayload>;
@@ -146,14 +132,41 @@ impl TypedChildCounterMap {
     }
 }
 
+/// The sibling selectors that become reachable for the elements of a single level of the tree,
+/// i.e. for the children of the same parent element.
+#[derive(Default)]
+pub struct SiblingJumps {
+    /// The jumps produced by the previous sibling element, only executed for the next element.
+    pub next: Vec<AddressRange>,
+    /// The jumps produced by all the previous sibling elements.
+    pub later: Vec<AddressRange>,
+}
+
+impl SiblingJumps {
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = &AddressRange> {
+        self.next.iter().chain(self.later.iter())
+    }
+}
+
 pub struct StackItem<'i, E: ElementData> {
     pub local_name: LocalName<'i>,
     pub element_data: E,
     pub jumps: Vec<AddressRange>,
+    /// The descendant selectors that become reachable once the element has matched.
+    /// Descendants of the elements that haven't matched the ancestor part of a selector
+    /// never execute the rest of it, so such selectors are rejected without any per-rule
+    /// state or attribute scans.
     pub hereditary_jumps: Vec<AddressRange>,
     pub child_counter: ChildCounter,
+    /// The sibling selectors that become reachable for the children of the element.
+    pub child_sibling_jumps: SiblingJumps,
     pub has_ancestor_with_hereditary_jumps: bool,
     pub stack_directive: StackDirective,
+    /// The language of the element, only resolved if languages are tracked.
+    pub lang: Option<Arc<[u8]>>,
+    /// The `id` of the element, only resolved if ids are tracked.
+    pub id: Option<Box<[u8]>>,
 }
 
 impl<'i, E: ElementData> StackItem<'i, E> {
@@ -165,8 +178,11 @@ impl<'i, E: ElementData> StackItem<'i, E> {
             jumps: Vec::default(),
             hereditary_jumps: Vec::default(),
             child_counter: Default::default(),
+            child_sibling_jumps: SiblingJumps::default(),
             has_ancestor_with_hereditary_jumps: false,
             stack_directive: StackDirective::Push,
+            lang: None,
+            id: None,
         }
     }
 
@@ -178,26 +194,75 @@ impl<'i, E: ElementData> StackItem<'i, E> {
             jumps: self.jumps,
             hereditary_jumps: self.hereditary_jumps,
             child_counter: self.child_counter,
+            child_sibling_jumps: self.child_sibling_jumps,
             has_ancestor_with_hereditary_jumps: self.has_ancestor_with_hereditary_jumps,
             stack_directive: self.stack_directive,
+            lang: self.lang,
+            id: self.id,
         }
     }
 }
 
-pub struct Stack<E: ElementData> {
+pub struct Stack<E: ElementData, S: Sharing = LocalSharing> {
     /// A counter for root elements
     root_child_counter: ChildCounter,
+    /// The sibling selectors that become reachable for root elements.
+    root_sibling_jumps: SiblingJumps,
     /// A typed counter for all elements on all frames. This is optional to indicate if types are actually being counted.
     typed_child_counters: Option<TypedChildCounterMap>,
-    items: LimitedVec<StackItem<'static, E>>,
+    items: LimitedVec<StackItem<'static, E>, S>,
+    /// The maximum depth the stack has reached so far.
+    max_depth: usize,
+    /// The number of elements that have been added to the stack's tree so far.
+    element_count: usize,
+    /// The indices of the negated selector lists the element that is being matched matches.
+    negated_selector_matches: Vec<usize>,
+    /// The elements that satisfy the `:has()` pseudo-classes, shared with the lookahead
+    /// rewriter that resolves them.
+    has_matches: SharedHasMatches<S>,
 }
 
-impl<E: ElementData> Stack<E> {
-    pub fn new(memory_limiter: SharedMemoryLimiter, enable_nth_of_type: bool) -> Self {
+impl<E: ElementData, S: Sharing> Stack<E, S> {
+    pub fn new(memory_limiter: SharedMemoryLimiter<S>, enable_nth_of_type: bool) -> Self {
         Stack {
             root_child_counter: Default::default(),
+            root_sibling_jumps: SiblingJumps::default(),
             typed_child_counters: if enable_nth_of_type { Some(Default::default()) } else { None },
             items: LimitedVec::new(memory_limiter),
+            max_depth: 0,
+            element_count: 0,
+            negated_selector_matches: Vec::default(),
+            has_matches: SharedHasMatches::default(),
+        }
+    }
+
+    #[cfg(feature = "selector_matching")]
+    #[inline]
+    pub fn has_matches(&self) -> &SharedHasMatches<S> {
+        &self.has_matches
+    }
+
+    #[inline]
+    pub fn reserve(&mut self, depth: usize) {
+        self.items.reserve(depth);
+    }
+
+    #[inline]
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// Removes all the items from the stack and resets the child counters, e.g. when a new
+    /// document starts.
+    pub fn clear(&mut self) {
+        self.items.drain(..);
+        self.root_child_counter = Default::default();
+        self.root_sibling_jumps = SiblingJumps::default();
+        self.element_count = 0;
+        self.negated_selector_matches.clear();
+
+        if let Some(counters) = &mut self.typed_child_counters {
+            *counters = Default::default();
         }
     }
 
@@ -208,12 +273,52 @@ impl<E: ElementData> Stack<E> {
             None => &mut self.root_child_counter,
         }.inc();
 
+        self.element_count += 1;
+
         if let Some(counters) = &mut self.typed_child_counters {
             counters.add_child(name, self.items.len());
         }
     }
 
-    pub fn build_state<'a, 'i>(&'a self, name: &LocalName<'i>) -> SelectorState<'i>
+    /// Returns the sibling jumps produced by the previous siblings of the element that is
+    /// being matched.
+    #[inline]
+    pub fn sibling_jumps(&self) -> &SiblingJumps {
+        match self.items.last() {
+            Some(last) => &last.child_sibling_jumps,
+            None => &self.root_sibling_jumps,
+        }
+    }
+
+    /// Adds the sibling jumps produced by the matched element. Called before pushing the
+    /// element to the stack.
+    pub fn add_sibling_jumps(&mut self, next: Vec<AddressRange>, later: Vec<AddressRange>) {
+        let sibling_jumps = match self.items.last_mut() {
+            Some(last) => &mut last.child_sibling_jumps,
+            None => &mut self.root_sibling_jumps,
+        };
+
+        // NOTE: the next sibling jumps are only applicable to the element
+        // that immediately follows the one that has produced them.
+        sibling_jumps.next = next;
+
+        // NOTE: the same later sibling jumps are produced by all the matching siblings,
+        // so we don't add duplicates to avoid executing the same instructions repeatedly.
+        for jumps in later {
+            if !sibling_jumps.later.contains(&jumps) {
+                sibling_jumps.later.push(jumps);
+            }
+        }
+    }
+
+    /// Sets the negated selector lists that the element that is being matched matches. Called
+    /// before the element's instructions are executed.
+    #[inline]
+    pub fn set_negated_selector_matches(&mut self, matches: Vec<usize>) {
+        self.negated_selector_matches = matches;
+    }
+
+    pub fn build_state<'a, 'i>(&'a self, name: &LocalName<'i>, ns: Namespace) -> SelectorState<'i>
     where
         'a: 'i // 'a outlives 'i, required to downcast 'a lifetimes into 'i
     {
@@ -222,11 +327,18 @@ impl<E: ElementData> Stack<E> {
             None => &self.root_child_counter,
         };
         SelectorState {
+            ns,
             cumulative,
             typed:
                 self.typed_child_counters
                     .as_ref()
-                    .and_then(|f| f.get(name, self.items.len()))
+                    .and_then(|f| f.get(name, self.items.len())),
+            lang: self.items.last().and_then(|last| last.lang.as_deref()),
+            is_root: self.items.is_empty(),
+            // NOTE: the element has been already added by `add_child`.
+            element_index: self.element_count.saturating_sub(1),
+            negated_selector_matches: &self.negated_selector_matches,
+            has_matches: &self.has_matches,
         }
     }
 
@@ -261,14 +373,27 @@ impl<E: ElementData> Stack<E> {
         }
     }
 
+    /// Pops all the items from the stack, starting from the innermost one.
+    pub fn pop_all(&mut self, popped_element_data_handler: impl FnMut(E)) {
+        if let Some(counters) = &mut self.typed_child_counters {
+            counters.pop_to(0);
+        }
+
+        self.items
+            .drain(..)
+            .rev()
+            .map(|i| i.element_data)
+            .for_each(popped_element_data_handler)
+    }
+
     #[inline]
-    pub fn items(&self) -> &[StackItem<E>] {
+    pub fn items(&self) -> &[StackItem<'static, E>] {
         &self.items
     }
 
     #[inline]
-    pub fn current_element_data_mut(&mut self) -> Option<&mut E> {
-        self.items.last_mut().map(|i| &mut i.element_data)
+    pub fn items_mut(&mut self) -> &mut [StackItem<'static, E>] {
+        &mut self.items
     }
 
     #[inline]
@@ -283,6 +408,8 @@ impl<E: ElementData> Stack<E> {
         }
 
         self.items.push(item)?;
+        self.max_depth = self.max_depth.max(self.items.len());
+
         Ok(())
     }
 }
@@ -318,7 +445,7 @@ mod tests {
 
     #[test]
     fn hereditary_jumps_flag() {
-        let mut stack = Stack::new(MemoryLimiter::new_shared(2048), false);
+        let mut stack: Stack<TestElementData> = Stack::new(MemoryLimiter::new_shared(2048), false);
 
         stack.push_item(item("item1", 0)).unwrap();
 
@@ -346,7 +473,7 @@ mod tests {
     fn pop_up_to() {
         macro_rules! assert_pop_result {
             ($up_to:expr, $expected_unmatched:expr, $expected_items:expr) => {{
-                let mut stack = Stack::new(MemoryLimiter::new_shared(2048), false);
+                let mut stack: Stack<TestElementData> = Stack::new(MemoryLimiter::new_shared(2048), false);
 
                 stack.push_item(item("html", 0)).unwrap();
                 stack.push_item(item("body", 1)).unwrap();
@@ -386,9 +513,24 @@ mod tests {
         assert_pop_result!("table", empty, ["html", "body", "div", "div", "span"]);
     }
 
+    #[test]
+    fn pop_all() {
+        let mut stack: Stack<TestElementData> = Stack::new(MemoryLimiter::new_shared(2048), false);
+        let mut popped = Vec::default();
+
+        stack.push_item(item("html", 0)).unwrap();
+        stack.push_item(item("body", 1)).unwrap();
+        stack.push_item(item("p", 2)).unwrap();
+
+        stack.pop_all(|d| popped.push(d.0));
+
+        assert_eq!(popped, vec![2, 1, 0]);
+        assert_eq!(stack.items().len(), 0);
+    }
+
     #[test]
     fn pop_up_to_on_empty_stack() {
-        let mut stack = Stack::new(MemoryLimiter::new_shared(2048), false);
+        let mut stack: Stack<TestElementData> = Stack::new(MemoryLimiter::new_shared(2048), false);
         let mut handler_called = false;
 
         stack.pop_up_to(local_name("div"), |_: TestElementData| {
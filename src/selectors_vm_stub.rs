@@ -0,0 +1,228 @@
+//! A stub of the selector matching VM that is used when neither the `selector_matching` nor
+//! the `basic_selectors` feature is enabled.
+//!
+//! [`Selector`] can't be constructed in this configuration, so the rewriter never has any
+//! element content handlers and never instantiates the VM. The stub only provides the types
+//! that are required by the rest of the crate, so it can be compiled without the CSS parsing
+//! dependencies.
+
+use crate::base::{LocalSharing, Sharing};
+use crate::html::{LocalName, Namespace};
+use crate::memory::SharedMemoryLimiter;
+use encoding_rs::Encoding;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::hash::Hash;
+use std::iter::Empty;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum Void {}
+
+/// A parsed CSS selector.
+///
+/// Selectors can't be parsed when neither the `selector_matching` nor the `basic_selectors`
+/// feature is enabled, so the type has no values.
+#[derive(Debug)]
+pub struct Selector(Void);
+
+impl Selector {
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        match self.0 {}
+    }
+
+    #[inline]
+    pub(crate) fn alternatives(&self) -> Vec<&str> {
+        match self.0 {}
+    }
+}
+
+pub trait ElementData: Default + Send + 'static {
+    type MatchPayload: PartialEq + Eq + Copy + Debug + Hash + 'static;
+}
+
+pub struct MatchInfo<P> {
+    pub payload: P,
+    pub alternative_idx: usize,
+    pub with_content: bool,
+}
+
+// NOTE: the VM is never instantiated, so it never fails.
+pub struct VmError<E: ElementData, S: Sharing, MatchPayload>(
+    pub Void,
+    PhantomData<(E, S, MatchPayload)>,
+);
+
+pub struct Ast<P>(PhantomData<P>);
+
+impl<P> Default for Ast<P> {
+    #[inline]
+    fn default() -> Self {
+        Ast(PhantomData)
+    }
+}
+
+impl<P> Clone for Ast<P> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Ast(PhantomData)
+    }
+}
+
+impl<P> Ast<P> {
+    #[inline]
+    pub fn add_selector(&mut self, selector: &Selector, _payload: P) {
+        match selector.0 {}
+    }
+
+    #[inline]
+    pub fn has_attribute_exprs(&self) -> bool {
+        false
+    }
+
+    #[inline]
+    pub fn is_scoped_to(&self, _local_name: &str) -> bool {
+        false
+    }
+
+    #[inline]
+    pub fn map_payload(&mut self, _map_payload: impl Fn(P) -> Option<P>) {}
+}
+
+pub struct Program<P>(Void, PhantomData<P>);
+
+pub struct Compiler<P>(PhantomData<P>);
+
+impl<P> Compiler<P> {
+    #[inline]
+    pub fn new(_encoding: &'static Encoding, _context: &[&str]) -> Self {
+        Compiler(PhantomData)
+    }
+
+    // NOTE: the AST is always empty, since selectors can't be constructed, so there is
+    // nothing to compile.
+    pub fn compile(self, _ast: Ast<P>) -> Program<P> {
+        unreachable!("Selector matching is disabled.")
+    }
+
+    #[inline]
+    pub fn extend(
+        program: &Program<P>,
+        _ast: Ast<P>,
+        _map_payload: impl Fn(P) -> Option<P>,
+    ) -> Program<P> {
+        match program.0 {}
+    }
+}
+
+pub struct OpenElementsPath<'s, E: ElementData>(Void, PhantomData<&'s E>);
+
+impl<'s, E: ElementData> OpenElementsPath<'s, E> {
+    #[inline]
+    pub fn ancestors(&self) -> OpenElementsPath<'s, E> {
+        match self.0 {}
+    }
+}
+
+impl<E: ElementData> Display for OpenElementsPath<'_, E> {
+    fn fmt(&self, _f: &mut Formatter) -> fmt::Result {
+        match self.0 {}
+    }
+}
+
+pub struct SelectorMatchingVm<E: ElementData, S: Sharing = LocalSharing> {
+    void: Void,
+    element_data: PhantomData<(E, S)>,
+}
+
+impl<E: ElementData, S: Sharing> SelectorMatchingVm<E, S> {
+    #[inline]
+    pub fn set_match_limit(&mut self, _payload: E::MatchPayload, _limit: usize) {
+        match self.void {}
+    }
+
+    #[inline]
+    pub fn set_payload_enabled(&mut self, _payload: E::MatchPayload, _enabled: bool) {
+        match self.void {}
+    }
+
+    #[inline]
+    pub fn from_program(
+        program: Arc<Program<E::MatchPayload>>,
+        _memory_limiter: SharedMemoryLimiter<S>,
+    ) -> Self {
+        match program.0 {}
+    }
+
+    #[inline]
+    pub fn enable_match_cache(&mut self, _capacity: usize) {
+        match self.void {}
+    }
+
+    #[inline]
+    pub fn disable_match_cache(&mut self) {
+        match self.void {}
+    }
+
+    #[inline]
+    pub fn preallocate_stack(&mut self, _depth: usize) {
+        match self.void {}
+    }
+
+    #[inline]
+    pub fn stack_depth(&self) -> usize {
+        match self.void {}
+    }
+
+    #[inline]
+    pub fn enable_id_tracking(&mut self) {
+        match self.void {}
+    }
+
+    #[inline]
+    pub fn open_elements_path(&self) -> OpenElementsPath<'_, E> {
+        match self.void {}
+    }
+
+    #[inline]
+    pub fn reset(&mut self) {
+        match self.void {}
+    }
+
+    #[inline]
+    pub fn exec_for_start_tag(
+        &mut self,
+        _local_name: LocalName,
+        _ns: Namespace,
+        _match_handler: &mut dyn FnMut(MatchInfo<E::MatchPayload>),
+    ) -> Result<(), VmError<E, S, E::MatchPayload>> {
+        match self.void {}
+    }
+
+    #[inline]
+    pub fn exec_for_end_tag(
+        &mut self,
+        _local_name: LocalName,
+        _unmatched_element_data_handler: impl FnMut(E),
+    ) {
+        match self.void {}
+    }
+
+    #[inline]
+    pub fn exec_for_end(&mut self, _unmatched_element_data_handler: impl FnMut(E)) {
+        match self.void {}
+    }
+
+    #[inline]
+    pub fn open_elements_mut(&mut self) -> Empty<(&LocalName<'static>, &mut E)> {
+        match self.void {}
+    }
+
+    #[inline]
+    pub fn current_element_data_mut_with_path(
+        &mut self,
+    ) -> (Option<&mut E>, OpenElementsPath<'_, E>) {
+        match self.void {}
+    }
+}
@@ -0,0 +1,280 @@
+//! Built-in [`TextFilter`]s and the filter combinators.
+//!
+//! [`TextFilter`]: ../trait.TextFilter.html
+
+use crate::TextFilter;
+use hashbrown::HashMap;
+use std::borrow::Cow;
+
+/// Runs the `filter` on the output of the previous filter, keeping the text borrowed for as long
+/// as none of the filters change it.
+#[inline]
+pub(crate) fn filter_cow<'t, F: TextFilter + ?Sized>(
+    filter: &mut F,
+    text: Cow<'t, str>,
+) -> Cow<'t, str> {
+    match text {
+        Cow::Borrowed(text) => filter.filter(text),
+        Cow::Owned(text) => Cow::Owned(filter.filter(&text).into_owned()),
+    }
+}
+
+impl<F: TextFilter + ?Sized> TextFilter for Box<F> {
+    #[inline]
+    fn filter<'t>(&mut self, text: &'t str) -> Cow<'t, str> {
+        (**self).filter(text)
+    }
+}
+
+impl<F: TextFilter + ?Sized> TextFilter for &mut F {
+    #[inline]
+    fn filter<'t>(&mut self, text: &'t str) -> Cow<'t, str> {
+        (**self).filter(text)
+    }
+}
+
+/// A filter that runs the `first` filter and then the `second` one on its output.
+///
+/// Produced by the [`TextFilter::then`] method.
+///
+/// [`TextFilter::then`]: ../trait.TextFilter.html#method.then
+#[derive(Debug, Clone)]
+pub struct Chain<A, B> {
+    pub(crate) first: A,
+    pub(crate) second: B,
+}
+
+impl<A: TextFilter, B: TextFilter> TextFilter for Chain<A, B> {
+    #[inline]
+    fn filter<'t>(&mut self, text: &'t str) -> Cow<'t, str> {
+        let text = self.first.filter(text);
+
+        filter_cow(&mut self.second, text)
+    }
+}
+
+/// A filter produced from a closure by the [`from_fn`] function.
+///
+/// [`from_fn`]: fn.from_fn.html
+#[derive(Debug, Clone)]
+pub struct FnFilter<F>(F);
+
+/// Creates a filter from the closure that returns the transformed text, or `None` if the text
+/// should be left intact.
+///
+/// # Example
+/// ```
+/// use lol_html::{rewrite_str, text_filters, RewriteStrSettings, TextFilter, TextPipeline};
+///
+/// let collapse_spaces = text_filters::from_fn(|text| {
+///     if text.contains("  ") {
+///         Some(text.split(' ').filter(|w| !w.is_empty()).collect::<Vec<_>>().join(" "))
+///     } else {
+///         None
+///     }
+/// });
+///
+/// let html = rewrite_str(
+///     "<p>Hello   :wave:</p>",
+///     RewriteStrSettings {
+///         document_content_handlers: vec![TextPipeline::default()
+///             .with_filter(collapse_spaces.then(text_filters::Shortcodes::default()))
+///             .into()],
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(html, "<p>Hello 👋</p>");
+/// ```
+#[inline]
+pub fn from_fn<F: FnMut(&str) -> Option<String>>(f: F) -> FnFilter<F> {
+    FnFilter(f)
+}
+
+impl<F: FnMut(&str) -> Option<String>> TextFilter for FnFilter<F> {
+    #[inline]
+    fn filter<'t>(&mut self, text: &'t str) -> Cow<'t, str> {
+        match (self.0)(text) {
+            Some(text) => Cow::Owned(text),
+            None => Cow::Borrowed(text),
+        }
+    }
+}
+
+const EMOJI: &[(&str, &str)] = &[
+    ("+1", "👍"),
+    ("-1", "👎"),
+    ("100", "💯"),
+    ("clap", "👏"),
+    ("cry", "😢"),
+    ("eyes", "👀"),
+    ("fire", "🔥"),
+    ("grin", "😁"),
+    ("heart", "❤️"),
+    ("joy", "😂"),
+    ("laughing", "😆"),
+    ("ok_hand", "👌"),
+    ("pray", "🙏"),
+    ("rocket", "🚀"),
+    ("smile", "😄"),
+    ("sob", "😭"),
+    ("sparkles", "✨"),
+    ("star", "⭐"),
+    ("sunglasses", "😎"),
+    ("tada", "🎉"),
+    ("thinking", "🤔"),
+    ("thumbsdown", "👎"),
+    ("thumbsup", "👍"),
+    ("warning", "⚠️"),
+    ("wave", "👋"),
+    ("white_check_mark", "✅"),
+    ("wink", "😉"),
+    ("x", "❌"),
+];
+
+#[inline]
+fn is_shortcode_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c == b'+' || c == b'-'
+}
+
+/// A filter that replaces the shortcodes (e.g. `:smile:`) with their replacements.
+///
+/// The filter constructed with `Shortcodes::default()` replaces the common emoji shortcodes with
+/// the emoji. The shortcodes that are not known to the filter are left as is.
+///
+/// # Example
+/// ```
+/// use lol_html::{rewrite_str, text_filters::Shortcodes, RewriteStrSettings, TextPipeline};
+///
+/// let shortcodes = Shortcodes::default().add("lol", "<abbr>LOL</abbr>");
+///
+/// let html = rewrite_str(
+///     "<p>:smile: :lol: :unknown: 10:30</p>",
+///     RewriteStrSettings {
+///         document_content_handlers: vec![TextPipeline::default().with_filter(shortcodes).into()],
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(html, "<p>😄 <abbr>LOL</abbr> :unknown: 10:30</p>");
+/// ```
+#[derive(Debug, Clone)]
+pub struct Shortcodes {
+    replacements: HashMap<String, String>,
+}
+
+impl Shortcodes {
+    /// Creates a filter without any shortcodes.
+    #[inline]
+    pub fn empty() -> Self {
+        Shortcodes {
+            replacements: HashMap::default(),
+        }
+    }
+
+    /// Adds the `shortcode` (without the surrounding colons) that is replaced with the
+    /// `replacement`. The replacement is inserted into the document as is, so it can contain HTML.
+    #[inline]
+    pub fn add(mut self, shortcode: &str, replacement: &str) -> Self {
+        self.replacements
+            .insert(shortcode.to_owned(), replacement.to_owned());
+
+        self
+    }
+
+    fn replacement(&self, text: &str) -> Option<(usize, &str)> {
+        let bytes = text.as_bytes();
+
+        debug_assert!(bytes.first() == Some(&b':'));
+
+        let len = bytes[1..]
+            .iter()
+            .take_while(|&&c| is_shortcode_char(c))
+            .count();
+
+        if len == 0 || bytes.get(len + 1) != Some(&b':') {
+            return None;
+        }
+
+        self.replacements
+            .get(&text[1..=len])
+            .map(|replacement| (len + 2, replacement.as_str()))
+    }
+}
+
+impl Default for Shortcodes {
+    #[inline]
+    fn default() -> Self {
+        EMOJI
+            .iter()
+            .fold(Shortcodes::empty(), |shortcodes, (shortcode, emoji)| {
+                shortcodes.add(shortcode, emoji)
+            })
+    }
+}
+
+impl TextFilter for Shortcodes {
+    fn filter<'t>(&mut self, text: &'t str) -> Cow<'t, str> {
+        let mut output = String::new();
+        let mut rest = text;
+        let mut copied = 0;
+
+        while let Some(pos) = rest.find(':') {
+            let start = text.len() - rest.len() + pos;
+
+            match self.replacement(&rest[pos..]) {
+                Some((len, replacement)) => {
+                    output.push_str(&text[copied..start]);
+                    output.push_str(replacement);
+                    copied = start + len;
+                    rest = &text[copied..];
+                }
+                None => rest = &rest[pos + 1..],
+            }
+        }
+
+        if copied == 0 {
+            Cow::Borrowed(text)
+        } else {
+            output.push_str(&text[copied..]);
+
+            Cow::Owned(output)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shortcodes() {
+        let mut shortcodes = Shortcodes::default().add("x", "[x]");
+
+        for (text, expected) in &[
+            ("", ""),
+            ("no shortcodes", "no shortcodes"),
+            (":smile:", "😄"),
+            ("::smile::", ":😄:"),
+            (":smile::wink:", "😄😉"),
+            ("a :x: b :+1:", "a [x] b 👍"),
+            ("10:30 :unknown: : : :smile", "10:30 :unknown: : : :smile"),
+            ("ratio 1:smile: ", "ratio 1😄 "),
+        ] {
+            assert_eq!(shortcodes.filter(text), *expected);
+        }
+    }
+
+    #[test]
+    fn combinators() {
+        let mut filter = from_fn(|text| Some(text.replace(";)", ":wink:")))
+            .then(Shortcodes::default())
+            .then(Box::new(from_fn(|text| Some(text.to_uppercase()))) as Box<dyn TextFilter>);
+
+        assert_eq!(filter.filter("hi ;)"), "HI 😉");
+
+        let mut unchanged = from_fn(|_| None).then(Shortcodes::empty());
+
+        assert!(matches!(unchanged.filter("foo"), Cow::Borrowed("foo")));
+    }
+}
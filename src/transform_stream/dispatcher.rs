@@ -1,27 +1,33 @@
-use super::*;
+use super::prefix_cache::RecordedLexeme;
 use crate::base::{Bytes, Range};
 use crate::html::{LocalName, Namespace};
 use crate::parser::{
-    Lexeme, LexemeSink, NonTagContentLexeme, ParserDirective, ParserOutputSink, TagHintSink,
-    TagLexeme, TagTokenOutline,
+    AttributeNamesBloom, AttributeOutline, Lexeme, LexemeSink, NonTagContentLexeme,
+    NonTagContentTokenOutline, ParserDirective, ParserOutputSink, TagHintSink, TagLexeme,
+    TagTokenOutline,
 };
 use crate::rewritable_units::{
-    DocumentEnd, Serialize, ToToken, Token, TokenCaptureFlags, TokenCapturer, TokenCapturerEvent,
+    DocumentEnd, ToToken, Token, TokenCaptureFlags, TokenCapturer, TokenCapturerEvent,
 };
 use crate::rewriter::RewritingError;
 use encoding_rs::Encoding;
-use std::rc::Rc;
 
 use TagTokenOutline::*;
 
 pub struct AuxStartTagInfo<'i> {
     pub input: &'i Bytes<'i>,
-    pub attr_buffer: SharedAttributeBuffer,
+    pub attr_buffer: &'i [AttributeOutline],
+    // NOTE: only the selector matching VM looks up the attributes by name.
+    #[cfg_attr(not(feature = "basic_selectors"), allow(dead_code))]
+    pub attr_names_bloom: AttributeNamesBloom,
+    // NOTE: only the selector matching VM needs the flag to handle self-closing foreign elements.
+    #[cfg_attr(not(feature = "basic_selectors"), allow(dead_code))]
     pub self_closing: bool,
 }
 
-type AuxStartTagInfoRequest<C> =
-    Box<dyn FnOnce(&mut C, AuxStartTagInfo<'_>) -> Result<TokenCaptureFlags, RewritingError>>;
+type AuxStartTagInfoRequest<C> = Box<
+    dyn FnOnce(&mut C, AuxStartTagInfo<'_>) -> Result<TokenCaptureFlags, RewritingError> + Send,
+>;
 
 pub enum DispatcherError<C> {
     InfoRequest(AuxStartTagInfoRequest<C>),
@@ -32,11 +38,15 @@ pub type StartTagHandlingResult<C> = Result<TokenCaptureFlags, DispatcherError<C
 
 pub trait TransformController: Sized {
     fn initial_capture_flags(&self) -> TokenCaptureFlags;
+    fn needs_attributes(&self) -> bool;
     fn handle_start_tag(&mut self, name: LocalName, ns: Namespace) -> StartTagHandlingResult<Self>;
     fn handle_end_tag(&mut self, name: LocalName) -> TokenCaptureFlags;
+    fn handle_spilled_start_tag(&mut self) -> TokenCaptureFlags;
     fn handle_token(&mut self, token: &mut Token) -> Result<(), RewritingError>;
     fn handle_end(&mut self, document_end: &mut DocumentEnd) -> Result<(), RewritingError>;
     fn should_emit_content(&self) -> bool;
+    fn should_pass_through(&self) -> bool;
+    fn reset(&mut self);
 }
 
 /// Defines an interface for the [`HtmlRewriter`]'s output.
@@ -73,6 +83,8 @@ where
     pending_element_aux_info_req: Option<AuxStartTagInfoRequest<C>>,
     emission_enabled: bool,
     encoding: &'static Encoding,
+    /// The lexemes of the document prefix that is being recorded for the prefix cache.
+    recorded_lexemes: Option<Vec<RecordedLexeme>>,
 }
 
 impl<C, O> Dispatcher<C, O>
@@ -92,9 +104,20 @@ where
             pending_element_aux_info_req: None,
             emission_enabled: true,
             encoding,
+            recorded_lexemes: None,
         }
     }
 
+    #[inline]
+    pub fn transform_controller(&self) -> &C {
+        &self.transform_controller
+    }
+
+    #[inline]
+    pub fn transform_controller_mut(&mut self) -> &mut C {
+        &mut self.transform_controller
+    }
+
     pub fn flush_remaining_input(&mut self, input: &[u8], consumed_byte_count: usize) {
         let output = &input[self.remaining_content_start..consumed_byte_count];
 
@@ -103,6 +126,87 @@ where
         }
 
         self.remaining_content_start = 0;
+        self.token_capturer.next_input(input, consumed_byte_count);
+    }
+
+    #[inline]
+    pub fn pass_through(&mut self, chunk: &[u8]) {
+        if !chunk.is_empty() {
+            self.output_sink.handle_chunk(chunk);
+        }
+    }
+
+    /// Emits the chunk of the input that is excluded from parsing, unless the content of
+    /// the current element is being removed. The chunk is accounted for in the source locations
+    /// of the following tokens.
+    pub fn emit_verbatim(&mut self, chunk: &[u8]) -> Result<(), RewritingError> {
+        self.flush_pending_captured_text()?;
+
+        if self.emission_enabled {
+            self.pass_through(chunk);
+        }
+
+        self.token_capturer.next_input(chunk, chunk.len());
+
+        Ok(())
+    }
+
+    /// Resets the per-document state of the dispatcher and its transform controller.
+    pub fn reset(&mut self) {
+        self.transform_controller.reset();
+
+        self.remaining_content_start = 0;
+        self.token_capturer = TokenCapturer::new(
+            self.transform_controller.initial_capture_flags(),
+            self.encoding,
+        );
+        self.got_flags_from_hint = false;
+        self.pending_element_aux_info_req = None;
+        self.emission_enabled = true;
+        self.recorded_lexemes = None;
+    }
+
+    /// Starts recording the lexemes. The parser is kept in the lexer mode during the recording,
+    /// so all the tags that are needed to reproduce the output are recorded as lexemes.
+    #[inline]
+    pub fn start_recording(&mut self) {
+        self.recorded_lexemes = Some(Vec::default());
+    }
+
+    /// Stops recording the lexemes and returns them, unless the recording has been abandoned.
+    #[inline]
+    pub fn finish_recording(&mut self) -> Option<Vec<RecordedLexeme>> {
+        self.recorded_lexemes.take()
+    }
+
+    /// Handles the recorded lexemes of the `input` as if they were produced by the parser.
+    pub fn replay(
+        &mut self,
+        input: &[u8],
+        lexemes: &[RecordedLexeme],
+    ) -> Result<(), RewritingError> {
+        for lexeme in lexemes {
+            match lexeme {
+                RecordedLexeme::Tag(token_outline, raw_range) => {
+                    self.handle_tag(&Lexeme::new(
+                        input.into(),
+                        token_outline.clone(),
+                        *raw_range,
+                    ))?;
+                }
+                RecordedLexeme::NonTagContent(token_outline, raw_range) => {
+                    self.handle_non_tag_content(&Lexeme::new(
+                        input.into(),
+                        token_outline.clone(),
+                        *raw_range,
+                    ))?;
+                }
+            }
+        }
+
+        self.flush_remaining_input(input, input.len());
+
+        Ok(())
     }
 
     pub fn finish(&mut self, input: &[u8]) -> Result<(), RewritingError> {
@@ -152,7 +256,15 @@ where
                     transform_controller.handle_token(&mut token)?;
 
                     if emission_enabled {
-                        token.to_bytes(&mut |c| output_sink.handle_chunk(c));
+                        token
+                            // NOTE: empty chunk signifies the end of the output, so we should
+                            // skip empty chunks produced by the token serialization.
+                            .serialize(&mut |c| {
+                                if !c.is_empty() {
+                                    output_sink.handle_chunk(c)
+                                }
+                            })
+                            .map_err(RewritingError::ContentHandlerError)?;
                     }
                 }
             }
@@ -175,34 +287,45 @@ where
         }
     }
 
+    /// Returns `true` if the attributes of the tag have been requested
+    /// to make a decision on the capture flags.
     fn adjust_capture_flags_for_tag_lexeme(
         &mut self,
         lexeme: &TagLexeme,
-    ) -> Result<(), RewritingError> {
+    ) -> Result<bool, RewritingError> {
         let input = lexeme.input();
 
         macro_rules! get_flags_from_aux_info_res {
-            ($handler:expr, $attributes:expr, $self_closing:expr) => {
+            ($handler:expr, $attributes:expr, $attr_names_bloom:expr, $self_closing:expr) => {
                 $handler(
                     &mut self.transform_controller,
                     AuxStartTagInfo {
                         input,
-                        attr_buffer: Rc::clone($attributes),
+                        attr_buffer: $attributes,
+                        attr_names_bloom: $attr_names_bloom,
                         self_closing: $self_closing,
                     },
                 )
             };
         }
 
+        let mut aux_info_requested = true;
+
         let capture_flags = match self.pending_element_aux_info_req.take() {
             // NOTE: tag hint was produced for the tag, but
             // attributes and self closing flag were requested.
             Some(aux_info_req) => match *lexeme.token_outline() {
                 StartTag {
                     ref attributes,
+                    attr_names_bloom,
                     self_closing,
                     ..
-                } => get_flags_from_aux_info_res!(aux_info_req, attributes, self_closing),
+                } => get_flags_from_aux_info_res!(
+                    aux_info_req,
+                    attributes,
+                    attr_names_bloom,
+                    self_closing
+                ),
                 _ => unreachable!("Tag should be a start tag at this point"),
             },
 
@@ -214,14 +337,23 @@ where
                     name_hash,
                     ns,
                     ref attributes,
+                    attr_names_bloom,
                     self_closing,
                 } => {
                     let name = LocalName::new(input, name, name_hash);
 
                     match self.transform_controller.handle_start_tag(name, ns) {
-                        Ok(flags) => Ok(flags),
+                        Ok(flags) => {
+                            aux_info_requested = false;
+                            Ok(flags)
+                        }
                         Err(DispatcherError::InfoRequest(aux_info_req)) => {
-                            get_flags_from_aux_info_res!(aux_info_req, attributes, self_closing)
+                            get_flags_from_aux_info_res!(
+                                aux_info_req,
+                                attributes,
+                                attr_names_bloom,
+                                self_closing
+                            )
                         }
                         Err(DispatcherError::RewritingError(e)) => Err(e),
                     }
@@ -229,6 +361,8 @@ where
 
                 EndTag { name, name_hash } => {
                     let name = LocalName::new(input, name, name_hash);
+
+                    aux_info_requested = false;
                     Ok(self.transform_controller.handle_end_tag(name))
                 }
             },
@@ -237,7 +371,7 @@ where
         match capture_flags {
             Ok(flags) => {
                 self.token_capturer.set_capture_flags(flags);
-                Ok(())
+                Ok(aux_info_requested)
             }
             Err(e) => Err(e),
         }
@@ -254,7 +388,7 @@ where
     }
 
     #[inline]
-    fn flush_pending_captured_text(&mut self) -> Result<(), RewritingError> {
+    pub fn flush_pending_captured_text(&mut self) -> Result<(), RewritingError> {
         let transform_controller = &mut self.transform_controller;
         let output_sink = &mut self.output_sink;
         let emission_enabled = self.emission_enabled;
@@ -266,7 +400,15 @@ where
                 transform_controller.handle_token(&mut token)?;
 
                 if emission_enabled {
-                    token.to_bytes(&mut |c| output_sink.handle_chunk(c));
+                    token
+                        // NOTE: empty chunk signifies the end of the output, so we should
+                        // skip empty chunks produced by the token serialization.
+                        .serialize(&mut |c| {
+                            if !c.is_empty() {
+                                output_sink.handle_chunk(c)
+                            }
+                        })
+                        .map_err(RewritingError::ContentHandlerError)?;
                 }
             }
 
@@ -295,6 +437,13 @@ where
         // handlers will be disabled before the receive the finalizing chunk.
         self.flush_pending_captured_text()?;
 
+        if let Some(ref mut lexemes) = self.recorded_lexemes {
+            lexemes.push(RecordedLexeme::Tag(
+                lexeme.token_outline().clone(),
+                lexeme.raw_range(),
+            ));
+        }
+
         if self.got_flags_from_hint {
             self.got_flags_from_hint = false;
         } else {
@@ -311,7 +460,11 @@ where
         self.try_produce_token_from_lexeme(lexeme)?;
         self.emission_enabled = self.transform_controller.should_emit_content();
 
-        Ok(self.get_next_parser_directive())
+        if self.recorded_lexemes.is_some() {
+            Ok(ParserDirective::Lex)
+        } else {
+            Ok(self.get_next_parser_directive())
+        }
     }
 
     #[inline]
@@ -319,8 +472,78 @@ where
         &mut self,
         lexeme: &NonTagContentLexeme,
     ) -> Result<(), RewritingError> {
+        if let Some(ref mut lexemes) = self.recorded_lexemes {
+            lexemes.push(RecordedLexeme::NonTagContent(
+                lexeme.token_outline().clone(),
+                lexeme.raw_range(),
+            ));
+        }
+
         self.try_produce_token_from_lexeme(lexeme)
     }
+
+    fn handle_spilled_tag(
+        &mut self,
+        lexeme: &TagLexeme,
+        forced: bool,
+    ) -> Result<bool, RewritingError> {
+        self.flush_pending_captured_text()?;
+
+        // NOTE: the spilled tag is passed through by the parser, so the
+        // prefix can't be reproduced from the recorded lexemes.
+        self.recorded_lexemes = None;
+
+        // NOTE: the tag is reported to the transform controller before the spilling,
+        // so the selector matching VM is aware of the element.
+        let aux_info_requested = if self.got_flags_from_hint {
+            self.got_flags_from_hint = false;
+            false
+        } else {
+            self.adjust_capture_flags_for_tag_lexeme(lexeme)?
+        };
+
+        // NOTE: unless spilling is forced, the tag can be spilled only if it's not needed
+        // by anyone: i.e. selector matching doesn't depend on its attributes and the start tag
+        // token is not captured.
+        if !forced
+            && (aux_info_requested
+                || self
+                    .token_capturer
+                    .capture_flags()
+                    .contains(TokenCaptureFlags::NEXT_START_TAG))
+        {
+            return Ok(false);
+        }
+
+        // NOTE: the token is never produced for the spilled tag, so the
+        // transform controller should discard the matched element handlers.
+        let flags = self.transform_controller.handle_spilled_start_tag();
+
+        self.token_capturer.set_capture_flags(flags);
+        self.emission_enabled = self.transform_controller.should_emit_content();
+
+        Ok(true)
+    }
+
+    #[inline]
+    fn should_capture(&self, token: &NonTagContentTokenOutline) -> bool {
+        let flags = self.token_capturer.capture_flags();
+
+        match token {
+            NonTagContentTokenOutline::Text(_) => flags.contains(TokenCaptureFlags::TEXT),
+            NonTagContentTokenOutline::Comment(_)
+            | NonTagContentTokenOutline::CommentChunk { .. } => {
+                flags.contains(TokenCaptureFlags::COMMENTS)
+            }
+            NonTagContentTokenOutline::ProcessingInstruction(_) => flags.intersects(
+                TokenCaptureFlags::COMMENTS | TokenCaptureFlags::PROCESSING_INSTRUCTIONS,
+            ),
+            NonTagContentTokenOutline::Doctype { .. } => {
+                flags.contains(TokenCaptureFlags::DOCTYPES)
+            }
+            NonTagContentTokenOutline::Eof => false,
+        }
+    }
 }
 
 impl<C, O> TagHintSink for Dispatcher<C, O>
@@ -1,78 +1,186 @@
 mod dispatcher;
+#[cfg(feature = "parse_ahead")]
+mod parse_ahead;
+mod prefix_cache;
 
 use self::dispatcher::Dispatcher;
-use crate::memory::{Arena, SharedMemoryLimiter};
-use crate::parser::{Parser, ParserDirective, SharedAttributeBuffer};
-use crate::rewriter::RewritingError;
+#[cfg(feature = "parse_ahead")]
+use self::parse_ahead::{ParseAhead, ParseAheadSettings, ParsedChunk};
+use crate::base::{LocalSharing, Sharing};
+use crate::memory::{Arena, MemoryLimitExceededError, SharedMemoryLimiter};
+use crate::parser::{Parser, ParserDirective};
+use crate::rewriter::{RewritingError, SpillSettings};
 use encoding_rs::Encoding;
-use std::cell::RefCell;
-use std::rc::Rc;
 
 pub use self::dispatcher::{
     AuxStartTagInfo, DispatcherError, OutputSink, StartTagHandlingResult, TransformController,
 };
+pub use self::prefix_cache::PrefixCache;
 
-pub struct TransformStreamSettings<C, O>
+pub struct TransformStreamSettings<C, O, S = LocalSharing>
 where
     C: TransformController,
     O: OutputSink,
+    S: Sharing,
 {
     pub transform_controller: C,
     pub output_sink: O,
     pub preallocated_parsing_buffer_size: usize,
-    pub memory_limiter: SharedMemoryLimiter,
+    pub preallocated_attribute_buffer_capacity: usize,
+    pub memory_limiter: SharedMemoryLimiter<S>,
     pub encoding: &'static Encoding,
     pub strict: bool,
+    pub spill_settings: SpillSettings,
+    pub stream_comments: bool,
+    pub passthrough_after: Option<usize>,
+    #[cfg(feature = "parse_ahead")]
+    pub parse_ahead_queue_capacity: Option<usize>,
 }
 
-pub struct TransformStream<C, O>
+pub struct TransformStream<C, O, S = LocalSharing>
 where
     C: TransformController,
     O: OutputSink,
+    S: Sharing,
 {
-    dispatcher: Rc<RefCell<Dispatcher<C, O>>>,
     parser: Parser<Dispatcher<C, O>>,
-    buffer: Arena,
+    buffer: Arena<S>,
     has_buffered_data: bool,
+    spill_settings: SpillSettings,
+    passthrough_after: Option<usize>,
+    bytes_before_passthrough: Option<usize>,
+    passthrough: bool,
+    document_started: bool,
+    encoding: &'static Encoding,
+    #[cfg(feature = "parse_ahead")]
+    parse_ahead: Option<ParseAhead<S>>,
 }
 
-impl<C, O> TransformStream<C, O>
+// NOTE: it's a macro instead of an instance method, so it can be used
+// with bytes that are borrowed from the buffer.
+macro_rules! spill {
+    ($self:tt, $bytes:expr, $err:expr) => {{
+        let spilled = $self
+            .parser
+            .spill_current_token($bytes, &$self.spill_settings)?;
+
+        if !spilled {
+            let err = match $self.parser.current_token_info($bytes) {
+                Some((token_type, name)) => $err.with_token(
+                    token_type,
+                    name.map(|name| name.as_lowercase_string($self.encoding)),
+                ),
+                None => $err,
+            };
+
+            return Err(RewritingError::MemoryLimitExceeded(err));
+        }
+
+        let consumed_byte_count = $self.parser.parse($bytes, false)?;
+
+        $self
+            .parser
+            .output_sink_mut()
+            .flush_remaining_input($bytes, consumed_byte_count);
+
+        consumed_byte_count
+    }};
+}
+
+impl<C, O, S> TransformStream<C, O, S>
 where
     C: TransformController,
     O: OutputSink,
+    S: Sharing,
 {
-    pub fn new(settings: TransformStreamSettings<C, O>) -> Self {
-        let initial_parser_directive = if settings
-            .transform_controller
-            .initial_capture_flags()
-            .is_empty()
-        {
-            ParserDirective::WherePossibleScanForTagsOnly
-        } else {
-            ParserDirective::Lex
-        };
-
-        let dispatcher = Rc::new(RefCell::new(Dispatcher::new(
+    pub fn new(settings: TransformStreamSettings<C, O, S>) -> Self {
+        let dispatcher = Dispatcher::new(
             settings.transform_controller,
             settings.output_sink,
             settings.encoding,
-        )));
+        );
+
+        // NOTE: the parse-ahead thread can't stop parsing at the given byte, since it runs
+        // ahead of the rewriter.
+        #[cfg(feature = "parse_ahead")]
+        let parse_ahead = match settings.parse_ahead_queue_capacity {
+            Some(queue_capacity) if settings.passthrough_after.is_none() => Some(ParseAhead::new(
+                ParseAheadSettings {
+                    queue_capacity,
+                    strict: settings.strict,
+                    attr_buffer_capacity: settings.preallocated_attribute_buffer_capacity,
+                    stream_comments: settings.stream_comments,
+                },
+                SharedMemoryLimiter::clone(&settings.memory_limiter),
+            )),
+            _ => None,
+        };
 
         let buffer = Arena::new(
             settings.memory_limiter,
             settings.preallocated_parsing_buffer_size,
         );
 
-        let parser = Parser::new(&dispatcher, initial_parser_directive, settings.strict);
+        let (initial_parser_directive, track_attributes) =
+            Self::parser_settings(dispatcher.transform_controller());
 
-        TransformStream {
+        let parser = Parser::new(
             dispatcher,
+            initial_parser_directive,
+            settings.strict,
+            settings.preallocated_attribute_buffer_capacity,
+            track_attributes,
+            settings.stream_comments,
+        );
+
+        TransformStream {
             parser,
             buffer,
             has_buffered_data: false,
+            spill_settings: settings.spill_settings,
+            passthrough_after: settings.passthrough_after,
+            bytes_before_passthrough: settings.passthrough_after,
+            passthrough: false,
+            document_started: false,
+            encoding: settings.encoding,
+            #[cfg(feature = "parse_ahead")]
+            parse_ahead,
+        }
+    }
+
+    #[inline]
+    fn parses_ahead(&self) -> bool {
+        #[cfg(feature = "parse_ahead")]
+        {
+            self.parse_ahead.is_some()
+        }
+
+        #[cfg(not(feature = "parse_ahead"))]
+        {
+            false
         }
     }
 
+    /// Returns the initial parser directive and whether the parser needs to track attributes
+    /// for the transform controller.
+    fn parser_settings(transform_controller: &C) -> (ParserDirective, bool) {
+        let directive = if transform_controller.initial_capture_flags().is_empty() {
+            ParserDirective::WherePossibleScanForTagsOnly
+        } else {
+            ParserDirective::Lex
+        };
+
+        (directive, transform_controller.needs_attributes())
+    }
+
+    fn reset_parser(&mut self) {
+        let (initial_parser_directive, track_attributes) =
+            Self::parser_settings(self.transform_controller());
+
+        self.parser
+            .reset(initial_parser_directive, track_attributes);
+    }
+
     fn buffer_blocked_bytes(
         &mut self,
         data: &[u8],
@@ -81,9 +189,19 @@ where
         if self.has_buffered_data {
             self.buffer.shift(consumed_byte_count);
         } else {
-            self.buffer
-                .init_with(&data[consumed_byte_count..])
-                .map_err(RewritingError::MemoryLimitExceeded)?;
+            let blocked = &data[consumed_byte_count..];
+
+            if let Err(err) = self.buffer.init_with(blocked) {
+                let spilled_byte_count = spill!(self, blocked, err);
+
+                if spilled_byte_count == blocked.len() {
+                    return Ok(());
+                }
+
+                self.buffer
+                    .init_with(&blocked[spilled_byte_count..])
+                    .map_err(RewritingError::MemoryLimitExceeded)?;
+            }
 
             self.has_buffered_data = true;
         }
@@ -93,14 +211,197 @@ where
         Ok(())
     }
 
+    fn spill_buffered_bytes(
+        &mut self,
+        err: MemoryLimitExceededError,
+    ) -> Result<(), RewritingError> {
+        let buffered = self.buffer.bytes();
+        let spilled_byte_count = spill!(self, buffered, err);
+
+        self.has_buffered_data = spilled_byte_count < buffered.len();
+        self.buffer.shift(spilled_byte_count);
+
+        Ok(())
+    }
+
+    fn enter_passthrough(&mut self) -> Result<(), RewritingError> {
+        let dispatcher = self.parser.output_sink_mut();
+
+        dispatcher.flush_pending_captured_text()?;
+
+        if self.has_buffered_data {
+            dispatcher.pass_through(self.buffer.bytes());
+            self.has_buffered_data = false;
+        }
+
+        self.passthrough = true;
+
+        Ok(())
+    }
+
+    /// Replays the lexemes of the chunk parsed by the parse-ahead thread. Once the transform
+    /// controller requests the passthrough, the following chunks are passed through as is.
+    #[cfg(feature = "parse_ahead")]
+    fn handle_parsed_chunk(
+        dispatcher: &mut Dispatcher<C, O>,
+        passthrough: &mut bool,
+        mut chunk: ParsedChunk,
+    ) -> Result<(), RewritingError> {
+        if *passthrough {
+            dispatcher.pass_through(chunk.input());
+
+            return Ok(());
+        }
+
+        let lexemes = chunk.take_lexemes();
+
+        dispatcher.replay(chunk.input(), &lexemes)?;
+
+        if dispatcher.transform_controller().should_pass_through() {
+            dispatcher.flush_pending_captured_text()?;
+            *passthrough = true;
+        }
+
+        Ok(())
+    }
+
     pub fn write(&mut self, data: &[u8]) -> Result<(), RewritingError> {
         trace!(@write data);
 
-        let chunk = if self.has_buffered_data {
-            self.buffer
-                .append(data)
-                .map_err(RewritingError::MemoryLimitExceeded)?;
+        self.document_started = true;
+
+        #[cfg(feature = "parse_ahead")]
+        if let Some(ref mut parse_ahead) = self.parse_ahead {
+            let dispatcher = self.parser.output_sink_mut();
+            let passthrough = &mut self.passthrough;
+
+            return parse_ahead.write(data, |chunk| {
+                Self::handle_parsed_chunk(dispatcher, passthrough, chunk)
+            });
+        }
+
+        if self.passthrough {
+            self.parser.output_sink_mut().pass_through(data);
+
+            return Ok(());
+        }
+
+        let (data, tail) = match self.bytes_before_passthrough {
+            Some(ref mut remaining) => {
+                let len = data.len().min(*remaining);
 
+                *remaining -= len;
+                data.split_at(len)
+            }
+            None => (data, &[][..]),
+        };
+
+        self.parse_chunk(data)?;
+
+        if self.bytes_before_passthrough == Some(0)
+            || self.transform_controller().should_pass_through()
+        {
+            self.enter_passthrough()?;
+            self.parser.output_sink_mut().pass_through(tail);
+        }
+
+        Ok(())
+    }
+
+    /// Emits the data verbatim without parsing it. The parser state is not affected by the data,
+    /// so parsing resumes after the data in the state the parser has been in before it.
+    pub fn write_verbatim(&mut self, data: &[u8]) -> Result<(), RewritingError> {
+        // NOTE: the data can't be emitted before the incomplete token that precedes it or
+        // the input that is parsed ahead, so it's parsed as the rest of the input instead.
+        if self.has_buffered_data || self.parses_ahead() {
+            return self.write(data);
+        }
+
+        trace!(@write data);
+
+        self.document_started = true;
+
+        if self.passthrough {
+            self.parser.output_sink_mut().pass_through(data);
+
+            return Ok(());
+        }
+
+        self.parser.output_sink_mut().emit_verbatim(data)?;
+
+        if let Some(ref mut remaining) = self.bytes_before_passthrough {
+            *remaining = remaining.saturating_sub(data.len());
+        }
+
+        if self.bytes_before_passthrough == Some(0) {
+            self.enter_passthrough()?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the prefix of the document, replaying the memoized lexemes of the prefix from the
+    /// cache if it has been parsed before. Otherwise, the lexemes are recorded and memoized, if
+    /// the parser can resume parsing after the prefix from scratch.
+    pub fn write_prefix(
+        &mut self,
+        prefix: &[u8],
+        cache: &mut PrefixCache,
+    ) -> Result<(), RewritingError> {
+        // NOTE: the prefix can only be memoized if it's the start of the document, so
+        // the state of the parser and the dispatcher is determined by the prefix alone.
+        if self.document_started || self.passthrough_after.is_some() || self.parses_ahead() {
+            return self.write(prefix);
+        }
+
+        trace!(@write prefix);
+
+        self.document_started = true;
+
+        if let Some(lexemes) = cache.get(prefix) {
+            self.parser.output_sink_mut().replay(prefix, lexemes)?;
+
+            // NOTE: the prefix is only memoized if the parser is at rest after it, so
+            // the parser can continue from the initial state of the lexer.
+            self.reset_parser();
+            self.parser.start_with_lexer();
+        } else {
+            self.parser.start_with_lexer();
+            self.parser.output_sink_mut().start_recording();
+
+            let res = self.parse_chunk(prefix);
+            let lexemes = self.parser.output_sink_mut().finish_recording();
+
+            res?;
+
+            if let Some(lexemes) = lexemes {
+                if !self.has_buffered_data && self.parser.is_at_rest() {
+                    cache.insert(prefix, lexemes);
+                }
+            }
+        }
+
+        if self.transform_controller().should_pass_through() {
+            self.enter_passthrough()?;
+        }
+
+        Ok(())
+    }
+
+    fn parse_chunk(&mut self, data: &[u8]) -> Result<(), RewritingError> {
+        if self.has_buffered_data {
+            if let Err(err) = self.buffer.append(data) {
+                self.spill_buffered_bytes(err)?;
+
+                if self.has_buffered_data {
+                    self.buffer
+                        .append(data)
+                        .map_err(RewritingError::MemoryLimitExceeded)?;
+                }
+            }
+        }
+
+        let chunk = if self.has_buffered_data {
             self.buffer.bytes()
         } else {
             data
@@ -110,8 +411,8 @@ where
 
         let consumed_byte_count = self.parser.parse(chunk, false)?;
 
-        self.dispatcher
-            .borrow_mut()
+        self.parser
+            .output_sink_mut()
             .flush_remaining_input(&chunk, consumed_byte_count);
 
         if consumed_byte_count < chunk.len() {
@@ -123,9 +424,36 @@ where
         Ok(())
     }
 
+    /// Handles all the chunks that are being parsed by the parse-ahead thread as the rest of
+    /// the current document.
+    #[cfg(feature = "parse_ahead")]
+    fn finish_parse_ahead(&mut self) -> Result<(), RewritingError> {
+        if let Some(ref mut parse_ahead) = self.parse_ahead {
+            let dispatcher = self.parser.output_sink_mut();
+            let passthrough = &mut self.passthrough;
+
+            parse_ahead.finish_document(|chunk| {
+                Self::handle_parsed_chunk(dispatcher, passthrough, chunk)
+            })?;
+        }
+
+        Ok(())
+    }
+
     pub fn end(&mut self) -> Result<(), RewritingError> {
         trace!(@end);
 
+        #[cfg(feature = "parse_ahead")]
+        if self.parse_ahead.is_some() {
+            self.finish_parse_ahead()?;
+
+            return self.parser.output_sink_mut().finish(&[]);
+        }
+
+        if self.passthrough {
+            return self.parser.output_sink_mut().finish(&[]);
+        }
+
         let chunk = if self.has_buffered_data {
             self.buffer.bytes()
         } else {
@@ -135,7 +463,56 @@ where
         trace!(@chunk chunk);
 
         self.parser.parse(chunk, true)?;
-        self.dispatcher.borrow_mut().finish(chunk)
+        self.parser.output_sink_mut().finish(chunk)
+    }
+
+    /// Finishes the current document without invoking the document end handlers and resets
+    /// the per-document state, so the following input is parsed as a new document.
+    pub fn start_new_document(&mut self) -> Result<(), RewritingError> {
+        trace!(@end);
+
+        #[cfg(feature = "parse_ahead")]
+        self.finish_parse_ahead()?;
+
+        if !self.passthrough && !self.parses_ahead() {
+            let chunk = if self.has_buffered_data {
+                self.buffer.bytes()
+            } else {
+                &[]
+            };
+
+            trace!(@chunk chunk);
+
+            self.parser.parse(chunk, true)?;
+
+            self.parser
+                .output_sink_mut()
+                .flush_remaining_input(chunk, chunk.len());
+        }
+
+        self.parser.output_sink_mut().reset();
+        self.reset_parser();
+        self.has_buffered_data = false;
+        self.bytes_before_passthrough = self.passthrough_after;
+        self.passthrough = false;
+        self.document_started = false;
+
+        Ok(())
+    }
+
+    #[inline]
+    pub fn transform_controller(&self) -> &C {
+        self.parser.output_sink().transform_controller()
+    }
+
+    #[inline]
+    pub fn transform_controller_mut(&mut self) -> &mut C {
+        self.parser.output_sink_mut().transform_controller_mut()
+    }
+
+    #[inline]
+    pub fn is_passthrough(&self) -> bool {
+        self.passthrough
     }
 
     #[cfg(feature = "integration_test")]
@@ -0,0 +1,326 @@
+use super::prefix_cache::RecordedLexeme;
+use crate::base::Sharing;
+use crate::html::{LocalName, Namespace};
+use crate::memory::SharedMemoryLimiter;
+use crate::parser::{
+    LexemeSink, NonTagContentLexeme, NonTagContentTokenOutline, Parser, ParserDirective,
+    ParserOutputSink, ParsingAmbiguityError, TagHintSink, TagLexeme,
+};
+use crate::rewriter::RewritingError;
+use std::mem;
+use std::panic;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+
+/// A part of the input parsed by the parse-ahead thread.
+pub struct ParsedChunk {
+    input: Vec<u8>,
+    lexemes: Vec<RecordedLexeme>,
+    finishes_document: bool,
+}
+
+impl ParsedChunk {
+    #[inline]
+    pub fn input(&self) -> &[u8] {
+        &self.input
+    }
+
+    /// Takes the lexemes of the chunk, so they can be replayed by the dispatcher.
+    pub fn take_lexemes(&mut self) -> Vec<RecordedLexeme> {
+        mem::take(&mut self.lexemes)
+    }
+}
+
+enum ParseAheadInput {
+    Chunk(Vec<u8>),
+    FinishDocument,
+}
+
+type ParseAheadOutput = Result<ParsedChunk, ParsingAmbiguityError>;
+
+/// Records all the lexemes produced by the parser. The parser is always kept in the lexer
+/// mode, since the content handlers that could let it skip the content are not known to
+/// the parse-ahead thread.
+#[derive(Default)]
+struct LexemeRecorder {
+    lexemes: Vec<RecordedLexeme>,
+}
+
+impl LexemeSink for LexemeRecorder {
+    fn handle_tag(&mut self, lexeme: &TagLexeme) -> Result<ParserDirective, RewritingError> {
+        self.lexemes.push(RecordedLexeme::Tag(
+            lexeme.token_outline().clone(),
+            lexeme.raw_range(),
+        ));
+
+        Ok(ParserDirective::Lex)
+    }
+
+    #[inline]
+    fn handle_non_tag_content(
+        &mut self,
+        lexeme: &NonTagContentLexeme,
+    ) -> Result<(), RewritingError> {
+        self.lexemes.push(RecordedLexeme::NonTagContent(
+            lexeme.token_outline().clone(),
+            lexeme.raw_range(),
+        ));
+
+        Ok(())
+    }
+
+    // NOTE: the parse-ahead can't be combined with the spill settings, so the parser never
+    // spills the tokens.
+    #[inline]
+    fn handle_spilled_tag(
+        &mut self,
+        _lexeme: &TagLexeme,
+        _forced: bool,
+    ) -> Result<bool, RewritingError> {
+        Ok(false)
+    }
+
+    #[inline]
+    fn should_capture(&self, _token: &NonTagContentTokenOutline) -> bool {
+        true
+    }
+}
+
+impl TagHintSink for LexemeRecorder {
+    #[inline]
+    fn handle_start_tag_hint(
+        &mut self,
+        _name: LocalName,
+        _ns: Namespace,
+    ) -> Result<ParserDirective, RewritingError> {
+        Ok(ParserDirective::Lex)
+    }
+
+    #[inline]
+    fn handle_end_tag_hint(&mut self, _name: LocalName) -> Result<ParserDirective, RewritingError> {
+        Ok(ParserDirective::Lex)
+    }
+}
+
+impl ParserOutputSink for LexemeRecorder {}
+
+#[derive(Clone, Copy)]
+pub struct ParseAheadSettings {
+    pub queue_capacity: usize,
+    pub strict: bool,
+    pub attr_buffer_capacity: usize,
+    pub stream_comments: bool,
+}
+
+fn create_parser(settings: ParseAheadSettings) -> Parser<LexemeRecorder> {
+    Parser::new(
+        LexemeRecorder::default(),
+        ParserDirective::Lex,
+        settings.strict,
+        settings.attr_buffer_capacity,
+        true,
+        settings.stream_comments,
+    )
+}
+
+/// Parses the input chunks received from the rewriter until the rewriter is dropped, sending
+/// the parsed part of the input along with its lexemes back for each of the received messages.
+/// The incomplete token at the end of the input is kept until the following chunks complete it.
+fn parse_ahead(
+    settings: ParseAheadSettings,
+    input: Receiver<ParseAheadInput>,
+    output: SyncSender<ParseAheadOutput>,
+) {
+    let mut parser = create_parser(settings);
+    let mut buffer = Vec::default();
+
+    for message in input {
+        let finishes_document = match message {
+            ParseAheadInput::Chunk(chunk) => {
+                buffer.extend_from_slice(&chunk);
+                false
+            }
+            ParseAheadInput::FinishDocument => true,
+        };
+
+        let consumed_byte_count = match parser.parse(&buffer, finishes_document) {
+            Ok(_) if finishes_document => buffer.len(),
+            Ok(consumed_byte_count) => consumed_byte_count,
+            Err(RewritingError::ParsingAmbiguity(err)) => {
+                let _ = output.send(Err(err));
+
+                return;
+            }
+            // NOTE: the recorder neither fails nor lets the parser spill the tokens.
+            Err(_) => unreachable!("The parse-ahead parser can only fail on parsing ambiguity."),
+        };
+
+        let blocked = buffer.split_off(consumed_byte_count);
+        let chunk = ParsedChunk {
+            input: mem::replace(&mut buffer, blocked),
+            lexemes: mem::take(&mut parser.output_sink_mut().lexemes),
+            finishes_document,
+        };
+
+        // NOTE: the rewriter has been dropped.
+        if output.send(Ok(chunk)).is_err() {
+            return;
+        }
+
+        if finishes_document {
+            parser = create_parser(settings);
+        }
+    }
+}
+
+/// Runs the parser on a separate thread, so the input can be parsed ahead while the content
+/// handlers are invoked for the previously parsed input on the rewriter's thread.
+///
+/// The thread and the rewriter are connected by the bounded queues of the input chunks and
+/// the parsed chunks. The parsed chunks are handled by the `handler` passed to the methods
+/// in the order of the input.
+///
+/// The input is accounted in the memory usage of the rewriter from the moment it's sent to
+/// the thread until its parsed part is handled, so the queued chunks and the incomplete token
+/// buffered by the thread are limited as well.
+pub struct ParseAhead<S: Sharing> {
+    input: SyncSender<ParseAheadInput>,
+    output: Receiver<ParseAheadOutput>,
+    thread: Option<JoinHandle<()>>,
+    memory_limiter: SharedMemoryLimiter<S>,
+    pending_message_count: usize,
+}
+
+impl<S: Sharing> ParseAhead<S> {
+    pub fn new(settings: ParseAheadSettings, memory_limiter: SharedMemoryLimiter<S>) -> Self {
+        let capacity = settings.queue_capacity.max(1);
+        let (input, thread_input) = mpsc::sync_channel(capacity);
+        let (thread_output, output) = mpsc::sync_channel(capacity);
+
+        let thread = thread::Builder::new()
+            .name("lol_html parse-ahead".into())
+            .spawn(move || parse_ahead(settings, thread_input, thread_output))
+            .expect("Failed to spawn the parse-ahead thread.");
+
+        ParseAhead {
+            input,
+            output,
+            thread: Some(thread),
+            memory_limiter,
+            pending_message_count: 0,
+        }
+    }
+
+    fn recv(&mut self) -> Result<ParsedChunk, RewritingError> {
+        match self.output.recv() {
+            Ok(res) => res.map_err(RewritingError::ParsingAmbiguity),
+            Err(_) => {
+                // NOTE: the thread only exits before the rewriter is dropped if it panics
+                // or sends an error, which has been received already.
+                if let Some(Err(panic)) = self.thread.take().map(JoinHandle::join) {
+                    panic::resume_unwind(panic);
+                }
+
+                panic!("The parse-ahead thread has exited unexpectedly.");
+            }
+        }
+    }
+
+    fn handle(
+        &mut self,
+        chunk: ParsedChunk,
+        handler: &mut impl FnMut(ParsedChunk) -> Result<(), RewritingError>,
+    ) -> Result<(), RewritingError> {
+        self.pending_message_count -= 1;
+        self.memory_limiter.lock().decrease_usage(chunk.input.len());
+
+        handler(chunk)
+    }
+
+    /// Accounts the bytes in the memory usage of the rewriter. If they don't fit into the limit,
+    /// the chunks that are being parsed are handled first to free up the memory.
+    fn reserve(
+        &mut self,
+        byte_count: usize,
+        handler: &mut impl FnMut(ParsedChunk) -> Result<(), RewritingError>,
+    ) -> Result<(), RewritingError> {
+        loop {
+            let res = self.memory_limiter.lock().increase_usage(byte_count);
+
+            match res {
+                Ok(()) => return Ok(()),
+                Err(err) if self.pending_message_count == 0 => {
+                    return Err(RewritingError::MemoryLimitExceeded(err));
+                }
+                Err(_) => {
+                    let chunk = self.recv()?;
+
+                    self.handle(chunk, handler)?;
+                }
+            }
+        }
+    }
+
+    /// Sends the message to the thread, handling the parsed chunks while the input queue
+    /// is full, so neither side blocks the other.
+    fn send(
+        &mut self,
+        mut message: ParseAheadInput,
+        handler: &mut impl FnMut(ParsedChunk) -> Result<(), RewritingError>,
+    ) -> Result<(), RewritingError> {
+        loop {
+            match self.input.try_send(message) {
+                Ok(()) => {
+                    self.pending_message_count += 1;
+
+                    return Ok(());
+                }
+                Err(TrySendError::Full(m)) | Err(TrySendError::Disconnected(m)) => {
+                    message = m;
+
+                    let chunk = self.recv()?;
+
+                    self.handle(chunk, handler)?;
+                }
+            }
+        }
+    }
+
+    /// Sends the chunk to the thread and handles the chunks that have already been parsed.
+    pub fn write(
+        &mut self,
+        data: &[u8],
+        mut handler: impl FnMut(ParsedChunk) -> Result<(), RewritingError>,
+    ) -> Result<(), RewritingError> {
+        self.reserve(data.len(), &mut handler)?;
+        self.send(ParseAheadInput::Chunk(data.to_vec()), &mut handler)?;
+
+        while let Ok(res) = self.output.try_recv() {
+            let chunk = res.map_err(RewritingError::ParsingAmbiguity)?;
+
+            self.handle(chunk, &mut handler)?;
+        }
+
+        Ok(())
+    }
+
+    /// Finishes the current document, handling all the remaining parsed chunks. The following
+    /// chunks are parsed as a new document.
+    pub fn finish_document(
+        &mut self,
+        mut handler: impl FnMut(ParsedChunk) -> Result<(), RewritingError>,
+    ) -> Result<(), RewritingError> {
+        self.send(ParseAheadInput::FinishDocument, &mut handler)?;
+
+        loop {
+            let chunk = self.recv()?;
+            let finishes_document = chunk.finishes_document;
+
+            self.handle(chunk, &mut handler)?;
+
+            if finishes_document {
+                return Ok(());
+            }
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use crate::base::Range;
+use crate::parser::{NonTagContentTokenOutline, TagTokenOutline};
+use hashbrown::HashMap;
+
+/// A lexeme of the document prefix that is recorded, so it can be replayed instead of
+/// parsing the same prefix again.
+pub enum RecordedLexeme {
+    Tag(TagTokenOutline, Range),
+    NonTagContent(Option<NonTagContentTokenOutline>, Range),
+}
+
+/// A cache of the parsed document prefixes.
+///
+/// Documents that are produced from the same template often share a large prefix, e.g. the
+/// `<head>` section. Once such a prefix is written with [`HtmlRewriter::write_prefix`], the
+/// lexemes produced by the parser for it are memoized in the cache, keyed by the hash of the
+/// prefix. The subsequent documents that start with the same prefix skip parsing it: the
+/// memoized lexemes are replayed to the rewriter instead. The content handlers are invoked
+/// for the replayed content as usual, so the output is the same as without the cache.
+///
+/// The cache must only be shared by the rewriters with the same [`Settings`], since the
+/// lexemes the parser produces depend on the content handlers.
+///
+/// [`HtmlRewriter::write_prefix`]: struct.HtmlRewriter.html#method.write_prefix
+/// [`Settings`]: struct.Settings.html
+#[derive(Default)]
+pub struct PrefixCache {
+    prefixes: HashMap<Box<[u8]>, Vec<RecordedLexeme>>,
+}
+
+impl PrefixCache {
+    /// Creates an empty cache.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of the memoized prefixes.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.prefixes.len()
+    }
+
+    /// Returns `true` if no prefixes have been memoized yet.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.prefixes.is_empty()
+    }
+
+    /// Removes all the memoized prefixes.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.prefixes.clear();
+    }
+
+    #[inline]
+    pub(crate) fn get(&self, prefix: &[u8]) -> Option<&[RecordedLexeme]> {
+        self.prefixes.get(prefix).map(|lexemes| &lexemes[..])
+    }
+
+    #[inline]
+    pub(crate) fn insert(&mut self, prefix: &[u8], lexemes: Vec<RecordedLexeme>) {
+        self.prefixes.insert(prefix.into(), lexemes);
+    }
+}
@@ -62,6 +62,11 @@ impl TransformController for TestTransformController<'_> {
     fn initial_capture_flags(&self) -> TokenCaptureFlags {
         self.capture_flags
     }
+
+    fn needs_attributes(&self) -> bool {
+        true
+    }
+
     fn handle_start_tag(&mut self, _: LocalName, _: Namespace) -> StartTagHandlingResult<Self> {
         Ok(self.capture_flags)
     }
@@ -70,6 +75,10 @@ impl TransformController for TestTransformController<'_> {
         self.capture_flags
     }
 
+    fn handle_spilled_start_tag(&mut self) -> TokenCaptureFlags {
+        self.capture_flags
+    }
+
     fn handle_token(&mut self, token: &mut Token) -> Result<(), RewritingError> {
         (self.token_handler)(token);
 
@@ -83,6 +92,12 @@ impl TransformController for TestTransformController<'_> {
     fn should_emit_content(&self) -> bool {
         true
     }
+
+    fn should_pass_through(&self) -> bool {
+        false
+    }
+
+    fn reset(&mut self) {}
 }
 
 pub fn parse(
@@ -100,14 +115,20 @@ pub fn parse(
     let transform_controller = TestTransformController::new(token_handler, capture_flags);
     let memory_limiter = MemoryLimiter::new_shared(2048);
 
-    let mut transform_stream = TransformStream::new(
+    let mut transform_stream: TransformStream<_, _> = TransformStream::new(
         TransformStreamSettings {
             transform_controller,
             output_sink: |chunk: &[u8]| output.push(chunk),
             preallocated_parsing_buffer_size: 0,
+            preallocated_attribute_buffer_capacity: 0,
             memory_limiter,
             encoding,
-            strict: true
+            strict: true,
+            spill_settings: Default::default(),
+            stream_comments: false,
+            passthrough_after: None,
+            #[cfg(feature = "parse_ahead")]
+            parse_ahead_queue_capacity: None,
         }
     );
 
@@ -142,12 +163,53 @@ fn filter_tokens(tokens: &[TestToken], capture_flags: TokenCaptureFlags) -> Vec<
                 true
             }
             TestToken::Comment(_) if capture_flags.contains(TokenCaptureFlags::COMMENTS) => true,
+            TestToken::ProcessingInstruction { .. }
+                if capture_flags.contains(TokenCaptureFlags::PROCESSING_INSTRUCTIONS) =>
+            {
+                true
+            }
             TestToken::Text(_) if capture_flags.contains(TokenCaptureFlags::TEXT) => true,
             _ => false,
         })
         .collect()
 }
 
+// NOTE: the test data doesn't have processing instructions, since they are bogus comments in
+// HTML. The comments that start with `<?` in the input are split into the target and the data
+// the same way the parser does it, i.e. the target is followed by whitespace and the closing
+// `?`, if any, is not a part of the data.
+fn to_processing_instructions(tokens: Vec<TestToken>, input: &str) -> Vec<TestToken> {
+    let input = input.replace('\0', "\u{FFFD}");
+
+    tokens
+        .into_iter()
+        .map(|t| match t {
+            TestToken::Comment(ref text)
+                if text.starts_with('?') && input.contains(&format!("<{}", text)) =>
+            {
+                let text = &text[1..];
+                let text = if text.ends_with('?') {
+                    &text[..text.len() - 1]
+                } else {
+                    text
+                };
+
+                let target_end = text
+                    .find(|c: char| c.is_ascii_whitespace())
+                    .unwrap_or(text.len());
+
+                TestToken::ProcessingInstruction {
+                    target: text[..target_end].to_string(),
+                    data: text[target_end..]
+                        .trim_start_matches(|c: char| c.is_ascii_whitespace())
+                        .to_string(),
+                }
+            }
+            t => t,
+        })
+        .collect()
+}
+
 fn fold_text_tokens(tokens: Vec<TestToken>) -> Vec<TestToken> {
     tokens.into_iter().fold(Vec::default(), |mut res, t| {
         if let TestToken::Text(ref text) = t {
@@ -178,13 +240,26 @@ impl TokenCapturingTests {
             TokenCaptureFlags::NEXT_END_TAG,
             TokenCaptureFlags::TEXT,
             TokenCaptureFlags::COMMENTS,
+            TokenCaptureFlags::PROCESSING_INSTRUCTIONS,
             TokenCaptureFlags::DOCTYPES,
             TokenCaptureFlags::empty(),
         ]
         .iter()
         .cloned()
         .for_each(|capture_flags| {
-            let mut expected_tokens = filter_tokens(&test.expected_tokens, capture_flags);
+            let mut expected_tokens = if capture_flags
+                .contains(TokenCaptureFlags::PROCESSING_INSTRUCTIONS)
+            {
+                filter_tokens(
+                    &to_processing_instructions(
+                        test.expected_tokens.clone(),
+                        test.input.as_str(),
+                    ),
+                    capture_flags,
+                )
+            } else {
+                filter_tokens(&test.expected_tokens, capture_flags)
+            };
             let mut token_list = TestTokenList::default();
 
             let parsing_result = parse(
@@ -23,6 +23,11 @@ pub enum TestToken {
 
     Comment(String),
 
+    ProcessingInstruction {
+        target: String,
+        data: String,
+    },
+
     StartTag {
         name: String,
         attributes: HashMap<String, String>,
@@ -134,6 +139,14 @@ impl Unescape for TestToken {
                 s.unescape()?;
             }
 
+            TestToken::ProcessingInstruction {
+                ref mut target,
+                ref mut data,
+            } => {
+                target.unescape()?;
+                data.unescape()?;
+            }
+
             TestToken::EndTag { ref mut name, .. } => {
                 name.unescape()?;
             }
@@ -214,6 +227,11 @@ impl TestTokenList {
                 system_id: t.system_id().map(|s| to_null_decoded(&s)),
                 force_quirks: t.force_quirks(),
             }),
+
+            Token::ProcessingInstruction(t) => self.0.push(TestToken::ProcessingInstruction {
+                target: to_null_decoded(&t.target()),
+                data: to_null_decoded(&t.data()),
+            }),
         }
     }
 }